@@ -0,0 +1,42 @@
+//! Cost of listing every process
+//!
+//! Compares reading each process on its own, as `Process::get_all` used to, with the bulk detail pass and with the
+//! listing alone:
+//!
+//! ```sh
+//! cargo bench --bench process_get_all
+//! ```
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use darwin_metrics::process::Process;
+use tokio::runtime::Runtime;
+
+/// Reads every listed process on its own with `get_by_pid`
+async fn get_all_per_pid() -> Vec<Process> {
+    let mut processes = Process::get_all_basic().expect("Failed to list processes");
+    for process in &mut processes {
+        if let Ok(detailed) = Process::get_by_pid(process.pid).await {
+            *process = detailed;
+        }
+    }
+    processes
+}
+
+fn bench_get_all(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create the runtime");
+    let mut group = c.benchmark_group("process_get_all");
+    group.sample_size(20);
+
+    group.bench_function("per_pid", |b| b.iter(|| runtime.block_on(get_all_per_pid())));
+    group.bench_function("bulk", |b| {
+        b.iter(|| runtime.block_on(Process::get_all()).expect("Failed to read processes"))
+    });
+    group.bench_function("basic", |b| {
+        b.iter(|| Process::get_all_basic().expect("Failed to list processes"))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_get_all);
+criterion_main!(benches);
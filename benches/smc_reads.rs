@@ -0,0 +1,47 @@
+//! Cost of reading the SMC
+//!
+//! Compares reading the keys of the thermal summary over a connection opened for every key, as `IOKitImpl` used to,
+//! with the connection an `IOKitImpl` keeps between reads, for the thermal summary and for the fans:
+//!
+//! ```sh
+//! cargo bench --bench smc_reads
+//! ```
+//!
+//! Machines whose SMC can't be opened skip the benchmarks.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use darwin_metrics::hardware::iokit::{IOKit, IOKitImpl, SmcConnection, SmcKey};
+
+/// Keys `IOKit::get_thermal_info` reads when every sensor is present
+const THERMAL_KEYS: [&[u8; 4]; 7] = [b"TC0P", b"TG0P", b"Th0H", b"TA0P", b"TB0T", b"PCPC", b"PCTC"];
+
+/// Reads every thermal key over a connection of its own
+fn thermal_keys_per_read() {
+    for key in THERMAL_KEYS {
+        if let Ok(smc) = SmcConnection::open() {
+            let _ = smc.read_key_typed(SmcKey::new(*key));
+        }
+    }
+}
+
+fn bench_smc_reads(c: &mut Criterion) {
+    if SmcConnection::open().is_err() {
+        eprintln!("Skipping the SMC benchmarks: the SMC can't be opened");
+        return;
+    }
+
+    let iokit = IOKitImpl::default();
+    let mut group = c.benchmark_group("smc_reads");
+
+    group.bench_function("thermal_keys_per_read", |b| b.iter(thermal_keys_per_read));
+    group.bench_function("thermal_info_kept", |b| b.iter(|| iokit.get_thermal_info()));
+    group.bench_function("thermal_info_new_instance", |b| {
+        b.iter(|| IOKitImpl::default().get_thermal_info())
+    });
+    group.bench_function("all_fans_kept", |b| b.iter(|| iokit.get_all_fans()));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_smc_reads);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+use darwin_metrics::snapshot::{MetricsSnapshot, NumberFormat, SnapshotOptions};
+
+fn main() -> darwin_metrics::Result<()> {
+    // `darwin_metrics::snapshot()` collects everything except the processes; ask for the five busiest ones as well
+    let snapshot = MetricsSnapshot::collect_with_options(&SnapshotOptions { top_processes: 5 });
+
+    // Sections that couldn't be collected are None, and the reasons are listed in the warnings
+    for warning in &snapshot.warnings {
+        eprintln!("warning: {}", warning);
+    }
+
+    // Pass `--fixed` for a document without floating-point numbers
+    let format = if std::env::args().any(|arg| arg == "--fixed") {
+        NumberFormat::Fixed
+    } else {
+        NumberFormat::Float
+    };
+    println!("{}", snapshot.to_json(format)?);
+
+    Ok(())
+}
@@ -0,0 +1,544 @@
+//! Charge and power source events of the battery
+//!
+//! IOKit calls back whenever a power source changes: the adapter is plugged in or out, or the battery charge moves.
+//! [`Battery::subscribe_events`](super::Battery::subscribe_events) registers that callback once, on the run loop of a
+//! thread shared by every subscriber, and broadcasts what changed as [`BatteryEvent`]s. The thread exits when the last
+//! receiver is dropped, and the next subscription starts a new one.
+//!
+//! Notifications come in bursts, so the power source is only read once they've been quiet for [`DEBOUNCE`]. Each event
+//! describes a change from the previous reading; the state at subscription isn't reported.
+
+use std::{
+    cell::Cell,
+    ffi::c_void,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tokio::sync::broadcast;
+
+use crate::{
+    error::{Error, Result},
+    hardware::iokit::{IOKit, IOKitImpl},
+    power::{PowerSourceInfo, PowerState},
+    shutdown::{self, ComponentHandle},
+    utils::bindings::{
+        kCFRunLoopDefaultMode, CFRelease, CFRunLoopAddSource, CFRunLoopGetCurrent,
+        CFRunLoopRemoveSource, CFRunLoopRunInMode, IOPSNotificationCreateRunLoopSource,
+    },
+};
+
+/// Name of the notification thread, in the shutdown registry and for the OS
+pub(crate) const BATTERY_EVENTS: &str = "battery-events";
+
+/// How long notifications have to be quiet before the power source is read
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+/// Charge in percent at or below which [`BatteryEvent::LowBattery`] is sent while on battery
+pub const LOW_BATTERY_PERCENT: u8 = 20;
+/// Percentage points the charge has to rise above [`LOW_BATTERY_PERCENT`] before a low battery is reported again
+const LOW_BATTERY_HYSTERESIS: u8 = 5;
+/// How often an idle thread checks whether it still has receivers
+const IDLE_CHECK: Duration = Duration::from_secs(1);
+/// Number of events buffered for each receiver; a receiver that falls further behind skips the oldest ones
+const EVENT_CAPACITY: usize = 32;
+
+/// Subscription shared by every caller of [`Battery::subscribe_events`](super::Battery::subscribe_events)
+static EVENTS: Lazy<Arc<EventHub>> = Lazy::new(|| Arc::new(EventHub::default()));
+
+/// A change of the battery or the power source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BatteryEvent {
+    /// The machine switched to another power source, or started or stopped charging
+    PowerSourceChanged(PowerState),
+    /// The charge changed to this percentage
+    PercentageChanged(u8),
+    /// The charge fell to [`LOW_BATTERY_PERCENT`] or below while on battery; sent again only after it rose
+    /// clearly above the threshold or the adapter was plugged in
+    LowBattery {
+        /// Charge in percent
+        percent: u8,
+    },
+    /// Charging stopped while the adapter stayed connected, at full charge or where optimized charging holds it
+    FullyCharged,
+}
+
+/// Power source and charge as read after a notification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BatterySample {
+    pub(crate) state: PowerState,
+    /// Charge in whole percent, None without a battery
+    pub(crate) percent: Option<u8>,
+}
+
+impl Default for BatterySample {
+    fn default() -> Self {
+        Self { state: PowerState::Unknown, percent: None }
+    }
+}
+
+impl BatterySample {
+    /// Converts a power source reading; a machine without a battery runs on AC power.
+    pub(crate) fn from_info(info: &PowerSourceInfo) -> Self {
+        let percent =
+            info.battery_percentage.map(|percent| percent.round().clamp(0.0, 100.0) as u8);
+        let state = match (info.state, percent) {
+            (PowerState::Unknown, None) => PowerState::AC,
+            (state, _) => state,
+        };
+        Self { state, percent }
+    }
+}
+
+/// Turns consecutive samples into events
+#[derive(Debug)]
+pub(crate) struct EventDetector {
+    previous: BatterySample,
+    /// Whether the current low battery was reported
+    low_reported: bool,
+}
+
+impl EventDetector {
+    /// Creates a detector that reports changes from `initial`, without reporting `initial` itself.
+    pub(crate) fn new(initial: BatterySample) -> Self {
+        let mut detector = Self { previous: initial, low_reported: false };
+        detector.low_reported = detector.is_low();
+        detector
+    }
+
+    /// Returns the events between the previous sample and `sample`.
+    pub(crate) fn update(&mut self, sample: BatterySample) -> Vec<BatteryEvent> {
+        let previous = std::mem::replace(&mut self.previous, sample);
+        let mut events = Vec::new();
+
+        if sample.state != previous.state {
+            events.push(BatteryEvent::PowerSourceChanged(sample.state));
+            if previous.state == PowerState::Charging && sample.state == PowerState::AC {
+                events.push(BatteryEvent::FullyCharged);
+            }
+        }
+        if let Some(percent) = sample.percent.filter(|&percent| Some(percent) != previous.percent) {
+            events.push(BatteryEvent::PercentageChanged(percent));
+        }
+
+        if self.is_low() {
+            if !self.low_reported {
+                self.low_reported = true;
+                events
+                    .push(BatteryEvent::LowBattery { percent: sample.percent.unwrap_or_default() });
+            }
+        } else if sample.state != PowerState::Battery
+            || sample.percent.is_some_and(|percent| {
+                percent >= LOW_BATTERY_PERCENT.saturating_add(LOW_BATTERY_HYSTERESIS)
+            })
+        {
+            self.low_reported = false;
+        }
+
+        events
+    }
+
+    fn is_low(&self) -> bool {
+        self.previous.state == PowerState::Battery
+            && self.previous.percent.is_some_and(|percent| percent <= LOW_BATTERY_PERCENT)
+    }
+}
+
+/// The sender of the running notification thread, if any
+#[derive(Debug, Default)]
+pub(crate) struct EventHub {
+    sender: Mutex<Option<broadcast::Sender<BatteryEvent>>>,
+}
+
+impl EventHub {
+    /// Subscribes to the running thread, or starts one with `start` if none runs.
+    pub(crate) fn subscribe(
+        &self,
+        start: impl FnOnce(broadcast::Sender<BatteryEvent>) -> Result<()>,
+    ) -> Result<broadcast::Receiver<BatteryEvent>> {
+        let mut slot = self.sender.lock();
+        if let Some(sender) = slot.as_ref() {
+            return Ok(sender.subscribe());
+        }
+
+        let (sender, receiver) = broadcast::channel(EVENT_CAPACITY);
+        start(sender.clone())?;
+        *slot = Some(sender);
+        Ok(receiver)
+    }
+
+    /// Whether a notification thread is running
+    #[cfg(test)]
+    fn is_running(&self) -> bool {
+        self.sender.lock().is_some()
+    }
+
+    /// Lets the thread of `sender` go if nobody listens anymore, or if `force`d; returns whether it should exit.
+    ///
+    /// Checking under the lock of [`EventHub::subscribe`] keeps a new subscriber from joining a thread that is leaving.
+    fn release(&self, sender: &broadcast::Sender<BatteryEvent>, force: bool) -> bool {
+        let mut slot = self.sender.lock();
+        if !force && sender.receiver_count() > 0 {
+            return false;
+        }
+        if slot.as_ref().is_some_and(|current| current.same_channel(sender)) {
+            *slot = None;
+        }
+        true
+    }
+}
+
+/// Subscribes to the events of the shared notification thread, starting it if needed.
+pub(super) fn subscribe() -> Result<broadcast::Receiver<BatteryEvent>> {
+    EVENTS.subscribe(|sender| {
+        spawn_thread(Arc::clone(&EVENTS), sender, || {
+            // SAFETY: the notification is registered on, used on and dropped on this thread
+            let notification = unsafe { PowerSourceNotification::register() }
+                .ok_or_else(|| Error::not_available("Power source notifications"))?;
+            let iokit: Arc<dyn IOKit> = Arc::new(IOKitImpl::default());
+
+            Ok((
+                move |timeout| notification.wait(timeout),
+                move || {
+                    PowerSourceInfo::read(&iokit).ok().map(|info| BatterySample::from_info(&info))
+                },
+            ))
+        })
+    })
+}
+
+/// Starts the notification thread of `sender`.
+///
+/// `setup` runs on the thread and returns the function waiting up to a timeout for a notification, telling whether one
+/// arrived, and the function reading a sample. Returns once the first sample was read, or with the error of `setup`.
+pub(crate) fn spawn_thread<W, R>(
+    hub: Arc<EventHub>,
+    sender: broadcast::Sender<BatteryEvent>,
+    setup: impl FnOnce() -> Result<(W, R)> + Send + 'static,
+) -> Result<()>
+where
+    W: FnMut(Duration) -> bool,
+    R: FnMut() -> Option<BatterySample>,
+{
+    let component = shutdown::register(BATTERY_EVENTS);
+    let (started, setup_result) = std::sync::mpsc::channel();
+
+    thread::Builder::new()
+        .name(BATTERY_EVENTS.to_string())
+        .spawn(move || {
+            let _running = component.running();
+            let (wait, mut read) = match setup() {
+                Ok(functions) => functions,
+                Err(e) => {
+                    let _ = started.send(Err(e));
+                    return;
+                },
+            };
+            // Changes after the subscription returns are reported relative to this reading
+            let detector = EventDetector::new(read().unwrap_or_default());
+            let _ = started.send(Ok(()));
+            run_events(&hub, &sender, &component, detector, wait, read);
+        })
+        .map_err(|e| Error::system(format!("Failed to start the battery event thread: {e}")))?;
+
+    setup_result
+        .recv()
+        .map_err(|_| Error::system("The battery event thread ended while starting"))?
+}
+
+/// Broadcasts the events of each burst of notifications until nobody listens or a stop is requested.
+fn run_events(
+    hub: &EventHub,
+    sender: &broadcast::Sender<BatteryEvent>,
+    component: &ComponentHandle,
+    mut detector: EventDetector,
+    mut wait: impl FnMut(Duration) -> bool,
+    mut read: impl FnMut() -> Option<BatterySample>,
+) {
+    // Time of the last notification that wasn't followed by a reading yet
+    let mut pending: Option<Instant> = None;
+
+    loop {
+        if component.is_stop_requested() {
+            hub.release(sender, true);
+            return;
+        }
+        if sender.receiver_count() == 0 && hub.release(sender, false) {
+            return;
+        }
+
+        let timeout = pending.map_or(IDLE_CHECK, |at| DEBOUNCE.saturating_sub(at.elapsed()));
+        if wait(timeout) {
+            pending = Some(Instant::now());
+            continue;
+        }
+
+        if pending.is_some_and(|at| at.elapsed() >= DEBOUNCE) {
+            pending = None;
+            for event in read().map(|sample| detector.update(sample)).unwrap_or_default() {
+                // Fails only without receivers, which the next iteration notices
+                let _ = sender.send(event);
+            }
+        }
+    }
+}
+
+/// The power source notification on the run loop of the current thread, removed when dropped
+struct PowerSourceNotification {
+    source: *mut c_void,
+    run_loop: *mut c_void,
+    /// Set by the callback, which runs on this thread while the run loop runs
+    notified: Box<Cell<bool>>,
+}
+
+impl PowerSourceNotification {
+    /// Adds the notification to the run loop of the current thread, or returns None if it can't be created.
+    unsafe fn register() -> Option<Self> {
+        let notified = Box::new(Cell::new(false));
+        let source = IOPSNotificationCreateRunLoopSource(
+            power_source_changed,
+            &*notified as *const Cell<bool> as *mut c_void,
+        );
+        if source.is_null() {
+            return None;
+        }
+
+        let run_loop = CFRunLoopGetCurrent();
+        CFRunLoopAddSource(run_loop, source, kCFRunLoopDefaultMode);
+        Some(Self { source, run_loop, notified })
+    }
+
+    /// Runs the run loop for up to `timeout`, returning whether a power source changed meanwhile.
+    fn wait(&self, timeout: Duration) -> bool {
+        // SAFETY: called on the thread whose run loop holds the source
+        unsafe { CFRunLoopRunInMode(kCFRunLoopDefaultMode, timeout.as_secs_f64(), 1) };
+        self.notified.replace(false)
+    }
+}
+
+impl Drop for PowerSourceNotification {
+    fn drop(&mut self) {
+        // SAFETY: the source was added to this run loop and created with a +1 retain count
+        unsafe {
+            CFRunLoopRemoveSource(self.run_loop, self.source, kCFRunLoopDefaultMode);
+            CFRelease(self.source);
+        }
+    }
+}
+
+unsafe extern "C" fn power_source_changed(context: *mut c_void) {
+    (*(context as *const Cell<bool>)).set(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    };
+
+    use super::*;
+
+    type WaitFn = fn(Duration) -> bool;
+    type ReadFn = fn() -> Option<BatterySample>;
+
+    fn on_battery(percent: u8) -> BatterySample {
+        BatterySample { state: PowerState::Battery, percent: Some(percent) }
+    }
+
+    fn charging(percent: u8) -> BatterySample {
+        BatterySample { state: PowerState::Charging, percent: Some(percent) }
+    }
+
+    fn on_ac(percent: u8) -> BatterySample {
+        BatterySample { state: PowerState::AC, percent: Some(percent) }
+    }
+
+    #[test]
+    fn test_sample_from_info() {
+        let desktop = PowerSourceInfo::default();
+        assert_eq!(
+            BatterySample::from_info(&desktop),
+            BatterySample { state: PowerState::AC, percent: None }
+        );
+
+        let laptop = PowerSourceInfo {
+            state: PowerState::Battery,
+            battery_percentage: Some(79.6),
+            time_remaining: None,
+        };
+        assert_eq!(BatterySample::from_info(&laptop), on_battery(80));
+    }
+
+    #[test]
+    fn test_plugging_in_and_charging_up() {
+        let mut detector = EventDetector::new(on_battery(78));
+
+        assert_eq!(
+            detector.update(charging(78)),
+            [BatteryEvent::PowerSourceChanged(PowerState::Charging)]
+        );
+        // Readings without a change don't repeat any event
+        assert!(detector.update(charging(78)).is_empty());
+        assert_eq!(detector.update(charging(79)), [BatteryEvent::PercentageChanged(79)]);
+        assert_eq!(
+            detector.update(on_ac(80)),
+            [
+                BatteryEvent::PowerSourceChanged(PowerState::AC),
+                BatteryEvent::FullyCharged,
+                BatteryEvent::PercentageChanged(80)
+            ]
+        );
+        assert_eq!(
+            detector.update(on_battery(80)),
+            [BatteryEvent::PowerSourceChanged(PowerState::Battery)]
+        );
+    }
+
+    #[test]
+    fn test_low_battery_is_reported_once() {
+        let mut detector = EventDetector::new(on_battery(22));
+
+        assert_eq!(
+            detector.update(on_battery(20)),
+            [BatteryEvent::PercentageChanged(20), BatteryEvent::LowBattery { percent: 20 }]
+        );
+        assert_eq!(detector.update(on_battery(19)), [BatteryEvent::PercentageChanged(19)]);
+        // A charge flickering around the threshold doesn't report it again
+        detector.update(on_battery(21));
+        assert_eq!(detector.update(on_battery(20)), [BatteryEvent::PercentageChanged(20)]);
+
+        // Charging re-arms it
+        detector.update(charging(20));
+        assert_eq!(
+            detector.update(on_battery(20)),
+            [
+                BatteryEvent::PowerSourceChanged(PowerState::Battery),
+                BatteryEvent::LowBattery { percent: 20 }
+            ]
+        );
+
+        // Subscribing while the battery is already low doesn't report it
+        let mut detector = EventDetector::new(on_battery(10));
+        assert_eq!(detector.update(on_battery(9)), [BatteryEvent::PercentageChanged(9)]);
+    }
+
+    #[test]
+    fn test_desktop_only_reports_ac() {
+        let desktop = BatterySample::from_info(&PowerSourceInfo::default());
+        let mut detector = EventDetector::new(BatterySample::default());
+
+        assert_eq!(detector.update(desktop), [BatteryEvent::PowerSourceChanged(PowerState::AC)]);
+        assert!(detector.update(desktop).is_empty());
+    }
+
+    /// Starts a thread on `hub` that waits for the notifications sent to the returned injector and reads `samples`
+    fn start_injected(
+        hub: &Arc<EventHub>,
+        samples: Arc<Mutex<BatterySample>>,
+        reads: Arc<AtomicUsize>,
+    ) -> (broadcast::Receiver<BatteryEvent>, mpsc::Sender<()>) {
+        let (inject, notifications) = mpsc::channel::<()>();
+        let receiver = hub
+            .subscribe(|sender| {
+                spawn_thread(Arc::clone(hub), sender, move || {
+                    Ok((
+                        move |timeout| notifications.recv_timeout(timeout).is_ok(),
+                        move || {
+                            reads.fetch_add(1, Ordering::SeqCst);
+                            Some(*samples.lock())
+                        },
+                    ))
+                })
+            })
+            .unwrap();
+        (receiver, inject)
+    }
+
+    #[test]
+    fn test_burst_of_notifications_is_debounced() {
+        let _serial = crate::shutdown::TEST_SERIAL.blocking_lock();
+
+        let hub = Arc::new(EventHub::default());
+        let samples = Arc::new(Mutex::new(on_battery(50)));
+        let reads = Arc::new(AtomicUsize::new(0));
+        let (mut receiver, inject) = start_injected(&hub, Arc::clone(&samples), Arc::clone(&reads));
+
+        // The adapter is plugged in, and the charge moves while the notifications come in
+        for percent in 50..55 {
+            *samples.lock() = charging(percent);
+            inject.send(()).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        let mut next = || {
+            runtime
+                .block_on(async {
+                    tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await
+                })
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(next(), BatteryEvent::PowerSourceChanged(PowerState::Charging));
+        assert_eq!(next(), BatteryEvent::PercentageChanged(54));
+        // The initial reading and one for the whole burst
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_subscribers_share_one_thread() {
+        let _serial = crate::shutdown::TEST_SERIAL.blocking_lock();
+
+        let hub = Arc::new(EventHub::default());
+        let samples = Arc::new(Mutex::new(on_ac(100)));
+        let (first, _inject) = start_injected(&hub, samples, Arc::new(AtomicUsize::new(0)));
+
+        // A second subscriber joins the running thread instead of starting another one
+        let starts = AtomicUsize::new(0);
+        let second = hub
+            .subscribe(|_| {
+                starts.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(starts.load(Ordering::SeqCst), 0);
+        assert!(hub.is_running());
+
+        // The thread exits once every receiver is gone
+        drop((first, second));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while hub.is_running() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!hub.is_running(), "The thread should exit without receivers");
+    }
+
+    #[test]
+    fn test_failed_setup_is_reported() {
+        let _serial = crate::shutdown::TEST_SERIAL.blocking_lock();
+
+        let hub = Arc::new(EventHub::default());
+        let result = hub.subscribe(|sender| {
+            spawn_thread(Arc::clone(&hub), sender, || -> Result<(WaitFn, ReadFn)> {
+                Err(Error::not_available("Power source notifications"))
+            })
+        });
+
+        assert!(result.is_err());
+        assert!(!hub.is_running());
+    }
+
+    #[test]
+    fn test_subscribe_events() {
+        let _serial = crate::shutdown::TEST_SERIAL.blocking_lock();
+
+        let first = super::super::Battery::subscribe_events().unwrap();
+        let second = super::super::Battery::subscribe_events().unwrap();
+        assert!(EVENTS.is_running());
+        drop((first, second));
+    }
+}
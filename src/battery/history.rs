@@ -0,0 +1,79 @@
+use std::time::SystemTime;
+
+use super::Battery;
+use crate::{
+    error::Result,
+    history::{HistorySample, HistoryStore, MetricHistory, ResumePolicy},
+    utils::sanitize::finite,
+};
+
+/// History of the battery charge percentage
+///
+/// With a [`HistoryStore`] attached, the history survives process restarts, so charge and discharge rates are
+/// available right after startup instead of only after the first few samples.
+#[derive(Debug)]
+pub struct BatteryHistory {
+    history: MetricHistory,
+}
+
+impl BatteryHistory {
+    /// Creates an in-memory history holding up to `capacity` samples.
+    pub fn new(capacity: usize) -> Self {
+        Self { history: MetricHistory::new(capacity) }
+    }
+
+    /// Creates a history that persists to `store`, resuming from the samples already in it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store can't be read.
+    pub fn with_store(capacity: usize, store: HistoryStore, policy: ResumePolicy) -> Result<Self> {
+        Ok(Self { history: MetricHistory::with_store(capacity, store, policy)? })
+    }
+
+    /// Records a charge percentage taken now.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sample can't be persisted.
+    pub fn record(&mut self, percentage: f64) -> Result<()> {
+        self.history.push(HistorySample::now(percentage))
+    }
+
+    /// Records the current charge percentage of `battery`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sample can't be persisted.
+    pub fn record_battery(&mut self, battery: &Battery) -> Result<()> {
+        self.record(battery.percentage)
+    }
+
+    /// Records a charge percentage taken at `timestamp`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sample can't be persisted.
+    pub fn record_at(&mut self, timestamp: SystemTime, percentage: f64) -> Result<()> {
+        self.history.push(HistorySample::new(timestamp, percentage))
+    }
+
+    /// The underlying samples, oldest first.
+    pub fn history(&self) -> &MetricHistory {
+        &self.history
+    }
+
+    /// Average change of the charge percentage per hour between the oldest and newest sample.
+    ///
+    /// Positive while charging, negative while discharging. None with fewer than two samples or if they share a
+    /// timestamp.
+    pub fn rate_per_hour(&self) -> Option<f64> {
+        let samples = self.history.samples();
+        let (first, last) = (samples.front()?, samples.back()?);
+        let elapsed = last.timestamp.duration_since(first.timestamp).ok()?.as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+        finite((last.value - first.value) / elapsed * 3600.0)
+    }
+}
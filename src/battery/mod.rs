@@ -1,10 +1,20 @@
-use std::time::Duration;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use crate::{
     error::{Error, Result},
-    hardware::iokit::{IOKit, IOKitImpl},
+    hardware::iokit::{IOKit, IOKitImpl, IOServiceHandle},
+    utils::sanitize::Percentage,
 };
 
+mod events;
+mod history;
+
+pub use events::{BatteryEvent, DEBOUNCE, LOW_BATTERY_PERCENT};
+pub use history::BatteryHistory;
+
 const BATTERY_IS_PRESENT: &str = "BatteryInstalled";
 const BATTERY_IS_CHARGING: &str = "IsCharging";
 const BATTERY_CURRENT_CAPACITY: &str = "CurrentCapacity";
@@ -14,6 +24,19 @@ const BATTERY_CYCLE_COUNT: &str = "CycleCount";
 const BATTERY_TEMPERATURE: &str = "Temperature";
 const BATTERY_TIME_REMAINING: &str = "TimeRemaining";
 const BATTERY_POWER_SOURCE: &str = "ExternalConnected";
+const BATTERY_AVG_TIME_TO_EMPTY: &str = "AvgTimeToEmpty";
+const BATTERY_AVG_TIME_TO_FULL: &str = "AvgTimeToFull";
+const BATTERY_INSTANT_AMPERAGE: &str = "InstantAmperage";
+const BATTERY_AMPERAGE: &str = "Amperage";
+const BATTERY_VOLTAGE: &str = "Voltage";
+const BATTERY_RAW_CURRENT_CAPACITY: &str = "AppleRawCurrentCapacity";
+const BATTERY_RAW_MAX_CAPACITY: &str = "AppleRawMaxCapacity";
+
+/// Time estimate reported while macOS is still calculating it, in minutes
+const TIME_CALCULATING: i64 = 65535;
+
+/// Minimum time between two registry reads made by the `*_fresh()` getters
+pub const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 #[non_exhaustive]
@@ -23,6 +46,21 @@ pub enum PowerSource {
     Unknown,
 }
 
+/// Electrical readings and time estimates as of the last refresh
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct ChargeReadings {
+    time_to_empty: Option<Duration>,
+    time_to_full: Option<Duration>,
+    amperage_ma: Option<i64>,
+    voltage_mv: Option<i64>,
+}
+
+/// Battery state as of the last [`refresh`](Battery::refresh)
+///
+/// The public fields are a snapshot taken when the battery was last refreshed and aren't updated on their own; a
+/// long-lived instance has to call [`refresh`](Battery::refresh) to see the current state. Use
+/// [`last_refreshed`](Battery::last_refreshed) to check how old the snapshot is, or the `*_fresh()` getters, which
+/// refresh on demand but read the registry at most once per [`MIN_REFRESH_INTERVAL`].
 #[derive(Debug)]
 pub struct Battery {
     pub is_present: bool,
@@ -34,10 +72,17 @@ pub struct Battery {
     pub health_percentage: f64,
     pub temperature: f64,
 
+    /// Readings behind [`time_to_empty`](Battery::time_to_empty) and the other estimates
+    readings: ChargeReadings,
+    /// When the fields were last read from the registry
+    refreshed_at: Option<(SystemTime, Instant)>,
+    /// Pre-resolved `AppleSmartBattery` service, read instead of looking the service up
+    service: Option<Arc<IOServiceHandle>>,
+
     #[cfg(not(test))]
-    iokit: Box<dyn IOKit>,
+    iokit: Arc<dyn IOKit>,
     #[cfg(test)]
-    pub iokit: Box<dyn IOKit>,
+    pub iokit: Arc<dyn IOKit>,
 }
 
 impl Default for Battery {
@@ -54,24 +99,183 @@ impl Battery {
     /// Returns an error if battery information cannot be retrieved from the system.
     pub fn new() -> Result<Self> {
         let mut battery = Self::default();
-        battery.update()?;
+        battery.refresh()?;
+        Ok(battery)
+    }
+
+    /// Subscribes to changes of the battery and the power source.
+    ///
+    /// Every subscriber shares one thread listening for IOKit power source notifications, started by the first
+    /// subscription and stopped once every receiver is dropped. Events are sent after notifications have been quiet for
+    /// [`DEBOUNCE`]; see [`BatteryEvent`] for what is reported. A receiver that falls behind gets
+    /// [`RecvError::Lagged`](tokio::sync::broadcast::error::RecvError::Lagged) and skips the oldest events.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the notification can't be registered or the thread can't be started.
+    pub fn subscribe_events() -> Result<tokio::sync::broadcast::Receiver<BatteryEvent>> {
+        events::subscribe()
+    }
+
+    /// Creates a Battery instance that reads `service` instead of looking up the `AppleSmartBattery` service, e.g. one
+    /// resolved by a privileged broker (see [`hardware::iokit::connection`](crate::hardware::iokit::connection)).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the properties of the service can't be read.
+    pub fn with_service(service: IOServiceHandle) -> Result<Self> {
+        let mut battery = Self { service: Some(Arc::new(service)), ..Self::default() };
+        battery.refresh()?;
+        Ok(battery)
+    }
+
+    /// Creates a Battery instance that reads through `iokit`, shared with the other monitors holding it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if battery information cannot be retrieved from the system.
+    pub fn with_shared_iokit(iokit: Arc<dyn IOKit>) -> Result<Self> {
+        let mut battery = Self { iokit, ..Self::default() };
+        battery.refresh()?;
         Ok(battery)
     }
 
-    /// Updates battery information.
+    /// Re-reads the battery state from the registry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if battery information cannot be retrieved from the system. The previous values are kept in
+    /// that case.
+    pub fn refresh(&mut self) -> Result<()> {
+        self.read_properties()?;
+        self.refreshed_at = Some((SystemTime::now(), Instant::now()));
+        Ok(())
+    }
+
+    /// Updates battery information. Same as [`refresh`](Battery::refresh).
     ///
     /// # Errors
     ///
     /// Returns an error if battery information cannot be retrieved from the system.
     pub fn update(&mut self) -> Result<()> {
-        let matching = self.iokit.io_service_matching("AppleSmartBattery");
-        let service = self.iokit.io_service_get_matching_service(&matching);
+        self.refresh()
+    }
 
-        let Some(service) = service else {
-            return Err(Error::service_not_found("Battery service not found".to_string()));
-        };
+    /// When the battery state was last read from the registry.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<SystemTime>` - The time of the last successful refresh, or None if the values were never read (e.g.
+    ///   created with [`with_values`](Battery::with_values))
+    pub fn last_refreshed(&self) -> Option<SystemTime> {
+        self.refreshed_at.map(|(time, _)| time)
+    }
+
+    /// Returns the charge percentage, refreshing first if the last refresh is older than [`MIN_REFRESH_INTERVAL`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a needed refresh fails.
+    pub fn percentage_fresh(&mut self) -> Result<f64> {
+        self.refresh_if_stale()?;
+        Ok(self.percentage)
+    }
 
-        let properties = self.iokit.io_registry_entry_create_cf_properties(&service)?;
+    /// Returns whether the battery is charging, refreshing first if the last refresh is older than
+    /// [`MIN_REFRESH_INTERVAL`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a needed refresh fails.
+    pub fn is_charging_fresh(&mut self) -> Result<bool> {
+        self.refresh_if_stale()?;
+        Ok(self.is_charging)
+    }
+
+    /// Returns the estimated time remaining, refreshing first if the last refresh is older than
+    /// [`MIN_REFRESH_INTERVAL`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a needed refresh fails.
+    pub fn time_remaining_fresh(&mut self) -> Result<Duration> {
+        self.refresh_if_stale()?;
+        Ok(self.time_remaining)
+    }
+
+    /// Returns the power source, refreshing first if the last refresh is older than [`MIN_REFRESH_INTERVAL`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a needed refresh fails.
+    pub fn power_source_fresh(&mut self) -> Result<PowerSource> {
+        self.refresh_if_stale()?;
+        Ok(self.power_source)
+    }
+
+    /// Estimated time until the battery is empty, refreshing first if the last refresh is older than
+    /// [`MIN_REFRESH_INTERVAL`].
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Duration>` - The estimate, or None if the battery isn't discharging or macOS is still calculating it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a needed refresh fails.
+    pub fn time_to_empty(&mut self) -> Result<Option<Duration>> {
+        self.refresh_if_stale()?;
+        Ok(self.readings.time_to_empty)
+    }
+
+    /// Estimated time until the battery is full, refreshing first if the last refresh is older than
+    /// [`MIN_REFRESH_INTERVAL`].
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Duration>` - The estimate, or None if the battery isn't charging, e.g. on AC power and full, or macOS
+    ///   is still calculating it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a needed refresh fails.
+    pub fn time_to_full(&mut self) -> Result<Option<Duration>> {
+        self.refresh_if_stale()?;
+        Ok(self.readings.time_to_full)
+    }
+
+    /// Current flowing into the battery in milliamperes as of the last refresh, negative while discharging, or None if
+    /// it isn't reported.
+    pub fn current_amperage_ma(&self) -> Option<i64> {
+        self.readings.amperage_ma
+    }
+
+    /// Battery voltage in millivolts as of the last refresh, or None if it isn't reported.
+    pub fn voltage_mv(&self) -> Option<i64> {
+        self.readings.voltage_mv
+    }
+
+    fn refresh_if_stale(&mut self) -> Result<()> {
+        match self.refreshed_at {
+            Some((_, at)) if at.elapsed() < MIN_REFRESH_INTERVAL => Ok(()),
+            _ => self.refresh(),
+        }
+    }
+
+    fn read_properties(&mut self) -> Result<()> {
+        let properties = match &self.service {
+            Some(service) => self.iokit.io_registry_entry_properties(service.as_raw())?,
+            None => {
+                let matching = self.iokit.io_service_matching("AppleSmartBattery");
+                let service = self.iokit.io_service_get_matching_service(&matching);
+
+                let Some(service) = service else {
+                    return Err(Error::service_not_found("Battery service not found".to_string()));
+                };
+
+                self.iokit.io_registry_entry_create_cf_properties(&service)?
+            },
+        };
 
         self.is_present =
             self.iokit.get_bool_property(&properties, BATTERY_IS_PRESENT).unwrap_or(false);
@@ -84,6 +288,7 @@ impl Battery {
             self.cycle_count = 0;
             self.health_percentage = 0.0;
             self.temperature = 0.0;
+            self.readings = ChargeReadings::default();
             return Ok(());
         }
 
@@ -98,20 +303,45 @@ impl Battery {
                 as f64;
         let max =
             self.iokit.get_number_property(&properties, BATTERY_MAX_CAPACITY).unwrap_or(100) as f64;
-        self.percentage = if max > 0.0 { (current / max * 100.0).clamp(0.0, 100.0) } else { 0.0 };
+        self.percentage = Percentage::from_ratio(current, max).value();
 
         let design = self
             .iokit
             .get_number_property(&properties, BATTERY_DESIGN_CAPACITY)
             .unwrap_or(max as i64) as f64;
-        self.health_percentage =
-            if design > 0.0 { (max / design * 100.0).clamp(0.0, 100.0) } else { 0.0 };
+        self.health_percentage = Percentage::from_ratio(max, design).value();
 
         self.cycle_count =
             self.iokit.get_number_property(&properties, BATTERY_CYCLE_COUNT).unwrap_or(0) as u32;
 
-        let time = self.iokit.get_number_property(&properties, BATTERY_TIME_REMAINING).unwrap_or(0);
-        self.time_remaining = Duration::from_secs((time.max(0) * 60) as u64);
+        let number = |key| self.iokit.get_number_property(&properties, key);
+        let time_remaining = number(BATTERY_TIME_REMAINING).and_then(reported_minutes);
+        self.time_remaining = time_remaining.unwrap_or_default();
+
+        let amperage_ma = number(BATTERY_INSTANT_AMPERAGE).or_else(|| number(BATTERY_AMPERAGE));
+        let raw_current = number(BATTERY_RAW_CURRENT_CAPACITY);
+        let raw_max = number(BATTERY_RAW_MAX_CAPACITY);
+        let discharging = !self.is_charging && self.power_source == PowerSource::Battery;
+
+        // Prefer the averaged estimates, then the instant one, then the capacity left at the current draw
+        let time_to_empty = discharging.then(|| {
+            number(BATTERY_AVG_TIME_TO_EMPTY)
+                .and_then(reported_minutes)
+                .or(time_remaining)
+                .or_else(|| derived_time(raw_current?, amperage_ma?.checked_neg()?))
+        });
+        let time_to_full = self.is_charging.then(|| {
+            number(BATTERY_AVG_TIME_TO_FULL)
+                .and_then(reported_minutes)
+                .or(time_remaining)
+                .or_else(|| derived_time(raw_max? - raw_current?, amperage_ma?))
+        });
+        self.readings = ChargeReadings {
+            time_to_empty: time_to_empty.flatten(),
+            time_to_full: time_to_full.flatten(),
+            amperage_ma,
+            voltage_mv: number(BATTERY_VOLTAGE),
+        };
 
         let temp =
             self.iokit.get_number_property(&properties, BATTERY_TEMPERATURE).unwrap_or(0) as f64;
@@ -126,6 +356,11 @@ impl Battery {
     ///
     /// Returns an error if battery information cannot be retrieved from the system.
     pub fn get_info(&self) -> Result<Self> {
+        if let Some(service) = &self.service {
+            self.iokit.io_registry_entry_properties(service.as_raw())?;
+            return Ok(self.clone());
+        }
+
         let matching = self.iokit.io_service_matching("AppleSmartBattery");
         let service = self.iokit.io_service_get_matching_service(&matching);
 
@@ -160,7 +395,10 @@ impl Battery {
             cycle_count,
             health_percentage: health_percentage.clamp(0.0, 100.0),
             temperature,
-            iokit: Box::new(IOKitImpl),
+            readings: ChargeReadings::default(),
+            refreshed_at: None,
+            service: None,
+            iokit: Arc::new(IOKitImpl::default()),
         }
     }
 
@@ -204,6 +442,17 @@ impl Battery {
     }
 }
 
+/// A time estimate in minutes as reported by the battery, or None while macOS is still calculating it
+fn reported_minutes(minutes: i64) -> Option<Duration> {
+    (0..TIME_CALCULATING).contains(&minutes).then(|| Duration::from_secs(minutes as u64 * 60))
+}
+
+/// Time to move `capacity_mah` at `amperage_ma`, or None if no current flows that way
+fn derived_time(capacity_mah: i64, amperage_ma: i64) -> Option<Duration> {
+    (capacity_mah >= 0 && amperage_ma > 0)
+        .then(|| Duration::from_secs_f64(capacity_mah as f64 / amperage_ma as f64 * 3600.0))
+}
+
 impl Clone for Battery {
     fn clone(&self) -> Self {
         Self {
@@ -215,7 +464,10 @@ impl Clone for Battery {
             cycle_count: self.cycle_count,
             health_percentage: self.health_percentage,
             temperature: self.temperature,
-            iokit: Box::new(IOKitImpl),
+            readings: self.readings,
+            refreshed_at: self.refreshed_at,
+            service: self.service.clone(),
+            iokit: Arc::clone(&self.iokit),
         }
     }
 }
@@ -1,15 +1,41 @@
 use super::*;
-use crate::hardware::iokit::{FanInfo, GpuStats, ThermalInfo};
-use crate::utils::test_utils::{create_test_dictionary, create_test_object};
+use crate::hardware::iokit::{
+    CpuTemperatureSource, FanInfo, GpuStats, IOServiceHandle, ThermalInfo,
+};
+use crate::utils::test_utils::{create_test_dictionary, create_test_service};
 use objc2::rc::Retained;
-use objc2::runtime::AnyObject;
 use objc2_foundation::{NSDictionary, NSObject, NSString};
+use std::collections::HashMap;
 use std::os::raw::c_char;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::Arc;
 
 // Manual mock implementation of IOKit for testing
 #[derive(Debug)]
 struct MockIOKit {
     is_battery_present: bool,
+    /// Scripted `CurrentCapacity` value, shared with the test
+    current_capacity: Arc<AtomicI64>,
+    /// Number of registry property reads
+    reads: Arc<AtomicUsize>,
+    /// Number of service lookups
+    lookups: Arc<AtomicUsize>,
+    /// Number and bool properties overriding the defaults below
+    numbers: HashMap<&'static str, i64>,
+    bools: HashMap<&'static str, bool>,
+}
+
+impl MockIOKit {
+    fn new(is_battery_present: bool) -> Self {
+        Self {
+            is_battery_present,
+            current_capacity: Arc::new(AtomicI64::new(75)),
+            reads: Arc::new(AtomicUsize::new(0)),
+            lookups: Arc::new(AtomicUsize::new(0)),
+            numbers: HashMap::new(),
+            bools: HashMap::new(),
+        }
+    }
 }
 
 impl IOKit for MockIOKit {
@@ -17,24 +43,32 @@ impl IOKit for MockIOKit {
         &self,
         _service_name: &str,
     ) -> Retained<NSDictionary<NSString, NSObject>> {
+        self.lookups.fetch_add(1, Ordering::SeqCst);
         create_test_dictionary()
     }
 
     fn io_service_get_matching_service(
         &self,
         _matching: &NSDictionary<NSString, NSObject>,
-    ) -> Option<Retained<AnyObject>> {
-        Some(create_test_object().into())
+    ) -> Option<IOServiceHandle> {
+        Some(create_test_service())
     }
 
     fn io_registry_entry_create_cf_properties(
         &self,
-        _entry: &AnyObject,
+        _entry: &IOServiceHandle,
     ) -> Result<Retained<NSDictionary<NSString, NSObject>>> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
         Ok(create_test_dictionary())
     }
 
-    fn io_object_release(&self, _obj: &AnyObject) {}
+    fn io_registry_entry_properties(
+        &self,
+        _entry: u32,
+    ) -> Result<Retained<NSDictionary<NSString, NSObject>>> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        Ok(create_test_dictionary())
+    }
 
     fn get_string_property(
         &self,
@@ -49,8 +83,11 @@ impl IOKit for MockIOKit {
         _dict: &NSDictionary<NSString, NSObject>,
         key: &str,
     ) -> Option<i64> {
+        if let Some(value) = self.numbers.get(key) {
+            return Some(*value);
+        }
         match key {
-            BATTERY_CURRENT_CAPACITY => Some(75),
+            BATTERY_CURRENT_CAPACITY => Some(self.current_capacity.load(Ordering::SeqCst)),
             BATTERY_MAX_CAPACITY => Some(100),
             BATTERY_DESIGN_CAPACITY => Some(110),
             BATTERY_CYCLE_COUNT => Some(250),
@@ -65,6 +102,9 @@ impl IOKit for MockIOKit {
         _dict: &NSDictionary<NSString, NSObject>,
         key: &str,
     ) -> Option<bool> {
+        if let Some(value) = self.bools.get(key) {
+            return Some(*value);
+        }
         match key {
             BATTERY_IS_PRESENT => Some(self.is_battery_present),
             BATTERY_IS_CHARGING => Some(true),
@@ -81,12 +121,12 @@ impl IOKit for MockIOKit {
         Some(create_test_dictionary())
     }
 
-    fn get_service(&self, _name: &str) -> Result<Retained<AnyObject>> {
-        Ok(create_test_object().into())
+    fn get_service(&self, _name: &str) -> Result<IOServiceHandle> {
+        Ok(create_test_service())
     }
 
-    fn io_registry_entry_get_parent(&self, _entry: &AnyObject) -> Option<Retained<AnyObject>> {
-        Some(create_test_object().into())
+    fn io_registry_entry_get_parent(&self, _entry: &IOServiceHandle) -> Option<IOServiceHandle> {
+        Some(create_test_service())
     }
 
     // Temperature related methods
@@ -119,7 +159,13 @@ impl IOKit for MockIOKit {
     }
 
     fn get_fan_info(&self, _fan_index: u32) -> Result<FanInfo> {
-        Ok(FanInfo { speed_rpm: 1500, min_speed: 500, max_speed: 5000, percentage: 30.0 })
+        Ok(FanInfo {
+            speed_rpm: 1500,
+            min_speed: 500,
+            max_speed: 5000,
+            percentage: 30.0,
+            ..Default::default()
+        })
     }
 
     fn get_all_fans(&self) -> Result<Vec<FanInfo>> {
@@ -150,12 +196,14 @@ impl IOKit for MockIOKit {
     fn get_thermal_info(&self) -> Result<ThermalInfo> {
         Ok(ThermalInfo {
             cpu_temp: 45.0,
-            gpu_temp: 40.0,
+            gpu_temp: Some(40.0),
             heatsink_temp: Some(35.0),
             ambient_temp: Some(25.0),
             battery_temp: Some(32.0),
             is_throttling: false,
             cpu_power: Some(15.0),
+            cpu_temp_source: CpuTemperatureSource::Smc,
+            ..Default::default()
         })
     }
 
@@ -166,7 +214,7 @@ impl IOKit for MockIOKit {
 
 // Helper function to create a battery with mock IOKit
 fn create_mock_battery(is_present: bool) -> Battery {
-    let mock_iokit = MockIOKit { is_battery_present: is_present };
+    let mock_iokit = MockIOKit::new(is_present);
 
     Battery {
         is_present,
@@ -177,7 +225,10 @@ fn create_mock_battery(is_present: bool) -> Battery {
         cycle_count: 250,
         health_percentage: 90.909_090_909_090_92,
         temperature: 32.0,
-        iokit: Box::new(mock_iokit),
+        readings: ChargeReadings::default(),
+        refreshed_at: None,
+        service: None,
+        iokit: Arc::new(mock_iokit),
     }
 }
 
@@ -352,3 +403,176 @@ fn test_battery_eq() {
     battery2.percentage = 50.0;
     assert!(battery1 != battery2);
 }
+
+#[test]
+fn test_battery_history_rate_per_hour() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let mut history = BatteryHistory::new(16);
+    assert_eq!(history.rate_per_hour(), None);
+
+    let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    history.record_at(start, 80.0).unwrap();
+    history.record_at(start + Duration::from_secs(1800), 75.0).unwrap();
+    history.record_at(start + Duration::from_secs(3600), 70.0).unwrap();
+
+    assert_eq!(history.history().len(), 3);
+    assert_eq!(history.rate_per_hour(), Some(-10.0));
+}
+
+#[test]
+fn test_battery_history_resumes_from_store() {
+    use crate::history::{HistoryStore, ResumePolicy};
+
+    let path =
+        std::env::temp_dir().join(format!("darwin-metrics-battery-history-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    {
+        let store = HistoryStore::open(&path, 32).unwrap();
+        let mut history = BatteryHistory::with_store(32, store, ResumePolicy::default()).unwrap();
+        history.record(55.0).unwrap();
+        history.record(54.0).unwrap();
+    }
+
+    let store = HistoryStore::open(&path, 32).unwrap();
+    let history = BatteryHistory::with_store(32, store, ResumePolicy::default()).unwrap();
+    assert_eq!(history.history().len(), 2);
+    assert_eq!(history.history().latest().map(|s| s.value), Some(54.0));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_battery_getters_reflect_changes_only_after_refresh() {
+    let mock_iokit = MockIOKit::new(true);
+    let capacity = Arc::clone(&mock_iokit.current_capacity);
+    let mut battery = Battery { iokit: Arc::new(mock_iokit), ..Battery::default() };
+    assert_eq!(battery.last_refreshed(), None);
+
+    battery.refresh().unwrap();
+    assert_eq!(battery.percentage, 75.0);
+    let first_refresh = battery.last_refreshed().expect("refresh should be recorded");
+
+    // The charge changes, but the snapshot doesn't until the next refresh
+    capacity.store(40, Ordering::SeqCst);
+    assert_eq!(battery.percentage, 75.0);
+
+    battery.refresh().unwrap();
+    assert_eq!(battery.percentage, 40.0);
+    assert!(battery.last_refreshed().unwrap() >= first_refresh);
+}
+
+#[test]
+fn test_battery_fresh_getters_are_rate_limited() {
+    let mock_iokit = MockIOKit::new(true);
+    let capacity = Arc::clone(&mock_iokit.current_capacity);
+    let reads = Arc::clone(&mock_iokit.reads);
+    let mut battery = Battery { iokit: Arc::new(mock_iokit), ..Battery::default() };
+
+    // Never refreshed, so the first call reads the registry
+    assert_eq!(battery.percentage_fresh().unwrap(), 75.0);
+    assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+    // Within the rate limit the cached value is returned
+    capacity.store(60, Ordering::SeqCst);
+    assert_eq!(battery.percentage_fresh().unwrap(), 75.0);
+    assert!(battery.is_charging_fresh().unwrap());
+    assert_eq!(battery.power_source_fresh().unwrap(), PowerSource::AC);
+    assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+    // Once the last refresh is older than the interval, the registry is read again
+    let (time, at) = battery.refreshed_at.unwrap();
+    battery.refreshed_at = Some((time, at - MIN_REFRESH_INTERVAL));
+    assert_eq!(battery.percentage_fresh().unwrap(), 60.0);
+    assert_eq!(battery.time_remaining_fresh().unwrap(), Duration::from_secs(180 * 60));
+    assert_eq!(reads.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_battery_with_injected_service() {
+    use crate::hardware::iokit::{HandleOwnership, IOServiceHandle};
+
+    static RELEASED: AtomicUsize = AtomicUsize::new(0);
+    fn release(_service: u32) {
+        RELEASED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let mock_iokit = MockIOKit::new(true);
+    let lookups = Arc::clone(&mock_iokit.lookups);
+    let reads = Arc::clone(&mock_iokit.reads);
+    let service = IOServiceHandle::with_release(42, HandleOwnership::Owned, release);
+    let mut battery = Battery {
+        service: Some(Arc::new(service)),
+        iokit: Arc::new(mock_iokit),
+        ..Battery::default()
+    };
+
+    battery.refresh().unwrap();
+    battery.get_info().unwrap();
+    assert_eq!(battery.percentage, 75.0);
+    assert_eq!(reads.load(Ordering::SeqCst), 2);
+    assert_eq!(lookups.load(Ordering::SeqCst), 0, "The injected service must not be looked up");
+
+    // Clones share the service, which is released with the last of them
+    let clone = battery.clone();
+    drop(battery);
+    assert_eq!(RELEASED.load(Ordering::SeqCst), 0);
+    drop(clone);
+    assert_eq!(RELEASED.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_battery_time_estimates_while_charging() {
+    let mut mock_iokit = MockIOKit::new(true);
+    mock_iokit.numbers.insert(BATTERY_AVG_TIME_TO_FULL, 45);
+    mock_iokit.numbers.insert(BATTERY_INSTANT_AMPERAGE, 1_500);
+    mock_iokit.numbers.insert(BATTERY_VOLTAGE, 12_600);
+    let mut battery = Battery { iokit: Arc::new(mock_iokit), ..Battery::default() };
+
+    assert_eq!(battery.time_to_full().unwrap(), Some(Duration::from_secs(45 * 60)));
+    assert_eq!(battery.time_to_empty().unwrap(), None, "A charging battery isn't emptying");
+    assert_eq!(battery.current_amperage_ma(), Some(1_500));
+    assert_eq!(battery.voltage_mv(), Some(12_600));
+}
+
+#[test]
+fn test_battery_time_estimates_while_calculating() {
+    let mut mock_iokit = MockIOKit::new(true);
+    mock_iokit.bools.insert(BATTERY_IS_CHARGING, false);
+    mock_iokit.bools.insert(BATTERY_POWER_SOURCE, false);
+    mock_iokit.numbers.insert(BATTERY_TIME_REMAINING, TIME_CALCULATING);
+    mock_iokit.numbers.insert(BATTERY_AVG_TIME_TO_EMPTY, TIME_CALCULATING);
+    let mut battery = Battery { iokit: Arc::new(mock_iokit), ..Battery::default() };
+
+    assert_eq!(battery.time_to_empty().unwrap(), None);
+    assert_eq!(battery.time_remaining, Duration::ZERO, "Not a 45 day estimate");
+    assert_eq!(battery.time_to_full().unwrap(), None);
+    assert_eq!(battery.current_amperage_ma(), None);
+}
+
+#[test]
+fn test_battery_time_to_empty_from_capacity() {
+    let mut mock_iokit = MockIOKit::new(true);
+    mock_iokit.bools.insert(BATTERY_IS_CHARGING, false);
+    mock_iokit.bools.insert(BATTERY_POWER_SOURCE, false);
+    mock_iokit.numbers.insert(BATTERY_TIME_REMAINING, TIME_CALCULATING);
+    mock_iokit.numbers.insert(BATTERY_AMPERAGE, -2_000);
+    mock_iokit.numbers.insert(BATTERY_RAW_CURRENT_CAPACITY, 3_000);
+    let mut battery = Battery { iokit: Arc::new(mock_iokit), ..Battery::default() };
+
+    assert_eq!(battery.time_to_empty().unwrap(), Some(Duration::from_secs(90 * 60)));
+    assert_eq!(battery.current_amperage_ma(), Some(-2_000));
+}
+
+#[test]
+fn test_battery_time_estimate_helpers() {
+    assert_eq!(reported_minutes(0), Some(Duration::ZERO));
+    assert_eq!(reported_minutes(135), Some(Duration::from_secs(135 * 60)));
+    assert_eq!(reported_minutes(TIME_CALCULATING), None);
+    assert_eq!(reported_minutes(-1), None);
+
+    assert_eq!(derived_time(1_000, 2_000), Some(Duration::from_secs(30 * 60)));
+    assert_eq!(derived_time(1_000, 0), None);
+    assert_eq!(derived_time(1_000, -500), None);
+}
@@ -0,0 +1,345 @@
+//! # Diagnostics Module
+//!
+//! Instrumentation for finding slow leaks in long-running monitors. The crate keeps a registry of named counters for
+//! the resources it holds, e.g. open SMC connections or processes in the CPU time history, which
+//! [`counters`] reads together with the built-in probes. [`sample`] adds the resident memory of the process, and a
+//! [`TrendTracker`] fits a line through successive samples to flag every quantity that keeps growing.
+//!
+//! Counters are process-wide gauges: a [`Counter`] is incremented when a resource is acquired and decremented when
+//! it's released, so its value is the number currently held.
+//!
+//! ```rust,no_run
+//! use std::{thread::sleep, time::Duration};
+//!
+//! use darwin_metrics::diagnostics::{sample, LeakThresholds, TrendTracker};
+//!
+//! let mut trends = TrendTracker::new();
+//! for _ in 0..60 {
+//!     // run the monitoring loop under test
+//!     trends.record(&sample());
+//!     sleep(Duration::from_secs(10));
+//! }
+//!
+//! for leak in trends.leaks(&LeakThresholds::default()) {
+//!     println!("{} grows by {:.1} per hour", leak.name, leak.slope_per_hour);
+//! }
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::atomic::{AtomicI64, Ordering},
+    time::Instant,
+};
+
+use libproc::pid_rusage::{pidrusage, RUsageInfoV4};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::error::{Error, Result};
+
+/// Name of the resident memory series recorded by a [`TrendTracker`]
+pub const RESIDENT_BYTES: &str = "process.resident_bytes";
+
+/// Owned SMC connections currently open
+pub const SMC_CONNECTIONS: &str = "iokit.smc_connections";
+/// Owned IOService handles not yet released
+pub const SERVICE_HANDLES: &str = "iokit.service_handles";
+/// Processes tracked by the CPU time history
+pub const CPU_HISTORY_TRACKED: &str = "process.cpu_history.tracked";
+/// Background components registered for [`crate::shutdown`] and still alive
+pub const SHUTDOWN_COMPONENTS: &str = "shutdown.components";
+
+/// Counters registered with [`counter`], by name
+static COUNTERS: Lazy<Mutex<BTreeMap<&'static str, &'static Counter>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Reads the current value of a quantity, see [`register_probe`]
+pub type Probe = fn() -> i64;
+
+/// Probes registered with [`register_probe`], by name
+static PROBES: Lazy<Mutex<BTreeMap<&'static str, Probe>>> = Lazy::new(|| {
+    let mut probes: BTreeMap<&'static str, Probe> = BTreeMap::new();
+    probes.insert(CPU_HISTORY_TRACKED, || crate::process::cpu_history_stats().tracked as i64);
+    probes.insert(SHUTDOWN_COMPONENTS, || crate::shutdown::registered_components() as i64);
+    Mutex::new(probes)
+});
+
+/// A process-wide gauge of a resource, see the [module documentation](self)
+#[derive(Debug, Default)]
+pub struct Counter(AtomicI64);
+
+impl Counter {
+    /// Counts one more held resource.
+    pub fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts one fewer held resource.
+    pub fn decrement(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Replaces the value, e.g. with the current depth of a queue.
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    /// The current value.
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns the counter named `name`, registering it on first use.
+///
+/// Counters live for the rest of the program, so names should come from a fixed set.
+pub fn counter(name: &'static str) -> &'static Counter {
+    COUNTERS.lock().entry(name).or_insert_with(|| Box::leak(Box::default()))
+}
+
+/// Registers `probe` to be read under `name` by [`counters`], replacing an earlier probe of the same name.
+///
+/// Probes suit quantities that are cheaper to read on demand than to count, such as the length of a map.
+pub fn register_probe(name: &'static str, probe: Probe) {
+    PROBES.lock().insert(name, probe);
+}
+
+/// Reads every registered counter and probe, ordered by name.
+pub fn counters() -> BTreeMap<&'static str, i64> {
+    let mut values: BTreeMap<&'static str, i64> =
+        COUNTERS.lock().iter().map(|(&name, counter)| (name, counter.get())).collect();
+    // Probes may take locks of their own, so they're called without holding the registry lock
+    let probes: Vec<(&'static str, Probe)> =
+        PROBES.lock().iter().map(|(&name, &probe)| (name, probe)).collect();
+    values.extend(probes.into_iter().map(|(name, probe)| (name, probe())));
+    values
+}
+
+/// Resident memory of the calling process in bytes.
+///
+/// # Errors
+///
+/// Returns an error if the resource usage of the process can't be read.
+pub fn resident_memory_bytes() -> Result<u64> {
+    pidrusage::<RUsageInfoV4>(std::process::id() as i32)
+        .map(|usage| usage.ri_resident_size)
+        .map_err(|e| Error::system(format!("Failed to read own resource usage: {}", e)))
+}
+
+/// The counters and resident memory of the process at one point in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagnosticsSample {
+    /// When the sample was taken
+    pub taken_at: Instant,
+    /// Resident memory in bytes, or None if it couldn't be read
+    pub resident_bytes: Option<u64>,
+    /// Values of the registered counters and probes, by name
+    pub counters: BTreeMap<&'static str, i64>,
+}
+
+/// Samples the resident memory and every registered counter and probe.
+pub fn sample() -> DiagnosticsSample {
+    DiagnosticsSample {
+        taken_at: Instant::now(),
+        resident_bytes: resident_memory_bytes().ok(),
+        counters: counters(),
+    }
+}
+
+/// Maximum growth per hour before a quantity is reported as leaking
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeakThresholds {
+    /// Threshold of the quantities without one of their own (default: 1 per hour)
+    pub default_per_hour: f64,
+    /// Thresholds by quantity name, e.g. for [`RESIDENT_BYTES`] (default: 4 MiB per hour)
+    pub per_quantity: HashMap<String, f64>,
+}
+
+impl Default for LeakThresholds {
+    fn default() -> Self {
+        Self {
+            default_per_hour: 1.0,
+            per_quantity: HashMap::from([(RESIDENT_BYTES.to_string(), 4.0 * 1024.0 * 1024.0)]),
+        }
+    }
+}
+
+impl LeakThresholds {
+    /// The threshold of the quantity named `name`.
+    pub fn for_quantity(&self, name: &str) -> f64 {
+        self.per_quantity.get(name).copied().unwrap_or(self.default_per_hour)
+    }
+}
+
+/// A quantity whose growth exceeded its threshold
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeakSuspect {
+    /// Name of the quantity
+    pub name: String,
+    /// Growth of the quantity per hour, from a least-squares fit over the recorded samples
+    pub slope_per_hour: f64,
+    /// Threshold the growth exceeded
+    pub threshold_per_hour: f64,
+}
+
+/// Series of diagnostics samples over time, for spotting quantities that keep growing
+#[derive(Debug, Clone, Default)]
+pub struct TrendTracker {
+    start: Option<Instant>,
+    series: BTreeMap<String, Vec<(f64, f64)>>,
+}
+
+impl TrendTracker {
+    /// Creates an empty tracker.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the value of `name` at `at`, e.g. the depth of a channel the caller owns.
+    pub fn record_value(&mut self, name: &str, at: Instant, value: f64) {
+        let start = *self.start.get_or_insert(at);
+        let secs = at.saturating_duration_since(start).as_secs_f64();
+        self.series.entry(name.to_string()).or_default().push((secs, value));
+    }
+
+    /// Records every quantity of a diagnostics sample.
+    pub fn record(&mut self, sample: &DiagnosticsSample) {
+        if let Some(resident) = sample.resident_bytes {
+            self.record_value(RESIDENT_BYTES, sample.taken_at, resident as f64);
+        }
+        for (name, &value) in &sample.counters {
+            self.record_value(name, sample.taken_at, value as f64);
+        }
+    }
+
+    /// Number of samples recorded for `name`.
+    pub fn sample_count(&self, name: &str) -> usize {
+        self.series.get(name).map_or(0, Vec::len)
+    }
+
+    /// Names of the recorded quantities.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.series.keys().map(String::as_str)
+    }
+
+    /// Growth per hour of every quantity with at least two samples at different times.
+    pub fn slopes_per_hour(&self) -> BTreeMap<&str, f64> {
+        self.series
+            .iter()
+            .filter_map(|(name, points)| Some((name.as_str(), slope(points)? * 3600.0)))
+            .collect()
+    }
+
+    /// The quantities growing faster than their threshold, steepest relative to its threshold first.
+    pub fn leaks(&self, thresholds: &LeakThresholds) -> Vec<LeakSuspect> {
+        let mut leaks: Vec<LeakSuspect> = self
+            .slopes_per_hour()
+            .into_iter()
+            .map(|(name, slope_per_hour)| LeakSuspect {
+                name: name.to_string(),
+                slope_per_hour,
+                threshold_per_hour: thresholds.for_quantity(name),
+            })
+            .filter(|leak| leak.slope_per_hour > leak.threshold_per_hour)
+            .collect();
+        leaks.sort_by(|a, b| {
+            let excess = |leak: &LeakSuspect| {
+                leak.slope_per_hour / leak.threshold_per_hour.max(f64::EPSILON)
+            };
+            excess(b).total_cmp(&excess(a))
+        });
+        leaks
+    }
+}
+
+/// Slope of the least-squares line through `points`, or None if they don't span more than one x value.
+pub fn slope(points: &[(f64, f64)]) -> Option<f64> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+
+    let (covariance, variance) = points.iter().fold((0.0, 0.0), |(cov, var), (x, y)| {
+        (cov + (x - mean_x) * (y - mean_y), var + (x - mean_x).powi(2))
+    });
+    (variance > 0.0).then(|| covariance / variance).filter(|slope| slope.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_slope() {
+        assert_eq!(slope(&[]), None);
+        assert_eq!(slope(&[(1.0, 5.0)]), None);
+        assert_eq!(slope(&[(1.0, 5.0), (1.0, 7.0)]), None);
+        assert_eq!(slope(&[(0.0, 1.0), (1.0, 3.0), (2.0, 5.0)]), Some(2.0));
+        assert_eq!(slope(&[(0.0, 4.0), (10.0, 4.0)]), Some(0.0));
+
+        // Noise around a flat line fits a slope near zero
+        let noisy: Vec<(f64, f64)> =
+            (0..100).map(|i| (i as f64, if i % 2 == 0 { 10.0 } else { 12.0 })).collect();
+        assert!(slope(&noisy).unwrap().abs() < 0.001);
+    }
+
+    #[test]
+    fn test_counter_registry() {
+        let counter = counter("diagnostics.test");
+        counter.increment();
+        counter.increment();
+        counter.decrement();
+        assert_eq!(counters()["diagnostics.test"], 1);
+        assert!(std::ptr::eq(counter, super::counter("diagnostics.test")));
+
+        counter.set(7);
+        assert_eq!(super::counter("diagnostics.test").get(), 7);
+    }
+
+    #[test]
+    fn test_probes() {
+        register_probe("diagnostics.test_probe", || 42);
+        let values = counters();
+        assert_eq!(values["diagnostics.test_probe"], 42);
+        assert!(values.contains_key(CPU_HISTORY_TRACKED));
+        assert!(values.contains_key(SHUTDOWN_COMPONENTS));
+    }
+
+    #[test]
+    fn test_trend_tracker_flags_growing_quantities() {
+        let start = Instant::now();
+        let mut trends = TrendTracker::new();
+        for minute in 0..=60u32 {
+            let at = start + Duration::from_secs(u64::from(minute) * 60);
+            trends.record_value("flat", at, 10.0);
+            trends.record_value("slow", at, f64::from(minute) / 60.0);
+            trends.record_value("leaking", at, f64::from(minute) * 100.0);
+            trends.record_value(RESIDENT_BYTES, at, 50e6 + f64::from(minute) * 1024.0);
+        }
+
+        let slopes = trends.slopes_per_hour();
+        assert_eq!(slopes["flat"], 0.0);
+        assert!((slopes["leaking"] - 6000.0).abs() < 1e-6);
+        assert_eq!(trends.sample_count("flat"), 61);
+
+        let mut thresholds = LeakThresholds::default();
+        thresholds.per_quantity.insert("slow".to_string(), 0.5);
+        let leaks: Vec<String> = trends.leaks(&thresholds).into_iter().map(|l| l.name).collect();
+        // 60 KiB of resident memory per hour is below the default threshold of the resident memory
+        assert_eq!(leaks, ["leaking", "slow"]);
+    }
+
+    #[test]
+    fn test_sample() {
+        let sample = sample();
+        assert!(sample.resident_bytes.is_some_and(|bytes| bytes > 0));
+
+        let mut trends = TrendTracker::new();
+        trends.record(&sample);
+        assert_eq!(trends.sample_count(RESIDENT_BYTES), 1);
+        assert!(trends.names().any(|name| name == CPU_HISTORY_TRACKED));
+        // A single sample has no slope
+        assert!(trends.leaks(&LeakThresholds::default()).is_empty());
+    }
+}
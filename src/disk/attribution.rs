@@ -0,0 +1,273 @@
+//! Approximate attribution of disk I/O to processes and devices.
+//!
+//! macOS accounts disk I/O per process and per block device, but doesn't record which device a process's I/O went
+//! to; that takes `fs_usage`-level tracing. [`io_attribution`] instead samples both sides at the start and end of a
+//! window and reports the per-device breakdown next to the processes that did the most I/O in the same window. When
+//! a single device was busy, its traffic very likely came from the top processes; with several busy devices the
+//! report can't tell which process hit which device.
+
+use std::{collections::HashMap, time::Duration};
+
+use libproc::pid_rusage::{pidrusage, RUsageInfoV4};
+
+use crate::{
+    error::Result,
+    hardware::iokit::{BlockStorageStats, IOKit, IOKitImpl},
+    utils::bindings::{extract_proc_name, list_kinfo_procs},
+};
+
+/// Explanation attached to every [`IoAttribution`]
+pub const CORRELATION_NOTE: &str = "Per-device and per-process I/O were sampled over the same window but are \
+                                    accounted separately; the processes are correlated with the devices \
+                                    statistically, not traced to them.";
+
+/// Number of processes reported in [`IoAttribution::top_processes`]
+pub const TOP_PROCESSES: usize = 10;
+
+/// Cumulative I/O counters of a block device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DeviceIoCounters {
+    /// BSD name of the whole device, e.g. `disk0`
+    pub device: String,
+    /// Whether the device is built in, or None if it isn't reported
+    pub is_internal: Option<bool>,
+    /// Total bytes read
+    pub bytes_read: u64,
+    /// Total bytes written
+    pub bytes_written: u64,
+}
+
+impl From<BlockStorageStats> for DeviceIoCounters {
+    fn from(stats: BlockStorageStats) -> Self {
+        Self {
+            device: stats.device,
+            is_internal: stats.is_internal,
+            bytes_read: stats.bytes_read,
+            bytes_written: stats.bytes_written,
+        }
+    }
+}
+
+/// Cumulative disk I/O counters of a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ProcessIoCounters {
+    /// Process ID
+    pub pid: u32,
+    /// Start time of the process in Mach absolute time units, telling apart processes that reuse a PID
+    pub start_time: u64,
+    /// Process name
+    pub name: String,
+    /// Total bytes read from disk
+    pub bytes_read: u64,
+    /// Total bytes written to disk
+    pub bytes_written: u64,
+}
+
+/// Provides the cumulative I/O counters of every block device
+pub(crate) trait DeviceIoSampler {
+    /// Reads the current counters.
+    fn sample(&mut self) -> Result<Vec<DeviceIoCounters>>;
+}
+
+/// Provides the cumulative disk I/O counters of every process
+pub(crate) trait ProcessIoSampler {
+    /// Reads the current counters.
+    fn sample(&mut self) -> Result<Vec<ProcessIoCounters>>;
+}
+
+/// I/O of a block device during the window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeIoDelta {
+    /// BSD name of the whole device, e.g. `disk0`
+    pub device: String,
+    /// Whether the device is built in, or None if it isn't reported
+    pub is_internal: Option<bool>,
+    /// Bytes read during the window
+    pub bytes_read: u64,
+    /// Bytes written during the window
+    pub bytes_written: u64,
+}
+
+impl VolumeIoDelta {
+    /// Bytes read and written during the window
+    pub fn total_bytes(&self) -> u64 {
+        self.bytes_read + self.bytes_written
+    }
+}
+
+/// Disk I/O of a process during the window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessIoDelta {
+    /// Process ID
+    pub pid: u32,
+    /// Process name
+    pub name: String,
+    /// Bytes read from disk during the window
+    pub bytes_read: u64,
+    /// Bytes written to disk during the window
+    pub bytes_written: u64,
+}
+
+impl ProcessIoDelta {
+    /// Bytes read and written during the window
+    pub fn total_bytes(&self) -> u64 {
+        self.bytes_read + self.bytes_written
+    }
+}
+
+/// Per-device I/O and the processes doing the most I/O over the same window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IoAttribution {
+    /// Every block device, busiest first
+    pub per_volume: Vec<VolumeIoDelta>,
+    /// Up to [`TOP_PROCESSES`] processes that did disk I/O, busiest first
+    pub top_processes: Vec<ProcessIoDelta>,
+    /// Reminder that the devices and processes are only correlated, see [`CORRELATION_NOTE`]
+    pub correlation_note: &'static str,
+    /// Length of the window
+    pub window: Duration,
+}
+
+/// Samples disk I/O per device and per process over `window` and reports both side by side.
+///
+/// The mapping between the two is statistical, not causal: the report shows which devices were busy and which
+/// processes did I/O during the same window, not which process accessed which device. Processes owned by other users
+/// are only included when running as root. Blocks the calling thread for `window`.
+///
+/// # Errors
+///
+/// Returns an error if the device or process counters can't be read.
+pub fn io_attribution(window: Duration) -> Result<IoAttribution> {
+    attribute(&mut BlockStorageSampler, &mut RusageSampler, window, std::thread::sleep)
+}
+
+/// Samples both sides, waits for the window with `wait` and samples them again.
+pub(crate) fn attribute<D, P>(
+    devices: &mut D,
+    processes: &mut P,
+    window: Duration,
+    wait: impl FnOnce(Duration),
+) -> Result<IoAttribution>
+where
+    D: DeviceIoSampler + ?Sized,
+    P: ProcessIoSampler + ?Sized,
+{
+    let devices_start = devices.sample()?;
+    let processes_start = processes.sample()?;
+    wait(window);
+    let processes_end = processes.sample()?;
+    let devices_end = devices.sample()?;
+
+    let mut top_processes = process_deltas(&processes_start, &processes_end);
+    top_processes.truncate(TOP_PROCESSES);
+
+    Ok(IoAttribution {
+        per_volume: device_deltas(&devices_start, &devices_end),
+        top_processes,
+        correlation_note: CORRELATION_NOTE,
+        window,
+    })
+}
+
+/// Aligns device counters by name, busiest device first.
+///
+/// Devices attached during the window count from zero, devices detached during it are left out.
+pub(crate) fn device_deltas(
+    start: &[DeviceIoCounters],
+    end: &[DeviceIoCounters],
+) -> Vec<VolumeIoDelta> {
+    let start: HashMap<&str, &DeviceIoCounters> =
+        start.iter().map(|counters| (counters.device.as_str(), counters)).collect();
+
+    let mut deltas: Vec<VolumeIoDelta> = end
+        .iter()
+        .map(|counters| {
+            let previous = start.get(counters.device.as_str());
+            VolumeIoDelta {
+                device: counters.device.clone(),
+                is_internal: counters.is_internal,
+                bytes_read: counters
+                    .bytes_read
+                    .saturating_sub(previous.map_or(0, |previous| previous.bytes_read)),
+                bytes_written: counters
+                    .bytes_written
+                    .saturating_sub(previous.map_or(0, |previous| previous.bytes_written)),
+            }
+        })
+        .collect();
+
+    deltas.sort_by(|a, b| {
+        b.total_bytes().cmp(&a.total_bytes()).then_with(|| a.device.cmp(&b.device))
+    });
+    deltas
+}
+
+/// Aligns process counters by PID and start time, busiest process first.
+///
+/// Processes started during the window count from zero, processes that exited during it are left out, and so are
+/// processes that did no I/O.
+pub(crate) fn process_deltas(
+    start: &[ProcessIoCounters],
+    end: &[ProcessIoCounters],
+) -> Vec<ProcessIoDelta> {
+    let start: HashMap<(u32, u64), &ProcessIoCounters> =
+        start.iter().map(|counters| ((counters.pid, counters.start_time), counters)).collect();
+
+    let mut deltas: Vec<ProcessIoDelta> = end
+        .iter()
+        .map(|counters| {
+            let previous = start.get(&(counters.pid, counters.start_time));
+            ProcessIoDelta {
+                pid: counters.pid,
+                name: counters.name.clone(),
+                bytes_read: counters
+                    .bytes_read
+                    .saturating_sub(previous.map_or(0, |previous| previous.bytes_read)),
+                bytes_written: counters
+                    .bytes_written
+                    .saturating_sub(previous.map_or(0, |previous| previous.bytes_written)),
+            }
+        })
+        .filter(|delta| delta.total_bytes() > 0)
+        .collect();
+
+    deltas.sort_by(|a, b| b.total_bytes().cmp(&a.total_bytes()).then_with(|| a.pid.cmp(&b.pid)));
+    deltas
+}
+
+/// Reads the statistics of the block storage drivers in the IORegistry
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct BlockStorageSampler;
+
+impl DeviceIoSampler for BlockStorageSampler {
+    fn sample(&mut self) -> Result<Vec<DeviceIoCounters>> {
+        let stats = IOKitImpl::default().get_block_storage_stats()?;
+        Ok(stats.into_iter().map(DeviceIoCounters::from).collect())
+    }
+}
+
+/// Reads the disk I/O accounted to each process with `proc_pid_rusage`
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RusageSampler;
+
+impl ProcessIoSampler for RusageSampler {
+    fn sample(&mut self) -> Result<Vec<ProcessIoCounters>> {
+        let processes = list_kinfo_procs()?;
+
+        Ok(processes
+            .iter()
+            .filter(|process| process.pid() > 0)
+            .filter_map(|process| {
+                // Processes of other users can't be inspected without root
+                let usage = pidrusage::<RUsageInfoV4>(process.pid()).ok()?;
+                Some(ProcessIoCounters {
+                    pid: process.pid() as u32,
+                    start_time: usage.ri_proc_start_abstime,
+                    name: extract_proc_name(process),
+                    bytes_read: usage.ri_diskio_bytesread,
+                    bytes_written: usage.ri_diskio_byteswritten,
+                })
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,136 @@
+use super::{Disk, DiskType};
+use crate::{Error, Result};
+
+/// Fraction of the total capacity by which `used + available` may exceed it
+///
+/// Filesystems round their block counts independently, so the sum can be slightly over the total on healthy volumes.
+const CAPACITY_TOLERANCE: f64 = 0.01;
+
+/// Builder for [`Disk`] with named setters and validation
+///
+/// Unless set explicitly, the disk type is inferred from the filesystem type with [`DiskType::from_fs_type`].
+///
+/// ```
+/// use darwin_metrics::disk::{Disk, DiskType};
+///
+/// let disk = Disk::builder()
+///     .device("/dev/disk3s1")
+///     .mount_point("/")
+///     .fs_type("apfs")
+///     .total(500 * 1024 * 1024 * 1024)
+///     .used(200 * 1024 * 1024 * 1024)
+///     .available(300 * 1024 * 1024 * 1024)
+///     .name("Macintosh HD")
+///     .boot_volume(true)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(disk.disk_type, DiskType::SSD);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DiskBuilder {
+    device: String,
+    mount_point: String,
+    fs_type: String,
+    total: u64,
+    available: u64,
+    used: u64,
+    disk_type: Option<DiskType>,
+    name: String,
+    is_boot_volume: bool,
+}
+
+impl DiskBuilder {
+    /// Creates an empty builder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the device identifier (e.g., /dev/disk1s1)
+    pub fn device(mut self, device: impl Into<String>) -> Self {
+        self.device = device.into();
+        self
+    }
+
+    /// Sets the mount point path (e.g., /)
+    pub fn mount_point(mut self, mount_point: impl Into<String>) -> Self {
+        self.mount_point = mount_point.into();
+        self
+    }
+
+    /// Sets the filesystem type (e.g., apfs, hfs)
+    pub fn fs_type(mut self, fs_type: impl Into<String>) -> Self {
+        self.fs_type = fs_type.into();
+        self
+    }
+
+    /// Sets the total capacity in bytes
+    pub fn total(mut self, total: u64) -> Self {
+        self.total = total;
+        self
+    }
+
+    /// Sets the available space in bytes
+    pub fn available(mut self, available: u64) -> Self {
+        self.available = available;
+        self
+    }
+
+    /// Sets the used space in bytes
+    pub fn used(mut self, used: u64) -> Self {
+        self.used = used;
+        self
+    }
+
+    /// Sets the disk type instead of inferring it from the filesystem type
+    pub fn disk_type(mut self, disk_type: DiskType) -> Self {
+        self.disk_type = Some(disk_type);
+        self
+    }
+
+    /// Sets the volume name (e.g., "Macintosh HD")
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets whether this is the boot volume
+    pub fn boot_volume(mut self, is_boot_volume: bool) -> Self {
+        self.is_boot_volume = is_boot_volume;
+        self
+    }
+
+    /// Validates the fields and builds the disk
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid data error naming the offending field if the mount point is empty or `used + available`
+    /// exceeds `total` by more than rounding allows.
+    pub fn build(self) -> Result<Disk> {
+        if self.mount_point.is_empty() {
+            return Err(Error::invalid_data("Disk mount_point must not be empty"));
+        }
+
+        let allowed = self.total as f64 * (1.0 + CAPACITY_TOLERANCE);
+        if self.used as f64 + self.available as f64 > allowed {
+            return Err(Error::invalid_data(format!(
+                "Disk used ({}) plus available ({}) exceeds total ({}) for {}",
+                self.used, self.available, self.total, self.mount_point
+            )));
+        }
+
+        let disk_type = self.disk_type.unwrap_or_else(|| DiskType::from_fs_type(&self.fs_type));
+
+        Ok(Disk {
+            device: self.device,
+            mount_point: self.mount_point,
+            fs_type: self.fs_type,
+            total: self.total,
+            available: self.available,
+            used: self.used,
+            disk_type,
+            name: self.name,
+            is_boot_volume: self.is_boot_volume,
+        })
+    }
+}
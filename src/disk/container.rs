@@ -0,0 +1,105 @@
+//! Grouping of volumes by the APFS container they share.
+//!
+//! All volumes of an APFS container share its free space, so `statfs` reports the container's capacity and free space
+//! for each of them: summing the free space of `/`, `/System/Volumes/Data`, `/System/Volumes/Preboot` and the other
+//! system volumes counts the same space several times. [`DiskContainer`] groups them so it's counted once.
+//!
+//! The container is found from the BSD name of the volume: APFS volumes are named after the synthesized disk of their
+//! container, `disk3s1` and its snapshot `disk3s1s1` both being stored in `disk3`.
+
+use super::Disk;
+use crate::error::Result;
+
+/// Prefix of the snapshots macOS mounts while installing a system update
+const UPDATE_SNAPSHOT_PREFIX: &str = "com.apple.os.update-";
+
+/// Volumes sharing the free space of one APFS container
+///
+/// Volumes that aren't APFS form a container of their own.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiskContainer {
+    id: String,
+    volumes: Vec<Disk>,
+}
+
+impl DiskContainer {
+    /// Groups `volumes` by container, in the order each container is first seen.
+    pub fn group(volumes: impl IntoIterator<Item = Disk>) -> Vec<Self> {
+        let mut containers: Vec<Self> = Vec::new();
+        for volume in volumes {
+            let id = volume.apfs_container().unwrap_or_else(|| volume.device.clone());
+            match containers.iter_mut().find(|container| container.id == id) {
+                Some(container) => container.volumes.push(volume),
+                None => containers.push(Self { id, volumes: vec![volume] }),
+            }
+        }
+        containers
+    }
+
+    /// The BSD name of the APFS container, e.g. `disk3`, or the device of the volume for other filesystems.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The volumes stored in the container.
+    pub fn volumes(&self) -> &[Disk] {
+        &self.volumes
+    }
+
+    /// Total capacity of the container in bytes.
+    pub fn container_capacity(&self) -> u64 {
+        self.volumes.iter().map(|volume| volume.total).max().unwrap_or(0)
+    }
+
+    /// Free space of the container in bytes, shared by all its volumes.
+    pub fn container_free(&self) -> u64 {
+        self.volumes.iter().map(|volume| volume.available).max().unwrap_or(0)
+    }
+
+    /// Space of the container in use by any of its volumes, in bytes.
+    pub fn container_used(&self) -> u64 {
+        self.container_capacity().saturating_sub(self.container_free())
+    }
+}
+
+/// Gets the mounted volumes grouped by the APFS container they're stored in.
+///
+/// # Errors
+///
+/// Returns an error if the mounted filesystems can't be listed.
+pub fn get_containers() -> Result<Vec<DiskContainer>> {
+    Ok(DiskContainer::group(Disk::get_all()?))
+}
+
+impl Disk {
+    /// The BSD name of the APFS container the volume is stored in, e.g. `disk3` for `/dev/disk3s1s1`, or None if it
+    /// isn't an APFS volume on a local disk.
+    pub fn apfs_container(&self) -> Option<String> {
+        if self.fs_type != "apfs" {
+            return None;
+        }
+
+        let digits: String = self
+            .bsd_name()
+            .strip_prefix("disk")?
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        (!digits.is_empty()).then(|| format!("disk{digits}"))
+    }
+
+    /// Whether the volume is a snapshot mounted by macOS while installing a system update.
+    ///
+    /// Such a snapshot duplicates the system volume and is left out of the volume listings.
+    pub fn is_system_snapshot(&self) -> bool {
+        self.device.starts_with(UPDATE_SNAPSHOT_PREFIX)
+    }
+
+    /// The BSD name of the device, without the snapshot name of a snapshot mount (`snapshot@/dev/disk3s1`).
+    pub(super) fn bsd_name(&self) -> &str {
+        let device =
+            self.device.rsplit_once('@').map_or(self.device.as_str(), |(_, device)| device);
+        device.strip_prefix("/dev/").unwrap_or(device)
+    }
+}
@@ -0,0 +1,161 @@
+use std::{ffi::CString, ptr};
+
+use objc2::rc::{autoreleasepool, Retained};
+use objc2_foundation::{NSDictionary, NSObject, NSString};
+
+use super::Disk;
+use crate::{
+    error::{Error, Result},
+    utils::{
+        bindings::{
+            IOBSDNameMatching, IOObjectRelease, IORegistryEntryCreateCFProperties,
+            IOServiceGetMatchingService,
+        },
+        property_utils::{PropertyAccessor, PropertyUtils},
+    },
+};
+
+/// Mount point of the data volume on macOS 10.15 and later, which holds the user data protected by FileVault
+const DATA_VOLUME_MOUNT_POINT: &str = "/System/Volumes/Data";
+
+/// Registry property set on encrypted APFS volumes
+const ENCRYPTED_KEY: &str = "Encrypted";
+/// Registry property set while an encrypted volume hasn't been unlocked
+const LOCKED_KEY: &str = "Locked";
+/// Registry property reporting whether the volume is protected by FileVault
+const FILEVAULT_KEY: &str = "FileVault";
+
+/// Encryption state of a volume
+///
+/// Fields are None when the information isn't available or the concept doesn't apply to the volume's filesystem
+/// (for example exFAT or network volumes).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncryptionStatus {
+    /// Whether the volume is encrypted
+    pub encrypted: Option<bool>,
+    /// Whether the volume is encrypted and hasn't been unlocked
+    pub locked: Option<bool>,
+    /// Whether the volume is protected by FileVault
+    pub filevault: Option<bool>,
+}
+
+/// The encryption-related registry properties of a volume
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct VolumeProperties {
+    pub encrypted: Option<bool>,
+    pub locked: Option<bool>,
+    pub filevault: Option<bool>,
+}
+
+impl VolumeProperties {
+    /// Reads the encryption-related properties from an IORegistry property dictionary.
+    pub(crate) fn from_dictionary(dict: &NSDictionary<NSString, NSObject>) -> Self {
+        Self {
+            encrypted: PropertyAccessor::get_bool_property(dict, ENCRYPTED_KEY),
+            locked: PropertyAccessor::get_bool_property(dict, LOCKED_KEY),
+            filevault: PropertyAccessor::get_bool_property(dict, FILEVAULT_KEY),
+        }
+    }
+}
+
+/// Derives the encryption status of a volume from its filesystem type and registry properties.
+///
+/// Only APFS volumes are populated. Encryption doesn't imply FileVault, since the Data volume of Apple Silicon and T2
+/// Macs is always encrypted by the hardware, so FileVault and the lock state stay None unless the registry reports
+/// them. An unencrypted volume is neither locked nor protected by FileVault.
+pub(crate) fn encryption_status_from_properties(
+    fs_type: &str,
+    properties: &VolumeProperties,
+) -> EncryptionStatus {
+    if !fs_type.eq_ignore_ascii_case("apfs") {
+        return EncryptionStatus::default();
+    }
+
+    let encrypted = properties.encrypted;
+    let (locked, filevault) = match encrypted {
+        Some(false) => (Some(false), Some(false)),
+        _ => (properties.locked, properties.filevault),
+    };
+
+    EncryptionStatus { encrypted, locked, filevault }
+}
+
+impl Disk {
+    /// Returns the encryption status of this volume.
+    ///
+    /// The status is read from the APFS volume's IORegistry entry, without privileged APIs. Non-APFS volumes report
+    /// None for every field.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the volume is APFS but its registry entry can't be found or read.
+    pub fn encryption_status(&self) -> Result<EncryptionStatus> {
+        if !self.fs_type.eq_ignore_ascii_case("apfs") {
+            return Ok(EncryptionStatus::default());
+        }
+
+        let bsd_name = self.device.trim_start_matches("/dev/");
+        let properties = registry_properties(bsd_name)?;
+        Ok(encryption_status_from_properties(&self.fs_type, &properties))
+    }
+}
+
+/// Returns whether FileVault is enabled for the system's data volume.
+///
+/// APFS doesn't publish the FileVault state in the IORegistry, so on current macOS releases this is only known when
+/// the data volume is unencrypted (and FileVault therefore off). An encrypted data volume reports None, since the
+/// hardware encryption of Apple Silicon and T2 Macs doesn't tell whether FileVault is on.
+///
+/// # Returns
+///
+/// * `Result<Option<bool>>` - Whether FileVault is on, or None if the system volume isn't APFS or doesn't report it
+///
+/// # Errors
+///
+/// Returns an error if the mounted volumes or the volume's registry entry can't be read.
+pub fn filevault_enabled() -> Result<Option<bool>> {
+    let volumes = Disk::get_all()?;
+
+    // Since macOS 10.15 the user data lives on a separate Data volume; older systems use a single root volume
+    let volume = volumes
+        .iter()
+        .find(|disk| disk.mount_point == DATA_VOLUME_MOUNT_POINT)
+        .or_else(|| volumes.iter().find(|disk| disk.mount_point == "/"))
+        .ok_or_else(|| Error::not_available("No system volume found"))?;
+
+    Ok(volume.encryption_status()?.filevault)
+}
+
+/// Looks up the IORegistry entry of a BSD device (e.g. `disk3s5`) and reads its encryption properties.
+fn registry_properties(bsd_name: &str) -> Result<VolumeProperties> {
+    let c_name = CString::new(bsd_name)
+        .map_err(|_| Error::invalid_data(format!("Invalid BSD name: {bsd_name}")))?;
+
+    autoreleasepool(|_| unsafe {
+        // IOServiceGetMatchingService consumes the matching dictionary
+        let matching = IOBSDNameMatching(0, 0, c_name.as_ptr());
+        if matching.is_null() {
+            return Err(Error::io_kit(format!(
+                "Failed to create matching dictionary for {bsd_name}"
+            )));
+        }
+
+        let service = IOServiceGetMatchingService(0, matching);
+        if service == 0 {
+            return Err(Error::service_not_found(format!("No registry entry for {bsd_name}")));
+        }
+
+        let mut props = ptr::null_mut();
+        let result = IORegistryEntryCreateCFProperties(service, &mut props, ptr::null_mut(), 0);
+        IOObjectRelease(service);
+
+        if result != 0 || props.is_null() {
+            return Err(Error::io_kit(format!("Failed to read registry properties of {bsd_name}")));
+        }
+
+        // The properties are returned with a +1 retain count and are toll-free bridged to NSDictionary
+        let dict = Retained::from_raw(props as *mut NSDictionary<NSString, NSObject>)
+            .ok_or_else(|| Error::io_kit("Failed to retain registry properties"))?;
+        Ok(VolumeProperties::from_dictionary(&dict))
+    })
+}
@@ -0,0 +1,97 @@
+//! Health of the drives volumes are stored on, from their SMART data.
+//!
+//! The SMART data is read through [`IOKit::get_smart_data`], see [`SmartData`] for what NVMe and SATA drives report.
+//! Drives without a SMART interface, such as drives in USB enclosures and virtual disks, return a not available
+//! error, which only concerns that drive.
+
+use super::Disk;
+use crate::{
+    error::{Error, Result},
+    hardware::iokit::{IOKit, IOKitImpl, SmartData},
+};
+
+/// NVMe critical warning bits that mean the drive is failing: spare below its threshold, degraded reliability,
+/// media placed in read-only mode and failed volatile memory backup
+const FAILING_WARNINGS: u8 = 0x01 | 0x04 | 0x08 | 0x10;
+/// Percentage of the rated endurance used from which a drive is reported as worn
+const WEAR_WARNING_PERCENT: u8 = 90;
+
+/// Summary of a drive's SMART data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DiskHealth {
+    /// Nothing in the SMART data points to a problem
+    Good,
+    /// The drive is worn, too hot or has had media errors, but hasn't reported failing
+    Warning,
+    /// The drive reports failing, or its spare capacity or rated endurance is used up
+    Failing,
+}
+
+impl SmartData {
+    /// Summarizes the SMART data as a [`DiskHealth`].
+    ///
+    /// A drive is failing when it says so (an NVMe critical warning other than temperature, or a SATA attribute past
+    /// its threshold), or when its spare capacity dropped below the threshold or all of its rated endurance is used.
+    /// It gets a warning when it's too hot, has used 90% of its rated endurance or has had media errors.
+    pub fn health(&self) -> DiskHealth {
+        let warning = self.critical_warning.unwrap_or(0);
+        let spare_exhausted = match (self.available_spare, self.available_spare_threshold) {
+            (Some(spare), Some(threshold)) => spare < threshold,
+            _ => false,
+        };
+
+        if warning & FAILING_WARNINGS != 0
+            || self.threshold_exceeded == Some(true)
+            || spare_exhausted
+            || self.percentage_used.is_some_and(|used| used >= 100)
+        {
+            DiskHealth::Failing
+        } else if warning != 0
+            || self.percentage_used.is_some_and(|used| used >= WEAR_WARNING_PERCENT)
+            || self.media_errors.is_some_and(|errors| errors > 0)
+        {
+            DiskHealth::Warning
+        } else {
+            DiskHealth::Good
+        }
+    }
+}
+
+/// Reads the health of the drive a volume is stored on
+pub trait DiskHealthMonitor {
+    /// Reads the SMART data of the drive.
+    ///
+    /// # Errors
+    ///
+    /// Returns a not available error if the drive has no SMART interface or the volume isn't stored on a local
+    /// drive, or another error if the SMART data can't be read.
+    fn smart_data(&self) -> Result<SmartData>;
+
+    /// Summarizes the SMART data of the drive, see [`SmartData::health`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`smart_data`](Self::smart_data).
+    fn health_status(&self) -> Result<DiskHealth> {
+        Ok(self.smart_data()?.health())
+    }
+}
+
+impl DiskHealthMonitor for Disk {
+    fn smart_data(&self) -> Result<SmartData> {
+        self.smart_data_from(&IOKitImpl::default())
+    }
+}
+
+impl Disk {
+    pub(crate) fn smart_data_from(&self, iokit: &dyn IOKit) -> Result<SmartData> {
+        if !self.device.contains("/dev/") {
+            return Err(Error::not_available(format!(
+                "{} isn't stored on a local drive",
+                self.mount_point
+            )));
+        }
+        iokit.get_smart_data(self.bsd_name())
+    }
+}
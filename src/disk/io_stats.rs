@@ -0,0 +1,187 @@
+//! I/O throughput of the physical disks.
+//!
+//! [`DiskIOStats`] reads the cumulative counters the block storage driver of each disk keeps (see
+//! [`BlockStorageStats`]) and turns the difference between two samples into rates. The counters belong to whole
+//! disks, so all volumes on a disk, including every APFS volume in a container, share its rates.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::{Disk, DiskPerformance, DiskStats};
+use crate::{
+    error::{Error, Result},
+    hardware::iokit::{BlockStorageStats, IOKit, IOKitImpl},
+    utils::sanitize::{sanitize_rate, Percentage},
+};
+
+/// Collects the I/O counters of every physical disk and the rates between samples
+#[derive(Debug, Clone)]
+pub struct DiskIOStats {
+    iokit: Arc<dyn IOKit>,
+    /// Counters of the previous sample by BSD name
+    previous_stats: HashMap<String, DiskStats>,
+}
+
+impl Default for DiskIOStats {
+    fn default() -> Self {
+        Self::with_shared_iokit(Arc::new(IOKitImpl::default()))
+    }
+}
+
+impl DiskIOStats {
+    /// Creates a collector without a previous sample.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a collector that reads through `iokit`, shared with the other monitors holding it.
+    pub fn with_shared_iokit(iokit: Arc<dyn IOKit>) -> Self {
+        Self { iokit, previous_stats: HashMap::new() }
+    }
+
+    /// Reads the cumulative counters of every physical disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block storage drivers can't be looked up.
+    pub fn counters(&self) -> Result<Vec<BlockStorageStats>> {
+        self.iokit.get_block_storage_stats()
+    }
+
+    /// Samples the counters and returns the rates of each disk since the previous sample, by BSD name.
+    ///
+    /// The first sample has nothing to compare with and returns no rates; neither do disks attached since the
+    /// previous sample.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block storage drivers can't be looked up.
+    pub fn sample(&mut self) -> Result<HashMap<String, DiskPerformance>> {
+        self.sample_at(Instant::now())
+    }
+
+    /// Whether a previous sample exists to compute rates against.
+    pub fn has_baseline(&self) -> bool {
+        !self.previous_stats.is_empty()
+    }
+
+    pub(crate) fn sample_at(&mut self, now: Instant) -> Result<HashMap<String, DiskPerformance>> {
+        let counters = self.counters()?;
+        let current: HashMap<String, DiskStats> = counters
+            .iter()
+            .map(|counters| (counters.device.clone(), DiskStats::from_counters(counters, now)))
+            .collect();
+
+        let rates = current
+            .iter()
+            .filter_map(|(device, stats)| {
+                let previous = self.previous_stats.get(device)?;
+                Some((device.clone(), stats.performance_since(device, previous)?))
+            })
+            .collect();
+
+        self.previous_stats = current;
+        Ok(rates)
+    }
+}
+
+impl Disk {
+    /// Reads the cumulative I/O counters of the physical disk the volume is stored on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the counters can't be read, or if the volume isn't stored on a local disk, e.g. a network
+    /// share.
+    pub fn io_stats(&self) -> Result<BlockStorageStats> {
+        self.io_stats_from(&IOKitImpl::default())
+    }
+
+    /// Measures the I/O rates of the physical disk the volume is stored on over `interval`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the counters can't be read, or if the volume isn't stored on a local disk.
+    pub async fn io_rates(&self, interval: Duration) -> Result<DiskPerformance> {
+        let device = self.io_stats()?.device;
+
+        let mut stats = DiskIOStats::new();
+        stats.sample()?;
+        tokio::time::sleep(interval).await;
+        stats.sample()?.remove(&device).ok_or_else(|| {
+            Error::not_available(format!("Disk {device} was detached during the interval"))
+        })
+    }
+
+    pub(crate) fn io_stats_from(&self, iokit: &dyn IOKit) -> Result<BlockStorageStats> {
+        iokit
+            .get_block_storage_stats()?
+            .into_iter()
+            .find(|stats| stats.contains(&self.device))
+            .ok_or_else(|| {
+                Error::not_available(format!("No physical disk found for {}", self.device))
+            })
+    }
+}
+
+impl DiskStats {
+    fn from_counters(counters: &BlockStorageStats, timestamp: Instant) -> Self {
+        Self {
+            read_ops: counters.reads,
+            write_ops: counters.writes,
+            bytes_read: counters.bytes_read,
+            bytes_written: counters.bytes_written,
+            read_time_ns: counters.read_time_ns,
+            write_time_ns: counters.write_time_ns,
+            timestamp,
+        }
+    }
+
+    /// Rates between `previous` and these counters, or None if no time passed.
+    pub(crate) fn performance_since(
+        &self,
+        device: &str,
+        previous: &DiskStats,
+    ) -> Option<DiskPerformance> {
+        let elapsed_secs =
+            self.timestamp.saturating_duration_since(previous.timestamp).as_secs_f64();
+        if elapsed_secs <= 0.0 {
+            return None;
+        }
+
+        // Counters that went backwards were reset, e.g. by a disk being reattached
+        let delta_read_ops = self.read_ops.saturating_sub(previous.read_ops);
+        let delta_write_ops = self.write_ops.saturating_sub(previous.write_ops);
+        let delta_bytes_read = self.bytes_read.saturating_sub(previous.bytes_read);
+        let delta_bytes_written = self.bytes_written.saturating_sub(previous.bytes_written);
+        let delta_read_time = self.read_time_ns.saturating_sub(previous.read_time_ns);
+        let delta_write_time = self.write_time_ns.saturating_sub(previous.write_time_ns);
+
+        let latency_ms = |time_ns: u64, ops: u64| {
+            if ops > 0 {
+                time_ns as f64 / (ops as f64 * 1_000_000.0)
+            } else {
+                0.0
+            }
+        };
+
+        // The time spent on I/O per second of the window is the average number of operations in flight
+        let busy_ns = delta_read_time.saturating_add(delta_write_time) as f64;
+        let elapsed_ns = elapsed_secs * 1_000_000_000.0;
+
+        Some(DiskPerformance {
+            device: device.to_string(),
+            reads_per_second: sanitize_rate(delta_read_ops as f64, elapsed_secs),
+            writes_per_second: sanitize_rate(delta_write_ops as f64, elapsed_secs),
+            bytes_read_per_second: sanitize_rate(delta_bytes_read as f64, elapsed_secs) as u64,
+            bytes_written_per_second: sanitize_rate(delta_bytes_written as f64, elapsed_secs)
+                as u64,
+            read_latency_ms: latency_ms(delta_read_time, delta_read_ops),
+            write_latency_ms: latency_ms(delta_write_time, delta_write_ops),
+            utilization: Percentage::from_ratio(busy_ns, elapsed_ns).value(),
+            queue_depth: sanitize_rate(busy_ns, elapsed_ns),
+        })
+    }
+}
@@ -1,9 +1,38 @@
-use std::{collections::HashMap, path::Path, time::Instant};
-
-use crate::{Error, Result};
+use std::{collections::HashMap, path::Path, sync::Arc, time::Instant};
+
+use crate::{
+    hardware::iokit::{bsd_name_matching, pci_device, IOKit, IOKitImpl},
+    Error, Result,
+};
+
+use crate::utils::{bindings::Statfs, byte_size::ByteSize, sanitize::Percentage};
+
+mod attribution;
+mod builder;
+mod container;
+mod encryption;
+mod health;
+mod io_stats;
+#[cfg(feature = "disk-events")]
+mod watcher;
+
+pub use attribution::{
+    io_attribution, IoAttribution, ProcessIoDelta, VolumeIoDelta, CORRELATION_NOTE, TOP_PROCESSES,
+};
+pub(crate) use attribution::{BlockStorageSampler, DeviceIoSampler};
+pub use builder::DiskBuilder;
+pub use container::{get_containers, DiskContainer};
+pub use encryption::{filevault_enabled, EncryptionStatus};
+pub use health::{DiskHealth, DiskHealthMonitor};
+pub use io_stats::DiskIOStats;
+#[cfg(feature = "disk-events")]
+pub use watcher::{DiskEvent, DiskWatcher};
+
+pub use crate::hardware::iokit::{BlockStorageStats, PciDevice, SmartData, SmartInterface};
 
 /// The type of disk storage device
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub enum DiskType {
     /// Hard Disk Drive
@@ -30,7 +59,25 @@ impl Default for DiskType {
     }
 }
 
+impl DiskType {
+    /// Infers the disk type from a filesystem type name as reported by `statfs`
+    ///
+    /// Local APFS and HFS volumes are assumed to be SSDs, as on all current Macs. exFAT and FAT volumes are almost
+    /// always removable media. Unrecognized filesystems map to [`DiskType::Unknown`].
+    pub fn from_fs_type(fs_type: &str) -> Self {
+        match fs_type.to_ascii_lowercase().as_str() {
+            "apfs" | "hfs" => Self::SSD,
+            "exfat" | "msdos" => Self::External,
+            "smbfs" | "nfs" | "afpfs" | "webdav" => Self::Network,
+            "devfs" | "autofs" => Self::Virtual,
+            _ => Self::Unknown,
+        }
+    }
+}
+
 /// Configuration struct for creating a Disk with detailed options
+///
+/// The default is an unnamed, non-boot volume of [`DiskType::Unknown`].
 #[derive(Debug, Clone, Default)]
 pub struct DiskConfig {
     /// Disk type (SSD, HDD, etc)
@@ -43,6 +90,7 @@ pub struct DiskConfig {
 
 /// Basic disk volume information
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Disk {
     /// Device identifier (e.g., /dev/disk1s1)
     pub device: String,
@@ -123,8 +171,8 @@ impl Default for DiskStats {
 /// Main struct for disk monitoring
 #[derive(Debug)]
 pub struct DiskMonitor {
-    /// Previous disk stats for calculating rates
-    previous_stats: HashMap<String, DiskStats>,
+    /// Counters of the previous update for calculating rates
+    io_stats: DiskIOStats,
     /// Last update time
     last_update: Instant,
 }
@@ -153,6 +201,7 @@ impl Disk {
     }
 
     /// Creates a new Disk instance with extended parameters
+    #[deprecated(since = "0.2.0", note = "use `Disk::builder()` or `Disk::from_statfs()` instead")]
     #[allow(clippy::too_many_arguments)]
     pub fn with_details(
         device: String,
@@ -176,148 +225,78 @@ impl Disk {
         }
     }
 
-    /// Gets information about the root filesystem
-    pub fn get_info() -> Result<Self> {
-        // Use a direct approach with statfs for the root filesystem
-        use std::{ffi::CStr, mem::MaybeUninit};
-
-        use crate::utils::bindings::{statfs, Statfs};
-
-        // Use a direct statfs call on the root path
-        let c_path = std::ffi::CString::new("/").expect("Failed to create CString for root path");
-        let mut fs_stat = MaybeUninit::<Statfs>::uninit();
-
-        let result = unsafe { statfs(c_path.as_ptr(), fs_stat.as_mut_ptr()) };
-
-        if result != 0 {
-            return Err(Error::system("Failed to get root filesystem information"));
-        }
-
-        // Extract data from statfs
-        let stat = unsafe { fs_stat.assume_init() };
+    /// Returns a builder for a Disk with named setters and validation
+    pub fn builder() -> DiskBuilder {
+        DiskBuilder::new()
+    }
 
-        // Extract filesystem type
-        let fs_type =
-            unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()).to_string_lossy().into_owned() };
+    /// Creates a Disk from the `statfs` information of a mounted filesystem
+    ///
+    /// The volume is named after the last component of its mount point ("Root" for `/`), which is also the only boot
+    /// volume, and its type is inferred from the filesystem type.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the reported capacities are inconsistent or the mount point is empty.
+    pub fn from_statfs(stat: &Statfs) -> Result<Self> {
+        use std::ffi::CStr;
+
+        // SAFETY: statfs NUL-terminates its name buffers
+        let (fs_type, mount_point, device) = unsafe {
+            (
+                CStr::from_ptr(stat.f_fstypename.as_ptr()).to_string_lossy().into_owned(),
+                CStr::from_ptr(stat.f_mntonname.as_ptr()).to_string_lossy().into_owned(),
+                CStr::from_ptr(stat.f_mntfromname.as_ptr()).to_string_lossy().into_owned(),
+            )
+        };
 
-        // Extract mount point (should be "/" for root)
-        let mount_point =
-            unsafe { CStr::from_ptr(stat.f_mntonname.as_ptr()).to_string_lossy().into_owned() };
+        let block_size = stat.f_bsize as u64;
+        let total = stat.f_blocks.saturating_mul(block_size);
+        let available = stat.f_bavail.saturating_mul(block_size);
+        let used = total.saturating_sub(stat.f_bfree.saturating_mul(block_size));
 
-        // Extract device name
-        let device =
-            unsafe { CStr::from_ptr(stat.f_mntfromname.as_ptr()).to_string_lossy().into_owned() };
+        let name = mount_point.split('/').next_back().unwrap_or("");
+        let name = if name.is_empty() { "Root" } else { name }.to_string();
+        let is_boot_volume = mount_point == "/";
 
-        // Calculate disk space values
-        let block_size = stat.f_bsize as u64;
-        let total = stat.f_blocks * block_size;
-        let available = stat.f_bavail * block_size;
-        let used = total - (stat.f_bfree * block_size);
-
-        // Create the Disk instance for root
-        let config = DiskConfig {
-            disk_type: DiskType::Unknown, // We'll skip the disk type detection for simplicity
-            name: "Root".to_string(),
-            is_boot_volume: true, // Root is always the boot volume
-        };
+        Self::builder()
+            .device(device)
+            .mount_point(mount_point)
+            .fs_type(fs_type)
+            .total(total)
+            .available(available)
+            .used(used)
+            .name(name)
+            .boot_volume(is_boot_volume)
+            .build()
+    }
 
-        Ok(Disk::with_details(device, mount_point, fs_type, total, available, used, config))
+    /// Gets information about the root filesystem
+    pub fn get_info() -> Result<Self> {
+        Self::get_for_path("/")
     }
 
     /// Gets information about all mounted filesystems
+    ///
+    /// Snapshots mounted during a system update are left out, see [`is_system_snapshot`](Self::is_system_snapshot).
     pub fn get_all() -> Result<Vec<Self>> {
-        // Use a direct approach with getfsstat for all filesystems
-        use std::{
-            ffi::CStr,
-            mem::{size_of, MaybeUninit},
-            os::raw::c_int,
-        };
-
-        use crate::utils::bindings::{getfsstat, Statfs, MNT_NOWAIT};
+        use std::ffi::CStr;
 
-        // First, call with null buffer to get the number of filesystems
-        let fs_count = unsafe { getfsstat(std::ptr::null_mut(), 0, MNT_NOWAIT) };
-
-        if fs_count < 0 {
-            return Err(Error::system("Failed to get filesystem count"));
-        }
-
-        // Allocate buffer for the filesystems
-        let buf_size = size_of::<Statfs>() * fs_count as usize;
-        let mut stats = vec![MaybeUninit::<Statfs>::uninit(); fs_count as usize];
-
-        // Get the actual data
-        let fs_count =
-            unsafe { getfsstat(stats.as_mut_ptr() as *mut Statfs, buf_size as c_int, MNT_NOWAIT) };
-
-        if fs_count < 0 {
-            return Err(Error::system("Failed to get filesystem information"));
-        }
-
-        // Process the data
-        let mut volumes = Vec::with_capacity(fs_count as usize);
-
-        for stat_uninit in stats.iter().take(fs_count as usize) {
-            let stat = unsafe { stat_uninit.assume_init() };
-
-            // Extract filesystem type
-            let fs_type = unsafe {
-                CStr::from_ptr(stat.f_fstypename.as_ptr()).to_string_lossy().into_owned()
-            };
+        let filesystems = mounted_filesystems()?;
+        let mut volumes = Vec::with_capacity(filesystems.len());
 
+        for stat in &filesystems {
             // Skip special filesystems
-            if ["devfs", "autofs", "msdos"].contains(&fs_type.as_str()) {
+            let fs_type = unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()) }.to_string_lossy();
+            if ["devfs", "autofs", "msdos"].contains(&fs_type.as_ref()) {
                 continue;
             }
 
-            // Extract mount point
-            let mount_point =
-                unsafe { CStr::from_ptr(stat.f_mntonname.as_ptr()).to_string_lossy().into_owned() };
-
-            // Extract device name
-            let device = unsafe {
-                CStr::from_ptr(stat.f_mntfromname.as_ptr()).to_string_lossy().into_owned()
-            };
-
-            // Calculate disk space values
-            let block_size = stat.f_bsize as u64;
-            let total = stat.f_blocks * block_size;
-            let available = stat.f_bavail * block_size;
-            let used = total - (stat.f_bfree * block_size);
-
-            // Check if it's a boot volume (typically mounted at "/")
-            let is_boot_volume = mount_point == "/";
-
-            // Get disk name - for now use the mount point name
-            let name = mount_point.split('/').next_back().unwrap_or("").to_string();
-            let name = if name.is_empty() { "Root".to_string() } else { name };
-
-            // Determine likely disk type based on device path
-            let disk_type = if device.starts_with("//")
-                || device.starts_with("afp:")
-                || device.starts_with("smb:")
-                || device.starts_with("nfs:")
-            {
-                DiskType::Network
-            } else if device.contains("disk") {
-                // For simplicity, we'll assume all local disks are SSDs A more accurate approach would use IOKit but
-                // we've avoided that due to memory issues
-                DiskType::SSD
-            } else {
-                DiskType::Unknown
-            };
-
-            let config = DiskConfig { disk_type, name, is_boot_volume };
-
-            volumes.push(Self::with_details(
-                device,
-                mount_point,
-                fs_type,
-                total,
-                available,
-                used,
-                config,
-            ));
+            let disk = Self::from_statfs(stat)?;
+            if disk.is_system_snapshot() {
+                continue;
+            }
+            volumes.push(disk);
         }
 
         Ok(volumes)
@@ -326,13 +305,9 @@ impl Disk {
     /// Gets information about a specific path
     pub fn get_for_path<P: AsRef<Path>>(path: P) -> Result<Self> {
         // Use direct statfs approach
-        use std::{
-            ffi::{CStr, CString},
-            mem::MaybeUninit,
-            os::unix::ffi::OsStrExt,
-        };
+        use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
 
-        use crate::utils::bindings::{statfs, Statfs};
+        use crate::utils::bindings::statfs;
 
         // Convert path to C string
         let c_path = CString::new(path.as_ref().as_os_str().as_bytes())
@@ -350,56 +325,12 @@ impl Disk {
         // Extract data
         let stat = unsafe { fs_stat.assume_init() };
 
-        // Extract filesystem type
-        let fs_type =
-            unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()).to_string_lossy().into_owned() };
-
-        // Extract mount point
-        let mount_point =
-            unsafe { CStr::from_ptr(stat.f_mntonname.as_ptr()).to_string_lossy().into_owned() };
-
-        // Extract device name
-        let device =
-            unsafe { CStr::from_ptr(stat.f_mntfromname.as_ptr()).to_string_lossy().into_owned() };
-
-        // Calculate disk space values
-        let block_size = stat.f_bsize as u64;
-        let total = stat.f_blocks * block_size;
-        let available = stat.f_bavail * block_size;
-        let used = total - (stat.f_bfree * block_size);
-
-        // Check if it's a boot volume
-        let is_boot_volume = mount_point == "/";
-
-        // Get disk name - for now use the mount point name
-        let name = mount_point.split('/').next_back().unwrap_or("").to_string();
-        let name = if name.is_empty() { "Root".to_string() } else { name };
-
-        // Determine likely disk type based on device path
-        let disk_type = if device.starts_with("//")
-            || device.starts_with("afp:")
-            || device.starts_with("smb:")
-            || device.starts_with("nfs:")
-        {
-            DiskType::Network
-        } else if device.contains("disk") {
-            // For simplicity, we'll assume all local disks are SSDs
-            DiskType::SSD
-        } else {
-            DiskType::Unknown
-        };
-
-        let config = DiskConfig { disk_type, name, is_boot_volume };
-
-        Ok(Self::with_details(device, mount_point, fs_type, total, available, used, config))
+        Self::from_statfs(&stat)
     }
 
     /// Calculates disk usage percentage
     pub fn usage_percentage(&self) -> f64 {
-        if self.total == 0 {
-            return 0.0;
-        }
-        (self.used as f64 / self.total as f64) * 100.0
+        Percentage::from_ratio(self.used as f64, self.total as f64).value()
     }
 
     /// Checks if disk is nearly full (>90% usage)
@@ -407,6 +338,21 @@ impl Disk {
         self.usage_percentage() > 90.0
     }
 
+    /// Total capacity as a [`ByteSize`]
+    pub fn total_bytes(&self) -> ByteSize {
+        ByteSize::from_bytes(self.total)
+    }
+
+    /// Available space as a [`ByteSize`]
+    pub fn available_bytes(&self) -> ByteSize {
+        ByteSize::from_bytes(self.available)
+    }
+
+    /// Used space as a [`ByteSize`]
+    pub fn used_bytes(&self) -> ByteSize {
+        ByteSize::from_bytes(self.used)
+    }
+
     /// Gets available space as a human-readable string
     pub fn available_display(&self) -> String {
         Self::format_bytes(self.available)
@@ -436,6 +382,24 @@ impl Disk {
         )
     }
 
+    /// PCI vendor and device IDs of the hardware the volume's drive is attached through, e.g. an NVMe SSD or the USB
+    /// controller of an external drive.
+    ///
+    /// None for volumes that aren't stored on a local drive and for drives that aren't on the PCI bus, like the internal
+    /// SSD of Apple Silicon Macs.
+    pub fn pci_device(&self) -> Option<PciDevice> {
+        self.pci_device_from(&IOKitImpl::default())
+    }
+
+    pub(crate) fn pci_device_from(&self, iokit: &dyn IOKit) -> Option<PciDevice> {
+        if !self.device.contains("/dev/") {
+            return None;
+        }
+        let matching = bsd_name_matching(self.bsd_name())?;
+        let media = iokit.io_service_get_matching_service(&matching)?;
+        pci_device(iokit, media)
+    }
+
     /// Formats bytes as a human-readable string
     pub fn format_bytes(bytes: u64) -> String {
         const KB: u64 = 1024;
@@ -457,13 +421,50 @@ impl Disk {
     }
 }
 
+/// Lists every mounted filesystem with `getfsstat`, including the special ones.
+fn mounted_filesystems() -> Result<Vec<Statfs>> {
+    // Use a direct approach with getfsstat for all filesystems
+    use std::{
+        mem::{size_of, MaybeUninit},
+        os::raw::c_int,
+    };
+
+    use crate::utils::bindings::{getfsstat, MNT_NOWAIT};
+
+    // First, call with null buffer to get the number of filesystems
+    let fs_count = unsafe { getfsstat(std::ptr::null_mut(), 0, MNT_NOWAIT) };
+
+    if fs_count < 0 {
+        return Err(Error::system("Failed to get filesystem count"));
+    }
+
+    // Allocate buffer for the filesystems
+    let buf_size = size_of::<Statfs>() * fs_count as usize;
+    let mut stats = vec![MaybeUninit::<Statfs>::uninit(); fs_count as usize];
+
+    // Get the actual data
+    let fs_count =
+        unsafe { getfsstat(stats.as_mut_ptr() as *mut Statfs, buf_size as c_int, MNT_NOWAIT) };
+
+    if fs_count < 0 {
+        return Err(Error::system("Failed to get filesystem information"));
+    }
+
+    Ok(stats.iter().take(fs_count as usize).map(|stat| unsafe { stat.assume_init() }).collect())
+}
+
 impl DiskMonitor {
     /// Creates a new DiskMonitor instance
     pub fn new() -> Self {
-        Self { previous_stats: HashMap::new(), last_update: Instant::now() }
+        Self { io_stats: DiskIOStats::new(), last_update: Instant::now() }
     }
 
-    /// Gets information about all mounted volumes
+    /// Creates a DiskMonitor that reads the I/O counters through `iokit`, shared with the other monitors holding it.
+    pub fn with_shared_iokit(iokit: Arc<dyn IOKit>) -> Self {
+        Self { io_stats: DiskIOStats::with_shared_iokit(iokit), last_update: Instant::now() }
+    }
+
+    /// Gets information about all mounted volumes, without the snapshots mounted during a system update
     pub fn get_volumes(&mut self) -> Result<Vec<Disk>> {
         use std::{
             ffi::CStr,
@@ -471,7 +472,7 @@ impl DiskMonitor {
             os::raw::c_int,
         };
 
-        use crate::utils::bindings::{getfsstat, Statfs, MNT_NOWAIT};
+        use crate::utils::bindings::{getfsstat, MNT_NOWAIT};
 
         // First, call with null buffer to get the number of filesystems
         let fs_count = unsafe { getfsstat(std::ptr::null_mut(), 0, MNT_NOWAIT) };
@@ -498,52 +499,18 @@ impl DiskMonitor {
         for stat_uninit in stats.iter().take(fs_count as usize) {
             let stat = unsafe { stat_uninit.assume_init() };
 
-            // Extract filesystem type
-            let fs_type = unsafe {
-                CStr::from_ptr(stat.f_fstypename.as_ptr()).to_string_lossy().into_owned()
-            };
-
             // Skip special filesystems
-            if ["devfs", "autofs", "msdos"].contains(&fs_type.as_str()) {
+            let fs_type = unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()) }.to_string_lossy();
+            if ["devfs", "autofs", "msdos"].contains(&fs_type.as_ref()) {
                 continue;
             }
 
-            // Extract mount point
-            let mount_point =
-                unsafe { CStr::from_ptr(stat.f_mntonname.as_ptr()).to_string_lossy().into_owned() };
-
-            // Extract device name
-            let device = unsafe {
-                CStr::from_ptr(stat.f_mntfromname.as_ptr()).to_string_lossy().into_owned()
-            };
-
-            // Calculate disk space values
-            let block_size = stat.f_bsize as u64;
-            let total = stat.f_blocks * block_size;
-            let available = stat.f_bavail * block_size;
-            let used = total - (stat.f_bfree * block_size);
-
-            // Check if it's a boot volume (typically mounted at "/")
-            let is_boot_volume = mount_point == "/";
-
-            // Get disk name - for now use the mount point name
-            let name = mount_point.split('/').next_back().unwrap_or("").to_string();
-            let name = if name.is_empty() { "Root".to_string() } else { name };
-
-            // Detect disk type
-            let disk_type = self.detect_disk_type(&device).unwrap_or(DiskType::Unknown);
-
-            let config = DiskConfig { disk_type, name, is_boot_volume };
-
-            volumes.push(Disk::with_details(
-                device,
-                mount_point,
-                fs_type,
-                total,
-                available,
-                used,
-                config,
-            ));
+            let mut disk = Disk::from_statfs(&stat)?;
+            if disk.is_system_snapshot() {
+                continue;
+            }
+            disk.disk_type = self.detect_disk_type(&disk.device).unwrap_or(DiskType::Unknown);
+            volumes.push(disk);
         }
 
         Ok(volumes)
@@ -551,13 +518,9 @@ impl DiskMonitor {
 
     /// Gets information about the volume containing the specified path
     pub fn get_volume_for_path<P: AsRef<Path>>(&mut self, path: P) -> Result<Disk> {
-        use std::{
-            ffi::{CStr, CString},
-            mem::MaybeUninit,
-            os::unix::ffi::OsStrExt,
-        };
+        use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
 
-        use crate::utils::bindings::{statfs, Statfs};
+        use crate::utils::bindings::statfs;
 
         // Convert path to C string
         let c_path = CString::new(path.as_ref().as_os_str().as_bytes())
@@ -575,214 +538,39 @@ impl DiskMonitor {
         // Extract data
         let stat = unsafe { stat.assume_init() };
 
-        // Extract filesystem type
-        let fs_type =
-            unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()).to_string_lossy().into_owned() };
-
-        // Extract mount point
-        let mount_point =
-            unsafe { CStr::from_ptr(stat.f_mntonname.as_ptr()).to_string_lossy().into_owned() };
-
-        // Extract device name
-        let device =
-            unsafe { CStr::from_ptr(stat.f_mntfromname.as_ptr()).to_string_lossy().into_owned() };
-
-        // Calculate disk space values
-        let block_size = stat.f_bsize as u64;
-        let total = stat.f_blocks * block_size;
-        let available = stat.f_bavail * block_size;
-        let used = total - (stat.f_bfree * block_size);
-
-        // Check if it's a boot volume
-        let is_boot_volume = mount_point == "/";
-
-        // Get disk name - for now use the mount point name
-        let name = mount_point.split('/').next_back().unwrap_or("").to_string();
-        let name = if name.is_empty() { "Root".to_string() } else { name };
-
-        // Detect disk type
-        let disk_type = self.detect_disk_type(&device).unwrap_or(DiskType::Unknown);
-
-        let config = DiskConfig { disk_type, name, is_boot_volume };
-
-        Ok(Disk::with_details(device, mount_point, fs_type, total, available, used, config))
+        let mut disk = Disk::from_statfs(&stat)?;
+        disk.disk_type = self.detect_disk_type(&disk.device).unwrap_or(DiskType::Unknown);
+        Ok(disk)
     }
 
-    /// Gets disk I/O performance metrics
+    /// Gets the I/O rates of each physical disk since the previous update, by BSD name (e.g. `disk0`)
+    ///
+    /// Without a previous update, the counters are sampled twice, 100ms apart.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the I/O counters can't be read.
     pub fn get_performance(&mut self) -> Result<HashMap<String, DiskPerformance>> {
-        let now = Instant::now();
-        let mut performance_map = HashMap::new();
-
-        // First call, initialize with a placeholder
-        if self.previous_stats.is_empty() {
-            // Create initial stats for the main disk - we'll update this on next call
-            let initial_stats = DiskStats {
-                read_ops: 0,
-                write_ops: 0,
-                bytes_read: 0,
-                bytes_written: 0,
-                read_time_ns: 0,
-                write_time_ns: 0,
-                timestamp: now,
-            };
-
-            self.previous_stats.insert("/dev/disk0".to_string(), initial_stats);
+        if !self.io_stats.has_baseline() {
+            self.io_stats.sample()?;
 
             // Sleep briefly to allow for meaningful deltas
             std::thread::sleep(std::time::Duration::from_millis(100));
-
-            // For the first call, return a placeholder with default values
-            let perf = DiskPerformance {
-                device: "/dev/disk0".to_string(),
-                reads_per_second: 0.0,
-                writes_per_second: 0.0,
-                bytes_read_per_second: 0,
-                bytes_written_per_second: 0,
-                read_latency_ms: 0.0,
-                write_latency_ms: 0.0,
-                utilization: 0.0,
-                queue_depth: 0.0,
-            };
-
-            performance_map.insert("/dev/disk0".to_string(), perf);
-            return Ok(performance_map);
-        }
-
-        // For subsequent calls, simulate some activity Instead of using IOKit which has memory management issues, we'll
-        // generate simulated stats
-        let read_ops = 1000 + (now.elapsed().as_millis() % 500) as u64;
-        let write_ops = 500 + (now.elapsed().as_millis() % 300) as u64;
-        let bytes_read = 20 * 1024 * 1024 + (now.elapsed().as_millis() % 10_000_000) as u64;
-        let bytes_written = 10 * 1024 * 1024 + (now.elapsed().as_millis() % 5_000_000) as u64;
-        let read_time_ns = 500_000_000 + (now.elapsed().as_millis() % 100_000) as u64 * 1000;
-        let write_time_ns = 300_000_000 + (now.elapsed().as_millis() % 80_000) as u64 * 1000;
-
-        // Calculate metrics for disk0
-        let device = "/dev/disk0".to_string();
-        if let Some(prev_stats) = self.previous_stats.get(&device) {
-            let elapsed_secs = now.duration_since(prev_stats.timestamp).as_secs_f64();
-
-            if elapsed_secs > 0.0 {
-                let delta_read_ops = read_ops - prev_stats.read_ops;
-                let delta_write_ops = write_ops - prev_stats.write_ops;
-                let delta_bytes_read = bytes_read - prev_stats.bytes_read;
-                let delta_bytes_written = bytes_written - prev_stats.bytes_written;
-                let delta_read_time = read_time_ns - prev_stats.read_time_ns;
-                let delta_write_time = write_time_ns - prev_stats.write_time_ns;
-
-                // Calculate rates
-                let reads_per_second = delta_read_ops as f64 / elapsed_secs;
-                let writes_per_second = delta_write_ops as f64 / elapsed_secs;
-
-                let bytes_read_per_second = delta_bytes_read / elapsed_secs.max(1.0) as u64;
-                let bytes_written_per_second = delta_bytes_written / elapsed_secs.max(1.0) as u64;
-
-                // Calculate latencies
-                let read_latency_ms = if delta_read_ops > 0 {
-                    delta_read_time as f64 / (delta_read_ops as f64 * 1_000_000.0)
-                } else {
-                    0.0
-                };
-
-                let write_latency_ms = if delta_write_ops > 0 {
-                    delta_write_time as f64 / (delta_write_ops as f64 * 1_000_000.0)
-                } else {
-                    0.0
-                };
-
-                // Calculate utilization (Total time / elapsed time)
-                let total_time_ns = delta_read_time + delta_write_time;
-                let elapsed_ns = (elapsed_secs * 1_000_000_000.0) as u64;
-
-                let utilization = if elapsed_ns > 0 {
-                    (total_time_ns as f64 / elapsed_ns as f64) * 100.0
-                } else {
-                    0.0
-                };
-
-                // Build performance struct
-                let perf = DiskPerformance {
-                    device: device.clone(),
-                    reads_per_second,
-                    writes_per_second,
-                    bytes_read_per_second,
-                    bytes_written_per_second,
-                    read_latency_ms,
-                    write_latency_ms,
-                    utilization: utilization.min(100.0),
-                    queue_depth: 2.0, // Simulated value
-                };
-
-                performance_map.insert(device.clone(), perf);
-            }
-        }
-
-        // Update current stats for next time
-        let current_stats = DiskStats {
-            read_ops,
-            write_ops,
-            bytes_read,
-            bytes_written,
-            read_time_ns,
-            write_time_ns,
-            timestamp: now,
-        };
-
-        self.previous_stats.insert(device, current_stats);
-
-        // If we have no data in the real implementation, we'd return a placeholder
-        if performance_map.is_empty() {
-            let perf = DiskPerformance {
-                device: "/dev/disk0".to_string(),
-                reads_per_second: 100.0,
-                writes_per_second: 50.0,
-                bytes_read_per_second: 10 * 1024 * 1024, // 10 MB/s
-                bytes_written_per_second: 5 * 1024 * 1024, // 5 MB/s
-                read_latency_ms: 2.5,
-                write_latency_ms: 3.0,
-                utilization: 25.0,
-                queue_depth: 2.0,
-            };
-
-            performance_map.insert("/dev/disk0".to_string(), perf);
         }
 
-        Ok(performance_map)
+        let performance = self.io_stats.sample()?;
+        self.last_update = Instant::now();
+        Ok(performance)
     }
 
-    /// Updates internal disk statistics
+    /// Samples the I/O counters, which the next call to [`get_performance`](Self::get_performance) measures from
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the I/O counters can't be read.
     pub fn update(&mut self) -> Result<()> {
-        let now = Instant::now();
-
-        // Use an alternative approach that doesn't rely on IOKit Instead of using IOKit which has memory management
-        // issues in our current code, we'll generate simulated stats for demonstration
-
-        // Simulate stats for the system disk
-        let device = "/dev/disk0".to_string();
-
-        // Generate some activity data that changes over time
-        let read_ops = 1000 + (now.elapsed().as_millis() % 500) as u64;
-        let write_ops = 500 + (now.elapsed().as_millis() % 300) as u64;
-        let bytes_read = 20 * 1024 * 1024 + (now.elapsed().as_millis() % 10_000_000) as u64;
-        let bytes_written = 10 * 1024 * 1024 + (now.elapsed().as_millis() % 5_000_000) as u64;
-        let read_time_ns = 500_000_000 + (now.elapsed().as_millis() % 100_000) as u64 * 1000;
-        let write_time_ns = 300_000_000 + (now.elapsed().as_millis() % 80_000) as u64 * 1000;
-
-        // Create stats object
-        let stats = DiskStats {
-            read_ops,
-            write_ops,
-            bytes_read,
-            bytes_written,
-            read_time_ns,
-            write_time_ns,
-            timestamp: now,
-        };
-
-        // Store the stats
-        self.previous_stats.insert(device, stats);
-
-        self.last_update = now;
+        self.io_stats.sample()?;
+        self.last_update = Instant::now();
         Ok(())
     }
 
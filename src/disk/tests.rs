@@ -152,6 +152,7 @@ fn test_disk_creation() {
 }
 
 #[test]
+#[allow(deprecated)]
 fn test_disk_with_details() {
     let config =
         DiskConfig { disk_type: DiskType::SSD, name: "Test SSD".to_string(), is_boot_volume: true };
@@ -221,14 +222,14 @@ fn test_format_bytes_edge_cases() {
 #[test]
 fn test_disk_monitor_new() {
     let monitor = DiskMonitor::new();
-    assert!(monitor.previous_stats.is_empty());
+    assert!(!monitor.io_stats.has_baseline());
     assert!(monitor.last_update <= Instant::now());
 }
 
 #[test]
 fn test_disk_monitor_default() {
     let monitor = DiskMonitor::default();
-    assert!(monitor.previous_stats.is_empty());
+    assert!(!monitor.io_stats.has_baseline());
     assert!(monitor.last_update <= Instant::now());
 }
 
@@ -352,7 +353,7 @@ fn test_display_methods_extended() {
 #[test]
 fn test_disk_monitor() {
     let monitor = DiskMonitor::new();
-    assert!(monitor.previous_stats.is_empty());
+    assert!(!monitor.io_stats.has_baseline());
     assert!(monitor.last_update <= std::time::Instant::now());
 }
 
@@ -647,207 +648,827 @@ fn test_get_volumes_filtering() {
 
 #[test]
 fn test_get_performance_initial_call() {
-    // This test specifically tests the first call path in get_performance when self.previous_stats.is_empty() is true
+    let mut monitor = DiskMonitor::new();
+    assert!(!monitor.io_stats.has_baseline());
+
+    // The first call takes its own baseline, so it already returns rates
+    let perf_map = monitor.get_performance().expect("First call should succeed");
+    assert!(monitor.io_stats.has_baseline());
+
+    for (device, perf) in &perf_map {
+        assert_eq!(&perf.device, device);
+        assert!(device.starts_with("disk"), "Rates are keyed by BSD name, got {device}");
+        assert!(perf.reads_per_second >= 0.0);
+        assert!(perf.writes_per_second >= 0.0);
+        assert!((0.0..=100.0).contains(&perf.utilization));
+        assert!(perf.queue_depth >= 0.0);
+    }
+}
+
+#[test]
+fn test_performance_metrics_calculation() {
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    let mut monitor = DiskMonitor::new();
+    monitor.update().expect("Baseline sample should succeed");
+
+    sleep(Duration::from_millis(100));
+
+    let perf_map = monitor.get_performance().expect("Second sample should succeed");
+    for perf in perf_map.values() {
+        assert!(perf.reads_per_second >= 0.0);
+        assert!(perf.writes_per_second >= 0.0);
+        assert!(perf.read_latency_ms >= 0.0);
+        assert!(perf.write_latency_ms >= 0.0);
+        assert!((0.0..=100.0).contains(&perf.utilization));
+        assert!(perf.queue_depth >= 0.0);
+
+        println!("Disk Performance ({}):", perf.device);
+        println!("  Read: {:.1} ops/sec", perf.reads_per_second);
+        println!("  Write: {:.1} ops/sec", perf.writes_per_second);
+        println!("  Read: {} bytes/sec", Disk::format_bytes(perf.bytes_read_per_second));
+        println!("  Write: {} bytes/sec", Disk::format_bytes(perf.bytes_written_per_second));
+        println!("  Utilization: {:.1}%", perf.utilization);
+    }
+}
 
-    // Create a fresh DiskMonitor with empty previous_stats
+#[test]
+fn test_multiple_performance_updates() {
     let mut monitor = DiskMonitor::new();
 
-    // Verify previous_stats is initially empty
-    assert!(monitor.previous_stats.is_empty());
-
-    // First call to get_performance should initialize stats and return placeholder
-    let perf_result = monitor.get_performance();
-    assert!(perf_result.is_ok(), "First call should succeed with placeholder data");
-
-    if let Ok(perf_map) = perf_result {
-        // Verify we got data for disk0
-        assert!(perf_map.contains_key("/dev/disk0"), "Should have placeholder for /dev/disk0");
-
-        // Check the placeholder has expected values (all zeros)
-        if let Some(perf) = perf_map.get("/dev/disk0") {
-            assert_eq!(perf.device, "/dev/disk0");
-            assert_eq!(perf.reads_per_second, 0.0);
-            assert_eq!(perf.writes_per_second, 0.0);
-            assert_eq!(perf.bytes_read_per_second, 0);
-            assert_eq!(perf.bytes_written_per_second, 0);
-            assert_eq!(perf.read_latency_ms, 0.0);
-            assert_eq!(perf.write_latency_ms, 0.0);
-            assert_eq!(perf.utilization, 0.0);
-            assert_eq!(perf.queue_depth, 0.0);
+    let first = monitor.get_performance();
+    assert!(first.is_ok());
+
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    for i in 0..3 {
+        let result = monitor.get_performance();
+        assert!(result.is_ok(), "Call #{} failed", i + 1);
+
+        if let Ok(perf_map) = result {
+            for perf in perf_map.values() {
+                assert!(perf.utilization >= 0.0 && perf.utilization <= 100.0);
+                assert!(perf.reads_per_second >= 0.0);
+                assert!(perf.writes_per_second >= 0.0);
+
+                println!(
+                    "Update #{} ({}): {:.1} reads/s, {:.1} writes/s, {:.1}% util",
+                    i + 1,
+                    perf.device,
+                    perf.reads_per_second,
+                    perf.writes_per_second,
+                    perf.utilization
+                );
+            }
         }
 
-        // Verify previous_stats is no longer empty after the call
-        assert!(!monitor.previous_stats.is_empty());
-        assert!(monitor.previous_stats.contains_key("/dev/disk0"));
+        std::thread::sleep(std::time::Duration::from_millis(50));
     }
 
-    // A second call should now use the "normal" path since stats are initialized
-    let second_perf_result = monitor.get_performance();
-    assert!(second_perf_result.is_ok());
+    assert!(monitor.io_stats.has_baseline());
+}
 
-    if let Ok(perf_map) = second_perf_result {
-        // Should still have data for disk0
-        assert!(perf_map.contains_key("/dev/disk0"));
+mod io_stats {
+    use std::{
+        collections::VecDeque,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
 
-        // But now the values should be non-zero (simulated activity)
-        if let Some(perf) = perf_map.get("/dev/disk0") {
-            // Since this is simulated data, we can't check exact values but we can verify they're different from the
-            // placeholder
-            assert!(
-                perf.reads_per_second > 0.0
-                    || perf.writes_per_second > 0.0
-                    || perf.bytes_read_per_second > 0
-                    || perf.bytes_written_per_second > 0,
-                "Should have non-zero performance metrics on second call"
-            );
+    use crate::disk::{BlockStorageStats, Disk, DiskIOStats};
+    use crate::hardware::iokit::MockIOKit;
+
+    fn counters(device: &str, media: &[&str], total: u64) -> BlockStorageStats {
+        BlockStorageStats {
+            device: device.to_string(),
+            media: media.iter().map(|name| name.to_string()).collect(),
+            is_internal: Some(true),
+            bytes_read: total * 4096,
+            bytes_written: total * 2048,
+            reads: total,
+            writes: total / 2,
+            read_time_ns: total * 1_000_000,
+            write_time_ns: total * 500_000,
         }
     }
+
+    /// An IOKit mock that returns each of `samples` in turn
+    fn scripted_iokit(samples: Vec<Vec<BlockStorageStats>>) -> MockIOKit {
+        let mut samples = VecDeque::from(samples);
+        let mut iokit = MockIOKit::new();
+        iokit
+            .expect_get_block_storage_stats()
+            .returning(move || Ok(samples.pop_front().unwrap_or_default()));
+        iokit
+    }
+
+    #[test]
+    fn test_rates_between_samples() {
+        let iokit = scripted_iokit(vec![
+            vec![counters("disk0", &["disk0s2"], 1_000)],
+            vec![counters("disk0", &["disk0s2"], 1_100)],
+        ]);
+        let mut stats = DiskIOStats::with_shared_iokit(Arc::new(iokit));
+
+        let start = Instant::now();
+        assert!(stats.sample_at(start).unwrap().is_empty(), "The first sample has no rates");
+        assert!(stats.has_baseline());
+
+        let rates = stats.sample_at(start + Duration::from_secs(2)).unwrap();
+        let perf = &rates["disk0"];
+        assert_eq!(perf.device, "disk0");
+        assert_eq!(perf.reads_per_second, 50.0);
+        assert_eq!(perf.writes_per_second, 25.0);
+        assert_eq!(perf.bytes_read_per_second, 100 * 4096 / 2);
+        assert_eq!(perf.bytes_written_per_second, 100 * 2048 / 2);
+        assert_eq!(perf.read_latency_ms, 1.0);
+        assert_eq!(perf.write_latency_ms, 1.0);
+        // 150 ms of I/O in a 2 s window
+        assert!((perf.utilization - 7.5).abs() < 1e-9);
+        assert!((perf.queue_depth - 0.075).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_reset_and_new_disks() {
+        let iokit = scripted_iokit(vec![
+            vec![counters("disk0", &[], 1_000)],
+            vec![counters("disk0", &[], 10), counters("disk4", &["disk4s1"], 10)],
+        ]);
+        let mut stats = DiskIOStats::with_shared_iokit(Arc::new(iokit));
+
+        let start = Instant::now();
+        stats.sample_at(start).unwrap();
+        let rates = stats.sample_at(start + Duration::from_secs(1)).unwrap();
+
+        // Counters that went backwards were reset and give no activity
+        let perf = &rates["disk0"];
+        assert_eq!(perf.reads_per_second, 0.0);
+        assert_eq!(perf.bytes_read_per_second, 0);
+        assert_eq!(perf.utilization, 0.0);
+
+        assert!(!rates.contains_key("disk4"), "A disk attached since the last sample has no rates");
+    }
+
+    #[test]
+    fn test_no_elapsed_time() {
+        let iokit = scripted_iokit(vec![
+            vec![counters("disk0", &[], 1_000)],
+            vec![counters("disk0", &[], 1_100)],
+        ]);
+        let mut stats = DiskIOStats::with_shared_iokit(Arc::new(iokit));
+
+        let now = Instant::now();
+        stats.sample_at(now).unwrap();
+        assert!(stats.sample_at(now).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_disk_io_stats() {
+        let iokit = scripted_iokit(vec![vec![
+            counters("disk0", &["disk0s1", "disk0s2", "disk3", "disk3s1", "disk3s1s1"], 10),
+            counters("disk4", &["disk4s1"], 20),
+        ]]);
+
+        let volume = Disk::new(
+            "/dev/disk3s1s1".to_string(),
+            "/".to_string(),
+            "apfs".to_string(),
+            1000,
+            750,
+            250,
+        );
+        let stats = volume.io_stats_from(&iokit).unwrap();
+        assert_eq!(stats.device, "disk0");
+        assert_eq!(stats.reads, 10);
+
+        let iokit = scripted_iokit(vec![vec![counters("disk0", &["disk0s1"], 10)]]);
+        let share = Disk::new(
+            "//user@server/share".to_string(),
+            "/Volumes/share".to_string(),
+            "smbfs".to_string(),
+            1000,
+            750,
+            250,
+        );
+        assert!(share.io_stats_from(&iokit).is_err());
+    }
+
+    #[test]
+    fn test_contains() {
+        let stats = counters("disk0", &["disk0s2", "disk3s1"], 0);
+        assert!(stats.contains("disk0"));
+        assert!(stats.contains("/dev/disk3s1"));
+        assert!(!stats.contains("disk3s2"));
+        assert!(!stats.contains("/dev/disk4"));
+    }
 }
 
-#[test]
-fn test_performance_metrics_calculation() {
-    // This test focuses on the calculation of performance metrics from raw stats in the get_performance method
+mod encryption {
+    use crate::disk::encryption::{encryption_status_from_properties, VolumeProperties};
+    use crate::disk::{Disk, EncryptionStatus};
+    use crate::utils::test_utils::create_test_dictionary_with_entries;
+
+    #[test]
+    fn test_encrypted_apfs_data_volume() {
+        let dict = create_test_dictionary_with_entries(&[("Encrypted", 1i64)]);
+        let properties = VolumeProperties::from_dictionary(&dict);
+        assert_eq!(properties.encrypted, Some(true));
+        assert_eq!(properties.locked, None);
+
+        // Hardware encryption of the Data volume doesn't imply FileVault, nor that it's unlocked
+        let status = encryption_status_from_properties("apfs", &properties);
+        assert_eq!(
+            status,
+            EncryptionStatus { encrypted: Some(true), locked: None, filevault: None }
+        );
+    }
 
-    use std::thread::sleep;
-    use std::time::Duration;
+    #[test]
+    fn test_locked_apfs_volume_with_explicit_filevault() {
+        let dict = create_test_dictionary_with_entries(&[
+            ("Encrypted", 1i64),
+            ("Locked", 1i64),
+            ("FileVault", 0i64),
+        ]);
+        let properties = VolumeProperties::from_dictionary(&dict);
+
+        let status = encryption_status_from_properties("apfs", &properties);
+        assert_eq!(
+            status,
+            EncryptionStatus { encrypted: Some(true), locked: Some(true), filevault: Some(false) }
+        );
+    }
 
-    let mut monitor = DiskMonitor::new();
+    #[test]
+    fn test_unencrypted_apfs_volume() {
+        let dict = create_test_dictionary_with_entries(&[("Encrypted", 0i64)]);
+        let properties = VolumeProperties::from_dictionary(&dict);
+
+        let status = encryption_status_from_properties("apfs", &properties);
+        assert_eq!(
+            status,
+            EncryptionStatus {
+                encrypted: Some(false),
+                locked: Some(false),
+                filevault: Some(false)
+            }
+        );
+    }
 
-    // Initialize by calling get_performance once
-    let _ = monitor.get_performance();
+    #[test]
+    fn test_unlocked_apfs_volume_with_explicit_filevault() {
+        let dict = create_test_dictionary_with_entries(&[
+            ("Encrypted", 1i64),
+            ("Locked", 0i64),
+            ("FileVault", 1i64),
+        ]);
+        let properties = VolumeProperties::from_dictionary(&dict);
+
+        let status = encryption_status_from_properties("apfs", &properties);
+        assert_eq!(
+            status,
+            EncryptionStatus { encrypted: Some(true), locked: Some(false), filevault: Some(true) }
+        );
+    }
 
-    // Sleep to allow for a meaningful time difference
-    sleep(Duration::from_millis(100));
+    #[test]
+    fn test_external_exfat_volume() {
+        // Encryption properties are ignored on filesystems where the concept doesn't apply
+        let dict = create_test_dictionary_with_entries(&[("Encrypted", 1i64)]);
+        let properties = VolumeProperties::from_dictionary(&dict);
+
+        let status = encryption_status_from_properties("exfat", &properties);
+        assert_eq!(status, EncryptionStatus::default());
+
+        let disk = Disk::new(
+            "/dev/disk4s1".to_string(),
+            "/Volumes/USB".to_string(),
+            "exfat".to_string(),
+            1000,
+            500,
+            500,
+        );
+        assert_eq!(disk.encryption_status().unwrap(), EncryptionStatus::default());
+    }
+}
 
-    // Get metrics after some time has passed
-    let perf_result = monitor.get_performance();
-    assert!(perf_result.is_ok());
+mod attribution {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc, time::Duration};
 
-    if let Ok(perf_map) = perf_result {
-        let disk0_perf = perf_map.get("/dev/disk0");
-        assert!(disk0_perf.is_some(), "Should have performance data for /dev/disk0");
+    use crate::disk::attribution::{
+        attribute, DeviceIoCounters, DeviceIoSampler, ProcessIoCounters, ProcessIoSampler,
+    };
+    use crate::disk::{io_attribution, CORRELATION_NOTE, TOP_PROCESSES};
+    use crate::error::{Error, Result};
 
-        if let Some(perf) = disk0_perf {
-            // Verify all metrics are within reasonable ranges
-            assert!(perf.reads_per_second >= 0.0);
-            assert!(perf.writes_per_second >= 0.0);
-            assert!(perf.read_latency_ms >= 0.0);
-            assert!(perf.write_latency_ms >= 0.0);
-            assert!((0.0..=100.0).contains(&perf.utilization));
-            assert!(perf.queue_depth >= 0.0);
+    type Log = Rc<RefCell<Vec<&'static str>>>;
 
-            // For simulated data, verify some are non-zero to ensure calculation happened
-            assert!(
-                perf.reads_per_second > 0.0
-                    || perf.writes_per_second > 0.0
-                    || perf.utilization > 0.0,
-                "At least some metrics should be non-zero"
-            );
+    /// Returns scripted samples in order and logs each call
+    struct FakeDevices {
+        samples: VecDeque<Vec<DeviceIoCounters>>,
+        log: Log,
+    }
 
-            // Format some values for debugging/display
-            println!("Disk Performance:");
-            println!("  Read: {:.1} ops/sec", perf.reads_per_second);
-            println!("  Write: {:.1} ops/sec", perf.writes_per_second);
-            println!("  Read: {} bytes/sec", Disk::format_bytes(perf.bytes_read_per_second));
-            println!("  Write: {} bytes/sec", Disk::format_bytes(perf.bytes_written_per_second));
-            println!("  Utilization: {:.1}%", perf.utilization);
+    impl DeviceIoSampler for FakeDevices {
+        fn sample(&mut self) -> Result<Vec<DeviceIoCounters>> {
+            self.log.borrow_mut().push("devices");
+            self.samples.pop_front().ok_or_else(|| Error::not_available("no device sample"))
         }
     }
+
+    struct FakeProcesses {
+        samples: VecDeque<Vec<ProcessIoCounters>>,
+        log: Log,
+    }
+
+    impl ProcessIoSampler for FakeProcesses {
+        fn sample(&mut self) -> Result<Vec<ProcessIoCounters>> {
+            self.log.borrow_mut().push("processes");
+            self.samples.pop_front().ok_or_else(|| Error::not_available("no process sample"))
+        }
+    }
+
+    fn device(name: &str, is_internal: bool, read: u64, written: u64) -> DeviceIoCounters {
+        DeviceIoCounters {
+            device: name.to_string(),
+            is_internal: Some(is_internal),
+            bytes_read: read,
+            bytes_written: written,
+        }
+    }
+
+    fn process(pid: u32, name: &str, read: u64, written: u64) -> ProcessIoCounters {
+        ProcessIoCounters {
+            pid,
+            start_time: 1000 + pid as u64,
+            name: name.to_string(),
+            bytes_read: read,
+            bytes_written: written,
+        }
+    }
+
+    fn run(
+        devices: [Vec<DeviceIoCounters>; 2],
+        processes: [Vec<ProcessIoCounters>; 2],
+    ) -> (crate::disk::IoAttribution, Vec<&'static str>) {
+        let log = Log::default();
+        let mut devices = FakeDevices { samples: devices.into(), log: log.clone() };
+        let mut processes = FakeProcesses { samples: processes.into(), log: log.clone() };
+
+        let wait_log = log.clone();
+        let report = attribute(&mut devices, &mut processes, Duration::from_secs(5), |window| {
+            assert_eq!(window, Duration::from_secs(5));
+            wait_log.borrow_mut().push("wait");
+        })
+        .unwrap();
+
+        let calls = log.borrow().clone();
+        (report, calls)
+    }
+
+    #[test]
+    fn test_samples_both_sides_around_the_window() {
+        let (report, calls) = run(Default::default(), Default::default());
+
+        assert_eq!(calls, vec!["devices", "processes", "wait", "processes", "devices"]);
+        assert_eq!(report.window, Duration::from_secs(5));
+        assert_eq!(report.correlation_note, CORRELATION_NOTE);
+    }
+
+    #[test]
+    fn test_idle_window() {
+        let devices = vec![device("disk0", true, 500, 800), device("disk4", false, 10, 20)];
+        let processes = vec![process(1, "launchd", 100, 200), process(90, "mds", 50, 0)];
+
+        let (report, _) = run([devices.clone(), devices], [processes.clone(), processes]);
+
+        assert_eq!(report.per_volume.len(), 2);
+        assert!(report.per_volume.iter().all(|volume| volume.total_bytes() == 0));
+        // Ties are ordered by device name
+        assert_eq!(report.per_volume[0].device, "disk0");
+        assert!(report.top_processes.is_empty());
+    }
+
+    #[test]
+    fn test_single_writer_on_external_drive() {
+        let (report, _) = run(
+            [
+                vec![device("disk0", true, 1000, 1000), device("disk4", false, 0, 0)],
+                vec![device("disk0", true, 1000, 1100), device("disk4", false, 0, 64 << 20)],
+            ],
+            [
+                vec![process(1, "launchd", 0, 0), process(412, "rsync", 4096, 1 << 20)],
+                vec![
+                    process(1, "launchd", 0, 0),
+                    process(412, "rsync", 8192, (1 << 20) + (64 << 20)),
+                ],
+            ],
+        );
+
+        let busiest = &report.per_volume[0];
+        assert_eq!((busiest.device.as_str(), busiest.is_internal), ("disk4", Some(false)));
+        assert_eq!(busiest.bytes_written, 64 << 20);
+        assert_eq!(report.per_volume[1].total_bytes(), 100);
+
+        assert_eq!(report.top_processes.len(), 1);
+        let writer = &report.top_processes[0];
+        assert_eq!((writer.pid, writer.name.as_str()), (412, "rsync"));
+        assert_eq!((writer.bytes_read, writer.bytes_written), (4096, 64 << 20));
+    }
+
+    #[test]
+    fn test_multiple_writers() {
+        let mut reused = process(77, "new-process", 10, 10);
+        reused.start_time = 99_999;
+
+        let (report, _) = run(
+            [vec![device("disk0", true, 0, 0)], vec![device("disk0", true, 900, 9000)]],
+            [
+                vec![
+                    process(10, "backupd", 0, 1000),
+                    process(20, "photoanalysisd", 500, 0),
+                    process(30, "exited", 0, 0),
+                    process(77, "old-process", 5000, 5000),
+                ],
+                vec![
+                    process(10, "backupd", 0, 6000),
+                    process(20, "photoanalysisd", 1500, 0),
+                    // Started during the window, all of its I/O counts
+                    process(40, "cp", 0, 3000),
+                    // PID 77 was reused; its counters must not be diffed against the old process
+                    reused,
+                ],
+            ],
+        );
+
+        let order: Vec<(u32, u64)> =
+            report.top_processes.iter().map(|p| (p.pid, p.total_bytes())).collect();
+        assert_eq!(order, vec![(10, 5000), (40, 3000), (20, 1000), (77, 20)]);
+        assert_eq!(report.top_processes[3].name, "new-process");
+        assert_eq!(report.per_volume[0].total_bytes(), 9900);
+    }
+
+    #[test]
+    fn test_top_processes_are_truncated() {
+        let start: Vec<_> = (1..=20).map(|pid| process(pid, "worker", 0, 0)).collect();
+        let end: Vec<_> = (1..=20).map(|pid| process(pid, "worker", 0, pid as u64 * 100)).collect();
+
+        let (report, _) = run(Default::default(), [start, end]);
+
+        assert_eq!(report.top_processes.len(), TOP_PROCESSES);
+        assert_eq!(report.top_processes[0].pid, 20);
+        assert_eq!(report.top_processes[TOP_PROCESSES - 1].pid, 11);
+    }
+
+    #[test]
+    fn test_counter_resets_do_not_underflow() {
+        let (report, _) = run(
+            [vec![device("disk0", true, 5000, 5000)], vec![device("disk0", true, 100, 200)]],
+            Default::default(),
+        );
+
+        assert_eq!(report.per_volume[0].total_bytes(), 0);
+    }
+
+    #[test]
+    fn test_sampling_errors_are_returned() {
+        let log = Log::default();
+        let mut devices = FakeDevices { samples: VecDeque::new(), log: log.clone() };
+        let mut processes = FakeProcesses { samples: VecDeque::new(), log: log.clone() };
+
+        let result = attribute(&mut devices, &mut processes, Duration::ZERO, |_| {
+            panic!("should fail before waiting")
+        });
+        assert!(result.is_err());
+        assert_eq!(*log.borrow(), vec!["devices"]);
+    }
+
+    #[test]
+    fn test_io_attribution_smoke() {
+        let report = io_attribution(Duration::from_millis(200)).expect("failed to sample disk I/O");
+
+        assert!(!report.per_volume.is_empty(), "the boot device should be listed");
+        assert!(report.top_processes.len() <= TOP_PROCESSES);
+    }
 }
 
-#[test]
-fn test_empty_performance_fallback() {
-    // Test that the fallback mechanism works when no performance data is available
+mod builder {
+    use std::{ffi::CString, mem::MaybeUninit};
 
-    // Create a custom monitor where we can manipulate the internals
-    let mut monitor = DiskMonitor::new();
+    use crate::disk::{Disk, DiskConfig, DiskType};
+    use crate::utils::bindings::{statfs, Statfs};
+
+    fn valid() -> crate::disk::DiskBuilder {
+        Disk::builder()
+            .device("/dev/disk3s1")
+            .mount_point("/Volumes/Data")
+            .fs_type("apfs")
+            .total(1000)
+            .used(250)
+            .available(750)
+    }
+
+    #[test]
+    fn test_builder() {
+        let disk = valid().name("Data").boot_volume(true).build().unwrap();
+
+        assert_eq!(disk.device, "/dev/disk3s1");
+        assert_eq!(disk.mount_point, "/Volumes/Data");
+        assert_eq!(disk.fs_type, "apfs");
+        assert_eq!(disk.total, 1000);
+        assert_eq!(disk.used, 250);
+        assert_eq!(disk.available, 750);
+        assert_eq!(disk.disk_type, DiskType::SSD, "Disk type should be inferred from apfs");
+        assert_eq!(disk.name, "Data");
+        assert!(disk.is_boot_volume);
+    }
+
+    #[test]
+    fn test_builder_explicit_disk_type() {
+        let disk = valid().disk_type(DiskType::External).build().unwrap();
+        assert_eq!(disk.disk_type, DiskType::External);
+    }
 
-    // Manipulate monitor to simulate a case where no stats get computed (this is a hack to test the fallback path)
-    {
-        // Initialize with a call
-        let _ = monitor.get_performance();
-
-        // Hack: Replace the internal previous_stats with a device that doesn't match disk0
-        monitor.previous_stats.clear();
-        let now = std::time::Instant::now();
-        monitor.previous_stats.insert(
-            "not-disk0".to_string(),
-            crate::disk::DiskStats {
-                read_ops: 0,
-                write_ops: 0,
-                bytes_read: 0,
-                bytes_written: 0,
-                read_time_ns: 0,
-                write_time_ns: 0,
-                timestamp: now,
-            },
+    #[test]
+    fn test_builder_empty_mount_point() {
+        let err = valid().mount_point("").build().unwrap_err();
+        assert!(err.to_string().contains("mount_point"), "Error should name the field: {err}");
+    }
+
+    #[test]
+    fn test_builder_capacity_exceeds_total() {
+        let err = valid().used(600).available(600).build().unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.contains("used") && message.contains("total"),
+            "Error should name the fields: {message}"
         );
     }
 
-    // Now when we call get_performance, it should use the fallback path because our device name doesn't match what's
-    // expected
-    let result = monitor.get_performance();
-    assert!(result.is_ok());
-
-    if let Ok(perf_map) = result {
-        // Should still have data for disk0 (from the fallback)
-        assert!(perf_map.contains_key("/dev/disk0"), "Fallback should create disk0 entry");
-
-        if let Some(perf) = perf_map.get("/dev/disk0") {
-            // Fallback values should be non-zero
-            assert_eq!(perf.device, "/dev/disk0");
-            assert_eq!(perf.reads_per_second, 100.0);
-            assert_eq!(perf.writes_per_second, 50.0);
-            assert_eq!(perf.bytes_read_per_second, 10 * 1024 * 1024); // 10 MB/s
-            assert_eq!(perf.bytes_written_per_second, 5 * 1024 * 1024); // 5 MB/s
-            assert_eq!(perf.read_latency_ms, 2.5);
-            assert_eq!(perf.write_latency_ms, 3.0);
-            assert_eq!(perf.utilization, 25.0);
-            assert_eq!(perf.queue_depth, 2.0);
-        }
+    #[test]
+    fn test_builder_capacity_rounding_tolerated() {
+        assert!(valid().used(255).available(750).build().is_ok());
+    }
+
+    #[test]
+    fn test_disk_config_default() {
+        let config = DiskConfig::default();
+
+        assert_eq!(config.disk_type, DiskType::Unknown);
+        assert!(config.name.is_empty());
+        assert!(!config.is_boot_volume);
+    }
+
+    #[test]
+    fn test_disk_type_from_fs_type() {
+        assert_eq!(DiskType::from_fs_type("apfs"), DiskType::SSD);
+        assert_eq!(DiskType::from_fs_type("hfs"), DiskType::SSD);
+        assert_eq!(DiskType::from_fs_type("exfat"), DiskType::External);
+        assert_eq!(DiskType::from_fs_type("smbfs"), DiskType::Network);
+        assert_eq!(DiskType::from_fs_type("nfs"), DiskType::Network);
+        assert_eq!(DiskType::from_fs_type("APFS"), DiskType::SSD);
+        assert_eq!(DiskType::from_fs_type("zfs"), DiskType::Unknown);
+        assert_eq!(DiskType::from_fs_type(""), DiskType::Unknown);
+    }
+
+    #[test]
+    fn test_from_statfs_root() {
+        let path = CString::new("/").unwrap();
+        let mut stat = MaybeUninit::<Statfs>::uninit();
+        assert_eq!(unsafe { statfs(path.as_ptr(), stat.as_mut_ptr()) }, 0);
+        let stat = unsafe { stat.assume_init() };
+
+        let disk = Disk::from_statfs(&stat).unwrap();
+
+        assert_eq!(disk.mount_point, "/");
+        assert_eq!(disk.name, "Root");
+        assert!(disk.is_boot_volume);
+        assert!(!disk.device.is_empty());
+        assert_eq!(disk.disk_type, DiskType::from_fs_type(&disk.fs_type));
+        assert!(disk.total > 0);
+        assert!(disk.used <= disk.total);
+        assert_eq!(disk.total, stat.f_blocks * stat.f_bsize as u64);
+        assert_eq!(disk.available, stat.f_bavail * stat.f_bsize as u64);
     }
 }
 
-#[test]
-fn test_multiple_performance_updates() {
-    // Test multiple successive calls to get_performance
-    let mut monitor = DiskMonitor::new();
+mod container {
+    use crate::disk::{get_containers, Disk, DiskContainer};
+
+    fn volume(device: &str, mount_point: &str, fs_type: &str, total: u64, available: u64) -> Disk {
+        Disk::builder()
+            .device(device)
+            .mount_point(mount_point)
+            .fs_type(fs_type)
+            .total(total)
+            .available(available)
+            .used(total - available)
+            .build()
+            .unwrap()
+    }
 
-    // First call initializes
-    let first = monitor.get_performance();
-    assert!(first.is_ok());
+    #[test]
+    fn test_apfs_container() {
+        assert_eq!(volume("/dev/disk3s1s1", "/", "apfs", 10, 5).apfs_container().unwrap(), "disk3");
+        assert_eq!(
+            volume("/dev/disk12s5", "/System/Volumes/VM", "apfs", 10, 5).apfs_container().unwrap(),
+            "disk12"
+        );
+        assert_eq!(
+            volume(
+                "com.apple.os.update-ABC@/dev/disk3s1",
+                "/System/Volumes/Update/mnt1",
+                "apfs",
+                10,
+                5
+            )
+            .apfs_container()
+            .unwrap(),
+            "disk3"
+        );
+        assert_eq!(volume("/dev/disk4s2", "/Volumes/Backup", "hfs", 10, 5).apfs_container(), None);
+        assert_eq!(
+            volume("map auto_home", "/System/Volumes/Data/home", "apfs", 10, 5).apfs_container(),
+            None
+        );
+    }
 
-    // Sleep to allow for meaningful time difference
-    std::thread::sleep(std::time::Duration::from_millis(50));
+    #[test]
+    fn test_is_system_snapshot() {
+        assert!(volume(
+            "com.apple.os.update-ABC@/dev/disk3s1",
+            "/System/Volumes/Update/mnt1",
+            "apfs",
+            10,
+            5
+        )
+        .is_system_snapshot());
+        assert!(!volume("/dev/disk3s1s1", "/", "apfs", 10, 5).is_system_snapshot());
+    }
 
-    // Multiple calls should update stats each time
-    for i in 0..3 {
-        let result = monitor.get_performance();
-        assert!(result.is_ok(), "Call #{} failed", i + 1);
+    #[test]
+    fn test_group() {
+        let containers = DiskContainer::group([
+            volume("/dev/disk3s1s1", "/", "apfs", 1000, 400),
+            volume("/dev/disk3s5", "/System/Volumes/Data", "apfs", 1000, 400),
+            volume("/dev/disk4s2", "/Volumes/Backup", "hfs", 500, 100),
+            volume("/dev/disk3s2", "/System/Volumes/Preboot", "apfs", 1000, 400),
+            volume("/dev/disk5s1", "/Volumes/External", "apfs", 2000, 1500),
+        ]);
+
+        let ids: Vec<_> = containers.iter().map(DiskContainer::id).collect();
+        assert_eq!(ids, ["disk3", "/dev/disk4s2", "disk5"]);
+
+        let system = &containers[0];
+        assert_eq!(system.volumes().len(), 3);
+        assert_eq!(system.container_capacity(), 1000);
+        assert_eq!(system.container_free(), 400, "The shared free space is counted once");
+        assert_eq!(system.container_used(), 600);
+
+        let free: u64 = containers.iter().map(DiskContainer::container_free).sum();
+        assert_eq!(free, 400 + 100 + 1500);
+    }
 
-        if let Ok(perf_map) = result {
-            assert!(perf_map.contains_key("/dev/disk0"));
+    #[test]
+    fn test_get_containers() {
+        let containers = get_containers().unwrap();
+        assert!(!containers.is_empty());
 
-            // Each call should give valid data
-            let perf = &perf_map["/dev/disk0"];
-            assert!(perf.utilization >= 0.0 && perf.utilization <= 100.0);
-            assert!(perf.reads_per_second >= 0.0);
-            assert!(perf.writes_per_second >= 0.0);
+        let volumes: Vec<_> = containers.iter().flat_map(DiskContainer::volumes).collect();
+        assert!(volumes.iter().any(|volume| volume.is_boot_volume));
+        assert!(volumes.iter().all(|volume| !volume.is_system_snapshot()));
+        for container in &containers {
+            assert!(container.container_free() <= container.container_capacity());
+        }
+    }
+}
 
-            println!(
-                "Update #{}: {:.1} reads/s, {:.1} writes/s, {:.1}% util",
-                i + 1,
-                perf.reads_per_second,
-                perf.writes_per_second,
-                perf.utilization
-            );
+mod health {
+    use crate::disk::{Disk, DiskHealth, DiskHealthMonitor, SmartData, SmartInterface};
+    use crate::error::Error;
+    use crate::hardware::iokit::MockIOKit;
+
+    fn nvme(percentage_used: u8, available_spare: u8) -> SmartData {
+        SmartData {
+            interface: SmartInterface::Nvme,
+            critical_warning: Some(0),
+            percentage_used: Some(percentage_used),
+            available_spare: Some(available_spare),
+            available_spare_threshold: Some(10),
+            media_errors: Some(0),
+            power_on_hours: Some(1000),
+            temperature_celsius: Some(35.0),
+            threshold_exceeded: None,
         }
+    }
 
-        // Sleep between calls
-        std::thread::sleep(std::time::Duration::from_millis(50));
+    fn volume(device: &str, fs_type: &str) -> Disk {
+        Disk::new(device.to_string(), "/Volumes/Test".to_string(), fs_type.to_string(), 100, 50, 50)
+    }
+
+    #[test]
+    fn test_health() {
+        assert_eq!(nvme(5, 100).health(), DiskHealth::Good);
+        assert_eq!(nvme(90, 100).health(), DiskHealth::Warning, "Most of the endurance used");
+        assert_eq!(nvme(100, 100).health(), DiskHealth::Failing, "All of the endurance used");
+        assert_eq!(nvme(5, 9).health(), DiskHealth::Failing, "Spare below the threshold");
+        assert_eq!(nvme(5, 10).health(), DiskHealth::Good, "Spare at the threshold");
+
+        let too_hot = SmartData { critical_warning: Some(0x02), ..nvme(5, 100) };
+        assert_eq!(too_hot.health(), DiskHealth::Warning);
+        let read_only = SmartData { critical_warning: Some(0x08), ..nvme(5, 100) };
+        assert_eq!(read_only.health(), DiskHealth::Failing);
+        let media_errors = SmartData { media_errors: Some(3), ..nvme(5, 100) };
+        assert_eq!(media_errors.health(), DiskHealth::Warning);
+
+        let sata = SmartData {
+            interface: SmartInterface::Ata,
+            threshold_exceeded: Some(false),
+            ..SmartData::default()
+        };
+        assert_eq!(sata.health(), DiskHealth::Good);
+        let sata = SmartData { threshold_exceeded: Some(true), ..sata };
+        assert_eq!(sata.health(), DiskHealth::Failing);
     }
 
-    // Verify the stats object is updated after multiple calls
-    assert!(!monitor.previous_stats.is_empty());
-    assert!(monitor.previous_stats.contains_key("/dev/disk0"));
+    #[test]
+    fn test_smart_data_from() {
+        let mut iokit = MockIOKit::new();
+        iokit
+            .expect_get_smart_data()
+            .withf(|bsd_name| bsd_name == "disk3s1")
+            .returning(|_| Ok(nvme(95, 100)));
+        iokit
+            .expect_get_smart_data()
+            .withf(|bsd_name| bsd_name == "disk4s1")
+            .returning(|bsd_name| Err(Error::not_available(format!("{bsd_name} has no SMART"))));
+
+        let internal = volume("/dev/disk3s1", "apfs");
+        assert_eq!(internal.smart_data_from(&iokit).unwrap().health(), DiskHealth::Warning);
+
+        let usb = volume("/dev/disk4s1", "exfat");
+        assert!(usb.smart_data_from(&iokit).unwrap_err().is_not_available());
+
+        let share = volume("//user@server/share", "smbfs");
+        assert!(share.smart_data_from(&iokit).unwrap_err().is_not_available());
+    }
+
+    #[test]
+    fn test_health_status_of_all_volumes() {
+        // SMART support depends on the drive and on whether the process may open the user client, so a missing
+        // interface is fine; what matters is that a volume without one doesn't keep the others from being read
+        for disk in Disk::get_all().unwrap() {
+            match disk.health_status() {
+                Ok(health) => println!("{}: {:?}", disk.mount_point, health),
+                Err(err) => println!("{}: {}", disk.mount_point, err),
+            }
+        }
+    }
+}
+
+mod pci {
+    use objc2::rc::Retained;
+    use objc2_foundation::{NSData, NSDictionary, NSObject, NSString};
+
+    use crate::disk::{Disk, PciDevice};
+    use crate::hardware::iokit::MockIOKit;
+    use crate::utils::test_utils::{create_test_dictionary, create_test_service_with_id};
+
+    fn volume(device: &str, fs_type: &str) -> Disk {
+        Disk::new(device.to_string(), "/Volumes/Test".to_string(), fs_type.to_string(), 100, 50, 50)
+    }
+
+    #[test]
+    fn test_pci_device_from() {
+        // The media 3 sits below the NVMe controller 2, which sits below the PCI device 1
+        let mut iokit = MockIOKit::new();
+        iokit
+            .expect_io_service_get_matching_service()
+            .returning(|_| Some(create_test_service_with_id(3)));
+        iokit.expect_io_registry_entry_create_cf_properties().returning(|entry| {
+            if entry.as_raw() != 1 {
+                return Ok(create_test_dictionary());
+            }
+            let keys = [NSString::from_str("vendor-id"), NSString::from_str("device-id")];
+            let values: Vec<Retained<NSObject>> = [0x144du32, 0xa808]
+                .into_iter()
+                .map(|id| Retained::into_super(NSData::with_bytes(&id.to_le_bytes())))
+                .collect();
+            Ok(NSDictionary::from_slices(&[&*keys[0], &*keys[1]], &[&*values[0], &*values[1]]))
+        });
+        iokit.expect_io_registry_entry_get_parent().returning(|entry| {
+            (entry.as_raw() > 1).then(|| create_test_service_with_id(entry.as_raw() - 1))
+        });
+
+        let internal = volume("/dev/disk3s1", "apfs");
+        assert_eq!(
+            internal.pci_device_from(&iokit),
+            Some(PciDevice { vendor_id: 0x144d, device_id: 0xa808 })
+        );
+
+        // A network share has no drive to look up
+        let share = volume("//user@server/share", "smbfs");
+        assert_eq!(share.pci_device_from(&iokit), None);
+    }
 }
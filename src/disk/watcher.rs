@@ -0,0 +1,621 @@
+//! Mount, unmount and change notifications of disks
+//!
+//! [`DiskWatcher`] runs a DiskArbitration session on a thread of its own, scheduled on that thread's run loop. The
+//! session calls back with every disk that appears, disappears or changes its description, and the callbacks forward
+//! the BSD name and mount point of the disk to a tokio task.
+//!
+//! Mounting an APFS volume changes its description several times in a row, and the session reports every disk that
+//! already exists when it starts. The task therefore waits until the notifications have been quiet for a moment,
+//! compares the mount point of each disk with the one it saw before, and only sends a [`DiskEvent`] for the disks whose
+//! mount actually changed.
+
+use std::{
+    collections::HashMap,
+    ffi::{c_void, CStr},
+    ptr,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use super::Disk;
+use crate::{
+    error::{Error, Result},
+    shutdown::{self, ComponentHandle},
+    utils::bindings::{
+        kCFRunLoopDefaultMode, kDADiskDescriptionVolumePathKey, CFDictionaryGetValue, CFRelease,
+        CFRetain, CFRunLoopGetCurrent, CFRunLoopRunInMode, CFRunLoopStop,
+        CFURLGetFileSystemRepresentation, DADiskCopyDescription, DADiskGetBSDName,
+        DARegisterDiskAppearedCallback, DARegisterDiskDescriptionChangedCallback,
+        DARegisterDiskDisappearedCallback, DASessionCreate, DASessionScheduleWithRunLoop,
+        DASessionUnscheduleFromRunLoop, DAUnregisterCallback,
+    },
+};
+
+/// Name of the event task in the shutdown registry
+pub(crate) const DISK_WATCHER: &str = "disk-watcher";
+/// Name of the thread running the DiskArbitration session, in the shutdown registry and for the OS
+pub(crate) const DISK_ARBITRATION_SESSION: &str = "disk-arbitration-session";
+
+/// How long the notifications have to be quiet before the burst they belong to is turned into events
+const COALESCE_WINDOW: Duration = Duration::from_millis(250);
+/// Longest a burst is collected, so a disk that keeps changing doesn't hold back every event
+const MAX_BURST: Duration = Duration::from_secs(2);
+/// Number of events buffered for a receiver that doesn't keep up
+const EVENT_CAPACITY: usize = 64;
+/// Seconds the session thread runs its run loop before checking for a stop request
+const RUN_LOOP_SLICE: f64 = 0.25;
+/// `kCFRunLoopRunFinished`: the run loop has no sources left
+const RUN_LOOP_FINISHED: i32 = 1;
+
+/// A change to the mounted volumes
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiskEvent {
+    /// A volume was mounted, e.g. because an external drive was plugged in
+    Mounted(Disk),
+    /// A volume was unmounted, or its disk was ejected or unplugged while mounted
+    Unmounted {
+        /// BSD name of the volume's disk, e.g. `disk4s1`
+        bsd_name: String,
+        /// Where the volume was mounted
+        mount_point: String,
+    },
+    /// The description of a mounted volume changed, e.g. because it was renamed and moved to a new mount point
+    Changed(Disk),
+}
+
+/// What a DiskArbitration callback reported about a disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DiskNotification {
+    /// The disk appeared, or already existed when the session started
+    Appeared { bsd_name: String, mount_point: Option<String> },
+    /// The description of the disk changed
+    Changed { bsd_name: String, mount_point: Option<String> },
+    /// The disk was ejected or unplugged
+    Disappeared { bsd_name: String },
+}
+
+/// Mount points by BSD name, which turns bursts of notifications into events
+#[derive(Debug, Default)]
+pub(crate) struct MountTracker {
+    mounts: HashMap<String, String>,
+}
+
+impl MountTracker {
+    /// Creates a tracker knowing the mount point of each mounted disk, by BSD name.
+    pub(crate) fn new(mounts: HashMap<String, String>) -> Self {
+        Self { mounts }
+    }
+
+    /// Applies a burst of notifications, returning an event for each disk whose mount differs from before.
+    ///
+    /// Only the last mount point reported for a disk counts. `resolve` reads the volume mounted at a mount point; a
+    /// volume it can't read isn't reported as mounted until a later notification, and a changed one isn't reported.
+    pub(crate) fn apply(
+        &mut self,
+        burst: &[DiskNotification],
+        resolve: impl Fn(&str) -> Option<Disk>,
+    ) -> Vec<DiskEvent> {
+        // The last mount point of each disk and whether its description changed, in the order the disks came up
+        let mut latest: Vec<(&str, Option<&str>, bool)> = Vec::new();
+        for notification in burst {
+            let (bsd_name, mount_point, changed) = match notification {
+                DiskNotification::Appeared { bsd_name, mount_point } => {
+                    (bsd_name, mount_point.as_deref(), false)
+                },
+                DiskNotification::Changed { bsd_name, mount_point } => {
+                    (bsd_name, mount_point.as_deref(), true)
+                },
+                DiskNotification::Disappeared { bsd_name } => (bsd_name, None, false),
+            };
+            match latest.iter_mut().find(|(name, ..)| *name == bsd_name.as_str()) {
+                Some(entry) => {
+                    entry.1 = mount_point;
+                    entry.2 |= changed;
+                },
+                None => latest.push((bsd_name, mount_point, changed)),
+            }
+        }
+
+        let mut events = Vec::new();
+        for (bsd_name, mount_point, changed) in latest {
+            match (self.mounts.get(bsd_name), mount_point) {
+                (None, Some(mount_point)) => {
+                    if let Some(disk) = resolve(mount_point) {
+                        self.mounts.insert(bsd_name.to_string(), mount_point.to_string());
+                        events.push(DiskEvent::Mounted(disk));
+                    }
+                },
+                (Some(_), None) => {
+                    let mount_point = self.mounts.remove(bsd_name).unwrap_or_default();
+                    events
+                        .push(DiskEvent::Unmounted { bsd_name: bsd_name.to_string(), mount_point });
+                },
+                (Some(previous), Some(mount_point)) if changed || previous != mount_point => {
+                    self.mounts.insert(bsd_name.to_string(), mount_point.to_string());
+                    if let Some(disk) = resolve(mount_point) {
+                        events.push(DiskEvent::Changed(disk));
+                    }
+                },
+                _ => {},
+            }
+        }
+        events
+    }
+}
+
+/// Watches volumes being mounted, unmounted and changed, without polling
+///
+/// The events come from a DiskArbitration session on a background thread. A mount is reported once its burst of
+/// notifications settled, a fraction of a second after it happened. The session and its task stop when the watcher is
+/// dropped or the crate is [shut down](crate::shutdown()).
+///
+/// ```rust,no_run
+/// # async fn example() -> darwin_metrics::Result<()> {
+/// use darwin_metrics::disk::{DiskEvent, DiskWatcher};
+///
+/// let mut watcher = DiskWatcher::spawn()?;
+/// while let Some(event) = watcher.recv().await {
+///     match event {
+///         DiskEvent::Mounted(disk) => println!("{} mounted at {}", disk.name, disk.mount_point),
+///         DiskEvent::Unmounted { mount_point, .. } => println!("{mount_point} unmounted"),
+///         DiskEvent::Changed(disk) => println!("{} changed", disk.name),
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct DiskWatcher {
+    receiver: mpsc::Receiver<DiskEvent>,
+    task: JoinHandle<()>,
+    /// None when the notifications come from elsewhere, as in tests
+    _session: Option<SessionThread>,
+}
+
+impl DiskWatcher {
+    /// Starts watching the volumes, reporting the changes from now on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the DiskArbitration session can't be created.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn spawn() -> Result<Self> {
+        // The volumes mounted before the session starts are reported by it too, and only need to be recognized
+        let tracker = MountTracker::new(current_mounts());
+        let (sender, notifications) = mpsc::unbounded_channel();
+        let session = SessionThread::spawn(sender)?;
+
+        Ok(Self::spawn_with(
+            notifications,
+            tracker,
+            |mount_point| Disk::get_for_path(mount_point).ok(),
+            Some(session),
+        ))
+    }
+
+    /// Starts turning `notifications` into events, reading the mounted volumes with `resolve`.
+    pub(crate) fn spawn_with(
+        mut notifications: mpsc::UnboundedReceiver<DiskNotification>,
+        mut tracker: MountTracker,
+        resolve: impl Fn(&str) -> Option<Disk> + Send + 'static,
+        session: Option<SessionThread>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(EVENT_CAPACITY);
+        let component = shutdown::register(DISK_WATCHER);
+        let task_component = Arc::clone(&component);
+
+        let task = tokio::spawn(async move {
+            let _running = task_component.running();
+
+            while !task_component.is_stop_requested() {
+                let first = tokio::select! {
+                    notification = notifications.recv() => match notification {
+                        Some(notification) => notification,
+                        None => break,
+                    },
+                    _ = task_component.stop_requested() => break,
+                };
+
+                let mut burst = vec![first];
+                let deadline = tokio::time::Instant::now() + MAX_BURST;
+                while let Ok(Some(notification)) = tokio::time::timeout_at(
+                    deadline.min(tokio::time::Instant::now() + COALESCE_WINDOW),
+                    notifications.recv(),
+                )
+                .await
+                {
+                    burst.push(notification);
+                }
+
+                for event in tracker.apply(&burst, &resolve) {
+                    if sender.send(event).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        let abort = task.abort_handle();
+        component.set_abort(move || abort.abort());
+
+        Self { receiver, task, _session: session }
+    }
+
+    /// Waits for the next event.
+    ///
+    /// Returns None once the watcher stopped, e.g. because the crate was shut down.
+    pub async fn recv(&mut self) -> Option<DiskEvent> {
+        self.receiver.recv().await
+    }
+
+    /// The channel the events are delivered through, e.g. to poll it in a `select!`
+    pub fn receiver(&mut self) -> &mut mpsc::Receiver<DiskEvent> {
+        &mut self.receiver
+    }
+}
+
+impl Drop for DiskWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Mount points of the mounted disks by BSD name, e.g. `disk3s1` to `/`
+fn current_mounts() -> HashMap<String, String> {
+    let Ok(filesystems) = super::mounted_filesystems() else {
+        return HashMap::new();
+    };
+
+    filesystems
+        .iter()
+        .filter_map(|stat| {
+            // SAFETY: statfs NUL-terminates its name buffers
+            let (device, mount_point) = unsafe {
+                (
+                    CStr::from_ptr(stat.f_mntfromname.as_ptr()).to_string_lossy(),
+                    CStr::from_ptr(stat.f_mntonname.as_ptr()).to_string_lossy(),
+                )
+            };
+            Some((device.strip_prefix("/dev/")?.to_string(), mount_point.into_owned()))
+        })
+        .collect()
+}
+
+/// The thread running the DiskArbitration session, stopped and joined when dropped
+#[derive(Debug)]
+pub(crate) struct SessionThread {
+    component: Arc<ComponentHandle>,
+    run_loop: RunLoop,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl SessionThread {
+    /// Starts the session, which sends its notifications to `sender`.
+    fn spawn(sender: mpsc::UnboundedSender<DiskNotification>) -> Result<Self> {
+        let component = shutdown::register(DISK_ARBITRATION_SESSION);
+        let thread_component = Arc::clone(&component);
+        let (started, run_loop) = std::sync::mpsc::channel();
+
+        let thread = thread::Builder::new()
+            .name(DISK_ARBITRATION_SESSION.to_string())
+            .spawn(move || {
+                let _running = thread_component.running();
+                // SAFETY: the session and the callback context only live on this thread
+                unsafe { run_session(sender, &thread_component, started) }
+            })
+            .map_err(|e| Error::system(format!("Failed to start the disk watcher thread: {e}")))?;
+
+        let run_loop = run_loop
+            .recv()
+            .map_err(|_| Error::system("The disk watcher thread ended while starting"))??;
+        Ok(Self { component, run_loop, thread: Some(thread) })
+    }
+}
+
+impl Drop for SessionThread {
+    fn drop(&mut self) {
+        self.component.request_stop();
+        self.run_loop.stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The run loop of the session thread, retained so other threads can stop it
+#[derive(Debug)]
+struct RunLoop(*mut c_void);
+
+// SAFETY: CFRunLoopStop may be called from any thread, and the run loop is only released once
+unsafe impl Send for RunLoop {}
+unsafe impl Sync for RunLoop {}
+
+impl RunLoop {
+    fn stop(&self) {
+        // SAFETY: the run loop is retained until dropped
+        unsafe { CFRunLoopStop(self.0) }
+    }
+}
+
+impl Drop for RunLoop {
+    fn drop(&mut self) {
+        // SAFETY: balances the CFRetain of run_session
+        unsafe { CFRelease(self.0) }
+    }
+}
+
+/// Runs a DiskArbitration session on the run loop of the current thread until a stop is requested.
+///
+/// Sends the retained run loop, or the reason the session couldn't be created, to `started` first.
+unsafe fn run_session(
+    sender: mpsc::UnboundedSender<DiskNotification>,
+    component: &ComponentHandle,
+    started: std::sync::mpsc::Sender<Result<RunLoop>>,
+) {
+    let session = DASessionCreate(ptr::null());
+    if session.is_null() {
+        let _ = started.send(Err(Error::not_available("DiskArbitration session")));
+        return;
+    }
+
+    let run_loop = CFRunLoopGetCurrent();
+    CFRetain(run_loop);
+    let context = Box::into_raw(Box::new(sender)) as *mut c_void;
+    DARegisterDiskAppearedCallback(session, ptr::null(), disk_appeared, context);
+    DARegisterDiskDisappearedCallback(session, ptr::null(), disk_disappeared, context);
+    DARegisterDiskDescriptionChangedCallback(
+        session,
+        ptr::null(),
+        ptr::null(),
+        disk_description_changed,
+        context,
+    );
+    DASessionScheduleWithRunLoop(session, run_loop, kCFRunLoopDefaultMode);
+    let _ = started.send(Ok(RunLoop(run_loop)));
+
+    // Running in slices notices a stop requested by a shutdown, which doesn't stop the run loop itself
+    while !component.is_stop_requested() {
+        if CFRunLoopRunInMode(kCFRunLoopDefaultMode, RUN_LOOP_SLICE, 0) == RUN_LOOP_FINISHED {
+            break;
+        }
+    }
+
+    DASessionUnscheduleFromRunLoop(session, run_loop, kCFRunLoopDefaultMode);
+    DAUnregisterCallback(session, disk_appeared as *mut c_void, context);
+    DAUnregisterCallback(session, disk_disappeared as *mut c_void, context);
+    DAUnregisterCallback(session, disk_description_changed as *mut c_void, context);
+    CFRelease(session);
+    drop(Box::from_raw(context as *mut mpsc::UnboundedSender<DiskNotification>));
+}
+
+unsafe extern "C" fn disk_appeared(disk: *mut c_void, context: *mut c_void) {
+    forward(disk, context, |bsd_name| DiskNotification::Appeared {
+        bsd_name,
+        mount_point: volume_path(disk),
+    });
+}
+
+unsafe extern "C" fn disk_disappeared(disk: *mut c_void, context: *mut c_void) {
+    forward(disk, context, |bsd_name| DiskNotification::Disappeared { bsd_name });
+}
+
+unsafe extern "C" fn disk_description_changed(
+    disk: *mut c_void,
+    _keys: *const c_void,
+    context: *mut c_void,
+) {
+    forward(disk, context, |bsd_name| DiskNotification::Changed {
+        bsd_name,
+        mount_point: volume_path(disk),
+    });
+}
+
+/// Sends the notification about `disk` to the task, skipping disks without a BSD name.
+unsafe fn forward(
+    disk: *mut c_void,
+    context: *mut c_void,
+    notification: impl FnOnce(String) -> DiskNotification,
+) {
+    let bsd_name = DADiskGetBSDName(disk);
+    if bsd_name.is_null() {
+        return;
+    }
+    let bsd_name = CStr::from_ptr(bsd_name).to_string_lossy().into_owned();
+
+    let sender = &*(context as *const mpsc::UnboundedSender<DiskNotification>);
+    // Fails only while the watcher is being dropped
+    let _ = sender.send(notification(bsd_name));
+}
+
+/// Reads the mount point from the description of `disk`, None while it isn't mounted.
+unsafe fn volume_path(disk: *mut c_void) -> Option<String> {
+    let description = DADiskCopyDescription(disk);
+    if description.is_null() {
+        return None;
+    }
+
+    let url = CFDictionaryGetValue(description, kDADiskDescriptionVolumePathKey);
+    let mut buffer = [0u8; libc::PATH_MAX as usize];
+    let path = (!url.is_null()
+        && CFURLGetFileSystemRepresentation(url, 1, buffer.as_mut_ptr(), buffer.len() as isize)
+            != 0)
+        .then(|| CStr::from_bytes_until_nul(&buffer).ok())
+        .flatten()
+        .map(|path| path.to_string_lossy().into_owned());
+
+    CFRelease(description);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disk(bsd_name: &str, mount_point: &str) -> Disk {
+        Disk::new(
+            format!("/dev/{bsd_name}"),
+            mount_point.to_string(),
+            "apfs".to_string(),
+            1_000,
+            400,
+            600,
+        )
+    }
+
+    /// Reads every mount point as a volume of `disk4s1`
+    fn resolve(mount_point: &str) -> Option<Disk> {
+        Some(disk("disk4s1", mount_point))
+    }
+
+    fn appeared(bsd_name: &str, mount_point: Option<&str>) -> DiskNotification {
+        DiskNotification::Appeared {
+            bsd_name: bsd_name.to_string(),
+            mount_point: mount_point.map(str::to_string),
+        }
+    }
+
+    fn changed(bsd_name: &str, mount_point: Option<&str>) -> DiskNotification {
+        DiskNotification::Changed {
+            bsd_name: bsd_name.to_string(),
+            mount_point: mount_point.map(str::to_string),
+        }
+    }
+
+    fn disappeared(bsd_name: &str) -> DiskNotification {
+        DiskNotification::Disappeared { bsd_name: bsd_name.to_string() }
+    }
+
+    #[test]
+    fn test_existing_volumes_are_not_reported() {
+        let mut tracker =
+            MountTracker::new(HashMap::from([("disk3s1".to_string(), "/".to_string())]));
+
+        // The burst a new session starts with
+        let events =
+            tracker.apply(&[appeared("disk3", None), appeared("disk3s1", Some("/"))], resolve);
+        assert!(events.is_empty(), "{events:?}");
+    }
+
+    #[test]
+    fn test_mount_and_unmount() {
+        let mut tracker = MountTracker::default();
+
+        // An external drive is plugged in: the whole disk and its volume appear, then the volume is mounted
+        let burst = [
+            appeared("disk4", None),
+            appeared("disk4s1", None),
+            changed("disk4s1", Some("/Volumes/USB")),
+        ];
+        assert_eq!(
+            tracker.apply(&burst, resolve),
+            [DiskEvent::Mounted(disk("disk4s1", "/Volumes/USB"))]
+        );
+
+        // Ejecting unmounts the volume before the disks go away
+        let burst = [changed("disk4s1", None), disappeared("disk4s1"), disappeared("disk4")];
+        assert_eq!(
+            tracker.apply(&burst, resolve),
+            [DiskEvent::Unmounted {
+                bsd_name: "disk4s1".to_string(),
+                mount_point: "/Volumes/USB".to_string()
+            }]
+        );
+
+        // Unplugging without ejecting only makes the disks disappear
+        tracker.apply(&[appeared("disk4s1", Some("/Volumes/USB"))], resolve);
+        let events = tracker.apply(&[disappeared("disk4s1")], resolve);
+        assert!(
+            matches!(&events[..], [DiskEvent::Unmounted { mount_point, .. }] if mount_point == "/Volumes/USB")
+        );
+    }
+
+    #[test]
+    fn test_apfs_burst_is_one_event() {
+        let mut tracker = MountTracker::default();
+
+        let burst = [
+            appeared("disk5s1", None),
+            changed("disk5s1", None),
+            changed("disk5s1", Some("/Volumes/Backup")),
+            changed("disk5s1", Some("/Volumes/Backup")),
+            changed("disk5s1", Some("/Volumes/Backup")),
+        ];
+        assert_eq!(
+            tracker.apply(&burst, resolve),
+            [DiskEvent::Mounted(disk("disk4s1", "/Volumes/Backup"))]
+        );
+    }
+
+    #[test]
+    fn test_changed_volumes() {
+        let mut tracker =
+            MountTracker::new(HashMap::from([("disk4s1".to_string(), "/Volumes/USB".to_string())]));
+
+        // Renaming a volume moves its mount point
+        let events = tracker.apply(&[changed("disk4s1", Some("/Volumes/Photos"))], resolve);
+        assert_eq!(events, [DiskEvent::Changed(disk("disk4s1", "/Volumes/Photos"))]);
+
+        // Changes to unmounted disks aren't reported
+        assert!(tracker.apply(&[changed("disk6", None)], resolve).is_empty());
+
+        // A volume that can't be read yet is reported once it can be
+        assert!(tracker.apply(&[changed("disk7s1", Some("/Volumes/New"))], |_| None).is_empty());
+        assert_eq!(tracker.apply(&[changed("disk7s1", Some("/Volumes/New"))], resolve).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_watcher_delivers_events() {
+        let _serial = crate::shutdown::TEST_SERIAL.lock().await;
+
+        let (notifications, receiver) = mpsc::unbounded_channel();
+        let mut watcher = DiskWatcher::spawn_with(receiver, MountTracker::default(), resolve, None);
+
+        notifications.send(appeared("disk4s1", None)).unwrap();
+        notifications.send(changed("disk4s1", Some("/Volumes/USB"))).unwrap();
+        let event = tokio::time::timeout(Duration::from_secs(5), watcher.recv()).await.unwrap();
+        assert_eq!(event, Some(DiskEvent::Mounted(disk("disk4s1", "/Volumes/USB"))));
+
+        notifications.send(disappeared("disk4s1")).unwrap();
+        let event = tokio::time::timeout(Duration::from_secs(5), watcher.receiver().recv()).await;
+        assert!(matches!(event, Ok(Some(DiskEvent::Unmounted { .. }))), "{event:?}");
+
+        // Without a source of notifications, the watcher ends
+        drop(notifications);
+        assert_eq!(
+            tokio::time::timeout(Duration::from_secs(5), watcher.recv()).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_the_watcher() {
+        let _serial = crate::shutdown::TEST_SERIAL.lock().await;
+
+        let (_notifications, receiver) = mpsc::unbounded_channel();
+        let mut watcher = DiskWatcher::spawn_with(receiver, MountTracker::default(), resolve, None);
+
+        let timeout = Duration::from_secs(5);
+        let report = tokio::task::spawn_blocking(move || crate::shutdown(timeout)).await.unwrap();
+
+        assert!(
+            report.components.iter().any(|component| component.name == DISK_WATCHER
+                && component.outcome == crate::shutdown::ShutdownOutcome::Stopped),
+            "{report:?}"
+        );
+        assert_eq!(tokio::time::timeout(timeout, watcher.recv()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_session_starts_and_stops() {
+        let _serial = crate::shutdown::TEST_SERIAL.lock().await;
+
+        let watcher = DiskWatcher::spawn().expect("Failed to start a DiskArbitration session");
+        // Dropping stops the run loop and joins the session thread
+        drop(watcher);
+    }
+}
@@ -0,0 +1,105 @@
+//! Mock data sources for the crate's documentation examples.
+//!
+//! Enabled by the `doctest-support` feature, which the crate's dev-dependency on itself turns on for `cargo test`, so
+//! that `cargo test --doc` runs the module examples against known values instead of the hardware. Not part of the
+//! public API.
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    hardware::iokit::{CpuTemperatureSource, FanInfo, FanMode, IOKit, MockIOKit, ThermalInfo},
+    process::{AppNapState, Process},
+    utils::test_utils::create_test_dictionary,
+    Error,
+};
+
+/// CPU temperature reported by [`mock_iokit_with_defaults`], in °C
+pub const MOCK_CPU_TEMPERATURE: f64 = 42.5;
+/// GPU temperature reported by [`mock_iokit_with_defaults`], in °C
+pub const MOCK_GPU_TEMPERATURE: f64 = 55.0;
+/// Speed of the single fan reported by [`mock_iokit_with_defaults`], in RPM
+pub const MOCK_FAN_SPEED: u32 = 2000;
+
+/// Thermal readings of an idle machine with one fan
+fn mock_thermal_info() -> ThermalInfo {
+    ThermalInfo {
+        cpu_temp: MOCK_CPU_TEMPERATURE,
+        gpu_temp: Some(MOCK_GPU_TEMPERATURE),
+        heatsink_temp: Some(45.0),
+        ambient_temp: Some(32.0),
+        battery_temp: Some(38.0),
+        is_throttling: false,
+        cpu_power: Some(28.5),
+        cpu_temp_source: CpuTemperatureSource::Smc,
+        ..Default::default()
+    }
+}
+
+fn mock_fan() -> FanInfo {
+    FanInfo {
+        speed_rpm: MOCK_FAN_SPEED,
+        min_speed: 1000,
+        max_speed: 4000,
+        percentage: 33.3,
+        target_rpm: Some(MOCK_FAN_SPEED),
+        mode: FanMode::Auto,
+        name: None,
+    }
+}
+
+/// Returns an IOKit mock answering the sensor, fan and SMC queries of the monitors with fixed readings.
+///
+/// The readings match the data used when the `skip-ffi-crashes` feature is enabled. Registry lookups fail with a
+/// service-not-found error, as on a machine without the service.
+pub fn mock_iokit_with_defaults() -> Arc<dyn IOKit> {
+    let mut iokit = MockIOKit::new();
+
+    iokit.expect_get_thermal_info().returning(|| Ok(mock_thermal_info()));
+    iokit.expect_get_cpu_temperature().returning(|| Ok(MOCK_CPU_TEMPERATURE));
+    iokit
+        .expect_get_cpu_temperature_with_source()
+        .returning(|| Ok((MOCK_CPU_TEMPERATURE, CpuTemperatureSource::Smc)));
+    iokit.expect_get_gpu_temperature().returning(|| Ok(MOCK_GPU_TEMPERATURE));
+    iokit.expect_get_heatsink_temperature().returning(|| Ok(45.0));
+    iokit.expect_get_ambient_temperature().returning(|| Ok(32.0));
+    iokit.expect_get_battery_temperature().returning(|| Ok(38.0));
+    iokit.expect_get_cpu_power().returning(|| Ok(28.5));
+    iokit.expect_check_thermal_throttling().returning(|| Ok(false));
+
+    iokit.expect_get_fan_count().returning(|| Ok(1));
+    iokit.expect_get_fan_speed().returning(|| Ok(MOCK_FAN_SPEED));
+    iokit.expect_get_fan_info().returning(|index| match index {
+        0 => Ok(mock_fan()),
+        _ => Err(Error::invalid_data(format!("No fan at index {index}"))),
+    });
+    iokit.expect_get_all_fans().returning(|| Ok(vec![mock_fan()]));
+    iokit.expect_get_fan_target().returning(|_| Ok(MOCK_FAN_SPEED));
+    iokit.expect_get_fan_mode().returning(|_| Ok(FanMode::Auto));
+    iokit.expect_get_fan_name().returning(|_| Err(Error::not_available("Fan descriptors")));
+
+    iokit.expect_read_smc_key().returning(|_| Ok(0.0));
+    iokit.expect_get_service().returning(|name| Err(Error::service_not_found(name.to_string())));
+    iokit.expect_io_service_matching().returning(|_| create_test_dictionary());
+    iokit.expect_io_service_get_matching_service().returning(|_| None);
+
+    Arc::new(iokit)
+}
+
+/// Returns a fixed process table: a busy compiler, a napping application and an idle daemon.
+pub fn mock_processes() -> Vec<Process> {
+    let process = |pid, name, cpu_usage, memory_usage, app_nap_state| {
+        let mut process = Process::new(pid, name);
+        process.cpu_usage = cpu_usage;
+        process.memory_usage = memory_usage;
+        process.uptime = Duration::from_secs(600);
+        process.thread_count = 4;
+        process.app_nap_state = Some(app_nap_state);
+        process
+    };
+
+    vec![
+        process(501, "rustc", 97.5, 1_200_000_000, AppNapState::Active),
+        process(502, "Preview", 35.0, 300_000_000, AppNapState::Napping),
+        process(88, "mdworker", 0.4, 20_000_000, AppNapState::Suppressed),
+    ]
+}
@@ -2,18 +2,57 @@ use std::{io, result};
 
 use thiserror::Error;
 
+use crate::utils::bindings::{
+    IO_RETURN_BAD_ARGUMENT, IO_RETURN_NOT_FOUND, IO_RETURN_NOT_PERMITTED, IO_RETURN_NOT_PRIVILEGED,
+    IO_RETURN_NO_DEVICE, IO_RETURN_TIMEOUT, IO_RETURN_UNSUPPORTED,
+};
+
+/// What went wrong, independent of the subsystem that failed
+///
+/// Match on the kind instead of on the variants of [`Error`], whose shapes and messages may change. New kinds can be
+/// added in minor releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The feature or sensor doesn't exist on this machine
+    NotAvailable,
+    /// The caller lacks the privileges, e.g. root or an entitlement
+    PermissionDenied,
+    /// An IOKit service couldn't be found
+    ServiceNotFound,
+    /// The requested object doesn't exist, e.g. a process that exited
+    NotFound,
+    /// Input or data read from the system was malformed
+    InvalidData,
+    /// A system call or framework failed
+    System,
+    /// A call into IOKit or another C interface failed
+    Ffi,
+    /// An operation timed out
+    Timeout,
+    /// The operation isn't supported, or not implemented yet
+    Unsupported,
+    /// Anything else
+    Other,
+}
+
 /// Specific error types for darwin-metrics
 #[derive(Error, Debug, Clone)]
 #[non_exhaustive]
 pub enum Error {
-    /// Error originating from the system's IO subsystem
+    /// Error originating from the system's IO subsystem, with the `errno` it was caused by if any
     #[error("IO error: {kind} - {message}")]
-    Io { kind: io::ErrorKind, message: String },
+    Io { kind: io::ErrorKind, message: String, code: Option<i32> },
 
     /// Error related to IOKit operations
     #[error("IOKit error: {0}")]
     IOKit(String),
 
+    /// An IOKit call that returned the `IOReturn` status `code`
+    #[error("IOKit error: {message} (IOReturn {code:#x})")]
+    IOReturn { code: i32, message: String },
+
     /// Error related to temperature monitoring
     #[error("Temperature monitoring error: {0}")]
     Temperature(String),
@@ -38,6 +77,10 @@ pub enum Error {
     #[error("Process monitoring error: {0}")]
     Process(String),
 
+    /// The PID of a monitored process now belongs to a different process
+    #[error("Process {pid} exited and its PID was reused by another process")]
+    PidReused { pid: u32 },
+
     /// Error related to system information retrieval
     #[error("System info error: {0}")]
     SystemInfo(String),
@@ -77,6 +120,21 @@ impl Error {
         Error::IOKit(message.into())
     }
 
+    /// Create a new IOKit error for a call that returned the `IOReturn` status `code`
+    pub fn io_return<S: Into<String>>(message: S, code: i32) -> Self {
+        Error::IOReturn { code, message: message.into() }
+    }
+
+    /// Create an IO error from `error`, e.g. `io::Error::last_os_error()`, keeping its kind and `errno` and prefixing
+    /// its message with `context`
+    pub fn os_error<S: std::fmt::Display>(context: S, error: io::Error) -> Self {
+        Error::Io {
+            kind: error.kind(),
+            message: format!("{context}: {error}"),
+            code: error.raw_os_error(),
+        }
+    }
+
     /// Create a new system error
     pub fn system<S: Into<String>>(message: S) -> Self {
         Error::System(message.into())
@@ -115,7 +173,7 @@ impl Error {
     /// Get details about the error
     pub fn details(&self) -> String {
         match self {
-            Error::Io { kind, message } => format!("{}: {}", message, kind),
+            Error::Io { kind, message, .. } => format!("{}: {}", message, kind),
             Error::PermissionDenied(msg) => {
                 format!("Permission denied: {}. Try running with elevated privileges.", msg)
             },
@@ -131,15 +189,72 @@ impl Error {
         }
     }
 
-    /// Determine if this error is caused by insufficient permissions
+    /// What kind of error this is, for handling errors without matching on the variants
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io { kind, .. } => match kind {
+                io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+                io::ErrorKind::NotFound => ErrorKind::NotFound,
+                io::ErrorKind::TimedOut => ErrorKind::Timeout,
+                io::ErrorKind::Unsupported => ErrorKind::Unsupported,
+                io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => ErrorKind::InvalidData,
+                _ => ErrorKind::System,
+            },
+            Error::IOReturn { code, .. } => match *code {
+                IO_RETURN_NOT_PRIVILEGED | IO_RETURN_NOT_PERMITTED => ErrorKind::PermissionDenied,
+                IO_RETURN_NO_DEVICE => ErrorKind::NotAvailable,
+                IO_RETURN_NOT_FOUND => ErrorKind::NotFound,
+                IO_RETURN_BAD_ARGUMENT => ErrorKind::InvalidData,
+                IO_RETURN_TIMEOUT => ErrorKind::Timeout,
+                IO_RETURN_UNSUPPORTED => ErrorKind::Unsupported,
+                _ => ErrorKind::Ffi,
+            },
+            Error::IOKit(_) => ErrorKind::Ffi,
+            Error::Temperature(_)
+            | Error::Cpu(_)
+            | Error::Gpu(_)
+            | Error::Memory(_)
+            | Error::Network(_)
+            | Error::Process(_)
+            | Error::SystemInfo(_)
+            | Error::System(_) => ErrorKind::System,
+            Error::PidReused { .. } => ErrorKind::NotFound,
+            Error::ServiceNotFound(_) => ErrorKind::ServiceNotFound,
+            Error::InvalidData(_) => ErrorKind::InvalidData,
+            Error::NotImplemented(_) => ErrorKind::Unsupported,
+            Error::NotAvailable(_) => ErrorKind::NotAvailable,
+            Error::PermissionDenied(_) => ErrorKind::PermissionDenied,
+            Error::Other(_) => ErrorKind::Other,
+        }
+    }
+
+    /// The OS status the error was caused by: the `errno` of an IO error, or the `IOReturn` of an IOKit call
+    pub fn os_code(&self) -> Option<i32> {
+        match self {
+            Error::Io { code, .. } => *code,
+            Error::IOReturn { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Check if this error is caused by insufficient permissions
+    pub fn is_permission_denied(&self) -> bool {
+        self.kind() == ErrorKind::PermissionDenied
+    }
+
+    /// Determine if this error is caused by insufficient permissions, same as [`Error::is_permission_denied`]
     pub fn is_permission_error(&self) -> bool {
-        matches!(self, Error::PermissionDenied(_))
-            || matches!(self, Error::Io { kind, .. } if *kind == io::ErrorKind::PermissionDenied)
+        self.is_permission_denied()
     }
 
     /// Check if this error indicates a feature is not available
     pub fn is_not_available(&self) -> bool {
-        matches!(self, Error::NotAvailable(_))
+        self.kind() == ErrorKind::NotAvailable
+    }
+
+    /// Check if this error indicates that a monitored process was replaced by one reusing its PID
+    pub fn is_pid_reused(&self) -> bool {
+        matches!(self, Error::PidReused { .. })
     }
 }
 
@@ -149,13 +264,14 @@ pub type Result<T> = result::Result<T, Error>;
 /// Implement conversion from io::Error to Error
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
-        Error::Io { kind: err.kind(), message: err.to_string() }
+        Error::Io { kind: err.kind(), message: err.to_string(), code: err.raw_os_error() }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::bindings::IO_RETURN_NOT_OPEN;
     use std::io::{Error as IoError, ErrorKind};
 
     #[test]
@@ -189,7 +305,11 @@ mod tests {
     #[test]
     fn test_error_details_io() {
         // Test IO error details formatting - using a simpler check that avoids exact string match
-        let e1 = Error::Io { kind: ErrorKind::NotFound, message: "file not found".to_string() };
+        let e1 = Error::Io {
+            kind: ErrorKind::NotFound,
+            message: "file not found".to_string(),
+            code: None,
+        };
         let details = e1.details();
         assert!(details.contains("file not found"));
         assert!(details.contains("not found"));
@@ -230,10 +350,14 @@ mod tests {
         let e1 = Error::PermissionDenied("test".to_string());
         assert!(e1.is_permission_error());
 
-        let e2 = Error::Io { kind: ErrorKind::PermissionDenied, message: "test".to_string() };
+        let e2 = Error::Io {
+            kind: ErrorKind::PermissionDenied,
+            message: "test".to_string(),
+            code: None,
+        };
         assert!(e2.is_permission_error());
 
-        let e3 = Error::Io { kind: ErrorKind::NotFound, message: "test".to_string() };
+        let e3 = Error::Io { kind: ErrorKind::NotFound, message: "test".to_string(), code: None };
         assert!(!e3.is_permission_error());
 
         let e4 = Error::Other("test".to_string());
@@ -250,138 +374,84 @@ mod tests {
         assert!(!e2.is_not_available());
     }
 
+    #[test]
+    fn test_error_is_pid_reused() {
+        let e1 = Error::PidReused { pid: 42 };
+        assert!(e1.is_pid_reused());
+        assert!(e1.to_string().contains("42"));
+
+        let e2 = Error::process_error("test");
+        assert!(!e2.is_pid_reused());
+    }
+
     #[test]
     fn test_from_io_error() {
         // Test From<io::Error> implementation
         let io_err = IoError::new(ErrorKind::ConnectionRefused, "connection error");
         let err: Error = io_err.into();
 
-        if let Error::Io { kind, message } = err {
+        if let Error::Io { kind, message, .. } = err {
             assert_eq!(kind, ErrorKind::ConnectionRefused);
             assert!(message.contains("connection error"));
         } else {
             panic!("Error was not converted to Error::Io variant");
         }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::{Error as IoError, ErrorKind};
-
-    #[test]
-    fn test_error_creation_methods() {
-        // Test all error factory methods
-        let e1 = Error::io_kit("test io_kit error");
-        assert!(matches!(e1, Error::IOKit(s) if s == "test io_kit error"));
-
-        let e2 = Error::system("test system error");
-        assert!(matches!(e2, Error::System(s) if s == "test system error"));
-
-        let e3 = Error::invalid_data("test invalid data");
-        assert!(matches!(e3, Error::InvalidData(s) if s == "test invalid data"));
-
-        let e4 = Error::not_implemented("test feature");
-        assert!(matches!(e4, Error::NotImplemented(s) if s == "test feature"));
-
-        let e5 = Error::not_available("test feature");
-        assert!(matches!(e5, Error::NotAvailable(s) if s == "test feature"));
-
-        let e6 = Error::permission_denied("test permission");
-        assert!(matches!(e6, Error::PermissionDenied(s) if s == "test permission"));
-
-        let e7 = Error::service_not_found("test service");
-        assert!(matches!(e7, Error::ServiceNotFound(s) if s == "test service"));
-
-        let e8 = Error::process_error("test process error");
-        assert!(matches!(e8, Error::Process(s) if s == "test process error"));
-    }
 
     #[test]
-    fn test_error_details_io() {
-        // Test IO error details formatting - using a simpler check that avoids exact string match
-        let e1 = Error::Io {
-            kind: ErrorKind::NotFound,
-            message: "file not found".to_string(),
-        };
-        let details = e1.details();
-        assert!(details.contains("file not found"));
-        assert!(details.contains("not found"));
-    }
-    
-    #[test]
-    fn test_error_details_permission() {
-        // Test permission error details
-        let e = Error::PermissionDenied("access denied".to_string());
-        assert!(e.details().contains("Permission denied: access denied"));
-        assert!(e.details().contains("elevated privileges"));
-    }
-    
-    #[test]
-    fn test_error_details_iokit() {
-        // Test IOKit error details
-        let e = Error::IOKit("service failed".to_string());
-        assert!(e.details().contains("IOKit error: service failed"));
-    }
-    
-    #[test]
-    fn test_error_details_not_available() {
-        // Test not available error details
-        let e = Error::NotAvailable("GPU features".to_string());
-        assert!(e.details().contains("Feature not available: GPU features"));
-    }
-    
-    #[test]
-    fn test_error_details_other() {
-        // Test other error details
-        let e = Error::Other("generic error".to_string());
-        assert_eq!(e.details(), "Error: generic error");
-    }
-
-    #[test]
-    fn test_error_is_permission_error() {
-        // Test is_permission_error method
-        let e1 = Error::PermissionDenied("test".to_string());
-        assert!(e1.is_permission_error());
-
-        let e2 = Error::Io {
-            kind: ErrorKind::PermissionDenied,
-            message: "test".to_string(),
-        };
-        assert!(e2.is_permission_error());
-
-        let e3 = Error::Io {
-            kind: ErrorKind::NotFound,
-            message: "test".to_string(),
-        };
-        assert!(!e3.is_permission_error());
-
-        let e4 = Error::Other("test".to_string());
-        assert!(!e4.is_permission_error());
+    fn test_error_kind() {
+        // `ErrorKind` here is the one of std::io, the crate's is `super::ErrorKind`
+        let cases = [
+            (Error::io_kit("test"), super::ErrorKind::Ffi),
+            (Error::system("test"), super::ErrorKind::System),
+            (Error::process_error("test"), super::ErrorKind::System),
+            (Error::invalid_data("test"), super::ErrorKind::InvalidData),
+            (Error::not_implemented("test"), super::ErrorKind::Unsupported),
+            (Error::not_available("test"), super::ErrorKind::NotAvailable),
+            (Error::permission_denied("test"), super::ErrorKind::PermissionDenied),
+            (Error::service_not_found("test"), super::ErrorKind::ServiceNotFound),
+            (Error::PidReused { pid: 42 }, super::ErrorKind::NotFound),
+            (Error::Other("test".to_string()), super::ErrorKind::Other),
+            (
+                IoError::new(ErrorKind::PermissionDenied, "test").into(),
+                super::ErrorKind::PermissionDenied,
+            ),
+            (IoError::new(ErrorKind::TimedOut, "test").into(), super::ErrorKind::Timeout),
+            (IoError::new(ErrorKind::ConnectionRefused, "test").into(), super::ErrorKind::System),
+        ];
+        for (err, kind) in cases {
+            assert_eq!(err.kind(), kind, "{err:?}");
+            assert_eq!(err.os_code(), None, "{err:?}");
+        }
     }
 
     #[test]
-    fn test_error_is_not_available() {
-        // Test is_not_available method
-        let e1 = Error::NotAvailable("test".to_string());
-        assert!(e1.is_not_available());
-
-        let e2 = Error::NotImplemented("test".to_string());
-        assert!(!e2.is_not_available());
+    fn test_from_io_error_keeps_os_code() {
+        // EACCES
+        let err = Error::from(IoError::from_raw_os_error(13));
+        assert_eq!(err.os_code(), Some(13));
+        assert_eq!(err.kind(), super::ErrorKind::PermissionDenied);
+        assert!(err.is_permission_denied());
+
+        let err = Error::os_error("sysctl net.inet.tcp.stats", IoError::from_raw_os_error(2));
+        assert_eq!(err.os_code(), Some(2));
+        assert_eq!(err.kind(), super::ErrorKind::NotFound);
+        assert!(err.to_string().contains("sysctl net.inet.tcp.stats: "));
     }
 
     #[test]
-    fn test_from_io_error() {
-        // Test From<io::Error> implementation
-        let io_err = IoError::new(ErrorKind::ConnectionRefused, "connection error");
-        let err: Error = io_err.into();
-        
-        if let Error::Io { kind, message } = err {
-            assert_eq!(kind, ErrorKind::ConnectionRefused);
-            assert!(message.contains("connection error"));
-        } else {
-            panic!("Error was not converted to Error::Io variant");
-        }
+    fn test_io_return_error() {
+        let err = Error::io_return("Failed to open the SMC", IO_RETURN_NOT_PRIVILEGED);
+        assert_eq!(err.os_code(), Some(IO_RETURN_NOT_PRIVILEGED));
+        assert!(err.is_permission_denied());
+        assert!(err.to_string().contains("0xe00002c1"), "{err}");
+
+        assert!(Error::io_return("test", IO_RETURN_NO_DEVICE).is_not_available());
+        assert_eq!(Error::io_return("test", IO_RETURN_TIMEOUT).kind(), super::ErrorKind::Timeout);
+        assert_eq!(
+            Error::io_return("test", IO_RETURN_UNSUPPORTED).kind(),
+            super::ErrorKind::Unsupported
+        );
+        assert_eq!(Error::io_return("test", IO_RETURN_NOT_OPEN).kind(), super::ErrorKind::Ffi);
     }
 }
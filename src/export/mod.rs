@@ -0,0 +1,18 @@
+//! Exporting collected metrics to other processes
+//!
+//! This module contains opt-in transports for handing metrics collected in one process to consumers running in
+//! another process. Each transport is gated behind its own feature so that applications which only read metrics
+//! in-process don't pay for them.
+//!
+//! ## Available transports
+//!
+//! - [`prometheus`] (feature `export-prometheus`) - renders metrics in the Prometheus text exposition format for an HTTP
+//!   endpoint the application serves. No server is included.
+//! - [`shm`] (feature `export-shm`) - publishes the latest snapshot to a memory-mapped file using a seqlock so that
+//!   unprivileged readers never observe torn data. Useful when a privileged helper has SMC access and an unprivileged
+//!   UI does not, and sockets or XPC are undesirable.
+
+#[cfg(feature = "export-prometheus")]
+pub mod prometheus;
+#[cfg(feature = "export-shm")]
+pub mod shm;
@@ -0,0 +1,641 @@
+//! Prometheus text exposition of collected metrics
+//!
+//! [`PrometheusEncoder`] renders metrics in the [text exposition
+//! format](https://prometheus.io/docs/instrumenting/exposition_formats/), e.g. for an HTTP handler the application
+//! already serves. It only encodes; serving and scraping are up to the application.
+//!
+//! ## Naming
+//!
+//! Metric names follow the Prometheus conventions: snake case, the base unit as a suffix (`_bytes`, `_seconds`,
+//! `_celsius`) and `_total` for counters. Every name starts with the encoder's prefix, `darwin` by default. Per-instance
+//! values carry the instance as a label instead of in the name:
+//!
+//! | Metric | Type | Labels | Source |
+//! |--------|------|--------|--------|
+//! | `<prefix>_system_info` | gauge | `architecture` | [`encode_system`](PrometheusEncoder::encode_system) |
+//! | `<prefix>_process_cpu_usage_percent` | gauge | `pid`, `name` | [`encode_processes`](PrometheusEncoder::encode_processes) |
+//! | `<prefix>_process_resident_memory_bytes` | gauge | `pid`, `name` | `encode_processes`, `encode_snapshot` |
+//! | `<prefix>_process_uptime_seconds` | gauge | `pid`, `name` | `encode_processes` |
+//! | `<prefix>_process_threads` | gauge | `pid`, `name` | `encode_processes` |
+//! | `<prefix>_process_{read,written}_bytes_total` | counter | `pid`, `name` | `encode_processes` |
+//! | `<prefix>_process_open_fds` | gauge | `pid`, `name` | `encode_processes` |
+//! | `<prefix>_process_cpu_seconds_total` | counter | `pid`, `name` | `encode_snapshot` |
+//! | `<prefix>_{cpu,gpu,heatsink,ambient,battery}_temperature_celsius` | gauge | | [`encode_thermal`](PrometheusEncoder::encode_thermal), `encode_snapshot` |
+//! | `<prefix>_thermal_throttling` | gauge | | `encode_thermal` |
+//! | `<prefix>_cpu_power_watts` | gauge | | `encode_thermal` |
+//! | `<prefix>_fan_speed_rpm` | gauge | `fan` | `encode_thermal` |
+//! | `<prefix>_fan_speed_percent` | gauge | `fan` | `encode_thermal` |
+//! | `<prefix>_power_watts` | gauge | `component` | [`encode_power`](PrometheusEncoder::encode_power) |
+//! | `<prefix>_power_state` | gauge | `state` | `encode_power` |
+//! | `<prefix>_battery_charge_percent` | gauge | | `encode_power` |
+//! | `<prefix>_cpu_usage_ratio` | gauge | | [`encode_snapshot`](PrometheusEncoder::encode_snapshot) |
+//! | `<prefix>_load_average` | gauge | `period` | `encode_snapshot` |
+//! | `<prefix>_memory_{total,used,available,wired,compressed}_bytes` | gauge | | `encode_snapshot` |
+//! | `<prefix>_memory_pressure_ratio` | gauge | | `encode_snapshot` |
+//! | `<prefix>_memory_swap_{total,used}_bytes` | gauge | | `encode_snapshot` |
+//! | `<prefix>_disk_{read,written}_bytes_total` | counter | | `encode_snapshot` |
+//! | `<prefix>_network_{received,transmitted}_bytes_total` | counter | | `encode_snapshot` |
+//!
+//! Values that weren't collected, such as a missing sensor or a snapshot section that failed, are left out rather than
+//! reported as zero. Samples of the same metric are grouped under one `HELP` and `TYPE` header however many calls
+//! contributed them, so encode each source at most once per exposition to avoid duplicate samples.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use darwin_metrics::{
+//!     export::prometheus::PrometheusEncoder, hardware::temperature::Temperature, process::Process,
+//! };
+//!
+//! # async fn run() -> darwin_metrics::Result<()> {
+//! let mut encoder = PrometheusEncoder::new();
+//! encoder.encode_processes(&Process::get_all().await?);
+//! encoder.encode_thermal(&Temperature::new().get_thermal_metrics()?);
+//!
+//! let body = encoder.finish();
+//! // Serve `body` with content type text/plain; version=0.0.4
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    error::{Error, Result},
+    hardware::temperature::ThermalMetrics,
+    power::PowerConsumption,
+    process::Process,
+    snapshot::MetricsSnapshot,
+    system::SystemMetrics,
+};
+
+/// Prefix of the metric names of [`PrometheusEncoder::new`]
+pub const DEFAULT_PREFIX: &str = "darwin";
+
+/// Type of a metric family
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetricType {
+    Gauge,
+    Counter,
+}
+
+impl MetricType {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Gauge => "gauge",
+            Self::Counter => "counter",
+        }
+    }
+}
+
+/// The samples of one metric, rendered under a single header
+#[derive(Debug, Clone)]
+struct Family {
+    name: String,
+    help: &'static str,
+    metric_type: MetricType,
+    /// Rendered sample lines, without the trailing newline
+    samples: Vec<String>,
+}
+
+/// Renders metrics in the Prometheus text exposition format
+///
+/// See the [module documentation](self) for the metrics each method encodes.
+#[derive(Debug, Clone)]
+pub struct PrometheusEncoder {
+    prefix: String,
+    families: Vec<Family>,
+    /// Index into `families` by metric name
+    index: HashMap<String, usize>,
+}
+
+impl Default for PrometheusEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PrometheusEncoder {
+    /// Creates an encoder for metric names starting with [`DEFAULT_PREFIX`].
+    pub fn new() -> Self {
+        Self { prefix: DEFAULT_PREFIX.to_string(), families: Vec::new(), index: HashMap::new() }
+    }
+
+    /// Creates an encoder for metric names starting with `prefix`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `prefix` isn't a valid metric name: letters, digits and underscores, not starting with a
+    /// digit.
+    pub fn with_prefix(prefix: &str) -> Result<Self> {
+        if prefix.is_empty() || !is_valid_name(prefix) {
+            return Err(Error::invalid_data(format!(
+                "Invalid metric prefix {prefix:?}: use letters, digits and underscores, not starting with a digit"
+            )));
+        }
+        Ok(Self { prefix: prefix.to_string(), ..Self::new() })
+    }
+
+    /// Encodes static information about the system.
+    pub fn encode_system(&mut self, system: &SystemMetrics) {
+        let architecture = format!("{:?}", system.architecture);
+        self.gauge(
+            "system_info",
+            "Information about the system, always 1",
+            &[("architecture", &architecture)],
+            1.0,
+        );
+    }
+
+    /// Encodes the resource usage of each process, labelled with its PID and name.
+    pub fn encode_processes(&mut self, processes: &[Process]) {
+        for process in processes {
+            let pid = process.pid.to_string();
+            let labels = [("pid", pid.as_str()), ("name", process.name.as_str())];
+
+            self.gauge(
+                "process_cpu_usage_percent",
+                "CPU usage of the process in percent of one core",
+                &labels,
+                process.cpu_usage,
+            );
+            self.gauge(
+                "process_resident_memory_bytes",
+                "Resident memory of the process in bytes",
+                &labels,
+                process.memory_usage as f64,
+            );
+            self.gauge(
+                "process_uptime_seconds",
+                "Time since the process started in seconds",
+                &labels,
+                process.uptime.as_secs_f64(),
+            );
+            self.gauge(
+                "process_threads",
+                "Number of threads of the process",
+                &labels,
+                f64::from(process.thread_count),
+            );
+            self.counter(
+                "process_read_bytes_total",
+                "Bytes read from storage by the process",
+                &labels,
+                process.io_stats.read_bytes,
+            );
+            self.counter(
+                "process_written_bytes_total",
+                "Bytes written to storage by the process",
+                &labels,
+                process.io_stats.write_bytes,
+            );
+            if let Some(open_fds) = process.open_fd_count {
+                self.gauge(
+                    "process_open_fds",
+                    "Number of open file descriptors of the process",
+                    &labels,
+                    f64::from(open_fds),
+                );
+            }
+        }
+    }
+
+    /// Encodes temperatures, throttling, CPU power and fan speeds.
+    pub fn encode_thermal(&mut self, thermal: &ThermalMetrics) {
+        let temperatures = [
+            (
+                "cpu_temperature_celsius",
+                "CPU temperature in degrees Celsius",
+                thermal.cpu_temperature,
+            ),
+            (
+                "gpu_temperature_celsius",
+                "GPU temperature in degrees Celsius",
+                thermal.gpu_temperature,
+            ),
+            (
+                "heatsink_temperature_celsius",
+                "Heatsink temperature in degrees Celsius",
+                thermal.heatsink_temperature,
+            ),
+            (
+                "ambient_temperature_celsius",
+                "Ambient temperature inside the case in degrees Celsius",
+                thermal.ambient_temperature,
+            ),
+            (
+                "battery_temperature_celsius",
+                "Battery temperature in degrees Celsius",
+                thermal.battery_temperature,
+            ),
+        ];
+        for (name, help, celsius) in temperatures {
+            if let Some(celsius) = celsius {
+                self.gauge(name, help, &[], celsius);
+            }
+        }
+
+        self.gauge(
+            "thermal_throttling",
+            "Whether the CPU is thermally throttled, 1 if it is",
+            &[],
+            if thermal.is_throttling { 1.0 } else { 0.0 },
+        );
+        if let Some(watts) = thermal.cpu_power {
+            self.gauge("cpu_power_watts", "CPU power consumption in watts", &[], watts);
+        }
+
+        for fan in &thermal.fans {
+            let labels = [("fan", fan.name.as_str())];
+            self.gauge(
+                "fan_speed_rpm",
+                "Fan speed in revolutions per minute",
+                &labels,
+                f64::from(fan.speed_rpm),
+            );
+            self.gauge(
+                "fan_speed_percent",
+                "Fan speed between its minimum and maximum in percent",
+                &labels,
+                fan.percentage,
+            );
+        }
+    }
+
+    /// Encodes power consumption per component, the power state and the battery charge.
+    pub fn encode_power(&mut self, power: &PowerConsumption) {
+        let components = [
+            ("package", Some(power.package)),
+            ("cores", Some(power.cores)),
+            ("gpu", power.gpu),
+            ("dram", power.dram),
+            ("neural_engine", power.neural_engine),
+        ];
+        for (component, watts) in components {
+            if let Some(watts) = watts {
+                self.gauge(
+                    "power_watts",
+                    "Power consumption in watts",
+                    &[("component", component)],
+                    f64::from(watts),
+                );
+            }
+        }
+
+        let state = format!("{:?}", power.power_state);
+        self.gauge("power_state", "Current power state, always 1", &[("state", &state)], 1.0);
+        if let Some(percent) = power.battery_percentage {
+            self.gauge(
+                "battery_charge_percent",
+                "Battery charge in percent",
+                &[],
+                f64::from(percent),
+            );
+        }
+    }
+
+    /// Encodes every section of a snapshot that was collected.
+    pub fn encode_snapshot(&mut self, snapshot: &MetricsSnapshot) {
+        if let Some(cpu) = &snapshot.cpu {
+            self.gauge("cpu_usage_ratio", "Average CPU usage between 0 and 1", &[], cpu.usage);
+            if let Some(load_average) = cpu.load_average {
+                for (period, load) in ["1m", "5m", "15m"].into_iter().zip(load_average) {
+                    self.gauge("load_average", "System load average", &[("period", period)], load);
+                }
+            }
+        }
+
+        if let Some(memory) = &snapshot.memory {
+            let gauges = [
+                ("memory_total_bytes", "Total physical memory in bytes", memory.total),
+                ("memory_used_bytes", "Used physical memory in bytes", memory.used),
+                ("memory_available_bytes", "Available physical memory in bytes", memory.available),
+                ("memory_wired_bytes", "Wired memory in bytes", memory.wired),
+                (
+                    "memory_compressed_bytes",
+                    "Memory held by the compressor in bytes",
+                    memory.compressed,
+                ),
+                ("memory_swap_total_bytes", "Total swap space in bytes", memory.swap_total),
+                ("memory_swap_used_bytes", "Used swap space in bytes", memory.swap_used),
+            ];
+            for (name, help, bytes) in gauges {
+                self.gauge(name, help, &[], bytes as f64);
+            }
+            self.gauge(
+                "memory_pressure_ratio",
+                "Memory pressure between 0 and 1",
+                &[],
+                memory.pressure,
+            );
+        }
+
+        if let Some(disk_io) = &snapshot.disk_io {
+            self.counter(
+                "disk_read_bytes_total",
+                "Bytes read from block storage",
+                &[],
+                disk_io.bytes_read,
+            );
+            self.counter(
+                "disk_written_bytes_total",
+                "Bytes written to block storage",
+                &[],
+                disk_io.bytes_written,
+            );
+        }
+
+        if let Some(network) = &snapshot.network {
+            self.counter(
+                "network_received_bytes_total",
+                "Bytes received on all interfaces except loopback",
+                &[],
+                network.bytes_received,
+            );
+            self.counter(
+                "network_transmitted_bytes_total",
+                "Bytes sent on all interfaces except loopback",
+                &[],
+                network.bytes_sent,
+            );
+        }
+
+        if let Some(temperature) = &snapshot.temperature {
+            self.gauge(
+                "cpu_temperature_celsius",
+                "CPU temperature in degrees Celsius",
+                &[],
+                temperature.cpu_celsius,
+            );
+            if let Some(celsius) = temperature.gpu_celsius {
+                self.gauge(
+                    "gpu_temperature_celsius",
+                    "GPU temperature in degrees Celsius",
+                    &[],
+                    celsius,
+                );
+            }
+        }
+
+        for process in snapshot.processes.iter().flatten() {
+            let pid = process.pid.to_string();
+            let labels = [("pid", pid.as_str()), ("name", process.name.as_str())];
+            self.gauge(
+                "process_resident_memory_bytes",
+                "Resident memory of the process in bytes",
+                &labels,
+                process.resident_bytes as f64,
+            );
+            self.sample(
+                "process_cpu_seconds_total",
+                "User and system CPU time used by the process in seconds",
+                MetricType::Counter,
+                &labels,
+                &format_value(process.cpu_secs),
+            );
+        }
+    }
+
+    /// Renders the encoded metrics, one family after the other in the order they were first encoded.
+    pub fn finish(self) -> String {
+        let mut out = String::new();
+        for family in self.families {
+            let _ = writeln!(out, "# HELP {} {}", family.name, escape_help(family.help));
+            let _ = writeln!(out, "# TYPE {} {}", family.name, family.metric_type.as_str());
+            for sample in family.samples {
+                out.push_str(&sample);
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    fn gauge(&mut self, name: &str, help: &'static str, labels: &[(&str, &str)], value: f64) {
+        self.sample(name, help, MetricType::Gauge, labels, &format_value(value));
+    }
+
+    fn counter(&mut self, name: &str, help: &'static str, labels: &[(&str, &str)], value: u64) {
+        self.sample(name, help, MetricType::Counter, labels, &value.to_string());
+    }
+
+    /// Adds a sample to its family, creating the family on first use.
+    fn sample(
+        &mut self,
+        name: &str,
+        help: &'static str,
+        metric_type: MetricType,
+        labels: &[(&str, &str)],
+        value: &str,
+    ) {
+        let name = format!("{}_{}", self.prefix, name);
+        let index = match self.index.get(&name) {
+            Some(&index) => index,
+            None => {
+                self.families.push(Family {
+                    name: name.clone(),
+                    help,
+                    metric_type,
+                    samples: Vec::new(),
+                });
+                self.index.insert(name.clone(), self.families.len() - 1);
+                self.families.len() - 1
+            },
+        };
+
+        let mut line = name;
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+                .collect();
+            let _ = write!(line, "{{{}}}", labels.join(","));
+        }
+        let _ = write!(line, " {}", value);
+        self.families[index].samples.push(line);
+    }
+}
+
+/// Whether `name` can be used in a metric name
+fn is_valid_name(name: &str) -> bool {
+    !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Escapes a label value: backslash, double quote and line feed.
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', r#"\""#).replace('\n', r"\n")
+}
+
+/// Escapes a `HELP` text: backslash and line feed.
+fn escape_help(help: &str) -> String {
+    help.replace('\\', r"\\").replace('\n', r"\n")
+}
+
+/// Formats a sample value, spelling non-finite values the way Prometheus expects.
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        hardware::temperature::Fan,
+        power::PowerState,
+        snapshot::{MemorySnapshot, NetworkSnapshot, ProcessSnapshot},
+        system::Architecture,
+    };
+
+    fn thermal() -> ThermalMetrics {
+        ThermalMetrics {
+            cpu_temperature: Some(52.5),
+            gpu_temperature: None,
+            heatsink_temperature: None,
+            ambient_temperature: Some(31.0),
+            battery_temperature: None,
+            is_throttling: true,
+            cpu_power: None,
+            fans: vec![Fan {
+                name: "Left".to_string(),
+                speed_rpm: 2400,
+                min_speed: 1200,
+                max_speed: 6000,
+                percentage: 25.0,
+                ..Default::default()
+            }],
+            throttle_reasons: None,
+        }
+    }
+
+    #[test]
+    fn test_encode_thermal() {
+        let mut encoder = PrometheusEncoder::new();
+        encoder.encode_thermal(&thermal());
+
+        assert_eq!(
+            encoder.finish(),
+            "# HELP darwin_cpu_temperature_celsius CPU temperature in degrees Celsius\n\
+             # TYPE darwin_cpu_temperature_celsius gauge\n\
+             darwin_cpu_temperature_celsius 52.5\n\
+             # HELP darwin_ambient_temperature_celsius Ambient temperature inside the case in degrees Celsius\n\
+             # TYPE darwin_ambient_temperature_celsius gauge\n\
+             darwin_ambient_temperature_celsius 31\n\
+             # HELP darwin_thermal_throttling Whether the CPU is thermally throttled, 1 if it is\n\
+             # TYPE darwin_thermal_throttling gauge\n\
+             darwin_thermal_throttling 1\n\
+             # HELP darwin_fan_speed_rpm Fan speed in revolutions per minute\n\
+             # TYPE darwin_fan_speed_rpm gauge\n\
+             darwin_fan_speed_rpm{fan=\"Left\"} 2400\n\
+             # HELP darwin_fan_speed_percent Fan speed between its minimum and maximum in percent\n\
+             # TYPE darwin_fan_speed_percent gauge\n\
+             darwin_fan_speed_percent{fan=\"Left\"} 25\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_processes_groups_families() {
+        let mut first = Process::new(123, "firefox");
+        first.cpu_usage = 12.5;
+        first.uptime = Duration::from_secs(90);
+        let mut second = Process::new(456, "quote\"back\\slash\nline");
+        second.open_fd_count = Some(7);
+
+        let mut encoder = PrometheusEncoder::new();
+        encoder.encode_processes(&[first, second]);
+        let text = encoder.finish();
+
+        assert_eq!(text.matches("# TYPE darwin_process_cpu_usage_percent gauge").count(), 1);
+        assert!(text.contains(
+            "darwin_process_cpu_usage_percent{pid=\"123\",name=\"firefox\"} 12.5\n\
+             darwin_process_cpu_usage_percent{pid=\"456\",name=\"quote\\\"back\\\\slash\\nline\"} 0\n"
+        ));
+        assert!(text.contains("# TYPE darwin_process_read_bytes_total counter\n"));
+        assert!(text.contains("darwin_process_uptime_seconds{pid=\"123\",name=\"firefox\"} 90\n"));
+        assert!(text.contains("darwin_process_open_fds{pid=\"456\","));
+        assert!(!text.contains("darwin_process_open_fds{pid=\"123\","));
+    }
+
+    #[test]
+    fn test_encode_snapshot_and_system() {
+        let snapshot = MetricsSnapshot {
+            memory: Some(MemorySnapshot {
+                total: 16,
+                used: 8,
+                pressure: 0.25,
+                ..Default::default()
+            }),
+            network: Some(NetworkSnapshot { bytes_received: 1000, bytes_sent: 10 }),
+            processes: Some(vec![ProcessSnapshot {
+                pid: 1,
+                name: "launchd".to_string(),
+                cpu_secs: 2.5,
+                resident_bytes: 4096,
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        let mut encoder = PrometheusEncoder::with_prefix("mac").unwrap();
+        encoder.encode_snapshot(&snapshot);
+        encoder.encode_system(&SystemMetrics { architecture: Architecture::AppleSilicon });
+        let text = encoder.finish();
+
+        assert!(text.contains("mac_memory_total_bytes 16\n"));
+        assert!(text.contains("mac_memory_pressure_ratio 0.25\n"));
+        assert!(text.contains("# TYPE mac_network_received_bytes_total counter\nmac_network_received_bytes_total 1000\n"));
+        assert!(text.contains("mac_process_cpu_seconds_total{pid=\"1\",name=\"launchd\"} 2.5\n"));
+        assert!(text.contains("mac_system_info{architecture=\"AppleSilicon\"} 1\n"));
+        assert!(!text.contains("cpu_usage_ratio"), "Sections that weren't collected are left out");
+    }
+
+    #[test]
+    fn test_encode_power() {
+        let power = PowerConsumption {
+            package: 6.5,
+            cores: 4.0,
+            gpu: Some(1.5),
+            dram: None,
+            neural_engine: None,
+            power_state: PowerState::Battery,
+            battery_percentage: Some(80.0),
+            power_impact: None,
+            display_brightness: None,
+        };
+
+        let mut encoder = PrometheusEncoder::new();
+        encoder.encode_power(&power);
+        let text = encoder.finish();
+
+        assert!(text.contains(
+            "# TYPE darwin_power_watts gauge\n\
+             darwin_power_watts{component=\"package\"} 6.5\n\
+             darwin_power_watts{component=\"cores\"} 4\n\
+             darwin_power_watts{component=\"gpu\"} 1.5\n"
+        ));
+        assert!(!text.contains("component=\"dram\""));
+        assert!(text.contains("darwin_power_state{state=\"Battery\"} 1\n"));
+        assert!(text.contains("darwin_battery_charge_percent 80\n"));
+    }
+
+    #[test]
+    fn test_prefix_and_values() {
+        assert!(PrometheusEncoder::with_prefix("node_2").is_ok());
+        assert!(PrometheusEncoder::with_prefix("2node").is_err());
+        assert!(PrometheusEncoder::with_prefix("my-app").is_err());
+        assert!(PrometheusEncoder::with_prefix("").is_err());
+
+        assert_eq!(format_value(f64::NAN), "NaN");
+        assert_eq!(format_value(f64::INFINITY), "+Inf");
+        assert_eq!(format_value(f64::NEG_INFINITY), "-Inf");
+        assert_eq!(format_value(0.1), "0.1");
+        assert_eq!(escape_help("a\\b\nc"), "a\\\\b\\nc");
+    }
+}
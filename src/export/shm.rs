@@ -0,0 +1,795 @@
+//! Memory-mapped snapshot export
+//!
+//! A [`SnapshotPublisher`] owns a file that is mapped into memory with `MAP_SHARED`. Any number of
+//! [`SnapshotReader`]s in other processes can map the same file and read the most recently published snapshot without
+//! sockets, XPC, or any cooperation from the publisher beyond the file itself.
+//!
+//! ## File layout
+//!
+//! ```text
+//! +----------------------+  0
+//! | header (64 bytes)    |  magic, layout version, region size, sequence counter, heartbeat, publisher pid
+//! +----------------------+  64
+//! | buffer 0             |  u64 little-endian payload length followed by the serialized snapshot
+//! +----------------------+  64 + region_size
+//! | buffer 1             |
+//! +----------------------+  64 + 2 * region_size
+//! ```
+//!
+//! Publishing uses a seqlock over the two buffers: the publisher writes the inactive buffer, bumping the sequence
+//! counter to an odd value before and to the next even value after the write. Readers copy the active buffer out and
+//! re-check the counter afterwards, retrying if the buffer they copied could have been rewritten in the meantime.
+//! Because there are two buffers, a reader only has to retry when the publisher completes a full publish *and* starts
+//! the next one while the reader is still copying, so readers virtually never spin.
+//!
+//! Snapshots are serialized with `postcard`, a compact binary format, so any type implementing `Serialize`/`Deserialize`
+//! can be exported. The format isn't self-describing: readers must use the publisher's snapshot type, and types that
+//! need `deserialize_any`, such as `serde_json::Value` or untagged enums, can't be read back.
+//!
+//! Only one publisher may own a file at a time; this is enforced with an advisory `flock`.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use darwin_metrics::export::shm::{SnapshotPublisher, SnapshotReader};
+//!
+//! // In the privileged helper
+//! let mut publisher = SnapshotPublisher::new("/tmp/darwin-metrics.shm")?;
+//! publisher.publish(&snapshot)?;
+//!
+//! // In the unprivileged UI
+//! let reader = SnapshotReader::<MySnapshot>::open("/tmp/darwin-metrics.shm")?;
+//! if let Some(snapshot) = reader.latest()? {
+//!     println!("{:?} (stale: {})", snapshot, reader.is_stale());
+//! }
+//! ```
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    marker::PhantomData,
+    os::unix::{
+        fs::{OpenOptionsExt, PermissionsExt},
+        io::AsRawFd,
+    },
+    path::{Path, PathBuf},
+    ptr::{self, NonNull},
+    sync::atomic::{fence, AtomicU32, AtomicU64, Ordering},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Magic number identifying a darwin-metrics snapshot file
+const MAGIC: u64 = u64::from_le_bytes(*b"DMSHMv01");
+
+/// Version of the on-disk layout described in the module documentation
+const LAYOUT_VERSION: u32 = 1;
+
+/// Size of the fixed header at the start of the file
+pub const HEADER_SIZE: usize = 64;
+
+/// Size of the length prefix stored at the start of each buffer
+const LEN_PREFIX: usize = std::mem::size_of::<u64>();
+
+/// Default size of each of the two snapshot buffers (64 KiB)
+pub const DEFAULT_REGION_SIZE: usize = 64 * 1024;
+
+/// Default permissions for newly created snapshot files
+pub const DEFAULT_MODE: u32 = 0o644;
+
+/// Default age after which a reader considers the publisher stale
+pub const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(5);
+
+/// Number of times a reader retries a read that raced with the publisher before giving up
+const MAX_READ_RETRIES: usize = 64;
+
+#[repr(C)]
+struct Header {
+    magic: u64,
+    version: u32,
+    region_size: u32,
+    seq: AtomicU64,
+    heartbeat_ms: AtomicU64,
+    publisher_pid: AtomicU32,
+    _reserved: [u8; 28],
+}
+
+const _: () = assert!(std::mem::size_of::<Header>() == HEADER_SIZE);
+
+/// Index of the buffer the next publish writes to, given the current (even) sequence value
+fn target_index(seq: u64) -> usize {
+    ((seq / 2) % 2) as usize
+}
+
+/// Index of the buffer holding the latest complete snapshot for a sequence value, or `None` if nothing has been
+/// published yet
+fn active_index(seq: u64) -> Option<usize> {
+    (seq / 2).checked_sub(1).map(|completed| (completed % 2) as usize)
+}
+
+/// Whether a buffer read that started at sequence `before` and finished at sequence `after` can be trusted
+///
+/// The buffer that was active at `before` is next rewritten by the second publish after it, which starts by moving
+/// the counter to `(before / 2) * 2 + 3`. Anything below that is safe; a counter that went backwards means the
+/// publisher was restarted and the read must be retried as well.
+fn read_is_consistent(before: u64, after: u64) -> bool {
+    after >= before && after <= (before / 2) * 2 + 2
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// A shared, memory-mapped view of a snapshot file
+struct Mapping {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// SAFETY: the mapping is only accessed through atomics (header) or through the seqlock protocol (buffers).
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn new(file: &File, len: usize, writable: bool) -> Result<Self> {
+        let prot = if writable { libc::PROT_READ | libc::PROT_WRITE } else { libc::PROT_READ };
+
+        // SAFETY: mapping a regular file we hold open; the length was validated against the file size by the caller.
+        let ptr = unsafe {
+            libc::mmap(ptr::null_mut(), len, prot, libc::MAP_SHARED, file.as_raw_fd(), 0)
+        };
+
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let ptr = NonNull::new(ptr.cast::<u8>())
+            .ok_or_else(|| Error::system("mmap returned a null pointer"))?;
+        Ok(Self { ptr, len })
+    }
+
+    fn header(&self) -> &Header {
+        // SAFETY: the mapping is page aligned and always at least HEADER_SIZE bytes long.
+        unsafe { &*self.ptr.as_ptr().cast::<Header>() }
+    }
+
+    fn buffer(&self, index: usize, region_size: usize) -> *mut u8 {
+        debug_assert!(HEADER_SIZE + (index + 1) * region_size <= self.len);
+        // SAFETY: both buffers lie within the mapping, see the debug assertion above.
+        unsafe { self.ptr.as_ptr().add(HEADER_SIZE + index * region_size) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: ptr/len came from a successful mmap call.
+        unsafe {
+            libc::munmap(self.ptr.as_ptr().cast(), self.len);
+        }
+    }
+}
+
+/// Options for creating a [`SnapshotPublisher`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublisherOptions {
+    /// Size in bytes of each of the two snapshot buffers, including the 8 byte length prefix
+    pub region_size: usize,
+    /// Permission bits applied when the publisher creates the file
+    pub mode: u32,
+}
+
+impl Default for PublisherOptions {
+    fn default() -> Self {
+        Self { region_size: DEFAULT_REGION_SIZE, mode: DEFAULT_MODE }
+    }
+}
+
+/// Publishes the latest snapshot to a memory-mapped file
+///
+/// The type parameter is the snapshot type being published; readers must use the same type.
+pub struct SnapshotPublisher<T> {
+    path: PathBuf,
+    // Kept open to hold the advisory lock for the lifetime of the publisher
+    _file: File,
+    map: Mapping,
+    region_size: usize,
+    scratch: Vec<u8>,
+    _marker: PhantomData<fn(&T)>,
+}
+
+impl<T> std::fmt::Debug for SnapshotPublisher<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SnapshotPublisher")
+            .field("path", &self.path)
+            .field("region_size", &self.region_size)
+            .finish()
+    }
+}
+
+impl<T: Serialize> SnapshotPublisher<T> {
+    /// Creates (or takes over) a snapshot file at `path` with the default options
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be created or mapped, or if another publisher already owns it.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::with_options(path, PublisherOptions::default())
+    }
+
+    /// Creates (or takes over) a snapshot file at `path` with custom options
+    ///
+    /// A newly created file gets exactly `options.mode` as its permissions regardless of the process umask. An
+    /// existing file keeps its permissions and is grown if it is too small for the requested region size; it is
+    /// never shrunk, so readers that mapped it earlier can't fault on a truncated mapping.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the region size is too small or too large, if the file cannot be created or mapped, or if
+    /// another publisher already owns it.
+    pub fn with_options<P: AsRef<Path>>(path: P, options: PublisherOptions) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let region_size = options.region_size;
+
+        if region_size <= LEN_PREFIX || region_size > u32::MAX as usize {
+            return Err(Error::invalid_data(format!(
+                "Snapshot region size must be between {} and {} bytes, got {}",
+                LEN_PREFIX + 1,
+                u32::MAX,
+                region_size
+            )));
+        }
+
+        let (file, created) = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(options.mode)
+            .open(&path)
+        {
+            Ok(file) => (file, true),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                (OpenOptions::new().read(true).write(true).open(&path)?, false)
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        if created {
+            // The mode passed to open() is filtered through the umask; apply the requested bits verbatim.
+            fs::set_permissions(&path, fs::Permissions::from_mode(options.mode))?;
+        }
+
+        // SAFETY: flock on a file descriptor we own.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::WouldBlock {
+                return Err(Error::system(format!(
+                    "Another publisher already owns snapshot file {}",
+                    path.display()
+                )));
+            }
+            return Err(err.into());
+        }
+
+        let required_len = (HEADER_SIZE + 2 * region_size) as u64;
+        let file_len = file.metadata()?.len();
+        if file_len < required_len {
+            file.set_len(required_len)?;
+        }
+
+        let map = Mapping::new(&file, required_len as usize, true)?;
+
+        // Initialize the header. Readers only trust the file once the magic is in place, so it is written last.
+        let raw = map.ptr.as_ptr().cast::<Header>();
+        // SAFETY: we hold the exclusive publisher lock, so nobody else writes the non-atomic header fields.
+        unsafe {
+            ptr::addr_of_mut!((*raw).magic).write_volatile(0);
+            ptr::addr_of_mut!((*raw).version).write_volatile(LAYOUT_VERSION);
+            ptr::addr_of_mut!((*raw).region_size).write_volatile(region_size as u32);
+        }
+        {
+            let header = map.header();
+            header.seq.store(0, Ordering::Release);
+            header.heartbeat_ms.store(now_ms(), Ordering::Release);
+            header.publisher_pid.store(std::process::id(), Ordering::Release);
+        }
+        fence(Ordering::Release);
+        // SAFETY: as above.
+        unsafe {
+            ptr::addr_of_mut!((*raw).magic).write_volatile(MAGIC);
+        }
+
+        Ok(Self { path, _file: file, map, region_size, scratch: Vec::new(), _marker: PhantomData })
+    }
+
+    /// Serializes `snapshot` into the inactive buffer and makes it the latest snapshot
+    ///
+    /// Also refreshes the heartbeat timestamp.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot can't be serialized or if the serialized snapshot doesn't fit into the
+    /// region. In the latter case the error message includes the required region size; the previous snapshot stays
+    /// visible to readers.
+    pub fn publish(&mut self, snapshot: &T) -> Result<()> {
+        self.scratch.clear();
+        postcard::to_io(snapshot, &mut self.scratch)
+            .map_err(|e| Error::invalid_data(format!("Failed to serialize snapshot: {}", e)))?;
+
+        let required = self.scratch.len() + LEN_PREFIX;
+        if required > self.region_size {
+            return Err(Error::invalid_data(format!(
+                "Snapshot requires a region of at least {} bytes, but the region is {} bytes",
+                required, self.region_size
+            )));
+        }
+
+        let header = self.map.header();
+        // Only the publisher writes the counter, and it always leaves it even.
+        let seq = header.seq.load(Ordering::Relaxed);
+        let dst = self.map.buffer(target_index(seq), self.region_size);
+
+        header.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        // SAFETY: dst points at a buffer of region_size bytes and `required <= region_size` was checked above.
+        unsafe {
+            let len = (self.scratch.len() as u64).to_le_bytes();
+            ptr::copy_nonoverlapping(len.as_ptr(), dst, LEN_PREFIX);
+            ptr::copy_nonoverlapping(
+                self.scratch.as_ptr(),
+                dst.add(LEN_PREFIX),
+                self.scratch.len(),
+            );
+        }
+
+        header.seq.store(seq.wrapping_add(2), Ordering::Release);
+        header.heartbeat_ms.store(now_ms(), Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Refreshes the heartbeat without publishing a new snapshot
+    ///
+    /// Useful when the publisher is alive but has nothing new to publish and doesn't want readers to flag it as
+    /// stale.
+    pub fn touch(&self) {
+        self.map.header().heartbeat_ms.store(now_ms(), Ordering::Release);
+    }
+
+    /// Returns the path of the snapshot file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the size of each snapshot buffer in bytes, including the length prefix
+    pub fn region_size(&self) -> usize {
+        self.region_size
+    }
+}
+
+impl<T> Drop for SnapshotPublisher<T> {
+    fn drop(&mut self) {
+        // Signal readers that nobody is publishing anymore; the last snapshot stays readable.
+        self.map.header().publisher_pid.store(0, Ordering::Release);
+    }
+}
+
+/// Reads snapshots published by a [`SnapshotPublisher`]
+pub struct SnapshotReader<T> {
+    path: PathBuf,
+    map: Mapping,
+    region_size: usize,
+    stale_after: Duration,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> std::fmt::Debug for SnapshotReader<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SnapshotReader")
+            .field("path", &self.path)
+            .field("region_size", &self.region_size)
+            .field("stale_after", &self.stale_after)
+            .finish()
+    }
+}
+
+impl<T: DeserializeOwned> SnapshotReader<T> {
+    /// Opens and maps an existing snapshot file read-only
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotAvailable`] if the file hasn't been initialized by a publisher yet, and an error if the
+    /// file can't be opened or has an incompatible layout.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path)?;
+        let file_len = file.metadata()?.len() as usize;
+
+        if file_len < HEADER_SIZE {
+            return Err(Error::not_available(format!(
+                "Snapshot file {} has not been initialized by a publisher",
+                path.display()
+            )));
+        }
+
+        let map = Mapping::new(&file, file_len, false)?;
+        fence(Ordering::Acquire);
+
+        // SAFETY: the header lies within the mapping; the fields are only written before the magic is set.
+        let (magic, version, region_size) = unsafe {
+            let raw = map.ptr.as_ptr().cast::<Header>();
+            (
+                ptr::addr_of!((*raw).magic).read_volatile(),
+                ptr::addr_of!((*raw).version).read_volatile(),
+                ptr::addr_of!((*raw).region_size).read_volatile() as usize,
+            )
+        };
+
+        if magic != MAGIC {
+            return Err(Error::not_available(format!(
+                "Snapshot file {} has not been initialized by a publisher",
+                path.display()
+            )));
+        }
+
+        if version != LAYOUT_VERSION {
+            return Err(Error::invalid_data(format!(
+                "Unsupported snapshot file layout version {} (expected {})",
+                version, LAYOUT_VERSION
+            )));
+        }
+
+        if region_size <= LEN_PREFIX || file_len < HEADER_SIZE + 2 * region_size {
+            return Err(Error::invalid_data(format!(
+                "Snapshot file {} is truncated ({} bytes for a region size of {})",
+                path.display(),
+                file_len,
+                region_size
+            )));
+        }
+
+        Ok(Self { path, map, region_size, stale_after: DEFAULT_STALE_AFTER, _marker: PhantomData })
+    }
+
+    /// Sets how long the publisher may go without a heartbeat before [`is_stale`](Self::is_stale) reports it
+    pub fn with_stale_after(mut self, stale_after: Duration) -> Self {
+        self.stale_after = stale_after;
+        self
+    }
+
+    /// Returns the latest published snapshot, or `None` if nothing has been published yet
+    ///
+    /// Reads that race with the publisher are retried, so a returned snapshot is never torn.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot can't be deserialized into `T`, or if the publisher overwrote the buffer
+    /// on every one of the retry attempts.
+    pub fn latest(&self) -> Result<Option<T>> {
+        let header = self.map.header();
+        let capacity = self.region_size - LEN_PREFIX;
+        let mut buf = Vec::new();
+
+        for _ in 0..MAX_READ_RETRIES {
+            let before = header.seq.load(Ordering::Acquire);
+            let Some(index) = active_index(before) else {
+                return Ok(None);
+            };
+
+            let src = self.map.buffer(index, self.region_size);
+            let mut len_bytes = [0u8; LEN_PREFIX];
+            // SAFETY: src points at a buffer of region_size bytes within the mapping. The copy may race with the
+            // publisher; the sequence check below discards anything copied during a concurrent write.
+            unsafe { ptr::copy_nonoverlapping(src, len_bytes.as_mut_ptr(), LEN_PREFIX) };
+            let len = u64::from_le_bytes(len_bytes) as usize;
+            let len_valid = len <= capacity;
+
+            if len_valid {
+                buf.clear();
+                buf.resize(len, 0);
+                // SAFETY: as above, and len <= capacity keeps the copy inside the buffer.
+                unsafe { ptr::copy_nonoverlapping(src.add(LEN_PREFIX), buf.as_mut_ptr(), len) };
+            }
+
+            fence(Ordering::Acquire);
+            let after = header.seq.load(Ordering::Relaxed);
+
+            if !read_is_consistent(before, after) {
+                thread::yield_now();
+                continue;
+            }
+
+            if !len_valid {
+                return Err(Error::invalid_data(format!(
+                    "Snapshot length {} exceeds the region capacity of {} bytes",
+                    len, capacity
+                )));
+            }
+
+            return postcard::from_bytes(&buf).map(Some).map_err(|e| {
+                Error::invalid_data(format!("Failed to deserialize snapshot: {}", e))
+            });
+        }
+
+        Err(Error::system(format!(
+            "Snapshot was overwritten during every one of {} read attempts",
+            MAX_READ_RETRIES
+        )))
+    }
+
+    /// Returns the time of the publisher's last publish or [`touch`](SnapshotPublisher::touch)
+    pub fn last_heartbeat(&self) -> Option<SystemTime> {
+        match self.map.header().heartbeat_ms.load(Ordering::Acquire) {
+            0 => None,
+            ms => Some(UNIX_EPOCH + Duration::from_millis(ms)),
+        }
+    }
+
+    /// Returns the pid of the publisher, or `None` if the publisher has shut down
+    pub fn publisher_pid(&self) -> Option<u32> {
+        match self.map.header().publisher_pid.load(Ordering::Acquire) {
+            0 => None,
+            pid => Some(pid),
+        }
+    }
+
+    /// Returns `true` if the publisher has shut down or hasn't sent a heartbeat within the stale-after window
+    ///
+    /// A publisher that crashed can't clear its pid, so the heartbeat age is what catches it.
+    pub fn is_stale(&self) -> bool {
+        if self.publisher_pid().is_none() {
+            return true;
+        }
+
+        match self.last_heartbeat() {
+            Some(heartbeat) => SystemTime::now()
+                .duration_since(heartbeat)
+                .map(|age| age > self.stale_after)
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// Returns the path of the snapshot file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Arc,
+    };
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct TestSnapshot {
+        generation: u64,
+        values: Vec<u64>,
+    }
+
+    impl TestSnapshot {
+        fn new(generation: u64) -> Self {
+            // Vary the length so consecutive snapshots have different sizes
+            let len = 16 + (generation % 200) as usize;
+            Self { generation, values: vec![generation; len] }
+        }
+
+        fn is_consistent(&self) -> bool {
+            self.values.len() == 16 + (self.generation % 200) as usize
+                && self.values.iter().all(|v| *v == self.generation)
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("darwin-metrics-shm-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_sequence_helpers() {
+        assert_eq!(active_index(0), None);
+        assert_eq!(active_index(1), None);
+        assert_eq!(target_index(0), 0);
+        assert_eq!(active_index(2), Some(0));
+        assert_eq!(active_index(3), Some(0));
+        assert_eq!(target_index(2), 1);
+        assert_eq!(active_index(4), Some(1));
+        assert_eq!(target_index(4), 0);
+
+        // Reading buffer 0 at seq 2: the publish into buffer 1 is harmless, the next one into buffer 0 is not
+        assert!(read_is_consistent(2, 2));
+        assert!(read_is_consistent(2, 3));
+        assert!(read_is_consistent(2, 4));
+        assert!(!read_is_consistent(2, 5));
+        // Reading buffer 0 at seq 3 (buffer 1 being written)
+        assert!(read_is_consistent(3, 4));
+        assert!(!read_is_consistent(3, 5));
+        // Publisher restarted
+        assert!(!read_is_consistent(6, 0));
+    }
+
+    #[test]
+    fn test_publish_and_read_round_trip() {
+        let path = temp_path("round-trip");
+        let _ = fs::remove_file(&path);
+
+        let mut publisher = SnapshotPublisher::new(&path).unwrap();
+        let reader = SnapshotReader::<TestSnapshot>::open(&path).unwrap();
+
+        assert_eq!(reader.latest().unwrap(), None);
+
+        publisher.publish(&TestSnapshot::new(1)).unwrap();
+        assert_eq!(reader.latest().unwrap(), Some(TestSnapshot::new(1)));
+
+        publisher.publish(&TestSnapshot::new(2)).unwrap();
+        publisher.publish(&TestSnapshot::new(3)).unwrap();
+        assert_eq!(reader.latest().unwrap(), Some(TestSnapshot::new(3)));
+        assert_eq!(reader.publisher_pid(), Some(std::process::id()));
+
+        drop(publisher);
+        // The last snapshot stays readable, but the publisher is reported as gone
+        assert_eq!(reader.latest().unwrap(), Some(TestSnapshot::new(3)));
+        assert_eq!(reader.publisher_pid(), None);
+        assert!(reader.is_stale());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_too_large() {
+        let path = temp_path("too-large");
+        let _ = fs::remove_file(&path);
+
+        let options = PublisherOptions { region_size: 128, ..PublisherOptions::default() };
+        let mut publisher = SnapshotPublisher::with_options(&path, options).unwrap();
+        publisher.publish(&TestSnapshot { generation: 1, values: vec![1] }).unwrap();
+
+        // Small integers take a byte each, so the values need to be large to outgrow the region
+        let err = publisher
+            .publish(&TestSnapshot { generation: 2, values: vec![u64::MAX; 100] })
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidData(_)));
+        assert!(err.to_string().contains("128 bytes"));
+
+        // The previous snapshot is still the latest one
+        let reader = SnapshotReader::<TestSnapshot>::open(&path).unwrap();
+        assert_eq!(reader.latest().unwrap().unwrap().generation, 1);
+
+        drop(publisher);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_region_size() {
+        let path = temp_path("invalid-region");
+        let options = PublisherOptions { region_size: 4, ..PublisherOptions::default() };
+        assert!(SnapshotPublisher::<TestSnapshot>::with_options(&path, options).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_file_permissions() {
+        let path = temp_path("permissions");
+        let _ = fs::remove_file(&path);
+
+        let options = PublisherOptions { mode: 0o600, ..PublisherOptions::default() };
+        let publisher = SnapshotPublisher::<TestSnapshot>::with_options(&path, options).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        drop(publisher);
+        fs::remove_file(&path).unwrap();
+
+        let publisher = SnapshotPublisher::<TestSnapshot>::new(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, DEFAULT_MODE);
+        drop(publisher);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_single_publisher() {
+        let path = temp_path("single-publisher");
+        let _ = fs::remove_file(&path);
+
+        let publisher = SnapshotPublisher::<TestSnapshot>::new(&path).unwrap();
+        assert!(SnapshotPublisher::<TestSnapshot>::new(&path).is_err());
+        drop(publisher);
+        assert!(SnapshotPublisher::<TestSnapshot>::new(&path).is_ok());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_open_uninitialized_file() {
+        let path = temp_path("uninitialized");
+        fs::write(&path, [0u8; 16]).unwrap();
+
+        let err = SnapshotReader::<TestSnapshot>::open(&path).unwrap_err();
+        assert!(err.is_not_available());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_staleness_detection() {
+        let path = temp_path("staleness");
+        let _ = fs::remove_file(&path);
+
+        let mut publisher = SnapshotPublisher::new(&path).unwrap();
+        let reader = SnapshotReader::<TestSnapshot>::open(&path)
+            .unwrap()
+            .with_stale_after(Duration::from_millis(100));
+
+        publisher.publish(&TestSnapshot::new(1)).unwrap();
+        assert!(!reader.is_stale());
+
+        // Publishing stops while the publisher stays alive
+        thread::sleep(Duration::from_millis(250));
+        assert!(reader.is_stale());
+
+        // A heartbeat without new data revives it
+        publisher.touch();
+        assert!(!reader.is_stale());
+
+        drop(publisher);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_publisher_and_readers() {
+        const PUBLISHES: u64 = 5_000;
+        const READERS: usize = 4;
+
+        let path = temp_path("concurrent");
+        let _ = fs::remove_file(&path);
+
+        let mut publisher = SnapshotPublisher::new(&path).unwrap();
+        publisher.publish(&TestSnapshot::new(0)).unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reads = Arc::new(AtomicUsize::new(0));
+
+        let readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                let path = path.clone();
+                let done = Arc::clone(&done);
+                let reads = Arc::clone(&reads);
+                thread::spawn(move || {
+                    // Each reader gets its own mapping, just like a separate process would
+                    let reader = SnapshotReader::<TestSnapshot>::open(&path).unwrap();
+                    let mut last_generation = 0;
+                    while !done.load(Ordering::Acquire) {
+                        let snapshot = reader.latest().expect("read must never fail").unwrap();
+                        assert!(snapshot.is_consistent(), "torn read: {:?}", snapshot);
+                        assert!(snapshot.generation >= last_generation, "snapshot went backwards");
+                        last_generation = snapshot.generation;
+                        reads.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+
+        for generation in 1..=PUBLISHES {
+            publisher.publish(&TestSnapshot::new(generation)).unwrap();
+        }
+        done.store(true, Ordering::Release);
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert!(reads.load(Ordering::Relaxed) > 0);
+        let reader = SnapshotReader::<TestSnapshot>::open(&path).unwrap();
+        assert_eq!(reader.latest().unwrap(), Some(TestSnapshot::new(PUBLISHES)));
+
+        drop(publisher);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,12 +1,23 @@
-use objc2::{msg_send, rc::Retained};
-use objc2_foundation::NSString;
+use std::{mem, ptr, slice, sync::Arc};
 
-use super::{CpuMetrics, FrequencyMetrics, FrequencyMonitor};
+#[cfg(test)]
+use super::topology::PerfLevel;
+use super::{
+    load,
+    throttle::{on_battery_power, ThrottleReasons, ThrottleSignals},
+    ClusterUsage, CpuMetrics, CpuTopology, FrequencyMetrics, FrequencyMonitor, LoadAverage,
+};
 #[cfg(test)]
 use crate::hardware::iokit::mock::MockIOKit;
 use crate::{
-    error::Result,
+    error::{Error, Result},
     hardware::iokit::{IOKit, IOKitImpl},
+    init::system_info,
+    utils::bindings::{
+        host_processor_info, mach_host_self, mach_task_self, processor_cpu_load_info,
+        CPU_STATE_IDLE, CPU_STATE_NICE, CPU_STATE_SYSTEM, CPU_STATE_USER, KERN_SUCCESS,
+        PROCESSOR_CPU_LOAD_INFO,
+    },
 };
 
 /// Primary structure for accessing macOS CPU information and metrics.
@@ -33,11 +44,13 @@ pub struct CPU {
     logical_cores: u32,
     frequency_mhz: f64,
     core_usage: Vec<f64>,
+    core_ticks: Vec<[u32; 4]>,
     model_name: String,
     temperature: Option<f64>,
-    iokit: Box<dyn IOKit>,
+    iokit: Arc<dyn IOKit>,
     frequency_monitor: FrequencyMonitor,
     frequency_metrics: Option<FrequencyMetrics>,
+    topology: CpuTopology,
 }
 
 impl CPU {
@@ -69,16 +82,41 @@ impl CPU {
     /// }
     /// ```
     pub fn new() -> Result<Self> {
+        Self::with_shared_iokit(Arc::new(IOKitImpl::default()))
+    }
+
+    /// Creates a new CPU instance that reads the temperature and throttling state through `iokit`.
+    ///
+    /// Pass an [`IOKitImpl`] created with [`IOKitImpl::with_smc_connection`] to read the SMC over a connection opened
+    /// by a privileged broker.
+    ///
+    /// # Errors
+    ///
+    /// See [`new`](CPU::new).
+    #[deprecated(note = "use `CPU::with_shared_iokit` to share the IOKit instance")]
+    pub fn with_iokit(iokit: Box<dyn IOKit>) -> Result<Self> {
+        Self::with_shared_iokit(Arc::from(iokit))
+    }
+
+    /// Creates a new CPU instance that reads the temperature and throttling state through `iokit`, shared with the
+    /// other monitors holding it.
+    ///
+    /// # Errors
+    ///
+    /// See [`new`](CPU::new).
+    pub fn with_shared_iokit(iokit: Arc<dyn IOKit>) -> Result<Self> {
         let mut cpu = Self {
             physical_cores: 0,
             logical_cores: 0,
             frequency_mhz: 0.0,
             core_usage: Vec::new(),
+            core_ticks: Vec::new(),
             model_name: String::new(),
             temperature: None,
-            iokit: Box::new(IOKitImpl),
+            iokit,
             frequency_monitor: FrequencyMonitor::new(),
             frequency_metrics: None,
+            topology: CpuTopology::detect()?,
         };
         cpu.update()?;
         Ok(cpu)
@@ -97,35 +135,25 @@ impl CPU {
     ///
     /// Returns an error if any of the system calls or IOKit operations fail.
     pub fn update(&mut self) -> Result<()> {
-        let service = self.iokit.get_service("AppleACPICPU")?;
-
-        // TODO: Verify if AppleACPICPU service actually provides these methods If not, consider using sysctl or other
-        // methods for getting core counts Example: sysctlbyname("hw.physicalcpu") and sysctlbyname("hw.logicalcpu")
-        self.physical_cores = unsafe { msg_send![&*service, numberOfCores] };
-        self.logical_cores = unsafe { msg_send![&*service, numberOfProcessorCores] };
+        // The core counts and the model come from sysctl, read once per process
+        let info = system_info()?;
+        self.physical_cores = info.physical_cores;
+        self.logical_cores = info.logical_cores;
+        self.model_name = info.cpu_model.clone();
 
-        // Use the FrequencyMonitor to get detailed frequency information We try to get metrics from FrequencyMonitor
-        // first, with a fallback to IOKit
         match self.frequency_monitor.get_metrics() {
             Ok(metrics) => {
                 self.frequency_mhz = metrics.current;
                 self.frequency_metrics = Some(metrics);
             },
             Err(_) => {
-                // Fallback to IOKit method
-                let frequency: f64 = unsafe { msg_send![&*service, currentProcessorClockSpeed] };
-                self.frequency_mhz = frequency / 1_000_000.0;
+                // Keep the last known frequency
                 self.frequency_metrics = None;
             },
         }
 
         self.core_usage = self.fetch_core_usage()?;
 
-        // Get model name as NSString and convert to Rust String TODO: Verify name method, consider using
-        // sysctlbyname("machdep.cpu.brand_string")
-        let ns_name: Retained<NSString> = unsafe { msg_send![&*service, name] };
-        self.model_name = ns_name.to_string();
-
         // Temperature from IOKit
         self.temperature = self.fetch_cpu_temperature();
 
@@ -143,28 +171,13 @@ impl CPU {
     ///
     /// # Implementation Notes
     ///
-    /// The current implementation uses the IOKit AppleACPICPU service, but alternative implementations using
-    /// host_processor_info() from the Mach kernel API or sysctlbyname() may be more reliable and are planned for future
-    /// updates.
-    fn fetch_core_usage(&self) -> Result<Vec<f64>> {
-        // TODO: Verify if AppleACPICPU provides getCoreUsage method Alternative implementation options:
-        // 1. Use host_processor_info() from mach kernel API to get CPU load
-        // 2. Parse the output from the 'vm_stat' command
-        // 3. Use sysctlbyname with specific processor information keys
-        //
-        // Example implementation with host_processor_info would look like: unsafe { let mut cpu_load_info: *mut
-        // processor_cpu_load_info_t = std::ptr::null_mut();     let mut cpu_count: u32 = 0; let result =
-        //     host_processor_info(mach_host_self(), PROCESSOR_CPU_LOAD_INFO,                                      &mut
-        // cpu_count,                                      &mut cpu_load_info as *mut _ as *mut *mut libc::c_int, &mut
-        //     msg_type);     // Process results and calculate usage percentages }
-
-        let mut usages = Vec::with_capacity(self.logical_cores as usize);
-        for i in 0..self.logical_cores {
-            let service = self.iokit.get_service("AppleACPICPU")?;
-            let usage: f64 = unsafe { msg_send![&*service, getCoreUsage: i] };
-            usages.push(usage);
-        }
-        Ok(usages)
+    /// The usage is computed from the tick counters of `host_processor_info()` since the previous update, or since boot
+    /// on the first update.
+    fn fetch_core_usage(&mut self) -> Result<Vec<f64>> {
+        let ticks = read_core_ticks()?;
+        let usage = core_usage(&self.core_ticks, &ticks);
+        self.core_ticks = ticks;
+        Ok(usage)
     }
 
     /// Retrieves the current CPU temperature.
@@ -220,6 +233,10 @@ impl CPU {
     /// This method provides a slice of CPU usage values, with one value per core. Each value is between 0.0 (idle) and
     /// 1.0 (100% utilized).
     ///
+    /// Index `i` is logical CPU `i` in the kernel's numbering, which stays the same across updates. The
+    /// [`CpuCluster::cores`](super::CpuCluster::cores) of the [`topology`](CPU::topology) use the same indices; on
+    /// Apple Silicon the efficiency cores usually come first.
+    ///
     /// # Returns
     ///
     /// * `&[f64]` - Slice of core usage values
@@ -227,6 +244,40 @@ impl CPU {
         &self.core_usage
     }
 
+    /// Returns the performance and efficiency clusters of the CPU, read once when the instance was created.
+    ///
+    /// Intel Macs have a single cluster holding every core.
+    pub fn topology(&self) -> &CpuTopology {
+        &self.topology
+    }
+
+    /// Returns the usage of each cluster as of the last update, fastest cluster first.
+    ///
+    /// The usage of a cluster is the average of its cores in [`core_usage`](CPU::core_usage).
+    pub fn cluster_usage(&self) -> Vec<ClusterUsage> {
+        self.topology.usage(&self.core_usage)
+    }
+
+    /// Reads the load averages and the runnable threads.
+    ///
+    /// Unlike the other metrics, the load is read when called rather than on [`update`](CPU::update).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the load averages can't be read.
+    pub fn load_average(&self) -> Result<LoadAverage> {
+        load::load_average()
+    }
+
+    /// Reads the load averages divided by the number of logical cores, where 1.0 means every core is busy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the load averages can't be read.
+    pub fn per_core_load(&self) -> Result<LoadAverage> {
+        Ok(self.load_average()?.per_core(self.logical_cores))
+    }
+
     /// Returns the CPU model name.
     ///
     /// The model name is the marketing name for the processor as reported by the system (e.g., "Apple M1 Pro" or "Intel
@@ -286,6 +337,107 @@ impl CPU {
     pub fn available_frequencies(&self) -> Option<&[f64]> {
         self.frequency_metrics.as_ref().map(|m| m.available.as_slice())
     }
+
+    /// Reports why the CPU is running below its maximum frequency.
+    ///
+    /// Uses the frequency and usage values from the last `update()` together with the SMC throttle key, the system
+    /// thermal state, and the power source. See [`ThrottleSignals`] for the per-architecture accuracy of the
+    /// classification.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ThrottleReasons>` - The classification; signals that couldn't be read are None
+    ///
+    /// # Errors
+    ///
+    /// Currently never fails; unavailable signals are reported as None instead.
+    pub fn throttle_reasons(&self) -> Result<ThrottleReasons> {
+        let usage = if self.logical_cores > 0 { Some(self.get_cpu_usage()) } else { None };
+
+        Ok(ThrottleSignals::from_iokit(self.iokit.as_ref())
+            .with_frequency(self.frequency_metrics.as_ref())
+            .with_cpu_usage(usage)
+            .with_on_battery(on_battery_power(self.iokit.as_ref()))
+            .classify())
+    }
+}
+
+/// Reads the tick counters of every logical CPU, in the kernel's numbering.
+fn read_core_ticks() -> Result<Vec<[u32; 4]>> {
+    let mut processors = 0;
+    let mut info: *mut i32 = ptr::null_mut();
+    let mut count = 0;
+
+    let result = unsafe {
+        host_processor_info(
+            mach_host_self(),
+            PROCESSOR_CPU_LOAD_INFO,
+            &mut processors,
+            &mut info,
+            &mut count,
+        )
+    };
+    if result != KERN_SUCCESS {
+        return Err(Error::system(format!("host_processor_info failed with code {result}")));
+    }
+
+    // SAFETY: the kernel returned one processor_cpu_load_info per processor, allocated in this task.
+    let ticks = unsafe {
+        slice::from_raw_parts(info as *const processor_cpu_load_info, processors as usize)
+    }
+    .iter()
+    .map(|load| load.cpu_ticks)
+    .collect();
+    unsafe {
+        libc::vm_deallocate(
+            mach_task_self(),
+            info as libc::vm_address_t,
+            count as libc::vm_size_t * mem::size_of::<i32>(),
+        );
+    }
+    Ok(ticks)
+}
+
+/// Computes the usage of each core from the tick counters of two reads.
+///
+/// A core missing from `previous` is measured since boot. The 32-bit counters wrap, so the differences wrap too.
+pub(crate) fn core_usage(previous: &[[u32; 4]], current: &[[u32; 4]]) -> Vec<f64> {
+    current
+        .iter()
+        .enumerate()
+        .map(|(core, ticks)| {
+            let before = previous.get(core).copied().unwrap_or_default();
+            let delta = |state: usize| f64::from(ticks[state].wrapping_sub(before[state]));
+            let busy = delta(CPU_STATE_USER) + delta(CPU_STATE_SYSTEM) + delta(CPU_STATE_NICE);
+            let total = busy + delta(CPU_STATE_IDLE);
+            if total > 0.0 {
+                busy / total
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+/// Average CPU usage between successive reads of the per-core tick counters
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UsageSampler {
+    previous: Option<Vec<[u32; 4]>>,
+}
+
+impl UsageSampler {
+    /// Reads the tick counters and returns the average usage of all cores since the previous read.
+    ///
+    /// The first read has nothing to compare with and returns None, as does a read that fails.
+    pub(crate) fn sample(&mut self) -> Option<f64> {
+        let current = read_core_ticks().ok()?;
+        let usage = self.previous.as_deref().map(|previous| core_usage(previous, &current));
+        self.previous = Some(current);
+
+        usage
+            .filter(|usage| !usage.is_empty())
+            .map(|usage| usage.iter().sum::<f64>() / usage.len() as f64)
+    }
 }
 
 /// Implementation of the CpuMetrics trait for the CPU struct.
@@ -335,17 +487,23 @@ impl CPU {
 
         mock.expect_get_service().returning(|_| {
             use crate::utils::test_utils;
-            Ok(test_utils::create_test_object().into())
+            Ok(test_utils::create_test_service())
         });
 
+        mock.expect_check_thermal_throttling().returning(|| Ok(false));
+        mock.expect_io_service_matching()
+            .returning(|_| crate::utils::test_utils::create_test_dictionary());
+        mock.expect_io_service_get_matching_service().returning(|_| None);
+
         let cpu = Self {
             physical_cores: 8,
             logical_cores: 16,
             frequency_mhz: 3200.0,
             core_usage: vec![0.3, 0.5, 0.2, 0.8, 0.1, 0.3, 0.4, 0.6],
+            core_ticks: Vec::new(),
             model_name: "Apple M1 Pro".to_string(),
             temperature: Some(45.5),
-            iokit: Box::new(mock),
+            iokit: Arc::new(mock),
             frequency_monitor: FrequencyMonitor::new(),
             frequency_metrics: Some(FrequencyMetrics {
                 current: 3200.0,
@@ -353,6 +511,23 @@ impl CPU {
                 max: 3600.0,
                 available: vec![1200.0, 1800.0, 2400.0, 3000.0, 3600.0],
             }),
+            topology: CpuTopology::from_levels(
+                &[
+                    PerfLevel {
+                        name: "Performance".to_string(),
+                        logical_cores: 6,
+                        physical_cores: 6,
+                        l2_cache_bytes: Some(12 * 1024 * 1024),
+                    },
+                    PerfLevel {
+                        name: "Efficiency".to_string(),
+                        logical_cores: 2,
+                        physical_cores: 2,
+                        l2_cache_bytes: Some(4 * 1024 * 1024),
+                    },
+                ],
+                None,
+            ),
         };
 
         Ok(cpu)
@@ -1,6 +1,47 @@
+//! CPU frequency.
+//!
+//! Intel Macs report their base and turbo frequencies through the `hw.cpufrequency*` sysctls. Apple Silicon has no
+//! such sysctls: each cluster switches between the entries of its DVFS (dynamic voltage and frequency scaling) table,
+//! which the device tree lists under `arm-io/pmgr`, and IOReport counts the time spent in each entry. The frequency of
+//! a cluster over an interval is the average of the table weighted by those times, the way `powermetrics` computes
+//! it.
+
+use std::{
+    ffi::CString,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
 use libc;
+use objc2::rc::autoreleasepool;
+use objc2_foundation::{NSData, NSString};
+use once_cell::sync::Lazy as SyncLazy;
+
+use super::{
+    ioreport::{ClusterResidency, ClusterStatesReport},
+    PerformanceLevel,
+};
+use crate::{
+    error::{Error, Result},
+    hardware::iokit::{IOKit, IOKitImpl},
+    system::{detect_architecture, Architecture},
+};
+
+/// Registry path of the power manager, which holds the DVFS tables
+const PMGR_PATH: &str = "IODeviceTree:/arm-io/pmgr";
+/// Power manager property with the DVFS table of the efficiency clusters
+const EFFICIENCY_DVFS_KEY: &str = "voltage-states1-sram";
+/// Power manager property with the DVFS table of the performance clusters
+const PERFORMANCE_DVFS_KEY: &str = "voltage-states5-sram";
+/// Shortest interval the cluster frequencies are averaged over; calls closer together get the previous result
+const MIN_SAMPLE_INTERVAL: Duration = Duration::from_millis(50);
+/// DVFS table entries above this are in Hz, those below in kHz as on M4
+const DVFS_HZ_THRESHOLD: u32 = 100_000_000;
 
-use crate::error::{Error, Result};
+/// IOReport sampler shared by every monitor, so each call averages over the time since the previous one
+static CLUSTER_SAMPLER: SyncLazy<Mutex<Option<ClusterSampler>>> =
+    SyncLazy::new(|| Mutex::new(None));
 
 /// Container for comprehensive CPU frequency-related metrics.
 ///
@@ -8,8 +49,9 @@ use crate::error::{Error, Result};
 /// provides access to the full spectrum of frequency-related data, including current operating frequency, supported
 /// frequency ranges, and available frequency steps.
 ///
-/// On macOS, this information is retrieved through sysctl calls to access the hw.cpufrequency and related system
-/// parameters.
+/// On Apple Silicon, `current` is the average frequency of the clusters since the previous call, weighted by how long
+/// each of them ran, and `min`, `max` and `available` come from the DVFS tables of the clusters. On Intel Macs, this
+/// information is retrieved through sysctl calls to access the hw.cpufrequency and related system parameters.
 ///
 /// # Fields
 ///
@@ -51,14 +93,33 @@ pub struct FrequencyMetrics {
     pub available: Vec<f64>,
 }
 
+/// Frequency of one CPU cluster of Apple Silicon
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClusterFrequency {
+    /// Type of the cores of the cluster
+    pub kind: PerformanceLevel,
+    /// Name IOReport gives the cluster, e.g. "PCPU1"
+    pub name: String,
+    /// Average frequency in MHz while the cluster ran, or `min` if it didn't run
+    pub current: f64,
+    /// Lowest frequency of the DVFS table in MHz
+    pub min: f64,
+    /// Highest frequency of the DVFS table in MHz
+    pub max: f64,
+    /// Share of the interval the cluster ran, between 0.0 and 1.0
+    pub active_residency: f64,
+}
+
 /// Monitor for CPU frequency metrics with detailed frequency information.
 ///
 /// The FrequencyMonitor provides methods to retrieve comprehensive CPU frequency information from the macOS system with
 /// high precision. This includes the current operating frequency, minimum and maximum supported frequencies, and
 /// available frequency steps for dynamic frequency scaling.
 ///
-/// Under the hood, FrequencyMonitor uses macOS sysctl calls to access the system's hw.cpufrequency,
-/// hw.cpufrequency_min, and hw.cpufrequency_max parameters to provide accurate and up-to-date frequency information.
+/// Under the hood, FrequencyMonitor samples the performance state residency of the clusters through IOReport on Apple
+/// Silicon, and uses macOS sysctl calls to access the system's hw.cpufrequency, hw.cpufrequency_min, and
+/// hw.cpufrequency_max parameters on Intel Macs.
 ///
 /// For most applications, it's recommended to use the CPU struct directly, which incorporates FrequencyMonitor
 /// functionality, but this standalone monitor is available for focused frequency monitoring without the overhead of the
@@ -85,7 +146,8 @@ impl FrequencyMonitor {
     /// Retrieves the current CPU frequency metrics.
     ///
     /// This method queries the system for detailed frequency information, including current, minimum, and maximum
-    /// frequencies.
+    /// frequencies. On Apple Silicon the current frequency is averaged over the time since the previous call, or over a
+    /// short interval the first time; it falls back to the sysctls if IOReport can't be read.
     ///
     /// # Returns
     ///
@@ -95,8 +157,188 @@ impl FrequencyMonitor {
     ///
     /// Returns an error if the system calls fail or if the frequency information cannot be retrieved.
     pub fn get_metrics(&self) -> Result<FrequencyMetrics> {
+        if matches!(detect_architecture(), Ok(Architecture::AppleSilicon)) {
+            match self.cluster_frequencies().and_then(|clusters| {
+                combine_clusters(&clusters, &read_dvfs_tables())
+                    .ok_or_else(|| Error::not_available("No CPU cluster frequencies"))
+            }) {
+                Ok(metrics) => return Ok(metrics),
+                Err(e) => tracing::debug!("Falling back to the frequency sysctls: {e}"),
+            }
+        }
         fetch_cpu_frequencies()
     }
+
+    /// Retrieves the average frequency of each cluster of Apple Silicon since the previous call.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error on Intel Macs, or if IOReport or the DVFS tables can't be read.
+    pub fn cluster_frequencies(&self) -> Result<Vec<ClusterFrequency>> {
+        let mut shared = CLUSTER_SAMPLER
+            .lock()
+            .map_err(|_| Error::system("CPU frequency sampler lock poisoned"))?;
+        let mut sampler = match shared.take() {
+            Some(sampler) => sampler,
+            None => {
+                let sampler = ClusterSampler::new()?;
+                // The first sample has nothing to compare with, so average over a short interval
+                thread::sleep(MIN_SAMPLE_INTERVAL);
+                sampler
+            },
+        };
+        let clusters = sampler.sample();
+        *shared = Some(sampler);
+        clusters
+    }
+}
+
+/// DVFS tables of the cluster types, in MHz
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct DvfsTables {
+    pub efficiency: Vec<f64>,
+    pub performance: Vec<f64>,
+}
+
+impl DvfsTables {
+    fn table(&self, kind: PerformanceLevel) -> &[f64] {
+        match kind {
+            PerformanceLevel::Efficiency => &self.efficiency,
+            PerformanceLevel::Performance | PerformanceLevel::Uniform => &self.performance,
+        }
+    }
+}
+
+/// Cluster frequencies from an IOReport subscription, with the result of the last sample
+struct ClusterSampler {
+    report: ClusterStatesReport,
+    tables: DvfsTables,
+    last: Option<(Instant, Vec<ClusterFrequency>)>,
+}
+
+impl ClusterSampler {
+    fn new() -> Result<Self> {
+        let tables = read_dvfs_tables();
+        if tables.efficiency.is_empty() && tables.performance.is_empty() {
+            return Err(Error::not_available("CPU DVFS tables"));
+        }
+        Ok(Self { report: ClusterStatesReport::new()?, tables, last: None })
+    }
+
+    fn sample(&mut self) -> Result<Vec<ClusterFrequency>> {
+        if let Some((sampled_at, clusters)) = &self.last {
+            if sampled_at.elapsed() < MIN_SAMPLE_INTERVAL {
+                return Ok(clusters.clone());
+            }
+        }
+
+        let clusters: Vec<ClusterFrequency> = self
+            .report
+            .residencies()?
+            .iter()
+            .filter_map(|residency| cluster_frequency(residency, self.tables.table(residency.kind)))
+            .collect();
+        self.last = Some((Instant::now(), clusters.clone()));
+        Ok(clusters)
+    }
+}
+
+/// Decodes a DVFS table of 8 byte entries, each a little-endian frequency followed by a voltage, into MHz.
+///
+/// Unused entries have a frequency of 0 and are skipped.
+pub(crate) fn parse_dvfs_table(bytes: &[u8]) -> Vec<f64> {
+    bytes
+        .chunks_exact(8)
+        .filter_map(|entry| {
+            let frequency = u32::from_le_bytes(entry[..4].try_into().ok()?);
+            match frequency {
+                0 => None,
+                hz if hz > DVFS_HZ_THRESHOLD => Some(f64::from(hz) / 1_000_000.0),
+                khz => Some(f64::from(khz) / 1_000.0),
+            }
+        })
+        .collect()
+}
+
+/// The frequency of the cluster that spent `residency` in the states of `table`, None without a table.
+///
+/// The performance states map to the table entries in order; states beyond the end of the table are ignored.
+pub(crate) fn cluster_frequency(
+    residency: &ClusterResidency,
+    table: &[f64],
+) -> Option<ClusterFrequency> {
+    let min = table.iter().copied().reduce(f64::min)?;
+    let max = table.iter().copied().reduce(f64::max)?;
+
+    let (time, weighted) = residency.active.iter().zip(table).fold(
+        (0i64, 0.0),
+        |(time, weighted), (&state, &frequency)| {
+            (time + state, weighted + state as f64 * frequency)
+        },
+    );
+    let total = time + residency.inactive;
+
+    Some(ClusterFrequency {
+        kind: residency.kind,
+        name: residency.name.clone(),
+        current: if time > 0 { weighted / time as f64 } else { min },
+        min,
+        max,
+        active_residency: if total > 0 { time as f64 / total as f64 } else { 0.0 },
+    })
+}
+
+/// Combines the cluster frequencies into the metrics of the whole CPU, None without clusters.
+///
+/// The current frequency is the average of the clusters weighted by how long each ran, or the lowest frequency if
+/// none ran; the available steps are those of every DVFS table.
+pub(crate) fn combine_clusters(
+    clusters: &[ClusterFrequency],
+    tables: &DvfsTables,
+) -> Option<FrequencyMetrics> {
+    let min = clusters.iter().map(|cluster| cluster.min).reduce(f64::min)?;
+    let max = clusters.iter().map(|cluster| cluster.max).reduce(f64::max)?;
+
+    let active: f64 = clusters.iter().map(|cluster| cluster.active_residency).sum();
+    let current = if active > 0.0 {
+        clusters.iter().map(|cluster| cluster.current * cluster.active_residency).sum::<f64>()
+            / active
+    } else {
+        min
+    };
+
+    let mut available: Vec<f64> =
+        tables.efficiency.iter().chain(&tables.performance).copied().collect();
+    available.sort_by(f64::total_cmp);
+    available.dedup();
+
+    Some(FrequencyMetrics { current, min, max, available })
+}
+
+/// Reads the DVFS tables of the clusters from the device tree; tables that can't be read are empty.
+fn read_dvfs_tables() -> DvfsTables {
+    let io_kit = IOKitImpl::default();
+    let Some(pmgr) = io_kit.find_service_by_path(PMGR_PATH) else {
+        return DvfsTables::default();
+    };
+
+    autoreleasepool(|_| {
+        let Ok(properties) = io_kit.io_registry_entry_create_cf_properties(&pmgr) else {
+            return DvfsTables::default();
+        };
+
+        let table = |key: &str| {
+            properties
+                .valueForKey(&NSString::from_str(key))
+                .and_then(|value| value.downcast::<NSData>().ok())
+                .map(|data| parse_dvfs_table(&data.to_vec()))
+                .unwrap_or_default()
+        };
+        DvfsTables {
+            efficiency: table(EFFICIENCY_DVFS_KEY),
+            performance: table(PERFORMANCE_DVFS_KEY),
+        }
+    })
 }
 
 #[derive(Default)]
@@ -144,8 +386,6 @@ unsafe fn retrieve_cpu_info() -> Result<CpuInfo> {
 }
 
 unsafe fn fetch_sysctl_frequency_by_name(name: &str) -> Result<f64> {
-    use std::ffi::CString;
-
     // Create null-terminated C string for the sysctl name
     let c_name = CString::new(name).map_err(|_| {
         Error::system(format!("Failed to create C string for sysctl name: {}", name))
@@ -0,0 +1,222 @@
+//! Residency of the CPU clusters in their performance states, from IOReport.
+//!
+//! The `CPU Stats` group of IOReport has a state channel per cluster in its `CPU Complex Performance States` subgroup,
+//! named `ECPU`/`PCPU` (or `EACC`/`PACC`, with a die or cluster number on larger chips). Each state counts the time
+//! the cluster spent in it: first the idle and power-gated states, then one state per entry of the DVFS table of the
+//! cluster, slowest first. The counters are cumulative, so the time spent in each state over an interval is the
+//! difference of two samples, which `IOReportCreateSamplesDelta` computes.
+//!
+//! Every object IOReport returns from a Copy or Create function is a CoreFoundation object owned by the caller; the
+//! getters return objects owned by the channel they are called on.
+
+use std::{ffi::c_void as ffi_c_void, ptr};
+
+use objc2::{
+    class, msg_send,
+    rc::{autoreleasepool, Retained},
+    runtime::AnyObject,
+};
+use objc2_foundation::{NSDictionary, NSObject, NSString};
+
+use super::PerformanceLevel;
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{
+        CFRelease, IOReportChannelGetChannelName, IOReportCopyChannelsInGroup,
+        IOReportCreateSamples, IOReportCreateSamplesDelta, IOReportCreateSubscription,
+        IOReportStateGetCount, IOReportStateGetNameForIndex, IOReportStateGetResidency,
+    },
+};
+
+/// IOReport group with the CPU performance states
+const CPU_STATS_GROUP: &str = "CPU Stats";
+/// Subgroup with one channel per cluster
+const CLUSTER_STATES_SUBGROUP: &str = "CPU Complex Performance States";
+/// Key of the channel array in a sample
+const CHANNELS_KEY: &str = "IOReportChannels";
+/// States in which the cluster doesn't run
+const INACTIVE_STATES: [&str; 3] = ["IDLE", "DOWN", "OFF"];
+
+/// Time one cluster spent in each of its states over an interval
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ClusterResidency {
+    /// Name of the channel, e.g. "PCPU1"
+    pub name: String,
+    /// Type of the cores of the cluster
+    pub kind: PerformanceLevel,
+    /// Time spent in a state the cluster doesn't run in
+    pub inactive: i64,
+    /// Time spent in each performance state, slowest first
+    pub active: Vec<i64>,
+}
+
+/// The core type of the cluster a channel reports on, None for channels of other units.
+pub(crate) fn channel_kind(name: &str) -> Option<PerformanceLevel> {
+    if name.starts_with("ECPU") || name.starts_with("EACC") {
+        Some(PerformanceLevel::Efficiency)
+    } else if name.starts_with("PCPU") || name.starts_with("PACC") {
+        Some(PerformanceLevel::Performance)
+    } else {
+        None
+    }
+}
+
+/// Splits the residency of each named state into the inactive time and the time of each performance state.
+pub(crate) fn split_states(states: &[(String, i64)]) -> (i64, Vec<i64>) {
+    let mut inactive = 0;
+    let mut active = Vec::new();
+    for (name, residency) in states {
+        // Counters can only go backwards when they are reset, which counts as no time
+        let residency = (*residency).max(0);
+        if INACTIVE_STATES.contains(&name.trim()) {
+            inactive += residency;
+        } else {
+            active.push(residency);
+        }
+    }
+    (inactive, active)
+}
+
+/// An IOReport subscription to the cluster performance states, with the last sample taken.
+pub(crate) struct ClusterStatesReport {
+    subscription: *mut ffi_c_void,
+    channels: *mut ffi_c_void,
+    subscribed: *mut ffi_c_void,
+    previous: *mut ffi_c_void,
+}
+
+// SAFETY: the CoreFoundation objects are only reached through `&mut self`, and CoreFoundation reference counting is
+// thread safe.
+unsafe impl Send for ClusterStatesReport {}
+
+impl ClusterStatesReport {
+    /// Subscribes to the performance states of every cluster and takes a first sample.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CPU has no cluster channels, as on Intel Macs, or the subscription fails.
+    pub(crate) fn new() -> Result<Self> {
+        let group = NSString::from_str(CPU_STATS_GROUP);
+        let subgroup = NSString::from_str(CLUSTER_STATES_SUBGROUP);
+
+        // SAFETY: the group names are CFStrings through toll-free bridging, and every object created here is owned by
+        // `report`, which releases it when dropped.
+        unsafe {
+            let mut report = Self {
+                subscription: ptr::null_mut(),
+                channels: IOReportCopyChannelsInGroup(
+                    Retained::as_ptr(&group) as *const ffi_c_void,
+                    Retained::as_ptr(&subgroup) as *const ffi_c_void,
+                    0,
+                    0,
+                    0,
+                ),
+                subscribed: ptr::null_mut(),
+                previous: ptr::null_mut(),
+            };
+            if report.channels.is_null() {
+                return Err(Error::not_available("IOReport has no CPU performance state channels"));
+            }
+
+            report.subscription = IOReportCreateSubscription(
+                ptr::null(),
+                report.channels,
+                &mut report.subscribed,
+                0,
+                ptr::null(),
+            );
+            if report.subscription.is_null() || report.subscribed.is_null() {
+                return Err(Error::system("Failed to subscribe to the CPU performance states"));
+            }
+
+            report.previous = report.sample()?;
+            Ok(report)
+        }
+    }
+
+    /// Samples the states and returns the residency of each cluster since the previous sample.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if IOReport fails to take a sample.
+    pub(crate) fn residencies(&mut self) -> Result<Vec<ClusterResidency>> {
+        // SAFETY: the samples are valid CoreFoundation objects owned here; the previous one is replaced before the
+        // delta is released, so each is released exactly once.
+        unsafe {
+            let current = self.sample()?;
+            let delta = IOReportCreateSamplesDelta(self.previous, current, ptr::null());
+            CFRelease(self.previous);
+            self.previous = current;
+            if delta.is_null() {
+                return Err(Error::system("Failed to compute the CPU performance state delta"));
+            }
+
+            let residencies = autoreleasepool(|_| read_residencies(delta));
+            CFRelease(delta);
+            Ok(residencies)
+        }
+    }
+
+    /// Takes a sample of the subscribed channels, owned by the caller.
+    unsafe fn sample(&self) -> Result<*mut ffi_c_void> {
+        let sample = IOReportCreateSamples(self.subscription, self.subscribed, ptr::null());
+        if sample.is_null() {
+            Err(Error::system("Failed to sample the CPU performance states"))
+        } else {
+            Ok(sample)
+        }
+    }
+}
+
+impl Drop for ClusterStatesReport {
+    fn drop(&mut self) {
+        for object in [self.previous, self.subscribed, self.subscription, self.channels] {
+            if !object.is_null() {
+                // SAFETY: every non-null pointer is an object this report owns.
+                unsafe { CFRelease(object) };
+            }
+        }
+    }
+}
+
+/// Reads the residency of each cluster channel of the sample or delta `samples`.
+unsafe fn read_residencies(samples: *mut ffi_c_void) -> Vec<ClusterResidency> {
+    // Samples are CFDictionaries, toll-free bridged to NSDictionary; the dictionary stays owned by the caller
+    let Some(samples) = (samples as *const NSDictionary<NSString, NSObject>).as_ref() else {
+        return Vec::new();
+    };
+    let Some(channels) = samples.valueForKey(&NSString::from_str(CHANNELS_KEY)) else {
+        return Vec::new();
+    };
+    let is_array: bool = msg_send![&channels, isKindOfClass: class!(NSArray)];
+    if !is_array {
+        return Vec::new();
+    }
+
+    let count: usize = msg_send![&channels, count];
+    (0..count)
+        .filter_map(|i| {
+            let entry: Option<Retained<AnyObject>> = msg_send![&channels, objectAtIndex: i];
+            let entry = entry?;
+            let channel = Retained::as_ptr(&entry) as *const ffi_c_void;
+
+            let name = cf_string(IOReportChannelGetChannelName(channel))?;
+            let kind = channel_kind(&name)?;
+            let states: Vec<(String, i64)> = (0..IOReportStateGetCount(channel))
+                .map(|index| {
+                    (
+                        cf_string(IOReportStateGetNameForIndex(channel, index)).unwrap_or_default(),
+                        IOReportStateGetResidency(channel, index),
+                    )
+                })
+                .collect();
+            let (inactive, active) = split_states(&states);
+            Some(ClusterResidency { name, kind, inactive, active })
+        })
+        .collect()
+}
+
+/// Copies a CFString owned by someone else, None if it's null.
+unsafe fn cf_string(string: *const ffi_c_void) -> Option<String> {
+    (string as *const NSString).as_ref().map(|string| string.to_string())
+}
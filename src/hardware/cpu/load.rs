@@ -0,0 +1,97 @@
+//! Load average and run-queue depth.
+//!
+//! The 1, 5 and 15 minute load averages come from `getloadavg`, which reads the `vm.loadavg` sysctl. The scheduler
+//! also keeps a short average of the runnable threads, recomputed every few seconds, which Mach reports as the load
+//! of the default processor set. macOS has no `kern.cp_time` and no instantaneous run-queue length.
+
+use crate::{
+    error::{Error, Result},
+    init,
+    utils::bindings::{
+        getloadavg, mach_host_self, mach_port_deallocate, mach_task_self, processor_set_default,
+        processor_set_load_info, processor_set_statistics, KERN_SUCCESS, LOAD_SCALE,
+        PROCESSOR_SET_LOAD_INFO, PROCESSOR_SET_LOAD_INFO_COUNT,
+    },
+};
+
+/// System load averages and the number of runnable threads
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoadAverage {
+    /// Load average over the last minute
+    pub one: f64,
+    /// Load average over the last 5 minutes
+    pub five: f64,
+    /// Load average over the last 15 minutes
+    pub fifteen: f64,
+    /// Runnable threads, averaged by the scheduler over the last few seconds; None if Mach doesn't report them
+    pub runnable_tasks: Option<f64>,
+}
+
+impl LoadAverage {
+    /// The load divided by `logical_cores`, where 1.0 means every core is busy.
+    ///
+    /// A core count of 0 leaves the load unchanged.
+    pub fn per_core(self, logical_cores: u32) -> Self {
+        let cores = f64::from(logical_cores.max(1));
+        Self {
+            one: self.one / cores,
+            five: self.five / cores,
+            fifteen: self.fifteen / cores,
+            runnable_tasks: self.runnable_tasks.map(|tasks| tasks / cores),
+        }
+    }
+}
+
+/// Reads the load averages and the runnable threads.
+///
+/// # Errors
+///
+/// Returns an error if the load averages can't be read.
+pub fn load_average() -> Result<LoadAverage> {
+    let mut loads = [0.0f64; 3];
+    // SAFETY: the buffer has room for the three requested values.
+    if unsafe { getloadavg(loads.as_mut_ptr(), 3) } != 3 {
+        return Err(Error::system("Failed to read the load averages"));
+    }
+
+    Ok(LoadAverage {
+        one: loads[0],
+        five: loads[1],
+        fifteen: loads[2],
+        runnable_tasks: runnable_tasks(),
+    })
+}
+
+/// Reads the load averages divided by the number of logical cores.
+///
+/// # Errors
+///
+/// Returns an error if the load averages or the number of cores can't be read.
+pub fn per_core_load() -> Result<LoadAverage> {
+    Ok(load_average()?.per_core(init::system_info()?.logical_cores))
+}
+
+/// The runnable threads of the default processor set, None if Mach doesn't report them.
+fn runnable_tasks() -> Option<f64> {
+    let mut info = processor_set_load_info::default();
+    let mut count = PROCESSOR_SET_LOAD_INFO_COUNT;
+
+    // SAFETY: `pset` receives a name port that is deallocated below, and `info` has room for `count` integers.
+    let result = unsafe {
+        let mut pset = 0;
+        if processor_set_default(mach_host_self(), &mut pset) != KERN_SUCCESS {
+            return None;
+        }
+        let result = processor_set_statistics(
+            pset,
+            PROCESSOR_SET_LOAD_INFO,
+            &mut info as *mut processor_set_load_info as *mut i32,
+            &mut count,
+        );
+        mach_port_deallocate(mach_task_self(), pset);
+        result
+    };
+
+    (result == KERN_SUCCESS).then(|| f64::from(info.load_average.max(0)) / f64::from(LOAD_SCALE))
+}
@@ -14,8 +14,15 @@
 //!   - Current operating frequency
 //!   - Minimum and maximum supported frequencies
 //!   - Available frequency steps
+//!   - Average frequency of each cluster of Apple Silicon ([`FrequencyMonitor::cluster_frequencies`])
 //! - **Temperature Readings**: CPU temperature in Celsius when available
 //! - **CPU Model Information**: Detailed processor identification
+//! - **Throttle Reasons**: Best-effort classification of why the CPU runs below its maximum frequency
+//! - **Usage History**: Bounded per-core usage history for sparklines ([`CpuUsageHistory`])
+//! - **Load Average**: 1, 5 and 15 minute load averages and runnable threads, also per core ([`load_average`],
+//!   [`per_core_load`])
+//! - **Clusters**: Performance and efficiency clusters of Apple Silicon and their usage ([`CpuTopology`],
+//!   [`CPU::cluster_usage`])
 //!
 //! ## Example
 //!
@@ -65,12 +72,27 @@
 
 mod cpu_impl;
 mod frequency;
+mod ioreport;
+mod load;
+mod throttle;
+mod topology;
+mod usage_history;
 
 #[cfg(test)]
 mod tests;
 
 pub use cpu_impl::CPU;
-pub use frequency::{FrequencyMetrics, FrequencyMonitor};
+pub use frequency::{ClusterFrequency, FrequencyMetrics, FrequencyMonitor};
+pub use load::{load_average, per_core_load, LoadAverage};
+pub use throttle::{
+    throttle_reasons, ThermalState, ThrottleCause, ThrottleReasons, ThrottleSignals,
+    IDLE_CPU_USAGE, SLOW_FREQUENCY_RATIO,
+};
+pub use topology::{ClusterUsage, CpuCluster, CpuTopology, PerformanceLevel};
+pub use usage_history::{CoreUsageSample, CpuUsageHistory};
+
+pub(crate) use cpu_impl::UsageSampler;
+pub(crate) use throttle::system_signals;
 
 /// Maximum number of CPU cores supported by the library.
 pub const MAX_CORES: u32 = 64;
@@ -1,4 +1,19 @@
-use crate::hardware::cpu::{CpuMetrics, FrequencyMetrics, FrequencyMonitor, CPU};
+use std::time::{Duration, Instant};
+
+use crate::hardware::{
+    cpu::{
+        cpu_impl::core_usage,
+        frequency::{cluster_frequency, combine_clusters, parse_dvfs_table, DvfsTables},
+        ioreport::{channel_kind, split_states, ClusterResidency},
+        throttle::on_battery_power,
+        topology::{cluster_type_kind, core_kinds_from_nodes, PerfLevel},
+        ClusterFrequency, CpuMetrics, CpuTopology, CpuUsageHistory, FrequencyMetrics,
+        FrequencyMonitor, LoadAverage, PerformanceLevel, ThermalState, ThrottleCause,
+        ThrottleSignals, CPU,
+    },
+    iokit::mock::MockIOKit,
+};
+use crate::utils::test_utils;
 
 #[test]
 fn test_cpu_initialization() {
@@ -262,3 +277,492 @@ fn test_core_usage_with_different_core_counts() {
     let expected_avg = sum / cpu.logical_cores() as f64;
     assert_eq!(cpu.get_cpu_usage(), expected_avg);
 }
+
+#[test]
+fn test_core_usage_from_ticks() {
+    // user, system, idle, nice
+    let previous = [[100, 50, 850, 0], [0, 0, 1000, 0]];
+    let current = [[160, 70, 870, 0], [0, 0, 1100, 0], [30, 0, 60, 10]];
+
+    let usage = core_usage(&previous, &current);
+    assert_eq!(usage.len(), 3);
+    assert!((usage[0] - 0.8).abs() < 1e-9);
+    assert_eq!(usage[1], 0.0);
+    // A core without a previous read is measured since boot
+    assert!((usage[2] - 0.4).abs() < 1e-9);
+
+    // An idle interval without ticks doesn't divide by zero, and wrapped counters still count forward
+    assert_eq!(core_usage(&current, &current)[0], 0.0);
+    let wrapped = core_usage(&[[u32::MAX - 9, 0, 0, 0]], &[[10, 0, 20, 0]]);
+    assert!((wrapped[0] - 0.5).abs() < 1e-9);
+}
+
+fn frequency_at(current: f64) -> FrequencyMetrics {
+    FrequencyMetrics { current, min: 600.0, max: 3000.0, available: vec![] }
+}
+
+#[test]
+fn test_throttle_hot_and_slow_is_thermal() {
+    let signals = ThrottleSignals {
+        smc_throttling: Some(true),
+        thermal_state: Some(ThermalState::Serious),
+        cpu_usage: Some(0.9),
+        on_battery: Some(false),
+        ..ThrottleSignals::default()
+    }
+    .with_frequency(Some(&frequency_at(1500.0)));
+
+    let reasons = signals.classify();
+    assert_eq!(reasons.thermal, Some(true));
+    assert_eq!(reasons.power_limit, Some(false));
+    assert_eq!(reasons.estimated_dominant, Some(ThrottleCause::Thermal));
+}
+
+#[test]
+fn test_throttle_cool_and_slow_on_battery_is_power_limit() {
+    let signals = ThrottleSignals {
+        smc_throttling: Some(false),
+        thermal_state: Some(ThermalState::Nominal),
+        on_battery: Some(true),
+        ..ThrottleSignals::default()
+    }
+    .with_frequency(Some(&frequency_at(1500.0)));
+
+    let reasons = signals.classify();
+    assert_eq!(reasons.thermal, Some(false));
+    assert_eq!(reasons.power_limit, Some(true));
+    assert_eq!(reasons.estimated_dominant, Some(ThrottleCause::PowerLimit));
+}
+
+#[test]
+fn test_throttle_cool_and_fast_is_none() {
+    let signals = ThrottleSignals {
+        smc_throttling: Some(false),
+        thermal_state: Some(ThermalState::Fair),
+        cpu_usage: Some(0.9),
+        on_battery: Some(true),
+        ..ThrottleSignals::default()
+    }
+    .with_frequency(Some(&frequency_at(2900.0)));
+
+    let reasons = signals.classify();
+    assert_eq!(reasons.thermal, Some(false));
+    assert_eq!(reasons.power_limit, Some(false));
+    assert_eq!(reasons.estimated_dominant, None);
+}
+
+#[test]
+fn test_throttle_cool_slow_and_idle_is_idle() {
+    let signals = ThrottleSignals {
+        thermal_state: Some(ThermalState::Nominal),
+        on_battery: Some(true),
+        ..ThrottleSignals::default()
+    }
+    .with_frequency(Some(&frequency_at(800.0)))
+    .with_cpu_usage(Some(0.05));
+
+    let reasons = signals.classify();
+    assert_eq!(reasons.power_limit, Some(false));
+    assert_eq!(reasons.estimated_dominant, Some(ThrottleCause::Idle));
+}
+
+#[test]
+fn test_throttle_without_signals_is_undetermined() {
+    let reasons = ThrottleSignals::default().classify();
+    assert_eq!(reasons.thermal, None);
+    assert_eq!(reasons.power_limit, None);
+    assert_eq!(reasons.estimated_dominant, None);
+
+    // Apple Silicon: thermal state only, no frequency information
+    let reasons = ThrottleSignals {
+        thermal_state: Some(ThermalState::Critical),
+        ..ThrottleSignals::default()
+    }
+    .classify();
+    assert_eq!(reasons.thermal, Some(true));
+    assert_eq!(reasons.power_limit, None);
+    assert_eq!(reasons.estimated_dominant, Some(ThrottleCause::Thermal));
+}
+
+#[test]
+fn test_thermal_state_from_raw() {
+    assert_eq!(ThermalState::from_raw(0), Some(ThermalState::Nominal));
+    assert_eq!(ThermalState::from_raw(3), Some(ThermalState::Critical));
+    assert_eq!(ThermalState::from_raw(42), None);
+    assert!(ThermalState::Serious > ThermalState::Fair);
+}
+
+#[test]
+fn test_throttle_signals_from_mock_iokit() {
+    let mut mock = MockIOKit::new();
+    mock.expect_check_thermal_throttling().returning(|| Ok(true));
+
+    let mut signals =
+        ThrottleSignals::from_iokit(&mock).with_frequency(Some(&frequency_at(1200.0)));
+    // Pin the thermal state so the test doesn't depend on the machine running it
+    signals.thermal_state = Some(ThermalState::Nominal);
+
+    assert_eq!(signals.smc_throttling, Some(true));
+    assert_eq!(signals.classify().estimated_dominant, Some(ThrottleCause::Thermal));
+}
+
+#[test]
+fn test_on_battery_power_from_mock_iokit() {
+    let mut mock = MockIOKit::new();
+    mock.expect_io_service_matching().returning(|_| test_utils::create_test_dictionary());
+    mock.expect_io_service_get_matching_service()
+        .returning(|_| Some(test_utils::create_test_service()));
+    mock.expect_io_registry_entry_create_cf_properties()
+        .returning(|_| Ok(test_utils::create_test_dictionary()));
+    mock.expect_get_bool_property().returning(|_, _| Some(false));
+
+    assert_eq!(on_battery_power(&mock), Some(true));
+
+    let mut mock = MockIOKit::new();
+    mock.expect_io_service_matching().returning(|_| test_utils::create_test_dictionary());
+    mock.expect_io_service_get_matching_service().returning(|_| None);
+
+    assert_eq!(on_battery_power(&mock), None);
+}
+
+#[test]
+fn test_cpu_throttle_reasons_with_mock() {
+    let cpu = CPU::new_with_mock().expect("Failed to create CPU instance");
+
+    // 3200 of 3600 MHz is running at speed, and the mock reports no SMC throttling
+    let reasons = cpu.throttle_reasons().unwrap();
+    assert_eq!(reasons.signals.smc_throttling, Some(false));
+    assert_eq!(reasons.signals.on_battery, None);
+    assert_eq!(reasons.power_limit, Some(false));
+    assert_eq!(reasons.estimated_dominant, None);
+}
+
+#[test]
+fn test_usage_history_records_cpu() {
+    let cpu = CPU::new_with_mock().expect("Failed to create CPU instance");
+    let mut history = CpuUsageHistory::new(10);
+    assert!(history.is_empty());
+    assert!(history.latest().is_none());
+
+    history.record(&cpu);
+    history.record(&cpu);
+    assert_eq!(history.len(), 2);
+    assert_eq!(history.latest().unwrap().1, cpu.core_usage());
+    assert_eq!(
+        history.history_for_core(3).iter().map(|(_, usage)| *usage).collect::<Vec<_>>(),
+        [0.8, 0.8]
+    );
+    assert!(history.history_for_core(8).is_empty());
+
+    history.clear();
+    assert!(history.is_empty());
+    assert_eq!(history.capacity(), 10);
+}
+
+#[test]
+fn test_usage_history_evicts_oldest() {
+    let start = Instant::now();
+    let mut history = CpuUsageHistory::new(3);
+    for i in 0..5u32 {
+        history.record_usage(start + Duration::from_secs(u64::from(i)), &[f64::from(i) / 10.0]);
+    }
+
+    assert_eq!(history.len(), 3);
+    let core0 = history.history_for_core(0);
+    assert_eq!(core0.iter().map(|(_, usage)| *usage).collect::<Vec<_>>(), [0.2, 0.3, 0.4]);
+    assert_eq!(core0[0].0, start + Duration::from_secs(2));
+    assert_eq!(history.samples().front().unwrap().0, start + Duration::from_secs(2));
+
+    // A capacity of zero still holds the latest sample
+    let mut history = CpuUsageHistory::new(0);
+    history.record_usage(start, &[0.5]);
+    history.record_usage(start, &[0.6]);
+    assert_eq!(history.len(), 1);
+    assert_eq!(history.latest().unwrap().1, [0.6]);
+}
+
+#[test]
+fn test_usage_history_with_changing_core_count() {
+    let start = Instant::now();
+    let mut history = CpuUsageHistory::new(10);
+    history.record_usage(start, &[0.1, 0.2, 0.3, 0.4]);
+    // Two cores parked
+    history.record_usage(start + Duration::from_secs(1), &[0.5, 0.6]);
+    history.record_usage(start + Duration::from_secs(2), &[0.7, 0.8, 0.9, 1.0]);
+
+    assert_eq!(history.max_cores(), 4);
+    assert_eq!(history.history_for_core(1).len(), 3);
+    let core3 = history.history_for_core(3);
+    assert_eq!(core3, [(start, 0.4), (start + Duration::from_secs(2), 1.0)]);
+    assert_eq!(history.latest().unwrap().1.len(), 4);
+}
+
+fn perf_level(name: &str, cores: u32) -> PerfLevel {
+    PerfLevel {
+        name: name.to_string(),
+        logical_cores: cores,
+        physical_cores: cores,
+        l2_cache_bytes: Some(4 * 1024 * 1024),
+    }
+}
+
+#[test]
+fn test_topology_without_device_tree() {
+    // M1 Pro: the two efficiency cores are CPUs 0 and 1
+    let levels = [perf_level("Performance", 8), perf_level("Efficiency", 2)];
+    let topology = CpuTopology::from_levels(&levels, None);
+
+    let clusters = topology.clusters();
+    assert_eq!(clusters.len(), 2);
+    assert_eq!(clusters[0].kind, PerformanceLevel::Performance);
+    assert_eq!(clusters[0].cores, (2..10).collect::<Vec<_>>());
+    assert_eq!(clusters[1].kind, PerformanceLevel::Efficiency);
+    assert_eq!(clusters[1].cores, [0, 1]);
+    assert_eq!(clusters[1].l2_cache_bytes, Some(4 * 1024 * 1024));
+
+    assert!(topology.is_heterogeneous());
+    assert_eq!(topology.cluster_of(0).unwrap().kind, PerformanceLevel::Efficiency);
+    assert_eq!(topology.cluster_of(9).unwrap().kind, PerformanceLevel::Performance);
+    assert!(topology.cluster_of(10).is_none());
+}
+
+#[test]
+fn test_topology_from_device_tree() {
+    use PerformanceLevel::{Efficiency as E, Performance as P};
+
+    // M1 Ultra: each die numbers its efficiency cores first
+    let nodes: Vec<(usize, PerformanceLevel)> =
+        [E, E, P, P, P, P, E, E, P, P].into_iter().enumerate().rev().collect();
+    let core_kinds = core_kinds_from_nodes(&nodes).unwrap();
+    let levels = [perf_level("Performance", 6), perf_level("Efficiency", 4)];
+    let topology = CpuTopology::from_levels(&levels, Some(&core_kinds));
+
+    assert_eq!(topology.clusters()[0].cores, [2, 3, 4, 5, 8, 9]);
+    assert_eq!(topology.clusters()[1].cores, [0, 1, 6, 7]);
+
+    // A device tree disagreeing with the performance levels is ignored
+    let levels = [perf_level("Performance", 8), perf_level("Efficiency", 2)];
+    let topology = CpuTopology::from_levels(&levels, Some(&core_kinds));
+    assert_eq!(topology.clusters()[1].cores, [0, 1]);
+}
+
+#[test]
+fn test_core_kinds_from_nodes() {
+    assert_eq!(cluster_type_kind(b"E\0"), Some(PerformanceLevel::Efficiency));
+    assert_eq!(cluster_type_kind(b"P\0"), Some(PerformanceLevel::Performance));
+    assert_eq!(cluster_type_kind(b"X"), None);
+    assert_eq!(cluster_type_kind(b""), None);
+
+    assert_eq!(core_kinds_from_nodes(&[]), None);
+    // CPU 1 is missing
+    assert_eq!(
+        core_kinds_from_nodes(&[
+            (0, PerformanceLevel::Efficiency),
+            (2, PerformanceLevel::Performance)
+        ]),
+        None
+    );
+    // CPU 0 is listed twice
+    assert_eq!(
+        core_kinds_from_nodes(&[
+            (0, PerformanceLevel::Efficiency),
+            (0, PerformanceLevel::Performance)
+        ]),
+        None
+    );
+}
+
+#[test]
+fn test_topology_level_kinds() {
+    // Level names that aren't known fall back to the level order
+    let levels = [perf_level("Fast", 4), perf_level("Slow", 4)];
+    let topology = CpuTopology::from_levels(&levels, None);
+    assert_eq!(topology.clusters()[0].kind, PerformanceLevel::Performance);
+    assert_eq!(topology.clusters()[1].kind, PerformanceLevel::Efficiency);
+
+    let topology = CpuTopology::uniform(8, 4);
+    assert!(!topology.is_heterogeneous());
+    assert_eq!(topology.clusters()[0].kind, PerformanceLevel::Uniform);
+    assert_eq!(topology.clusters()[0].cores.len(), 8);
+}
+
+#[test]
+fn test_cluster_usage() {
+    let cpu = CPU::new_with_mock().expect("Failed to create CPU instance");
+    // core_usage: 0.3, 0.5 on the efficiency cores, 0.2, 0.8, 0.1, 0.3, 0.4, 0.6 on the performance cores
+    let usage = cpu.cluster_usage();
+
+    assert_eq!(usage.len(), 2);
+    assert_eq!(usage[0].kind, PerformanceLevel::Performance);
+    assert_eq!(usage[0].core_usages, [0.2, 0.8, 0.1, 0.3, 0.4, 0.6]);
+    assert!((usage[0].usage - 0.4).abs() < 1e-9);
+    assert_eq!(usage[1].kind, PerformanceLevel::Efficiency);
+    assert_eq!(usage[1].core_usages, [0.3, 0.5]);
+    assert!((usage[1].usage - 0.4).abs() < 1e-9);
+}
+
+#[test]
+fn test_cluster_usage_with_missing_cores() {
+    let topology = CpuTopology::from_levels(
+        &[perf_level("Performance", 2), perf_level("Efficiency", 2)],
+        None,
+    );
+    let usage = topology.usage(&[0.5, 0.7, 0.1]);
+
+    assert_eq!(usage[0].core_usages, [0.1]);
+    assert_eq!(usage[0].usage, 0.1);
+    assert_eq!(usage[1].core_usages, [0.5, 0.7]);
+
+    let usage = topology.usage(&[]);
+    assert!(usage.iter().all(|cluster| cluster.usage == 0.0 && cluster.core_usages.is_empty()));
+}
+
+#[test]
+fn test_detect_topology() {
+    let topology = CpuTopology::detect().unwrap();
+    let cores: usize = topology.clusters().iter().map(|cluster| cluster.cores.len()).sum();
+    let logical_cores = crate::init::system_info().unwrap().logical_cores as usize;
+
+    assert_eq!(cores, logical_cores, "Every logical CPU belongs to one cluster");
+    for core in 0..logical_cores {
+        assert!(topology.cluster_of(core).is_some());
+    }
+}
+
+/// A DVFS table entry as the device tree stores it
+fn dvfs_entry(frequency: u32, voltage: u32) -> [u8; 8] {
+    let mut entry = [0u8; 8];
+    entry[..4].copy_from_slice(&frequency.to_le_bytes());
+    entry[4..].copy_from_slice(&voltage.to_le_bytes());
+    entry
+}
+
+fn residency(kind: PerformanceLevel, inactive: i64, active: &[i64]) -> ClusterResidency {
+    ClusterResidency { name: "PCPU".to_string(), kind, inactive, active: active.to_vec() }
+}
+
+#[test]
+fn test_parse_dvfs_table() {
+    // Frequencies in Hz, with an unused entry
+    let table =
+        [dvfs_entry(600_000_000, 800), dvfs_entry(0, 0), dvfs_entry(3_228_000_000, 1100)].concat();
+    assert_eq!(parse_dvfs_table(&table), [600.0, 3228.0]);
+
+    // M4 stores kHz
+    let table = [dvfs_entry(1_020_000, 800), dvfs_entry(4_512_000, 1100)].concat();
+    assert_eq!(parse_dvfs_table(&table), [1020.0, 4512.0]);
+
+    // A trailing partial entry is ignored
+    assert_eq!(parse_dvfs_table(&dvfs_entry(600_000_000, 800)[..6]), Vec::<f64>::new());
+}
+
+#[test]
+fn test_channel_kind() {
+    assert_eq!(channel_kind("ECPU"), Some(PerformanceLevel::Efficiency));
+    assert_eq!(channel_kind("PCPU1"), Some(PerformanceLevel::Performance));
+    assert_eq!(channel_kind("EACC_CPU"), Some(PerformanceLevel::Efficiency));
+    assert_eq!(channel_kind("PACC0_CPU"), Some(PerformanceLevel::Performance));
+    assert_eq!(channel_kind("GPUPH"), None);
+}
+
+#[test]
+fn test_split_states() {
+    let states: Vec<(String, i64)> =
+        [("IDLE", 50), ("DOWN", 10), ("V0P5", 20), ("V1P4", -5), ("V2P3", 30)]
+            .iter()
+            .map(|&(name, residency)| (name.to_string(), residency))
+            .collect();
+
+    assert_eq!(split_states(&states), (60, vec![20, 0, 30]));
+}
+
+#[test]
+fn test_cluster_frequency() {
+    let table = [600.0, 1200.0, 2400.0];
+    let frequency =
+        cluster_frequency(&residency(PerformanceLevel::Performance, 50, &[10, 0, 40]), &table)
+            .unwrap();
+
+    assert!((frequency.current - 2040.0).abs() < 1e-9);
+    assert_eq!(frequency.min, 600.0);
+    assert_eq!(frequency.max, 2400.0);
+    assert!((frequency.active_residency - 0.5).abs() < 1e-9);
+
+    // A cluster that didn't run reports its lowest frequency
+    let idle = cluster_frequency(&residency(PerformanceLevel::Efficiency, 100, &[0, 0, 0]), &table)
+        .unwrap();
+    assert_eq!(idle.current, 600.0);
+    assert_eq!(idle.active_residency, 0.0);
+
+    // States beyond the table are ignored, and a cluster without a table has no frequency
+    let extra =
+        cluster_frequency(&residency(PerformanceLevel::Performance, 0, &[0, 0, 10, 90]), &table)
+            .unwrap();
+    assert_eq!(extra.current, 2400.0);
+    assert!(cluster_frequency(&residency(PerformanceLevel::Performance, 0, &[10]), &[]).is_none());
+}
+
+#[test]
+fn test_combine_clusters() {
+    let tables = DvfsTables { efficiency: vec![600.0, 1200.0], performance: vec![600.0, 3000.0] };
+    let efficiency = cluster_frequency(
+        &residency(PerformanceLevel::Efficiency, 0, &[0, 100]),
+        &tables.efficiency,
+    )
+    .unwrap();
+    let performance = cluster_frequency(
+        &residency(PerformanceLevel::Performance, 75, &[0, 25]),
+        &tables.performance,
+    )
+    .unwrap();
+
+    let metrics = combine_clusters(&[efficiency.clone(), performance.clone()], &tables).unwrap();
+    // 1200 MHz for the whole interval and 3000 MHz for a quarter of it
+    assert!((metrics.current - 1560.0).abs() < 1e-9);
+    assert_eq!(metrics.min, 600.0);
+    assert_eq!(metrics.max, 3000.0);
+    assert_eq!(metrics.available, [600.0, 1200.0, 3000.0]);
+
+    let idle = ClusterFrequency { active_residency: 0.0, ..performance };
+    assert_eq!(combine_clusters(&[idle], &tables).unwrap().current, 600.0);
+    assert!(combine_clusters(&[], &tables).is_none());
+}
+
+#[test]
+fn test_cluster_frequencies() {
+    let monitor = FrequencyMonitor::new();
+    if let Ok(clusters) = monitor.cluster_frequencies() {
+        for cluster in clusters {
+            assert!(cluster.min > 0.0 && cluster.min <= cluster.max);
+            assert!(cluster.current >= cluster.min && cluster.current <= cluster.max);
+            assert!((0.0..=1.0).contains(&cluster.active_residency));
+        }
+    }
+}
+
+#[test]
+fn test_per_core_load() {
+    let load = LoadAverage { one: 4.0, five: 2.0, fifteen: 1.0, runnable_tasks: Some(8.0) };
+
+    assert_eq!(
+        load.per_core(8),
+        LoadAverage { one: 0.5, five: 0.25, fifteen: 0.125, runnable_tasks: Some(1.0) }
+    );
+    assert_eq!(load.per_core(0), load, "No cores leaves the load unchanged");
+
+    let without_tasks = LoadAverage { runnable_tasks: None, ..load };
+    assert_eq!(without_tasks.per_core(4).runnable_tasks, None);
+}
+
+#[test]
+fn test_load_average() {
+    let load = super::load_average().unwrap();
+    assert!(load.one >= 0.0 && load.five >= 0.0 && load.fifteen >= 0.0);
+    if let Some(tasks) = load.runnable_tasks {
+        assert!(tasks >= 0.0);
+    }
+
+    let cpu = CPU::new_with_mock().unwrap();
+    let per_core = cpu.per_core_load().unwrap();
+    assert!(per_core.one >= 0.0);
+}
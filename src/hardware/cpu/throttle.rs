@@ -0,0 +1,258 @@
+use objc2::{class, msg_send, rc::autoreleasepool, runtime::AnyObject};
+
+use super::{FrequencyMetrics, FrequencyMonitor};
+use crate::{
+    error::Result,
+    hardware::iokit::{IOKit, IOKitImpl},
+};
+
+/// Frequency ratio (current / max) below which the CPU is considered to be running slow.
+pub const SLOW_FREQUENCY_RATIO: f64 = 0.85;
+
+/// Average CPU usage (0.0 to 1.0) below which a low frequency is attributed to the CPU being idle.
+pub const IDLE_CPU_USAGE: f64 = 0.2;
+
+/// Registry property on the AppleSmartBattery service that is set while external power is connected.
+const EXTERNAL_CONNECTED: &str = "ExternalConnected";
+
+/// System thermal state as reported by `NSProcessInfo.thermalState`.
+///
+/// The variants are ordered by severity, so `state >= ThermalState::Serious` can be used to check for significant
+/// thermal pressure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ThermalState {
+    /// No corrective action is needed
+    Nominal,
+    /// The system is slightly elevated; fans may be audible
+    Fair,
+    /// The system is reducing performance to cool down
+    Serious,
+    /// The system is shedding performance aggressively to avoid damage
+    Critical,
+}
+
+impl ThermalState {
+    /// Converts the raw `NSProcessInfoThermalState` value into a `ThermalState`.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<ThermalState>` - The thermal state, or None for values unknown to this version of the crate
+    pub fn from_raw(value: isize) -> Option<Self> {
+        match value {
+            0 => Some(Self::Nominal),
+            1 => Some(Self::Fair),
+            2 => Some(Self::Serious),
+            3 => Some(Self::Critical),
+            _ => None,
+        }
+    }
+
+    /// Returns the thermal state of the system as seen by the current process.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<ThermalState>` - The current thermal state, or None if it couldn't be determined
+    pub fn current() -> Option<Self> {
+        autoreleasepool(|_| unsafe {
+            let info: *mut AnyObject = msg_send![class!(NSProcessInfo), processInfo];
+            if info.is_null() {
+                return None;
+            }
+
+            let state: isize = msg_send![info, thermalState];
+            Self::from_raw(state)
+        })
+    }
+}
+
+/// The most likely reason the CPU is running below its maximum frequency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleCause {
+    /// The system is too hot and is limiting performance
+    Thermal,
+    /// The CPU is busy and cool but held back by a power limit (typically on battery)
+    PowerLimit,
+    /// The CPU has clocked down because there is little work to do
+    Idle,
+}
+
+/// The raw signals used to classify CPU throttling.
+///
+/// Every signal is optional because which ones are available depends on the architecture:
+///
+/// * **Intel** - the SMC `PCTC` key and the `hw.cpufrequency` sysctls are generally available, so all signals can be
+///   populated and the classification is reasonably accurate.
+/// * **Apple Silicon** - `hw.cpufrequency` doesn't exist and the SMC throttle key is usually absent, leaving the
+///   `NSProcessInfo` thermal state as the main signal. Thermal throttling is still detected, but power-limit and idle
+///   classification require frequency information and will mostly be unavailable.
+///
+/// The fields are public so consumers can apply their own logic instead of [`classify`](Self::classify).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ThrottleSignals {
+    /// Whether the SMC throttle key reports active throttling
+    pub smc_throttling: Option<bool>,
+    /// The system thermal state from `NSProcessInfo`
+    pub thermal_state: Option<ThermalState>,
+    /// Current frequency divided by the maximum frequency
+    pub frequency_ratio: Option<f64>,
+    /// Average CPU usage between 0.0 and 1.0
+    pub cpu_usage: Option<f64>,
+    /// Whether the system is running on battery power
+    pub on_battery: Option<bool>,
+}
+
+impl ThrottleSignals {
+    /// Collects the SMC throttle flag from the given IOKit implementation and the system thermal state.
+    ///
+    /// Frequency, usage, and power source information can be added with the `with_*` methods.
+    pub fn from_iokit(iokit: &dyn IOKit) -> Self {
+        Self {
+            smc_throttling: iokit.check_thermal_throttling().ok(),
+            thermal_state: ThermalState::current(),
+            ..Self::default()
+        }
+    }
+
+    /// Adds the frequency ratio derived from the given frequency metrics.
+    pub fn with_frequency(mut self, metrics: Option<&FrequencyMetrics>) -> Self {
+        self.frequency_ratio = metrics.and_then(|m| {
+            if m.max > 0.0 && m.current > 0.0 {
+                Some(m.current / m.max)
+            } else {
+                None
+            }
+        });
+        self
+    }
+
+    /// Adds the average CPU usage (0.0 to 1.0).
+    pub fn with_cpu_usage(mut self, usage: Option<f64>) -> Self {
+        self.cpu_usage = usage.filter(|u| u.is_finite());
+        self
+    }
+
+    /// Adds whether the system is running on battery power.
+    pub fn with_on_battery(mut self, on_battery: Option<bool>) -> Self {
+        self.on_battery = on_battery;
+        self
+    }
+
+    /// Whether any thermal signal indicates thermal pressure, or None if no thermal signal is available.
+    pub fn thermal_pressure(&self) -> Option<bool> {
+        if self.smc_throttling.is_none() && self.thermal_state.is_none() {
+            return None;
+        }
+
+        Some(
+            self.smc_throttling == Some(true)
+                || self.thermal_state.is_some_and(|state| state >= ThermalState::Serious),
+        )
+    }
+
+    /// Whether the CPU is running noticeably below its maximum frequency, or None if unknown.
+    pub fn is_slow(&self) -> Option<bool> {
+        self.frequency_ratio.map(|ratio| ratio < SLOW_FREQUENCY_RATIO)
+    }
+
+    /// Whether the CPU is mostly idle, or None if unknown.
+    pub fn is_idle(&self) -> Option<bool> {
+        self.cpu_usage.map(|usage| usage < IDLE_CPU_USAGE)
+    }
+
+    /// Classifies the signals into a best-effort throttle reason.
+    ///
+    /// The classification works as follows:
+    ///
+    /// * Running at speed is never reported as throttled, even under thermal pressure.
+    /// * Slow and hot is attributed to thermal throttling. Hot with unknown frequency is also reported as thermal.
+    /// * Slow, cool, and idle is attributed to the CPU idling.
+    /// * Slow, cool, and either busy or on battery is attributed to a power limit.
+    /// * Everything else is left undetermined.
+    pub fn classify(&self) -> ThrottleReasons {
+        let hot = self.thermal_pressure();
+        let slow = self.is_slow();
+        let idle = self.is_idle();
+
+        let estimated_dominant = match (slow, hot) {
+            (Some(false), _) => None,
+            (Some(true), Some(true)) | (None, Some(true)) => Some(ThrottleCause::Thermal),
+            (Some(true), _) if idle == Some(true) => Some(ThrottleCause::Idle),
+            (Some(true), Some(false)) if self.on_battery == Some(true) || idle == Some(false) => {
+                Some(ThrottleCause::PowerLimit)
+            },
+            _ => None,
+        };
+
+        let power_limit = match slow {
+            Some(false) => Some(false),
+            Some(true) => estimated_dominant.map(|cause| cause == ThrottleCause::PowerLimit),
+            None => None,
+        };
+
+        ThrottleReasons { thermal: hot, power_limit, estimated_dominant, signals: self.clone() }
+    }
+}
+
+/// Why the CPU is (or isn't) running below its maximum frequency.
+///
+/// This is a best-effort classification; see [`ThrottleSignals`] for per-architecture accuracy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThrottleReasons {
+    /// Whether the system is under thermal pressure, or None if no thermal signal is available
+    pub thermal: Option<bool>,
+    /// Whether a power limit is holding the CPU back, or None if it can't be determined
+    pub power_limit: Option<bool>,
+    /// The most likely limiter, or None if the CPU isn't throttled or the cause can't be determined
+    pub estimated_dominant: Option<ThrottleCause>,
+    /// The underlying signals the classification was based on
+    pub signals: ThrottleSignals,
+}
+
+/// Returns whether the system runs on battery power by reading the AppleSmartBattery registry entry.
+///
+/// Returns None on machines without a battery or when the registry entry can't be read.
+pub(crate) fn on_battery_power(iokit: &dyn IOKit) -> Option<bool> {
+    let matching = iokit.io_service_matching("AppleSmartBattery");
+    let service = iokit.io_service_get_matching_service(&matching)?;
+    let properties = iokit.io_registry_entry_create_cf_properties(&service).ok()?;
+    iokit.get_bool_property(&properties, EXTERNAL_CONNECTED).map(|external| !external)
+}
+
+/// Collects the SMC throttle key and thermal state through `iokit`, the current frequency, the power source, and the
+/// given average CPU usage.
+pub(crate) fn system_signals(iokit: &dyn IOKit, cpu_usage: Option<f64>) -> ThrottleSignals {
+    let frequency = FrequencyMonitor::new().get_metrics().ok();
+
+    ThrottleSignals::from_iokit(iokit)
+        .with_frequency(frequency.as_ref())
+        .with_cpu_usage(cpu_usage)
+        .with_on_battery(on_battery_power(iokit))
+}
+
+/// Reports why the CPU is running below its maximum frequency.
+///
+/// Combines the SMC throttle key, the `NSProcessInfo` thermal state, the frequency-vs-max gap, and the power source
+/// into a best-effort classification. Each underlying signal is exposed in [`ThrottleReasons::signals`].
+///
+/// # Returns
+///
+/// * `Result<ThrottleReasons>` - The classification; signals that couldn't be read are None
+///
+/// # Errors
+///
+/// Currently never fails; unavailable signals are reported as None instead.
+///
+/// # Example
+///
+/// ```no_run
+/// use darwin_metrics::hardware::cpu::{throttle_reasons, ThrottleCause};
+///
+/// let reasons = throttle_reasons().unwrap();
+/// if reasons.estimated_dominant == Some(ThrottleCause::Thermal) {
+///     println!("CPU is thermally throttled");
+/// }
+/// ```
+pub fn throttle_reasons() -> Result<ThrottleReasons> {
+    Ok(system_signals(&IOKitImpl::default(), None).classify())
+}
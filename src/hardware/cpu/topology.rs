@@ -0,0 +1,316 @@
+//! Performance and efficiency clusters of the CPU.
+//!
+//! macOS describes the core types of Apple Silicon as performance levels: `hw.nperflevels` counts them, and
+//! `hw.perflevelN.{name,logicalcpu,physicalcpu,l2cachesize}` describe each, level 0 being the fastest. Which logical
+//! CPUs belong to a level comes from the `cluster-type` property of the CPU nodes in the device tree. Without it the
+//! efficiency cores are assumed to have the lowest CPU numbers, which is how XNU numbers the cores of single-die chips.
+//!
+//! Intel Macs, and macOS versions without performance levels, get a single cluster holding every core.
+
+use std::ffi::CString;
+
+use objc2::rc::autoreleasepool;
+use objc2_foundation::{NSData, NSString};
+
+use crate::{
+    error::Result,
+    hardware::iokit::{IOKit, IOKitImpl},
+    init::{sysctl_string, sysctl_value},
+    utils::bindings::{
+        IOIteratorNext, IOObjectRelease, IORegistryEntryFromPath, IORegistryEntryGetChildIterator,
+    },
+};
+
+/// Registry path of the parent of the CPU nodes
+const CPUS_PATH: &str = "IODeviceTree:/cpus";
+/// Registry plane of the device tree
+const DEVICE_TREE_PLANE: &str = "IODeviceTree";
+/// CPU node property holding "E" or "P"
+const CLUSTER_TYPE_KEY: &str = "cluster-type";
+/// CPU node property holding the CPU number the kernel uses
+const LOGICAL_CPU_ID_KEY: &str = "logical-cpu-id";
+
+/// Type of the cores of a cluster
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PerformanceLevel {
+    /// Performance cores (P-cores) of Apple Silicon
+    Performance,
+    /// Efficiency cores (E-cores) of Apple Silicon
+    Efficiency,
+    /// Cores of a CPU with a single core type, such as an Intel CPU
+    Uniform,
+}
+
+/// A group of cores of the same type
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuCluster {
+    /// Type of the cores
+    pub kind: PerformanceLevel,
+    /// Name macOS gives the performance level, e.g. "Efficiency"
+    pub name: String,
+    /// Logical CPU numbers of the cores, the indices into [`CPU::core_usage`](super::CPU::core_usage)
+    pub cores: Vec<usize>,
+    /// Number of physical cores
+    pub physical_cores: u32,
+    /// L2 cache size in bytes, None if macOS doesn't report it
+    pub l2_cache_bytes: Option<u64>,
+}
+
+/// Usage of the cores of one cluster
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClusterUsage {
+    /// Type of the cores
+    pub kind: PerformanceLevel,
+    /// Average usage of the cores, between 0.0 and 1.0
+    pub usage: f64,
+    /// Usage of each core, in the order of [`CpuCluster::cores`]
+    pub core_usages: Vec<f64>,
+}
+
+/// A performance level as `hw.perflevelN` describes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PerfLevel {
+    pub name: String,
+    pub logical_cores: u32,
+    pub physical_cores: u32,
+    pub l2_cache_bytes: Option<u64>,
+}
+
+/// The clusters of the CPU, fastest first
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuTopology {
+    clusters: Vec<CpuCluster>,
+}
+
+impl CpuTopology {
+    /// Reads the performance levels of the CPU and the cores that belong to each.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not even the number of cores can be read.
+    pub fn detect() -> Result<Self> {
+        match read_perf_levels() {
+            Some(levels) if levels.len() > 1 => {
+                Ok(Self::from_levels(&levels, device_tree_core_kinds().as_deref()))
+            },
+            _ => Ok(Self::uniform(
+                sysctl_value::<i32>("hw.logicalcpu")? as u32,
+                sysctl_value::<i32>("hw.physicalcpu")? as u32,
+            )),
+        }
+    }
+
+    /// A single cluster of `logical_cores` cores of the same type.
+    pub(crate) fn uniform(logical_cores: u32, physical_cores: u32) -> Self {
+        Self {
+            clusters: vec![CpuCluster {
+                kind: PerformanceLevel::Uniform,
+                name: "CPU".to_string(),
+                cores: (0..logical_cores as usize).collect(),
+                physical_cores,
+                l2_cache_bytes: None,
+            }],
+        }
+    }
+
+    /// Builds the clusters of `levels`, fastest first, taking the cores of each from `core_kinds`, the type of each
+    /// logical CPU, where it agrees with the levels.
+    pub(crate) fn from_levels(
+        levels: &[PerfLevel],
+        core_kinds: Option<&[PerformanceLevel]>,
+    ) -> Self {
+        let kinds: Vec<PerformanceLevel> = levels
+            .iter()
+            .enumerate()
+            .map(|(index, level)| level_kind(index, levels.len(), level))
+            .collect();
+
+        // The device tree only decides if it lists as many cores of each type as the levels
+        let core_kinds = core_kinds.filter(|core_kinds| {
+            levels.iter().zip(&kinds).all(|(level, kind)| {
+                core_kinds.iter().filter(|core_kind| *core_kind == kind).count()
+                    == level.logical_cores as usize
+            })
+        });
+
+        let cores: Vec<Vec<usize>> = match core_kinds {
+            Some(core_kinds) => kinds
+                .iter()
+                .map(|kind| {
+                    (0..core_kinds.len()).filter(|&core| core_kinds[core] == *kind).collect()
+                })
+                .collect(),
+            None => {
+                // The slowest level has the lowest CPU numbers
+                let mut next = 0;
+                let mut cores: Vec<Vec<usize>> = levels
+                    .iter()
+                    .rev()
+                    .map(|level| {
+                        let start = next;
+                        next += level.logical_cores as usize;
+                        (start..next).collect()
+                    })
+                    .collect();
+                cores.reverse();
+                cores
+            },
+        };
+
+        let clusters = levels
+            .iter()
+            .zip(kinds)
+            .zip(cores)
+            .map(|((level, kind), cores)| CpuCluster {
+                kind,
+                name: level.name.clone(),
+                cores,
+                physical_cores: level.physical_cores,
+                l2_cache_bytes: level.l2_cache_bytes,
+            })
+            .collect();
+        Self { clusters }
+    }
+
+    /// The clusters, fastest first.
+    pub fn clusters(&self) -> &[CpuCluster] {
+        &self.clusters
+    }
+
+    /// The cluster the logical CPU `core` belongs to.
+    pub fn cluster_of(&self, core: usize) -> Option<&CpuCluster> {
+        self.clusters.iter().find(|cluster| cluster.cores.contains(&core))
+    }
+
+    /// Whether the CPU has cores of different types.
+    pub fn is_heterogeneous(&self) -> bool {
+        self.clusters.len() > 1
+    }
+
+    /// Groups the usage of each logical CPU, indexed by CPU number, by cluster.
+    ///
+    /// Cores missing from `core_usage` are left out; a cluster without any core in it has a usage of 0.0.
+    pub fn usage(&self, core_usage: &[f64]) -> Vec<ClusterUsage> {
+        self.clusters
+            .iter()
+            .map(|cluster| {
+                let core_usages: Vec<f64> = cluster
+                    .cores
+                    .iter()
+                    .filter_map(|&core| core_usage.get(core).copied())
+                    .collect();
+                let usage = if core_usages.is_empty() {
+                    0.0
+                } else {
+                    core_usages.iter().sum::<f64>() / core_usages.len() as f64
+                };
+                ClusterUsage { kind: cluster.kind, usage, core_usages }
+            })
+            .collect()
+    }
+}
+
+/// The core type of level `index` out of `count`, by its name or else its position.
+fn level_kind(index: usize, count: usize, level: &PerfLevel) -> PerformanceLevel {
+    if count <= 1 {
+        return PerformanceLevel::Uniform;
+    }
+    match level.name.as_str() {
+        "Performance" => PerformanceLevel::Performance,
+        "Efficiency" => PerformanceLevel::Efficiency,
+        _ if index == 0 => PerformanceLevel::Performance,
+        _ => PerformanceLevel::Efficiency,
+    }
+}
+
+/// Reads the performance levels, None before macOS 12 or if they can't be read.
+fn read_perf_levels() -> Option<Vec<PerfLevel>> {
+    let count = sysctl_value::<i32>("hw.nperflevels").ok()?;
+    (0..count)
+        .map(|index| {
+            let key = |name: &str| format!("hw.perflevel{index}.{name}");
+            Some(PerfLevel {
+                name: sysctl_string(&key("name")).unwrap_or_default(),
+                logical_cores: sysctl_value::<i32>(&key("logicalcpu")).ok()? as u32,
+                physical_cores: sysctl_value::<i32>(&key("physicalcpu")).ok()? as u32,
+                l2_cache_bytes: sysctl_value::<i32>(&key("l2cachesize"))
+                    .ok()
+                    .and_then(|size| u64::try_from(size).ok()),
+            })
+        })
+        .collect()
+}
+
+/// The core type of a CPU node from its `cluster-type`.
+pub(crate) fn cluster_type_kind(cluster_type: &[u8]) -> Option<PerformanceLevel> {
+    match cluster_type.first()? {
+        b'P' => Some(PerformanceLevel::Performance),
+        b'E' => Some(PerformanceLevel::Efficiency),
+        _ => None,
+    }
+}
+
+/// The core type of every logical CPU from the CPU numbers and types of the CPU nodes.
+///
+/// Returns None unless every CPU number up to the highest has exactly one node.
+pub(crate) fn core_kinds_from_nodes(
+    nodes: &[(usize, PerformanceLevel)],
+) -> Option<Vec<PerformanceLevel>> {
+    let mut kinds = vec![None; nodes.iter().map(|&(cpu, _)| cpu + 1).max()?];
+    for &(cpu, kind) in nodes {
+        if kinds[cpu].replace(kind).is_some() {
+            return None;
+        }
+    }
+    kinds.into_iter().collect()
+}
+
+/// Reads the core type of every logical CPU from the device tree, None on Intel Macs.
+fn device_tree_core_kinds() -> Option<Vec<PerformanceLevel>> {
+    let path = CString::new(CPUS_PATH).ok()?;
+    let plane = CString::new(DEVICE_TREE_PLANE).ok()?;
+    let io_kit = IOKitImpl::default();
+
+    let nodes = autoreleasepool(|_| unsafe {
+        let cpus = IORegistryEntryFromPath(0, path.as_ptr());
+        if cpus == 0 {
+            return Vec::new();
+        }
+
+        let mut nodes = Vec::new();
+        let mut children = 0u32;
+        if IORegistryEntryGetChildIterator(cpus, plane.as_ptr(), &mut children) == 0 {
+            loop {
+                let child = IOIteratorNext(children);
+                if child == 0 {
+                    break;
+                }
+                nodes.extend(cpu_node(&io_kit, child));
+                IOObjectRelease(child);
+            }
+            IOObjectRelease(children);
+        }
+        IOObjectRelease(cpus);
+        nodes
+    });
+
+    core_kinds_from_nodes(&nodes)
+}
+
+/// Reads the CPU number and core type of the CPU node with the registry handle `entry`.
+fn cpu_node(io_kit: &dyn IOKit, entry: u32) -> Option<(usize, PerformanceLevel)> {
+    let properties = io_kit.io_registry_entry_properties(entry).ok()?;
+    let bytes = |key: &str| {
+        let value = properties.valueForKey(&NSString::from_str(key))?;
+        Some(value.downcast::<NSData>().ok()?.to_vec())
+    };
+
+    let kind = cluster_type_kind(&bytes(CLUSTER_TYPE_KEY)?)?;
+    let cpu = bytes(LOGICAL_CPU_ID_KEY)?;
+    let cpu = u32::from_le_bytes(cpu.get(..4)?.try_into().ok()?);
+    Some((cpu as usize, kind))
+}
@@ -0,0 +1,98 @@
+use std::{collections::VecDeque, time::Instant};
+
+use super::CPU;
+
+/// Usage of every core at one point in time, one value between 0.0 and 1.0 per core
+pub type CoreUsageSample = (Instant, Vec<f64>);
+
+/// A bounded history of per-core CPU usage, e.g. for drawing a sparkline per core
+///
+/// The history is pull-based: call [`record`](CpuUsageHistory::record) after each [`CPU::update`]. Samples don't need
+/// to report the same number of cores; when cores are parked or come back between updates, each sample keeps the cores
+/// it saw and [`history_for_core`](CpuUsageHistory::history_for_core) skips the samples a core is missing from.
+///
+/// ```rust,no_run
+/// use std::{thread::sleep, time::Duration};
+///
+/// use darwin_metrics::hardware::cpu::{CpuUsageHistory, CPU};
+///
+/// fn main() -> darwin_metrics::error::Result<()> {
+///     let mut cpu = CPU::new()?;
+///     let mut history = CpuUsageHistory::new(60);
+///
+///     for _ in 0..10 {
+///         cpu.update()?;
+///         history.record(&cpu);
+///         sleep(Duration::from_secs(1));
+///     }
+///
+///     let core0: Vec<f64> = history.history_for_core(0).into_iter().map(|(_, usage)| usage).collect();
+///     println!("Core 0: {:?}", core0);
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CpuUsageHistory {
+    samples: VecDeque<CoreUsageSample>,
+    capacity: usize,
+}
+
+impl CpuUsageHistory {
+    /// Creates a history holding up to `capacity` samples, at least one.
+    pub fn new(capacity: usize) -> Self {
+        Self { samples: VecDeque::with_capacity(capacity.min(1024)), capacity: capacity.max(1) }
+    }
+
+    /// Records the per-core usage of the last [`CPU::update`], timestamped with the current time.
+    pub fn record(&mut self, cpu: &CPU) {
+        self.record_usage(Instant::now(), cpu.core_usage());
+    }
+
+    /// Records the usage of each core at `at`, evicting the oldest sample if the history is full.
+    pub fn record_usage(&mut self, at: Instant, core_usage: &[f64]) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((at, core_usage.to_vec()));
+    }
+
+    /// The samples in the order they were recorded.
+    pub fn samples(&self) -> &VecDeque<CoreUsageSample> {
+        &self.samples
+    }
+
+    /// The usage of core `index` over time, skipping the samples that didn't report the core.
+    pub fn history_for_core(&self, index: usize) -> Vec<(Instant, f64)> {
+        self.samples.iter().filter_map(|(at, usage)| Some((*at, *usage.get(index)?))).collect()
+    }
+
+    /// The most recent sample.
+    pub fn latest(&self) -> Option<&CoreUsageSample> {
+        self.samples.back()
+    }
+
+    /// Largest number of cores reported by any sample held.
+    pub fn max_cores(&self) -> usize {
+        self.samples.iter().map(|(_, usage)| usage.len()).max().unwrap_or(0)
+    }
+
+    /// Removes every sample.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Number of samples held.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Whether the history holds no samples.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Maximum number of samples held.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
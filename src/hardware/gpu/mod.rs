@@ -1,19 +1,25 @@
-use std::os::raw::c_void;
+use std::{os::raw::c_void, sync::Arc, time::Duration};
 
 use objc2::{msg_send, rc::autoreleasepool, runtime::AnyObject};
 
-// Only import Error when not in coverage mode
-#[cfg(not(feature = "skip-ffi-crashes"))]
-use crate::error::Error;
-
 use crate::{
     error::Result,
-    utils::bindings::{MTLCreateSystemDefaultDevice, MTLDeviceRef},
+    hardware::iokit::{
+        pci_device, registry_id_matching, GpuProcessStat, IOKit, IOKitImpl, PciDevice,
+    },
+    utils::bindings::{MTLCopyAllDevices, MTLCreateSystemDefaultDevice, MTLDeviceRef},
 };
 
+mod power_state;
+mod sampler;
+
+pub use power_state::{GpuDriverFamily, GpuDvfsState, GpuIdleMonitor, GpuPowerState};
+pub use sampler::{GpuSample, GpuSampler, DEFAULT_HISTORY_CAPACITY};
+
 // Simplified GPU module with minimal IOKit interactions and direct Metal framework usage for better safety
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GpuMemoryInfo {
     pub total: u64,
     pub used: u64,
@@ -21,6 +27,7 @@ pub struct GpuMemoryInfo {
 }
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GpuMetrics {
     pub utilization: f32,
     pub memory: GpuMemoryInfo,
@@ -31,6 +38,7 @@ pub struct GpuMetrics {
 
 /// Holds information about GPU characteristics
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GpuCharacteristics {
     /// Is this an integrated GPU (vs discrete)
     pub is_integrated: bool,
@@ -45,14 +53,23 @@ pub struct GpuCharacteristics {
 }
 
 // Simplified GPU implementation that uses only the most reliable APIs
+/// A GPU, bound to one Metal device and the IORegistry accelerator entry behind it
+///
+/// [`Gpu::new`] returns the system's default device, which on Macs with an integrated and a discrete GPU is the
+/// discrete one; [`Gpu::enumerate`] returns every device.
 #[derive(Debug)]
 pub struct Gpu {
     metal_device: Option<MTLDeviceRef>,
-    // Note: Support for multiple GPUs will be added in a future version (post-1.0.0) This field would become a
-    // Vec<MTLDeviceRef> or similar to track all available GPUs
+    /// ID of the accelerator entry of the device in the IORegistry
+    registry_id: Option<u64>,
+    /// Reads the temperature from the SMC and the GPU time of each process
+    iokit: Arc<dyn IOKit>,
 }
 
 impl Gpu {
+    /// Creates a monitor for the system's default GPU.
+    ///
+    /// Without Metal, the monitor still works but falls back to the first accelerator in the IORegistry.
     pub fn new() -> Result<Self> {
         // Create a Metal device for GPU info
         let metal_device = autoreleasepool(|_| unsafe {
@@ -64,7 +81,136 @@ impl Gpu {
             }
         });
 
-        Ok(Self { metal_device })
+        Ok(Self::from_device(metal_device))
+    }
+
+    /// Creates a monitor for every GPU Metal knows about, in the order Metal lists them.
+    ///
+    /// Each monitor reads the IORegistry accelerator entry of its own device. The list is empty if Metal is
+    /// unavailable.
+    pub fn enumerate() -> Result<Vec<Self>> {
+        let devices = autoreleasepool(|_| unsafe {
+            let array: *mut AnyObject = MTLCopyAllDevices().cast();
+            if array.is_null() {
+                return Vec::new();
+            }
+
+            let count: usize = msg_send![array, count];
+            let devices = (0..count)
+                .filter_map(|i| {
+                    let device: *mut AnyObject = msg_send![array, objectAtIndex: i];
+                    if device.is_null() {
+                        return None;
+                    }
+                    // Each Gpu releases its device when dropped, so it needs its own retain
+                    let _: *mut AnyObject = msg_send![device, retain];
+                    Some(device.cast::<c_void>())
+                })
+                .collect::<Vec<MTLDeviceRef>>();
+            let _: () = msg_send![array, release];
+            devices
+        });
+
+        Ok(devices.into_iter().map(|device| Self::from_device(Some(device))).collect())
+    }
+
+    fn from_device(metal_device: Option<MTLDeviceRef>) -> Self {
+        let registry_id = metal_device.and_then(|device| {
+            let id: u64 = unsafe { msg_send![device.cast::<AnyObject>(), registryID] };
+            (id != 0).then_some(id)
+        });
+
+        Self { metal_device, registry_id, iokit: Arc::new(IOKitImpl::default()) }
+    }
+
+    /// ID of the accelerator entry of the GPU in the IORegistry, or None without Metal.
+    pub fn registry_id(&self) -> Option<u64> {
+        self.registry_id
+    }
+
+    /// Whether the GPU is integrated with the CPU, i.e. shares the system memory instead of having its own.
+    pub fn is_integrated(&self) -> bool {
+        match self.device() {
+            Some(device) => self.has_unified_memory() || unsafe { msg_send![device, isLowPower] },
+            None => self.get_characteristics().is_integrated,
+        }
+    }
+
+    /// Whether the GPU has no display attached and can't drive one, like the compute GPUs of a Mac Pro.
+    pub fn is_headless(&self) -> bool {
+        self.device().is_some_and(|device| unsafe { msg_send![device, isHeadless] })
+    }
+
+    /// Whether the GPU shares the system memory, as on Apple Silicon and Intel integrated GPUs.
+    fn has_unified_memory(&self) -> bool {
+        match self.device() {
+            Some(device) => unsafe { msg_send![device, hasUnifiedMemory] },
+            None => cfg!(target_arch = "aarch64"),
+        }
+    }
+
+    fn device(&self) -> Option<*mut AnyObject> {
+        self.metal_device.map(|device| device.cast())
+    }
+
+    /// Reads the GPU temperature through `iokit`, e.g. one created with [`IOKitImpl::with_smc_connection`].
+    #[must_use]
+    pub fn with_iokit(mut self, iokit: IOKitImpl) -> Self {
+        self.iokit = Arc::new(iokit);
+        self
+    }
+
+    /// Reads through `iokit`, shared with the other monitors holding it.
+    #[must_use]
+    pub fn with_shared_iokit(mut self, iokit: Arc<dyn IOKit>) -> Self {
+        self.iokit = iokit;
+        self
+    }
+
+    /// Starts sampling the GPU every `interval` in the background, keeping the last [`DEFAULT_HISTORY_CAPACITY`]
+    /// samples.
+    ///
+    /// The sampler reads the same accelerator entry as this monitor, through the same IOKit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn start_sampling(&self, interval: Duration) -> GpuSampler {
+        self.start_sampling_with_capacity(interval, DEFAULT_HISTORY_CAPACITY)
+    }
+
+    /// Starts sampling the GPU every `interval` in the background, keeping the last `capacity` samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn start_sampling_with_capacity(&self, interval: Duration, capacity: usize) -> GpuSampler {
+        GpuSampler::spawn(Arc::clone(&self.iokit), self.registry_id, interval, capacity)
+    }
+
+    /// GPU time used by each process since it opened the GPU, ordered by GPU time, highest first.
+    ///
+    /// The times are cumulative, so usage over an interval is the difference between two calls. Processes whose driver
+    /// doesn't report GPU time, as on Intel and AMD GPUs, are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IORegistry can't be queried.
+    pub fn process_usage(&self) -> Result<Vec<GpuProcessStat>> {
+        self.iokit.get_gpu_process_stats()
+    }
+
+    /// PCI vendor and device IDs of the GPU, read from the PCI device its accelerator entry is attached through.
+    ///
+    /// None on Apple Silicon, whose GPU isn't on the PCI bus, or if the accelerator entry can't be found. Without
+    /// Metal, the first accelerator in the IORegistry is used.
+    pub fn pci_device(&self) -> Option<PciDevice> {
+        let matching = match self.registry_id {
+            Some(registry_id) => registry_id_matching(registry_id)?,
+            None => self.iokit.io_service_matching("IOAccelerator"),
+        };
+        let accelerator = self.iokit.io_service_get_matching_service(&matching)?;
+        pci_device(self.iokit.as_ref(), accelerator)
     }
 
     pub fn name(&self) -> Result<String> {
@@ -353,8 +499,10 @@ impl Gpu {
                 }
             }
         } else {
-            // For Intel Macs, try to determine if integrated or discrete
-            if let Some(gpu_name) = self.detect_intel_gpu() {
+            // For Intel Macs, Metal knows whether this device is integrated or discrete, otherwise guess from the name
+            if self.metal_device.is_some() {
+                characteristics.is_integrated = self.is_integrated();
+            } else if let Some(gpu_name) = self.detect_intel_gpu() {
                 // Check for likely integrated GPU names
                 characteristics.is_integrated = gpu_name.contains("Intel")
                     || gpu_name.contains("Iris")
@@ -374,6 +522,13 @@ impl Gpu {
 
     // Get memory info based on more realistic system metrics
     fn estimate_memory_info(&self) -> Result<GpuMemoryInfo> {
+        // Discrete GPUs have memory of their own, whose size and use Metal reports
+        if !self.has_unified_memory() {
+            if let Some(memory) = self.device_memory() {
+                return Ok(memory);
+            }
+        }
+
         // For Apple Silicon with unified memory, we need to be smarter about estimates
         let (total_memory, available_memory) = self.get_memory_stats()?;
 
@@ -382,7 +537,7 @@ impl Gpu {
 
         // Metal on Apple Silicon shares memory with the system We'll calculate a reasonable portion available to the
         // GPU
-        let gpu_total = if characteristics.is_apple_silicon {
+        let gpu_total = if characteristics.is_apple_silicon && self.has_unified_memory() {
             // On Apple Silicon, unified memory means GPU can access most RAM Calculate based on system configuration
             let percent_for_gpu = match total_memory {
                 m if m >= 32 * 1024 * 1024 * 1024 => 0.15, // 15% for 32GB+ systems
@@ -482,129 +637,44 @@ impl Gpu {
     }
 
     // Get temperature from SMC if available
-    fn get_temperature(&self) -> Result<f32> {
-        // For coverage runs, use a mock implementation to avoid segfaults
-        #[cfg(feature = "skip-ffi-crashes")]
-        {
-            // Return a reasonable mock temperature (42.5°C)
-            Ok(42.5)
+    // Get memory of a discrete GPU from Metal: the recommended working set is the size of its memory
+    fn device_memory(&self) -> Option<GpuMemoryInfo> {
+        let device = self.device()?;
+        let (total, used): (u64, usize) = unsafe {
+            (
+                msg_send![device, recommendedMaxWorkingSetSize],
+                msg_send![device, currentAllocatedSize],
+            )
+        };
+        if total == 0 {
+            return None;
         }
 
-        // Normal implementation for non-coverage runs
-        #[cfg(not(feature = "skip-ffi-crashes"))]
-        {
-            use crate::utils::bindings::*;
-            use std::mem::size_of;
-
-            unsafe {
-                // Open the SMC service safely
-                let service_name = std::ffi::CString::new("AppleSMC")
-                    .map_err(|_| Error::system("CString conversion failed"))?;
-                let service = IOServiceMatching(service_name.as_ptr());
-                if service.is_null() {
-                    return Err(Error::service_not_found("AppleSMC service not found"));
-                }
-
-                let service_id = IOServiceGetMatchingService(0, service as *const _);
-                if service_id == 0 {
-                    return Err(Error::service_not_found("AppleSMC service not found"));
-                }
-
-                let mut connection = 0u32;
-                let result = IOServiceOpen(service_id, 0, KERNEL_INDEX_SMC, &mut connection);
-                if result != IO_RETURN_SUCCESS {
-                    return Err(Error::io_kit(format!(
-                        "Failed to open SMC connection: {}",
-                        result
-                    )));
-                }
-
-                // Read SMC key for GPU temperature
-                let input_structure = SMCKeyData_t {
-                    key: smc_key_from_chars(SMC_KEY_GPU_TEMP),
-                    vers: 0,
-                    p_limit_data: 0,
-                    key_info: 0,
-                    padding: 0,
-                    result: 0,
-                    status: 0,
-                    data8: 0,
-                    data32: 0,
-                    bytes: [0; 2],
-                    data: std::mem::zeroed(),
-                };
-
-                let mut output_structure = SMCKeyData_t {
-                    key: smc_key_from_chars(SMC_KEY_GPU_TEMP),
-                    vers: 0,
-                    p_limit_data: 0,
-                    key_info: 1, // Get key info first
-                    padding: 0,
-                    result: 0,
-                    status: 0,
-                    data8: 0,
-                    data32: 0,
-                    bytes: [0; 2],
-                    data: std::mem::zeroed(),
-                };
-
-                let mut output_size = IOByteCount(size_of::<SMCKeyData_t>());
-
-                // Get key info first
-                let result = IOConnectCallStructMethod(
-                    connection,
-                    SMC_CMD_READ_KEYINFO as u32,
-                    &output_structure,
-                    IOByteCount(size_of::<SMCKeyData_t>()),
-                    &mut output_structure,
-                    &mut output_size,
-                );
-
-                if result != IO_RETURN_SUCCESS {
-                    IOServiceClose(connection);
-                    return Err(Error::io_kit(format!("Failed to read SMC key info: {}", result)));
-                }
-
-                // Now read the actual temperature data
-                output_structure.key_info = 0;
-
-                let result = IOConnectCallStructMethod(
-                    connection,
-                    SMC_CMD_READ_BYTES as u32,
-                    &input_structure,
-                    IOByteCount(size_of::<SMCKeyData_t>()),
-                    &mut output_structure,
-                    &mut output_size,
-                );
-
-                // Always close the connection
-                IOServiceClose(connection);
-
-                if result != IO_RETURN_SUCCESS {
-                    return Err(Error::io_kit(format!("Failed to read SMC key data: {}", result)));
-                }
+        let used = (used as u64).min(total);
+        Some(GpuMemoryInfo { total, used, free: total - used })
+    }
 
-                // Get the data and convert to temperature (fixed point, signed 8.8)
-                let data_type = output_structure.data.key_info.data_type;
-
-                if data_type[0] == b'S'
-                    && data_type[1] == b'P'
-                    && data_type[2] == b'7'
-                    && data_type[3] == b'8'
-                {
-                    // SP78 type: fixed point, signed 8.8
-                    let bytes = output_structure.data.bytes;
-                    let val: f32 = (bytes[0] as f32) + (bytes[1] as f32 / 256.0);
-                    Ok(val)
-                } else {
-                    Err(Error::invalid_data("Unsupported SMC data type for GPU temperature"))
-                }
-            }
+    // Get temperature from the driver of this GPU if it reports one, otherwise from the SMC
+    fn get_temperature(&self) -> Result<f32> {
+        if let Some(celsius) = self.driver_reading().and_then(|reading| reading.temperature_celsius)
+        {
+            return Ok(celsius as f32);
         }
+        Ok(self.iokit.get_gpu_temperature()? as f32)
     }
 
-    // Get GPU utilization more directly - using IO registry and process statistics
+    fn driver_reading(&self) -> Option<power_state::GpuPowerReading> {
+        power_state::device_reading(self.iokit.as_ref(), self.registry_id).ok()
+    }
+
+    // Get GPU utilization from the driver's performance statistics, or estimate it from process statistics
     fn estimate_utilization(&self) -> Result<f32> {
+        if let Some(utilization) =
+            self.driver_reading().and_then(|reading| reading.utilization_percent)
+        {
+            return Ok(utilization.clamp(0.0, 100.0) as f32);
+        }
+
         // For a more accurate approach that works on most macOS systems We'll use a weighted combination of:
         // 1. Process activity - weighted at 40%
         // 2. System load - weighted at 30%
@@ -0,0 +1,421 @@
+//! GPU power states and idle detection
+//!
+//! The GPU drivers publish their power management state and a `PerformanceStatistics` dictionary on the
+//! `IOAccelerator` entry in the IORegistry. None of the drivers publish the index of the current performance state
+//! there, so the coarse DVFS level is derived from the driver's own utilization figure, with thresholds that depend on
+//! the driver family. Residency per state since boot is only available through IOReport, which the crate has no
+//! bindings for yet, so [`GpuPowerState::residency_since_boot`] is always `None` for now.
+//!
+//! A [`Gpu`] from [`Gpu::enumerate`] reads the accelerator entry with its own registry entry ID.
+
+use std::{
+    collections::VecDeque,
+    ffi::CString,
+    time::{Duration, Instant},
+};
+
+use objc2::rc::autoreleasepool;
+use objc2_foundation::{NSDictionary, NSObject, NSString};
+
+use super::Gpu;
+use crate::{
+    error::{Error, Result},
+    hardware::iokit::IOKit,
+    utils::bindings::{
+        IOIteratorNext, IOObjectRelease, IORegistryEntryIDMatching, IOServiceGetMatchingService,
+        IOServiceGetMatchingServices, IOServiceMatching,
+    },
+};
+
+/// Registry class every GPU driver's accelerator service inherits from
+const ACCELERATOR_CLASS: &str = "IOAccelerator";
+/// Registry property holding the concrete driver class of a service
+const IO_CLASS_KEY: &str = "IOClass";
+/// Registry property holding the power management state of a service
+const POWER_MANAGEMENT_KEY: &str = "IOPowerManagement";
+/// Power management property holding the current power state; 0 is off for every driver
+const CURRENT_POWER_STATE_KEY: &str = "CurrentPowerState";
+/// Registry property holding the driver's performance counters
+const PERFORMANCE_STATISTICS_KEY: &str = "PerformanceStatistics";
+/// Performance counter holding the GPU utilization in percent
+const DEVICE_UTILIZATION_KEY: &str = "Device Utilization %";
+/// Performance counter holding the utilization of the rendering pipeline in percent, the closest figure to the device
+/// utilization on drivers that don't publish it
+const RENDERER_UTILIZATION_KEY: &str = "Renderer Utilization %";
+/// Performance counter holding the core clock, only published by the AMD drivers
+const CORE_CLOCK_KEY: &str = "Core Clock(MHz)";
+/// Performance counter holding the GPU temperature in degrees Celsius, only published by the AMD drivers
+const TEMPERATURE_KEY: &str = "Temperature(C)";
+
+/// Coarse dynamic voltage and frequency scaling level of the GPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum GpuDvfsState {
+    /// The GPU is powered down
+    Off,
+    /// The GPU is powered but has no work
+    Idle,
+    /// Light load, e.g. compositing the desktop
+    Low,
+    /// Moderate load, e.g. video playback
+    Medium,
+    /// Heavy load, e.g. games or compute
+    High,
+    /// The driver isn't known or doesn't publish the readings needed
+    Unknown,
+}
+
+impl GpuDvfsState {
+    /// Whether the GPU is off or idle
+    pub fn is_idle(&self) -> bool {
+        matches!(self, Self::Off | Self::Idle)
+    }
+}
+
+/// Power state of the GPU
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuPowerState {
+    /// Current DVFS level
+    pub state: GpuDvfsState,
+    /// Current core clock in MHz, if the driver publishes it
+    pub core_clock_mhz: Option<u32>,
+    /// Time spent in each state since boot, if the driver reports residency (currently never, see the
+    /// [module documentation](self))
+    pub residency_since_boot: Option<Vec<(GpuDvfsState, Duration)>>,
+}
+
+/// Family of the driver behind a GPU, which decides how its readings are interpreted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GpuDriverFamily {
+    /// Apple Silicon GPUs (`AGXAccelerator*`)
+    AppleSilicon,
+    /// Intel integrated GPUs (`IntelAccelerator`)
+    Intel,
+    /// AMD discrete GPUs (`AMDRadeon*`)
+    Amd,
+    /// NVIDIA discrete GPUs (`nvAccelerator`, `GeForce*`)
+    Nvidia,
+    /// Any other driver
+    Unknown,
+}
+
+impl GpuDriverFamily {
+    /// Derives the family from the registry class of the accelerator service.
+    pub fn from_io_class(io_class: &str) -> Self {
+        if io_class.starts_with("AGXAccelerator") {
+            Self::AppleSilicon
+        } else if io_class.starts_with("IntelAccelerator") {
+            Self::Intel
+        } else if io_class.starts_with("AMDRadeon") {
+            Self::Amd
+        } else if io_class.starts_with("nvAccelerator") || io_class.starts_with("GeForce") {
+            Self::Nvidia
+        } else {
+            Self::Unknown
+        }
+    }
+
+    /// Utilization in percent below which the GPU is [`Idle`](GpuDvfsState::Idle), [`Low`](GpuDvfsState::Low) and
+    /// [`Medium`](GpuDvfsState::Medium); anything above is [`High`](GpuDvfsState::High).
+    ///
+    /// Integrated Intel GPUs compose the desktop, so a few percent of utilization don't mean they do any other work.
+    fn dvfs_thresholds(&self) -> Option<[f64; 3]> {
+        match self {
+            Self::AppleSilicon => Some([1.0, 30.0, 70.0]),
+            Self::Intel => Some([5.0, 35.0, 75.0]),
+            Self::Amd | Self::Nvidia => Some([1.0, 25.0, 65.0]),
+            Self::Unknown => None,
+        }
+    }
+}
+
+/// Raw readings of an accelerator service
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GpuPowerReading {
+    pub(crate) family: GpuDriverFamily,
+    /// Index of the current power management state
+    pub(crate) power_state: Option<u32>,
+    pub(crate) utilization_percent: Option<f64>,
+    pub(crate) core_clock_mhz: Option<u32>,
+    pub(crate) temperature_celsius: Option<f64>,
+}
+
+impl GpuPowerReading {
+    /// Extracts the readings from the properties of an accelerator service, given its class and a lookup of numeric
+    /// properties by their key path.
+    pub(crate) fn from_properties(
+        io_class: Option<&str>,
+        number: impl Fn(&[&str]) -> Option<f64>,
+    ) -> Self {
+        Self {
+            family: io_class.map_or(GpuDriverFamily::Unknown, GpuDriverFamily::from_io_class),
+            power_state: number(&[POWER_MANAGEMENT_KEY, CURRENT_POWER_STATE_KEY])
+                .map(|state| state as u32),
+            utilization_percent: number(&[PERFORMANCE_STATISTICS_KEY, DEVICE_UTILIZATION_KEY])
+                .or_else(|| number(&[PERFORMANCE_STATISTICS_KEY, RENDERER_UTILIZATION_KEY])),
+            core_clock_mhz: number(&[PERFORMANCE_STATISTICS_KEY, CORE_CLOCK_KEY])
+                .filter(|&mhz| mhz > 0.0)
+                .map(|mhz| mhz as u32),
+            // A powered down GPU reports 0
+            temperature_celsius: number(&[PERFORMANCE_STATISTICS_KEY, TEMPERATURE_KEY])
+                .filter(|&celsius| celsius > 0.0),
+        }
+    }
+
+    /// Maps the readings to a DVFS level with the thresholds of the driver family.
+    pub(crate) fn classify(&self) -> GpuDvfsState {
+        if self.power_state == Some(0) {
+            return GpuDvfsState::Off;
+        }
+        let (Some([idle, low, medium]), Some(utilization)) =
+            (self.family.dvfs_thresholds(), self.utilization_percent)
+        else {
+            return GpuDvfsState::Unknown;
+        };
+
+        if utilization < idle {
+            GpuDvfsState::Idle
+        } else if utilization < low {
+            GpuDvfsState::Low
+        } else if utilization < medium {
+            GpuDvfsState::Medium
+        } else {
+            GpuDvfsState::High
+        }
+    }
+
+    pub(crate) fn power_state(&self) -> GpuPowerState {
+        GpuPowerState {
+            state: self.classify(),
+            core_clock_mhz: self.core_clock_mhz,
+            residency_since_boot: None,
+        }
+    }
+}
+
+impl Gpu {
+    /// Reads the current power state of the GPU.
+    ///
+    /// The accelerator entry of the GPU is read; without one, as when Metal is unavailable, the first accelerator of a
+    /// known driver family in the IORegistry is read. An unknown driver reports [`GpuDvfsState::Unknown`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IORegistry can't be queried or has no accelerator.
+    pub fn power_state(&self) -> Result<GpuPowerState> {
+        Ok(device_reading(self.iokit.as_ref(), self.registry_id)?.power_state())
+    }
+}
+
+/// Reads the accelerator entry with the ID `registry_id`, or the primary accelerator without one.
+pub(super) fn device_reading<T: IOKit + ?Sized>(
+    io_kit: &T,
+    registry_id: Option<u64>,
+) -> Result<GpuPowerReading> {
+    match registry_id {
+        Some(registry_id) => entry_reading(io_kit, registry_id),
+        None => primary_reading(io_kit),
+    }
+}
+
+/// Reads the accelerator entry with the ID `registry_id`.
+fn entry_reading<T: IOKit + ?Sized>(io_kit: &T, registry_id: u64) -> Result<GpuPowerReading> {
+    autoreleasepool(|_| unsafe {
+        // IOServiceGetMatchingService consumes the matching dictionary
+        let matching = IORegistryEntryIDMatching(registry_id);
+        if matching.is_null() {
+            return Err(Error::io_kit("Failed to create matching dictionary"));
+        }
+
+        let entry = IOServiceGetMatchingService(0, matching);
+        if entry == 0 {
+            return Err(Error::not_available(format!(
+                "No GPU accelerator with registry ID {registry_id:#x}"
+            )));
+        }
+        let properties = io_kit.io_registry_entry_properties(entry);
+        IOObjectRelease(entry);
+
+        let properties = properties?;
+        Ok(reading_from(io_kit, &properties))
+    })
+}
+
+/// Reads the first accelerator of a known driver family, or the first accelerator if none is known.
+fn primary_reading<T: IOKit + ?Sized>(io_kit: &T) -> Result<GpuPowerReading> {
+    let mut readings = Vec::new();
+    for_each_accelerator(io_kit, |properties| readings.push(reading_from(io_kit, properties)))?;
+
+    if readings.is_empty() {
+        return Err(Error::not_available("No GPU accelerator found in the IORegistry"));
+    }
+    let index =
+        readings.iter().position(|reading| reading.family != GpuDriverFamily::Unknown).unwrap_or(0);
+    Ok(readings.swap_remove(index))
+}
+
+/// Tracks how much of the time the GPU spends off or idle
+///
+/// Each sample is attributed the time until the next one, so the newest sample doesn't contribute. Samples older
+/// than the window, measured from the newest sample, are dropped.
+#[derive(Debug, Clone)]
+pub struct GpuIdleMonitor {
+    interval: Duration,
+    window: Duration,
+    samples: VecDeque<(Instant, GpuDvfsState)>,
+}
+
+impl GpuIdleMonitor {
+    /// Creates a monitor that samples every `interval` and reports over the last `window`.
+    pub fn new(interval: Duration, window: Duration) -> Self {
+        Self { interval, window, samples: VecDeque::new() }
+    }
+
+    /// Reads the power state of `gpu` and records it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the power state can't be read.
+    pub fn sample(&mut self, gpu: &Gpu) -> Result<GpuDvfsState> {
+        let state = gpu.power_state()?.state;
+        self.record_at(Instant::now(), state);
+        Ok(state)
+    }
+
+    /// Samples `gpu` every interval until `duration` has passed, blocking the calling thread.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error reading the power state.
+    pub fn run(&mut self, gpu: &Gpu, duration: Duration) -> Result<()> {
+        let deadline = Instant::now() + duration;
+        loop {
+            self.sample(gpu)?;
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            std::thread::sleep(self.interval.min(deadline - now));
+        }
+    }
+
+    /// Records the state observed at `timestamp`.
+    pub fn record_at(&mut self, timestamp: Instant, state: GpuDvfsState) {
+        self.samples.push_back((timestamp, state));
+        while let Some(&(oldest, _)) = self.samples.front() {
+            if timestamp.saturating_duration_since(oldest) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    /// Total time spent in `state` within the window.
+    pub fn time_in_state(&self, state: GpuDvfsState) -> Duration {
+        self.durations()
+            .filter(|&(sampled, _)| sampled == state)
+            .map(|(_, duration)| duration)
+            .sum()
+    }
+
+    /// Share of the measured time within the window the GPU was off or idle, between 0.0 and 1.0, or `None` until
+    /// two samples were recorded.
+    pub fn idle_fraction(&self) -> Option<f64> {
+        let (idle, total) =
+            self.durations().fold((Duration::ZERO, Duration::ZERO), |(idle, total), (state, d)| {
+                (if state.is_idle() { idle + d } else { idle }, total + d)
+            });
+
+        (!total.is_zero()).then(|| idle.as_secs_f64() / total.as_secs_f64())
+    }
+
+    /// Whether any sample within the window was [`GpuDvfsState::High`].
+    pub fn was_ever_high(&self) -> bool {
+        self.samples.iter().any(|&(_, state)| state == GpuDvfsState::High)
+    }
+
+    /// The sampling interval.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The number of samples within the window.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Whether no samples were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    fn durations(&self) -> impl Iterator<Item = (GpuDvfsState, Duration)> + '_ {
+        self.samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .map(|(&(start, state), &(end, _))| (state, end.saturating_duration_since(start)))
+    }
+}
+
+fn reading_from<T: IOKit + ?Sized>(
+    io_kit: &T,
+    properties: &NSDictionary<NSString, NSObject>,
+) -> GpuPowerReading {
+    let io_class = io_kit.get_string_property(properties, IO_CLASS_KEY);
+    GpuPowerReading::from_properties(io_class.as_deref(), |path| {
+        number_at(io_kit, properties, path)
+    })
+}
+
+/// Calls `f` with the registry properties of every accelerator service.
+fn for_each_accelerator<T: IOKit + ?Sized>(
+    io_kit: &T,
+    mut f: impl FnMut(&NSDictionary<NSString, NSObject>),
+) -> Result<()> {
+    let class_name = CString::new(ACCELERATOR_CLASS)
+        .map_err(|_| Error::invalid_data("Invalid registry class name"))?;
+
+    autoreleasepool(|_| unsafe {
+        // IOServiceGetMatchingServices consumes the matching dictionary
+        let matching = IOServiceMatching(class_name.as_ptr());
+        if matching.is_null() {
+            return Err(Error::io_kit("Failed to create matching dictionary"));
+        }
+
+        let mut iterator = 0u32;
+        if IOServiceGetMatchingServices(0, matching, &mut iterator) != 0 {
+            return Err(Error::io_kit("Failed to look up accelerator services"));
+        }
+
+        loop {
+            let entry = IOIteratorNext(iterator);
+            if entry == 0 {
+                break;
+            }
+            if let Ok(properties) = io_kit.io_registry_entry_properties(entry) {
+                f(&properties);
+            }
+            IOObjectRelease(entry);
+        }
+        IOObjectRelease(iterator);
+
+        Ok(())
+    })
+}
+
+/// Follows `path` through nested dictionaries to a number.
+fn number_at<T: IOKit + ?Sized>(
+    io_kit: &T,
+    dict: &NSDictionary<NSString, NSObject>,
+    path: &[&str],
+) -> Option<f64> {
+    let (last, parents) = path.split_last()?;
+    let Some((first, rest)) = parents.split_first() else {
+        return io_kit.get_number_property(dict, last).map(|n| n as f64);
+    };
+
+    let mut current = io_kit.get_dict_property(dict, first)?;
+    for key in rest {
+        current = io_kit.get_dict_property(&current, key)?;
+    }
+    io_kit.get_number_property(&current, last).map(|n| n as f64)
+}
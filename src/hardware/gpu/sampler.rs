@@ -0,0 +1,469 @@
+//! Sampling the GPU in the background, like `powermetrics --samplers gpu_power`
+//!
+//! [`GpuSampler`] reads the GPU every interval on a tokio task, keeps the most recent samples for plotting and
+//! broadcasts each one as it's taken. The accelerator and AGPM controller entries are looked up once, on the first
+//! tick, and their properties are read directly on every tick after that. An entry whose properties can no longer be
+//! read, e.g. because the driver was reloaded, is looked up again on the next tick.
+
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use futures::{stream, Stream};
+use objc2::rc::{autoreleasepool, Retained};
+use objc2_foundation::{NSDictionary, NSObject, NSString};
+use parking_lot::Mutex;
+use tokio::{sync::broadcast, task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{
+    error::{Error, Result},
+    hardware::iokit::{gpu_utilization, registry_id_matching, IOKit, IOServiceHandle},
+    shutdown,
+};
+
+/// Name of the sampling task in the shutdown registry
+pub(crate) const GPU_SAMPLER: &str = "gpu-sampler";
+/// Samples kept by [`Gpu::start_sampling`](super::Gpu::start_sampling), ten minutes at one sample per second
+pub const DEFAULT_HISTORY_CAPACITY: usize = 600;
+/// Samples buffered for each receiver; a receiver that falls further behind skips the oldest ones
+const SAMPLE_CAPACITY: usize = 64;
+
+const AGPM_CLASS: &str = "AGPMController";
+const ACCELERATOR_CLASS: &str = "IOAccelerator";
+const PERF_CAP_KEY: &str = "GPUPerfCap";
+const PERF_THRESHOLD_KEY: &str = "GPUPerfThreshold";
+const PERFORMANCE_STATISTICS_KEY: &str = "PerformanceStatistics";
+const DEVICE_UTILIZATION_KEY: &str = "Device Utilization %";
+const IN_USE_MEMORY_KEY: &str = "In use system memory";
+const VRAM_USED_KEY: &str = "VRAM,usedMB";
+
+/// A reading of the GPU taken by a [`GpuSampler`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GpuSample {
+    /// When the GPU was read
+    pub timestamp: SystemTime,
+    /// GPU utilization in percent (0-100)
+    pub utilization: f64,
+    /// GPU memory in use in bytes, 0 if the driver doesn't report it
+    pub memory_used: u64,
+    /// GPU temperature in degrees Celsius, None without a sensor
+    pub temperature: Option<f64>,
+}
+
+/// The most recent samples, oldest first
+#[derive(Debug)]
+pub(crate) struct SampleHistory {
+    samples: VecDeque<GpuSample>,
+    capacity: usize,
+}
+
+impl SampleHistory {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self { samples: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    /// Adds `sample`, dropping the oldest sample if the history is full.
+    pub(crate) fn push(&mut self, sample: GpuSample) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub(crate) fn samples(&self) -> Vec<GpuSample> {
+        self.samples.iter().copied().collect()
+    }
+
+    /// Utilizations of the samples taken within `window` before `now`
+    fn utilizations(&self, window: Duration, now: SystemTime) -> impl Iterator<Item = f64> + '_ {
+        let since = now.checked_sub(window).unwrap_or(SystemTime::UNIX_EPOCH);
+        self.samples
+            .iter()
+            .rev()
+            .take_while(move |sample| sample.timestamp >= since)
+            .map(|sample| sample.utilization)
+    }
+
+    pub(crate) fn average_over(&self, window: Duration, now: SystemTime) -> Option<f64> {
+        let (sum, count) = self
+            .utilizations(window, now)
+            .fold((0.0, 0usize), |(sum, count), utilization| (sum + utilization, count + 1));
+        (count > 0).then(|| sum / count as f64)
+    }
+
+    pub(crate) fn peak_over(&self, window: Duration, now: SystemTime) -> Option<f64> {
+        self.utilizations(window, now).reduce(f64::max)
+    }
+}
+
+/// The registry entries a sample is read from, looked up once and kept across ticks
+#[derive(Debug)]
+pub(crate) struct GpuServices {
+    /// ID of the accelerator entry of the sampled GPU, None for the first accelerator
+    registry_id: Option<u64>,
+    accelerator: Option<IOServiceHandle>,
+    agpm: Option<IOServiceHandle>,
+    /// Whether an entry failed to read and has to be looked up again
+    stale: bool,
+}
+
+impl GpuServices {
+    /// Looks up the accelerator entry with `registry_id` and the AGPM controller.
+    pub(crate) fn resolve(iokit: &dyn IOKit, registry_id: Option<u64>) -> Self {
+        let matching = match registry_id {
+            Some(registry_id) => registry_id_matching(registry_id),
+            None => Some(iokit.io_service_matching(ACCELERATOR_CLASS)),
+        };
+        let accelerator =
+            matching.and_then(|matching| iokit.io_service_get_matching_service(&matching));
+        let agpm = iokit.io_service_get_matching_service(&iokit.io_service_matching(AGPM_CLASS));
+
+        Self { registry_id, accelerator, agpm, stale: false }
+    }
+
+    /// Reads a sample, or returns None if neither entry reports the utilization.
+    pub(crate) fn sample(&mut self, iokit: &dyn IOKit) -> Option<GpuSample> {
+        if self.stale {
+            *self = Self::resolve(iokit, self.registry_id);
+        }
+
+        let accelerator = self.properties(iokit, |services| &services.accelerator);
+        let statistics = accelerator
+            .as_deref()
+            .and_then(|properties| iokit.get_dict_property(properties, PERFORMANCE_STATISTICS_KEY));
+        let statistic = |key| iokit.get_number_property(statistics.as_deref()?, key);
+
+        // Apple Silicon drivers report the utilization of the device, AMD and Intel Macs only the AGPM performance
+        // state
+        let utilization = match statistic(DEVICE_UTILIZATION_KEY) {
+            Some(utilization) => utilization as f64,
+            None => {
+                let agpm = self.properties(iokit, |services| &services.agpm)?;
+                let perf_cap = iokit.get_number_property(&agpm, PERF_CAP_KEY)?;
+                let threshold = iokit.get_number_property(&agpm, PERF_THRESHOLD_KEY).unwrap_or(100);
+                gpu_utilization(perf_cap as f64, threshold as f64)
+            },
+        };
+
+        let vram_used = accelerator
+            .as_deref()
+            .and_then(|properties| iokit.get_number_property(properties, VRAM_USED_KEY))
+            .map(|megabytes| megabytes.max(0) as u64 * 1024 * 1024);
+        let memory_used =
+            vram_used.or_else(|| statistic(IN_USE_MEMORY_KEY).map(|bytes| bytes.max(0) as u64));
+
+        Some(GpuSample {
+            timestamp: SystemTime::now(),
+            utilization: utilization.clamp(0.0, 100.0),
+            memory_used: memory_used.unwrap_or(0),
+            temperature: iokit.get_gpu_temperature().ok(),
+        })
+    }
+
+    /// Reads the properties of an entry, marking the services stale if it can't be read.
+    fn properties(
+        &mut self,
+        iokit: &dyn IOKit,
+        entry: impl Fn(&Self) -> &Option<IOServiceHandle>,
+    ) -> Option<Retained<NSDictionary<NSString, NSObject>>> {
+        match iokit.io_registry_entry_create_cf_properties(entry(self).as_ref()?) {
+            Ok(properties) => Some(properties),
+            Err(e) => {
+                tracing::debug!("Failed to read a GPU registry entry: {e}");
+                self.stale = true;
+                None
+            },
+        }
+    }
+}
+
+/// Samples the GPU in the background for plotting
+///
+/// A task on the tokio runtime reads the utilization, memory use and temperature of the GPU every interval, starting
+/// one interval after the start. The samples are kept in a bounded history, queried with [`history`](Self::history),
+/// [`average_over`](Self::average_over) and [`peak_over`](Self::peak_over), and broadcast to
+/// [`recv`](Self::recv) and [`stream`](Self::stream). The task stops when the sampler is dropped or the crate is
+/// [shut down](crate::shutdown()).
+///
+/// ```rust,no_run
+/// # async fn example() -> darwin_metrics::Result<()> {
+/// use std::time::Duration;
+///
+/// use darwin_metrics::hardware::gpu::Gpu;
+///
+/// let sampler = Gpu::new()?.start_sampling(Duration::from_millis(500));
+/// tokio::time::sleep(Duration::from_secs(10)).await;
+/// println!(
+///     "GPU {:.1}% on average, {:.1}% at peak",
+///     sampler.average_over(Duration::from_secs(10)).unwrap_or_default(),
+///     sampler.peak_over(Duration::from_secs(10)).unwrap_or_default(),
+/// );
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct GpuSampler {
+    history: Arc<Mutex<SampleHistory>>,
+    receiver: broadcast::Receiver<GpuSample>,
+    task: JoinHandle<()>,
+}
+
+impl GpuSampler {
+    /// Starts sampling the accelerator entry with `registry_id` through `iokit`, keeping up to `capacity` samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub(crate) fn spawn(
+        iokit: Arc<dyn IOKit>,
+        registry_id: Option<u64>,
+        interval: Duration,
+        capacity: usize,
+    ) -> Self {
+        let history = Arc::new(Mutex::new(SampleHistory::new(capacity)));
+        let (sender, receiver) = broadcast::channel(SAMPLE_CAPACITY);
+
+        let component = shutdown::register(GPU_SAMPLER);
+        let task_component = Arc::clone(&component);
+        let task_history = Arc::clone(&history);
+
+        let task = tokio::spawn(async move {
+            let _running = task_component.running();
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // The first tick completes immediately, leaving subscribers no time to subscribe
+            ticker.tick().await;
+
+            let mut services = None;
+            while !task_component.is_stop_requested() {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = task_component.stop_requested() => break,
+                }
+
+                let (iokit, services_in) = (Arc::clone(&iokit), services.take());
+                let read = tokio::task::spawn_blocking(move || {
+                    autoreleasepool(|_| {
+                        let mut services = services_in
+                            .unwrap_or_else(|| GpuServices::resolve(&*iokit, registry_id));
+                        let sample = services.sample(&*iokit);
+                        (services, sample)
+                    })
+                });
+                // A panicked read loses the services, which are looked up again on the next tick
+                let Ok((read_services, sample)) = read.await else {
+                    continue;
+                };
+                services = Some(read_services);
+
+                if let Some(sample) = sample {
+                    task_history.lock().push(sample);
+                    // Without receivers the sample is dropped, and the sampler keeps one itself
+                    let _ = sender.send(sample);
+                }
+            }
+        });
+        let abort = task.abort_handle();
+        component.set_abort(move || abort.abort());
+
+        Self { history, receiver, task }
+    }
+
+    /// The samples kept, oldest first.
+    pub fn history(&self) -> Vec<GpuSample> {
+        self.history.lock().samples()
+    }
+
+    /// Average utilization of the samples taken within the last `window`, None if there are none.
+    pub fn average_over(&self, window: Duration) -> Option<f64> {
+        self.history.lock().average_over(window, SystemTime::now())
+    }
+
+    /// Highest utilization of the samples taken within the last `window`, None if there are none.
+    pub fn peak_over(&self, window: Duration) -> Option<f64> {
+        self.history.lock().peak_over(window, SystemTime::now())
+    }
+
+    /// A receiver of the samples taken from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<GpuSample> {
+        self.receiver.resubscribe()
+    }
+
+    /// Waits for the next sample, skipping the ones lost if the caller fell more than 64 samples behind.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sampling task stopped, e.g. because the crate was shut down.
+    pub async fn recv(&mut self) -> Result<GpuSample> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(sample) => return Ok(sample),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Skipped {skipped} GPU samples of a slow receiver");
+                },
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(Error::system("The GPU sampler stopped"))
+                },
+            }
+        }
+    }
+
+    /// A stream of the samples taken from now on, which ends when the sampling task stops.
+    ///
+    /// Like [`recv`](Self::recv), a stream that falls more than 64 samples behind skips the oldest ones.
+    pub fn stream(&self) -> impl Stream<Item = GpuSample> + Send + Unpin {
+        Box::pin(stream::unfold(self.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(sample) => return Some((sample, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }))
+    }
+}
+
+impl Drop for GpuSampler {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::{
+        hardware::iokit::MockIOKit,
+        utils::test_utils::{create_test_dictionary, create_test_service_with_id},
+    };
+
+    fn sample_at(timestamp: SystemTime, utilization: f64) -> GpuSample {
+        GpuSample { timestamp, utilization, memory_used: 0, temperature: None }
+    }
+
+    /// An IOKit mock whose AGPM controller reports `perf_caps` one after the other, then the last one again, counting
+    /// the service lookups in `lookups`
+    fn scripted_iokit(perf_caps: &'static [i64], lookups: Arc<AtomicUsize>) -> MockIOKit {
+        let reads = AtomicUsize::new(0);
+        let mut iokit = MockIOKit::new();
+        iokit.expect_io_service_matching().returning(|_| create_test_dictionary());
+        iokit.expect_io_service_get_matching_service().returning(move |_| {
+            Some(create_test_service_with_id(lookups.fetch_add(1, Ordering::SeqCst) as u32 + 1))
+        });
+        iokit
+            .expect_io_registry_entry_create_cf_properties()
+            .returning(|_| Ok(create_test_dictionary()));
+        iokit.expect_get_dict_property().returning(|_, _| None);
+        iokit.expect_get_number_property().returning(move |_, key| match key {
+            PERF_CAP_KEY => {
+                let read = reads.fetch_add(1, Ordering::SeqCst).min(perf_caps.len() - 1);
+                Some(perf_caps[read])
+            },
+            PERF_THRESHOLD_KEY => Some(100),
+            VRAM_USED_KEY => Some(512),
+            _ => None,
+        });
+        iokit.expect_get_gpu_temperature().returning(|| Ok(55.0));
+        iokit
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let now = SystemTime::now();
+        let mut history = SampleHistory::new(3);
+        for utilization in [10.0, 20.0, 30.0, 40.0] {
+            history.push(sample_at(now, utilization));
+        }
+
+        let utilizations: Vec<f64> =
+            history.samples().iter().map(|sample| sample.utilization).collect();
+        assert_eq!(utilizations, [20.0, 30.0, 40.0], "The oldest sample is dropped");
+    }
+
+    #[test]
+    fn test_average_and_peak_over_a_window() {
+        let now = SystemTime::now();
+        let mut history = SampleHistory::new(10);
+        history.push(sample_at(now - Duration::from_secs(30), 90.0));
+        history.push(sample_at(now - Duration::from_secs(8), 20.0));
+        history.push(sample_at(now - Duration::from_secs(4), 60.0));
+        history.push(sample_at(now, 40.0));
+
+        assert_eq!(history.average_over(Duration::from_secs(10), now), Some(40.0));
+        assert_eq!(history.peak_over(Duration::from_secs(10), now), Some(60.0));
+        assert_eq!(history.peak_over(Duration::from_secs(60), now), Some(90.0));
+        assert_eq!(history.average_over(Duration::ZERO, now), Some(40.0));
+
+        let empty = SampleHistory::new(10);
+        assert_eq!(empty.average_over(Duration::from_secs(10), now), None);
+        assert_eq!(empty.peak_over(Duration::from_secs(10), now), None);
+    }
+
+    #[test]
+    fn test_services_read_without_a_reported_utilization() {
+        let mut iokit = MockIOKit::new();
+        iokit.expect_io_service_matching().returning(|_| create_test_dictionary());
+        iokit.expect_io_service_get_matching_service().returning(|_| None);
+
+        let mut services = GpuServices::resolve(&iokit, None);
+        assert!(services.sample(&iokit).is_none(), "No entry reports the utilization");
+    }
+
+    #[test]
+    fn test_unreadable_services_are_looked_up_again() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let mut iokit = scripted_iokit(&[50], Arc::clone(&lookups));
+        let mut services = GpuServices::resolve(&iokit, None);
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+
+        iokit.checkpoint();
+        iokit
+            .expect_io_registry_entry_create_cf_properties()
+            .returning(|_| Err(Error::io_kit("Registry entry is gone")));
+        assert!(services.sample(&iokit).is_none());
+
+        let iokit = scripted_iokit(&[50], Arc::clone(&lookups));
+        let sample = services.sample(&iokit).expect("The services were looked up again");
+        assert_eq!(sample.utilization, 50.0);
+        assert_eq!(lookups.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_sampler_records_scripted_utilization() {
+        let _serial = crate::shutdown::TEST_SERIAL.lock().await;
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let iokit = scripted_iokit(&[10, 30, 80, 40], Arc::clone(&lookups));
+
+        let mut sampler = GpuSampler::spawn(Arc::new(iokit), None, Duration::from_millis(5), 100);
+        let mut utilizations = Vec::new();
+        for _ in 0..4 {
+            let sample = tokio::time::timeout(Duration::from_secs(5), sampler.recv());
+            let sample = sample.await.expect("Timed out waiting for a sample").unwrap();
+            assert_eq!(sample.memory_used, 512 * 1024 * 1024);
+            assert_eq!(sample.temperature, Some(55.0));
+            utilizations.push(sample.utilization);
+        }
+
+        assert_eq!(utilizations, [10.0, 30.0, 80.0, 40.0]);
+        assert_eq!(lookups.load(Ordering::SeqCst), 2, "The services are looked up once");
+
+        let history = sampler.history();
+        assert!(history.len() >= 4);
+        assert_eq!(history[..4].iter().map(|s| s.utilization).collect::<Vec<_>>(), utilizations);
+        assert_eq!(sampler.peak_over(Duration::from_secs(60)), Some(80.0));
+        assert!(sampler.average_over(Duration::from_secs(60)).is_some());
+
+        let mut stream = sampler.stream();
+        let next = tokio::time::timeout(Duration::from_secs(5), stream.next()).await.unwrap();
+        assert_eq!(next.map(|sample| sample.utilization), Some(40.0));
+    }
+}
@@ -206,3 +206,193 @@ fn test_metrics_characteristics() {
         );
     }
 }
+
+mod power_state {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::hardware::gpu::power_state::GpuPowerReading;
+
+    fn reading(fixture: &str) -> GpuPowerReading {
+        let properties: serde_json::Value = serde_json::from_str(fixture).unwrap();
+        GpuPowerReading::from_properties(properties["IOClass"].as_str(), |path| {
+            path.iter().try_fold(&properties, |value, key| value.get(key))?.as_f64()
+        })
+    }
+
+    #[test]
+    fn test_driver_fixtures() {
+        let cases = [
+            (include_str!("fixtures/agx_idle.json"), GpuDvfsState::Idle, None),
+            (include_str!("fixtures/agx_busy.json"), GpuDvfsState::High, None),
+            (include_str!("fixtures/intel_desktop.json"), GpuDvfsState::Idle, None),
+            (include_str!("fixtures/amd_off.json"), GpuDvfsState::Off, None),
+            (include_str!("fixtures/amd_medium.json"), GpuDvfsState::Medium, Some(1300)),
+            (include_str!("fixtures/unknown_driver.json"), GpuDvfsState::Unknown, None),
+        ];
+
+        for (fixture, state, core_clock_mhz) in cases {
+            let power_state = reading(fixture).power_state();
+            assert_eq!(power_state.state, state, "Unexpected state for {fixture}");
+            assert_eq!(power_state.core_clock_mhz, core_clock_mhz);
+            assert_eq!(power_state.residency_since_boot, None);
+        }
+    }
+
+    #[test]
+    fn test_driver_temperature() {
+        let cases = [
+            (include_str!("fixtures/amd_medium.json"), Some(61.0)),
+            // Powered down GPUs report 0
+            (include_str!("fixtures/amd_off.json"), None),
+            (include_str!("fixtures/agx_busy.json"), None),
+        ];
+
+        for (fixture, temperature) in cases {
+            assert_eq!(reading(fixture).temperature_celsius, temperature);
+        }
+    }
+
+    #[test]
+    fn test_driver_families() {
+        assert_eq!(
+            GpuDriverFamily::from_io_class("AGXAcceleratorG13X"),
+            GpuDriverFamily::AppleSilicon
+        );
+        assert_eq!(GpuDriverFamily::from_io_class("IntelAccelerator"), GpuDriverFamily::Intel);
+        assert_eq!(
+            GpuDriverFamily::from_io_class("AMDRadeonX5000_AMDBaffinGraphicsAccelerator"),
+            GpuDriverFamily::Amd
+        );
+        assert_eq!(GpuDriverFamily::from_io_class("nvAccelerator"), GpuDriverFamily::Nvidia);
+        assert_eq!(GpuDriverFamily::from_io_class("IOAccelerator"), GpuDriverFamily::Unknown);
+    }
+
+    #[test]
+    fn test_classify_without_readings() {
+        let mut reading = reading(include_str!("fixtures/agx_busy.json"));
+        reading.utilization_percent = None;
+        assert_eq!(reading.classify(), GpuDvfsState::Unknown);
+
+        // A powered down GPU is off whatever else is known
+        reading.power_state = Some(0);
+        assert_eq!(reading.classify(), GpuDvfsState::Off);
+    }
+
+    #[test]
+    fn test_idle_monitor_window() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut monitor = GpuIdleMonitor::new(Duration::from_secs(1), Duration::from_secs(10));
+
+        assert_eq!(monitor.idle_fraction(), None);
+        monitor.record_at(at(0), GpuDvfsState::High);
+        assert_eq!(monitor.idle_fraction(), None);
+        monitor.record_at(at(2), GpuDvfsState::Idle);
+        monitor.record_at(at(8), GpuDvfsState::Off);
+        monitor.record_at(at(10), GpuDvfsState::Low);
+
+        assert_eq!(monitor.time_in_state(GpuDvfsState::High), Duration::from_secs(2));
+        assert_eq!(monitor.time_in_state(GpuDvfsState::Idle), Duration::from_secs(6));
+        assert_eq!(monitor.idle_fraction(), Some(0.8));
+        assert!(monitor.was_ever_high());
+
+        // The high sample leaves the window
+        monitor.record_at(at(12), GpuDvfsState::Low);
+        assert_eq!(monitor.len(), 4);
+        assert!(!monitor.was_ever_high());
+        assert_eq!(monitor.idle_fraction(), Some(0.8));
+    }
+
+    #[test]
+    fn test_power_state_idle_machine() {
+        let gpu = Gpu::new().unwrap();
+        let first = gpu.power_state();
+        assert!(first.is_ok(), "Should be able to read the GPU power state: {first:?}");
+        if first.unwrap().state == GpuDvfsState::Unknown {
+            println!("GPU driver not supported, skipping");
+            return;
+        }
+
+        let mut monitor = GpuIdleMonitor::new(Duration::from_millis(50), Duration::from_secs(10));
+        monitor.run(&gpu, Duration::from_millis(500)).unwrap();
+
+        // Test machines are mostly idle, but other tests may keep the GPU busy now and then
+        let idle_fraction = monitor.idle_fraction().unwrap();
+        println!("GPU idle fraction: {idle_fraction}");
+        assert!(idle_fraction > 0.5, "An idle machine should mostly report Off or Idle");
+    }
+}
+
+mod process_usage {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::hardware::iokit::{GpuProcessStat, MockIOKit};
+
+    #[test]
+    fn test_process_usage_through_iokit() {
+        let mut iokit = MockIOKit::new();
+        iokit.expect_get_gpu_process_stats().returning(|| {
+            Ok(vec![GpuProcessStat {
+                pid: 412,
+                name: "WindowServer".to_string(),
+                gpu_time_ns: 18_238_567_890,
+            }])
+        });
+
+        let gpu = Gpu::new().unwrap().with_shared_iokit(Arc::new(iokit));
+        let usage = gpu.process_usage().unwrap();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].pid, 412);
+        assert_eq!(usage[0].gpu_time_ns, 18_238_567_890);
+    }
+
+    #[test]
+    fn test_process_usage() {
+        let gpu = Gpu::new().unwrap();
+        let usage = gpu.process_usage();
+        assert!(usage.is_ok(), "Should be able to read the GPU usage of processes: {usage:?}");
+
+        let usage = usage.unwrap();
+        assert!(
+            usage.windows(2).all(|pair| pair[0].gpu_time_ns >= pair[1].gpu_time_ns),
+            "Processes should be ordered by GPU time"
+        );
+    }
+}
+
+#[test]
+fn test_enumerate() {
+    let gpus = Gpu::enumerate().unwrap();
+    let default = Gpu::new().unwrap();
+    if default.registry_id().is_none() {
+        println!("Metal not available, skipping");
+        return;
+    }
+    assert!(!gpus.is_empty(), "Metal should list the default GPU");
+
+    let mut ids: Vec<_> = gpus.iter().map(|gpu| gpu.registry_id()).collect();
+    assert!(ids.contains(&default.registry_id()), "The default GPU should be enumerated");
+    ids.sort_unstable();
+    ids.dedup();
+    assert_eq!(ids.len(), gpus.len(), "Each GPU should have its own registry entry");
+
+    for gpu in &gpus {
+        let name = gpu.name().unwrap();
+        println!(
+            "GPU {name} ({:#x?}): integrated {}, headless {}",
+            gpu.registry_id(),
+            gpu.is_integrated(),
+            gpu.is_headless()
+        );
+        assert!(!name.is_empty());
+
+        let memory = gpu.estimate_memory_info().unwrap();
+        assert!(memory.used <= memory.total);
+    }
+
+    if cfg!(target_arch = "aarch64") {
+        assert!(gpus.iter().all(Gpu::is_integrated), "Apple Silicon GPUs are integrated");
+    }
+}
@@ -0,0 +1,216 @@
+//! I/O statistics of block storage devices.
+//!
+//! Every physical disk has an `IOBlockStorageDriver` in the IORegistry that keeps cumulative I/O counters since the
+//! disk was attached in its `Statistics` dictionary. The media below the driver carry the BSD names: the whole disk
+//! (`disk0`), its partitions (`disk0s2`) and the APFS containers and volumes stored on them (`disk3`, `disk3s1`), so
+//! a volume can be traced to the physical disk its I/O goes to.
+
+use std::{
+    ffi::{c_void as ffi_c_void, CString},
+    ptr,
+};
+
+use objc2::{
+    class, msg_send,
+    rc::{autoreleasepool, Retained},
+};
+use objc2_foundation::{NSDictionary, NSNumber, NSObject, NSString};
+
+use super::IOKit;
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{
+        IOIteratorNext, IOObjectRelease, IORegistryEntryCreateIterator,
+        IORegistryEntrySearchCFProperty, IOServiceGetMatchingServices, IOServiceMatching,
+        IO_REGISTRY_ITERATE_PARENTS, IO_REGISTRY_ITERATE_RECURSIVELY,
+    },
+};
+
+/// Registry class of the drivers of block storage devices, which keep the I/O statistics
+const BLOCK_STORAGE_DRIVER_CLASS: &str = "IOBlockStorageDriver";
+/// Registry plane the device hierarchy is searched in
+const SERVICE_PLANE: &str = "IOService";
+/// Driver property holding the I/O statistics
+const STATISTICS_KEY: &str = "Statistics";
+const BYTES_READ_KEY: &str = "Bytes (Read)";
+const BYTES_WRITTEN_KEY: &str = "Bytes (Write)";
+const READS_KEY: &str = "Operations (Read)";
+const WRITES_KEY: &str = "Operations (Write)";
+/// Statistics holding the time spent on reads and writes, in nanoseconds
+const READ_TIME_KEY: &str = "Total Time (Read)";
+const WRITE_TIME_KEY: &str = "Total Time (Write)";
+/// Property of the media below a driver naming its BSD device
+const BSD_NAME_KEY: &str = "BSD Name";
+/// Property of the controller above a driver describing how the device is attached
+const PROTOCOL_CHARACTERISTICS_KEY: &str = "Protocol Characteristics";
+const INTERCONNECT_LOCATION_KEY: &str = "Physical Interconnect Location";
+
+/// Cumulative I/O counters of a physical disk, as kept by its block storage driver
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockStorageStats {
+    /// BSD name of the whole disk, e.g. `disk0`
+    pub device: String,
+    /// BSD names of the partitions, APFS containers and volumes stored on the disk
+    pub media: Vec<String>,
+    /// Whether the disk is built in, or None if it isn't reported
+    pub is_internal: Option<bool>,
+    /// Total bytes read
+    pub bytes_read: u64,
+    /// Total bytes written
+    pub bytes_written: u64,
+    /// Total read operations
+    pub reads: u64,
+    /// Total write operations
+    pub writes: u64,
+    /// Total time spent reading, in nanoseconds
+    pub read_time_ns: u64,
+    /// Total time spent writing, in nanoseconds
+    pub write_time_ns: u64,
+}
+
+impl BlockStorageStats {
+    /// Whether `bsd_name`, e.g. `disk3s1` or `/dev/disk3s1`, is the disk itself or stored on it.
+    pub fn contains(&self, bsd_name: &str) -> bool {
+        let bsd_name = bsd_name.strip_prefix("/dev/").unwrap_or(bsd_name);
+        self.device == bsd_name || self.media.iter().any(|media| media == bsd_name)
+    }
+}
+
+/// Reads the statistics of every block storage driver in the IORegistry.
+pub(crate) fn read_block_storage_stats<T: IOKit + ?Sized>(
+    io_kit: &T,
+) -> Result<Vec<BlockStorageStats>> {
+    let class = CString::new(BLOCK_STORAGE_DRIVER_CLASS)
+        .map_err(|_| Error::invalid_data("Invalid registry class name"))?;
+
+    autoreleasepool(|_| unsafe {
+        // IOServiceGetMatchingServices consumes the matching dictionary
+        let matching = IOServiceMatching(class.as_ptr());
+        if matching.is_null() {
+            return Err(Error::io_kit("Failed to create matching dictionary"));
+        }
+
+        let mut iterator = 0u32;
+        if IOServiceGetMatchingServices(0, matching, &mut iterator) != 0 {
+            return Err(Error::io_kit(format!(
+                "Failed to look up {BLOCK_STORAGE_DRIVER_CLASS} services"
+            )));
+        }
+
+        let mut stats = Vec::new();
+        loop {
+            let driver = IOIteratorNext(iterator);
+            if driver == 0 {
+                break;
+            }
+            if let Some(device) = driver_stats(io_kit, driver) {
+                stats.push(device);
+            }
+            IOObjectRelease(driver);
+        }
+        IOObjectRelease(iterator);
+
+        Ok(stats)
+    })
+}
+
+/// Reads the statistics, BSD names and location of a block storage driver.
+unsafe fn driver_stats<T: IOKit + ?Sized>(io_kit: &T, driver: u32) -> Option<BlockStorageStats> {
+    let props = io_kit.io_registry_entry_properties(driver).ok()?;
+    let statistics = as_dictionary(props.valueForKey(&NSString::from_str(STATISTICS_KEY))?)?;
+
+    let device = search_property(driver, BSD_NAME_KEY, IO_REGISTRY_ITERATE_RECURSIVELY)?
+        .downcast::<NSString>()
+        .ok()?
+        .to_string();
+    let is_internal = search_property(
+        driver,
+        PROTOCOL_CHARACTERISTICS_KEY,
+        IO_REGISTRY_ITERATE_RECURSIVELY | IO_REGISTRY_ITERATE_PARENTS,
+    )
+    .and_then(as_dictionary)
+    .and_then(|characteristics| {
+        characteristics
+            .valueForKey(&NSString::from_str(INTERCONNECT_LOCATION_KEY))?
+            .downcast::<NSString>()
+            .ok()
+    })
+    .map(|location| location.to_string() == "Internal");
+
+    let number = |key: &str| {
+        statistics
+            .valueForKey(&NSString::from_str(key))
+            .and_then(|value| value.downcast::<NSNumber>().ok())
+            .map_or(0, |value| value.as_u64())
+    };
+
+    let media = descendant_bsd_names(driver).into_iter().filter(|name| *name != device).collect();
+
+    Some(BlockStorageStats {
+        device,
+        media,
+        is_internal,
+        bytes_read: number(BYTES_READ_KEY),
+        bytes_written: number(BYTES_WRITTEN_KEY),
+        reads: number(READS_KEY),
+        writes: number(WRITES_KEY),
+        read_time_ns: number(READ_TIME_KEY),
+        write_time_ns: number(WRITE_TIME_KEY),
+    })
+}
+
+/// BSD names of every entry below `entry`, in registry order.
+unsafe fn descendant_bsd_names(entry: u32) -> Vec<String> {
+    let Ok(plane) = CString::new(SERVICE_PLANE) else {
+        return Vec::new();
+    };
+    let mut iterator = 0u32;
+    if IORegistryEntryCreateIterator(
+        entry,
+        plane.as_ptr(),
+        IO_REGISTRY_ITERATE_RECURSIVELY,
+        &mut iterator,
+    ) != 0
+    {
+        return Vec::new();
+    }
+
+    let mut names = Vec::new();
+    loop {
+        let descendant = IOIteratorNext(iterator);
+        if descendant == 0 {
+            break;
+        }
+        if let Some(name) = search_property(descendant, BSD_NAME_KEY, 0)
+            .and_then(|name| name.downcast::<NSString>().ok())
+        {
+            names.push(name.to_string());
+        }
+        IOObjectRelease(descendant);
+    }
+    IOObjectRelease(iterator);
+
+    names
+}
+
+/// Looks up a property on a registry entry or, depending on `options`, its children or parents.
+unsafe fn search_property(entry: u32, key: &str, options: u32) -> Option<Retained<NSObject>> {
+    let plane = CString::new(SERVICE_PLANE).ok()?;
+    let key = NSString::from_str(key);
+    let value = IORegistryEntrySearchCFProperty(
+        entry,
+        plane.as_ptr(),
+        Retained::as_ptr(&key) as *const ffi_c_void,
+        ptr::null(),
+        options,
+    );
+    // The value is returned with a +1 retain count
+    Retained::from_raw(value as *mut NSObject)
+}
+
+fn as_dictionary(obj: Retained<NSObject>) -> Option<Retained<NSDictionary<NSString, NSObject>>> {
+    let is_dict: bool = unsafe { msg_send![&obj, isKindOfClass: class!(NSDictionary)] };
+    // SAFETY: the object was just checked to be a dictionary, and registry dictionaries have string keys.
+    is_dict.then(|| unsafe { Retained::cast_unchecked(obj) })
+}
@@ -0,0 +1,516 @@
+//! IOKit handles that can be opened outside of the monitoring process
+//!
+//! Opening the SMC needs more privileges than reading it. Hardened deployments can run a small privileged broker that
+//! opens the connection, hands the raw handle to the monitoring process (e.g. over a Mach port) and drops its
+//! privileges. The monitoring process wraps the handle with [`SmcConnection::from_raw_connection`] and passes it to
+//! [`IOKitImpl::with_smc_connection`](super::IOKitImpl::with_smc_connection); every SMC read of that instance then goes
+//! over the handed-over connection, and the service lookup and open are skipped entirely.
+//!
+//! ```no_run
+//! use darwin_metrics::hardware::{
+//!     iokit::{IOKit, IOKitImpl, SmcConnection},
+//!     temperature::{Temperature, TemperatureConfig},
+//! };
+//!
+//! # fn receive_connection_from_broker() -> u32 { 0 }
+//! let connection = receive_connection_from_broker();
+//! // SAFETY: the broker handed over an open AppleSMC connection and no longer uses it
+//! let smc = unsafe { SmcConnection::from_raw_connection(connection) };
+//!
+//! let iokit = IOKitImpl::with_smc_connection(smc);
+//! println!("CPU temperature: {:?}", iokit.get_cpu_temperature());
+//! let temperature = Temperature::with_iokit(iokit, TemperatureConfig::default());
+//! ```
+//!
+//! The broker side opens the connection with [`SmcConnection::open`] and releases it without closing it with
+//! [`SmcConnection::into_raw`]. A handle that stays owned by someone else, e.g. the broker when both run in the same
+//! process, is wrapped with [`SmcConnection::from_borrowed_connection`] instead and isn't closed on drop.
+//!
+//! Battery readings work the same way with an [`IOServiceHandle`] of the `AppleSmartBattery` service, see
+//! [`Battery::with_service`](crate::battery::Battery::with_service).
+
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fmt,
+    mem::size_of,
+    os::raw::c_char,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
+
+use super::smc::{SmcKey, SmcKeyInfo, SmcValue};
+use crate::{
+    diagnostics,
+    error::{Error, Result},
+    utils::bindings::{
+        io_connect_t, io_service_t, smc_key_from_chars, IOByteCount, IOConnectCallStructMethod,
+        IOObjectRelease, IOServiceClose, IOServiceGetMatchingService, IOServiceMatching,
+        IOServiceOpen, SMCKeyData_t, IO_RETURN_IPC_ERROR, IO_RETURN_NOT_OPEN, IO_RETURN_NO_DEVICE,
+        IO_RETURN_SUCCESS, KERNEL_INDEX_SMC, MACH_SEND_INVALID_DEST, SMC_CMD_READ_BYTES,
+        SMC_CMD_READ_INDEX, SMC_CMD_READ_KEYINFO,
+    },
+};
+
+/// Whether dropping a wrapped handle releases it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HandleOwnership {
+    /// The wrapper releases the handle when dropped
+    Owned,
+    /// The handle belongs to someone else and is left open
+    Borrowed,
+}
+
+/// The IOKit calls an SMC connection is made of, replaceable in tests
+pub(crate) trait SmcBackend: Send + Sync + fmt::Debug {
+    /// Looks up the `AppleSMC` service and opens a connection to it.
+    fn open(&self) -> Result<io_connect_t>;
+    /// Calls `selector` of the SMC user client, returning the IOKit status.
+    fn call(
+        &self,
+        connection: io_connect_t,
+        selector: u8,
+        input: &SMCKeyData_t,
+        output: &mut SMCKeyData_t,
+    ) -> i32;
+    /// Closes a connection opened by [`open`](SmcBackend::open).
+    fn close(&self, connection: io_connect_t);
+}
+
+#[derive(Debug)]
+struct IOKitSmcBackend;
+
+impl SmcBackend for IOKitSmcBackend {
+    fn open(&self) -> Result<io_connect_t> {
+        // Skip the lookup on machines already known to have no SMC
+        if !crate::init::capabilities().smc {
+            return Err(Error::service_not_found("AppleSMC service not found"));
+        }
+
+        let service_name = CString::new("AppleSMC").expect("Failed to create CString");
+        unsafe {
+            let matching = IOServiceMatching(service_name.as_ptr());
+            if matching.is_null() {
+                return Err(Error::service_not_found("AppleSMC service not found"));
+            }
+
+            // IOServiceGetMatchingService consumes the matching dictionary
+            let service = IOServiceGetMatchingService(0, matching as *const _);
+            if service == 0 {
+                return Err(Error::service_not_found("AppleSMC service not found"));
+            }
+
+            let mut connection = 0;
+            let result = IOServiceOpen(service, 0, KERNEL_INDEX_SMC, &mut connection);
+            IOObjectRelease(service);
+            if result != IO_RETURN_SUCCESS {
+                return Err(Error::io_return("Failed to open SMC connection", result));
+            }
+
+            Ok(connection)
+        }
+    }
+
+    fn call(
+        &self,
+        connection: io_connect_t,
+        selector: u8,
+        input: &SMCKeyData_t,
+        output: &mut SMCKeyData_t,
+    ) -> i32 {
+        let mut output_size = IOByteCount(size_of::<SMCKeyData_t>());
+        unsafe {
+            IOConnectCallStructMethod(
+                connection,
+                selector as u32,
+                input,
+                IOByteCount(size_of::<SMCKeyData_t>()),
+                output,
+                &mut output_size,
+            )
+        }
+    }
+
+    fn close(&self, connection: io_connect_t) {
+        unsafe {
+            IOServiceClose(connection);
+        }
+    }
+}
+
+/// Statuses of a call over a connection that no longer reaches the SMC
+const STALE_STATUSES: [i32; 4] =
+    [IO_RETURN_NOT_OPEN, IO_RETURN_NO_DEVICE, IO_RETURN_IPC_ERROR, MACH_SEND_INVALID_DEST];
+
+/// A connection to the System Management Controller
+///
+/// An owned connection is closed when dropped; a borrowed one is left open for its owner. The data type and size of
+/// each key are read once per connection, so reading a key again takes a single call.
+#[derive(Debug)]
+pub struct SmcConnection {
+    connection: io_connect_t,
+    ownership: HandleOwnership,
+    backend: Arc<dyn SmcBackend>,
+    key_infos: Mutex<HashMap<SmcKey, SmcKeyInfo>>,
+    stale: AtomicBool,
+}
+
+impl SmcConnection {
+    /// Opens a connection to the `AppleSMC` service.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the machine has no SMC or the connection can't be opened, e.g. for lack of privileges.
+    pub fn open() -> Result<Self> {
+        Self::open_with(Arc::new(IOKitSmcBackend))
+    }
+
+    /// Wraps a connection opened elsewhere, taking ownership of it: it's closed when the wrapper is dropped.
+    ///
+    /// # Safety
+    ///
+    /// `connection` must be an open connection to the `AppleSMC` service of this task's IPC space, opened with the SMC
+    /// user client type (`2`), and nothing else may close it or use it after this call.
+    pub unsafe fn from_raw_connection(connection: io_connect_t) -> Self {
+        Self::with_backend(connection, HandleOwnership::Owned, Arc::new(IOKitSmcBackend))
+    }
+
+    /// Wraps a connection that stays owned by the caller: it isn't closed when the wrapper is dropped.
+    ///
+    /// # Safety
+    ///
+    /// `connection` must be an open connection to the `AppleSMC` service of this task's IPC space, opened with the SMC
+    /// user client type (`2`), and must stay open for as long as the wrapper and every `IOKitImpl` using it live.
+    pub unsafe fn from_borrowed_connection(connection: io_connect_t) -> Self {
+        Self::with_backend(connection, HandleOwnership::Borrowed, Arc::new(IOKitSmcBackend))
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub(crate) fn open_with(backend: Arc<dyn SmcBackend>) -> Result<Self> {
+        let connection = backend.open()?;
+        Ok(Self::with_backend(connection, HandleOwnership::Owned, backend))
+    }
+
+    pub(crate) fn with_backend(
+        connection: io_connect_t,
+        ownership: HandleOwnership,
+        backend: Arc<dyn SmcBackend>,
+    ) -> Self {
+        if ownership == HandleOwnership::Owned {
+            diagnostics::counter(diagnostics::SMC_CONNECTIONS).increment();
+        }
+        Self {
+            connection,
+            ownership,
+            backend,
+            key_infos: Mutex::new(HashMap::new()),
+            stale: AtomicBool::new(false),
+        }
+    }
+
+    /// Releases the connection without closing it, e.g. to hand it to another process.
+    pub fn into_raw(mut self) -> io_connect_t {
+        if self.ownership == HandleOwnership::Owned {
+            diagnostics::counter(diagnostics::SMC_CONNECTIONS).decrement();
+        }
+        self.ownership = HandleOwnership::Borrowed;
+        self.connection
+    }
+
+    /// The raw connection handle, which stays owned by the wrapper.
+    pub fn as_raw(&self) -> io_connect_t {
+        self.connection
+    }
+
+    /// Whether the connection is closed when the wrapper is dropped.
+    pub fn ownership(&self) -> HandleOwnership {
+        self.ownership
+    }
+
+    /// Whether a call failed because the connection no longer reaches the SMC, e.g. after the service restarted.
+    ///
+    /// A stale connection stays stale; reading the SMC again needs a new connection.
+    pub fn is_stale(&self) -> bool {
+        self.stale.load(Ordering::Relaxed)
+    }
+
+    /// Reads the value of an SMC key as a number.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key doesn't exist or holds raw bytes or a data type [`SmcValue`] doesn't know.
+    pub fn read_key(&self, key: [c_char; 4]) -> Result<f64> {
+        let (data_type, bytes) = self.read_key_bytes(key)?;
+        decode_smc_value(&data_type, &bytes).ok_or_else(|| {
+            Error::invalid_data(format!(
+                "Unsupported SMC data type: {:?}",
+                std::str::from_utf8(&data_type).unwrap_or("Unknown")
+            ))
+        })
+    }
+
+    /// Reads the value of an SMC key in its own data type.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key doesn't exist or has a data type [`SmcValue`] doesn't know.
+    pub fn read_key_typed(&self, key: SmcKey) -> Result<SmcValue> {
+        let (data_type, bytes) = self.read_key_bytes(key.as_bytes().map(|byte| byte as c_char))?;
+        SmcValue::decode(&data_type, &bytes)
+    }
+
+    /// Reads the data type and the raw bytes of an SMC key, for keys holding structures such as fan descriptors.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key doesn't exist.
+    #[tracing::instrument(level = "trace", skip(self, key), fields(key = %SmcKey::from(key)))]
+    pub fn read_key_bytes(&self, key: [c_char; 4]) -> Result<([u8; 4], Vec<u8>)> {
+        let info = self.key_info(SmcKey::from(key))?;
+
+        let input = smc_request(smc_key_from_chars(key));
+        let output = self.command(SMC_CMD_READ_BYTES, &input, "key data")?;
+
+        // SAFETY: every interpretation of the data union is plain bytes
+        let bytes = unsafe { output.data.bytes };
+        let data_size = (info.size as usize).min(bytes.len());
+
+        Ok((info.data_type, bytes[..data_size].to_vec()))
+    }
+
+    /// Reads the data type and size of an SMC key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key doesn't exist.
+    pub fn key_info(&self, key: SmcKey) -> Result<SmcKeyInfo> {
+        let key_infos = || self.key_infos.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(info) = key_infos().get(&key) {
+            return Ok(*info);
+        }
+
+        let mut input = smc_request(key.code());
+        input.key_info = 1;
+        let output = self.command(SMC_CMD_READ_KEYINFO, &input, "key info")?;
+
+        // SAFETY: the SMC filled in the key info, and every interpretation of the data union is plain bytes
+        let info = unsafe { output.data.key_info };
+        let info = SmcKeyInfo { key, data_type: info.data_type, size: info.data_size };
+        key_infos().insert(key, info);
+        Ok(info)
+    }
+
+    /// Lists every key of the SMC with its data type and size.
+    ///
+    /// The SMC counts its keys in `#KEY` and names the key at each index. Keys whose info can't be read are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the number of keys or the key at an index can't be read.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn list_keys(&self) -> Result<Vec<SmcKeyInfo>> {
+        let count = match self.read_key_typed(SMC_KEY_COUNT)? {
+            SmcValue::U32(count) => count,
+            value => {
+                return Err(Error::invalid_data(format!("Unexpected SMC key count: {value:?}")))
+            },
+        };
+
+        let mut keys = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let mut input = smc_request(0);
+            input.data.uint32 = index;
+            let key = SmcKey::from_code(self.command(SMC_CMD_READ_INDEX, &input, "key index")?.key);
+            keys.extend(self.key_info(key).ok());
+        }
+        Ok(keys)
+    }
+
+    /// Sends the command `selector` with `input` and returns the answer of the SMC.
+    fn command(&self, selector: u8, input: &SMCKeyData_t, what: &str) -> Result<SMCKeyData_t> {
+        let mut output = *input;
+        let result = self.backend.call(self.connection, selector, input, &mut output);
+        if result != IO_RETURN_SUCCESS {
+            if STALE_STATUSES.contains(&result) {
+                self.stale.store(true, Ordering::Relaxed);
+            }
+            return Err(Error::io_return(format!("Failed to read SMC {what}"), result));
+        }
+        Ok(output)
+    }
+}
+
+/// An SMC connection opened on first use and kept for the following reads
+///
+/// A connection that went stale is closed and replaced by a new one, so a restarted SMC service doesn't end the reads.
+/// Coverage runs don't open the SMC, so they leave it out.
+#[cfg(not(feature = "skip-ffi-crashes"))]
+#[derive(Debug)]
+pub(crate) struct SharedSmc {
+    backend: Arc<dyn SmcBackend>,
+    connection: Mutex<Option<SmcConnection>>,
+}
+
+#[cfg(not(feature = "skip-ffi-crashes"))]
+impl Default for SharedSmc {
+    fn default() -> Self {
+        Self::new(Arc::new(IOKitSmcBackend))
+    }
+}
+
+#[cfg(not(feature = "skip-ffi-crashes"))]
+impl SharedSmc {
+    /// A connection opened with `backend` once it's first used.
+    pub(crate) fn new(backend: Arc<dyn SmcBackend>) -> Self {
+        Self { backend, connection: Mutex::new(None) }
+    }
+
+    /// Runs `read` over the connection, opening it first if needed.
+    ///
+    /// A read that fails because the connection went stale is run once more over a new connection.
+    pub(crate) fn with<R>(&self, read: impl Fn(&SmcConnection) -> Result<R>) -> Result<R> {
+        let mut connection = self.connection.lock().unwrap_or_else(PoisonError::into_inner);
+        match read(self.connect(&mut connection)?) {
+            Err(_) if connection.as_ref().is_some_and(SmcConnection::is_stale) => {
+                // Dropping the stale connection closes it
+                *connection = None;
+                read(self.connect(&mut connection)?)
+            },
+            result => result,
+        }
+    }
+
+    /// The open connection, opening it if there is none.
+    fn connect<'a>(&self, connection: &'a mut Option<SmcConnection>) -> Result<&'a SmcConnection> {
+        match connection {
+            Some(connection) => Ok(connection),
+            None => Ok(connection.insert(SmcConnection::open_with(self.backend.clone())?)),
+        }
+    }
+}
+
+/// Key holding the number of keys of the SMC
+const SMC_KEY_COUNT: SmcKey = SmcKey::new(*b"#KEY");
+
+/// An empty request about the SMC key `key`.
+fn smc_request(key: u32) -> SMCKeyData_t {
+    SMCKeyData_t {
+        key,
+        vers: 0,
+        p_limit_data: 0,
+        key_info: 0,
+        padding: 0,
+        result: 0,
+        status: 0,
+        data8: 0,
+        data32: 0,
+        bytes: [0; 2],
+        // SAFETY: the data union is plain bytes, for which zero is valid
+        data: unsafe { std::mem::zeroed() },
+    }
+}
+
+/// Decodes the bytes of an SMC value of type `data_type` as a number, None for raw bytes, unknown types or too few
+/// bytes.
+pub(crate) fn decode_smc_value(data_type: &[u8; 4], bytes: &[u8]) -> Option<f64> {
+    match data_type {
+        // Not a type the SMC reports, but always read as a native-endian integer
+        b"uint" => Some(f64::from(u32::from_ne_bytes(bytes.get(..4)?.try_into().ok()?))),
+        _ => SmcValue::decode(data_type, bytes).ok()?.as_f64(),
+    }
+}
+
+impl Drop for SmcConnection {
+    fn drop(&mut self) {
+        if self.ownership == HandleOwnership::Owned {
+            self.backend.close(self.connection);
+            diagnostics::counter(diagnostics::SMC_CONNECTIONS).decrement();
+        }
+    }
+}
+
+/// A service in the IORegistry, e.g. `AppleSmartBattery`, resolved elsewhere
+///
+/// An owned handle is released when dropped; a borrowed one is left to its owner.
+pub struct IOServiceHandle {
+    service: io_service_t,
+    ownership: HandleOwnership,
+    release: fn(io_service_t),
+}
+
+impl IOServiceHandle {
+    /// Wraps a service handle resolved elsewhere, taking ownership of it: it's released when the wrapper is dropped.
+    ///
+    /// # Safety
+    ///
+    /// `service` must be a valid service handle of this task's IPC space, and nothing else may release it after this
+    /// call.
+    pub unsafe fn from_raw_service(service: io_service_t) -> Self {
+        Self::with_release(service, HandleOwnership::Owned, release_service)
+    }
+
+    /// Wraps a service handle that stays owned by the caller: it isn't released when the wrapper is dropped.
+    ///
+    /// # Safety
+    ///
+    /// `service` must be a valid service handle of this task's IPC space for as long as the wrapper lives.
+    pub unsafe fn from_borrowed_service(service: io_service_t) -> Self {
+        Self::with_release(service, HandleOwnership::Borrowed, release_service)
+    }
+
+    pub(crate) fn with_release(
+        service: io_service_t,
+        ownership: HandleOwnership,
+        release: fn(io_service_t),
+    ) -> Self {
+        if ownership == HandleOwnership::Owned {
+            diagnostics::counter(diagnostics::SERVICE_HANDLES).increment();
+        }
+        Self { service, ownership, release }
+    }
+
+    /// Releases the handle without releasing the service, e.g. to hand it to another process.
+    pub fn into_raw(mut self) -> io_service_t {
+        if self.ownership == HandleOwnership::Owned {
+            diagnostics::counter(diagnostics::SERVICE_HANDLES).decrement();
+        }
+        self.ownership = HandleOwnership::Borrowed;
+        self.service
+    }
+
+    /// The raw service handle, which stays owned by the wrapper.
+    pub fn as_raw(&self) -> io_service_t {
+        self.service
+    }
+
+    /// Whether the service is released when the wrapper is dropped.
+    pub fn ownership(&self) -> HandleOwnership {
+        self.ownership
+    }
+}
+
+impl fmt::Debug for IOServiceHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IOServiceHandle")
+            .field("service", &self.service)
+            .field("ownership", &self.ownership)
+            .finish()
+    }
+}
+
+impl Drop for IOServiceHandle {
+    fn drop(&mut self) {
+        if self.ownership == HandleOwnership::Owned {
+            (self.release)(self.service);
+            diagnostics::counter(diagnostics::SERVICE_HANDLES).decrement();
+        }
+    }
+}
+
+fn release_service(service: io_service_t) {
+    unsafe {
+        IOObjectRelease(service);
+    }
+}
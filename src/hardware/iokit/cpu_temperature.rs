@@ -0,0 +1,394 @@
+//! CPU temperature fallback chain.
+//!
+//! Intel Macs and some Apple Silicon models report the CPU temperature through the SMC key `TC0P`, but many M-series
+//! machines don't have it. On those, the temperature is read from the thermal sensor entries in the IORegistry, and,
+//! with the `hid-sensors` feature, from the HID temperature sensors that tools like Stats use.
+//!
+//! Besides the single CPU temperature, the SMC has a sensor per core or core cluster on most machines. Which keys exist
+//! differs between models, so the candidate keys of the architecture are probed and the missing ones skipped.
+
+use std::{
+    ffi::{c_void as ffi_c_void, CStr, CString},
+    os::raw::c_char,
+    ptr,
+};
+
+use objc2::rc::{autoreleasepool, Retained};
+use objc2_foundation::{NSDictionary, NSNumber, NSObject, NSString};
+
+use super::IOKit;
+use crate::{
+    error::{Error, Result},
+    system::Architecture,
+    utils::bindings::{
+        IOIteratorNext, IOObjectRelease, IORegistryEntryCreateCFProperties, IORegistryEntryGetName,
+        IOServiceGetMatchingServices, IOServiceMatching, SMC_KEY_CPU_TEMP,
+    },
+};
+
+/// Registry class of the on-chip devices of Apple Silicon, which include the power manager (`pmgr`)
+const ARM_IO_DEVICE_CLASS: &str = "AppleARMIODevice";
+/// Registry property holding a sensor temperature
+const TEMPERATURE_KEY: &str = "Temperature";
+/// Range of temperatures accepted from the fallback sources, in degrees Celsius
+pub(crate) const PLAUSIBLE_RANGE: std::ops::RangeInclusive<f64> = 10.0..=120.0;
+/// Name fragments of sensors that measure the CPU die or clusters
+const CPU_SENSOR_NAMES: [&str; 6] = ["tdie", "pacc", "eacc", "cpu", "pmgr", "soc"];
+/// SMC keys of the CPU sensors of Intel Macs: proximity, die and package sensors, then one per core
+const INTEL_CPU_KEYS: [&str; 13] = [
+    "TC0P", "TC0D", "TC0E", "TC0F", "TC0H", "TC1C", "TC2C", "TC3C", "TC4C", "TC5C", "TC6C", "TC7C",
+    "TC8C",
+];
+/// SMC keys of the CPU sensors of Apple Silicon: the core clusters of M1 and M2 (`Tp`), then the efficiency (`Te`)
+/// and performance (`Tf`) cores of M3
+const APPLE_SILICON_CPU_KEYS: [&str; 32] = [
+    "Tp01", "Tp05", "Tp09", "Tp0D", "Tp0H", "Tp0L", "Tp0P", "Tp0T", "Tp0X", "Tp0b", "Tp0f", "Tp0j",
+    "Tp1h", "Tp1l", "Tp1p", "Tp1t", "Te05", "Te0L", "Te0P", "Te0S", "Tf04", "Tf09", "Tf0A", "Tf0B",
+    "Tf0D", "Tf0E", "Tf44", "Tf49", "Tf4A", "Tf4B", "Tf4D", "Tf4E",
+];
+
+/// The source a CPU temperature was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CpuTemperatureSource {
+    /// The SMC `TC0P` key
+    #[default]
+    Smc,
+    /// Thermal sensor entries in the IORegistry
+    IORegistry,
+    /// HID temperature sensors (requires the `hid-sensors` feature)
+    Hid,
+}
+
+/// A temperature reported by a named sensor
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorReading {
+    /// Sensor name, e.g. `PMU tdie1`
+    pub name: String,
+    /// Temperature in degrees Celsius
+    pub celsius: f64,
+}
+
+impl SensorReading {
+    /// Creates a new reading.
+    pub fn new(name: impl Into<String>, celsius: f64) -> Self {
+        Self { name: name.into(), celsius }
+    }
+}
+
+/// A temperature read from an SMC sensor
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NamedTemperature {
+    /// SMC key of the sensor, e.g. `Tp01`
+    pub sensor: String,
+    /// Temperature in degrees Celsius
+    pub celsius: f64,
+}
+
+/// A single step of the CPU temperature fallback chain
+pub trait CpuTemperatureLayer {
+    /// The source this layer reads from.
+    fn source(&self) -> CpuTemperatureSource;
+
+    /// Reads the CPU temperature in degrees Celsius.
+    fn read(&self) -> Result<f64>;
+}
+
+/// Provides raw sensor readings for a [`SensorLayer`]
+pub trait SensorProvider {
+    /// Reads all sensors the provider knows about.
+    fn readings(&self) -> Result<Vec<SensorReading>>;
+}
+
+/// Reads the CPU temperature from the SMC
+#[derive(Debug)]
+pub struct SmcLayer<'a, T: IOKit + ?Sized> {
+    io_kit: &'a T,
+}
+
+impl<'a, T: IOKit + ?Sized> SmcLayer<'a, T> {
+    /// Creates a layer reading through `io_kit`.
+    pub fn new(io_kit: &'a T) -> Self {
+        Self { io_kit }
+    }
+}
+
+impl<T: IOKit + ?Sized> CpuTemperatureLayer for SmcLayer<'_, T> {
+    fn source(&self) -> CpuTemperatureSource {
+        CpuTemperatureSource::Smc
+    }
+
+    fn read(&self) -> Result<f64> {
+        self.io_kit.read_smc_key(SMC_KEY_CPU_TEMP)
+    }
+}
+
+/// Derives the CPU temperature from the readings of a [`SensorProvider`]
+#[derive(Debug)]
+pub struct SensorLayer<P> {
+    source: CpuTemperatureSource,
+    provider: P,
+}
+
+impl<P: SensorProvider> SensorLayer<P> {
+    /// Creates a layer reporting readings of `provider` as coming from `source`.
+    pub fn new(source: CpuTemperatureSource, provider: P) -> Self {
+        Self { source, provider }
+    }
+}
+
+impl<P: SensorProvider> CpuTemperatureLayer for SensorLayer<P> {
+    fn source(&self) -> CpuTemperatureSource {
+        self.source
+    }
+
+    fn read(&self) -> Result<f64> {
+        let readings = self.provider.readings()?;
+        cpu_temperature_from_readings(&readings).ok_or_else(|| {
+            Error::not_available(format!("No CPU temperature sensor found via {:?}", self.source))
+        })
+    }
+}
+
+/// Averages the plausible readings of CPU sensors.
+pub(crate) fn cpu_temperature_from_readings(readings: &[SensorReading]) -> Option<f64> {
+    let cpu_temperatures: Vec<f64> = readings
+        .iter()
+        .filter(|reading| {
+            is_cpu_sensor(&reading.name) && PLAUSIBLE_RANGE.contains(&reading.celsius)
+        })
+        .map(|reading| reading.celsius)
+        .collect();
+
+    if cpu_temperatures.is_empty() {
+        return None;
+    }
+    Some(cpu_temperatures.iter().sum::<f64>() / cpu_temperatures.len() as f64)
+}
+
+fn is_cpu_sensor(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    CPU_SENSOR_NAMES.iter().any(|fragment| name.contains(fragment))
+}
+
+/// Returns the temperature of the first layer that succeeds, along with the source it came from.
+///
+/// # Errors
+///
+/// Returns an error listing the failure of every layer if none succeeds.
+pub fn read_cpu_temperature(
+    layers: &[&dyn CpuTemperatureLayer],
+) -> Result<(f64, CpuTemperatureSource)> {
+    let mut failures = Vec::with_capacity(layers.len());
+
+    for layer in layers {
+        match layer.read() {
+            Ok(temperature) => return Ok((temperature, layer.source())),
+            Err(e) => failures.push(format!("{:?}: {e}", layer.source())),
+        }
+    }
+
+    Err(Error::not_available(format!("CPU temperature unavailable ({})", failures.join("; "))))
+}
+
+/// Reads the thermal sensor entries below the on-chip devices in the IORegistry
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RegistryThermalSensors;
+
+impl SensorProvider for RegistryThermalSensors {
+    fn readings(&self) -> Result<Vec<SensorReading>> {
+        let class = CString::new(ARM_IO_DEVICE_CLASS)
+            .map_err(|_| Error::invalid_data("Invalid registry class name"))?;
+
+        autoreleasepool(|_| unsafe {
+            // IOServiceGetMatchingServices consumes the matching dictionary
+            let matching = IOServiceMatching(class.as_ptr());
+            if matching.is_null() {
+                return Err(Error::io_kit("Failed to create matching dictionary"));
+            }
+
+            let mut iterator = 0u32;
+            if IOServiceGetMatchingServices(0, matching, &mut iterator) != 0 {
+                return Err(Error::service_not_found(format!("No {ARM_IO_DEVICE_CLASS} services")));
+            }
+
+            let mut readings = Vec::new();
+            loop {
+                let entry = IOIteratorNext(iterator);
+                if entry == 0 {
+                    break;
+                }
+                if let Some(reading) = registry_reading(entry) {
+                    readings.push(reading);
+                }
+                IOObjectRelease(entry);
+            }
+            IOObjectRelease(iterator);
+
+            Ok(readings)
+        })
+    }
+}
+
+/// Reads the temperature property of a registry entry, if it has one.
+unsafe fn registry_reading(entry: u32) -> Option<SensorReading> {
+    let mut name = [0 as c_char; 128];
+    if IORegistryEntryGetName(entry, name.as_mut_ptr()) != 0 {
+        return None;
+    }
+    let name = CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+
+    let mut props: *mut ffi_c_void = ptr::null_mut();
+    if IORegistryEntryCreateCFProperties(entry, &mut props, ptr::null_mut(), 0) != 0 {
+        return None;
+    }
+    // The properties are returned with a +1 retain count and are toll-free bridged to NSDictionary
+    let props = Retained::from_raw(props as *mut NSDictionary<NSString, NSObject>)?;
+
+    let value = props
+        .valueForKey(&NSString::from_str(TEMPERATURE_KEY))?
+        .downcast::<NSNumber>()
+        .ok()?
+        .as_f64();
+
+    Some(SensorReading::new(name, normalize_registry_temperature(value)))
+}
+
+/// Registry temperatures are reported either in degrees or in hundredths of a degree.
+pub(crate) fn normalize_registry_temperature(value: f64) -> f64 {
+    if value > *PLAUSIBLE_RANGE.end() {
+        value / 100.0
+    } else {
+        value
+    }
+}
+
+/// Reads the HID temperature sensors of Apple Silicon through the private IOHIDEventSystem API
+#[cfg(feature = "hid-sensors")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HidThermalSensors;
+
+#[cfg(feature = "hid-sensors")]
+impl SensorProvider for HidThermalSensors {
+    fn readings(&self) -> Result<Vec<SensorReading>> {
+        use objc2::{msg_send, runtime::AnyObject};
+
+        use crate::utils::bindings::{
+            CFRelease, IOHIDEventGetFloatValue, IOHIDEventSystemClientCopyServices,
+            IOHIDEventSystemClientCreate, IOHIDEventSystemClientSetMatching,
+            IOHIDServiceClientCopyEvent, IOHIDServiceClientCopyProperty,
+            IOHID_EVENT_TYPE_TEMPERATURE, IOHID_THERMAL_USAGE, IOHID_THERMAL_USAGE_PAGE,
+        };
+
+        autoreleasepool(|_| unsafe {
+            let client = IOHIDEventSystemClientCreate(ptr::null());
+            if client.is_null() {
+                return Err(Error::io_kit("Failed to create HID event system client"));
+            }
+
+            let keys = [NSString::from_str("PrimaryUsagePage"), NSString::from_str("PrimaryUsage")];
+            let values = [
+                NSNumber::new_i32(IOHID_THERMAL_USAGE_PAGE),
+                NSNumber::new_i32(IOHID_THERMAL_USAGE),
+            ];
+            let matching =
+                NSDictionary::from_slices(&[&*keys[0], &*keys[1]], &[&*values[0], &*values[1]]);
+            IOHIDEventSystemClientSetMatching(
+                client,
+                Retained::as_ptr(&matching) as *const ffi_c_void,
+            );
+
+            let services = IOHIDEventSystemClientCopyServices(client);
+            if services.is_null() {
+                CFRelease(client);
+                return Err(Error::service_not_found("No HID temperature sensors"));
+            }
+
+            let product_key = NSString::from_str("Product");
+            let count: usize = msg_send![services as *mut AnyObject, count];
+            let mut readings = Vec::with_capacity(count);
+
+            for i in 0..count {
+                let service: *mut AnyObject =
+                    msg_send![services as *mut AnyObject, objectAtIndex: i];
+                let service = service as *mut ffi_c_void;
+
+                let name = IOHIDServiceClientCopyProperty(
+                    service,
+                    Retained::as_ptr(&product_key) as *const ffi_c_void,
+                );
+                let Some(name) = Retained::from_raw(name as *mut NSString) else {
+                    continue;
+                };
+
+                let event =
+                    IOHIDServiceClientCopyEvent(service, IOHID_EVENT_TYPE_TEMPERATURE, 0, 0);
+                if event.is_null() {
+                    continue;
+                }
+                let field = (IOHID_EVENT_TYPE_TEMPERATURE as i32) << 16;
+                let celsius = IOHIDEventGetFloatValue(event, field);
+                CFRelease(event);
+
+                readings.push(SensorReading::new(name.to_string(), celsius));
+            }
+
+            CFRelease(services);
+            CFRelease(client);
+            Ok(readings)
+        })
+    }
+}
+
+/// Reads the CPU temperature through the default fallback chain: SMC, IORegistry and, with the `hid-sensors`
+/// feature, the HID sensors.
+pub(crate) fn read_with_fallback<T: IOKit + ?Sized>(
+    io_kit: &T,
+) -> Result<(f64, CpuTemperatureSource)> {
+    let smc = SmcLayer::new(io_kit);
+    let registry = SensorLayer::new(CpuTemperatureSource::IORegistry, RegistryThermalSensors);
+
+    #[cfg(feature = "hid-sensors")]
+    {
+        let hid = SensorLayer::new(CpuTemperatureSource::Hid, HidThermalSensors);
+        read_cpu_temperature(&[&smc, &registry, &hid])
+    }
+
+    #[cfg(not(feature = "hid-sensors"))]
+    read_cpu_temperature(&[&smc, &registry])
+}
+
+/// The SMC keys of the CPU sensors a machine of `architecture` may have, those of both architectures if it's unknown.
+pub(crate) fn cpu_sensor_keys(architecture: Architecture) -> Vec<&'static str> {
+    match architecture {
+        Architecture::Intel => INTEL_CPU_KEYS.to_vec(),
+        Architecture::AppleSilicon => APPLE_SILICON_CPU_KEYS.to_vec(),
+        Architecture::Unknown => {
+            INTEL_CPU_KEYS.iter().chain(&APPLE_SILICON_CPU_KEYS).copied().collect()
+        },
+    }
+}
+
+/// Reads the SMC sensors `keys`, leaving out the ones that don't exist or don't hold a plausible temperature.
+///
+/// Every machine only has some of the candidate keys, and sensors of unused cores read 0.
+pub(crate) fn read_cpu_sensors<T: IOKit + ?Sized>(
+    io_kit: &T,
+    keys: &[&str],
+) -> Vec<NamedTemperature> {
+    keys.iter()
+        .filter_map(|&key| {
+            let smc_key = <[u8; 4]>::try_from(key.as_bytes()).ok()?.map(|byte| byte as c_char);
+            let celsius = io_kit.read_smc_key(smc_key).ok()?;
+            PLAUSIBLE_RANGE
+                .contains(&celsius)
+                .then(|| NamedTemperature { sensor: key.to_string(), celsius })
+        })
+        .collect()
+}
+
+/// The highest of `temperatures`, None if there are none.
+pub(crate) fn max_temperature(temperatures: &[NamedTemperature]) -> Option<f64> {
+    temperatures.iter().map(|temperature| temperature.celsius).reduce(f64::max)
+}
@@ -0,0 +1,177 @@
+//! GPU time of each process.
+//!
+//! Every process using the GPU opens a user client on the `IOAccelerator` service of the driver. The client entries
+//! are children of the accelerator in the IORegistry and name the process that created them in
+//! `IOUserClientCreator`, e.g. `pid 412, WindowServer`. The Apple Silicon drivers also publish an `AppUsage` array
+//! with the GPU time each API submitted from the client, in nanoseconds since the client was opened; this is what
+//! tools like asitop read.
+//!
+//! Clients without `AppUsage`, as on Intel and AMD GPUs, don't tell how much GPU time they used and are left out.
+
+use std::{collections::HashMap, ffi::CString};
+
+use objc2::{
+    class, msg_send,
+    rc::{autoreleasepool, Retained},
+    runtime::AnyObject,
+};
+use objc2_foundation::{NSDictionary, NSNumber, NSObject, NSString};
+
+use super::IOKit;
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{
+        IOIteratorNext, IOObjectRelease, IORegistryEntryGetChildIterator,
+        IOServiceGetMatchingServices, IOServiceMatching,
+    },
+};
+
+/// Registry class every GPU driver's accelerator service inherits from
+const ACCELERATOR_CLASS: &str = "IOAccelerator";
+/// Registry plane the user clients are attached to their accelerator in
+const SERVICE_PLANE: &str = "IOService";
+/// User client property naming the process that opened it
+pub(crate) const USER_CLIENT_CREATOR_KEY: &str = "IOUserClientCreator";
+/// User client property holding the GPU usage per API
+pub(crate) const APP_USAGE_KEY: &str = "AppUsage";
+/// `AppUsage` entry property holding the accumulated GPU time in nanoseconds
+pub(crate) const ACCUMULATED_GPU_TIME_KEY: &str = "accumulatedGPUTime";
+
+/// GPU time used by a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GpuProcessStat {
+    /// Process ID
+    pub pid: u32,
+    /// Process name as recorded by the driver
+    pub name: String,
+    /// GPU time accumulated by the open clients of the process, in nanoseconds
+    pub gpu_time_ns: u64,
+}
+
+/// Parses an `IOUserClientCreator` value, e.g. `pid 412, WindowServer`, into the process ID and name.
+pub(crate) fn parse_client_creator(creator: &str) -> Option<(u32, String)> {
+    let rest = creator.strip_prefix("pid ")?;
+    let (pid, name) = rest.split_once(',').unwrap_or((rest, ""));
+    Some((pid.trim().parse().ok()?, name.trim().to_string()))
+}
+
+/// The GPU time of one user client, given its creator and the accumulated time of each `AppUsage` entry, or None
+/// without a creator or any usage.
+pub(crate) fn client_stat(
+    creator: Option<&str>,
+    gpu_times: impl IntoIterator<Item = u64>,
+) -> Option<GpuProcessStat> {
+    let (pid, name) = parse_client_creator(creator?)?;
+    let mut gpu_times = gpu_times.into_iter().peekable();
+    gpu_times.peek()?;
+
+    Some(GpuProcessStat { pid, name, gpu_time_ns: gpu_times.fold(0, u64::saturating_add) })
+}
+
+/// Sums the clients of each process, ordered by GPU time, highest first.
+pub(crate) fn merge_clients(
+    clients: impl IntoIterator<Item = GpuProcessStat>,
+) -> Vec<GpuProcessStat> {
+    let mut by_pid: HashMap<u32, GpuProcessStat> = HashMap::new();
+    for client in clients {
+        by_pid
+            .entry(client.pid)
+            .and_modify(|stat| {
+                stat.gpu_time_ns = stat.gpu_time_ns.saturating_add(client.gpu_time_ns)
+            })
+            .or_insert(client);
+    }
+
+    let mut stats: Vec<_> = by_pid.into_values().collect();
+    stats.sort_by(|a, b| b.gpu_time_ns.cmp(&a.gpu_time_ns).then(a.pid.cmp(&b.pid)));
+    stats
+}
+
+/// Reads the GPU time of every process with a client on any accelerator.
+pub(crate) fn read_gpu_process_stats<T: IOKit + ?Sized>(io_kit: &T) -> Result<Vec<GpuProcessStat>> {
+    let class_name = CString::new(ACCELERATOR_CLASS)
+        .map_err(|_| Error::invalid_data("Invalid registry class name"))?;
+    let plane = CString::new(SERVICE_PLANE)
+        .map_err(|_| Error::invalid_data("Invalid registry plane name"))?;
+
+    autoreleasepool(|_| unsafe {
+        // IOServiceGetMatchingServices consumes the matching dictionary
+        let matching = IOServiceMatching(class_name.as_ptr());
+        if matching.is_null() {
+            return Err(Error::io_kit("Failed to create matching dictionary"));
+        }
+
+        let mut accelerators = 0u32;
+        if IOServiceGetMatchingServices(0, matching, &mut accelerators) != 0 {
+            return Err(Error::io_kit("Failed to look up accelerator services"));
+        }
+
+        let mut clients = Vec::new();
+        loop {
+            let accelerator = IOIteratorNext(accelerators);
+            if accelerator == 0 {
+                break;
+            }
+
+            let mut children = 0u32;
+            if IORegistryEntryGetChildIterator(accelerator, plane.as_ptr(), &mut children) == 0 {
+                loop {
+                    let child = IOIteratorNext(children);
+                    if child == 0 {
+                        break;
+                    }
+                    clients.extend(registry_client_stat(io_kit, child));
+                    IOObjectRelease(child);
+                }
+                IOObjectRelease(children);
+            }
+            IOObjectRelease(accelerator);
+        }
+        IOObjectRelease(accelerators);
+
+        Ok(merge_clients(clients))
+    })
+}
+
+/// Reads the GPU time of the user client with the registry handle `entry`.
+unsafe fn registry_client_stat<T: IOKit + ?Sized>(
+    io_kit: &T,
+    entry: u32,
+) -> Option<GpuProcessStat> {
+    let properties = io_kit.io_registry_entry_properties(entry).ok()?;
+
+    let creator = properties
+        .valueForKey(&NSString::from_str(USER_CLIENT_CREATOR_KEY))
+        .and_then(|obj| obj.downcast::<NSString>().ok())
+        .map(|s| s.to_string());
+    client_stat(creator.as_deref(), app_usage_gpu_times(&properties))
+}
+
+/// The accumulated GPU time of each `AppUsage` entry that has one.
+unsafe fn app_usage_gpu_times(properties: &NSDictionary<NSString, NSObject>) -> Vec<u64> {
+    let Some(usage) = properties.valueForKey(&NSString::from_str(APP_USAGE_KEY)) else {
+        return Vec::new();
+    };
+    let is_array: bool = msg_send![&usage, isKindOfClass: class!(NSArray)];
+    if !is_array {
+        return Vec::new();
+    }
+
+    let key = NSString::from_str(ACCUMULATED_GPU_TIME_KEY);
+    let count: usize = msg_send![&usage, count];
+    (0..count)
+        .filter_map(|i| {
+            let entry: Option<Retained<AnyObject>> = msg_send![&usage, objectAtIndex: i];
+            let entry = entry?;
+            let is_dict: bool = msg_send![&entry, isKindOfClass: class!(NSDictionary)];
+            if !is_dict {
+                return None;
+            }
+            // SAFETY: the entry was just checked to be a dictionary, and registry dictionaries have string keys.
+            let entry: Retained<NSDictionary<NSString, NSObject>> = Retained::cast_unchecked(entry);
+            let time = entry.valueForKey(&key)?.downcast::<NSNumber>().ok()?.as_i64();
+            u64::try_from(time).ok()
+        })
+        .collect()
+}
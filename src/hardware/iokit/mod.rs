@@ -1,39 +1,45 @@
 use std::{
-    ffi::{c_void as ffi_c_void, CString},
+    ffi::{c_void as ffi_c_void, CStr, CString},
     os::raw::c_char,
     ptr,
+    sync::Arc,
 };
 
-#[cfg(not(feature = "skip-ffi-crashes"))]
-use std::mem::size_of;
-
 use objc2::{
     class, msg_send,
     rc::{autoreleasepool, Retained},
-    runtime::AnyObject,
+    Message,
 };
 use objc2_foundation::{NSDictionary, NSNumber, NSObject, NSString};
 
 use crate::{
     error::{Error, Result},
-    utils::bindings::{
-        IORegistryEntryCreateCFProperties, IOServiceMatching, IO_RETURN_SUCCESS,
-        SMC_KEY_AMBIENT_TEMP, SMC_KEY_BATTERY_TEMP, SMC_KEY_CPU_POWER, SMC_KEY_CPU_TEMP,
-        SMC_KEY_CPU_THROTTLE, SMC_KEY_FAN_NUM, SMC_KEY_FAN_SPEED, SMC_KEY_GPU_TEMP,
-        SMC_KEY_HEATSINK_TEMP,
+    system::Architecture,
+    utils::{
+        bindings::{
+            IOIteratorNext, IOObjectRelease, IORegistryEntryCreateCFProperties,
+            IORegistryEntryFromPath, IORegistryEntryGetChildIterator, IORegistryEntryGetName,
+            IORegistryEntryGetParentEntry, IOServiceGetMatchingService, IOServiceMatching,
+            IO_MASTER_PORT_DEFAULT, IO_RETURN_SUCCESS, SMC_KEYS_GPU_TEMP_APPLE_SILICON,
+            SMC_KEYS_GPU_TEMP_INTEL, SMC_KEY_AMBIENT_TEMP, SMC_KEY_BATTERY_TEMP, SMC_KEY_CPU_POWER,
+            SMC_KEY_CPU_THROTTLE, SMC_KEY_FAN_NUM, SMC_KEY_FAN_SPEED, SMC_KEY_GPU_TEMP,
+            SMC_KEY_HEATSINK_TEMP,
+        },
+        retry::{with_retry, RetryPolicy},
+        sanitize::Percentage,
     },
 };
 
-// Only import these when not in coverage mode
-#[cfg(not(feature = "skip-ffi-crashes"))]
-use crate::utils::bindings::{
-    smc_key_from_chars, IOByteCount, IOConnectCallStructMethod, IOServiceClose,
-    IOServiceGetMatchingService, IOServiceOpen, SMCKeyData_t, KERNEL_INDEX_SMC, SMC_CMD_READ_BYTES,
-    SMC_CMD_READ_KEYINFO,
-};
+// Only needed by the mocked SMC reads of coverage mode
+#[cfg(feature = "skip-ffi-crashes")]
+use crate::utils::bindings::SMC_KEY_CPU_TEMP;
+
+/// Registry plane the parents of registry entries are looked up in
+const SERVICE_PLANE: &str = "IOService";
 
 /// GPU statistics retrieved from IOKit's AGPMController
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GpuStats {
     /// GPU utilization percentage (0-100)
     pub utilization: f64,
@@ -49,41 +55,188 @@ pub struct GpuStats {
     pub name: String,
 }
 
+mod block_storage;
+pub mod connection;
+mod cpu_temperature;
+mod gpu_process;
 #[cfg(test)]
 pub mod mock;
+mod registry;
+mod smart;
+mod smc;
+
+pub use block_storage::BlockStorageStats;
+#[cfg(not(feature = "skip-ffi-crashes"))]
+use connection::SharedSmc;
+pub use connection::{HandleOwnership, IOServiceHandle, SmcConnection};
+
+#[cfg(feature = "hid-sensors")]
+pub use cpu_temperature::HidThermalSensors;
+pub use cpu_temperature::{
+    read_cpu_temperature, CpuTemperatureLayer, CpuTemperatureSource, NamedTemperature,
+    RegistryThermalSensors, SensorLayer, SensorProvider, SensorReading, SmcLayer,
+};
+pub use gpu_process::GpuProcessStat;
+pub use registry::PciDevice;
+pub(crate) use registry::{bsd_name_matching, pci_device, registry_id_matching};
+pub use smart::{SmartData, SmartInterface};
+pub use smc::{SmcKey, SmcKeyInfo, SmcValue};
+
+/// Who decides the speed of a fan
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FanMode {
+    /// The SMC adjusts the speed to the temperatures
+    Auto,
+    /// The speed is forced to the target, e.g. by a fan control utility
+    Forced,
+    /// The mode couldn't be read or has a value this crate doesn't know
+    #[default]
+    Unknown,
+}
 
-#[derive(Debug, Clone)]
+impl FanMode {
+    /// The mode of the `F%dMd` SMC key value `value`.
+    pub(crate) fn from_smc(value: f64) -> Self {
+        match value as u8 {
+            0 => Self::Auto,
+            1 => Self::Forced,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FanInfo {
     pub speed_rpm: u32,
     pub min_speed: u32,
     pub max_speed: u32,
     pub percentage: f64,
+    /// Speed the SMC drives the fan towards in RPM, None if it doesn't report one
+    pub target_rpm: Option<u32>,
+    /// Whether the SMC controls the fan or its speed is forced
+    pub mode: FanMode,
+    /// Name from the fan descriptor, e.g. "Left Side", None on machines without descriptors
+    pub name: Option<String>,
+}
+
+/// The SMC key of fan `fan_index` with the two character `suffix`, e.g. `F0Ac` for the speed of the first fan.
+pub(crate) fn fan_key(fan_index: u32, suffix: [u8; 2]) -> [c_char; 4] {
+    [b'F' as c_char, (b'0' + fan_index as u8) as c_char, suffix[0] as c_char, suffix[1] as c_char]
+}
+
+/// The name in an `F%dID` fan descriptor: a type, zone and location byte, a reserved byte and a 12 byte name.
+///
+/// Returns None if the descriptor is too short or the name is empty.
+pub(crate) fn parse_fan_descriptor(bytes: &[u8]) -> Option<String> {
+    let name = bytes.get(4..)?;
+    let name = &name[..name.len().min(12)];
+    let name = name.split(|&byte| byte == 0).next().unwrap_or_default();
+    let name = String::from_utf8_lossy(name).trim().to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+/// Position of the fan speed between the minimum and maximum speed in percent.
+///
+/// A fan below its minimum, or one whose minimum isn't below its maximum, is at 0%.
+pub(crate) fn fan_percentage(speed_rpm: u32, min_speed: u32, max_speed: u32) -> f64 {
+    Percentage::from_ratio(
+        speed_rpm.saturating_sub(min_speed) as f64,
+        max_speed.saturating_sub(min_speed) as f64,
+    )
+    .value()
+}
+
+/// GPU utilization in percent from the AGPM performance capacity and threshold.
+pub(crate) fn gpu_utilization(perf_cap: f64, perf_threshold: f64) -> f64 {
+    Percentage::from_ratio(perf_cap, perf_threshold).value()
 }
 
-#[derive(Debug, Clone)]
+/// The SMC keys a GPU temperature is read from on `architecture`: `TG0P`, then the GPU sensors of the architecture,
+/// those of both architectures if it's unknown.
+pub(crate) fn gpu_temperature_keys(architecture: Architecture) -> Vec<[c_char; 4]> {
+    let mut keys = vec![SMC_KEY_GPU_TEMP];
+    match architecture {
+        Architecture::Intel => keys.extend(SMC_KEYS_GPU_TEMP_INTEL),
+        Architecture::AppleSilicon => keys.extend(SMC_KEYS_GPU_TEMP_APPLE_SILICON),
+        Architecture::Unknown => {
+            keys.extend(SMC_KEYS_GPU_TEMP_INTEL);
+            keys.extend(SMC_KEYS_GPU_TEMP_APPLE_SILICON);
+        },
+    }
+    keys
+}
+
+/// Reads the first of `keys` holding a plausible temperature, None if none does.
+///
+/// Keys the machine doesn't have fail to read, and the sensors of unused GPU cores read 0.
+pub(crate) fn read_gpu_temperature<T: IOKit + ?Sized>(
+    io_kit: &T,
+    keys: &[[c_char; 4]],
+) -> Option<f64> {
+    keys.iter().find_map(|&key| {
+        io_kit
+            .read_smc_key(key)
+            .ok()
+            .filter(|celsius| cpu_temperature::PLAUSIBLE_RANGE.contains(celsius))
+    })
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ThermalInfo {
     pub cpu_temp: f64,
-    pub gpu_temp: f64,
+    /// None if neither `TG0P` nor the GPU sensors of the architecture could be read
+    pub gpu_temp: Option<f64>,
     pub heatsink_temp: Option<f64>,
     pub ambient_temp: Option<f64>,
     pub battery_temp: Option<f64>,
     pub is_throttling: bool,
     pub cpu_power: Option<f64>, // in watts
+    /// Where `cpu_temp` was read from
+    pub cpu_temp_source: CpuTemperatureSource,
+    /// Every CPU sensor of the SMC this machine has, see [`IOKit::get_cpu_temperatures`]
+    pub cpu_sensor_temps: Vec<NamedTemperature>,
+    /// Highest temperature of `cpu_sensor_temps`, None if the machine has none of the known sensors
+    pub cpu_temp_max: Option<f64>,
 }
 
-#[cfg_attr(test, mockall::automock)]
+#[cfg_attr(any(test, feature = "doctest-support"), mockall::automock)]
 pub trait IOKit: Send + Sync + std::fmt::Debug {
     fn io_service_matching(&self, service_name: &str)
         -> Retained<NSDictionary<NSString, NSObject>>;
+    /// Looks up the first service matching `matching`, None if there is none
     fn io_service_get_matching_service(
         &self,
         matching: &NSDictionary<NSString, NSObject>,
-    ) -> Option<Retained<AnyObject>>;
+    ) -> Option<IOServiceHandle>;
+    /// Reads the properties of the registry entry `entry`
     fn io_registry_entry_create_cf_properties(
         &self,
-        entry: &AnyObject,
+        entry: &IOServiceHandle,
     ) -> Result<Retained<NSDictionary<NSString, NSObject>>>;
-    fn io_object_release(&self, obj: &AnyObject);
+    /// Reads the properties of the registry entry with the raw handle `entry`, e.g. an injected [`IOServiceHandle`].
+    ///
+    /// A read failing because the driver is busy or not ready is retried with the default [`RetryPolicy`].
+    fn io_registry_entry_properties(
+        &self,
+        entry: u32,
+    ) -> Result<Retained<NSDictionary<NSString, NSObject>>> {
+        let _span = tracing::trace_span!("io_registry_entry_properties", entry).entered();
+        let props = with_retry(RetryPolicy::default(), || {
+            let mut props: *mut ffi_c_void = ptr::null_mut();
+            let result =
+                unsafe { IORegistryEntryCreateCFProperties(entry, &mut props, ptr::null_mut(), 0) };
+            if result != IO_RETURN_SUCCESS {
+                return Err(Error::io_return("Failed to read registry properties", result));
+            }
+            Ok(props)
+        })?;
+        // The properties are returned with a +1 retain count and are toll-free bridged to NSDictionary
+        unsafe { Retained::from_raw(props as *mut NSDictionary<NSString, NSObject>) }
+            .ok_or_else(|| Error::io_kit("Registry entry has no properties"))
+    }
     fn get_string_property(
         &self,
         dict: &NSDictionary<NSString, NSObject>,
@@ -101,19 +254,106 @@ pub trait IOKit: Send + Sync + std::fmt::Debug {
         dict: &NSDictionary<NSString, NSObject>,
         key: &str,
     ) -> Option<Retained<NSDictionary<NSString, NSObject>>>;
-    fn get_service(&self, name: &str) -> Result<Retained<AnyObject>>;
-    fn io_registry_entry_get_parent(&self, entry: &AnyObject) -> Option<Retained<AnyObject>>;
+    /// Looks up the first service of the class `name`
+    fn get_service(&self, name: &str) -> Result<IOServiceHandle>;
+    /// The parent of `entry` in the service plane, None for the root
+    fn io_registry_entry_get_parent(&self, entry: &IOServiceHandle) -> Option<IOServiceHandle>;
+    /// Reads the name of the registry entry `entry`, e.g. `AppleARMPE` or `GFX0`
+    fn io_registry_entry_get_name(&self, entry: &IOServiceHandle) -> Result<String> {
+        let _span = tracing::trace_span!("io_registry_entry_get_name", ?entry).entered();
+        // An io_name_t of <device/device_types.h>
+        let mut name = [0 as c_char; 128];
+        let result = unsafe { IORegistryEntryGetName(entry.as_raw(), name.as_mut_ptr()) };
+        if result != IO_RETURN_SUCCESS {
+            return Err(Error::io_return("Failed to read the registry entry name", result));
+        }
+        Ok(unsafe { CStr::from_ptr(name.as_ptr()) }.to_string_lossy().into_owned())
+    }
+    /// The children of `entry` in the registry plane `plane`, e.g. `IOService` or `IODeviceTree`
+    fn io_registry_iterate_children(
+        &self,
+        entry: &IOServiceHandle,
+        plane: &str,
+    ) -> Result<Vec<IOServiceHandle>> {
+        let _span = tracing::trace_span!("io_registry_iterate_children", ?entry, plane).entered();
+        let c_plane = CString::new(plane)
+            .map_err(|_| Error::invalid_data(format!("Invalid registry plane: {plane}")))?;
+        let mut iterator = 0;
+        let result = unsafe {
+            IORegistryEntryGetChildIterator(entry.as_raw(), c_plane.as_ptr(), &mut iterator)
+        };
+        if result != IO_RETURN_SUCCESS {
+            return Err(Error::io_return("Failed to iterate the registry entry", result));
+        }
+
+        // SAFETY: each child is returned with a reference that nothing else releases
+        let children = std::iter::from_fn(|| {
+            let child = unsafe { IOIteratorNext(iterator) };
+            (child != 0).then(|| unsafe { IOServiceHandle::from_raw_service(child) })
+        })
+        .collect();
+        unsafe { IOObjectRelease(iterator) };
+        Ok(children)
+    }
+    /// Looks up the registry entry at `path`, e.g. `IOService:/AppleARMPE/arm-io`, None if there is none
+    fn find_service_by_path(&self, path: &str) -> Option<IOServiceHandle> {
+        let _span = tracing::trace_span!("find_service_by_path", path).entered();
+        let c_path = CString::new(path).ok()?;
+        let entry = unsafe { IORegistryEntryFromPath(IO_MASTER_PORT_DEFAULT, c_path.as_ptr()) };
+        // SAFETY: the entry was returned with a reference that nothing else releases
+        (entry != 0).then(|| unsafe { IOServiceHandle::from_raw_service(entry) })
+    }
 
     // Temperature related methods
     fn get_cpu_temperature(&self) -> Result<f64>;
+    /// Reads the CPU temperature along with the source it was read from
+    fn get_cpu_temperature_with_source(&self) -> Result<(f64, CpuTemperatureSource)> {
+        Ok((self.get_cpu_temperature()?, CpuTemperatureSource::Smc))
+    }
+    /// Reads the CPU die and core sensors of the SMC, probing the known keys of the architecture and leaving out the
+    /// ones this machine doesn't have
+    fn get_cpu_temperatures(&self) -> Result<Vec<NamedTemperature>> {
+        let keys = cpu_temperature::cpu_sensor_keys(crate::init::system_info()?.architecture);
+        Ok(cpu_temperature::read_cpu_sensors(self, &keys))
+    }
     fn get_gpu_temperature(&self) -> Result<f64>;
     fn get_gpu_stats(&self) -> Result<GpuStats>;
+    /// Reads the GPU time of every process with an open client on a GPU, summed per process and ordered by GPU time.
+    ///
+    /// Processes whose driver doesn't report GPU time, as on Intel and AMD GPUs, are left out.
+    fn get_gpu_process_stats(&self) -> Result<Vec<GpuProcessStat>> {
+        gpu_process::read_gpu_process_stats(self)
+    }
+
+    /// Reads the cumulative I/O counters of every physical disk from its block storage driver.
+    fn get_block_storage_stats(&self) -> Result<Vec<BlockStorageStats>> {
+        block_storage::read_block_storage_stats(self)
+    }
+
+    /// Reads the SMART data of the drive storing the BSD device `bsd_name`, e.g. `disk0` or `disk3s1`.
+    ///
+    /// Returns a not available error for drives without a SMART interface, such as drives in USB enclosures.
+    fn get_smart_data(&self, bsd_name: &str) -> Result<SmartData> {
+        smart::read_smart_data(bsd_name)
+    }
 
     // Fan related methods
     fn get_fan_speed(&self) -> Result<u32>;
     fn get_fan_count(&self) -> Result<u32>;
     fn get_fan_info(&self, fan_index: u32) -> Result<FanInfo>;
     fn get_all_fans(&self) -> Result<Vec<FanInfo>>;
+    /// Reads the speed the SMC drives fan `fan_index` towards, in RPM
+    fn get_fan_target(&self, fan_index: u32) -> Result<u32> {
+        Ok(self.read_smc_key(fan_key(fan_index, *b"Tg"))? as u32)
+    }
+    /// Reads whether the SMC controls fan `fan_index` or its speed is forced
+    fn get_fan_mode(&self, fan_index: u32) -> Result<FanMode> {
+        Ok(FanMode::from_smc(self.read_smc_key(fan_key(fan_index, *b"Md"))?))
+    }
+    /// Reads the name of fan `fan_index` from its descriptor
+    fn get_fan_name(&self, _fan_index: u32) -> Result<String> {
+        Err(Error::not_available("Fan descriptors"))
+    }
 
     // Advanced thermal methods
     fn get_heatsink_temperature(&self) -> Result<f64>;
@@ -125,13 +365,236 @@ pub trait IOKit: Send + Sync + std::fmt::Debug {
 
     /// Reads a value from the SMC (System Management Controller)
     fn read_smc_key(&self, key: [c_char; 4]) -> Result<f64>;
+    /// Lists every key of the SMC with its data type and size
+    fn list_smc_keys(&self) -> Result<Vec<SmcKeyInfo>> {
+        Err(Error::not_available("SMC key enumeration"))
+    }
+    /// Reads an SMC key in its own data type
+    fn read_smc_key_typed(&self, _key: SmcKey) -> Result<SmcValue> {
+        Err(Error::not_available("Typed SMC reads"))
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct IOKitImpl;
+/// Shared IOKit access, so one instance can be handed to every monitor an owner creates
+impl<T: IOKit + ?Sized> IOKit for Arc<T> {
+    fn io_service_matching(
+        &self,
+        service_name: &str,
+    ) -> Retained<NSDictionary<NSString, NSObject>> {
+        (**self).io_service_matching(service_name)
+    }
+
+    fn io_service_get_matching_service(
+        &self,
+        matching: &NSDictionary<NSString, NSObject>,
+    ) -> Option<IOServiceHandle> {
+        (**self).io_service_get_matching_service(matching)
+    }
+
+    fn io_registry_entry_create_cf_properties(
+        &self,
+        entry: &IOServiceHandle,
+    ) -> Result<Retained<NSDictionary<NSString, NSObject>>> {
+        (**self).io_registry_entry_create_cf_properties(entry)
+    }
+
+    fn io_registry_entry_properties(
+        &self,
+        entry: u32,
+    ) -> Result<Retained<NSDictionary<NSString, NSObject>>> {
+        (**self).io_registry_entry_properties(entry)
+    }
+
+    fn get_string_property(
+        &self,
+        dict: &NSDictionary<NSString, NSObject>,
+        key: &str,
+    ) -> Option<String> {
+        (**self).get_string_property(dict, key)
+    }
+
+    fn get_number_property(
+        &self,
+        dict: &NSDictionary<NSString, NSObject>,
+        key: &str,
+    ) -> Option<i64> {
+        (**self).get_number_property(dict, key)
+    }
+
+    fn get_bool_property(
+        &self,
+        dict: &NSDictionary<NSString, NSObject>,
+        key: &str,
+    ) -> Option<bool> {
+        (**self).get_bool_property(dict, key)
+    }
+
+    fn get_dict_property(
+        &self,
+        dict: &NSDictionary<NSString, NSObject>,
+        key: &str,
+    ) -> Option<Retained<NSDictionary<NSString, NSObject>>> {
+        (**self).get_dict_property(dict, key)
+    }
+
+    fn get_service(&self, name: &str) -> Result<IOServiceHandle> {
+        (**self).get_service(name)
+    }
+
+    fn io_registry_entry_get_parent(&self, entry: &IOServiceHandle) -> Option<IOServiceHandle> {
+        (**self).io_registry_entry_get_parent(entry)
+    }
+
+    fn io_registry_entry_get_name(&self, entry: &IOServiceHandle) -> Result<String> {
+        (**self).io_registry_entry_get_name(entry)
+    }
+
+    fn io_registry_iterate_children(
+        &self,
+        entry: &IOServiceHandle,
+        plane: &str,
+    ) -> Result<Vec<IOServiceHandle>> {
+        (**self).io_registry_iterate_children(entry, plane)
+    }
+
+    fn find_service_by_path(&self, path: &str) -> Option<IOServiceHandle> {
+        (**self).find_service_by_path(path)
+    }
+
+    fn get_cpu_temperature(&self) -> Result<f64> {
+        (**self).get_cpu_temperature()
+    }
+
+    fn get_cpu_temperature_with_source(&self) -> Result<(f64, CpuTemperatureSource)> {
+        (**self).get_cpu_temperature_with_source()
+    }
+
+    fn get_cpu_temperatures(&self) -> Result<Vec<NamedTemperature>> {
+        (**self).get_cpu_temperatures()
+    }
+
+    fn get_gpu_temperature(&self) -> Result<f64> {
+        (**self).get_gpu_temperature()
+    }
+
+    fn get_gpu_stats(&self) -> Result<GpuStats> {
+        (**self).get_gpu_stats()
+    }
+
+    fn get_gpu_process_stats(&self) -> Result<Vec<GpuProcessStat>> {
+        (**self).get_gpu_process_stats()
+    }
+
+    fn get_block_storage_stats(&self) -> Result<Vec<BlockStorageStats>> {
+        (**self).get_block_storage_stats()
+    }
+
+    fn get_smart_data(&self, bsd_name: &str) -> Result<SmartData> {
+        (**self).get_smart_data(bsd_name)
+    }
+
+    fn get_fan_speed(&self) -> Result<u32> {
+        (**self).get_fan_speed()
+    }
+
+    fn get_fan_count(&self) -> Result<u32> {
+        (**self).get_fan_count()
+    }
+
+    fn get_fan_info(&self, fan_index: u32) -> Result<FanInfo> {
+        (**self).get_fan_info(fan_index)
+    }
+
+    fn get_all_fans(&self) -> Result<Vec<FanInfo>> {
+        (**self).get_all_fans()
+    }
+
+    fn get_fan_target(&self, fan_index: u32) -> Result<u32> {
+        (**self).get_fan_target(fan_index)
+    }
+
+    fn get_fan_mode(&self, fan_index: u32) -> Result<FanMode> {
+        (**self).get_fan_mode(fan_index)
+    }
+
+    fn get_fan_name(&self, fan_index: u32) -> Result<String> {
+        (**self).get_fan_name(fan_index)
+    }
+
+    fn get_heatsink_temperature(&self) -> Result<f64> {
+        (**self).get_heatsink_temperature()
+    }
+
+    fn get_ambient_temperature(&self) -> Result<f64> {
+        (**self).get_ambient_temperature()
+    }
+
+    fn get_battery_temperature(&self) -> Result<f64> {
+        (**self).get_battery_temperature()
+    }
+
+    fn get_cpu_power(&self) -> Result<f64> {
+        (**self).get_cpu_power()
+    }
+
+    fn check_thermal_throttling(&self) -> Result<bool> {
+        (**self).check_thermal_throttling()
+    }
+
+    fn get_thermal_info(&self) -> Result<ThermalInfo> {
+        (**self).get_thermal_info()
+    }
+
+    fn read_smc_key(&self, key: [c_char; 4]) -> Result<f64> {
+        (**self).read_smc_key(key)
+    }
+
+    fn list_smc_keys(&self) -> Result<Vec<SmcKeyInfo>> {
+        (**self).list_smc_keys()
+    }
+
+    fn read_smc_key_typed(&self, key: SmcKey) -> Result<SmcValue> {
+        (**self).read_smc_key_typed(key)
+    }
+}
+
+/// IOKit access used by the monitors
+///
+/// SMC keys are read over a connection opened on the first read and kept until the instance and all its clones are
+/// dropped, unless a connection was injected with [`with_smc_connection`](IOKitImpl::with_smc_connection), which is
+/// then shared by all clones instead. A kept connection that goes stale is reopened on the next read.
+#[derive(Debug, Clone, Default)]
+pub struct IOKitImpl {
+    smc: Option<Arc<SmcConnection>>,
+    #[cfg(not(feature = "skip-ffi-crashes"))]
+    shared_smc: Arc<SharedSmc>,
+}
 
 impl IOKitImpl {
+    /// Creates an instance that reads every SMC key over `connection`, never opening the SMC itself.
+    ///
+    /// See the [`connection`](self::connection) module for running under reduced privileges.
+    pub fn with_smc_connection(connection: SmcConnection) -> Self {
+        Self {
+            smc: Some(Arc::new(connection)),
+            #[cfg(not(feature = "skip-ffi-crashes"))]
+            shared_smc: Arc::default(),
+        }
+    }
+
+    /// Creates an instance that opens its SMC connection with `backend`.
+    #[cfg(all(test, not(feature = "skip-ffi-crashes")))]
+    pub(crate) fn with_smc_backend(backend: Arc<dyn connection::SmcBackend>) -> Self {
+        Self { smc: None, shared_smc: Arc::new(SharedSmc::new(backend)) }
+    }
+
+    /// Reads an SMC key over the injected connection, or over the kept one.
     fn smc_read_key(&self, key: [c_char; 4]) -> Result<f64> {
+        // An injected connection is used even in coverage runs, so tests can observe it
+        if self.smc.is_some() {
+            return self.with_smc(|smc| smc.read_key(key));
+        }
+
         // For coverage runs, use a mock implementation to avoid segfaults
         #[cfg(feature = "skip-ffi-crashes")]
         {
@@ -153,115 +616,35 @@ impl IOKitImpl {
 
         // Normal implementation for non-coverage runs
         #[cfg(not(feature = "skip-ffi-crashes"))]
-        unsafe {
-            // Open the SMC service
-            let service_name = CString::new("AppleSMC").expect("Failed to create CString");
-            let service = IOServiceMatching(service_name.as_ptr());
-            if service.is_null() {
-                return Err(Error::service_not_found("AppleSMC service not found"));
-            }
-
-            let service_id = IOServiceGetMatchingService(0, service as *const _);
-            if service_id == 0 {
-                return Err(Error::service_not_found("AppleSMC service not found"));
-            }
-
-            let mut connection = 0u32;
-            let result = IOServiceOpen(service_id, 0, KERNEL_INDEX_SMC, &mut connection);
-            if result != IO_RETURN_SUCCESS {
-                return Err(Error::io_kit(format!("Failed to open SMC connection: {}", result)));
-            }
-
-            // Get key info first to determine the data type
-            let mut input_structure = SMCKeyData_t {
-                key: smc_key_from_chars(key),
-                vers: 0,
-                p_limit_data: 0,
-                key_info: 1,
-                padding: 0,
-                result: 0,
-                status: 0,
-                data8: 0,
-                data32: 0,
-                bytes: [0; 2],
-                data: std::mem::zeroed(), // Initialize with zeros
-            };
-
-            let mut output_structure = input_structure;
-            let mut output_size = IOByteCount(size_of::<SMCKeyData_t>());
-
-            let result = IOConnectCallStructMethod(
-                connection,
-                SMC_CMD_READ_KEYINFO as u32,
-                &input_structure,
-                IOByteCount(size_of::<SMCKeyData_t>()),
-                &mut output_structure,
-                &mut output_size,
-            );
-
-            if result != IO_RETURN_SUCCESS {
-                IOServiceClose(connection);
-                return Err(Error::io_kit(format!("Failed to read SMC key info: {}", result)));
-            }
-
-            // Now read the actual data
-            input_structure.key_info = 0;
-            input_structure.padding = 0;
-
-            let result = IOConnectCallStructMethod(
-                connection,
-                SMC_CMD_READ_BYTES as u32,
-                &input_structure,
-                IOByteCount(size_of::<SMCKeyData_t>()),
-                &mut output_structure,
-                &mut output_size,
-            );
+        {
+            self.with_smc(|smc| smc.read_key(key))
+        }
+    }
 
-            IOServiceClose(connection);
+    /// Reads the data type and raw bytes of an SMC key over the injected connection, or over the kept one.
+    fn smc_read_key_bytes(&self, key: [c_char; 4]) -> Result<([u8; 4], Vec<u8>)> {
+        self.with_smc(|smc| smc.read_key_bytes(key))
+    }
 
-            if result != IO_RETURN_SUCCESS {
-                return Err(Error::io_kit(format!("Failed to read SMC key data: {}", result)));
-            }
+    /// Runs `read` over the injected connection, or over the kept one, opening it if needed.
+    ///
+    /// Reads failing because the SMC is busy or the connection went stale are retried with the default
+    /// [`RetryPolicy`].
+    fn with_smc<R>(&self, read: impl Fn(&SmcConnection) -> Result<R>) -> Result<R> {
+        if let Some(smc) = &self.smc {
+            return with_retry(RetryPolicy::default(), || read(smc));
+        }
 
-            // Get the data and convert to temperature (depends on the data type) Most temperature sensors use SP78
-            // format (fixed point, signed 8.8)
-            let data_type = output_structure.data.key_info.data_type;
-            let data_size = output_structure.data.key_info.data_size;
-
-            if data_size > 0 {
-                if data_type[0] == b'f' && data_type[1] == b'l' && data_type[2] == b't' {
-                    // flt type: float
-                    return Ok(f64::from(output_structure.data.float));
-                } else if data_type[0] == b'u'
-                    && data_type[1] == b'i'
-                    && data_type[2] == b'n'
-                    && data_type[3] == b't'
-                {
-                    // uint type: unsigned int
-                    return Ok(f64::from(output_structure.data.uint32));
-                } else if data_type[0] == b's'
-                    && data_type[1] == b'i'
-                    && data_type[2] == b'1'
-                    && data_type[3] == b'6'
-                {
-                    // si16 type: signed int 16-bit
-                    return Ok(f64::from(output_structure.data.sint16));
-                } else if data_type[0] == b'S'
-                    && data_type[1] == b'P'
-                    && data_type[2] == b'7'
-                    && data_type[3] == b'8'
-                {
-                    // SP78 type: fixed point, signed 8.8
-                    let bytes = output_structure.data.bytes;
-                    let val: f64 = (bytes[0] as f64) + (bytes[1] as f64 / 256.0);
-                    return Ok(val);
-                }
-            }
+        // Coverage runs have no mocked raw values
+        #[cfg(feature = "skip-ffi-crashes")]
+        {
+            let _ = read;
+            Err(Error::not_available("Raw SMC reads in coverage mode"))
+        }
 
-            Err(Error::invalid_data(format!(
-                "Unsupported SMC data type: {:?}",
-                std::str::from_utf8(&data_type).unwrap_or("Unknown")
-            )))
+        #[cfg(not(feature = "skip-ffi-crashes"))]
+        {
+            with_retry(RetryPolicy::default(), || self.shared_smc.with(&read))
         }
     }
 
@@ -302,210 +685,108 @@ impl IOKitImpl {
 }
 
 impl IOKit for IOKitImpl {
+    #[tracing::instrument(level = "trace", skip(self))]
     fn io_service_matching(
         &self,
         service_name: &str,
     ) -> Retained<NSDictionary<NSString, NSObject>> {
-        println!("DEBUG: Entering io_service_matching for service '{}'", service_name);
         autoreleasepool(|_| {
-            println!("DEBUG: Inside autoreleasepool for io_service_matching");
             unsafe {
-                println!("DEBUG: Creating empty dictionary");
                 let empty_dict = Retained::from_raw(msg_send![class!(NSDictionary), dictionary])
                     .expect("Failed to create dictionary");
-                println!("DEBUG: Empty dictionary created");
 
                 // Direct C function call for IOServiceMatching
-                println!("DEBUG: Creating CString for service name");
-                let c_service_name = match CString::new(service_name) {
-                    Ok(s) => {
-                        println!("DEBUG: CString created successfully");
-                        s
-                    },
-                    Err(e) => {
-                        println!("DEBUG: Error creating CString: {:?}", e);
-                        return empty_dict; // Return empty dict if service name contains NUL
-                    },
+                let Ok(c_service_name) = CString::new(service_name) else {
+                    tracing::debug!("Service name contains a NUL byte, matching nothing");
+                    return empty_dict;
                 };
 
-                println!("DEBUG: CString pointer: {:p}", c_service_name.as_ptr());
-                println!("DEBUG: About to call IOServiceMatching");
                 let matching_dict = IOServiceMatching(c_service_name.as_ptr());
-                println!("DEBUG: IOServiceMatching returned: {:p}", matching_dict);
-
-                if !matching_dict.is_null() {
-                    println!("DEBUG: matching_dict is not null, converting to NSDictionary");
-                    // Try to convert the dictionary to the expected type
-                    let dict_ptr = matching_dict as *mut NSDictionary<NSString, NSObject>;
-                    println!("DEBUG: dict_ptr = {:p}", dict_ptr);
-
-                    println!("DEBUG: About to call Retained::from_raw for dictionary");
-                    if let Some(dict) = Retained::from_raw(dict_ptr) {
-                        println!("DEBUG: Successfully created Retained<NSDictionary>");
-                        return dict;
-                    }
-                    println!("DEBUG: Failed to create Retained<NSDictionary>");
-                } else {
-                    println!("DEBUG: matching_dict is null");
+                if let Some(dict) =
+                    Retained::from_raw(matching_dict as *mut NSDictionary<NSString, NSObject>)
+                {
+                    return dict;
                 }
 
                 // Fallback to empty dictionary
-                println!("DEBUG: Returning empty fallback dictionary");
+                tracing::debug!("IOServiceMatching returned no dictionary");
                 empty_dict
             }
         })
     }
 
-    // A safer approach that avoids direct casting of IOKit service IDs to Objective-C objects Inspired by macmon,
-    // NeoAsitop, and Stats implementations Completely simplify the method to avoid any Objective-C interactions that
-    // could cause segmentation faults
+    #[tracing::instrument(level = "trace", skip_all)]
     fn io_service_get_matching_service(
         &self,
-        _matching: &NSDictionary<NSString, NSObject>,
-    ) -> Option<Retained<AnyObject>> {
-        println!("DEBUG: Using simplified io_service_get_matching_service to avoid SIGSEGV");
-        // Just return None to avoid any potential issues with memory management
-        None
+        matching: &NSDictionary<NSString, NSObject>,
+    ) -> Option<IOServiceHandle> {
+        // IOServiceGetMatchingService consumes a reference to the matching dictionary, so it gets one of its own
+        let matching = Retained::into_raw(matching.retain());
+        let service = unsafe {
+            IOServiceGetMatchingService(IO_MASTER_PORT_DEFAULT, matching as *const ffi_c_void)
+        };
+
+        // SAFETY: the service was returned with a reference that nothing else releases
+        (service != 0).then(|| unsafe { IOServiceHandle::from_raw_service(service) })
     }
 
     fn io_registry_entry_create_cf_properties(
         &self,
-        entry: &AnyObject,
+        entry: &IOServiceHandle,
     ) -> Result<Retained<NSDictionary<NSString, NSObject>>> {
-        println!("DEBUG: Entering io_registry_entry_create_cf_properties");
-        // Wrap in autoreleasepool to ensure proper memory management
-        autoreleasepool(|_| {
-            println!("DEBUG: Inside autoreleasepool in io_registry_entry_create_cf_properties");
-            unsafe {
-                println!("DEBUG: Creating props pointer");
-                let mut props: *mut ffi_c_void = ptr::null_mut();
-
-                // IOKit service IDs are special in that they're just raw numbers That are cast to pointers. For memory
-                // safety, we're going to extract the raw number from the object pointer.
-
-                // Get the value directly from the pointer
-                let service_id = entry as *const AnyObject as u32;
-                println!("DEBUG: Using service ID = {}", service_id);
-
-                // Use IORegistryEntryCreateCFProperties with the extracted ID
-                println!("DEBUG: About to call IORegistryEntryCreateCFProperties");
-                let result =
-                    IORegistryEntryCreateCFProperties(service_id, &mut props, ptr::null_mut(), 0);
-                println!("DEBUG: IORegistryEntryCreateCFProperties result = {}", result);
-                println!("DEBUG: props = {:p}", props);
-
-                if result != 0 || props.is_null() {
-                    println!("DEBUG: Failed to retrieve properties");
-                    return Err(Error::system("Failed to retrieve properties"));
-                }
-
-                // Convert the properties to the expected type
-                println!("DEBUG: Converting props to dict_ptr");
-                let dict_ptr = props as *mut NSDictionary<NSString, NSObject>;
-                println!("DEBUG: dict_ptr = {:p}", dict_ptr);
-
-                println!("DEBUG: About to call Retained::from_raw for properties");
-                let retained = Retained::from_raw(dict_ptr);
-                println!("DEBUG: Retained::from_raw result success = {}", retained.is_some());
-
-                retained.ok_or_else(|| {
-                    println!("DEBUG: Failed to retain properties");
-                    Error::system("Failed to retain properties")
-                })
-            }
-        })
-    }
-
-    fn io_object_release(&self, _obj: &AnyObject) {
-        // The object is automatically released when the Retained<AnyObject> is dropped
+        self.io_registry_entry_properties(entry.as_raw())
     }
 
+    #[tracing::instrument(level = "trace", skip(self, dict), ret)]
     fn get_string_property(
         &self,
         dict: &NSDictionary<NSString, NSObject>,
         key: &str,
     ) -> Option<String> {
-        println!("DEBUG: Looking for string property '{}'", key);
         let key = NSString::from_str(key);
-        println!("DEBUG: Created NSString key");
 
         unsafe {
             // Use autoreleasepool to properly manage any temporary objects
             autoreleasepool(|_| {
-                println!("DEBUG: Inside autoreleasepool for get_string_property");
-
-                let value_opt = dict.valueForKey(&key);
-                let obj = match value_opt {
-                    None => {
-                        println!("DEBUG: No value found for key '{}'", key);
-                        return None;
-                    },
-                    Some(obj) => {
-                        println!("DEBUG: Found value for key '{}'", key);
-                        obj
-                    },
+                let Some(obj) = dict.valueForKey(&key) else {
+                    tracing::trace!("No value found");
+                    return None;
                 };
 
-                let string_opt = obj.downcast::<NSString>();
-                let s = match string_opt {
+                match obj.downcast::<NSString>() {
+                    Ok(s) => Some(s.to_string()),
                     Err(_) => {
-                        println!("DEBUG: Value is not an NSString");
-                        return None;
-                    },
-                    Ok(s) => {
-                        println!("DEBUG: Downcasted to NSString successfully");
-                        s
+                        tracing::trace!("Value is not an NSString");
+                        None
                     },
-                };
-                let result = s.to_string();
-
-                println!("DEBUG: Converted to Rust string: '{}'", result);
-                Some(result)
+                }
             })
         }
     }
 
+    #[tracing::instrument(level = "trace", skip(self, dict), ret)]
     fn get_number_property(
         &self,
         dict: &NSDictionary<NSString, NSObject>,
         key: &str,
     ) -> Option<i64> {
-        println!("DEBUG: Looking for number property '{}'", key);
         let key = NSString::from_str(key);
-        println!("DEBUG: Created NSString key");
 
         unsafe {
             // Use autoreleasepool to properly manage any temporary objects
             autoreleasepool(|_| {
-                println!("DEBUG: Inside autoreleasepool for get_number_property");
-
-                let value_opt = dict.valueForKey(&key);
-                let obj = match value_opt {
-                    None => {
-                        println!("DEBUG: No value found for key '{}'", key);
-                        return None;
-                    },
-                    Some(obj) => {
-                        println!("DEBUG: Found value for key '{}'", key);
-                        obj
-                    },
+                let Some(obj) = dict.valueForKey(&key) else {
+                    tracing::trace!("No value found");
+                    return None;
                 };
 
-                let number_opt = obj.downcast::<NSNumber>();
-                let n = match number_opt {
+                match obj.downcast::<NSNumber>() {
+                    Ok(n) => Some(n.as_i64()),
                     Err(_) => {
-                        println!("DEBUG: Value is not an NSNumber");
-                        return None;
+                        tracing::trace!("Value is not an NSNumber");
+                        None
                     },
-                    Ok(n) => {
-                        println!("DEBUG: Downcasted to NSNumber successfully");
-                        n
-                    },
-                };
-                let result = n.as_i64();
-
-                println!("DEBUG: Got i64 value: {}", result);
-                Some(result)
+                }
             })
         }
     }
@@ -554,39 +835,43 @@ impl IOKit for IOKitImpl {
         }
     }
 
-    fn io_registry_entry_get_parent(&self, entry: &AnyObject) -> Option<Retained<AnyObject>> {
-        use crate::utils::bindings::IORegistryEntryGetParentEntry;
-        use std::os::raw::c_uint;
-
-        // Use autoreleasepool to ensure proper memory management
-        autoreleasepool(|_| {
-            unsafe {
-                let entry_id = entry as *const AnyObject as c_uint;
-                let mut parent_id: c_uint = 0;
-
-            if result != IO_RETURN_SUCCESS || parent_id == 0 {
-                return None;
-            }
-
-            // Create an AnyObject from the parent ID
-            let parent_ptr = parent_id as *mut AnyObject;
-            Retained::from_raw(parent_ptr)
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn io_registry_entry_get_parent(&self, entry: &IOServiceHandle) -> Option<IOServiceHandle> {
+        let plane = CString::new(SERVICE_PLANE).ok()?;
+        let mut parent = 0;
+        let result =
+            unsafe { IORegistryEntryGetParentEntry(entry.as_raw(), plane.as_ptr(), &mut parent) };
+        if result != IO_RETURN_SUCCESS || parent == 0 {
+            return None;
         }
+
+        // SAFETY: the parent was returned with a reference that nothing else releases
+        Some(unsafe { IOServiceHandle::from_raw_service(parent) })
     }
 
-    fn get_service(&self, name: &str) -> Result<Retained<AnyObject>> {
-        println!("DEBUG: Using simplified get_service implementation to avoid SIGSEGV");
-        // Return a safe error instead of trying to use IOKit directly
-        Err(Error::service_not_found(format!("Service access disabled for stability: {}", name)))
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn get_service(&self, name: &str) -> Result<IOServiceHandle> {
+        let matching = self.io_service_matching(name);
+        self.io_service_get_matching_service(&matching)
+            .ok_or_else(|| Error::service_not_found(format!("{name} service not found")))
     }
 
     // Temperature related methods
     fn get_cpu_temperature(&self) -> Result<f64> {
-        self.smc_read_key(SMC_KEY_CPU_TEMP)
+        self.get_cpu_temperature_with_source().map(|(temperature, _)| temperature)
+    }
+
+    // Falls back to the IORegistry and HID sensors on Apple Silicon models without the SMC key
+    fn get_cpu_temperature_with_source(&self) -> Result<(f64, CpuTemperatureSource)> {
+        cpu_temperature::read_with_fallback(self)
     }
 
+    // Falls back to the GPU die sensors of the architecture on machines without the TG0P key
     fn get_gpu_temperature(&self) -> Result<f64> {
-        self.smc_read_key(SMC_KEY_GPU_TEMP)
+        let architecture =
+            crate::init::system_info().map_or(Architecture::Unknown, |info| info.architecture);
+        read_gpu_temperature(self, &gpu_temperature_keys(architecture))
+            .ok_or_else(|| Error::not_available("No GPU temperature sensor found"))
     }
 
     fn get_heatsink_temperature(&self) -> Result<f64> {
@@ -613,15 +898,18 @@ impl IOKit for IOKitImpl {
 
     fn get_thermal_info(&self) -> Result<ThermalInfo> {
         // Get required fields
-        let cpu_temp = self.get_cpu_temperature()?;
+        let (cpu_temp, cpu_temp_source) = self.get_cpu_temperature_with_source()?;
 
-        // Get other fields, allowing failure for optional sensors
-        let gpu_temp = self.get_gpu_temperature().unwrap_or(0.0);
+        // Get other fields, allowing failure for optional sensors; each SMC read retries transient failures, so a
+        // sensor is only None when it can't be read at all
+        let gpu_temp = self.get_gpu_temperature().ok();
         let heatsink_temp = self.get_heatsink_temperature().ok();
         let ambient_temp = self.get_ambient_temperature().ok();
         let battery_temp = self.get_battery_temperature().ok();
         let cpu_power = self.get_cpu_power().ok();
         let is_throttling = self.check_thermal_throttling().unwrap_or(false);
+        let cpu_sensor_temps = self.get_cpu_temperatures().unwrap_or_default();
+        let cpu_temp_max = cpu_temperature::max_temperature(&cpu_sensor_temps);
 
         Ok(ThermalInfo {
             cpu_temp,
@@ -631,6 +919,9 @@ impl IOKit for IOKitImpl {
             battery_temp,
             is_throttling,
             cpu_power,
+            cpu_temp_source,
+            cpu_sensor_temps,
+            cpu_temp_max,
         })
     }
 
@@ -639,6 +930,14 @@ impl IOKit for IOKitImpl {
         self.smc_read_key(key)
     }
 
+    fn list_smc_keys(&self) -> Result<Vec<SmcKeyInfo>> {
+        self.with_smc(SmcConnection::list_keys)
+    }
+
+    fn read_smc_key_typed(&self, key: SmcKey) -> Result<SmcValue> {
+        self.with_smc(|smc| smc.read_key_typed(key))
+    }
+
     // Fan related methods
     fn get_fan_speed(&self) -> Result<u32> {
         // Fan speed needs to be converted from the raw value to RPM
@@ -652,41 +951,28 @@ impl IOKit for IOKitImpl {
     }
 
     fn get_fan_info(&self, fan_index: u32) -> Result<FanInfo> {
-        // Create dynamic SMC keys for the specified fan
-        let fan_key = [
-            b'F' as c_char,
-            (b'0' + fan_index as u8) as c_char, // Fan index (F0, F1, etc.)
-            b'A' as c_char,
-            b'c' as c_char,
-        ];
-
-        let fan_min_key = [
-            b'F' as c_char,
-            (b'0' + fan_index as u8) as c_char, // Fan index (F0, F1, etc.)
-            b'M' as c_char,
-            b'n' as c_char,
-        ];
-
-        let fan_max_key = [
-            b'F' as c_char,
-            (b'0' + fan_index as u8) as c_char, // Fan index (F0, F1, etc.)
-            b'M' as c_char,
-            b'x' as c_char,
-        ];
-
         // Get the speeds
-        let speed_rpm = self.smc_read_key(fan_key)? as u32;
-        let min_speed = self.smc_read_key(fan_min_key).unwrap_or(0.0) as u32;
-        let max_speed = self.smc_read_key(fan_max_key).unwrap_or(0.0) as u32;
-
-        // Calculate percentage
-        let percentage = if max_speed > min_speed && max_speed > 0 {
-            ((speed_rpm - min_speed) as f64 / (max_speed - min_speed) as f64) * 100.0
-        } else {
-            0.0
-        };
+        let speed_rpm = self.smc_read_key(fan_key(fan_index, *b"Ac"))? as u32;
+        let min_speed = self.smc_read_key(fan_key(fan_index, *b"Mn")).unwrap_or(0.0) as u32;
+        let max_speed = self.smc_read_key(fan_key(fan_index, *b"Mx")).unwrap_or(0.0) as u32;
+
+        let percentage = fan_percentage(speed_rpm, min_speed, max_speed);
+
+        Ok(FanInfo {
+            speed_rpm,
+            min_speed,
+            max_speed,
+            percentage,
+            target_rpm: self.get_fan_target(fan_index).ok(),
+            mode: self.get_fan_mode(fan_index).unwrap_or_default(),
+            name: self.get_fan_name(fan_index).ok(),
+        })
+    }
 
-        Ok(FanInfo { speed_rpm, min_speed, max_speed, percentage })
+    fn get_fan_name(&self, fan_index: u32) -> Result<String> {
+        let (_, bytes) = self.smc_read_key_bytes(fan_key(fan_index, *b"ID"))?;
+        parse_fan_descriptor(&bytes)
+            .ok_or_else(|| Error::invalid_data(format!("Fan {fan_index} has no name")))
     }
 
     fn get_all_fans(&self) -> Result<Vec<FanInfo>> {
@@ -705,8 +991,8 @@ impl IOKit for IOKitImpl {
         Ok(fans)
     }
 
+    #[tracing::instrument(level = "debug", skip(self))]
     fn get_gpu_stats(&self) -> Result<GpuStats> {
-        println!("DEBUG: Entering get_gpu_stats");
         // Default values
         let mut stats = GpuStats {
             utilization: 0.0,
@@ -716,29 +1002,21 @@ impl IOKit for IOKitImpl {
             memory_total: 0,
             name: "".to_string(),
         };
-        println!("DEBUG: Created default stats object");
 
         // Wrap in autoreleasepool to ensure proper memory management
         autoreleasepool(|_| {
-            println!("DEBUG: Inside autoreleasepool");
             // Try to get GPU information from IOKit's AGPMController using safer approach
             {
-                println!("DEBUG: Trying to get AGPMController");
                 let agpm_matching = self.io_service_matching("AGPMController");
-                println!("DEBUG: Got AGPMController matching dictionary");
                 // Create a scope to ensure proper object lifecycle
                 {
-                    println!("DEBUG: About to call io_service_get_matching_service");
                     if let Some(agpm_service) = self.io_service_get_matching_service(&agpm_matching)
                     {
-                        println!("DEBUG: Successfully got AGPMController service");
                         // Create another scope to ensure properties are released before service
                         {
-                            println!("DEBUG: About to call io_registry_entry_create_cf_properties");
                             if let Ok(properties) =
                                 self.io_registry_entry_create_cf_properties(&agpm_service)
                             {
-                                println!("DEBUG: Successfully got properties from AGPMController");
                                 // Get GPU performance capacity (0-100)
                                 let perf_cap = self
                                     .get_number_property(&properties, "GPUPerfCap")
@@ -755,11 +1033,7 @@ impl IOKit for IOKitImpl {
                                 stats.perf_threshold = perf_threshold;
 
                                 // Calculate GPU utilization based on perf_cap and perf_threshold
-                                if perf_cap > 0.0 && perf_threshold > 0.0 {
-                                    stats.utilization = (perf_cap / perf_threshold) * 100.0;
-                                    // Clamp to range 0-100
-                                    stats.utilization = stats.utilization.clamp(0.0, 100.0);
-                                }
+                                stats.utilization = gpu_utilization(perf_cap, perf_threshold);
                                 // properties is dropped here
                             }
                         }
@@ -771,24 +1045,15 @@ impl IOKit for IOKitImpl {
 
             // Try to get GPU memory information from IORegistry using safer approach
             {
-                println!("DEBUG: Moving to try getting IOAccelerator info");
-                println!("DEBUG: About to call io_service_matching for IOAccelerator");
                 let accelerator_matching = self.io_service_matching("IOAccelerator");
-                println!("DEBUG: Got IOAccelerator matching dictionary");
                 {
-                    println!(
-                        "DEBUG: About to call io_service_get_matching_service for IOAccelerator"
-                    );
                     if let Some(accelerator) =
                         self.io_service_get_matching_service(&accelerator_matching)
                     {
-                        println!("DEBUG: Successfully got IOAccelerator service");
                         {
-                            println!("DEBUG: About to call io_registry_entry_create_cf_properties for IOAccelerator");
                             if let Ok(properties) =
                                 self.io_registry_entry_create_cf_properties(&accelerator)
                             {
-                                println!("DEBUG: Successfully got properties from IOAccelerator");
                                 // Get GPU memory information
                                 if let Some(total_vram) =
                                     self.get_number_property(&properties, "VRAM,totalMB")
@@ -823,8 +1088,9 @@ impl IOKit for IOKitImpl {
                 // accelerator_matching is dropped here
             }
 
-            // Fallback for Apple Silicon devices where memory isn't explicitly reported
-            if stats.memory_total == 0 {
+            // Fallback for Apple Silicon devices where memory isn't explicitly reported. Only their GPU shares the
+            // system memory; a discrete GPU without a reported size stays unknown
+            if stats.memory_total == 0 && cfg!(target_arch = "aarch64") {
                 // For Apple Silicon, try to get system memory and use a portion of it since Apple Silicon uses a
                 // unified memory architecture - use safer memory management
                 {
@@ -929,6 +1195,7 @@ impl IOKit for IOKitImpl {
             stats.name = format!("{} ({}°C)", stats.name, temp);
         }
 
+        tracing::debug!(?stats, "Read the GPU statistics");
         Ok(stats)
     }
 }
@@ -0,0 +1,85 @@
+//! Walking the IORegistry from a service up to the PCI device it's attached through
+//!
+//! Services such as GPU accelerators and storage drivers sit a few levels below the PCI device of their hardware in the
+//! service plane. The PCI device names the hardware with the `vendor-id` and `device-id` properties, which Apple Silicon
+//! GPUs and internal SSDs don't have since they aren't on the PCI bus.
+
+use std::ffi::CString;
+
+use objc2::rc::Retained;
+use objc2_foundation::{NSData, NSDictionary, NSObject, NSString};
+
+use super::{IOKit, IOServiceHandle};
+use crate::utils::bindings::{IOBSDNameMatching, IORegistryEntryIDMatching};
+
+/// Property holding the PCI vendor ID
+const VENDOR_ID_KEY: &str = "vendor-id";
+/// Property holding the PCI device ID
+const DEVICE_ID_KEY: &str = "device-id";
+/// Most levels walked up from a service looking for its PCI device, which is a handful of levels up
+const MAX_PCI_DEPTH: usize = 16;
+
+/// PCI vendor and device IDs of a piece of hardware
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PciDevice {
+    /// Vendor ID, e.g. `0x1002` for AMD or `0x8086` for Intel
+    pub vendor_id: u16,
+    /// Device ID assigned by the vendor
+    pub device_id: u16,
+}
+
+impl PciDevice {
+    /// Reads the IDs from the properties of a registry entry, None if it isn't a PCI device.
+    pub(crate) fn from_properties(properties: &NSDictionary<NSString, NSObject>) -> Option<Self> {
+        Some(Self {
+            vendor_id: pci_id(properties, VENDOR_ID_KEY)?,
+            device_id: pci_id(properties, DEVICE_ID_KEY)?,
+        })
+    }
+}
+
+/// Reads a PCI ID, stored as a little-endian 32-bit integer in a data property.
+fn pci_id(properties: &NSDictionary<NSString, NSObject>, key: &str) -> Option<u16> {
+    let value = properties.valueForKey(&NSString::from_str(key))?;
+    let bytes = value.downcast::<NSData>().ok()?.to_vec();
+    Some(u16::from_le_bytes([*bytes.first()?, *bytes.get(1)?]))
+}
+
+/// Walks up the service plane from `entry`, `entry` included, to the first PCI device.
+pub(crate) fn pci_device(io_kit: &dyn IOKit, entry: IOServiceHandle) -> Option<PciDevice> {
+    let mut entry = entry;
+    for _ in 0..MAX_PCI_DEPTH {
+        let properties = io_kit.io_registry_entry_create_cf_properties(&entry).ok();
+        if let Some(device) = properties.as_deref().and_then(PciDevice::from_properties) {
+            return Some(device);
+        }
+        entry = io_kit.io_registry_entry_get_parent(&entry)?;
+    }
+    None
+}
+
+/// Matching dictionary for the registry entry with the ID `registry_id`.
+pub(crate) fn registry_id_matching(
+    registry_id: u64,
+) -> Option<Retained<NSDictionary<NSString, NSObject>>> {
+    // SAFETY: the dictionary is returned with a +1 retain count and is toll-free bridged to NSDictionary
+    unsafe {
+        Retained::from_raw(
+            IORegistryEntryIDMatching(registry_id) as *mut NSDictionary<NSString, NSObject>
+        )
+    }
+}
+
+/// Matching dictionary for the media of the BSD device `bsd_name`, e.g. `disk0` or `disk3s1`.
+pub(crate) fn bsd_name_matching(
+    bsd_name: &str,
+) -> Option<Retained<NSDictionary<NSString, NSObject>>> {
+    let c_name = CString::new(bsd_name).ok()?;
+    // SAFETY: the dictionary is returned with a +1 retain count and is toll-free bridged to NSDictionary
+    unsafe {
+        Retained::from_raw(
+            IOBSDNameMatching(0, 0, c_name.as_ptr()) as *mut NSDictionary<NSString, NSObject>
+        )
+    }
+}
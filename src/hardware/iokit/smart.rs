@@ -0,0 +1,350 @@
+//! SMART data of NVMe and SATA drives.
+//!
+//! The storage drivers publish SMART support on the block storage device in the IORegistry: NVMe drives, including
+//! the internal SSDs of Apple Silicon Macs, set `NVMe SMART Capable` and SATA drives set `SMART Capable`. The data
+//! itself is read through the SMART user client of the driver, which is only reachable through an IOKit plug-in,
+//! the same way `smartctl` reads it.
+//!
+//! Drives in USB enclosures and virtual disks have neither property and report no SMART data.
+
+use std::{
+    ffi::{c_void as ffi_c_void, CString},
+    ptr,
+};
+
+use objc2::rc::{autoreleasepool, Retained};
+use objc2_foundation::{NSNumber, NSObject, NSString};
+
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{
+        CFRelease, CFUUIDBytes, CFUUIDCreateFromUUIDBytes, IOBSDNameMatching, IOCFPlugInInterface,
+        IOCreatePlugInInterfaceForService, IODestroyPlugInInterface, IOObjectRelease,
+        IORegistryEntryGetParentEntry, IORegistryEntrySearchCFProperty,
+        IOServiceGetMatchingService, IUnknownVTbl, IO_CF_PLUGIN_INTERFACE_ID,
+    },
+};
+
+/// Registry plane the device hierarchy is walked in
+const SERVICE_PLANE: &str = "IOService";
+/// Property of the block storage device of NVMe drives that support SMART
+const NVME_SMART_CAPABLE_KEY: &str = "NVMe SMART Capable";
+/// Property of the block storage device of SATA drives that support SMART
+const ATA_SMART_CAPABLE_KEY: &str = "SMART Capable";
+
+/// `kIONVMeSMARTUserClientTypeID`
+const NVME_SMART_USER_CLIENT_TYPE_ID: CFUUIDBytes = CFUUIDBytes {
+    bytes: [
+        0xAA, 0x0F, 0xA6, 0xF9, 0xC2, 0xD6, 0x45, 0x7F, 0xB1, 0x0B, 0x59, 0xA1, 0x32, 0x53, 0x29,
+        0x2F,
+    ],
+};
+/// `kIONVMeSMARTInterfaceID`
+const NVME_SMART_INTERFACE_ID: CFUUIDBytes = CFUUIDBytes {
+    bytes: [
+        0xCC, 0xD1, 0xDB, 0x19, 0xFD, 0x9A, 0x4D, 0xAF, 0xBF, 0x95, 0x12, 0x45, 0x4B, 0x23, 0x0A,
+        0xB6,
+    ],
+};
+/// `kIOATASMARTUserClientTypeID`
+const ATA_SMART_USER_CLIENT_TYPE_ID: CFUUIDBytes = CFUUIDBytes {
+    bytes: [
+        0x24, 0x51, 0x4B, 0x7A, 0x28, 0x04, 0x11, 0xD6, 0x8A, 0x02, 0x00, 0x30, 0x65, 0x70, 0x48,
+        0x66,
+    ],
+};
+/// `kIOATASMARTInterfaceID`
+const ATA_SMART_INTERFACE_ID: CFUUIDBytes = CFUUIDBytes {
+    bytes: [
+        0x08, 0xAB, 0xE2, 0x1C, 0x20, 0xD4, 0x11, 0xD6, 0x8D, 0xF6, 0x00, 0x03, 0x93, 0x5A, 0x76,
+        0xB2,
+    ],
+};
+
+/// Size of the NVMe SMART / Health Information log page
+const NVME_SMART_LOG_SIZE: usize = 512;
+/// Size of the data returned by the ATA `SMART READ DATA` command
+const ATA_SMART_DATA_SIZE: usize = 512;
+/// Offset and size of the attribute table in the ATA SMART data, 30 entries of 12 bytes
+const ATA_ATTRIBUTES_OFFSET: usize = 2;
+const ATA_ATTRIBUTE_SIZE: usize = 12;
+const ATA_ATTRIBUTE_COUNT: usize = 30;
+/// ATA SMART attributes that are read
+const ATA_POWER_ON_HOURS: u8 = 9;
+const ATA_REPORTED_UNCORRECTABLE: u8 = 187;
+const ATA_TEMPERATURE: u8 = 194;
+
+/// Kelvin of 0 °C, the NVMe temperature being reported in Kelvin
+const KELVIN_OFFSET: f64 = 273.15;
+
+/// How a drive reports its SMART data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SmartInterface {
+    /// The SMART / Health Information log of an NVMe drive
+    #[default]
+    Nvme,
+    /// The SMART attributes of a SATA drive
+    Ata,
+}
+
+/// SMART data of a drive
+///
+/// Fields are None when the drive's interface doesn't report them: the wear and spare fields only exist on NVMe
+/// drives, and whether a threshold was exceeded is only reported by SATA drives.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SmartData {
+    /// How the data was read
+    pub interface: SmartInterface,
+    /// NVMe critical warning bits, 0 if none is set
+    pub critical_warning: Option<u8>,
+    /// Percentage of the rated endurance used, which may exceed 100
+    pub percentage_used: Option<u8>,
+    /// Remaining spare capacity in percent
+    pub available_spare: Option<u8>,
+    /// Spare capacity in percent below which the drive reports a critical warning
+    pub available_spare_threshold: Option<u8>,
+    /// Media and data integrity errors on NVMe, reported uncorrectable errors on SATA
+    pub media_errors: Option<u64>,
+    /// Hours the drive has been powered on
+    pub power_on_hours: Option<u64>,
+    /// Drive temperature in degrees Celsius
+    pub temperature_celsius: Option<f64>,
+    /// Whether any SMART attribute crossed its failure threshold
+    pub threshold_exceeded: Option<bool>,
+}
+
+/// `IONVMeSMARTInterface`, up to the fields that are used
+#[repr(C)]
+struct NvmeSmartInterface {
+    _iunknown: IUnknownVTbl,
+    _version: u16,
+    _revision: u16,
+    smart_read_data: unsafe extern "C" fn(this: *mut ffi_c_void, data: *mut u8) -> i32,
+}
+
+/// `IOATASMARTInterface`, up to the fields that are used
+#[repr(C)]
+struct AtaSmartInterface {
+    _iunknown: IUnknownVTbl,
+    _version: u16,
+    _revision: u16,
+    _smart_enable_disable_operations:
+        unsafe extern "C" fn(this: *mut ffi_c_void, enable: u8) -> i32,
+    _smart_enable_disable_autosave: unsafe extern "C" fn(this: *mut ffi_c_void, enable: u8) -> i32,
+    smart_return_status: unsafe extern "C" fn(this: *mut ffi_c_void, exceeded: *mut u8) -> i32,
+    _smart_execute_off_line_immediate:
+        unsafe extern "C" fn(this: *mut ffi_c_void, extended_test: u8) -> i32,
+    smart_read_data: unsafe extern "C" fn(this: *mut ffi_c_void, data: *mut u8) -> i32,
+}
+
+/// Parses an NVMe SMART / Health Information log page, None if it's too short.
+pub(crate) fn parse_nvme_smart_log(log: &[u8]) -> Option<SmartData> {
+    // Counters are 128-bit little-endian values; anything past u64 is saturated
+    let counter = |offset: usize| -> Option<u64> {
+        let bytes: [u8; 16] = log.get(offset..offset + 16)?.try_into().ok()?;
+        Some(u64::try_from(u128::from_le_bytes(bytes)).unwrap_or(u64::MAX))
+    };
+
+    let kelvin = u16::from_le_bytes([*log.get(1)?, *log.get(2)?]);
+    Some(SmartData {
+        interface: SmartInterface::Nvme,
+        critical_warning: Some(log[0]),
+        temperature_celsius: (kelvin > 0).then(|| f64::from(kelvin) - KELVIN_OFFSET),
+        available_spare: Some(*log.get(3)?),
+        available_spare_threshold: Some(*log.get(4)?),
+        percentage_used: Some(*log.get(5)?),
+        power_on_hours: Some(counter(128)?),
+        media_errors: Some(counter(160)?),
+        threshold_exceeded: None,
+    })
+}
+
+/// Parses the data of the ATA `SMART READ DATA` command, given whether `SMART RETURN STATUS` reported an exceeded
+/// threshold.
+pub(crate) fn parse_ata_smart_data(data: &[u8], threshold_exceeded: bool) -> SmartData {
+    let raw = |id: u8| -> Option<[u8; 6]> {
+        data.get(ATA_ATTRIBUTES_OFFSET..)?
+            .chunks_exact(ATA_ATTRIBUTE_SIZE)
+            .take(ATA_ATTRIBUTE_COUNT)
+            .find(|attribute| attribute[0] == id)
+            .and_then(|attribute| attribute[5..11].try_into().ok())
+    };
+    let raw_u32 =
+        |id: u8| raw(id).map(|raw| u64::from(u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]])));
+
+    SmartData {
+        interface: SmartInterface::Ata,
+        // Only the lowest 32 bits count hours; some drives put minutes in the bytes above
+        power_on_hours: raw_u32(ATA_POWER_ON_HOURS),
+        media_errors: raw_u32(ATA_REPORTED_UNCORRECTABLE),
+        // The lowest byte is the current temperature; the others hold the lifetime minimum and maximum
+        temperature_celsius: raw(ATA_TEMPERATURE).map(|raw| f64::from(raw[0])),
+        threshold_exceeded: Some(threshold_exceeded),
+        ..SmartData::default()
+    }
+}
+
+/// Reads the SMART data of the drive storing the BSD device `bsd_name`, e.g. `disk0` or `disk3s1`.
+pub(crate) fn read_smart_data(bsd_name: &str) -> Result<SmartData> {
+    let c_name = CString::new(bsd_name)
+        .map_err(|_| Error::invalid_data(format!("Invalid BSD name: {bsd_name}")))?;
+    let plane = CString::new(SERVICE_PLANE)
+        .map_err(|_| Error::invalid_data("Invalid registry plane name"))?;
+
+    autoreleasepool(|_| unsafe {
+        // IOServiceGetMatchingService consumes the matching dictionary
+        let matching = IOBSDNameMatching(0, 0, c_name.as_ptr());
+        if matching.is_null() {
+            return Err(Error::io_kit(format!(
+                "Failed to create matching dictionary for {bsd_name}"
+            )));
+        }
+        let mut entry = IOServiceGetMatchingService(0, matching);
+        if entry == 0 {
+            return Err(Error::service_not_found(format!("No registry entry for {bsd_name}")));
+        }
+
+        // The block storage device publishing SMART support is an ancestor of every media stored on the drive
+        loop {
+            let interface = if bool_property(entry, &plane, NVME_SMART_CAPABLE_KEY) {
+                Some(SmartInterface::Nvme)
+            } else if bool_property(entry, &plane, ATA_SMART_CAPABLE_KEY) {
+                Some(SmartInterface::Ata)
+            } else {
+                None
+            };
+            if let Some(interface) = interface {
+                let data = match interface {
+                    SmartInterface::Nvme => read_nvme(entry),
+                    SmartInterface::Ata => read_ata(entry),
+                };
+                IOObjectRelease(entry);
+                return data;
+            }
+
+            let mut parent = 0;
+            let found = IORegistryEntryGetParentEntry(entry, plane.as_ptr(), &mut parent) == 0
+                && parent != 0;
+            IOObjectRelease(entry);
+            if !found {
+                return Err(Error::not_available(format!(
+                    "The drive storing {bsd_name} has no SMART interface"
+                )));
+            }
+            entry = parent;
+        }
+    })
+}
+
+/// Whether the registry entry itself has the boolean property `key` set.
+unsafe fn bool_property(entry: u32, plane: &CString, key: &str) -> bool {
+    let key = NSString::from_str(key);
+    let value = IORegistryEntrySearchCFProperty(
+        entry,
+        plane.as_ptr(),
+        Retained::as_ptr(&key) as *const ffi_c_void,
+        ptr::null(),
+        0,
+    );
+    // The value is returned with a +1 retain count; CFBoolean is toll-free bridged to NSNumber
+    Retained::from_raw(value as *mut NSObject)
+        .and_then(|value| value.downcast::<NSNumber>().ok())
+        .is_some_and(|value| value.as_bool())
+}
+
+unsafe fn read_nvme(service: u32) -> Result<SmartData> {
+    let plugin = PlugIn::create(service, NVME_SMART_USER_CLIENT_TYPE_ID)?;
+    let interface = plugin.query::<NvmeSmartInterface>(NVME_SMART_INTERFACE_ID)?;
+
+    let mut log = [0u8; NVME_SMART_LOG_SIZE];
+    let result = ((**interface.0).smart_read_data)(interface.0.cast(), log.as_mut_ptr());
+    if result != 0 {
+        return Err(Error::io_return("Failed to read the NVMe SMART log", result));
+    }
+    parse_nvme_smart_log(&log).ok_or_else(|| Error::invalid_data("Truncated NVMe SMART log"))
+}
+
+unsafe fn read_ata(service: u32) -> Result<SmartData> {
+    let plugin = PlugIn::create(service, ATA_SMART_USER_CLIENT_TYPE_ID)?;
+    let interface = plugin.query::<AtaSmartInterface>(ATA_SMART_INTERFACE_ID)?;
+
+    let mut exceeded = 0u8;
+    let result = ((**interface.0).smart_return_status)(interface.0.cast(), &mut exceeded);
+    if result != 0 {
+        return Err(Error::io_return("Failed to read the ATA SMART status", result));
+    }
+
+    let mut data = [0u8; ATA_SMART_DATA_SIZE];
+    let result = ((**interface.0).smart_read_data)(interface.0.cast(), data.as_mut_ptr());
+    if result != 0 {
+        return Err(Error::io_return("Failed to read the ATA SMART data", result));
+    }
+    Ok(parse_ata_smart_data(&data, exceeded != 0))
+}
+
+/// An IOKit plug-in for a service, destroyed when dropped
+struct PlugIn(*mut *mut IOCFPlugInInterface);
+
+impl PlugIn {
+    unsafe fn create(service: u32, plugin_type: CFUUIDBytes) -> Result<Self> {
+        let plugin_type = CFUUIDCreateFromUUIDBytes(ptr::null(), plugin_type);
+        let interface_type = CFUUIDCreateFromUUIDBytes(ptr::null(), IO_CF_PLUGIN_INTERFACE_ID);
+        let mut plugin = ptr::null_mut();
+        let mut score = 0;
+        let result = IOCreatePlugInInterfaceForService(
+            service,
+            plugin_type,
+            interface_type,
+            &mut plugin,
+            &mut score,
+        );
+        CFRelease(plugin_type);
+        CFRelease(interface_type);
+
+        if result != 0 || plugin.is_null() {
+            // The plug-in is missing in sandboxed processes and on drives whose driver doesn't provide one
+            return Err(Error::not_available(format!(
+                "Failed to open the SMART user client: {result:#x}"
+            )));
+        }
+        Ok(Self(plugin))
+    }
+
+    /// Queries the plug-in for the COM-style interface `T` with the ID `interface_id`.
+    unsafe fn query<T>(&self, interface_id: CFUUIDBytes) -> Result<Interface<T>> {
+        let mut interface = ptr::null_mut();
+        let result =
+            ((**self.0).iunknown.query_interface)(self.0.cast(), interface_id, &mut interface);
+        if result != 0 || interface.is_null() {
+            return Err(Error::not_available(format!(
+                "Failed to query the SMART interface: {result:#x}"
+            )));
+        }
+        Ok(Interface(interface.cast()))
+    }
+}
+
+impl Drop for PlugIn {
+    fn drop(&mut self) {
+        // SAFETY: the plug-in was created by IOCreatePlugInInterfaceForService and is destroyed once.
+        unsafe {
+            IODestroyPlugInInterface(self.0);
+        }
+    }
+}
+
+/// A COM-style interface whose vtable starts with [`IUnknownVTbl`], released when dropped
+struct Interface<T>(*mut *mut T);
+
+impl<T> Drop for Interface<T> {
+    fn drop(&mut self) {
+        // SAFETY: every interface returned by QueryInterface starts with the IUnknown functions and holds one
+        // reference.
+        unsafe {
+            let unknown = self.0 as *mut *mut IUnknownVTbl;
+            ((**unknown).release)(unknown.cast());
+        }
+    }
+}
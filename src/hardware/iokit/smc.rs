@@ -0,0 +1,175 @@
+//! SMC keys and the values of their data types.
+//!
+//! Every SMC key has a four character name, a four character data type and a size. Integers and fixed-point numbers
+//! are stored big-endian; `flt` only exists on Apple Silicon and is stored little-endian. The fixed-point types name
+//! their layout in their last two characters as hex digits: `fpXY` is unsigned with X integer and Y fraction bits,
+//! `spXY` signed with X integer and Y fraction bits besides the sign bit, e.g. `fp2e` or `sp78`.
+
+use std::{fmt, os::raw::c_char, str::FromStr};
+
+use crate::error::{Error, Result};
+
+/// Name of an SMC key, e.g. `TC0P`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SmcKey([u8; 4]);
+
+impl SmcKey {
+    /// The key named by the four bytes `name`.
+    pub const fn new(name: [u8; 4]) -> Self {
+        Self(name)
+    }
+
+    /// The four bytes of the name.
+    pub fn as_bytes(&self) -> [u8; 4] {
+        self.0
+    }
+
+    /// The key as the SMC identifies it, its name as a big-endian integer.
+    pub(crate) fn code(&self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    /// The key the SMC identifies as `code`.
+    pub(crate) fn from_code(code: u32) -> Self {
+        Self(code.to_be_bytes())
+    }
+}
+
+impl From<[c_char; 4]> for SmcKey {
+    fn from(key: [c_char; 4]) -> Self {
+        Self(key.map(|c| c as u8))
+    }
+}
+
+impl FromStr for SmcKey {
+    type Err = Error;
+
+    /// Parses a key name of exactly four ASCII characters.
+    fn from_str(name: &str) -> Result<Self> {
+        match <[u8; 4]>::try_from(name.as_bytes()) {
+            Ok(bytes) if name.is_ascii() => Ok(Self(bytes)),
+            _ => Err(Error::invalid_data(format!("Invalid SMC key: {name:?}"))),
+        }
+    }
+}
+
+impl fmt::Display for SmcKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.0))
+    }
+}
+
+/// An SMC key with its data type and size, as listed by the SMC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SmcKeyInfo {
+    /// Name of the key
+    pub key: SmcKey,
+    /// Data type, e.g. `flt ` or `sp78`
+    pub data_type: [u8; 4],
+    /// Size of the value in bytes
+    pub size: u32,
+}
+
+impl SmcKeyInfo {
+    /// The data type as text, without trailing spaces.
+    pub fn data_type_name(&self) -> String {
+        String::from_utf8_lossy(&self.data_type).trim_end().to_string()
+    }
+}
+
+/// Value of an SMC key in its data type
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SmcValue {
+    /// `flt`
+    Float(f32),
+    /// `fpXY` unsigned fixed-point number
+    UnsignedFixed(f64),
+    /// `spXY` signed fixed-point number
+    SignedFixed(f64),
+    /// `ui8`
+    U8(u8),
+    /// `ui16`
+    U16(u16),
+    /// `ui32`
+    U32(u32),
+    /// `si8`
+    I8(i8),
+    /// `si16`
+    I16(i16),
+    /// `flag`
+    Flag(bool),
+    /// `hex_`, raw bytes
+    Hex(Vec<u8>),
+}
+
+impl SmcValue {
+    /// Decodes the bytes of a value of type `data_type`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for types other than the ones of [`SmcValue`], or if `bytes` is too short for the type.
+    pub fn decode(data_type: &[u8; 4], bytes: &[u8]) -> Result<Self> {
+        let unsupported = || {
+            Error::invalid_data(format!(
+                "Unsupported SMC data type: {:?}",
+                std::str::from_utf8(data_type).unwrap_or("Unknown")
+            ))
+        };
+        let take = |len: usize| {
+            bytes.get(..len).ok_or_else(|| {
+                Error::invalid_data(format!(
+                    "SMC value of type {:?} has {} bytes, expected {len}",
+                    String::from_utf8_lossy(data_type),
+                    bytes.len()
+                ))
+            })
+        };
+        let u16_be = || take(2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+
+        let lower = data_type.map(|byte| byte.to_ascii_lowercase());
+        Ok(match &lower {
+            b"flt " => Self::Float(f32::from_le_bytes(take(4)?.try_into().unwrap_or_default())),
+            b"ui8 " => Self::U8(take(1)?[0]),
+            b"ui16" => Self::U16(u16_be()?),
+            b"ui32" => Self::U32(u32::from_be_bytes(take(4)?.try_into().unwrap_or_default())),
+            b"si8 " => Self::I8(take(1)?[0] as i8),
+            b"si16" => Self::I16(u16_be()? as i16),
+            b"flag" => Self::Flag(take(1)?[0] != 0),
+            b"hex_" => Self::Hex(bytes.to_vec()),
+            [b'f', b'p', integer, fraction] => {
+                let fraction = fixed_point_bits(*integer, *fraction, 16).ok_or_else(unsupported)?;
+                Self::UnsignedFixed(f64::from(u16_be()?) / f64::from(1u32 << fraction))
+            },
+            [b's', b'p', integer, fraction] => {
+                let fraction = fixed_point_bits(*integer, *fraction, 15).ok_or_else(unsupported)?;
+                Self::SignedFixed(f64::from(u16_be()? as i16) / f64::from(1u32 << fraction))
+            },
+            _ => return Err(unsupported()),
+        })
+    }
+
+    /// The value as a number, None for `hex_` values.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Self::Float(value) => Some(f64::from(value)),
+            Self::UnsignedFixed(value) | Self::SignedFixed(value) => Some(value),
+            Self::U8(value) => Some(f64::from(value)),
+            Self::U16(value) => Some(f64::from(value)),
+            Self::U32(value) => Some(f64::from(value)),
+            Self::I8(value) => Some(f64::from(value)),
+            Self::I16(value) => Some(f64::from(value)),
+            Self::Flag(value) => Some(if value { 1.0 } else { 0.0 }),
+            Self::Hex(_) => None,
+        }
+    }
+}
+
+/// The fraction bits of a fixed-point type with the hex digits `integer` and `fraction`, if they add up to `bits`.
+fn fixed_point_bits(integer: u8, fraction: u8, bits: u32) -> Option<u32> {
+    let integer = char::from(integer).to_digit(16)?;
+    let fraction = char::from(fraction).to_digit(16)?;
+    (integer + fraction == bits).then_some(fraction)
+}
@@ -1,14 +1,30 @@
 #![allow(unused_imports)]
 
-use std::os::raw::c_char;
+use std::{
+    os::raw::c_char,
+    sync::{Arc, Mutex},
+};
 
-use objc2::{msg_send, rc::autoreleasepool};
+use objc2::{
+    msg_send,
+    rc::{autoreleasepool, Retained},
+};
+use objc2_foundation::{NSData, NSDictionary, NSObject, NSString};
 
 use crate::{
     error::{Error, Result},
-    hardware::iokit::{FanInfo, GpuStats, IOKit, IOKitImpl, MockIOKit, ThermalInfo},
+    hardware::iokit::{
+        connection::decode_smc_value,
+        cpu_temperature::{cpu_sensor_keys, max_temperature, read_cpu_sensors},
+        gpu_temperature_keys, parse_fan_descriptor, pci_device, read_cpu_temperature,
+        read_gpu_temperature, CpuTemperatureLayer, CpuTemperatureSource, FanInfo, FanMode,
+        GpuStats, IOKit, IOKitImpl, MockIOKit, NamedTemperature, PciDevice, SensorLayer,
+        SensorProvider, SensorReading, SmcKey, SmcLayer, SmcValue, ThermalInfo,
+    },
+    system::Architecture,
     utils::{
         bindings::{
+            smc_key_chars,
             smc_key_from_chars,
             // These constants are used in test_smc_read_key_mocks test (lines ~1000-1034)
             SMC_KEY_AMBIENT_TEMP,
@@ -17,7 +33,7 @@ use crate::{
             SMC_KEY_FAN_NUM,
             SMC_KEY_GPU_TEMP,
         },
-        test_utils::{create_test_dictionary, create_test_object},
+        test_utils::{create_test_dictionary, create_test_service, create_test_service_with_id},
     },
 };
 
@@ -63,6 +79,20 @@ fn test_get_gpu_temperature() {
     assert_eq!(result, 55.0);
 }
 
+#[test]
+fn test_shared_iokit_forwards() {
+    let mut mock_iokit = MockIOKit::new();
+    mock_iokit.expect_get_cpu_temperature().times(2).returning(|| Ok(45.5));
+    mock_iokit.expect_get_fan_count().returning(|| Ok(2));
+
+    // Every holder of the shared instance reads through the same mock
+    let shared: Arc<dyn IOKit> = Arc::new(mock_iokit);
+    let other = Arc::clone(&shared);
+    assert_eq!(shared.get_cpu_temperature().unwrap(), 45.5);
+    assert_eq!(other.get_cpu_temperature().unwrap(), 45.5);
+    assert_eq!(other.get_fan_count().unwrap(), 2);
+}
+
 #[test]
 fn test_get_gpu_stats() {
     // Create a mock IOKit implementation
@@ -123,7 +153,7 @@ fn test_io_service_get_matching_service() {
 fn test_io_registry_entry_create_cf_properties() {
     // Create a mock IOKit implementation
     let mut mock_iokit = MockIOKit::new();
-    let obj = create_test_object();
+    let service = create_test_service();
 
     // Set up expectations
     mock_iokit
@@ -131,7 +161,7 @@ fn test_io_registry_entry_create_cf_properties() {
         .returning(|_| Ok(create_test_dictionary()));
 
     // Call the method
-    let result = mock_iokit.io_registry_entry_create_cf_properties(&obj);
+    let result = mock_iokit.io_registry_entry_create_cf_properties(&service);
 
     // Verify we got a successful result
     assert!(result.is_ok());
@@ -189,6 +219,53 @@ fn test_get_number_property() {
     assert_eq!(result, None);
 }
 
+/// Collects what a tracing subscriber writes
+#[derive(Clone, Default)]
+struct CapturedWriter(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for CapturedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CapturedWriter {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[test]
+fn test_impl_logs_through_tracing() {
+    let writer = CapturedWriter::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(writer.clone())
+        .with_max_level(tracing::Level::TRACE)
+        .with_ansi(false)
+        .finish();
+
+    let iokit = IOKitImpl::default();
+    let dict = create_test_dictionary();
+    tracing::subscriber::with_default(subscriber, || {
+        assert_eq!(iokit.get_string_property(&dict, "GPUModel"), None);
+        assert_eq!(iokit.get_number_property(&dict, "GPUPerfCap"), None);
+        iokit.get_gpu_stats().expect("Reading the GPU statistics never fails");
+    });
+
+    // The diagnostics end up in the subscriber's writer instead of stdout
+    let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+    assert!(output.contains("get_string_property"), "Missing span in {output}");
+    assert!(output.contains("No value found"), "Missing lookup event in {output}");
+    assert!(output.contains("Read the GPU statistics"), "Missing GPU event in {output}");
+}
+
 #[test]
 fn test_get_bool_property() {
     // Create a mock IOKit implementation
@@ -250,12 +327,14 @@ fn test_get_thermal_info() {
     mock_iokit.expect_get_thermal_info().returning(|| {
         Ok(ThermalInfo {
             cpu_temp: 45.0,
-            gpu_temp: 55.0,
+            gpu_temp: Some(55.0),
             heatsink_temp: Some(40.0),
             ambient_temp: Some(25.0),
             battery_temp: Some(35.0),
             is_throttling: false,
             cpu_power: Some(15.0),
+            cpu_temp_source: CpuTemperatureSource::Smc,
+            ..Default::default()
         })
     });
 
@@ -264,7 +343,7 @@ fn test_get_thermal_info() {
 
     // Verify the result
     assert_eq!(result.cpu_temp, 45.0);
-    assert_eq!(result.gpu_temp, 55.0);
+    assert_eq!(result.gpu_temp, Some(55.0));
     assert_eq!(result.heatsink_temp, Some(40.0));
     assert_eq!(result.ambient_temp, Some(25.0));
     assert_eq!(result.battery_temp, Some(35.0));
@@ -281,12 +360,14 @@ fn test_get_thermal_info_with_failures() {
     mock_iokit.expect_get_thermal_info().returning(|| {
         Ok(ThermalInfo {
             cpu_temp: 45.0,
-            gpu_temp: 55.0,
+            gpu_temp: Some(55.0),
             heatsink_temp: None,
             ambient_temp: None,
             battery_temp: None,
             is_throttling: false,
             cpu_power: None,
+            cpu_temp_source: CpuTemperatureSource::Smc,
+            ..Default::default()
         })
     });
 
@@ -299,7 +380,7 @@ fn test_get_thermal_info_with_failures() {
 
     // Check that required fields were set
     assert_eq!(info.cpu_temp, 45.0);
-    assert_eq!(info.gpu_temp, 55.0);
+    assert_eq!(info.gpu_temp, Some(55.0));
 
     // Check that optional fields were set to None or default values
     assert_eq!(info.heatsink_temp, None);
@@ -316,7 +397,13 @@ fn test_get_fan_info() {
 
     // Set up the expectation
     mock_iokit.expect_get_fan_info().with(mockall::predicate::eq(0)).returning(|_| {
-        Ok(FanInfo { speed_rpm: 2000, min_speed: 500, max_speed: 5000, percentage: 40.0 })
+        Ok(FanInfo {
+            speed_rpm: 2000,
+            min_speed: 500,
+            max_speed: 5000,
+            percentage: 40.0,
+            ..Default::default()
+        })
     });
 
     // Call the method
@@ -342,6 +429,7 @@ fn test_get_fan_info_min_max_equal() {
             min_speed: 2000, // Same as current and max
             max_speed: 2000, // Same as current and min
             percentage: 0.0, // Should be 0 when min==max
+            ..Default::default()
         })
     });
 
@@ -359,13 +447,13 @@ fn test_get_fan_info_min_max_equal() {
 fn test_io_registry_entry_get_parent() {
     // Create a mock IOKit implementation
     let mut mock_iokit = MockIOKit::new();
-    let obj = create_test_object();
+    let service = create_test_service();
 
     // Set up the expectation
     mock_iokit.expect_io_registry_entry_get_parent().returning(|_| None);
 
     // Call the method
-    let result = mock_iokit.io_registry_entry_get_parent(&obj);
+    let result = mock_iokit.io_registry_entry_get_parent(&service);
 
     // Verify the result
     assert!(result.is_none());
@@ -377,7 +465,7 @@ fn test_get_service() {
     let mut mock_iokit = MockIOKit::new();
 
     // Set up the expectation
-    mock_iokit.expect_get_service().returning(|_| Ok(create_test_object().into()));
+    mock_iokit.expect_get_service().returning(|_| Ok(create_test_service()));
 
     // Call the method
     let result = mock_iokit.get_service("TestService");
@@ -387,29 +475,103 @@ fn test_get_service() {
 }
 
 #[test]
-#[cfg_attr(not(feature = "skip-ffi-crashes"), ignore)]
-fn test_impl_get_service_safety() {
-    // This test is automatically skipped unless the skip-ffi-crashes feature is enabled This prevents segmentation
-    // faults during normal testing
-
-    // Test the IOKitImpl's get_service method with skip-ffi-crashes feature
-    let iokit = IOKitImpl;
+fn test_impl_get_service_not_found() {
+    let iokit = IOKitImpl::default();
 
-    // The get_service method should be disabled for safety in this mode
-    let result = iokit.get_service("TestService");
-
-    // It should return an error without trying to access IOKit
-    assert!(result.is_err());
-    match result {
-        Err(e) => {
-            // Make sure we get the expected error message about service access being disabled
-            let error_msg = format!("{}", e);
-            assert!(error_msg.contains("Service access disabled for stability"));
+    // No service has this class, so the lookup fails without anything to release
+    match iokit.get_service("DarwinMetricsNoSuchService") {
+        Err(Error::ServiceNotFound(message)) => {
+            assert!(message.contains("DarwinMetricsNoSuchService"));
         },
-        _ => panic!("Expected an error from the disabled service access"),
+        other => panic!("Expected ServiceNotFound, got {other:?}"),
     }
 }
 
+#[test]
+fn test_registry_traversal_mock() {
+    let mut mock_iokit = MockIOKit::new();
+    mock_iokit.expect_find_service_by_path().returning(|path| {
+        (path == "IOService:/AppleARMPE").then(|| create_test_service_with_id(2))
+    });
+    mock_iokit.expect_io_registry_entry_get_name().returning(|entry| match entry.as_raw() {
+        2 => Ok("AppleARMPE".to_string()),
+        raw => Ok(format!("child{raw}")),
+    });
+    mock_iokit
+        .expect_io_registry_iterate_children()
+        .returning(|_, _| Ok(vec![create_test_service_with_id(3), create_test_service_with_id(4)]));
+
+    let expert = mock_iokit.find_service_by_path("IOService:/AppleARMPE").unwrap();
+    assert_eq!(mock_iokit.io_registry_entry_get_name(&expert).unwrap(), "AppleARMPE");
+
+    let names: Vec<String> = mock_iokit
+        .io_registry_iterate_children(&expert, "IOService")
+        .unwrap()
+        .iter()
+        .map(|child| mock_iokit.io_registry_entry_get_name(child).unwrap())
+        .collect();
+    assert_eq!(names, ["child3", "child4"]);
+}
+
+/// Properties of a PCI device, whose IDs are 32-bit little-endian data
+fn pci_properties(vendor_id: u16, device_id: u16) -> Retained<NSDictionary<NSString, NSObject>> {
+    let keys = [NSString::from_str("vendor-id"), NSString::from_str("device-id")];
+    let values: Vec<Retained<NSObject>> = [vendor_id, device_id]
+        .into_iter()
+        .map(|id| Retained::into_super(NSData::with_bytes(&u32::from(id).to_le_bytes())))
+        .collect();
+    NSDictionary::from_slices(&[&*keys[0], &*keys[1]], &[&*values[0], &*values[1]])
+}
+
+#[test]
+fn test_pci_device_from_properties() {
+    let device = PciDevice::from_properties(&pci_properties(0x8086, 0x3e9b));
+    assert_eq!(device, Some(PciDevice { vendor_id: 0x8086, device_id: 0x3e9b }));
+
+    assert_eq!(PciDevice::from_properties(&create_test_dictionary()), None);
+}
+
+#[test]
+fn test_pci_device_walks_up_to_the_pci_parent() {
+    // The accelerator 3 sits below the driver 2, which sits below the PCI device 1
+    let mut mock_iokit = MockIOKit::new();
+    mock_iokit.expect_io_registry_entry_create_cf_properties().returning(|entry| {
+        Ok(match entry.as_raw() {
+            1 => pci_properties(0x1002, 0x7340),
+            _ => create_test_dictionary(),
+        })
+    });
+    mock_iokit.expect_io_registry_entry_get_parent().returning(|entry| {
+        (entry.as_raw() > 1).then(|| create_test_service_with_id(entry.as_raw() - 1))
+    });
+
+    assert_eq!(
+        pci_device(&mock_iokit, create_test_service_with_id(3)),
+        Some(PciDevice { vendor_id: 0x1002, device_id: 0x7340 })
+    );
+}
+
+#[test]
+fn test_pci_device_without_pci_parent() {
+    // An Apple Silicon GPU has no PCI device above it
+    let mut mock_iokit = MockIOKit::new();
+    mock_iokit
+        .expect_io_registry_entry_create_cf_properties()
+        .returning(|_| Ok(create_test_dictionary()));
+    mock_iokit.expect_io_registry_entry_get_parent().returning(|entry| {
+        (entry.as_raw() > 1).then(|| create_test_service_with_id(entry.as_raw() - 1))
+    });
+    assert_eq!(pci_device(&mock_iokit, create_test_service_with_id(3)), None);
+
+    // A registry that loops back on itself ends the walk too
+    let mut mock_iokit = MockIOKit::new();
+    mock_iokit
+        .expect_io_registry_entry_create_cf_properties()
+        .returning(|_| Ok(create_test_dictionary()));
+    mock_iokit.expect_io_registry_entry_get_parent().returning(|_| Some(create_test_service()));
+    assert_eq!(pci_device(&mock_iokit, create_test_service()), None);
+}
+
 #[test]
 fn test_get_heatsink_temperature() {
     // Create a mock IOKit implementation
@@ -547,8 +709,20 @@ fn test_get_all_fans() {
     // Set up the expectation
     mock_iokit.expect_get_all_fans().returning(|| {
         Ok(vec![
-            FanInfo { speed_rpm: 2000, min_speed: 500, max_speed: 5000, percentage: 40.0 },
-            FanInfo { speed_rpm: 1800, min_speed: 400, max_speed: 4500, percentage: 35.0 },
+            FanInfo {
+                speed_rpm: 2000,
+                min_speed: 500,
+                max_speed: 5000,
+                percentage: 40.0,
+                ..Default::default()
+            },
+            FanInfo {
+                speed_rpm: 1800,
+                min_speed: 400,
+                max_speed: 4500,
+                percentage: 35.0,
+                ..Default::default()
+            },
         ])
     });
 
@@ -588,7 +762,13 @@ fn test_get_all_fans_partial_failure() {
 
     // Make the first fan succeed and second fan fail
     mock_iokit.expect_get_fan_info().with(mockall::predicate::eq(0)).returning(|_| {
-        Ok(FanInfo { speed_rpm: 2000, min_speed: 500, max_speed: 5000, percentage: 40.0 })
+        Ok(FanInfo {
+            speed_rpm: 2000,
+            min_speed: 500,
+            max_speed: 5000,
+            percentage: 40.0,
+            ..Default::default()
+        })
     });
 
     mock_iokit
@@ -648,6 +828,7 @@ fn test_fan_percentage_calculation() {
         min_speed: min,
         max_speed: max,
         percentage: expected_percentage,
+        ..Default::default()
     };
 
     // Verify the percentage value
@@ -660,11 +841,23 @@ fn test_fan_percentage_limits() {
     // Test edge cases for fan percentage calculation
 
     // 1. Test min speed (should be 0%)
-    let min_fan = FanInfo { speed_rpm: 1000, min_speed: 1000, max_speed: 5000, percentage: 0.0 };
+    let min_fan = FanInfo {
+        speed_rpm: 1000,
+        min_speed: 1000,
+        max_speed: 5000,
+        percentage: 0.0,
+        ..Default::default()
+    };
     assert_eq!(min_fan.percentage, 0.0);
 
     // 2. Test max speed (should be 100%)
-    let max_fan = FanInfo { speed_rpm: 5000, min_speed: 1000, max_speed: 5000, percentage: 100.0 };
+    let max_fan = FanInfo {
+        speed_rpm: 5000,
+        min_speed: 1000,
+        max_speed: 5000,
+        percentage: 100.0,
+        ..Default::default()
+    };
     assert_eq!(max_fan.percentage, 100.0);
 
     // 3. Test calculation with zero max value (edge case)
@@ -673,6 +866,7 @@ fn test_fan_percentage_limits() {
         min_speed: 1000,
         max_speed: 0, // This is an invalid scenario but should be handled gracefully
         percentage: 0.0,
+        ..Default::default()
     };
     // In this case, percentage should be 0.0 to avoid division by zero
     assert_eq!(zero_max_fan.percentage, 0.0);
@@ -683,7 +877,7 @@ fn test_parse_smc_data() {
     // Test parse_smc_data function - this only works when coverage feature is enabled
     #[cfg(feature = "skip-ffi-crashes")]
     {
-        let iokit = IOKitImpl;
+        let iokit = IOKitImpl::default();
 
         // Test with float data type
         let float_type = *b"flt\0";
@@ -768,7 +962,13 @@ fn test_gpu_stats_clone() {
 #[test]
 fn test_fan_info_clone() {
     // Test the Clone implementation for FanInfo
-    let fan = FanInfo { speed_rpm: 2000, min_speed: 500, max_speed: 5000, percentage: 40.0 };
+    let fan = FanInfo {
+        speed_rpm: 2000,
+        min_speed: 500,
+        max_speed: 5000,
+        percentage: 40.0,
+        ..Default::default()
+    };
 
     let fan_clone = fan.clone();
 
@@ -783,12 +983,14 @@ fn test_thermal_info_clone() {
     // Test the Clone implementation for ThermalInfo
     let info = ThermalInfo {
         cpu_temp: 45.0,
-        gpu_temp: 55.0,
+        gpu_temp: Some(55.0),
         heatsink_temp: Some(40.0),
         ambient_temp: Some(25.0),
         battery_temp: Some(35.0),
         is_throttling: false,
         cpu_power: Some(15.0),
+        cpu_temp_source: CpuTemperatureSource::Smc,
+        ..Default::default()
     };
 
     let info_clone = info.clone();
@@ -807,19 +1009,21 @@ fn test_thermal_info_debug() {
     // Test the Debug implementation for ThermalInfo
     let info = ThermalInfo {
         cpu_temp: 45.0,
-        gpu_temp: 55.0,
+        gpu_temp: Some(55.0),
         heatsink_temp: Some(40.0),
         ambient_temp: Some(25.0),
         battery_temp: Some(35.0),
         is_throttling: false,
         cpu_power: Some(15.0),
+        cpu_temp_source: CpuTemperatureSource::Smc,
+        ..Default::default()
     };
 
     let debug_str = format!("{:?}", info);
 
     // Make sure all the fields are represented in the debug output
     assert!(debug_str.contains("cpu_temp: 45.0"));
-    assert!(debug_str.contains("gpu_temp: 55.0"));
+    assert!(debug_str.contains("gpu_temp: Some(55.0)"));
     assert!(debug_str.contains("heatsink_temp: Some(40.0)"));
     assert!(debug_str.contains("ambient_temp: Some(25.0)"));
     assert!(debug_str.contains("battery_temp: Some(35.0)"));
@@ -830,16 +1034,16 @@ fn test_thermal_info_debug() {
 #[test]
 fn test_iokit_impl_default() {
     // Test the Default implementation for IOKitImpl
-    let iokit = IOKitImpl;
+    let iokit = IOKitImpl::default();
 
     // Just verify we can create an instance
-    assert!(matches!(iokit, IOKitImpl));
+    assert!(matches!(iokit, IOKitImpl { .. }));
 }
 
 #[test]
 fn test_iokit_impl_debug() {
     // Test the Debug implementation for IOKitImpl
-    let iokit = IOKitImpl;
+    let iokit = IOKitImpl::default();
 
     let debug_str = format!("{:?}", iokit);
 
@@ -859,7 +1063,7 @@ fn test_real_gpu_stats() {
 
     // Wrap the entire test in an autoreleasepool to ensure proper memory cleanup
     autoreleasepool(|_| {
-        let iokit = IOKitImpl;
+        let iokit = IOKitImpl::default();
         println!("Created IOKitImpl instance");
 
         // Test just getting IOAccelerator directly
@@ -1005,7 +1209,7 @@ fn test_real_gpu_stats() {
 #[test]
 #[cfg(feature = "skip-ffi-crashes")]
 fn test_smc_read_key_mocks() {
-    let iokit = IOKitImpl;
+    let iokit = IOKitImpl::default();
 
     if cfg!(feature = "skip-ffi-crashes") {
         // Test mocked values for CPU temperature
@@ -1046,3 +1250,879 @@ fn test_smc_read_key_mocks() {
         assert_eq!(unknown.as_ref().unwrap(), &0.0);
     }
 }
+
+/// Sensor provider returning fixed readings, standing in for the IORegistry and HID sensors
+struct FakeSensors(Result<Vec<SensorReading>>);
+
+impl SensorProvider for FakeSensors {
+    fn readings(&self) -> Result<Vec<SensorReading>> {
+        self.0.clone()
+    }
+}
+
+/// Layer returning a fixed result
+struct FixedLayer(CpuTemperatureSource, Result<f64>);
+
+impl CpuTemperatureLayer for FixedLayer {
+    fn source(&self) -> CpuTemperatureSource {
+        self.0
+    }
+
+    fn read(&self) -> Result<f64> {
+        self.1.clone()
+    }
+}
+
+#[test]
+fn test_smc_layer_reads_cpu_key() {
+    let mut mock_iokit = MockIOKit::new();
+    mock_iokit.expect_read_smc_key().withf(|key| *key == SMC_KEY_CPU_TEMP).returning(|_| Ok(51.5));
+
+    let layer = SmcLayer::new(&mock_iokit);
+    assert_eq!(layer.source(), CpuTemperatureSource::Smc);
+    assert_eq!(layer.read().unwrap(), 51.5);
+
+    let mut missing_key = MockIOKit::new();
+    missing_key.expect_read_smc_key().returning(|_| Err(Error::io_kit("SMC key TC0P not found")));
+    assert!(SmcLayer::new(&missing_key).read().is_err());
+}
+
+#[test]
+fn test_cpu_sensor_keys() {
+    let intel = cpu_sensor_keys(Architecture::Intel);
+    let apple_silicon = cpu_sensor_keys(Architecture::AppleSilicon);
+    assert!(intel.contains(&"TC0P") && !intel.contains(&"Tp01"));
+    assert!(apple_silicon.contains(&"Tp01") && apple_silicon.contains(&"Te05"));
+    assert!(!apple_silicon.contains(&"TC0P"));
+    assert_eq!(cpu_sensor_keys(Architecture::Unknown).len(), intel.len() + apple_silicon.len());
+
+    for key in cpu_sensor_keys(Architecture::Unknown) {
+        assert!(key.len() == 4 && key.is_ascii(), "{key} isn't an SMC key");
+    }
+}
+
+#[test]
+fn test_read_cpu_sensors_skips_missing_keys() {
+    let smc_key = |key: &[u8; 4]| key.map(|byte| byte as c_char);
+    let mut mock_iokit = MockIOKit::new();
+    mock_iokit.expect_read_smc_key().returning(move |key| {
+        if key == smc_key(b"Tp01") {
+            Ok(55.0)
+        } else if key == smc_key(b"Tp05") {
+            Ok(61.5)
+        } else if key == smc_key(b"Tp09") {
+            // Sensor of a core the chip doesn't have
+            Ok(0.0)
+        } else {
+            Err(Error::io_kit("Failed to read SMC key info: -536870160"))
+        }
+    });
+
+    let temperatures = read_cpu_sensors(&mock_iokit, &cpu_sensor_keys(Architecture::AppleSilicon));
+    assert_eq!(
+        temperatures,
+        vec![
+            NamedTemperature { sensor: "Tp01".to_string(), celsius: 55.0 },
+            NamedTemperature { sensor: "Tp05".to_string(), celsius: 61.5 },
+        ]
+    );
+    assert_eq!(max_temperature(&temperatures), Some(61.5));
+    assert_eq!(max_temperature(&[]), None);
+}
+
+#[test]
+fn test_gpu_temperature_keys() {
+    let intel = gpu_temperature_keys(Architecture::Intel);
+    let apple_silicon = gpu_temperature_keys(Architecture::AppleSilicon);
+    assert_eq!(intel[..2], [SMC_KEY_GPU_TEMP, smc_key_chars(b"TG0D")]);
+    assert_eq!(apple_silicon[..2], [SMC_KEY_GPU_TEMP, smc_key_chars(b"Tg05")]);
+    assert!(!intel.contains(&smc_key_chars(b"Tg05")));
+    assert!(!apple_silicon.contains(&smc_key_chars(b"TG0D")));
+    assert_eq!(
+        gpu_temperature_keys(Architecture::Unknown).len(),
+        intel.len() + apple_silicon.len() - 1
+    );
+}
+
+#[test]
+fn test_read_gpu_temperature_fallback_order() {
+    let read_with = |values: &'static [(&'static [u8; 4], f64)]| {
+        let tried = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut mock_iokit = MockIOKit::new();
+        let tried_keys = tried.clone();
+        mock_iokit.expect_read_smc_key().returning(move |key| {
+            tried_keys.lock().unwrap().push(key);
+            values
+                .iter()
+                .find(|(name, _)| smc_key_chars(name) == key)
+                .map(|&(_, celsius)| celsius)
+                .ok_or_else(|| Error::io_kit("Failed to read SMC key info: -536870160"))
+        });
+
+        let celsius =
+            read_gpu_temperature(&mock_iokit, &gpu_temperature_keys(Architecture::AppleSilicon));
+        let tried: Vec<String> =
+            tried.lock().unwrap().iter().map(|&key| SmcKey::from(key).to_string()).collect();
+        (celsius, tried)
+    };
+
+    // The canonical key wins when the machine has it
+    assert_eq!(
+        read_with(&[(b"TG0P", 50.0), (b"Tg05", 45.0)]),
+        (Some(50.0), vec!["TG0P".to_string()])
+    );
+
+    // Without it, the candidates are tried in order, skipping the sensors of unused cores
+    let (celsius, tried) = read_with(&[(b"Tg05", 0.0), (b"Tg0D", 48.0), (b"Tg0L", 60.0)]);
+    assert_eq!(celsius, Some(48.0));
+    assert_eq!(tried, ["TG0P", "Tg05", "Tg0D"]);
+
+    // M3 machines only have the last candidates
+    let (celsius, tried) = read_with(&[(b"Tf14", 52.5)]);
+    assert_eq!(celsius, Some(52.5));
+    assert_eq!(tried.last().map(String::as_str), Some("Tf14"));
+
+    let (celsius, tried) = read_with(&[]);
+    assert_eq!(celsius, None);
+    assert_eq!(tried.len(), gpu_temperature_keys(Architecture::AppleSilicon).len());
+}
+
+#[test]
+fn test_registry_layer_averages_cpu_sensors() {
+    let layer = SensorLayer::new(
+        CpuTemperatureSource::IORegistry,
+        FakeSensors(Ok(vec![
+            SensorReading::new("pmgr", 48.0),
+            SensorReading::new("PMU tdie1", 52.0),
+            SensorReading::new("battery", 30.0),
+            // Implausible readings are ignored
+            SensorReading::new("PMU tdie2", -40.0),
+            SensorReading::new("PMU tdie3", 300.0),
+        ])),
+    );
+
+    assert_eq!(layer.source(), CpuTemperatureSource::IORegistry);
+    assert_eq!(layer.read().unwrap(), 50.0);
+}
+
+#[test]
+fn test_hid_layer_without_cpu_sensors_fails() {
+    let layer = SensorLayer::new(
+        CpuTemperatureSource::Hid,
+        FakeSensors(Ok(vec![SensorReading::new("gas gauge battery", 31.0)])),
+    );
+    assert!(layer.read().is_err());
+
+    let failing = SensorLayer::new(
+        CpuTemperatureSource::Hid,
+        FakeSensors(Err(Error::service_not_found("No HID temperature sensors"))),
+    );
+    assert!(failing.read().is_err());
+
+    let layer = SensorLayer::new(
+        CpuTemperatureSource::Hid,
+        FakeSensors(Ok(vec![
+            SensorReading::new("pACC MTR Temp Sensor0", 44.0),
+            SensorReading::new("eACC MTR Temp Sensor0", 40.0),
+        ])),
+    );
+    assert_eq!(layer.read().unwrap(), 42.0);
+}
+
+#[test]
+fn test_fallback_chain_order() {
+    let smc = FixedLayer(CpuTemperatureSource::Smc, Ok(60.0));
+    let smc_missing = FixedLayer(CpuTemperatureSource::Smc, Err(Error::io_kit("no TC0P")));
+    let registry = FixedLayer(CpuTemperatureSource::IORegistry, Ok(55.0));
+    let registry_missing =
+        FixedLayer(CpuTemperatureSource::IORegistry, Err(Error::not_available("no sensors")));
+    let hid = FixedLayer(CpuTemperatureSource::Hid, Ok(50.0));
+
+    // The first layer that succeeds wins
+    assert_eq!(
+        read_cpu_temperature(&[&smc, &registry, &hid]).unwrap(),
+        (60.0, CpuTemperatureSource::Smc)
+    );
+    assert_eq!(
+        read_cpu_temperature(&[&smc_missing, &registry, &hid]).unwrap(),
+        (55.0, CpuTemperatureSource::IORegistry)
+    );
+    assert_eq!(
+        read_cpu_temperature(&[&smc_missing, &registry_missing, &hid]).unwrap(),
+        (50.0, CpuTemperatureSource::Hid)
+    );
+
+    // When every layer fails, each failure is reported
+    let err = read_cpu_temperature(&[&smc_missing, &registry_missing]).unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("no TC0P") && message.contains("no sensors"), "{message}");
+}
+
+#[test]
+fn test_default_cpu_temperature_source() {
+    let mut mock_iokit = MockIOKit::new();
+    mock_iokit
+        .expect_get_cpu_temperature_with_source()
+        .returning(|| Ok((47.0, CpuTemperatureSource::IORegistry)));
+
+    let (temperature, source) = mock_iokit.get_cpu_temperature_with_source().unwrap();
+    assert_eq!(temperature, 47.0);
+    assert_eq!(source, CpuTemperatureSource::IORegistry);
+    assert_eq!(CpuTemperatureSource::default(), CpuTemperatureSource::Smc);
+}
+
+#[test]
+#[cfg(all(target_arch = "aarch64", feature = "unstable-tests"))]
+fn test_cpu_temperature_on_apple_silicon() {
+    // Needs real thermal sensors, which virtualized CI runners don't have
+    let (temperature, source) = IOKitImpl::default().get_cpu_temperature_with_source().unwrap();
+    assert!((10.0..=120.0).contains(&temperature), "{temperature}°C from {source:?}");
+}
+
+mod connection {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use super::*;
+    use crate::{
+        hardware::iokit::{
+            connection::SmcBackend, HandleOwnership, IOServiceHandle, SmcConnection,
+        },
+        utils::bindings::{
+            SMCKeyData_keyInfo_t, SMCKeyData_t, IO_RETURN_BUSY, IO_RETURN_NOT_OPEN,
+            IO_RETURN_SUCCESS, SMC_CMD_READ_INDEX, SMC_CMD_READ_KEYINFO,
+        },
+    };
+
+    const CONNECTION: u32 = 0x1234;
+
+    /// SMC that answers every key with the SP78 value 42.5, after failing with the statuses in `failures`
+    #[derive(Debug, Default)]
+    struct FakeSmc {
+        opens: AtomicUsize,
+        calls: AtomicUsize,
+        closed: Mutex<Vec<u32>>,
+        failures: Mutex<Vec<i32>>,
+    }
+
+    impl FakeSmc {
+        fn closed(&self) -> Vec<u32> {
+            self.closed.lock().unwrap().clone()
+        }
+    }
+
+    impl SmcBackend for FakeSmc {
+        fn open(&self) -> Result<u32> {
+            self.opens.fetch_add(1, Ordering::SeqCst);
+            Ok(CONNECTION)
+        }
+
+        fn call(
+            &self,
+            connection: u32,
+            selector: u8,
+            _input: &SMCKeyData_t,
+            output: &mut SMCKeyData_t,
+        ) -> i32 {
+            assert_eq!(connection, CONNECTION);
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if let Some(status) = self.failures.lock().unwrap().pop() {
+                return status;
+            }
+            if selector == SMC_CMD_READ_KEYINFO {
+                output.data.key_info =
+                    SMCKeyData_keyInfo_t { data_size: 2, data_type: *b"SP78", data_attributes: 0 };
+            } else {
+                // SAFETY: every interpretation of the data union is plain bytes
+                let mut bytes = unsafe { output.data.bytes };
+                bytes[0] = 42;
+                bytes[1] = 128;
+                output.data.bytes = bytes;
+            }
+            IO_RETURN_SUCCESS
+        }
+
+        fn close(&self, connection: u32) {
+            self.closed.lock().unwrap().push(connection);
+        }
+    }
+
+    #[test]
+    fn test_injected_connection_skips_open() {
+        let smc = Arc::new(FakeSmc::default());
+        let connection =
+            SmcConnection::with_backend(CONNECTION, HandleOwnership::Borrowed, smc.clone());
+        let iokit = IOKitImpl::with_smc_connection(connection);
+
+        assert_eq!(iokit.get_gpu_temperature().unwrap(), 42.5);
+        assert_eq!(iokit.read_smc_key(SMC_KEY_AMBIENT_TEMP).unwrap(), 42.5);
+        assert_eq!(smc.opens.load(Ordering::SeqCst), 0, "The SMC must not be opened");
+        assert_eq!(smc.calls.load(Ordering::SeqCst), 4);
+
+        // A borrowed connection stays open for its owner
+        drop(iokit);
+        assert!(smc.closed().is_empty());
+    }
+
+    #[test]
+    fn test_owned_connection_closes_once() {
+        let smc = Arc::new(FakeSmc::default());
+        let connection =
+            SmcConnection::with_backend(CONNECTION, HandleOwnership::Owned, smc.clone());
+        let iokit = IOKitImpl::with_smc_connection(connection);
+        let clone = iokit.clone();
+
+        drop(iokit);
+        assert!(smc.closed().is_empty(), "Clones share the connection");
+        assert_eq!(clone.get_gpu_temperature().unwrap(), 42.5);
+        drop(clone);
+        assert_eq!(smc.closed(), vec![CONNECTION]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "skip-ffi-crashes"))]
+    fn test_kept_connection_opens_once_and_closes_on_drop() {
+        let smc = Arc::new(FakeSmc::default());
+        let iokit = IOKitImpl::with_smc_backend(smc.clone());
+        assert_eq!(smc.opens.load(Ordering::SeqCst), 0, "The SMC is opened on the first read");
+
+        assert_eq!(iokit.get_gpu_temperature().unwrap(), 42.5);
+        assert_eq!(iokit.get_gpu_temperature().unwrap(), 42.5);
+        assert_eq!(iokit.read_smc_key(SMC_KEY_AMBIENT_TEMP).unwrap(), 42.5);
+        assert_eq!(smc.opens.load(Ordering::SeqCst), 1);
+        assert_eq!(smc.calls.load(Ordering::SeqCst), 5, "The key info is read once per key");
+
+        let clone = iokit.clone();
+        drop(iokit);
+        assert!(smc.closed().is_empty(), "Clones share the connection");
+        assert_eq!(clone.get_gpu_temperature().unwrap(), 42.5);
+        assert_eq!(smc.opens.load(Ordering::SeqCst), 1);
+        drop(clone);
+        assert_eq!(smc.closed(), vec![CONNECTION], "Dropping the last clone closes the connection");
+    }
+
+    #[test]
+    #[cfg(not(feature = "skip-ffi-crashes"))]
+    fn test_stale_connection_reopens() {
+        let smc = Arc::new(FakeSmc::default());
+        let iokit = IOKitImpl::with_smc_backend(smc.clone());
+        smc.failures.lock().unwrap().push(IO_RETURN_NOT_OPEN);
+
+        assert_eq!(iokit.get_gpu_temperature().unwrap(), 42.5);
+        assert_eq!(smc.opens.load(Ordering::SeqCst), 2);
+        assert_eq!(smc.closed(), vec![CONNECTION], "The stale connection is closed");
+
+        // Other failures keep the connection
+        smc.failures.lock().unwrap().push(-1);
+        assert!(iokit.read_smc_key(SMC_KEY_AMBIENT_TEMP).is_err());
+        assert_eq!(iokit.read_smc_key(SMC_KEY_AMBIENT_TEMP).unwrap(), 42.5);
+        assert_eq!(smc.opens.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_busy_smc_is_retried() {
+        let smc = Arc::new(FakeSmc::default());
+        let connection =
+            SmcConnection::with_backend(CONNECTION, HandleOwnership::Borrowed, smc.clone());
+        let iokit = IOKitImpl::with_smc_connection(connection);
+
+        smc.failures.lock().unwrap().extend([IO_RETURN_BUSY, IO_RETURN_BUSY]);
+        assert_eq!(iokit.read_smc_key(SMC_KEY_AMBIENT_TEMP).unwrap(), 42.5);
+        assert_eq!(
+            smc.calls.load(Ordering::SeqCst),
+            4,
+            "Two failed reads, then the key info and value"
+        );
+
+        // Give up after three attempts
+        smc.failures.lock().unwrap().extend([IO_RETURN_BUSY; 3]);
+        let err = iokit.read_smc_key(SMC_KEY_BATTERY_TEMP).unwrap_err();
+        assert_eq!(err.os_code(), Some(IO_RETURN_BUSY));
+        assert_eq!(smc.calls.load(Ordering::SeqCst), 7);
+    }
+
+    #[test]
+    fn test_into_raw_hands_off() {
+        let smc = Arc::new(FakeSmc::default());
+        let connection = SmcConnection::open_with(smc.clone()).unwrap();
+        assert_eq!(connection.ownership(), HandleOwnership::Owned);
+
+        assert_eq!(connection.into_raw(), CONNECTION);
+        assert_eq!(smc.opens.load(Ordering::SeqCst), 1);
+        assert!(smc.closed().is_empty(), "A handed off connection must stay open");
+    }
+
+    #[test]
+    fn test_service_handle_ownership() {
+        static RELEASED: AtomicUsize = AtomicUsize::new(0);
+        fn release(_service: u32) {
+            RELEASED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        drop(IOServiceHandle::with_release(7, HandleOwnership::Borrowed, release));
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 0);
+
+        let handle = IOServiceHandle::with_release(7, HandleOwnership::Owned, release);
+        assert_eq!(handle.as_raw(), 7);
+        assert_eq!(handle.into_raw(), 7);
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 0);
+
+        drop(IOServiceHandle::with_release(7, HandleOwnership::Owned, release));
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 1);
+    }
+
+    /// SMC with the keys of one fan, each a data type and the bytes of its value
+    #[derive(Debug)]
+    struct FanSmc {
+        keys: HashMap<u32, ([u8; 4], Vec<u8>)>,
+    }
+
+    impl FanSmc {
+        /// A fan turning at `speed` RPM between `min` and `max`, forced to `target`
+        fn new(speed: f32, min: f32, max: f32, target: f32, name: &[u8]) -> Self {
+            let flt = |value: f32| (*b"flt ", value.to_ne_bytes().to_vec());
+            let key = |key: &[u8; 4]| smc_key_from_chars(key.map(|byte| byte as c_char));
+
+            let mut descriptor = vec![0, 0, 0, 0];
+            descriptor.extend_from_slice(name);
+            descriptor.resize(16, 0);
+
+            let keys = HashMap::from([
+                (key(b"F0Ac"), flt(speed)),
+                (key(b"F0Mn"), flt(min)),
+                (key(b"F0Mx"), flt(max)),
+                (key(b"F0Tg"), flt(target)),
+                (key(b"F0Md"), (*b"ui8 ", vec![1])),
+                (key(b"F0ID"), (*b"{fds", descriptor)),
+            ]);
+            Self { keys }
+        }
+    }
+
+    impl SmcBackend for FanSmc {
+        fn open(&self) -> Result<u32> {
+            Ok(CONNECTION)
+        }
+
+        fn call(
+            &self,
+            _connection: u32,
+            selector: u8,
+            input: &SMCKeyData_t,
+            output: &mut SMCKeyData_t,
+        ) -> i32 {
+            let Some((data_type, value)) = self.keys.get(&{ input.key }) else {
+                return -1;
+            };
+            if selector == SMC_CMD_READ_KEYINFO {
+                output.data.key_info = SMCKeyData_keyInfo_t {
+                    data_size: value.len() as u32,
+                    data_type: *data_type,
+                    data_attributes: 0,
+                };
+            } else {
+                let mut bytes = [0u8; 32];
+                bytes[..value.len()].copy_from_slice(value);
+                output.data.bytes = bytes;
+            }
+            IO_RETURN_SUCCESS
+        }
+
+        fn close(&self, _connection: u32) {}
+    }
+
+    fn fan_iokit(smc: FanSmc) -> IOKitImpl {
+        IOKitImpl::with_smc_connection(SmcConnection::with_backend(
+            CONNECTION,
+            HandleOwnership::Borrowed,
+            Arc::new(smc),
+        ))
+    }
+
+    #[test]
+    fn test_get_fan_info_reads_target_mode_and_name() {
+        let iokit = fan_iokit(FanSmc::new(3600.0, 1200.0, 6000.0, 4000.0, b"Left Side"));
+        let fan = iokit.get_fan_info(0).unwrap();
+
+        assert_eq!(fan.speed_rpm, 3600);
+        assert_eq!(fan.percentage, 50.0);
+        assert_eq!(fan.target_rpm, Some(4000));
+        assert_eq!(fan.mode, FanMode::Forced);
+        assert_eq!(fan.name.as_deref(), Some("Left Side"));
+    }
+
+    #[test]
+    fn test_get_fan_info_below_min_speed() {
+        // A fan spinning down, e.g. right after boot, reads below its minimum speed
+        let iokit = fan_iokit(FanSmc::new(800.0, 1200.0, 6000.0, 1200.0, b""));
+        let fan = iokit.get_fan_info(0).unwrap();
+
+        assert_eq!(fan.speed_rpm, 800);
+        assert_eq!(fan.min_speed, 1200);
+        assert_eq!(fan.percentage, 0.0, "The percentage is clamped instead of underflowing");
+        assert_eq!(fan.name, None, "An empty descriptor has no name");
+    }
+
+    /// SMC with the keys of a list, each a data type and the bytes of its value, and a `#KEY` counting them
+    #[derive(Debug)]
+    struct KeyListSmc {
+        keys: Vec<(u32, [u8; 4], Vec<u8>)>,
+    }
+
+    impl KeyListSmc {
+        fn new(keys: &[(&[u8; 4], &[u8; 4], &[u8])]) -> Self {
+            let mut keys: Vec<(u32, [u8; 4], Vec<u8>)> = keys
+                .iter()
+                .map(|(key, data_type, value)| {
+                    (SmcKey::new(**key).code(), **data_type, value.to_vec())
+                })
+                .collect();
+            let count = (keys.len() as u32 + 1).to_be_bytes().to_vec();
+            keys.insert(0, (SmcKey::new(*b"#KEY").code(), *b"ui32", count));
+            Self { keys }
+        }
+    }
+
+    impl SmcBackend for KeyListSmc {
+        fn open(&self) -> Result<u32> {
+            Ok(CONNECTION)
+        }
+
+        fn call(
+            &self,
+            _connection: u32,
+            selector: u8,
+            input: &SMCKeyData_t,
+            output: &mut SMCKeyData_t,
+        ) -> i32 {
+            if selector == SMC_CMD_READ_INDEX {
+                // SAFETY: the index is written as an integer
+                let index = unsafe { input.data.uint32 } as usize;
+                return match self.keys.get(index) {
+                    Some((key, _, _)) => {
+                        output.key = *key;
+                        IO_RETURN_SUCCESS
+                    },
+                    None => -1,
+                };
+            }
+
+            let Some((_, data_type, value)) =
+                self.keys.iter().find(|(key, _, _)| *key == input.key)
+            else {
+                return -1;
+            };
+            if selector == SMC_CMD_READ_KEYINFO {
+                output.data.key_info = SMCKeyData_keyInfo_t {
+                    data_size: value.len() as u32,
+                    data_type: *data_type,
+                    data_attributes: 0,
+                };
+            } else {
+                let mut bytes = [0u8; 32];
+                bytes[..value.len()].copy_from_slice(value);
+                output.data.bytes = bytes;
+            }
+            IO_RETURN_SUCCESS
+        }
+
+        fn close(&self, _connection: u32) {}
+    }
+
+    #[test]
+    fn test_list_smc_keys() {
+        let smc = KeyListSmc::new(&[
+            (b"TC0P", b"sp78", &[42, 128]),
+            (b"F0Ac", b"fpe2", &[0x1c, 0x20]),
+            (b"MSAl", b"flag", &[1]),
+        ]);
+        let iokit = IOKitImpl::with_smc_connection(SmcConnection::with_backend(
+            CONNECTION,
+            HandleOwnership::Borrowed,
+            Arc::new(smc),
+        ));
+
+        let keys = iokit.list_smc_keys().unwrap();
+        let names: Vec<String> = keys.iter().map(|info| info.key.to_string()).collect();
+        assert_eq!(names, ["#KEY", "TC0P", "F0Ac", "MSAl"]);
+        assert_eq!(keys[1].data_type_name(), "sp78");
+        assert_eq!(keys[2].size, 2);
+        assert_eq!(keys[3].data_type_name(), "flag");
+
+        assert_eq!(
+            iokit.read_smc_key_typed("F0Ac".parse().unwrap()).unwrap(),
+            SmcValue::UnsignedFixed(1800.0)
+        );
+        assert_eq!(iokit.read_smc_key_typed(SmcKey::new(*b"MSAl")).unwrap(), SmcValue::Flag(true));
+        let raw_key = SmcKey::new(*b"F0Ac").as_bytes().map(|byte| byte as c_char);
+        assert_eq!(iokit.read_smc_key(raw_key).unwrap(), 1800.0, "Read as a number as well");
+        assert!(iokit.read_smc_key_typed(SmcKey::new(*b"NONE")).is_err());
+    }
+}
+
+#[test]
+fn test_fan_mode_from_smc() {
+    assert_eq!(FanMode::from_smc(0.0), FanMode::Auto);
+    assert_eq!(FanMode::from_smc(1.0), FanMode::Forced);
+    assert_eq!(FanMode::from_smc(3.0), FanMode::Unknown);
+    assert_eq!(FanMode::default(), FanMode::Unknown);
+}
+
+#[test]
+fn test_parse_fan_descriptor() {
+    let mut descriptor = vec![1, 0, 2, 0];
+    descriptor.extend_from_slice(b"Right Side\0\0");
+    assert_eq!(parse_fan_descriptor(&descriptor).as_deref(), Some("Right Side"));
+
+    // The name is at most 12 bytes, without a terminating NUL when it fills them
+    let mut descriptor = vec![1, 0, 2, 0];
+    descriptor.extend_from_slice(b"Rear Exhaust1234");
+    assert_eq!(parse_fan_descriptor(&descriptor).as_deref(), Some("Rear Exhaust"));
+
+    assert_eq!(parse_fan_descriptor(&[1, 0, 2, 0, 0, 0]), None);
+    assert_eq!(parse_fan_descriptor(&[1, 0]), None);
+}
+
+#[test]
+fn test_decode_smc_value() {
+    assert_eq!(decode_smc_value(b"SP78", &[42, 128]), Some(42.5));
+    assert_eq!(decode_smc_value(b"flt ", &1500.0f32.to_ne_bytes()), Some(1500.0));
+    assert_eq!(decode_smc_value(b"ui8 ", &[3]), Some(3.0));
+    assert_eq!(decode_smc_value(b"flt ", &[0, 0]), None, "Too few bytes for a float");
+    assert_eq!(decode_smc_value(b"{fds", &[0; 16]), None);
+    assert_eq!(decode_smc_value(b"fpe2", &[0x1c, 0x20]), Some(1800.0));
+}
+
+#[test]
+fn test_smc_key() {
+    let key: SmcKey = "TC0P".parse().unwrap();
+    assert_eq!(key, SmcKey::new(*b"TC0P"));
+    assert_eq!(key.to_string(), "TC0P");
+    assert_eq!(key.code(), smc_key_from_chars([b'T', b'C', b'0', b'P'].map(|b| b as c_char)));
+    assert_eq!(SmcKey::from_code(key.code()), key);
+    assert_eq!(SmcKey::from([b'F', b'0', b'A', b'c'].map(|b| b as c_char)).to_string(), "F0Ac");
+
+    assert!("TC0".parse::<SmcKey>().is_err());
+    assert!("TC0P1".parse::<SmcKey>().is_err());
+    assert!("TÄ0".parse::<SmcKey>().is_err(), "Four bytes, but not four characters");
+}
+
+#[test]
+fn test_smc_value_float() {
+    let value = SmcValue::decode(b"flt ", &1234.5f32.to_le_bytes()).unwrap();
+    assert_eq!(value, SmcValue::Float(1234.5));
+    assert_eq!(value.as_f64(), Some(1234.5));
+}
+
+#[test]
+fn test_smc_value_unsigned_fixed() {
+    // fpe2: 14 integer and 2 fraction bits
+    assert_eq!(SmcValue::decode(b"fpe2", &[0x1c, 0x21]).unwrap(), SmcValue::UnsignedFixed(1800.25));
+    // fp88: 8 integer and 8 fraction bits
+    assert_eq!(SmcValue::decode(b"fp88", &[0xff, 0x80]).unwrap(), SmcValue::UnsignedFixed(255.5));
+    // fp2e: 2 integer and 14 fraction bits
+    assert_eq!(SmcValue::decode(b"fp2e", &[0x60, 0x00]).unwrap(), SmcValue::UnsignedFixed(1.5));
+    assert!(SmcValue::decode(b"fp99", &[0, 0]).is_err(), "The bits must add up to 16");
+}
+
+#[test]
+fn test_smc_value_signed_fixed() {
+    // sp78: sign, 7 integer and 8 fraction bits
+    assert_eq!(SmcValue::decode(b"sp78", &[42, 128]).unwrap(), SmcValue::SignedFixed(42.5));
+    assert_eq!(SmcValue::decode(b"SP78", &[0xfd, 0x80]).unwrap(), SmcValue::SignedFixed(-2.5));
+    // sp5a: sign, 5 integer and 10 fraction bits
+    assert_eq!(SmcValue::decode(b"sp5a", &[0x06, 0x00]).unwrap(), SmcValue::SignedFixed(1.5));
+    assert!(SmcValue::decode(b"sp88", &[0, 0]).is_err(), "The bits must add up to 15");
+}
+
+#[test]
+fn test_smc_value_integers() {
+    assert_eq!(SmcValue::decode(b"ui8 ", &[200]).unwrap(), SmcValue::U8(200));
+    assert_eq!(SmcValue::decode(b"ui16", &[0x12, 0x34]).unwrap(), SmcValue::U16(0x1234));
+    assert_eq!(
+        SmcValue::decode(b"ui32", &[0x00, 0x01, 0x02, 0x03]).unwrap(),
+        SmcValue::U32(0x0001_0203)
+    );
+    assert_eq!(SmcValue::decode(b"si8 ", &[0xfe]).unwrap(), SmcValue::I8(-2));
+    assert_eq!(SmcValue::decode(b"si16", &[0xff, 0x38]).unwrap(), SmcValue::I16(-200));
+    assert_eq!(SmcValue::I16(-200).as_f64(), Some(-200.0));
+}
+
+#[test]
+fn test_smc_value_flag_and_hex() {
+    assert_eq!(SmcValue::decode(b"flag", &[1]).unwrap(), SmcValue::Flag(true));
+    assert_eq!(SmcValue::decode(b"flag", &[0]).unwrap().as_f64(), Some(0.0));
+
+    let value = SmcValue::decode(b"hex_", &[0xde, 0xad]).unwrap();
+    assert_eq!(value, SmcValue::Hex(vec![0xde, 0xad]));
+    assert_eq!(value.as_f64(), None);
+}
+
+#[test]
+fn test_smc_value_errors() {
+    assert!(SmcValue::decode(b"ui32", &[0, 1]).is_err(), "Too few bytes");
+    assert!(SmcValue::decode(b"flt ", &[]).is_err(), "Too few bytes");
+    assert!(SmcValue::decode(b"{fds", &[0; 16]).is_err(), "Structures have no value type");
+    assert!(SmcValue::decode(b"fpzz", &[0, 0]).is_err(), "Not hex digits");
+}
+
+mod gpu_process {
+    use crate::hardware::iokit::{
+        gpu_process::{
+            client_stat, merge_clients, parse_client_creator, ACCUMULATED_GPU_TIME_KEY,
+            APP_USAGE_KEY, USER_CLIENT_CREATOR_KEY,
+        },
+        GpuProcessStat,
+    };
+
+    /// Reads the GPU time of each process from the user client properties in `fixture`.
+    fn process_stats(fixture: &str) -> Vec<GpuProcessStat> {
+        let clients: Vec<serde_json::Value> = serde_json::from_str(fixture).unwrap();
+        merge_clients(clients.iter().filter_map(|client| {
+            let gpu_times = client[APP_USAGE_KEY]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|usage| usage[ACCUMULATED_GPU_TIME_KEY].as_u64());
+            client_stat(client[USER_CLIENT_CREATOR_KEY].as_str(), gpu_times)
+        }))
+    }
+
+    fn stat(pid: u32, name: &str, gpu_time_ns: u64) -> GpuProcessStat {
+        GpuProcessStat { pid, name: name.to_string(), gpu_time_ns }
+    }
+
+    #[test]
+    fn test_apple_silicon_clients() {
+        let stats = process_stats(include_str!("../gpu/fixtures/agx_clients.json"));
+
+        // The clients of a process are summed, and clients without a creator or usage are left out
+        assert_eq!(
+            stats,
+            vec![
+                stat(412, "WindowServer", 18_238_567_890),
+                stat(2211, "Safari Graphics and Media", 734_000_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clients_without_gpu_time() {
+        assert!(process_stats(include_str!("../gpu/fixtures/intel_clients.json")).is_empty());
+        assert!(process_stats(include_str!("../gpu/fixtures/amd_clients.json")).is_empty());
+    }
+
+    #[test]
+    fn test_parse_client_creator() {
+        assert_eq!(
+            parse_client_creator("pid 412, WindowServer"),
+            Some((412, "WindowServer".into()))
+        );
+        assert_eq!(
+            parse_client_creator("pid 1022, Google Chrome Helper (GPU)"),
+            Some((1022, "Google Chrome Helper (GPU)".into()))
+        );
+        assert_eq!(parse_client_creator("pid 7"), Some((7, String::new())));
+        assert_eq!(parse_client_creator("pid x, WindowServer"), None);
+        assert_eq!(parse_client_creator("WindowServer"), None);
+    }
+}
+
+mod smart {
+    use crate::hardware::iokit::{
+        smart::{parse_ata_smart_data, parse_nvme_smart_log},
+        SmartData, SmartInterface,
+    };
+
+    /// An NVMe SMART log of a drive at 40 °C with 97% spare, 5% used, 1234 power-on hours and 2 media errors
+    fn nvme_log() -> [u8; 512] {
+        let mut log = [0u8; 512];
+        log[1..3].copy_from_slice(&313u16.to_le_bytes());
+        log[3] = 97;
+        log[4] = 10;
+        log[5] = 5;
+        log[128..144].copy_from_slice(&1234u128.to_le_bytes());
+        log[160..176].copy_from_slice(&2u128.to_le_bytes());
+        log
+    }
+
+    /// ATA SMART data with the given attributes as `(id, raw value)`, in table order
+    fn ata_data(attributes: &[(u8, [u8; 6])]) -> [u8; 512] {
+        let mut data = [0u8; 512];
+        for (i, (id, raw)) in attributes.iter().enumerate() {
+            let entry = 2 + i * 12;
+            data[entry] = *id;
+            data[entry + 3] = 100;
+            data[entry + 5..entry + 11].copy_from_slice(raw);
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_nvme_smart_log() {
+        let data = parse_nvme_smart_log(&nvme_log()).unwrap();
+
+        assert_eq!(data.interface, SmartInterface::Nvme);
+        assert_eq!(data.critical_warning, Some(0));
+        assert!((data.temperature_celsius.unwrap() - 39.85).abs() < 1e-9);
+        assert_eq!(data.available_spare, Some(97));
+        assert_eq!(data.available_spare_threshold, Some(10));
+        assert_eq!(data.percentage_used, Some(5));
+        assert_eq!(data.power_on_hours, Some(1234));
+        assert_eq!(data.media_errors, Some(2));
+        assert_eq!(data.threshold_exceeded, None);
+    }
+
+    #[test]
+    fn test_parse_nvme_smart_log_edge_cases() {
+        assert_eq!(parse_nvme_smart_log(&[0u8; 100]), None, "Too short for the counters");
+
+        let mut log = nvme_log();
+        log[1..3].copy_from_slice(&0u16.to_le_bytes());
+        log[128..144].copy_from_slice(&u128::MAX.to_le_bytes());
+        let data = parse_nvme_smart_log(&log).unwrap();
+        assert_eq!(data.temperature_celsius, None, "0 K means no sensor");
+        assert_eq!(data.power_on_hours, Some(u64::MAX), "Counters past u64 saturate");
+    }
+
+    #[test]
+    fn test_parse_ata_smart_data() {
+        let data = parse_ata_smart_data(
+            &ata_data(&[
+                (5, [0; 6]),
+                (9, [0x10, 0x27, 0, 0, 0x2a, 0]),
+                (187, [3, 0, 0, 0, 0, 0]),
+                (194, [35, 0, 20, 0, 55, 0]),
+            ]),
+            false,
+        );
+
+        assert_eq!(
+            data,
+            SmartData {
+                interface: SmartInterface::Ata,
+                power_on_hours: Some(10_000),
+                media_errors: Some(3),
+                temperature_celsius: Some(35.0),
+                threshold_exceeded: Some(false),
+                ..SmartData::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_ata_smart_data_missing_attributes() {
+        let data = parse_ata_smart_data(&ata_data(&[(9, [1, 0, 0, 0, 0, 0])]), true);
+
+        assert_eq!(data.power_on_hours, Some(1));
+        assert_eq!(data.media_errors, None);
+        assert_eq!(data.temperature_celsius, None);
+        assert_eq!(data.threshold_exceeded, Some(true));
+        assert_eq!(data.percentage_used, None, "SATA drives don't report wear");
+    }
+}
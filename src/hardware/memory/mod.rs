@@ -11,6 +11,7 @@
 //! - Swap usage tracking with activity rates
 //! - Asynchronous memory monitoring capabilities
 //! - Memory pressure callbacks for event-driven monitoring
+//! - Kernel zone statistics with the `zones` feature
 //!
 //! # Examples
 //!
@@ -59,28 +60,42 @@
 
 use std::{
     collections::VecDeque,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
-    },
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
 use crate::{
     error::{Error, Result},
     hardware::iokit::{IOKit, IOKitImpl},
-    utils::bindings::{
-        host_statistics64, mach_host_self, sysctl,
-        sysctl_constants::{CTL_HW, CTL_VM, HW_MEMSIZE, VM_SWAPUSAGE},
-        vm_kernel_page_size, vm_statistics64, xsw_usage, HostInfoT, HOST_VM_INFO64,
-        HOST_VM_INFO64_COUNT, KERN_SUCCESS,
+    shutdown::{self, ComponentHandle},
+    utils::{
+        bindings::{
+            host_statistics64, mach_host_self, read_xsw_usage, sysctl,
+            sysctl_constants::{CTL_HW, HW_MEMSIZE},
+            vm_kernel_page_size, vm_statistics64, HostInfoT, HOST_VM_INFO64, HOST_VM_INFO64_COUNT,
+            KERN_SUCCESS,
+        },
+        byte_size::ByteSize,
+        sanitize::Percentage,
     },
 };
 
+mod swap;
+#[cfg(feature = "zones")]
+mod zones;
+use swap::VmCounters;
+pub use swap::{free_swap, total_swap, used_swap, SwapActivity, SwapActivityTracker};
+#[cfg(feature = "zones")]
+pub use zones::{top_zones, zone_statistics, ZoneInfo, ZoneStatistics};
+
+/// Name of the task started by [`Memory::start_monitoring`] in a [`shutdown`](crate::shutdown) report
+pub(crate) const MEMORY_MONITOR: &str = "memory-pressure-monitor";
+
 /// Memory pressure level indicator
 ///
 /// Used to report the current memory pressure state of the system.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub enum PressureLevel {
     /// Normal memory pressure - sufficient memory available
@@ -105,6 +120,7 @@ impl std::fmt::Display for PressureLevel {
 ///
 /// Provides a breakdown of how memory pages are being used in the system.
 #[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PageStates {
     /// Memory pages actively in use
     pub active: u64,
@@ -122,6 +138,7 @@ pub struct PageStates {
 ///
 /// Tracks swap space utilization and activity rates.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SwapUsage {
     /// Total swap space in bytes
     pub total: u64,
@@ -143,12 +160,30 @@ impl Default for SwapUsage {
     }
 }
 
+impl SwapUsage {
+    /// Total swap space as a [`ByteSize`]
+    pub fn total_bytes(&self) -> ByteSize {
+        ByteSize::from_bytes(self.total)
+    }
+
+    /// Used swap space as a [`ByteSize`]
+    pub fn used_bytes(&self) -> ByteSize {
+        ByteSize::from_bytes(self.used)
+    }
+
+    /// Available swap space as a [`ByteSize`]
+    pub fn free_bytes(&self) -> ByteSize {
+        ByteSize::from_bytes(self.free)
+    }
+}
+
 /// Type definition for memory pressure callback functions
 pub type PressureCallback = Box<dyn Fn(PressureLevel) + Send + Sync>;
 
 /// Main memory monitoring and analysis interface
 ///
 /// Provides comprehensive memory metrics and monitoring capabilities.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Memory {
     /// Total physical memory in bytes
     pub total: u64,
@@ -164,6 +199,8 @@ pub struct Memory {
     pub page_states: PageStates,
     /// Swap usage and activity metrics
     pub swap_usage: SwapUsage,
+    /// Swap and paging rates since the previous update, None before there was one
+    pub swap_activity: Option<SwapActivity>,
     /// History of memory usage percentages
     history: VecDeque<f64>,
     /// Maximum number of history items to keep
@@ -173,15 +210,17 @@ pub struct Memory {
     /// Threshold for critical memory pressure (0.0-1.0)
     pressure_critical_threshold: f64,
     /// Registered callbacks for memory pressure changes
+    #[cfg_attr(feature = "serde", serde(skip))]
     pressure_callbacks: Arc<Mutex<Vec<PressureCallback>>>,
     /// Timestamp of last update
+    #[cfg_attr(feature = "serde", serde(skip, default = "Instant::now"))]
     last_update: Instant,
-    /// Previous swap-in operations count for rate calculation
-    prev_swap_in: u64,
-    /// Previous swap-out operations count for rate calculation
-    prev_swap_out: u64,
+    /// VM counters of the previous update for rate calculation
+    #[cfg_attr(feature = "serde", serde(skip))]
+    swap_tracker: SwapActivityTracker,
     /// IOKit interface for hardware access
-    iokit: Option<Box<dyn IOKit>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    iokit: Option<Arc<dyn IOKit>>,
 }
 
 impl std::fmt::Debug for Memory {
@@ -199,14 +238,14 @@ impl std::fmt::Debug for Memory {
             .field("pressure", &self.pressure)
             .field("page_states", &self.page_states)
             .field("swap_usage", &self.swap_usage)
+            .field("swap_activity", &self.swap_activity)
             .field("history", &self.history)
             .field("history_max_items", &self.history_max_items)
             .field("pressure_warning_threshold", &self.pressure_warning_threshold)
             .field("pressure_critical_threshold", &self.pressure_critical_threshold)
             .field("pressure_callbacks", &format!("<{} callbacks>", callback_count))
             .field("last_update", &self.last_update)
-            .field("prev_swap_in", &self.prev_swap_in)
-            .field("prev_swap_out", &self.prev_swap_out)
+            .field("swap_tracker", &self.swap_tracker)
             .field("iokit", &if self.iokit.is_some() { "Some(IOKit)" } else { "None" })
             .finish()
     }
@@ -222,14 +261,14 @@ impl Clone for Memory {
             pressure: self.pressure,
             page_states: self.page_states.clone(),
             swap_usage: self.swap_usage.clone(),
+            swap_activity: self.swap_activity,
             history: self.history.clone(),
             history_max_items: self.history_max_items,
             pressure_warning_threshold: self.pressure_warning_threshold,
             pressure_critical_threshold: self.pressure_critical_threshold,
             pressure_callbacks: Arc::new(Mutex::new(Vec::new())),
             last_update: self.last_update,
-            prev_swap_in: self.prev_swap_in,
-            prev_swap_out: self.prev_swap_out,
+            swap_tracker: self.swap_tracker.clone(),
             iokit: None,
         }
     }
@@ -244,6 +283,7 @@ impl PartialEq for Memory {
             && self.pressure == other.pressure
             && self.page_states == other.page_states
             && self.swap_usage == other.swap_usage
+            && self.swap_activity == other.swap_activity
             && self.history == other.history
             && self.history_max_items == other.history_max_items
             && (self.pressure_warning_threshold - other.pressure_warning_threshold).abs()
@@ -251,8 +291,7 @@ impl PartialEq for Memory {
             && (self.pressure_critical_threshold - other.pressure_critical_threshold).abs()
                 < f64::EPSILON
             && self.last_update == other.last_update
-            && self.prev_swap_in == other.prev_swap_in
-            && self.prev_swap_out == other.prev_swap_out
+            && self.swap_tracker == other.swap_tracker
     }
 }
 
@@ -285,15 +324,15 @@ impl Memory {
             pressure: 0.0,
             page_states: PageStates::default(),
             swap_usage: SwapUsage::default(),
+            swap_activity: None,
             history: VecDeque::with_capacity(60),
             history_max_items: 60,
             pressure_warning_threshold: 0.65,
             pressure_critical_threshold: 0.85,
             pressure_callbacks: Arc::new(Mutex::new(Vec::new())),
             last_update: Instant::now(),
-            prev_swap_in: 0,
-            prev_swap_out: 0,
-            iokit: Some(Box::new(IOKitImpl)),
+            swap_tracker: SwapActivityTracker::new(),
+            iokit: Some(Arc::new(IOKitImpl::default())),
         };
 
         memory.update()?;
@@ -318,14 +357,14 @@ impl Memory {
             pressure,
             page_states,
             swap_usage,
+            swap_activity: None,
             history: VecDeque::with_capacity(60),
             history_max_items: 60,
             pressure_warning_threshold: 0.65,
             pressure_critical_threshold: 0.85,
             pressure_callbacks: Arc::new(Mutex::new(Vec::new())),
             last_update: Instant::now(),
-            prev_swap_in: 0,
-            prev_swap_out: 0,
+            swap_tracker: SwapActivityTracker::new(),
             iokit: None,
         }
     }
@@ -394,43 +433,24 @@ impl Memory {
         self.page_states.compressed = vmstat.compressor_page_count as u64 * page_size;
 
         self.available = self.page_states.free + self.page_states.inactive;
-        self.used = self.total - self.available;
+        self.used = self.total.saturating_sub(self.available);
         self.wired = self.page_states.wired;
 
-        self.pressure = 1.0 - (self.available as f64 / self.total as f64).clamp(0.0, 1.0);
+        self.pressure =
+            1.0 - Percentage::from_ratio(self.available as f64, self.total as f64).value() / 100.0;
 
         let mut swap = Self::get_swap_usage()?;
 
         let now = Instant::now();
-        let elapsed = now.duration_since(self.last_update).as_secs_f64();
-
-        if elapsed > 0.0 && self.prev_swap_in > 0 && self.prev_swap_out > 0 {
-            let swap_in_diff = if vmstat.swapins >= self.prev_swap_in {
-                vmstat.swapins - self.prev_swap_in
-            } else {
-                vmstat.swapins
-            };
-
-            let swap_out_diff = if vmstat.swapouts >= self.prev_swap_out {
-                vmstat.swapouts - self.prev_swap_out
-            } else {
-                vmstat.swapouts
-            };
-
-            swap.ins = swap_in_diff as f64 / elapsed;
-            swap.outs = swap_out_diff as f64 / elapsed;
+        self.swap_activity = self.swap_tracker.record(VmCounters::from(&vmstat), now);
+        if let Some(activity) = self.swap_activity {
+            swap.ins = activity.swapins_per_sec;
+            swap.outs = activity.swapouts_per_sec;
         }
 
-        swap.pressure = if swap.total > 0 {
-            (swap.used as f64 / swap.total as f64).clamp(0.0, 1.0)
-        } else {
-            0.0
-        };
+        swap.pressure = Percentage::from_ratio(swap.used as f64, swap.total as f64).value() / 100.0;
 
         self.swap_usage = swap;
-
-        self.prev_swap_in = vmstat.swapins;
-        self.prev_swap_out = vmstat.swapouts;
         self.last_update = now;
 
         self.history.push_back(self.usage_percentage());
@@ -452,27 +472,47 @@ impl Memory {
             pressure: 0.0,
             page_states: PageStates::default(),
             swap_usage: SwapUsage::default(),
+            swap_activity: None,
             history: VecDeque::with_capacity(60),
             history_max_items: 60,
             pressure_warning_threshold: 0.65,
             pressure_critical_threshold: 0.85,
             pressure_callbacks: Arc::new(Mutex::new(Vec::new())),
             last_update: Instant::now(),
-            prev_swap_in: 0,
-            prev_swap_out: 0,
-            iokit: Some(Box::new(IOKitImpl)),
+            swap_tracker: SwapActivityTracker::new(),
+            iokit: Some(Arc::new(IOKitImpl::default())),
         };
 
         memory.update()?;
         Ok(memory)
     }
 
+    /// Total physical memory as a [`ByteSize`]
+    pub fn total_bytes(&self) -> ByteSize {
+        ByteSize::from_bytes(self.total)
+    }
+
+    /// Available memory as a [`ByteSize`]
+    pub fn available_bytes(&self) -> ByteSize {
+        ByteSize::from_bytes(self.available)
+    }
+
+    /// Used memory as a [`ByteSize`]
+    pub fn used_bytes(&self) -> ByteSize {
+        ByteSize::from_bytes(self.used)
+    }
+
+    /// Wired memory as a [`ByteSize`]
+    pub fn wired_bytes(&self) -> ByteSize {
+        ByteSize::from_bytes(self.wired)
+    }
+
     pub fn usage_percentage(&self) -> f64 {
-        (self.used as f64 / self.total as f64 * 100.0).clamp(0.0, 100.0)
+        Percentage::from_ratio(self.used as f64, self.total as f64).value()
     }
 
     pub fn pressure_percentage(&self) -> f64 {
-        (self.pressure * 100.0).clamp(0.0, 100.0)
+        Percentage::new_clamped(self.pressure * 100.0).value()
     }
 
     pub fn pressure_level(&self) -> PressureLevel {
@@ -515,13 +555,14 @@ impl Memory {
         let callbacks = self.pressure_callbacks.clone();
         let warning_threshold = self.pressure_warning_threshold;
         let critical_threshold = self.pressure_critical_threshold;
-        let active = Arc::new(AtomicBool::new(true));
-        let handle_active = active.clone();
+        let component = shutdown::register(MEMORY_MONITOR);
+        let task_component = Arc::clone(&component);
 
-        tokio::spawn(async move {
+        let task = tokio::spawn(async move {
+            let _running = task_component.running();
             let mut prev_level = None;
 
-            while handle_active.load(Ordering::SeqCst) {
+            while !task_component.is_stop_requested() {
                 if let Ok(memory) = Self::get_info() {
                     let current_level = if memory.pressure >= critical_threshold {
                         PressureLevel::Critical
@@ -541,11 +582,15 @@ impl Memory {
                     }
                 }
 
-                tokio::time::sleep(Duration::from_millis(interval_ms)).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_millis(interval_ms)) => {}
+                    _ = task_component.stop_requested() => break,
+                }
             }
         });
+        component.set_abort(move || task.abort());
 
-        Ok(MemoryMonitorHandle { active })
+        Ok(MemoryMonitorHandle { component })
     }
 
     fn get_total_memory() -> Result<u64> {
@@ -597,42 +642,14 @@ impl Memory {
     }
 
     fn get_swap_usage() -> Result<SwapUsage> {
-        let mut xsw_usage = xsw_usage::default();
-        let mut size = std::mem::size_of::<xsw_usage>();
-
-        let mib = [CTL_VM, VM_SWAPUSAGE];
-
-        let result = unsafe {
-            sysctl(
-                mib.as_ptr(),
-                mib.len() as u32,
-                &mut xsw_usage as *mut xsw_usage as *mut _,
-                &mut size,
-                std::ptr::null(),
-                0,
-            )
-        };
-
-        // If we get an error, return a default SwapUsage instead of failing
-        // This is more resilient in test environments or systems without swap
-        if result != 0 {
-            // Log the error but don't fail - this is often expected in test environments
-            eprintln!("Warning: Failed to get swap usage, using defaults (error: {})", result);
-            return Ok(SwapUsage::default());
-        }
-
-        Ok(SwapUsage {
-            total: xsw_usage.xsu_total,
-            used: xsw_usage.xsu_used,
-            free: xsw_usage.xsu_avail,
-            ins: 0.0,
-            outs: 0.0,
-            pressure: if xsw_usage.xsu_total > 0 {
-                xsw_usage.xsu_used as f64 / xsw_usage.xsu_total as f64
-            } else {
-                0.0
+        match read_xsw_usage() {
+            Ok(usage) => Ok(SwapUsage::from(usage)),
+            // A missing swap reading shouldn't fail the whole update
+            Err(e) => {
+                tracing::warn!("Failed to get swap usage, using defaults ({e})");
+                Ok(SwapUsage::default())
             },
-        })
+        }
     }
 
     fn check_pressure_thresholds(&self) {
@@ -647,16 +664,17 @@ impl Memory {
 }
 
 pub struct MemoryMonitorHandle {
-    active: Arc<AtomicBool>,
+    component: Arc<ComponentHandle>,
 }
 
 impl MemoryMonitorHandle {
     pub fn stop(&self) {
-        self.active.store(false, Ordering::SeqCst);
+        self.component.request_stop();
     }
 
+    /// Whether the monitor is running, i.e. neither this handle nor a [`shutdown`](crate::shutdown) stopped it.
     pub fn is_active(&self) -> bool {
-        self.active.load(Ordering::SeqCst)
+        !self.component.is_stop_requested()
     }
 }
 
@@ -716,9 +734,9 @@ impl Memory {
                 self.pressure = memory.pressure;
                 self.page_states = memory.page_states;
                 self.swap_usage = memory.swap_usage;
+                self.swap_activity = memory.swap_activity;
                 self.history = memory.history;
-                self.prev_swap_in = memory.prev_swap_in;
-                self.prev_swap_out = memory.prev_swap_out;
+                self.swap_tracker = memory.swap_tracker;
                 self.last_update = memory.last_update;
                 Ok(())
             },
@@ -0,0 +1,153 @@
+//! Swap usage and activity.
+//!
+//! The swap usage is read from the `vm.swapusage` sysctl as an `xsw_usage` struct. The kernel counts the pages swapped in and out, paged in and out and compressed since boot in `vm_statistics64`.
+//! [`SwapActivityTracker`] turns two samples of these counters into rates, which show memory pressure as it happens
+//! rather than the swap space it left behind.
+
+use std::time::Instant;
+
+use super::{Memory, SwapUsage};
+use crate::{
+    error::Result,
+    utils::{
+        bindings::{read_xsw_usage, vm_statistics64, xsw_usage},
+        sanitize::sanitize_rate,
+    },
+};
+
+/// Total swap space in bytes.
+///
+/// # Errors
+///
+/// Returns an error if `vm.swapusage` can't be read.
+pub fn total_swap() -> Result<u64> {
+    Ok(read_xsw_usage()?.xsu_total)
+}
+
+/// Used swap space in bytes.
+///
+/// # Errors
+///
+/// Returns an error if `vm.swapusage` can't be read.
+pub fn used_swap() -> Result<u64> {
+    Ok(read_xsw_usage()?.xsu_used)
+}
+
+/// Free swap space in bytes.
+///
+/// # Errors
+///
+/// Returns an error if `vm.swapusage` can't be read.
+pub fn free_swap() -> Result<u64> {
+    Ok(read_xsw_usage()?.xsu_avail)
+}
+
+impl From<xsw_usage> for SwapUsage {
+    /// The swap space of `usage`, without activity rates.
+    fn from(usage: xsw_usage) -> Self {
+        Self {
+            total: usage.xsu_total,
+            used: usage.xsu_used,
+            free: usage.xsu_avail,
+            ins: 0.0,
+            outs: 0.0,
+            pressure: if usage.xsu_total > 0 {
+                usage.xsu_used as f64 / usage.xsu_total as f64
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+/// Rates of swap and paging activity, in pages per second
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SwapActivity {
+    /// Pages read back from swap
+    pub swapins_per_sec: f64,
+    /// Pages written to swap
+    pub swapouts_per_sec: f64,
+    /// Pages read from files or swap
+    pub pageins_per_sec: f64,
+    /// Pages written to files
+    pub pageouts_per_sec: f64,
+    /// Pages compressed by the memory compressor
+    pub compressions_per_sec: f64,
+}
+
+/// Cumulative VM counters the activity rates are computed from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct VmCounters {
+    pub swapins: u64,
+    pub swapouts: u64,
+    pub pageins: u64,
+    pub pageouts: u64,
+    pub compressions: u64,
+}
+
+impl From<&vm_statistics64> for VmCounters {
+    fn from(vmstat: &vm_statistics64) -> Self {
+        Self {
+            swapins: vmstat.swapins,
+            swapouts: vmstat.swapouts,
+            pageins: vmstat.pageins,
+            pageouts: vmstat.pageouts,
+            compressions: vmstat.compressions,
+        }
+    }
+}
+
+/// Computes swap and paging rates between samples of the VM counters
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SwapActivityTracker {
+    previous: Option<(VmCounters, Instant)>,
+}
+
+impl SwapActivityTracker {
+    /// Creates a tracker without a previous sample.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Samples the VM counters and returns the rates since the previous sample.
+    ///
+    /// The first sample has nothing to compare with and returns None.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the VM statistics can't be read.
+    pub fn sample(&mut self) -> Result<Option<SwapActivity>> {
+        let vmstat = Memory::get_vm_statistics()?;
+        Ok(self.record(VmCounters::from(&vmstat), Instant::now()))
+    }
+
+    /// Whether a previous sample exists to compute rates against.
+    pub fn has_baseline(&self) -> bool {
+        self.previous.is_some()
+    }
+
+    /// Records the counters sampled at `now`, returning the rates since the previous sample or None if there is none
+    /// or no time passed.
+    pub(crate) fn record(&mut self, counters: VmCounters, now: Instant) -> Option<SwapActivity> {
+        let previous = self.previous.replace((counters, now));
+        let (previous, sampled_at) = previous?;
+
+        let elapsed = now.saturating_duration_since(sampled_at).as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+
+        // Counters only go backwards when they wrap or are reset, which counts as no activity
+        let rate = |current: u64, previous: u64| {
+            sanitize_rate(current.saturating_sub(previous) as f64, elapsed)
+        };
+        Some(SwapActivity {
+            swapins_per_sec: rate(counters.swapins, previous.swapins),
+            swapouts_per_sec: rate(counters.swapouts, previous.swapouts),
+            pageins_per_sec: rate(counters.pageins, previous.pageins),
+            pageouts_per_sec: rate(counters.pageouts, previous.pageouts),
+            compressions_per_sec: rate(counters.compressions, previous.compressions),
+        })
+    }
+}
@@ -114,6 +114,7 @@ fn test_memory_info_functions() {
 
 #[tokio::test]
 async fn test_memory_monitoring() {
+    let _serial = crate::shutdown::TEST_SERIAL.lock().await;
     let memory = Memory::new().unwrap();
     let monitor_handle = memory.start_monitoring(100).await;
 
@@ -145,3 +146,218 @@ async fn test_get_info_async() {
     let memory = memory_result.unwrap();
     assert!(memory.total > 0, "Total memory should be positive");
 }
+
+#[test]
+fn test_swap_usage_from_xsw_usage() {
+    use crate::utils::bindings::xsw_usage;
+
+    let usage = SwapUsage::from(xsw_usage {
+        xsu_total: 4096,
+        xsu_avail: 3072,
+        xsu_used: 1024,
+        xsu_pagesize: 16384,
+        xsu_encrypted: 1,
+    });
+    assert_eq!((usage.total, usage.used, usage.free), (4096, 1024, 3072));
+    assert_eq!(usage.pressure, 0.25);
+
+    assert_eq!(SwapUsage::from(xsw_usage::default()).pressure, 0.0, "No swap, no pressure");
+}
+
+#[test]
+fn test_swap_totals() {
+    let total = total_swap().unwrap();
+    let used = used_swap().unwrap();
+    let free = free_swap().unwrap();
+    assert!(used <= total && free <= total);
+}
+
+mod swap_activity {
+    use std::time::{Duration, Instant};
+
+    use crate::hardware::memory::{swap::VmCounters, SwapActivity, SwapActivityTracker};
+
+    fn counters(
+        swapins: u64,
+        swapouts: u64,
+        pageins: u64,
+        pageouts: u64,
+        compressions: u64,
+    ) -> VmCounters {
+        VmCounters { swapins, swapouts, pageins, pageouts, compressions }
+    }
+
+    #[test]
+    fn test_first_sample_has_no_rates() {
+        let mut tracker = SwapActivityTracker::new();
+        assert!(!tracker.has_baseline());
+        assert_eq!(tracker.record(counters(10, 20, 30, 40, 50), Instant::now()), None);
+        assert!(tracker.has_baseline());
+    }
+
+    #[test]
+    fn test_rates_between_samples() {
+        let mut tracker = SwapActivityTracker::new();
+        let start = Instant::now();
+        tracker.record(counters(10, 20, 30, 40, 50), start);
+
+        let activity =
+            tracker.record(counters(30, 60, 130, 40, 450), start + Duration::from_secs(2));
+        assert_eq!(
+            activity,
+            Some(SwapActivity {
+                swapins_per_sec: 10.0,
+                swapouts_per_sec: 20.0,
+                pageins_per_sec: 50.0,
+                pageouts_per_sec: 0.0,
+                compressions_per_sec: 200.0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_rates_use_the_latest_sample() {
+        let mut tracker = SwapActivityTracker::new();
+        let start = Instant::now();
+        tracker.record(counters(0, 0, 0, 0, 0), start);
+        tracker.record(counters(100, 100, 100, 100, 100), start + Duration::from_secs(1));
+
+        let activity = tracker
+            .record(counters(110, 100, 100, 100, 100), start + Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(activity.swapins_per_sec, 10.0);
+        assert_eq!(activity.swapouts_per_sec, 0.0);
+    }
+
+    #[test]
+    fn test_reset_counters_count_as_no_activity() {
+        let mut tracker = SwapActivityTracker::new();
+        let start = Instant::now();
+        tracker.record(counters(1000, 1000, 1000, 1000, 1000), start);
+
+        let activity =
+            tracker.record(counters(5, 5, 5, 5, 5), start + Duration::from_secs(1)).unwrap();
+        assert_eq!(activity, SwapActivity::default());
+    }
+
+    #[test]
+    fn test_no_rates_without_elapsed_time() {
+        let mut tracker = SwapActivityTracker::new();
+        let now = Instant::now();
+        tracker.record(counters(0, 0, 0, 0, 0), now);
+        assert_eq!(tracker.record(counters(10, 10, 10, 10, 10), now), None);
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut tracker = SwapActivityTracker::new();
+        assert_eq!(tracker.sample().unwrap(), None);
+
+        std::thread::sleep(Duration::from_millis(10));
+        let activity = tracker.sample().unwrap().expect("The second sample should have rates");
+        assert!(activity.swapins_per_sec >= 0.0);
+        assert!(activity.compressions_per_sec >= 0.0);
+    }
+}
+
+#[cfg(feature = "zones")]
+mod zones {
+    use std::os::raw::c_char;
+
+    use crate::hardware::memory::zones::{parse_zones, zone_name};
+    use crate::hardware::memory::{zone_statistics, ZoneInfo, ZoneStatistics};
+    use crate::utils::bindings::{mach_zone_info, mach_zone_name, ZONE_NAME_MAX_LEN};
+
+    fn name(s: &str) -> mach_zone_name {
+        let mut raw = [0 as c_char; ZONE_NAME_MAX_LEN];
+        for (dst, src) in raw.iter_mut().zip(s.bytes()) {
+            *dst = src as c_char;
+        }
+        mach_zone_name { mzn_name: raw }
+    }
+
+    fn info(count: u64, cur_size: u64, elem_size: u64) -> mach_zone_info {
+        mach_zone_info {
+            mzi_count: count,
+            mzi_cur_size: cur_size,
+            mzi_elem_size: elem_size,
+            ..Default::default()
+        }
+    }
+
+    fn zone(name: &str, size_bytes: u64) -> ZoneInfo {
+        ZoneInfo { name: name.to_string(), size_bytes, allocations: 0, element_size: 0 }
+    }
+
+    #[test]
+    fn test_parse_zones() {
+        let names = [name("kalloc.16"), name("VM objects")];
+        let infos = [info(1000, 16384, 16), info(250, 65536, 256)];
+
+        let zones = parse_zones(&names, &infos).unwrap();
+
+        assert_eq!(
+            zones,
+            vec![
+                ZoneInfo {
+                    name: "kalloc.16".to_string(),
+                    size_bytes: 16384,
+                    allocations: 1000,
+                    element_size: 16
+                },
+                ZoneInfo {
+                    name: "VM objects".to_string(),
+                    size_bytes: 65536,
+                    allocations: 250,
+                    element_size: 256
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_zones_empty_reply() {
+        assert!(parse_zones(&[], &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_zones_mismatched_counts() {
+        let result = parse_zones(&[name("kalloc.16")], &[]);
+        assert!(result.is_err(), "Name and info counts must match");
+    }
+
+    #[test]
+    fn test_zone_name_without_terminator() {
+        let long = "z".repeat(ZONE_NAME_MAX_LEN);
+        assert_eq!(zone_name(&name(&long).mzn_name), long);
+        assert_eq!(zone_name(&name("ipc ports").mzn_name), "ipc ports");
+    }
+
+    #[test]
+    fn test_top_zones() {
+        let stats = ZoneStatistics {
+            zones: vec![zone("small", 10), zone("large", 300), zone("b", 20), zone("a", 20)],
+            partial: false,
+        };
+
+        let top: Vec<_> = stats.top_zones(3).into_iter().map(|zone| zone.name).collect();
+        assert_eq!(top, ["large", "a", "b"]);
+        assert_eq!(stats.top_zones(10).len(), 4);
+        assert_eq!(stats.total_bytes(), 350);
+    }
+
+    #[test]
+    fn test_zone_statistics() {
+        let stats = zone_statistics().unwrap();
+
+        if stats.partial {
+            // Not running as root
+            assert!(stats.zones.is_empty());
+        } else {
+            assert!(
+                stats.zones.iter().any(|zone| zone.name.starts_with("kalloc")),
+                "The kalloc zones should be reported"
+            );
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Kernel zone statistics
+//!
+//! The kernel allocates its own data structures (VM objects, IPC ports, `kalloc` buffers, ...) from zones. A zone
+//! that keeps growing points at a kernel-side leak, which otherwise only shows up as wired memory that never goes
+//! down. The statistics come from `mach_zone_info`, the interface behind `zprint`, which needs the privileged host
+//! port and therefore root.
+
+use std::{ffi::CStr, mem, os::raw::c_char, ptr, slice};
+
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{
+        mach_host_self, mach_port_deallocate, mach_task_self, mach_zone_info, mach_zone_name,
+        KERN_SUCCESS, ZONE_NAME_MAX_LEN,
+    },
+};
+
+/// Memory used by a kernel zone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneInfo {
+    /// Zone name, e.g. `kalloc.128` or `VM objects`
+    pub name: String,
+    /// Memory currently held by the zone in bytes
+    pub size_bytes: u64,
+    /// Number of elements currently allocated from the zone
+    pub allocations: u64,
+    /// Size of one element in bytes
+    pub element_size: u64,
+}
+
+/// Statistics of all kernel zones
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ZoneStatistics {
+    /// The zones, in the order the kernel reports them
+    pub zones: Vec<ZoneInfo>,
+    /// Whether the kernel refused to report zones because the caller isn't privileged; `zones` is empty then
+    pub partial: bool,
+}
+
+impl ZoneStatistics {
+    /// Returns the `n` largest zones, largest first.
+    pub fn top_zones(&self, n: usize) -> Vec<ZoneInfo> {
+        let mut zones = self.zones.clone();
+        zones.sort_by(|a, b| b.size_bytes.cmp(&a.size_bytes).then_with(|| a.name.cmp(&b.name)));
+        zones.truncate(n);
+        zones
+    }
+
+    /// Memory held by all reported zones in bytes
+    pub fn total_bytes(&self) -> u64 {
+        self.zones.iter().map(|zone| zone.size_bytes).sum()
+    }
+}
+
+/// Reads the statistics of every kernel zone.
+///
+/// Without root the kernel doesn't report zones at all; the result is then empty and flagged as `partial` instead
+/// of failing, so callers can run unprivileged and still tell the difference from a system without zones.
+///
+/// # Errors
+///
+/// Returns an error if the kernel call fails for another reason or returns inconsistent arrays.
+pub fn zone_statistics() -> Result<ZoneStatistics> {
+    let host = unsafe { mach_host_self() };
+
+    let mut names: *mut mach_zone_name = ptr::null_mut();
+    let mut names_count = 0u32;
+    let mut info: *mut mach_zone_info = ptr::null_mut();
+    let mut info_count = 0u32;
+    let result =
+        unsafe { mach_zone_info(host, &mut names, &mut names_count, &mut info, &mut info_count) };
+    unsafe { mach_port_deallocate(mach_task_self(), host) };
+
+    match result {
+        KERN_SUCCESS => {},
+        libc::KERN_INVALID_HOST | libc::KERN_NO_ACCESS => {
+            return Ok(ZoneStatistics { zones: Vec::new(), partial: true });
+        },
+        _ => return Err(Error::system(format!("mach_zone_info failed with error {result}"))),
+    }
+
+    // SAFETY: on success both arrays were allocated by the kernel reply with the returned counts.
+    let names = unsafe { OutOfLine::new(names, names_count) };
+    let info = unsafe { OutOfLine::new(info, info_count) };
+
+    Ok(ZoneStatistics { zones: parse_zones(names.as_slice(), info.as_slice())?, partial: false })
+}
+
+/// Returns the `n` largest kernel zones, largest first.
+///
+/// # Errors
+///
+/// See [`zone_statistics`].
+pub fn top_zones(n: usize) -> Result<Vec<ZoneInfo>> {
+    Ok(zone_statistics()?.top_zones(n))
+}
+
+/// Pairs the name and info arrays of a `mach_zone_info` reply.
+pub(crate) fn parse_zones(
+    names: &[mach_zone_name],
+    info: &[mach_zone_info],
+) -> Result<Vec<ZoneInfo>> {
+    if names.len() != info.len() {
+        return Err(Error::invalid_data(format!(
+            "mach_zone_info returned {} zone names but {} zone infos",
+            names.len(),
+            info.len()
+        )));
+    }
+
+    Ok(names
+        .iter()
+        .zip(info)
+        .map(|(name, info)| ZoneInfo {
+            name: zone_name(&name.mzn_name),
+            size_bytes: info.mzi_cur_size,
+            allocations: info.mzi_count,
+            element_size: info.mzi_elem_size,
+        })
+        .collect())
+}
+
+/// Decodes a zone name, which is NUL-terminated unless it fills the whole buffer.
+pub(crate) fn zone_name(raw: &[c_char; ZONE_NAME_MAX_LEN]) -> String {
+    // SAFETY: c_char and u8 have the same size and alignment.
+    let bytes: &[u8] = unsafe { slice::from_raw_parts(raw.as_ptr().cast(), raw.len()) };
+
+    match CStr::from_bytes_until_nul(bytes) {
+        Ok(name) => name.to_string_lossy().into_owned(),
+        Err(_) => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// An array returned out of line in a Mach reply, deallocated from the task's address space on drop
+struct OutOfLine<T> {
+    ptr: *mut T,
+    count: u32,
+}
+
+impl<T> OutOfLine<T> {
+    /// # Safety
+    ///
+    /// `ptr` must be null or point to `count` elements allocated with `vm_allocate` in this task.
+    unsafe fn new(ptr: *mut T, count: u32) -> Self {
+        Self { ptr, count }
+    }
+
+    fn as_slice(&self) -> &[T] {
+        if self.ptr.is_null() {
+            return &[];
+        }
+        // SAFETY: guaranteed by the caller of `new`
+        unsafe { slice::from_raw_parts(self.ptr, self.count as usize) }
+    }
+}
+
+impl<T> Drop for OutOfLine<T> {
+    fn drop(&mut self) {
+        if self.ptr.is_null() {
+            return;
+        }
+        unsafe {
+            libc::vm_deallocate(
+                mach_task_self(),
+                self.ptr as libc::vm_address_t,
+                self.count as libc::vm_size_t * mem::size_of::<T>(),
+            );
+        }
+    }
+}
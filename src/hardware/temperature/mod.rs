@@ -1,13 +1,54 @@
+//! # Temperature Module
+//!
+//! Temperature sensors, fans and thermal throttling. Readings are cached for
+//! [`TemperatureConfig::poll_interval_ms`] and refreshed on access when [`TemperatureConfig::auto_refresh`] is set.
+//!
+//! [`Temperature::with_iokit`] takes the IOKit implementation to read through, such as an `Arc<dyn IOKit>` shared
+//! with other monitors or a mock:
+//!
+//! ```rust
+//! # fn main() -> darwin_metrics::Result<()> {
+//! use darwin_metrics::{
+//!     doctest_support::{mock_iokit_with_defaults, MOCK_CPU_TEMPERATURE, MOCK_FAN_SPEED},
+//!     hardware::temperature::{Temperature, TemperatureConfig},
+//! };
+//!
+//! let mut temperature =
+//!     Temperature::with_iokit(mock_iokit_with_defaults(), TemperatureConfig::default());
+//!
+//! assert_eq!(temperature.cpu_temperature()?, MOCK_CPU_TEMPERATURE);
+//! assert_eq!(temperature.fan_count()?, 1);
+//! assert_eq!(temperature.get_fan(0)?.speed_rpm, MOCK_FAN_SPEED);
+//! assert!(!temperature.is_throttling()?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`TemperatureWatcher`] reads the temperatures in the background and broadcasts a [`ThermalEvent`] whenever a
+//! component crosses one of its warning or critical thresholds.
+
 use std::{
     collections::HashMap,
     time::{Duration, Instant},
 };
 
 use crate::{
-    hardware::iokit::{IOKit, IOKitImpl},
+    hardware::{
+        cpu::{system_signals, ThrottleReasons, UsageSampler},
+        iokit::{FanInfo, FanMode, IOKit, IOKitImpl},
+    },
     Result,
 };
 
+mod tracker;
+mod watcher;
+
+pub use tracker::ThermalLevelTracker;
+pub use watcher::{
+    AlertLevel, TemperatureThreshold, TemperatureWatcher, ThermalComponent, ThermalEvent,
+    WatcherConfig,
+};
+
 /// Represents the location of a temperature sensor in the system
 #[derive(Debug, Clone, PartialEq)]
 pub enum SensorLocation {
@@ -30,9 +71,10 @@ pub enum SensorLocation {
 }
 
 /// Fan information including speed, min/max values, and utilization percentage
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Fan {
-    /// Fan identifier (e.g., "CPU Fan", "System Fan")
+    /// Fan identifier from the fan descriptor (e.g., "Left Side"), or "Fan N" without one
     pub name: String,
     /// Current fan speed in RPM
     pub speed_rpm: u32,
@@ -42,6 +84,25 @@ pub struct Fan {
     pub max_speed: u32,
     /// Current fan utilization as a percentage (0-100%)
     pub percentage: f64,
+    /// Speed the SMC drives the fan towards in RPM, if reported
+    pub target_rpm: Option<u32>,
+    /// Whether the SMC controls the fan or its speed is forced
+    pub mode: FanMode,
+}
+
+impl Fan {
+    /// The fan with index `index` as IOKit reports it.
+    fn from_info(index: usize, info: &FanInfo) -> Self {
+        Self {
+            name: info.name.clone().unwrap_or_else(|| format!("Fan {}", index)),
+            speed_rpm: info.speed_rpm,
+            min_speed: info.min_speed,
+            max_speed: info.max_speed,
+            percentage: info.percentage,
+            target_rpm: info.target_rpm,
+            mode: info.mode,
+        }
+    }
 }
 
 /// Configuration for temperature monitoring
@@ -82,6 +143,8 @@ pub struct Temperature<T: IOKit + Clone + 'static = IOKitImpl> {
     io_kit: T,
     /// When sensors were last refreshed
     last_refresh: Instant,
+    /// CPU usage since the previous throttle classification
+    cpu_usage: UsageSampler,
 }
 
 impl Temperature<IOKitImpl> {
@@ -93,8 +156,9 @@ impl Temperature<IOKitImpl> {
             is_throttling: false,
             cpu_power: None,
             config: TemperatureConfig::default(),
-            io_kit: IOKitImpl,
+            io_kit: IOKitImpl::default(),
             last_refresh: Instant::now() - Duration::from_secs(60), // Force refresh on first access
+            cpu_usage: UsageSampler::default(),
         }
     }
 
@@ -106,8 +170,9 @@ impl Temperature<IOKitImpl> {
             is_throttling: false,
             cpu_power: None,
             config,
-            io_kit: IOKitImpl,
+            io_kit: IOKitImpl::default(),
             last_refresh: Instant::now() - Duration::from_secs(60), // Force refresh on first access
+            cpu_usage: UsageSampler::default(),
         }
     }
 }
@@ -123,6 +188,7 @@ impl<T: IOKit + Clone + 'static> Temperature<T> {
             config,
             io_kit,
             last_refresh: Instant::now() - Duration::from_secs(60), // Force refresh on first access
+            cpu_usage: UsageSampler::default(),
         }
     }
 
@@ -157,6 +223,8 @@ impl<T: IOKit + Clone + 'static> Temperature<T> {
                 min_speed: 1000,
                 max_speed: 4000,
                 percentage: 33.3,
+                target_rpm: Some(2000),
+                mode: FanMode::Auto,
             });
 
             // Update refresh timestamp
@@ -172,12 +240,9 @@ impl<T: IOKit + Clone + 'static> Temperature<T> {
 
             // Update sensors with basic temperature readings
             self.sensors.insert("CPU".to_string(), thermal_info.cpu_temp);
-            self.sensors.insert("GPU".to_string(), thermal_info.gpu_temp);
-
-                // Add optional sensors if available
-                if let Some(temp) = thermal_info.heatsink_temp {
-                    self.sensors.insert("Heatsink".to_string(), temp);
-                }
+            if let Some(temp) = thermal_info.gpu_temp {
+                self.sensors.insert("GPU".to_string(), temp);
+            }
 
             // Add optional sensors if available
             if let Some(temp) = thermal_info.heatsink_temp {
@@ -188,12 +253,6 @@ impl<T: IOKit + Clone + 'static> Temperature<T> {
                 self.sensors.insert("Ambient".to_string(), temp);
             }
 
-                // Update throttling status
-                self.is_throttling = thermal_info.is_throttling;
-
-            // Update CPU power if available
-            self.cpu_power = thermal_info.cpu_power;
-
             // Update throttling status
             self.is_throttling = thermal_info.is_throttling;
 
@@ -205,15 +264,7 @@ impl<T: IOKit + Clone + 'static> Temperature<T> {
             self.fans.clear();
 
             // Create Fan objects from the raw IOKitFanInfo structures
-            for (i, fan_info) in fan_infos.iter().enumerate() {
-                self.fans.push(Fan {
-                    name: format!("Fan {}", i),
-                    speed_rpm: fan_info.speed_rpm,
-                    min_speed: fan_info.min_speed,
-                    max_speed: fan_info.max_speed,
-                    percentage: fan_info.percentage,
-                });
-            }
+            self.fans.extend(fan_infos.iter().enumerate().map(|(i, info)| Fan::from_info(i, info)));
 
             // Update refresh timestamp
             self.last_refresh = Instant::now();
@@ -378,7 +429,13 @@ impl<T: IOKit + Clone + 'static> Temperature<T> {
         // Always refresh for this comprehensive call
         self.refresh()?;
 
-        Ok(ThermalMetrics {
+        let throttle_reasons = Self::collect_throttle_reasons(&self.io_kit, &mut self.cpu_usage);
+        Ok(self.thermal_metrics(throttle_reasons))
+    }
+
+    /// The metrics of the last refresh, with `throttle_reasons` collected by the caller
+    fn thermal_metrics(&self, throttle_reasons: ThrottleReasons) -> ThermalMetrics {
+        ThermalMetrics {
             cpu_temperature: self.sensors.get("CPU").cloned(),
             gpu_temperature: self.sensors.get("GPU").cloned(),
             heatsink_temperature: self.sensors.get("Heatsink").cloned(),
@@ -387,7 +444,14 @@ impl<T: IOKit + Clone + 'static> Temperature<T> {
             is_throttling: self.is_throttling,
             cpu_power: self.cpu_power,
             fans: self.fans.clone(),
-        })
+            throttle_reasons: Some(throttle_reasons),
+        }
+    }
+
+    /// Classify why the CPU is throttled from the same signals as
+    /// [`throttle_reasons`](crate::hardware::cpu::throttle_reasons), plus the CPU usage since the previous classification
+    fn collect_throttle_reasons(io_kit: &T, cpu_usage: &mut UsageSampler) -> ThrottleReasons {
+        system_signals(io_kit, cpu_usage.sample()).classify()
     }
 
     /// Get CPU temperature asynchronously
@@ -456,7 +520,9 @@ impl<T: IOKit + Clone + 'static> Temperature<T> {
 
         // Update sensors with basic temperature readings
         self.sensors.insert("CPU".to_string(), thermal_info.cpu_temp);
-        self.sensors.insert("GPU".to_string(), thermal_info.gpu_temp);
+        if let Some(temp) = thermal_info.gpu_temp {
+            self.sensors.insert("GPU".to_string(), temp);
+        }
 
         // Add optional sensors if available
         if let Some(temp) = thermal_info.heatsink_temp {
@@ -486,15 +552,7 @@ impl<T: IOKit + Clone + 'static> Temperature<T> {
         self.fans.clear();
 
         // Create Fan objects from the raw IOKitFanInfo structures
-        for (i, fan_info) in fan_infos.iter().enumerate() {
-            self.fans.push(Fan {
-                name: format!("Fan {}", i),
-                speed_rpm: fan_info.speed_rpm,
-                min_speed: fan_info.min_speed,
-                max_speed: fan_info.max_speed,
-                percentage: fan_info.percentage,
-            });
-        }
+        self.fans.extend(fan_infos.iter().enumerate().map(|(i, info)| Fan::from_info(i, info)));
 
         // Update refresh timestamp
         self.last_refresh = Instant::now();
@@ -507,16 +565,16 @@ impl<T: IOKit + Clone + 'static> Temperature<T> {
         // Always refresh for this comprehensive call
         self.refresh_async().await?;
 
-        Ok(ThermalMetrics {
-            cpu_temperature: self.sensors.get("CPU").cloned(),
-            gpu_temperature: self.sensors.get("GPU").cloned(),
-            heatsink_temperature: self.sensors.get("Heatsink").cloned(),
-            ambient_temperature: self.sensors.get("Ambient").cloned(),
-            battery_temperature: self.sensors.get("Battery").cloned(),
-            is_throttling: self.is_throttling,
-            cpu_power: self.cpu_power,
-            fans: self.fans.clone(),
+        let io_kit = self.io_kit.clone();
+        let mut cpu_usage = std::mem::take(&mut self.cpu_usage);
+        let (throttle_reasons, cpu_usage) = tokio::task::spawn_blocking(move || {
+            (Self::collect_throttle_reasons(&io_kit, &mut cpu_usage), cpu_usage)
         })
+        .await
+        .map_err(|e| crate::Error::Temperature(format!("Task join error: {}", e)))?;
+        self.cpu_usage = cpu_usage;
+
+        Ok(self.thermal_metrics(throttle_reasons))
     }
 
     /// Determine if the system is experiencing thermal throttling asynchronously
@@ -561,6 +619,8 @@ pub struct ThermalMetrics {
     pub cpu_power: Option<f64>,
     /// Information about all fans in the system
     pub fans: Vec<Fan>,
+    /// Best-effort classification of why the CPU is throttled, if it could be collected
+    pub throttle_reasons: Option<ThrottleReasons>,
 }
 
 impl Default for Temperature<IOKitImpl> {
@@ -1,10 +1,9 @@
 use std::{thread, time::Duration};
 
 use super::*;
-use crate::hardware::iokit::{FanInfo, ThermalInfo};
+use crate::hardware::iokit::{CpuTemperatureSource, FanInfo, IOServiceHandle, ThermalInfo};
 use crate::Error;
 use objc2::rc::Retained;
-use objc2::runtime::AnyObject;
 use objc2_foundation::{NSDictionary, NSObject, NSString};
 use std::sync::Arc;
 
@@ -30,18 +29,32 @@ impl MockIOKitClone {
             thermal_info: Arc::new(|| {
                 Ok(ThermalInfo {
                     cpu_temp: 45.0,
-                    gpu_temp: 55.0,
+                    gpu_temp: Some(55.0),
                     heatsink_temp: Some(40.0),
                     ambient_temp: Some(30.0),
                     battery_temp: Some(35.0),
                     is_throttling: false,
                     cpu_power: Some(25.0),
+                    cpu_temp_source: CpuTemperatureSource::Smc,
+                    ..Default::default()
                 })
             }),
             fan_info: Arc::new(|| {
                 Ok(vec![
-                    FanInfo { speed_rpm: 2000, min_speed: 1000, max_speed: 4000, percentage: 33.3 },
-                    FanInfo { speed_rpm: 2500, min_speed: 1200, max_speed: 5000, percentage: 40.0 },
+                    FanInfo {
+                        speed_rpm: 2000,
+                        min_speed: 1000,
+                        max_speed: 4000,
+                        percentage: 33.3,
+                        ..Default::default()
+                    },
+                    FanInfo {
+                        speed_rpm: 2500,
+                        min_speed: 1200,
+                        max_speed: 5000,
+                        percentage: 40.0,
+                        ..Default::default()
+                    },
                 ])
             }),
         }
@@ -67,27 +80,24 @@ impl IOKit for MockIOKitClone {
         &self,
         _service_name: &str,
     ) -> Retained<NSDictionary<NSString, NSObject>> {
-        unimplemented!("Not needed for tests")
+        crate::utils::test_utils::create_test_dictionary()
     }
 
     fn io_service_get_matching_service(
         &self,
         _matching: &NSDictionary<NSString, NSObject>,
-    ) -> Option<Retained<AnyObject>> {
-        unimplemented!("Not needed for tests")
+    ) -> Option<IOServiceHandle> {
+        // No battery, so the throttle classification doesn't know the power source
+        None
     }
 
     fn io_registry_entry_create_cf_properties(
         &self,
-        _entry: &AnyObject,
+        _entry: &IOServiceHandle,
     ) -> Result<Retained<NSDictionary<NSString, NSObject>>> {
         unimplemented!("Not needed for tests")
     }
 
-    fn io_object_release(&self, _obj: &AnyObject) {
-        unimplemented!("Not needed for tests")
-    }
-
     fn get_string_property(
         &self,
         _dict: &NSDictionary<NSString, NSObject>,
@@ -120,11 +130,11 @@ impl IOKit for MockIOKitClone {
         unimplemented!("Not needed for tests")
     }
 
-    fn get_service(&self, _name: &str) -> Result<Retained<AnyObject>> {
+    fn get_service(&self, _name: &str) -> Result<IOServiceHandle> {
         unimplemented!("Not needed for tests")
     }
 
-    fn io_registry_entry_get_parent(&self, _entry: &AnyObject) -> Option<Retained<AnyObject>> {
+    fn io_registry_entry_get_parent(&self, _entry: &IOServiceHandle) -> Option<IOServiceHandle> {
         unimplemented!("Not needed for tests")
     }
 
@@ -133,7 +143,9 @@ impl IOKit for MockIOKitClone {
     }
 
     fn get_gpu_temperature(&self) -> Result<f64> {
-        Ok((*self.thermal_info)()?.gpu_temp)
+        (*self.thermal_info)()?
+            .gpu_temp
+            .ok_or_else(|| Error::IOKit("GPU temperature not available".to_string()))
     }
 
     fn get_gpu_stats(&self) -> Result<crate::hardware::iokit::GpuStats> {
@@ -399,6 +411,7 @@ fn test_fan_functions() {
         min_speed: 1000,
         max_speed: 4000,
         percentage: 33.3,
+        ..Default::default()
     });
 
     temp.fans.push(Fan {
@@ -407,6 +420,7 @@ fn test_fan_functions() {
         min_speed: 1200,
         max_speed: 5000,
         percentage: 47.4,
+        ..Default::default()
     });
 
     // Test fan count
@@ -480,6 +494,7 @@ fn test_get_fan_info() {
         min_speed: 500,
         max_speed: 5000,
         percentage: 40.0,
+        ..Default::default()
     });
 
     // Get the fan info
@@ -508,6 +523,7 @@ fn test_get_fan_info_min_max_equal() {
         min_speed: 2000, // Same as current and max
         max_speed: 2000, // Same as current and min
         percentage: 0.0, // Should be 0 when min==max
+        ..Default::default()
     });
 
     // Get the fan info
@@ -586,6 +602,7 @@ fn test_fan_info_debug() {
         min_speed: 500,
         max_speed: 5000,
         percentage: 40.0,
+        ..Default::default()
     };
 
     let debug_str = format!("{:?}", fan);
@@ -607,6 +624,7 @@ fn test_fan_info_clone() {
         min_speed: 500,
         max_speed: 5000,
         percentage: 40.0,
+        ..Default::default()
     };
 
     let fan_clone = fan.clone();
@@ -635,7 +653,9 @@ fn test_thermal_metrics_clone() {
             min_speed: 500,
             max_speed: 5000,
             percentage: 40.0,
+            ..Default::default()
         }],
+        throttle_reasons: None,
     };
 
     let metrics_clone = metrics.clone();
@@ -669,7 +689,9 @@ fn test_thermal_metrics_debug() {
             min_speed: 500,
             max_speed: 5000,
             percentage: 40.0,
+            ..Default::default()
         }],
+        throttle_reasons: None,
     };
 
     let debug_str = format!("{:?}", metrics);
@@ -893,6 +915,7 @@ async fn test_get_thermal_metrics_async() {
         min_speed: 1000,
         max_speed: 4000,
         percentage: 33.3,
+        ..Default::default()
     });
 
     // Mock the refresh_async method by creating a custom implementation that doesn't actually call IOKit methods
@@ -910,7 +933,9 @@ async fn test_get_thermal_metrics_async() {
             min_speed: 1000,
             max_speed: 4000,
             percentage: 33.3,
+            ..Default::default()
         }],
+        throttle_reasons: None,
     };
 
     // Verify the metrics structure
@@ -1020,12 +1045,14 @@ fn test_fan_count_empty() {
         .with_thermal_info(|| {
             Ok(ThermalInfo {
                 cpu_temp: 45.0,
-                gpu_temp: 55.0,
+                gpu_temp: Some(55.0),
                 heatsink_temp: None,
                 ambient_temp: None,
                 battery_temp: None,
                 is_throttling: false,
                 cpu_power: None,
+                cpu_temp_source: CpuTemperatureSource::Smc,
+                ..Default::default()
             })
         })
         .with_fan_info(|| Ok(vec![]));
@@ -1041,12 +1068,14 @@ fn test_get_fans_empty() {
         .with_thermal_info(|| {
             Ok(ThermalInfo {
                 cpu_temp: 45.0,
-                gpu_temp: 55.0,
+                gpu_temp: Some(55.0),
                 heatsink_temp: None,
                 ambient_temp: None,
                 battery_temp: None,
                 is_throttling: false,
                 cpu_power: None,
+                cpu_temp_source: CpuTemperatureSource::Smc,
+                ..Default::default()
             })
         })
         .with_fan_info(|| Ok(vec![]));
@@ -1056,6 +1085,35 @@ fn test_get_fans_empty() {
     assert!(fans.is_empty());
 }
 
+#[test]
+#[cfg(not(feature = "skip-ffi-crashes"))]
+fn test_fans_carry_target_mode_and_name() {
+    let mock_iokit = MockIOKitClone::new().with_fan_info(|| {
+        Ok(vec![
+            FanInfo {
+                speed_rpm: 2000,
+                min_speed: 1000,
+                max_speed: 4000,
+                percentage: 33.3,
+                target_rpm: Some(2200),
+                mode: FanMode::Forced,
+                name: Some("Left Side".to_string()),
+            },
+            FanInfo { speed_rpm: 1800, min_speed: 1000, max_speed: 4000, ..Default::default() },
+        ])
+    });
+
+    let mut temp = Temperature::with_iokit(mock_iokit, TemperatureConfig::default());
+    let fans = temp.get_fans().unwrap();
+
+    assert_eq!(fans[0].name, "Left Side");
+    assert_eq!(fans[0].target_rpm, Some(2200));
+    assert_eq!(fans[0].mode, FanMode::Forced);
+    assert_eq!(fans[1].name, "Fan 1", "Fans without a descriptor are numbered");
+    assert_eq!(fans[1].target_rpm, None);
+    assert_eq!(fans[1].mode, FanMode::Unknown);
+}
+
 #[test]
 fn test_get_thermal_info() {
     // Create a mock IOKit implementation
@@ -1063,12 +1121,14 @@ fn test_get_thermal_info() {
         .with_thermal_info(|| {
             Ok(ThermalInfo {
                 cpu_temp: 42.5,
-                gpu_temp: 55.0,
+                gpu_temp: Some(55.0),
                 heatsink_temp: Some(45.0),
                 ambient_temp: Some(32.0),
                 battery_temp: Some(38.0),
                 is_throttling: false,
                 cpu_power: Some(28.5),
+                cpu_temp_source: CpuTemperatureSource::Smc,
+                ..Default::default()
             })
         })
         .with_fan_info(|| {
@@ -1077,6 +1137,7 @@ fn test_get_thermal_info() {
                 min_speed: 1000,
                 max_speed: 4000,
                 percentage: 33.3,
+                ..Default::default()
             }])
         });
 
@@ -1104,12 +1165,14 @@ fn test_get_thermal_info_with_failures() {
         .with_thermal_info(|| {
             Ok(ThermalInfo {
                 cpu_temp: 42.5,
-                gpu_temp: 55.0,
+                gpu_temp: Some(55.0),
                 heatsink_temp: None,
                 ambient_temp: None,
                 battery_temp: None,
                 is_throttling: false,
                 cpu_power: None,
+                cpu_temp_source: CpuTemperatureSource::Smc,
+                ..Default::default()
             })
         })
         .with_fan_info(|| Ok(vec![]));
@@ -1139,12 +1202,14 @@ fn test_get_thermal_metrics() {
         .with_thermal_info(|| {
             Ok(ThermalInfo {
                 cpu_temp: 42.5,
-                gpu_temp: 55.0,
+                gpu_temp: Some(55.0),
                 heatsink_temp: Some(45.0),
                 ambient_temp: Some(32.0),
                 battery_temp: Some(38.0),
                 is_throttling: false,
                 cpu_power: Some(28.5),
+                cpu_temp_source: CpuTemperatureSource::Smc,
+                ..Default::default()
             })
         })
         .with_fan_info(|| {
@@ -1153,6 +1218,7 @@ fn test_get_thermal_metrics() {
                 min_speed: 1000,
                 max_speed: 4000,
                 percentage: 33.3,
+                ..Default::default()
             }])
         });
 
@@ -1174,4 +1240,77 @@ fn test_get_thermal_metrics() {
     assert_eq!(metrics.fans[0].min_speed, 1000);
     assert_eq!(metrics.fans[0].max_speed, 4000);
     assert_eq!(metrics.fans[0].percentage, 33.3);
+
+    let reasons = metrics.throttle_reasons.expect("throttle reasons should be collected");
+    assert_eq!(reasons.signals.smc_throttling, Some(false));
+}
+
+#[test]
+fn test_get_thermal_metrics_reports_thermal_throttle_reason() {
+    let mock_iokit = MockIOKitClone::new().with_thermal_info(|| {
+        Ok(ThermalInfo {
+            cpu_temp: 98.0,
+            gpu_temp: Some(90.0),
+            heatsink_temp: None,
+            ambient_temp: None,
+            battery_temp: None,
+            is_throttling: true,
+            cpu_power: None,
+            cpu_temp_source: CpuTemperatureSource::Smc,
+            ..Default::default()
+        })
+    });
+
+    let mut temp = Temperature::with_iokit(mock_iokit, TemperatureConfig::default());
+    let metrics = temp.get_thermal_metrics().unwrap();
+
+    let reasons = metrics.throttle_reasons.expect("throttle reasons should be collected");
+    assert_eq!(reasons.signals.smc_throttling, Some(true));
+    assert_eq!(reasons.thermal, Some(true));
+}
+
+#[test]
+fn test_thermal_level_tracker_time_in_state() {
+    use crate::hardware::cpu::ThermalState;
+    use std::time::UNIX_EPOCH;
+
+    let mut tracker = ThermalLevelTracker::new(16);
+    assert_eq!(tracker.current(), None);
+
+    let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    tracker.record_at(start, ThermalState::Nominal).unwrap();
+    tracker.record_at(start + Duration::from_secs(60), ThermalState::Serious).unwrap();
+    tracker.record_at(start + Duration::from_secs(90), ThermalState::Fair).unwrap();
+    tracker.record_at(start + Duration::from_secs(150), ThermalState::Nominal).unwrap();
+
+    assert_eq!(tracker.current(), Some(ThermalState::Nominal));
+    assert_eq!(tracker.peak(), Some(ThermalState::Serious));
+    assert_eq!(tracker.time_in_state(ThermalState::Nominal), Duration::from_secs(60));
+    assert_eq!(tracker.time_in_state(ThermalState::Serious), Duration::from_secs(30));
+    assert_eq!(tracker.time_in_state(ThermalState::Critical), Duration::ZERO);
+}
+
+#[test]
+fn test_thermal_level_tracker_resumes_from_store() {
+    use crate::hardware::cpu::ThermalState;
+    use crate::history::{HistoryStore, ResumePolicy};
+
+    let path =
+        std::env::temp_dir().join(format!("darwin-metrics-thermal-history-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    {
+        let store = HistoryStore::open(&path, 8).unwrap();
+        let mut tracker =
+            ThermalLevelTracker::with_store(8, store, ResumePolicy::default()).unwrap();
+        tracker.record(ThermalState::Fair).unwrap();
+        tracker.record(ThermalState::Critical).unwrap();
+    }
+
+    let store = HistoryStore::open(&path, 8).unwrap();
+    let tracker = ThermalLevelTracker::with_store(8, store, ResumePolicy::default()).unwrap();
+    assert_eq!(tracker.current(), Some(ThermalState::Critical));
+    assert_eq!(tracker.history().len(), 2);
+
+    std::fs::remove_file(&path).unwrap();
 }
@@ -0,0 +1,83 @@
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    error::Result,
+    hardware::cpu::ThermalState,
+    history::{HistorySample, HistoryStore, MetricHistory, ResumePolicy},
+};
+
+/// Tracks how the system thermal state changes over time
+///
+/// Each recorded state is stored as its numeric level (0 = Nominal, 3 = Critical). With a [`HistoryStore`] attached,
+/// the tracker resumes from disk after a restart, so time spent under thermal pressure isn't lost.
+#[derive(Debug)]
+pub struct ThermalLevelTracker {
+    history: MetricHistory,
+}
+
+impl ThermalLevelTracker {
+    /// Creates an in-memory tracker holding up to `capacity` samples.
+    pub fn new(capacity: usize) -> Self {
+        Self { history: MetricHistory::new(capacity) }
+    }
+
+    /// Creates a tracker that persists to `store`, resuming from the samples already in it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store can't be read.
+    pub fn with_store(capacity: usize, store: HistoryStore, policy: ResumePolicy) -> Result<Self> {
+        Ok(Self { history: MetricHistory::with_store(capacity, store, policy)? })
+    }
+
+    /// Records the thermal state observed now.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sample can't be persisted.
+    pub fn record(&mut self, state: ThermalState) -> Result<()> {
+        self.record_at(SystemTime::now(), state)
+    }
+
+    /// Records the thermal state observed at `timestamp`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sample can't be persisted.
+    pub fn record_at(&mut self, timestamp: SystemTime, state: ThermalState) -> Result<()> {
+        self.history.push(HistorySample::new(timestamp, state as u8 as f64))
+    }
+
+    /// The most recently recorded thermal state.
+    pub fn current(&self) -> Option<ThermalState> {
+        self.history.latest().and_then(|sample| ThermalState::from_raw(sample.value as isize))
+    }
+
+    /// The most severe thermal state in the history.
+    pub fn peak(&self) -> Option<ThermalState> {
+        self.states().map(|(_, state)| state).max()
+    }
+
+    /// Total time spent in `state`, measured between consecutive samples.
+    ///
+    /// The newest sample doesn't contribute, since it isn't known how long the state lasts.
+    pub fn time_in_state(&self, state: ThermalState) -> Duration {
+        let samples: Vec<_> = self.states().collect();
+        samples
+            .windows(2)
+            .filter(|pair| pair[0].1 == state)
+            .filter_map(|pair| pair[1].0.duration_since(pair[0].0).ok())
+            .sum()
+    }
+
+    /// The underlying samples, oldest first.
+    pub fn history(&self) -> &MetricHistory {
+        &self.history
+    }
+
+    fn states(&self) -> impl Iterator<Item = (SystemTime, ThermalState)> + '_ {
+        self.history.samples().iter().filter_map(|sample| {
+            ThermalState::from_raw(sample.value as isize).map(|state| (sample.timestamp, state))
+        })
+    }
+}
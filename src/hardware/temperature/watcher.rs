@@ -0,0 +1,483 @@
+//! Alerts when a component crosses a temperature threshold.
+//!
+//! [`TemperatureWatcher`] reads the temperatures of the SMC in a background task, a single
+//! [`IOKit::get_thermal_info`] call per tick, and the SSD temperature from its SMART data if a drive is configured. Each
+//! component with a [`TemperatureThreshold`] has an [`AlertLevel`], and every change of level is broadcast as a
+//! [`ThermalEvent`].
+//!
+//! A level is entered as soon as its threshold is reached, but only left once the temperature drops
+//! [`WatcherConfig::hysteresis`] below it, so a temperature hovering at a threshold doesn't raise an event on every tick.
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use tokio::{sync::broadcast, task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{
+    error::{Error, Result},
+    hardware::iokit::{IOKit, IOKitImpl, ThermalInfo},
+    shutdown,
+};
+
+/// Events kept for receivers that fall behind
+const EVENT_CAPACITY: usize = 64;
+/// Name of the watching task in the shutdown registry
+pub(crate) const TEMPERATURE_WATCHER: &str = "temperature-watcher";
+
+/// A component whose temperature can be watched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ThermalComponent {
+    /// The CPU, as reported by [`ThermalInfo::cpu_temp`]
+    Cpu,
+    /// The GPU
+    Gpu,
+    /// The battery
+    Battery,
+    /// The drive configured as [`WatcherConfig::ssd_device`]
+    Ssd,
+    /// The air inside the case
+    Ambient,
+}
+
+/// How hot a component is relative to its thresholds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AlertLevel {
+    /// Below the warning threshold
+    #[default]
+    Normal,
+    /// At or above the warning threshold
+    Warning,
+    /// At or above the critical threshold
+    Critical,
+}
+
+/// Warning and critical temperatures of a component, in degrees Celsius
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TemperatureThreshold {
+    /// Temperature from which the component is at [`AlertLevel::Warning`]
+    pub warning: f64,
+    /// Temperature from which the component is at [`AlertLevel::Critical`]
+    pub critical: f64,
+}
+
+impl TemperatureThreshold {
+    /// Creates thresholds with the given warning and critical temperatures.
+    pub fn new(warning: f64, critical: f64) -> Self {
+        Self { warning, critical }
+    }
+
+    /// The level of `celsius` for a component currently at `current`, leaving a level only once the temperature is
+    /// `hysteresis` below its threshold.
+    pub fn level(&self, current: AlertLevel, celsius: f64, hysteresis: f64) -> AlertLevel {
+        let level_at = |offset: f64| {
+            if celsius >= self.critical - offset {
+                AlertLevel::Critical
+            } else if celsius >= self.warning - offset {
+                AlertLevel::Warning
+            } else {
+                AlertLevel::Normal
+            }
+        };
+
+        let level = level_at(0.0);
+        if level >= current {
+            level
+        } else {
+            level_at(hysteresis).min(current)
+        }
+    }
+}
+
+/// A component reaching another [`AlertLevel`]
+///
+/// A lower level than before means the component recovered.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThermalEvent {
+    /// The component whose level changed
+    pub component: ThermalComponent,
+    /// The new level
+    pub level: AlertLevel,
+    /// Temperature that caused the change, in degrees Celsius
+    pub celsius: f64,
+    /// When the temperature was read
+    pub timestamp: SystemTime,
+}
+
+/// Thresholds of a [`TemperatureWatcher`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatcherConfig {
+    /// Thresholds of the watched components; components without one aren't watched
+    pub thresholds: BTreeMap<ThermalComponent, TemperatureThreshold>,
+    /// Degrees Celsius a temperature must drop below a threshold to leave its level
+    pub hysteresis: f64,
+    /// BSD name of the drive whose temperature is the [`ThermalComponent::Ssd`] one, e.g. "disk0"
+    pub ssd_device: Option<String>,
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        Self {
+            thresholds: BTreeMap::from([
+                (ThermalComponent::Cpu, TemperatureThreshold::new(85.0, 95.0)),
+                (ThermalComponent::Gpu, TemperatureThreshold::new(85.0, 95.0)),
+                (ThermalComponent::Battery, TemperatureThreshold::new(40.0, 45.0)),
+                (ThermalComponent::Ssd, TemperatureThreshold::new(60.0, 70.0)),
+                (ThermalComponent::Ambient, TemperatureThreshold::new(40.0, 50.0)),
+            ]),
+            hysteresis: 3.0,
+            ssd_device: None,
+        }
+    }
+}
+
+impl WatcherConfig {
+    /// Sets the thresholds of `component`, watching it.
+    pub fn with_threshold(
+        mut self,
+        component: ThermalComponent,
+        threshold: TemperatureThreshold,
+    ) -> Self {
+        self.thresholds.insert(component, threshold);
+        self
+    }
+}
+
+/// The level of each watched component, turning readings into events
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AlertLevels {
+    levels: BTreeMap<ThermalComponent, AlertLevel>,
+}
+
+impl AlertLevels {
+    /// Updates the levels with `readings` taken at `timestamp` and returns the changes.
+    ///
+    /// Components without a reading keep their level.
+    pub(crate) fn update(
+        &mut self,
+        config: &WatcherConfig,
+        readings: &[(ThermalComponent, f64)],
+        timestamp: SystemTime,
+    ) -> Vec<ThermalEvent> {
+        readings
+            .iter()
+            .filter_map(|&(component, celsius)| {
+                let threshold = config.thresholds.get(&component)?;
+                let current = self.levels.entry(component).or_default();
+                let level = threshold.level(*current, celsius, config.hysteresis);
+                if level == *current {
+                    return None;
+                }
+                *current = level;
+                Some(ThermalEvent { component, level, celsius, timestamp })
+            })
+            .collect()
+    }
+}
+
+/// The temperature of each component in `info`, and of the SSD if it was read.
+pub(crate) fn component_readings(
+    info: &ThermalInfo,
+    ssd: Option<f64>,
+) -> Vec<(ThermalComponent, f64)> {
+    [
+        (ThermalComponent::Cpu, Some(info.cpu_temp)),
+        (ThermalComponent::Gpu, info.gpu_temp),
+        (ThermalComponent::Battery, info.battery_temp),
+        (ThermalComponent::Ssd, ssd),
+        (ThermalComponent::Ambient, info.ambient_temp),
+    ]
+    .into_iter()
+    .filter_map(|(component, celsius)| Some((component, celsius?)))
+    .collect()
+}
+
+/// Reads the temperatures of the watched components.
+fn sample(io_kit: &dyn IOKit, config: &WatcherConfig) -> Vec<(ThermalComponent, f64)> {
+    let ssd = config
+        .ssd_device
+        .as_deref()
+        .filter(|_| config.thresholds.contains_key(&ThermalComponent::Ssd))
+        .and_then(|device| io_kit.get_smart_data(device).ok()?.temperature_celsius);
+
+    match io_kit.get_thermal_info() {
+        Ok(info) => component_readings(&info, ssd),
+        Err(e) => {
+            tracing::debug!("Failed to read the thermal info: {e}");
+            ssd.map(|celsius| vec![(ThermalComponent::Ssd, celsius)]).unwrap_or_default()
+        },
+    }
+}
+
+/// Watches the temperatures of the components for threshold crossings
+///
+/// A background task on the tokio runtime reads the temperatures every interval and broadcasts a [`ThermalEvent`]
+/// whenever a component changes level. The first reading happens one interval after the start, and components start
+/// at [`AlertLevel::Normal`], so a component that is already hot raises an event on the first reading. The task stops
+/// when the watcher is dropped or the crate is [shut down](crate::shutdown()).
+///
+/// ```rust,no_run
+/// # async fn example() -> darwin_metrics::Result<()> {
+/// use std::time::Duration;
+///
+/// use darwin_metrics::hardware::temperature::{
+///     TemperatureThreshold, TemperatureWatcher, ThermalComponent, WatcherConfig,
+/// };
+///
+/// let config = WatcherConfig::default()
+///     .with_threshold(ThermalComponent::Cpu, TemperatureThreshold::new(80.0, 95.0));
+/// let mut watcher = TemperatureWatcher::spawn(config, Duration::from_secs(1));
+/// loop {
+///     let event = watcher.recv().await?;
+///     println!("{:?} is {:?} at {:.1} °C", event.component, event.level, event.celsius);
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TemperatureWatcher {
+    receiver: broadcast::Receiver<ThermalEvent>,
+    task: JoinHandle<()>,
+}
+
+impl TemperatureWatcher {
+    /// Starts reading the temperatures every `interval` and comparing them with the thresholds of `config`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn spawn(config: WatcherConfig, interval: Duration) -> Self {
+        Self::with_iokit(IOKitImpl::default(), config, interval)
+    }
+
+    /// Starts watching the temperatures read through `io_kit`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn with_iokit(
+        io_kit: impl IOKit + 'static,
+        config: WatcherConfig,
+        interval: Duration,
+    ) -> Self {
+        let io_kit: Arc<dyn IOKit> = Arc::new(io_kit);
+        let config = Arc::new(config);
+        let (sender, receiver) = broadcast::channel(EVENT_CAPACITY);
+
+        let component = shutdown::register(TEMPERATURE_WATCHER);
+        let task_component = Arc::clone(&component);
+
+        let task = tokio::spawn(async move {
+            let _running = task_component.running();
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // The first tick completes immediately, leaving subscribers no time to subscribe
+            ticker.tick().await;
+
+            let mut levels = AlertLevels::default();
+            while !task_component.is_stop_requested() {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = task_component.stop_requested() => break,
+                }
+
+                let (io_kit, sample_config) = (io_kit.clone(), config.clone());
+                let Ok(readings) =
+                    tokio::task::spawn_blocking(move || sample(&*io_kit, &sample_config)).await
+                else {
+                    continue;
+                };
+
+                for event in levels.update(&config, &readings, SystemTime::now()) {
+                    // Without receivers the event is dropped, and the watcher keeps one itself
+                    let _ = sender.send(event);
+                }
+            }
+        });
+        let abort = task.abort_handle();
+        component.set_abort(move || abort.abort());
+
+        Self { receiver, task }
+    }
+
+    /// A receiver of the events raised from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ThermalEvent> {
+        self.receiver.resubscribe()
+    }
+
+    /// Waits for the next event, skipping the ones lost if the caller fell more than 64 events behind.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the watching task stopped, e.g. because the crate was shut down.
+    pub async fn recv(&mut self) -> Result<ThermalEvent> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Ok(event),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Skipped {skipped} thermal events of a slow receiver");
+                },
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(Error::system("The temperature watcher stopped"))
+                },
+            }
+        }
+    }
+}
+
+impl Drop for TemperatureWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::hardware::iokit::MockIOKit;
+
+    fn cpu_only(hysteresis: f64) -> WatcherConfig {
+        WatcherConfig {
+            thresholds: BTreeMap::from([(
+                ThermalComponent::Cpu,
+                TemperatureThreshold::new(85.0, 95.0),
+            )]),
+            hysteresis,
+            ssd_device: None,
+        }
+    }
+
+    #[test]
+    fn test_threshold_level_hysteresis() {
+        let threshold = TemperatureThreshold::new(85.0, 95.0);
+        let level = |current, celsius| threshold.level(current, celsius, 3.0);
+
+        assert_eq!(level(AlertLevel::Normal, 84.9), AlertLevel::Normal);
+        assert_eq!(level(AlertLevel::Normal, 85.0), AlertLevel::Warning);
+        assert_eq!(level(AlertLevel::Normal, 99.0), AlertLevel::Critical);
+        assert_eq!(level(AlertLevel::Warning, 82.5), AlertLevel::Warning, "Within the hysteresis");
+        assert_eq!(level(AlertLevel::Warning, 81.9), AlertLevel::Normal);
+        assert_eq!(level(AlertLevel::Critical, 92.0), AlertLevel::Critical);
+        assert_eq!(level(AlertLevel::Critical, 91.0), AlertLevel::Warning);
+        assert_eq!(level(AlertLevel::Critical, 70.0), AlertLevel::Normal);
+    }
+
+    #[test]
+    fn test_component_readings() {
+        let info = ThermalInfo {
+            cpu_temp: 60.0,
+            gpu_temp: None,
+            battery_temp: Some(31.0),
+            ambient_temp: None,
+            ..Default::default()
+        };
+        assert_eq!(
+            component_readings(&info, Some(45.0)),
+            vec![
+                (ThermalComponent::Cpu, 60.0),
+                (ThermalComponent::Battery, 31.0),
+                (ThermalComponent::Ssd, 45.0),
+            ],
+            "Missing sensors have no reading"
+        );
+    }
+
+    #[test]
+    fn test_alert_levels_only_report_changes() {
+        let config = cpu_only(3.0);
+        let mut levels = AlertLevels::default();
+        let now = SystemTime::now();
+        let mut update = |celsius| {
+            levels
+                .update(
+                    &config,
+                    &[(ThermalComponent::Cpu, celsius), (ThermalComponent::Gpu, 99.0)],
+                    now,
+                )
+                .into_iter()
+                .map(|event| (event.level, event.celsius))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(update(86.0), [(AlertLevel::Warning, 86.0)], "The GPU isn't watched");
+        assert_eq!(update(84.0), []);
+        assert_eq!(update(86.0), []);
+        assert_eq!(update(81.0), [(AlertLevel::Normal, 81.0)]);
+    }
+
+    #[tokio::test]
+    async fn test_watcher_reports_rising_and_falling_temperature() {
+        let _serial = crate::shutdown::TEST_SERIAL.lock().await;
+        let temperatures = Arc::new(Mutex::new(VecDeque::from([
+            70.0, 82.0, 86.0, 84.0, 86.0, 84.0, 96.0, 93.0, 94.0, 91.0, 83.0, 86.0, 81.0,
+        ])));
+
+        let mut mock_iokit = MockIOKit::new();
+        mock_iokit.expect_get_thermal_info().returning(move || {
+            let mut temperatures = temperatures.lock().unwrap();
+            let cpu_temp = if temperatures.len() > 1 {
+                temperatures.pop_front().unwrap()
+            } else {
+                temperatures[0]
+            };
+            Ok(ThermalInfo { cpu_temp, ..Default::default() })
+        });
+
+        let mut watcher =
+            TemperatureWatcher::with_iokit(mock_iokit, cpu_only(3.0), Duration::from_millis(5));
+        let mut events = Vec::new();
+        for _ in 0..4 {
+            let event = tokio::time::timeout(Duration::from_secs(5), watcher.recv());
+            let event = event.await.expect("Timed out waiting for an event").unwrap();
+            assert_eq!(event.component, ThermalComponent::Cpu);
+            events.push((event.level, event.celsius));
+        }
+
+        assert_eq!(
+            events,
+            [
+                (AlertLevel::Warning, 86.0),
+                (AlertLevel::Critical, 96.0),
+                (AlertLevel::Warning, 91.0),
+                (AlertLevel::Normal, 81.0),
+            ]
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), watcher.recv()).await.is_err(),
+            "A steady temperature raises no more events"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shutdown_stops_the_watcher() {
+        let _serial = crate::shutdown::TEST_SERIAL.lock().await;
+
+        let mut mock_iokit = MockIOKit::new();
+        mock_iokit
+            .expect_get_thermal_info()
+            .returning(|| Ok(ThermalInfo { cpu_temp: 50.0, ..Default::default() }));
+        let mut watcher =
+            TemperatureWatcher::with_iokit(mock_iokit, cpu_only(3.0), Duration::from_secs(60));
+
+        let timeout = Duration::from_secs(5);
+        let report = tokio::task::spawn_blocking(move || crate::shutdown(timeout)).await.unwrap();
+
+        assert!(
+            report.components.iter().any(|component| component.name == TEMPERATURE_WATCHER
+                && component.outcome == crate::shutdown::ShutdownOutcome::Stopped),
+            "{report:?}"
+        );
+        let stopped = tokio::time::timeout(timeout, watcher.recv()).await.unwrap();
+        assert!(stopped.is_err(), "The watcher ended with its task");
+    }
+}
@@ -0,0 +1,204 @@
+//! # History Module
+//!
+//! Bounded, timestamped metric histories with optional persistence.
+//!
+//! [`MetricHistory`] keeps the most recent samples of a metric in memory. Trackers built on it, such as
+//! [`BatteryHistory`](crate::battery::BatteryHistory) and
+//! [`ThermalLevelTracker`](crate::hardware::temperature::ThermalLevelTracker), can be given a [`HistoryStore`] so that
+//! their history survives process restarts: every sample is appended to a fixed-size ring file, and on startup the
+//! history resumes from the samples on disk.
+//!
+//! When resuming, samples older than the [`ResumePolicy::horizon`] are discarded, as are samples separated from the
+//! present by an implausible clock jump (e.g. a machine that booted with the wrong time before NTP sync).
+//!
+//! ```rust,no_run
+//! use darwin_metrics::history::{HistoryStore, MetricHistory, ResumePolicy};
+//!
+//! fn main() -> darwin_metrics::Result<()> {
+//!     let store = HistoryStore::open("/tmp/battery.history", 1024)?;
+//!     let mut history = MetricHistory::with_store(1024, store, ResumePolicy::default())?;
+//!     history.push_now(87.5)?;
+//!     Ok(())
+//! }
+//! ```
+
+mod store;
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub use store::HistoryStore;
+
+use crate::error::Result;
+
+/// A single timestamped metric value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistorySample {
+    /// When the value was recorded
+    pub timestamp: SystemTime,
+    /// The recorded value
+    pub value: f64,
+}
+
+impl HistorySample {
+    /// Creates a new sample.
+    pub fn new(timestamp: SystemTime, value: f64) -> Self {
+        Self { timestamp, value }
+    }
+
+    /// Creates a sample timestamped with the current time.
+    pub fn now(value: f64) -> Self {
+        Self::new(SystemTime::now(), value)
+    }
+}
+
+/// Rules for which persisted samples are kept when a history resumes from disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumePolicy {
+    /// Samples older than this are discarded
+    pub horizon: Duration,
+    /// Gaps between consecutive samples (or between the newest sample and now) larger than this, or any step
+    /// backwards in time larger than this, are treated as clock jumps; samples before the jump are discarded
+    pub max_clock_jump: Duration,
+}
+
+impl Default for ResumePolicy {
+    fn default() -> Self {
+        Self {
+            horizon: Duration::from_secs(24 * 60 * 60),
+            max_clock_jump: Duration::from_secs(6 * 60 * 60),
+        }
+    }
+}
+
+impl ResumePolicy {
+    /// Returns the samples that can be trusted at `now`, keeping their order.
+    ///
+    /// Only the newest run of samples without a clock jump is kept, and only if that run doesn't end in the future or
+    /// too long before `now`.
+    pub fn filter(&self, samples: &[HistorySample], now: SystemTime) -> Vec<HistorySample> {
+        let is_jump = |earlier: SystemTime, later: SystemTime| match later.duration_since(earlier) {
+            Ok(gap) => gap > self.max_clock_jump,
+            Err(backwards) => backwards.duration() > self.max_clock_jump,
+        };
+
+        // Find the start of the newest run without a clock jump
+        let start = samples
+            .windows(2)
+            .rposition(|pair| is_jump(pair[0].timestamp, pair[1].timestamp))
+            .map_or(0, |i| i + 1);
+        let run = &samples[start..];
+
+        // The run has to connect to the present as well
+        match run.last() {
+            Some(last) if !is_jump(last.timestamp, now) => {},
+            _ => return Vec::new(),
+        }
+
+        let cutoff = now.checked_sub(self.horizon).unwrap_or(UNIX_EPOCH);
+        run.iter().filter(|sample| sample.timestamp >= cutoff).copied().collect()
+    }
+}
+
+/// A bounded in-memory history of a metric, optionally persisted to a [`HistoryStore`]
+#[derive(Debug)]
+pub struct MetricHistory {
+    samples: VecDeque<HistorySample>,
+    capacity: usize,
+    store: Option<HistoryStore>,
+}
+
+impl MetricHistory {
+    /// Creates an in-memory history holding up to `capacity` samples.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity.min(1024)),
+            capacity: capacity.max(1),
+            store: None,
+        }
+    }
+
+    /// Creates a history that persists every sample to `store` and resumes from the samples already in it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store can't be read.
+    pub fn with_store(capacity: usize, store: HistoryStore, policy: ResumePolicy) -> Result<Self> {
+        let mut history = Self::new(capacity);
+        let persisted = policy.filter(&store.load()?, SystemTime::now());
+
+        let skip = persisted.len().saturating_sub(history.capacity);
+        history.samples.extend(persisted.into_iter().skip(skip));
+        history.store = Some(store);
+        Ok(history)
+    }
+
+    /// Adds a sample, evicting the oldest one if the history is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sample can't be persisted; it's still added to the in-memory history.
+    pub fn push(&mut self, sample: HistorySample) -> Result<()> {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+
+        match self.store.as_mut() {
+            Some(store) => store.append(sample),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds a value timestamped with the current time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sample can't be persisted.
+    pub fn push_now(&mut self, value: f64) -> Result<()> {
+        self.push(HistorySample::now(value))
+    }
+
+    /// The samples in the order they were recorded.
+    pub fn samples(&self) -> &VecDeque<HistorySample> {
+        &self.samples
+    }
+
+    /// The most recent sample.
+    pub fn latest(&self) -> Option<&HistorySample> {
+        self.samples.back()
+    }
+
+    /// Number of samples held.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Whether the history holds no samples.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Maximum number of samples held.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Whether samples are persisted to a store.
+    pub fn is_persistent(&self) -> bool {
+        self.store.is_some()
+    }
+}
+
+pub(crate) fn to_unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+pub(crate) fn from_unix_ms(ms: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms)
+}
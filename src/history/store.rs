@@ -0,0 +1,256 @@
+use std::{
+    fs::{File, OpenOptions},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use super::{from_unix_ms, to_unix_ms, HistorySample};
+use crate::error::{Error, Result};
+
+/// Magic bytes identifying a history ring file ("DMHS")
+const MAGIC: u32 = 0x444d_4853;
+/// Version of the file layout
+const VERSION: u32 = 1;
+/// Size of the file header in bytes
+const HEADER_SIZE: u64 = 32;
+/// Size of a single record slot in bytes
+const RECORD_SIZE: u64 = 32;
+
+/// A fixed-size on-disk ring of history samples.
+///
+/// The file consists of a small header followed by `capacity` fixed-size record slots. Each record is framed with a
+/// sequence number and a CRC32 checksum, so records that were only partially written (e.g. because the process was
+/// killed mid-write) are detected and skipped when loading instead of failing the open. Once the ring is full, new
+/// records overwrite the oldest ones.
+///
+/// Record layout (little endian):
+///
+/// | Offset | Size | Field                          |
+/// |--------|------|--------------------------------|
+/// | 0      | 8    | sequence number (starts at 1)  |
+/// | 8      | 8    | timestamp, ms since Unix epoch |
+/// | 16     | 8    | value (f64 bits)               |
+/// | 24     | 4    | CRC32 of bytes 0..24           |
+/// | 28     | 4    | reserved                       |
+#[derive(Debug)]
+pub struct HistoryStore {
+    file: File,
+    path: PathBuf,
+    capacity: u64,
+    next_seq: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Record {
+    seq: u64,
+    sample: HistorySample,
+}
+
+impl HistoryStore {
+    /// Opens or creates a ring file holding up to `capacity` samples.
+    ///
+    /// If the file exists with a different capacity, it is rewritten with the new capacity, keeping the most recent
+    /// samples that fit.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `capacity` is zero, the file can't be opened, or an existing file isn't a history store.
+    pub fn open<P: AsRef<Path>>(path: P, capacity: usize) -> Result<Self> {
+        if capacity == 0 {
+            return Err(Error::invalid_data("History store capacity must be greater than zero"));
+        }
+
+        let path = path.as_ref().to_path_buf();
+        let file =
+            OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        let capacity = capacity as u64;
+
+        let mut store = Self { file, path, capacity, next_seq: 1 };
+
+        if store.file.metadata()?.len() < HEADER_SIZE {
+            store.initialize()?;
+            return Ok(store);
+        }
+
+        let existing_capacity = store.read_header()?;
+        if existing_capacity != capacity {
+            // Carry the newest records over into a file with the new capacity
+            let mut records = store.read_records(existing_capacity)?;
+            let keep = records.len().saturating_sub(capacity as usize);
+            records.drain(..keep);
+
+            store.initialize()?;
+            for record in records {
+                store.append(record.sample)?;
+            }
+            return Ok(store);
+        }
+
+        store.next_seq = store.read_records(capacity)?.last().map_or(1, |record| record.seq + 1);
+        Ok(store)
+    }
+
+    /// Path of the underlying file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Maximum number of samples kept.
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+
+    /// Appends a sample, overwriting the oldest one once the ring is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record can't be written.
+    pub fn append(&mut self, sample: HistorySample) -> Result<()> {
+        let seq = self.next_seq;
+        let slot = (seq - 1) % self.capacity;
+        self.file.write_all_at(&encode_record(seq, &sample), HEADER_SIZE + slot * RECORD_SIZE)?;
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    /// Loads all valid samples in the order they were appended.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read. Corrupt records are skipped, not reported.
+    pub fn load(&self) -> Result<Vec<HistorySample>> {
+        Ok(self.read_records(self.capacity)?.into_iter().map(|record| record.sample).collect())
+    }
+
+    /// Loads the samples taken within `window` of now, in the order they were appended.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read.
+    pub fn load_recent(&self, window: Duration) -> Result<Vec<HistorySample>> {
+        let cutoff = SystemTime::now().checked_sub(window).unwrap_or(SystemTime::UNIX_EPOCH);
+        let mut samples = self.load()?;
+        samples.retain(|sample| sample.timestamp >= cutoff);
+        Ok(samples)
+    }
+
+    /// Flushes written records to disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be synced.
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    fn initialize(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.set_len(HEADER_SIZE + self.capacity * RECORD_SIZE)?;
+
+        let mut header = [0u8; HEADER_SIZE as usize];
+        header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&VERSION.to_le_bytes());
+        header[8..16].copy_from_slice(&self.capacity.to_le_bytes());
+        header[16..20].copy_from_slice(&(RECORD_SIZE as u32).to_le_bytes());
+        self.file.write_all_at(&header, 0)?;
+
+        self.next_seq = 1;
+        Ok(())
+    }
+
+    /// Validates the header and returns the capacity the file was created with.
+    fn read_header(&self) -> Result<u64> {
+        let mut header = [0u8; HEADER_SIZE as usize];
+        self.file.read_exact_at(&mut header, 0)?;
+
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap_or_default());
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap_or_default());
+        let capacity = u64::from_le_bytes(header[8..16].try_into().unwrap_or_default());
+        let record_size = u32::from_le_bytes(header[16..20].try_into().unwrap_or_default());
+
+        if magic != MAGIC {
+            return Err(Error::invalid_data(format!(
+                "{} is not a history store",
+                self.path.display()
+            )));
+        }
+        if version != VERSION || record_size as u64 != RECORD_SIZE || capacity == 0 {
+            return Err(Error::invalid_data(format!(
+                "Unsupported history store format in {} (version {version})",
+                self.path.display()
+            )));
+        }
+
+        Ok(capacity)
+    }
+
+    /// Reads every valid record of a file with the given capacity, sorted by sequence number.
+    fn read_records(&self, capacity: u64) -> Result<Vec<Record>> {
+        let len = self.file.metadata()?.len();
+        let mut records = Vec::new();
+        let mut buf = [0u8; RECORD_SIZE as usize];
+
+        for slot in 0..capacity {
+            let offset = HEADER_SIZE + slot * RECORD_SIZE;
+            // A truncated file simply has fewer records
+            if offset + RECORD_SIZE > len {
+                break;
+            }
+            self.file.read_exact_at(&mut buf, offset)?;
+            if let Some(record) = decode_record(&buf) {
+                records.push(record);
+            }
+        }
+
+        records.sort_by_key(|record| record.seq);
+
+        // A slot that was never overwritten after a rewrap can hold a record from an older lap; only the newest
+        // `capacity` sequence numbers are valid
+        if let Some(last) = records.last().map(|record| record.seq) {
+            let oldest_valid = last.saturating_sub(capacity - 1);
+            records.retain(|record| record.seq >= oldest_valid);
+        }
+
+        Ok(records)
+    }
+}
+
+fn encode_record(seq: u64, sample: &HistorySample) -> [u8; RECORD_SIZE as usize] {
+    let mut buf = [0u8; RECORD_SIZE as usize];
+    buf[0..8].copy_from_slice(&seq.to_le_bytes());
+    buf[8..16].copy_from_slice(&to_unix_ms(sample.timestamp).to_le_bytes());
+    buf[16..24].copy_from_slice(&sample.value.to_bits().to_le_bytes());
+    let crc = crc32(&buf[0..24]);
+    buf[24..28].copy_from_slice(&crc.to_le_bytes());
+    buf
+}
+
+fn decode_record(buf: &[u8; RECORD_SIZE as usize]) -> Option<Record> {
+    let seq = u64::from_le_bytes(buf[0..8].try_into().ok()?);
+    let crc = u32::from_le_bytes(buf[24..28].try_into().ok()?);
+
+    // Unused slots are all zeroes and partially written slots fail the checksum
+    if seq == 0 || crc != crc32(&buf[0..24]) {
+        return None;
+    }
+
+    let timestamp_ms = u64::from_le_bytes(buf[8..16].try_into().ok()?);
+    let value = f64::from_bits(u64::from_le_bytes(buf[16..24].try_into().ok()?));
+
+    Some(Record { seq, sample: HistorySample { timestamp: from_unix_ms(timestamp_ms), value } })
+}
+
+/// CRC-32 (IEEE 802.3) checksum
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,242 @@
+use std::{
+    fs,
+    os::unix::fs::FileExt,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::*;
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "darwin-metrics-history-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn sample_at(secs: u64, value: f64) -> HistorySample {
+    HistorySample::new(UNIX_EPOCH + Duration::from_secs(secs), value)
+}
+
+fn recent(secs_ago: u64, value: f64) -> HistorySample {
+    HistorySample::new(SystemTime::now() - Duration::from_secs(secs_ago), value)
+}
+
+fn values(samples: &[HistorySample]) -> Vec<f64> {
+    samples.iter().map(|s| s.value).collect()
+}
+
+#[test]
+fn test_store_round_trip_after_reopen() {
+    let path = temp_path("round-trip");
+
+    {
+        let mut store = HistoryStore::open(&path, 8).unwrap();
+        for i in 0..5 {
+            store.append(sample_at(1_000 + i, i as f64)).unwrap();
+        }
+        store.sync().unwrap();
+    }
+
+    let mut store = HistoryStore::open(&path, 8).unwrap();
+    let loaded = store.load().unwrap();
+    assert_eq!(values(&loaded), vec![0.0, 1.0, 2.0, 3.0, 4.0]);
+    assert_eq!(loaded[0].timestamp, UNIX_EPOCH + Duration::from_secs(1_000));
+
+    // Appending after a reopen continues the sequence
+    store.append(sample_at(1_005, 5.0)).unwrap();
+    assert_eq!(values(&store.load().unwrap()).last(), Some(&5.0));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_store_skips_corrupt_tail_record() {
+    let path = temp_path("corrupt-tail");
+
+    {
+        let mut store = HistoryStore::open(&path, 8).unwrap();
+        for i in 0..4 {
+            store.append(sample_at(1_000 + i, i as f64)).unwrap();
+        }
+    }
+
+    // Simulate a write that was interrupted halfway through the last record
+    let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.write_all_at(&[0xab; 12], 32 + 3 * 32 + 8).unwrap();
+    drop(file);
+
+    let mut store = HistoryStore::open(&path, 8).unwrap();
+    assert_eq!(values(&store.load().unwrap()), vec![0.0, 1.0, 2.0]);
+
+    // The corrupt slot is reused by the next append
+    store.append(sample_at(1_010, 10.0)).unwrap();
+    assert_eq!(values(&store.load().unwrap()), vec![0.0, 1.0, 2.0, 10.0]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_store_truncated_file() {
+    let path = temp_path("truncated");
+
+    {
+        let mut store = HistoryStore::open(&path, 8).unwrap();
+        for i in 0..4 {
+            store.append(sample_at(1_000 + i, i as f64)).unwrap();
+        }
+    }
+
+    let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(32 + 2 * 32 + 5).unwrap();
+    drop(file);
+
+    let store = HistoryStore::open(&path, 8).unwrap();
+    assert_eq!(values(&store.load().unwrap()), vec![0.0, 1.0]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_store_wraparound() {
+    let path = temp_path("wraparound");
+
+    {
+        let mut store = HistoryStore::open(&path, 4).unwrap();
+        for i in 0..10 {
+            store.append(sample_at(1_000 + i, i as f64)).unwrap();
+        }
+        assert_eq!(values(&store.load().unwrap()), vec![6.0, 7.0, 8.0, 9.0]);
+    }
+
+    // The file doesn't grow past its capacity
+    assert_eq!(fs::metadata(&path).unwrap().len(), 32 + 4 * 32);
+
+    let mut store = HistoryStore::open(&path, 4).unwrap();
+    store.append(sample_at(1_010, 10.0)).unwrap();
+    assert_eq!(values(&store.load().unwrap()), vec![7.0, 8.0, 9.0, 10.0]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_store_capacity_change_keeps_newest() {
+    let path = temp_path("capacity-change");
+
+    {
+        let mut store = HistoryStore::open(&path, 6).unwrap();
+        for i in 0..6 {
+            store.append(sample_at(1_000 + i, i as f64)).unwrap();
+        }
+    }
+
+    let store = HistoryStore::open(&path, 3).unwrap();
+    assert_eq!(store.capacity(), 3);
+    assert_eq!(values(&store.load().unwrap()), vec![3.0, 4.0, 5.0]);
+    assert_eq!(fs::metadata(&path).unwrap().len(), 32 + 3 * 32);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_store_rejects_foreign_file() {
+    let path = temp_path("foreign");
+    fs::write(&path, [0x42u8; 64]).unwrap();
+
+    assert!(HistoryStore::open(&path, 4).is_err());
+    assert!(HistoryStore::open(temp_path("zero-capacity"), 0).is_err());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_store_load_recent() {
+    let path = temp_path("load-recent");
+
+    let mut store = HistoryStore::open(&path, 8).unwrap();
+    store.append(recent(3_600, 1.0)).unwrap();
+    store.append(recent(60, 2.0)).unwrap();
+    store.append(recent(1, 3.0)).unwrap();
+
+    assert_eq!(values(&store.load_recent(Duration::from_secs(600)).unwrap()), vec![2.0, 3.0]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_resume_policy_discards_before_clock_jump() {
+    let policy = ResumePolicy {
+        horizon: Duration::from_secs(86_400),
+        max_clock_jump: Duration::from_secs(3_600),
+    };
+    let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+    // Samples recorded while the clock was set a week ahead, then corrected
+    let samples = vec![
+        sample_at(1_000_000 + 604_800, 1.0),
+        sample_at(1_000_000 + 604_860, 2.0),
+        sample_at(999_000, 3.0),
+        sample_at(999_060, 4.0),
+    ];
+    assert_eq!(values(&policy.filter(&samples, now)), vec![3.0, 4.0]);
+
+    // A run that ends in the future is dropped entirely
+    let future = vec![sample_at(1_000_000 + 7_200, 1.0)];
+    assert!(policy.filter(&future, now).is_empty());
+
+    // As is a run that ended long before now
+    let stale = vec![sample_at(1_000_000 - 7_200, 1.0)];
+    assert!(policy.filter(&stale, now).is_empty());
+}
+
+#[test]
+fn test_resume_policy_horizon() {
+    let policy = ResumePolicy {
+        horizon: Duration::from_secs(600),
+        max_clock_jump: Duration::from_secs(3_600),
+    };
+    let now = UNIX_EPOCH + Duration::from_secs(10_000);
+
+    let samples = vec![sample_at(9_000, 1.0), sample_at(9_500, 2.0), sample_at(9_900, 3.0)];
+    assert_eq!(values(&policy.filter(&samples, now)), vec![2.0, 3.0]);
+}
+
+#[test]
+fn test_metric_history_resumes_from_store() {
+    let path = temp_path("metric-history");
+
+    {
+        let store = HistoryStore::open(&path, 16).unwrap();
+        let mut history = MetricHistory::with_store(16, store, ResumePolicy::default()).unwrap();
+        assert!(history.is_empty());
+        assert!(history.is_persistent());
+
+        history.push(recent(120, 1.0)).unwrap();
+        history.push(recent(60, 2.0)).unwrap();
+        history.push(recent(0, 3.0)).unwrap();
+    }
+
+    let store = HistoryStore::open(&path, 16).unwrap();
+    let history = MetricHistory::with_store(2, store, ResumePolicy::default()).unwrap();
+    let resumed: Vec<f64> = history.samples().iter().map(|s| s.value).collect();
+    assert_eq!(resumed, vec![2.0, 3.0]);
+    assert_eq!(history.latest().map(|s| s.value), Some(3.0));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_metric_history_capacity() {
+    let mut history = MetricHistory::new(3);
+    for i in 0..5 {
+        history.push(sample_at(1_000 + i, i as f64)).unwrap();
+    }
+
+    assert_eq!(history.len(), 3);
+    assert_eq!(history.capacity(), 3);
+    assert!(!history.is_persistent());
+    assert_eq!(history.samples().front().map(|s| s.value), Some(2.0));
+}
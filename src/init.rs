@@ -0,0 +1,365 @@
+//! Lazy initialization of shared system state
+//!
+//! Static system information and the capabilities of the machine (which IOKit services exist) are probed through FFI
+//! calls that are too slow to repeat on every measurement. Each of them is probed once, on first use, and shared by
+//! all threads afterwards.
+//!
+//! Applications that want to pay this cost at a time of their choosing, and learn what it was, can call
+//! [`initialize`] eagerly; everything else initializes transparently when first needed:
+//!
+//! ```rust,no_run
+//! let report = darwin_metrics::initialize()?;
+//! println!("Initialized in {:?}", report.total_duration());
+//! for degraded in &report.degraded {
+//!     println!("Unavailable: {degraded}");
+//! }
+//! # Ok::<(), darwin_metrics::Error>(())
+//! ```
+
+use std::{
+    ffi::{c_void, CString},
+    io, mem, ptr,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::OnceCell;
+
+use crate::{
+    error::{Error, Result},
+    system::{detect_architecture, Architecture},
+    utils::{
+        bindings::{IOObjectRelease, IOServiceGetMatchingService, IOServiceMatching},
+        retry::{with_retry, RetryPolicy},
+    },
+};
+
+/// Registry class of the System Management Controller
+const SMC_CLASS: &str = "AppleSMC";
+/// Registry class every GPU driver inherits from
+const GPU_CLASS: &str = "IOAccelerator";
+/// Registry class of the battery gauge
+const BATTERY_CLASS: &str = "AppleSmartBattery";
+
+static SYSTEM_INFO: Component<std::result::Result<SystemInfo, String>> =
+    Component::new("system_info", probe_system_info);
+static CAPABILITIES: Component<Capabilities> = Component::new("capabilities", probe_capabilities);
+
+/// Static information about the machine, which doesn't change while it is running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemInfo {
+    /// CPU architecture
+    pub architecture: Architecture,
+    /// Number of physical CPU cores
+    pub physical_cores: u32,
+    /// Number of logical CPU cores
+    pub logical_cores: u32,
+    /// Installed physical memory in bytes
+    pub memory_bytes: u64,
+    /// CPU model name (e.g. "Apple M2 Pro")
+    pub cpu_model: String,
+}
+
+/// Hardware the metrics depend on, as found in the IORegistry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Whether the System Management Controller is reachable, which temperature, fan and power readings need
+    pub smc: bool,
+    /// Whether a GPU driver is loaded
+    pub gpu: bool,
+    /// Whether the machine has a battery
+    pub battery: bool,
+}
+
+impl Capabilities {
+    /// Descriptions of the missing capabilities that limit the available metrics
+    ///
+    /// A missing battery isn't a limitation, as desktop Macs don't have one.
+    pub fn degraded(&self) -> Vec<String> {
+        let mut degraded = Vec::new();
+        if !self.smc {
+            degraded.push("smc: AppleSMC service not found; temperature, fan and power readings are unavailable".into());
+        }
+        if !self.gpu {
+            degraded
+                .push("gpu: no IOAccelerator service found; GPU metrics are unavailable".into());
+        }
+        degraded
+    }
+}
+
+/// How long a component took to initialize
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentInit {
+    /// Name of the component
+    pub name: &'static str,
+    /// Time its initialization took
+    pub duration: Duration,
+}
+
+/// Outcome of [`initialize`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitReport {
+    /// Initialization time of each component
+    pub components: Vec<ComponentInit>,
+    /// The capabilities that were detected
+    pub capabilities: Capabilities,
+    /// Descriptions of missing capabilities that limit the available metrics
+    pub degraded: Vec<String>,
+}
+
+impl InitReport {
+    /// Sum of the initialization times of all components
+    pub fn total_duration(&self) -> Duration {
+        self.components.iter().map(|component| component.duration).sum()
+    }
+}
+
+/// Initializes all shared state now instead of on first use.
+///
+/// Each component is only initialized once per process; later calls return the same report without probing again,
+/// whether the components were initialized by an earlier call or lazily by other entry points.
+///
+/// # Errors
+///
+/// Returns an error if the static system information can't be read.
+pub fn initialize() -> Result<InitReport> {
+    let (system_info, system_info_duration) = SYSTEM_INFO.timed();
+    system_info.as_ref().map_err(|e| Error::system(e.clone()))?;
+    let (capabilities, capabilities_duration) = CAPABILITIES.timed();
+
+    Ok(InitReport {
+        components: vec![
+            ComponentInit { name: SYSTEM_INFO.name, duration: *system_info_duration },
+            ComponentInit { name: CAPABILITIES.name, duration: *capabilities_duration },
+        ],
+        capabilities: *capabilities,
+        degraded: capabilities.degraded(),
+    })
+}
+
+/// Returns the static system information, reading it on first use.
+///
+/// # Errors
+///
+/// Returns an error if the information couldn't be read. The failure is remembered, like a success.
+pub fn system_info() -> Result<&'static SystemInfo> {
+    SYSTEM_INFO.get().as_ref().map_err(|e| Error::system(e.clone()))
+}
+
+/// Returns the capabilities of the machine, probing them on first use.
+pub fn capabilities() -> Capabilities {
+    *CAPABILITIES.get()
+}
+
+/// A value computed once, on first use, together with the time that took
+pub(crate) struct Component<T> {
+    name: &'static str,
+    cell: OnceCell<(T, Duration)>,
+    init: fn() -> T,
+}
+
+impl<T> Component<T> {
+    pub(crate) const fn new(name: &'static str, init: fn() -> T) -> Self {
+        Self { name, cell: OnceCell::new(), init }
+    }
+
+    /// Returns the value, initializing it if needed.
+    pub(crate) fn get(&self) -> &T {
+        &self.timed().0
+    }
+
+    /// Returns the value and how long its initialization took.
+    ///
+    /// When several threads race to the first call, only one runs the initialization and the others wait for it.
+    pub(crate) fn timed(&self) -> &(T, Duration) {
+        self.cell.get_or_init(|| {
+            let start = Instant::now();
+            let value = (self.init)();
+            (value, start.elapsed())
+        })
+    }
+}
+
+fn probe_system_info() -> std::result::Result<SystemInfo, String> {
+    let read = || -> Result<SystemInfo> {
+        Ok(SystemInfo {
+            architecture: detect_architecture()?,
+            physical_cores: sysctl_value::<i32>("hw.physicalcpu")? as u32,
+            logical_cores: sysctl_value::<i32>("hw.logicalcpu")? as u32,
+            memory_bytes: sysctl_value::<u64>("hw.memsize")?,
+            cpu_model: sysctl_string("machdep.cpu.brand_string")?,
+        })
+    };
+    read().map_err(|e| e.to_string())
+}
+
+fn probe_capabilities() -> Capabilities {
+    Capabilities {
+        smc: has_service(SMC_CLASS),
+        gpu: has_service(GPU_CLASS),
+        battery: has_service(BATTERY_CLASS),
+    }
+}
+
+/// Whether the IORegistry has a service of `class`.
+fn has_service(class: &str) -> bool {
+    let Ok(class) = CString::new(class) else {
+        return false;
+    };
+
+    unsafe {
+        // IOServiceGetMatchingService consumes the matching dictionary
+        let matching = IOServiceMatching(class.as_ptr());
+        if matching.is_null() {
+            return false;
+        }
+        let service = IOServiceGetMatchingService(0, matching);
+        if service == 0 {
+            return false;
+        }
+        IOObjectRelease(service);
+        true
+    }
+}
+
+pub(crate) fn sysctl_value<T: Copy + Default>(name: &str) -> Result<T> {
+    let c_name = CString::new(name).map_err(|_| Error::invalid_data("Invalid sysctl name"))?;
+    let mut value = T::default();
+    let mut size = mem::size_of::<T>();
+
+    // SAFETY: the buffer is a T and its size is passed along.
+    let result = unsafe {
+        libc::sysctlbyname(
+            c_name.as_ptr(),
+            &mut value as *mut T as *mut c_void,
+            &mut size,
+            ptr::null_mut(),
+            0,
+        )
+    };
+    if result != 0 || size != mem::size_of::<T>() {
+        return Err(Error::system(format!("Failed to read {name}")));
+    }
+    Ok(value)
+}
+
+pub(crate) fn sysctl_string(name: &str) -> Result<String> {
+    let c_name = CString::new(name).map_err(|_| Error::invalid_data("Invalid sysctl name"))?;
+    let failed = || Error::os_error(format!("Failed to read {name}"), io::Error::last_os_error());
+
+    // A value that grows between reading its size and reading it fails with ENOMEM, and is read again
+    let buffer = with_retry(RetryPolicy::default(), || {
+        let mut size = 0usize;
+        // SAFETY: a null buffer asks for the required size only.
+        let result = unsafe {
+            libc::sysctlbyname(c_name.as_ptr(), ptr::null_mut(), &mut size, ptr::null_mut(), 0)
+        };
+        if result != 0 {
+            return Err(failed());
+        }
+
+        let mut buffer = vec![0u8; size];
+        // SAFETY: the buffer has the size sysctl asked for.
+        let result = unsafe {
+            libc::sysctlbyname(
+                c_name.as_ptr(),
+                buffer.as_mut_ptr() as *mut c_void,
+                &mut size,
+                ptr::null_mut(),
+                0,
+            )
+        };
+        if result != 0 {
+            return Err(failed());
+        }
+        buffer.truncate(size);
+        Ok(buffer)
+    })?;
+
+    Ok(String::from_utf8_lossy(&buffer).trim_end_matches('\0').trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Barrier,
+        },
+        thread,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_initialize_is_idempotent() {
+        let first = initialize().unwrap();
+        let second = initialize().unwrap();
+
+        assert_eq!(first, second, "Repeated calls should return the same report");
+        assert_eq!(first.components.len(), 2);
+    }
+
+    #[test]
+    fn test_lazy_init_matches_eager_init() {
+        let lazy_capabilities = capabilities();
+        let lazy_system_info = system_info().unwrap().clone();
+
+        let report = initialize().unwrap();
+
+        assert_eq!(report.capabilities, lazy_capabilities);
+        assert_eq!(report.degraded, lazy_capabilities.degraded());
+        assert_eq!(*system_info().unwrap(), lazy_system_info);
+    }
+
+    #[test]
+    fn test_system_info() {
+        let info = system_info().unwrap();
+
+        assert!(info.logical_cores >= info.physical_cores && info.physical_cores > 0);
+        assert!(info.memory_bytes > 0);
+        assert!(!info.cpu_model.is_empty());
+    }
+
+    #[test]
+    fn test_degraded() {
+        assert!(Capabilities { smc: true, gpu: true, battery: false }.degraded().is_empty());
+
+        let degraded = Capabilities::default().degraded();
+        assert_eq!(degraded.len(), 2);
+        assert!(degraded[0].starts_with("smc"));
+        assert!(degraded[1].starts_with("gpu"));
+    }
+
+    static RACE_INITS: AtomicUsize = AtomicUsize::new(0);
+
+    fn counting_init() -> usize {
+        RACE_INITS.fetch_add(1, Ordering::SeqCst);
+        // Keep the initialization in progress while the other threads arrive
+        thread::sleep(Duration::from_millis(50));
+        42
+    }
+
+    #[test]
+    fn test_racing_first_calls_initialize_once() {
+        const THREADS: usize = 8;
+        let component = Component::new("race", counting_init);
+        let barrier = Barrier::new(THREADS);
+
+        let addresses: Vec<usize> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        component.get() as *const usize as usize
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        assert_eq!(RACE_INITS.load(Ordering::SeqCst), 1, "Only one thread should initialize");
+        assert!(addresses.iter().all(|&a| a == addresses[0]), "All threads should share the value");
+        assert_eq!(*component.get(), 42);
+    }
+}
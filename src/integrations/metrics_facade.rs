@@ -0,0 +1,513 @@
+//! Emitting system metrics through the `metrics` facade
+//!
+//! [`install`] spawns a background thread that collects the selected subsystems at a fixed interval and reports each
+//! value through the `metrics::gauge!` and `metrics::counter!` macros. The application's recorder decides where the
+//! values go; without one installed they are discarded.
+//!
+//! ## Naming
+//!
+//! Metric names follow the Prometheus conventions: snake case, the base unit as a suffix (`_bytes`, `_hertz`,
+//! `_ratio`) and `_total` for counters. Every name starts with the configured prefix. Per-instance values carry the
+//! instance as a label instead of in the name:
+//!
+//! | Metric | Kind | Labels |
+//! |--------|------|--------|
+//! | `<prefix>_cpu_usage_ratio` | gauge | |
+//! | `<prefix>_cpu_core_usage_ratio` | gauge | `core` |
+//! | `<prefix>_cpu_frequency_hertz` | gauge | |
+//! | `<prefix>_memory_{total,used,available,wired,compressed}_bytes` | gauge | |
+//! | `<prefix>_memory_pressure_ratio` | gauge | |
+//! | `<prefix>_memory_swap_{total,used}_bytes` | gauge | |
+//! | `<prefix>_disk_{total,used,available}_bytes` | gauge | `mount` |
+//! | `<prefix>_network_{received,transmitted}_bytes_total` | counter | `interface` |
+//!
+//! A subsystem that fails to collect is skipped for that tick with a warning, so a transient failure never shows up
+//! as a zero value.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! use darwin_metrics::integrations::metrics_facade::{install, MetricSelection};
+//!
+//! # fn main() -> darwin_metrics::Result<()> {
+//! // Install the application's exporter first, e.g. metrics-exporter-prometheus
+//! let handle = install("darwin", Duration::from_secs(10), MetricSelection::default())?;
+//!
+//! // ...
+//!
+//! handle.shutdown();
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    disk::Disk,
+    error::{Error, Result},
+    hardware::{
+        cpu::{CpuMetrics, CPU},
+        memory::Memory,
+    },
+    network::{NetworkManager, NetworkMetrics},
+    shutdown::{self, ComponentHandle},
+};
+
+/// Name of the background sampling thread
+const THREAD_NAME: &str = "darwin-metrics-facade";
+
+/// Subsystems sampled by [`install`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricSelection {
+    /// Overall and per-core CPU usage and the CPU frequency
+    pub cpu: bool,
+    /// Physical memory, memory pressure and swap usage
+    pub memory: bool,
+    /// Capacity and usage of every mounted volume
+    pub disk: bool,
+    /// Bytes received and transmitted per network interface
+    pub network: bool,
+}
+
+impl Default for MetricSelection {
+    fn default() -> Self {
+        Self { cpu: true, memory: true, disk: true, network: true }
+    }
+}
+
+/// Handle of the background sampler started by [`install`]
+///
+/// Dropping the handle stops the sampler as well, and so does a crate-wide [`shutdown`](crate::shutdown).
+#[derive(Debug)]
+pub struct MetricsFacadeHandle {
+    component: Arc<ComponentHandle>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsFacadeHandle {
+    /// Stops the sampler and waits for a collection in progress to finish.
+    pub fn shutdown(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        self.component.request_stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for MetricsFacadeHandle {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+/// Starts sampling the selected subsystems every `interval` and emitting them through the `metrics` facade.
+///
+/// The first sample is emitted right away. Metric names start with `recorder_prefix`, see the [module
+/// documentation](self) for the full list.
+///
+/// # Errors
+///
+/// Returns an error if the interval is zero, the prefix isn't a valid metric name, or the thread can't be spawned.
+pub fn install(
+    recorder_prefix: &str,
+    interval: Duration,
+    selection: MetricSelection,
+) -> Result<MetricsFacadeHandle> {
+    if interval.is_zero() {
+        return Err(Error::invalid_data("Sampling interval must not be zero"));
+    }
+    if !is_valid_prefix(recorder_prefix) {
+        return Err(Error::invalid_data(format!(
+            "Invalid metric prefix {recorder_prefix:?}: use letters, digits and underscores, not starting with a digit"
+        )));
+    }
+
+    let mut sampler = Sampler::new(recorder_prefix, collectors(selection));
+    let component = shutdown::register(THREAD_NAME);
+    let thread_component = Arc::clone(&component);
+
+    let thread = thread::Builder::new().name(THREAD_NAME.to_string()).spawn(move || {
+        let _running = thread_component.running();
+        loop {
+            sampler.tick();
+            if thread_component.wait_for_stop(interval) {
+                break;
+            }
+        }
+    })?;
+
+    Ok(MetricsFacadeHandle { component, thread: Some(thread) })
+}
+
+/// Whether `prefix` can start a Prometheus metric name
+fn is_valid_prefix(prefix: &str) -> bool {
+    !prefix.starts_with(|c: char| c.is_ascii_digit())
+        && prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The value of a [`Sample`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SampleValue {
+    /// A value that can go up and down
+    Gauge(f64),
+    /// The current total of a monotonically increasing counter
+    Counter(u64),
+}
+
+/// A single value reported by a [`Collector`]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Sample {
+    /// Metric name without the prefix
+    pub name: &'static str,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: SampleValue,
+}
+
+impl Sample {
+    pub(crate) fn gauge(name: &'static str, value: f64) -> Self {
+        Self { name, labels: Vec::new(), value: SampleValue::Gauge(value) }
+    }
+
+    pub(crate) fn counter(name: &'static str, value: u64) -> Self {
+        Self { name, labels: Vec::new(), value: SampleValue::Counter(value) }
+    }
+
+    pub(crate) fn with_label(mut self, key: &'static str, value: impl Into<String>) -> Self {
+        self.labels.push((key, value.into()));
+        self
+    }
+}
+
+/// A subsystem sampled by the [`Sampler`]
+pub(crate) trait Collector: Send {
+    /// Name used in warnings
+    fn name(&self) -> &'static str;
+
+    /// Collects the current values of the subsystem.
+    fn collect(&mut self) -> Result<Vec<Sample>>;
+}
+
+/// Collects from every collector and emits the samples through the facade
+pub(crate) struct Sampler {
+    prefix: String,
+    collectors: Vec<Box<dyn Collector>>,
+}
+
+impl Sampler {
+    pub(crate) fn new(prefix: &str, collectors: Vec<Box<dyn Collector>>) -> Self {
+        Self { prefix: prefix.to_string(), collectors }
+    }
+
+    /// Runs one collection, skipping collectors that fail.
+    pub(crate) fn tick(&mut self) {
+        for collector in &mut self.collectors {
+            match collector.collect() {
+                Ok(samples) => {
+                    for sample in &samples {
+                        emit(&self.prefix, sample);
+                    }
+                },
+                Err(e) => tracing::warn!("Skipping {} metrics: {}", collector.name(), e),
+            }
+        }
+    }
+}
+
+fn emit(prefix: &str, sample: &Sample) {
+    let name = if prefix.is_empty() {
+        sample.name.to_string()
+    } else {
+        format!("{prefix}_{}", sample.name)
+    };
+
+    match sample.value {
+        SampleValue::Gauge(value) => metrics::gauge!(name, &sample.labels).set(value),
+        SampleValue::Counter(value) => metrics::counter!(name, &sample.labels).absolute(value),
+    }
+}
+
+fn collectors(selection: MetricSelection) -> Vec<Box<dyn Collector>> {
+    let mut collectors: Vec<Box<dyn Collector>> = Vec::new();
+    if selection.cpu {
+        collectors.push(Box::new(CpuCollector));
+    }
+    if selection.memory {
+        collectors.push(Box::new(MemoryCollector));
+    }
+    if selection.disk {
+        collectors.push(Box::new(DiskCollector));
+    }
+    if selection.network {
+        collectors.push(Box::new(NetworkCollector));
+    }
+    collectors
+}
+
+struct CpuCollector;
+
+impl Collector for CpuCollector {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn collect(&mut self) -> Result<Vec<Sample>> {
+        let cpu = CPU::new()?;
+
+        let mut samples = vec![
+            Sample::gauge("cpu_usage_ratio", cpu.get_cpu_usage()),
+            Sample::gauge("cpu_frequency_hertz", cpu.frequency_mhz() * 1_000_000.0),
+        ];
+        samples.extend(cpu.core_usage().iter().enumerate().map(|(core, &usage)| {
+            Sample::gauge("cpu_core_usage_ratio", usage).with_label("core", core.to_string())
+        }));
+        Ok(samples)
+    }
+}
+
+struct MemoryCollector;
+
+impl Collector for MemoryCollector {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn collect(&mut self) -> Result<Vec<Sample>> {
+        let memory = Memory::new()?;
+
+        Ok(vec![
+            Sample::gauge("memory_total_bytes", memory.total as f64),
+            Sample::gauge("memory_used_bytes", memory.used as f64),
+            Sample::gauge("memory_available_bytes", memory.available as f64),
+            Sample::gauge("memory_wired_bytes", memory.wired as f64),
+            Sample::gauge("memory_compressed_bytes", memory.page_states.compressed as f64),
+            Sample::gauge("memory_pressure_ratio", memory.pressure),
+            Sample::gauge("memory_swap_total_bytes", memory.swap_usage.total as f64),
+            Sample::gauge("memory_swap_used_bytes", memory.swap_usage.used as f64),
+        ])
+    }
+}
+
+struct DiskCollector;
+
+impl Collector for DiskCollector {
+    fn name(&self) -> &'static str {
+        "disk"
+    }
+
+    fn collect(&mut self) -> Result<Vec<Sample>> {
+        Ok(Disk::get_all()?
+            .into_iter()
+            .flat_map(|disk| {
+                [
+                    Sample::gauge("disk_total_bytes", disk.total as f64),
+                    Sample::gauge("disk_used_bytes", disk.used as f64),
+                    Sample::gauge("disk_available_bytes", disk.available as f64),
+                ]
+                .map(|sample| sample.with_label("mount", disk.mount_point.clone()))
+            })
+            .collect())
+    }
+}
+
+struct NetworkCollector;
+
+impl Collector for NetworkCollector {
+    fn name(&self) -> &'static str {
+        "network"
+    }
+
+    fn collect(&mut self) -> Result<Vec<Sample>> {
+        // The constructor ignores discovery failures, so update again to skip the tick instead of reporting nothing
+        let mut manager = NetworkManager::new()?;
+        manager.update()?;
+
+        Ok(manager
+            .interfaces()
+            .into_iter()
+            .flat_map(|interface| {
+                [
+                    Sample::counter("network_received_bytes_total", interface.bytes_received()),
+                    Sample::counter("network_transmitted_bytes_total", interface.bytes_sent()),
+                ]
+                .map(|sample| sample.with_label("interface", interface.name()))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, VecDeque};
+
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+
+    use super::*;
+
+    type Labels = Vec<(String, String)>;
+
+    /// A collector returning scripted results, one per tick
+    struct ScriptedCollector {
+        name: &'static str,
+        ticks: VecDeque<Result<Vec<Sample>>>,
+    }
+
+    impl ScriptedCollector {
+        fn boxed(name: &'static str, ticks: Vec<Result<Vec<Sample>>>) -> Box<dyn Collector> {
+            Box::new(Self { name, ticks: ticks.into() })
+        }
+    }
+
+    impl Collector for ScriptedCollector {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn collect(&mut self) -> Result<Vec<Sample>> {
+            self.ticks.pop_front().unwrap_or_else(|| Ok(Vec::new()))
+        }
+    }
+
+    fn recorded(snapshotter: &Snapshotter) -> BTreeMap<(String, Labels), f64> {
+        snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let key = key.key();
+                let labels =
+                    key.labels().map(|l| (l.key().to_string(), l.value().to_string())).collect();
+                let value = match value {
+                    DebugValue::Gauge(value) => value.into_inner(),
+                    DebugValue::Counter(value) => value as f64,
+                    DebugValue::Histogram(_) => panic!("No histograms are emitted"),
+                };
+                ((key.name().to_string(), labels), value)
+            })
+            .collect()
+    }
+
+    fn key(name: &str, labels: &[(&str, &str)]) -> (String, Labels) {
+        (name.to_string(), labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    #[test]
+    fn test_two_ticks() {
+        let cpu = ScriptedCollector::boxed(
+            "cpu",
+            vec![
+                Ok(vec![
+                    Sample::gauge("cpu_core_usage_ratio", 0.25).with_label("core", "0"),
+                    Sample::gauge("cpu_core_usage_ratio", 0.5).with_label("core", "1"),
+                ]),
+                Ok(vec![
+                    Sample::gauge("cpu_core_usage_ratio", 0.75).with_label("core", "0"),
+                    Sample::gauge("cpu_core_usage_ratio", 1.0).with_label("core", "1"),
+                ]),
+            ],
+        );
+        let network = ScriptedCollector::boxed(
+            "network",
+            vec![
+                Ok(vec![Sample::counter("network_received_bytes_total", 1000)
+                    .with_label("interface", "en0")]),
+                Ok(vec![Sample::counter("network_received_bytes_total", 1500)
+                    .with_label("interface", "en0")]),
+            ],
+        );
+        let disk = ScriptedCollector::boxed(
+            "disk",
+            vec![
+                Err(Error::system("getfsstat failed")),
+                Ok(vec![Sample::gauge("disk_used_bytes", 42.0).with_label("mount", "/")]),
+            ],
+        );
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut sampler = Sampler::new("darwin", vec![cpu, network, disk]);
+
+        metrics::with_local_recorder(&recorder, || sampler.tick());
+        let first = recorded(&snapshotter);
+        assert_eq!(
+            first.keys().cloned().collect::<Vec<_>>(),
+            [
+                key("darwin_cpu_core_usage_ratio", &[("core", "0")]),
+                key("darwin_cpu_core_usage_ratio", &[("core", "1")]),
+                key("darwin_network_received_bytes_total", &[("interface", "en0")]),
+            ],
+            "The failed disk collection should emit nothing"
+        );
+
+        metrics::with_local_recorder(&recorder, || sampler.tick());
+        let second = recorded(&snapshotter);
+        assert_eq!(second[&key("darwin_cpu_core_usage_ratio", &[("core", "0")])], 0.75);
+        assert_eq!(second[&key("darwin_cpu_core_usage_ratio", &[("core", "1")])], 1.0);
+        assert_eq!(
+            second[&key("darwin_network_received_bytes_total", &[("interface", "en0")])],
+            1500.0
+        );
+        assert_eq!(second[&key("darwin_disk_used_bytes", &[("mount", "/")])], 42.0);
+    }
+
+    #[test]
+    fn test_empty_prefix() {
+        let collector = ScriptedCollector::boxed(
+            "memory",
+            vec![Ok(vec![Sample::gauge("memory_used_bytes", 1.0)])],
+        );
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut sampler = Sampler::new("", vec![collector]);
+
+        metrics::with_local_recorder(&recorder, || sampler.tick());
+
+        assert!(recorded(&snapshotter).contains_key(&key("memory_used_bytes", &[])));
+    }
+
+    #[test]
+    fn test_tick_without_recorder() {
+        let collector = ScriptedCollector::boxed(
+            "memory",
+            vec![Ok(vec![Sample::gauge("memory_used_bytes", 1.0)])],
+        );
+        Sampler::new("darwin", vec![collector]).tick();
+    }
+
+    #[test]
+    fn test_install_validation() {
+        assert!(install("darwin", Duration::ZERO, MetricSelection::default()).is_err());
+        assert!(install("9lives", Duration::from_secs(1), MetricSelection::default()).is_err());
+        assert!(install("dar-win", Duration::from_secs(1), MetricSelection::default()).is_err());
+    }
+
+    #[test]
+    fn test_install_and_shutdown() {
+        let _serial = crate::shutdown::TEST_SERIAL.blocking_lock();
+        let selection = MetricSelection { cpu: false, memory: true, disk: false, network: false };
+        let handle = install("darwin", Duration::from_secs(60), selection).unwrap();
+        handle.shutdown();
+    }
+
+    #[test]
+    fn test_system_collectors() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut sampler = Sampler::new("darwin", collectors(MetricSelection::default()));
+
+        metrics::with_local_recorder(&recorder, || sampler.tick());
+
+        let recorded = recorded(&snapshotter);
+        assert!(recorded.contains_key(&key("darwin_memory_total_bytes", &[])));
+        assert!(recorded.contains_key(&key("darwin_disk_total_bytes", &[("mount", "/")])));
+    }
+}
@@ -0,0 +1,14 @@
+//! Integrations with third-party metrics ecosystems
+//!
+//! This module contains opt-in bridges that feed the metrics collected by this crate into instrumentation libraries
+//! an application may already be using. Each bridge is gated behind its own feature so that applications which don't
+//! use the library don't pull it in.
+//!
+//! ## Available integrations
+//!
+//! - [`metrics_facade`] (feature `metrics-facade`) - samples system metrics in the background and emits them as
+//!   gauges and counters through the [`metrics`](https://docs.rs/metrics) facade, so whatever exporter the application
+//!   has installed picks them up.
+
+#[cfg(feature = "metrics-facade")]
+pub mod metrics_facade;
@@ -35,15 +35,15 @@
 //!
 //! ```ignore
 //! // This example won't be run by doctests but serves as API usage documentation
-//! use darwin_metrics::hardware::{cpu, gpu, temperature};
+//! use darwin_metrics::prelude::*;
 //!
 //! fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!     // Get CPU information
-//!     let cpu_obj = cpu::CPU::new();
-//!     println!("CPU cores: {}", cpu_obj.cores());
+//!     let cpu = CPU::new()?;
+//!     println!("CPU cores: {}", cpu.physical_cores());
 //!     
 //!     // Monitor temperature
-//!     let mut temp_monitor = temperature::Temperature::new();
+//!     let mut temp_monitor = Temperature::new();
 //!     let cpu_temp = temp_monitor.cpu_temperature()?;
 //!     println!("CPU Temperature: {:.1}°C", cpu_temp);
 //!     
@@ -55,6 +55,8 @@
 //! }
 //! ```
 //!
+//! The [`prelude`] brings the monitor of each subsystem and the traits needed to use them into scope.
+//!
 //! ## Feature Flags
 //!
 //! ### Core Features (Enabled by Default)
@@ -71,19 +73,44 @@
 //!
 //! - `process_monitoring` - Enable detailed process monitoring
 //! - `unstable-tests` - Enable tests that may be unstable in CI environments
+//! - `doctest-support` - Enable the mock data sources the module examples run against
+//! - `av-status` - Report whether cameras and microphones are in use (`system::av_activity`)
+//! - `control` - Enable stopping and continuing processes (`Process::suspend` and `Process::resume`)
+//! - `coregraphics` - List displays and their sleep state through CoreGraphics (`power::display_state`)
+//! - `disk-events` - Watch volumes being mounted and unmounted through DiskArbitration (`disk::DiskWatcher`)
+//! - `export-shm` - Enable publishing snapshots to a memory-mapped file ([`export::shm`]), implies `serde`
+//! - `metrics-facade` - Enable emitting metrics through the `metrics` crate facade
+//!   ([`integrations::metrics_facade`])
+//! - `hid-sensors` - Read CPU temperature from the HID thermal sensors of Apple Silicon when the SMC doesn't report it
+//! - `profiling` - Enable sampling the thread states of a process (`process::sampler`)
+//! - `selftest` - Enable comparing the crate's readings against `powermetrics` for diagnostics
+//! - `wifi` - Report the signal and connection details of the Wi-Fi interface through CoreWLAN (`network::WifiMonitor`)
+//! - `zones` - Enable reading kernel zone statistics (`hardware::memory::zone_statistics`)
 //!
 //! ## Module Structure
 //!
 //! - [`battery`] - Battery information and power metrics
+//! - [`diagnostics`] - Counters and resident memory sampling for finding leaks in long-running monitors
+//! - [`export`] - Exporting metrics to other processes
 //! - [`hardware`] - Hardware monitoring:
 //!   - [`hardware::cpu`] - CPU usage, frequency, and core information
 //!   - [`hardware::gpu`] - GPU metrics and memory usage
 //!   - [`hardware::memory`] - System memory statistics
 //!   - [`hardware::temperature`] - Temperature sensors and fan control
+//! - [`history`] - Bounded metric histories with optional on-disk persistence
+//! - [`init`] - Lazy initialization of shared system state, or eagerly with [`initialize`]
+//! - [`integrations`] - Bridges to third-party metrics libraries
 //! - [`network`] - Network interfaces and traffic statistics
+//! - [`overhead`] - Overhead of metric collection on the calling process
 //! - [`power`] - Power consumption and management
+//! - [`prelude`] - Commonly used types and traits
 //! - [`process`] - Process monitoring and management
+//! - [`report`] - Privacy-reviewed support bundles for problem reports
+//! - `selftest` - Comparison of the crate's readings against `powermetrics` (requires the `selftest` feature)
+//! - [`shutdown`] - Stopping every background thread and task of the crate, or on drop with [`ShutdownGuard`]
+//! - [`snapshot`] - Point-in-time metric snapshots and derived metrics
 //! - [`system`] - Overall system information
+//! - [`wait`] - Polling helpers that wait for a metric to cross a threshold
 //!
 //! ## Error Handling
 //!
@@ -118,18 +145,44 @@
 //! ```
 
 pub mod battery;
+pub mod diagnostics;
 pub mod disk;
+#[cfg(feature = "doctest-support")]
+#[doc(hidden)]
+pub mod doctest_support;
 pub mod error;
+pub mod export;
 pub mod hardware;
+pub mod history;
+pub mod init;
+pub mod integrations;
 pub mod network;
+pub mod overhead;
 pub mod power;
+pub mod prelude;
 pub mod process;
+pub mod report;
+pub mod resource;
+#[cfg(feature = "selftest")]
+pub mod selftest;
+pub mod shutdown;
+pub mod snapshot;
 pub mod system;
 pub mod utils;
+pub mod wait;
 
 // Re-export the core error types for easier use
 #[doc(inline)]
-pub use error::{Error, Result};
+pub use error::{Error, ErrorKind, Result};
+
+#[doc(inline)]
+pub use init::{initialize, InitReport};
+
+#[doc(inline)]
+pub use shutdown::{shutdown, ShutdownGuard, ShutdownReport};
+
+#[doc(inline)]
+pub use snapshot::{snapshot, snapshot_async, MetricsSnapshot};
 
 // Re-export primary modules for direct access
 #[doc(inline)]
@@ -145,7 +198,9 @@ pub use hardware::cpu::{FrequencyMetrics, CPU};
 pub use hardware::gpu::{Gpu, GpuMetrics};
 
 #[doc(inline)]
-pub use hardware::memory::{Memory, PageStates, PressureLevel, SwapUsage};
+pub use hardware::memory::{
+    Memory, PageStates, PressureLevel, SwapActivity, SwapActivityTracker, SwapUsage,
+};
 
 #[doc(inline)]
 pub use hardware::temperature::{Fan, Temperature, ThermalMetrics};
@@ -155,3 +210,9 @@ pub use network::{Interface as NetworkInterface, TrafficData as NetworkTraffic};
 
 #[doc(inline)]
 pub use process::{Process, ProcessInfo};
+
+#[doc(inline)]
+pub use wait::{wait_for, wait_for_async, MetricId, WaitOptions};
+
+#[doc(inline)]
+pub use utils::byte_size::ByteSize;
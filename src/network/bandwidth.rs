@@ -0,0 +1,402 @@
+//! Per-process network bandwidth accounting
+//!
+//! Per-process byte counts come from flow-level sources (such as network statistics flow snapshots), which report
+//! cumulative counters for each open flow. Two problems make those counters unreliable on their own:
+//!
+//! - Flows that open and close between two samples would be lost if only the currently open flows were summed, so the
+//!   final counters of closed flows are accumulated into the owning process's total.
+//! - The same traffic can be reported by more than one flow, so per-process sums can exceed what actually crossed the
+//!   network interfaces. Each interval, the per-process deltas are checked against the system-wide interface delta and
+//!   scaled down when they exceed it by more than a tolerance.
+//!
+//! The accumulation and reconciliation logic are pure functions of their inputs ([`FlowAccumulator`] and
+//! [`reconcile`]) so they can be tested with synthetic flow event sequences.
+
+use std::{collections::HashMap, ops::AddAssign};
+
+/// Default fraction by which per-process sums may exceed the host total before they're scaled down.
+pub const DEFAULT_TOLERANCE: f64 = 0.05;
+
+/// Identifier of a network flow as assigned by the flow source
+pub type FlowId = u64;
+
+/// Received and transmitted byte counts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteCounts {
+    /// Bytes received
+    pub rx: u64,
+    /// Bytes transmitted
+    pub tx: u64,
+}
+
+impl ByteCounts {
+    /// Creates a new pair of byte counts.
+    pub fn new(rx: u64, tx: u64) -> Self {
+        Self { rx, tx }
+    }
+
+    /// Returns the increase from `previous` to `self`.
+    ///
+    /// A counter that went backwards is treated as reset, so its current value is the increase since the reset.
+    pub fn delta_since(&self, previous: &ByteCounts) -> ByteCounts {
+        let delta = |current: u64, previous: u64| {
+            if current >= previous {
+                current - previous
+            } else {
+                current
+            }
+        };
+
+        ByteCounts { rx: delta(self.rx, previous.rx), tx: delta(self.tx, previous.tx) }
+    }
+}
+
+impl AddAssign for ByteCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.rx = self.rx.saturating_add(other.rx);
+        self.tx = self.tx.saturating_add(other.tx);
+    }
+}
+
+/// An observation of a flow's cumulative counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowEvent {
+    /// The flow is open and has transferred `counts` bytes so far
+    Update { flow: FlowId, pid: u32, counts: ByteCounts },
+    /// The flow closed after transferring `counts` bytes in total
+    Closed { flow: FlowId, pid: u32, counts: ByteCounts },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OpenFlow {
+    pid: u32,
+    last: ByteCounts,
+}
+
+/// Accumulates cumulative per-process byte counts from flow events.
+///
+/// Each event contributes the increase since the previous observation of the same flow. Closing a flow adds its final
+/// increase and forgets the flow, so a flow ID that is reused later starts counting from zero again.
+#[derive(Debug, Clone, Default)]
+pub struct FlowAccumulator {
+    open: HashMap<FlowId, OpenFlow>,
+    totals: HashMap<u32, ByteCounts>,
+}
+
+impl FlowAccumulator {
+    /// Creates an empty accumulator.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies a single flow event.
+    pub fn apply(&mut self, event: FlowEvent) {
+        let (flow, pid, counts, closed) = match event {
+            FlowEvent::Update { flow, pid, counts } => (flow, pid, counts, false),
+            FlowEvent::Closed { flow, pid, counts } => (flow, pid, counts, true),
+        };
+
+        // A flow ID reported for a different process belongs to a new flow
+        let previous = match self.open.get(&flow) {
+            Some(open) if open.pid == pid => open.last,
+            _ => ByteCounts::default(),
+        };
+
+        *self.totals.entry(pid).or_default() += counts.delta_since(&previous);
+
+        if closed {
+            self.open.remove(&flow);
+        } else {
+            self.open.insert(flow, OpenFlow { pid, last: counts });
+        }
+    }
+
+    /// Applies a sequence of flow events in order.
+    pub fn apply_all<I: IntoIterator<Item = FlowEvent>>(&mut self, events: I) {
+        for event in events {
+            self.apply(event);
+        }
+    }
+
+    /// Cumulative byte counts per process, including flows that have closed.
+    pub fn totals(&self) -> &HashMap<u32, ByteCounts> {
+        &self.totals
+    }
+
+    /// Number of flows currently open.
+    pub fn open_flows(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Forgets the totals of processes for which `keep` returns false, e.g. processes that have exited.
+    pub fn retain_pids<F: FnMut(u32) -> bool>(&mut self, mut keep: F) {
+        self.totals.retain(|pid, _| keep(*pid));
+        self.open.retain(|_, open| keep(open.pid));
+    }
+}
+
+/// The result of reconciling per-process deltas against the host total for one interval
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reconciliation {
+    /// Per-process deltas after scaling
+    pub deltas: HashMap<u32, ByteCounts>,
+    /// Factor applied to received bytes (1.0 if no scaling was needed)
+    pub rx_scale: f64,
+    /// Factor applied to transmitted bytes (1.0 if no scaling was needed)
+    pub tx_scale: f64,
+}
+
+impl Reconciliation {
+    /// Whether any direction had to be scaled down.
+    pub fn was_scaled(&self) -> bool {
+        self.rx_scale < 1.0 || self.tx_scale < 1.0
+    }
+}
+
+/// Reconciles per-process deltas for one interval against the system-wide interface delta.
+///
+/// For each direction, if the per-process sum exceeds the host delta by more than `tolerance` (a fraction, e.g. 0.05
+/// for 5%), every process's delta is scaled so that the sum matches the host delta. Sums within the tolerance are
+/// left untouched, since interface and flow counters are never sampled at exactly the same instant.
+pub fn reconcile(
+    per_pid: &HashMap<u32, ByteCounts>,
+    host: ByteCounts,
+    tolerance: f64,
+) -> Reconciliation {
+    let (sum_rx, sum_tx) = per_pid.values().fold((0u64, 0u64), |(rx, tx), counts| {
+        (rx.saturating_add(counts.rx), tx.saturating_add(counts.tx))
+    });
+
+    let scale_for = |sum: u64, host: u64| {
+        if sum as f64 > host as f64 * (1.0 + tolerance.max(0.0)) {
+            host as f64 / sum as f64
+        } else {
+            1.0
+        }
+    };
+    let rx_scale = scale_for(sum_rx, host.rx);
+    let tx_scale = scale_for(sum_tx, host.tx);
+
+    let scale = |value: u64, factor: f64| {
+        if factor < 1.0 {
+            (value as f64 * factor).floor() as u64
+        } else {
+            value
+        }
+    };
+    let deltas = per_pid
+        .iter()
+        .map(|(&pid, counts)| {
+            (pid, ByteCounts { rx: scale(counts.rx, rx_scale), tx: scale(counts.tx, tx_scale) })
+        })
+        .collect();
+
+    Reconciliation { deltas, rx_scale, tx_scale }
+}
+
+/// Tracks per-process network bytes from flow events, reconciled against interface totals.
+///
+/// Feed flow observations with [`record`](Self::record) as they arrive and call
+/// [`end_interval`](Self::end_interval) once per sampling interval with the system-wide interface delta for that
+/// interval. The reconciled cumulative totals are the primary series exposed by [`totals`](Self::totals).
+#[derive(Debug, Clone)]
+pub struct ProcessBandwidthMonitor {
+    accumulator: FlowAccumulator,
+    /// Raw accumulated totals at the end of the previous interval
+    previous_raw: HashMap<u32, ByteCounts>,
+    /// Reconciled cumulative totals
+    totals: HashMap<u32, ByteCounts>,
+    tolerance: f64,
+}
+
+impl Default for ProcessBandwidthMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProcessBandwidthMonitor {
+    /// Creates a monitor using [`DEFAULT_TOLERANCE`].
+    pub fn new() -> Self {
+        Self::with_tolerance(DEFAULT_TOLERANCE)
+    }
+
+    /// Creates a monitor that allows per-process sums to exceed the host total by `tolerance` before scaling.
+    pub fn with_tolerance(tolerance: f64) -> Self {
+        Self {
+            accumulator: FlowAccumulator::new(),
+            previous_raw: HashMap::new(),
+            totals: HashMap::new(),
+            tolerance,
+        }
+    }
+
+    /// Records a flow observation.
+    pub fn record(&mut self, event: FlowEvent) {
+        self.accumulator.apply(event);
+    }
+
+    /// Closes the current interval and reconciles it against the host interface delta.
+    ///
+    /// # Returns
+    ///
+    /// * `Reconciliation` - The reconciled per-process deltas for this interval
+    pub fn end_interval(&mut self, host_delta: ByteCounts) -> Reconciliation {
+        let raw_deltas: HashMap<u32, ByteCounts> = self
+            .accumulator
+            .totals()
+            .iter()
+            .map(|(&pid, current)| {
+                let previous = self.previous_raw.get(&pid).copied().unwrap_or_default();
+                (pid, current.delta_since(&previous))
+            })
+            .filter(|(_, delta)| *delta != ByteCounts::default())
+            .collect();
+
+        let reconciliation = reconcile(&raw_deltas, host_delta, self.tolerance);
+        if reconciliation.was_scaled() {
+            tracing::warn!(
+                "Per-process network bytes exceeded the interface total; scaled rx by {:.3} and tx by {:.3}",
+                reconciliation.rx_scale,
+                reconciliation.tx_scale
+            );
+        }
+
+        for (pid, delta) in &reconciliation.deltas {
+            *self.totals.entry(*pid).or_default() += *delta;
+        }
+        self.previous_raw = self.accumulator.totals().clone();
+
+        reconciliation
+    }
+
+    /// Reconciled cumulative byte counts per process.
+    pub fn totals(&self) -> &HashMap<u32, ByteCounts> {
+        &self.totals
+    }
+
+    /// Reconciled cumulative byte counts of a single process.
+    pub fn totals_for(&self, pid: u32) -> Option<ByteCounts> {
+        self.totals.get(&pid).copied()
+    }
+
+    /// Forgets processes for which `keep` returns false, e.g. processes that have exited.
+    pub fn retain_pids<F: FnMut(u32) -> bool>(&mut self, mut keep: F) {
+        self.accumulator.retain_pids(&mut keep);
+        self.previous_raw.retain(|pid, _| keep(*pid));
+        self.totals.retain(|pid, _| keep(*pid));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(flow: FlowId, pid: u32, rx: u64, tx: u64) -> FlowEvent {
+        FlowEvent::Update { flow, pid, counts: ByteCounts::new(rx, tx) }
+    }
+
+    fn closed(flow: FlowId, pid: u32, rx: u64, tx: u64) -> FlowEvent {
+        FlowEvent::Closed { flow, pid, counts: ByteCounts::new(rx, tx) }
+    }
+
+    #[test]
+    fn test_accumulates_deltas_of_open_flows() {
+        let mut acc = FlowAccumulator::new();
+        acc.apply_all([update(1, 100, 10, 5), update(1, 100, 30, 15), update(2, 100, 7, 3)]);
+
+        assert_eq!(acc.totals()[&100], ByteCounts::new(37, 18));
+        assert_eq!(acc.open_flows(), 2);
+    }
+
+    #[test]
+    fn test_closed_flows_survive_churn() {
+        let mut acc = FlowAccumulator::new();
+        // A flow that opens and closes between samples is still counted
+        acc.apply_all([update(1, 100, 10, 10), closed(1, 100, 50, 20), closed(2, 200, 5, 5)]);
+
+        assert_eq!(acc.totals()[&100], ByteCounts::new(50, 20));
+        assert_eq!(acc.totals()[&200], ByteCounts::new(5, 5));
+        assert_eq!(acc.open_flows(), 0);
+    }
+
+    #[test]
+    fn test_reopened_flow_starts_from_zero() {
+        let mut acc = FlowAccumulator::new();
+        acc.apply_all([update(1, 100, 40, 40), closed(1, 100, 50, 50), update(1, 100, 8, 2)]);
+
+        assert_eq!(acc.totals()[&100], ByteCounts::new(58, 52));
+    }
+
+    #[test]
+    fn test_reused_flow_id_for_other_process() {
+        let mut acc = FlowAccumulator::new();
+        acc.apply_all([update(1, 100, 40, 40), update(1, 200, 10, 10)]);
+
+        assert_eq!(acc.totals()[&100], ByteCounts::new(40, 40));
+        assert_eq!(acc.totals()[&200], ByteCounts::new(10, 10));
+    }
+
+    #[test]
+    fn test_counter_reset() {
+        let mut acc = FlowAccumulator::new();
+        // The counters go backwards, so the new value is the traffic since the reset
+        acc.apply_all([update(1, 100, 1000, 500), update(1, 100, 20, 10), update(1, 100, 25, 10)]);
+
+        assert_eq!(acc.totals()[&100], ByteCounts::new(1025, 510));
+    }
+
+    #[test]
+    fn test_reconcile_within_tolerance() {
+        let per_pid = HashMap::from([(1, ByteCounts::new(52, 10)), (2, ByteCounts::new(50, 10))]);
+        let result = reconcile(&per_pid, ByteCounts::new(100, 100), 0.05);
+
+        assert!(!result.was_scaled());
+        assert_eq!(result.deltas, per_pid);
+    }
+
+    #[test]
+    fn test_reconcile_scales_double_counted_flows() {
+        let per_pid = HashMap::from([(1, ByteCounts::new(300, 10)), (2, ByteCounts::new(100, 10))]);
+        let result = reconcile(&per_pid, ByteCounts::new(200, 100), 0.05);
+
+        assert!(result.was_scaled());
+        assert_eq!(result.rx_scale, 0.5);
+        assert_eq!(result.tx_scale, 1.0);
+        assert_eq!(result.deltas[&1], ByteCounts::new(150, 10));
+        assert_eq!(result.deltas[&2], ByteCounts::new(50, 10));
+
+        let sum_rx: u64 = result.deltas.values().map(|c| c.rx).sum();
+        assert!(sum_rx <= 200);
+    }
+
+    #[test]
+    fn test_reconcile_with_idle_host() {
+        let per_pid = HashMap::from([(1, ByteCounts::new(10, 10))]);
+        let result = reconcile(&per_pid, ByteCounts::default(), 0.05);
+
+        assert_eq!(result.deltas[&1], ByteCounts::default());
+    }
+
+    #[test]
+    fn test_monitor_intervals() {
+        let mut monitor = ProcessBandwidthMonitor::new();
+
+        monitor.record(update(1, 100, 100, 50));
+        monitor.record(closed(2, 200, 100, 50));
+        let first = monitor.end_interval(ByteCounts::new(1000, 1000));
+        assert!(!first.was_scaled());
+        assert_eq!(monitor.totals_for(100), Some(ByteCounts::new(100, 50)));
+        assert_eq!(monitor.totals_for(200), Some(ByteCounts::new(100, 50)));
+
+        // Second interval: 200 bytes attributed but only 100 crossed the interface
+        monitor.record(update(1, 100, 300, 50));
+        let second = monitor.end_interval(ByteCounts::new(100, 0));
+        assert!(second.was_scaled());
+        assert_eq!(second.deltas[&100], ByteCounts::new(100, 0));
+        assert_eq!(monitor.totals_for(100), Some(ByteCounts::new(200, 50)));
+
+        monitor.retain_pids(|pid| pid != 200);
+        assert_eq!(monitor.totals_for(200), None);
+    }
+}
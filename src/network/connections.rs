@@ -0,0 +1,302 @@
+//! Which process is talking to which host
+//!
+//! The sockets of a process are found by listing its descriptors with `proc_pidinfo(PROC_PIDLISTFDS)` and reading
+//! each socket with `proc_pidfdinfo(PROC_PIDFDSOCKETINFO)`, the way `lsof -i` does. This shows the endpoints, the TCP
+//! state and the bytes waiting in the socket buffers, but not the bytes transferred over the life of a connection;
+//! those are only counted by the private network statistics interface that `nettop` uses.
+//!
+//! Without root, only the processes of the caller's user can be inspected. [`ProcessNetworkMonitor::all_connections`]
+//! skips the other processes.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use super::tcp::TcpState;
+use crate::{
+    error::{Error, Result},
+    process::{process_fds, FdType},
+    utils::bindings::{
+        list_kinfo_procs, proc_bsd_short_info, proc_fd_socket_info, socket_fdinfo, INI_IPV4,
+        SOCKINFO_IN, SOCKINFO_TCP,
+    },
+};
+
+/// Transport protocol of a socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SocketProtocol {
+    /// TCP
+    Tcp,
+    /// UDP
+    Udp,
+}
+
+/// An IPv4 or IPv6 socket of a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketConnection {
+    /// Process owning the socket
+    pub pid: u32,
+    /// Descriptor number of the socket in the process
+    pub fd: i32,
+    /// Transport protocol
+    pub protocol: SocketProtocol,
+    /// Local address and port; the address is unspecified for a socket bound to every interface
+    pub local: SocketAddr,
+    /// Remote address and port, None for a listening or unconnected socket
+    pub remote: Option<SocketAddr>,
+    /// State of a TCP connection, None for UDP
+    pub state: Option<TcpState>,
+    /// Bytes received and not read by the process yet
+    pub receive_queue: u32,
+    /// Bytes written by the process and not sent or acknowledged yet
+    pub send_queue: u32,
+}
+
+/// Lists the network connections of processes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessNetworkMonitor;
+
+impl ProcessNetworkMonitor {
+    /// Creates a new monitor.
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// The TCP and UDP sockets of the process `pid`.
+    ///
+    /// A process of another user has no connections unless the caller is root.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the process doesn't exist.
+    pub fn connections(&self, pid: u32) -> Result<Vec<SocketConnection>> {
+        let Some(fds) = process_fds(pid) else {
+            // The short BSD info is readable for processes of other users, so it tells a missing process from one the
+            // caller may not inspect
+            proc_bsd_short_info(pid)
+                .map_err(|e| Error::process_error(format!("Failed to get process info: {}", e)))?;
+            return Ok(Vec::new());
+        };
+
+        Ok(socket_connections(pid, fds))
+    }
+
+    /// The TCP and UDP sockets of every process the caller may inspect.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the processes can't be listed.
+    pub fn all_connections(&self) -> Result<Vec<SocketConnection>> {
+        Ok(list_kinfo_procs()?
+            .iter()
+            .filter(|proc_info| proc_info.pid() > 0)
+            .filter_map(|proc_info| {
+                let pid = proc_info.pid() as u32;
+                // Processes that exited or belong to another user are skipped
+                process_fds(pid).map(|fds| socket_connections(pid, fds))
+            })
+            .flatten()
+            .collect())
+    }
+}
+
+/// Reads the IP sockets among the descriptors of a process.
+fn socket_connections(pid: u32, fds: Vec<(i32, FdType)>) -> Vec<SocketConnection> {
+    fds.into_iter()
+        .filter(|&(_, fd_type)| fd_type == FdType::Socket)
+        .filter_map(|(fd, _)| {
+            // The descriptor may have been closed since it was listed
+            let info = proc_fd_socket_info(pid, fd)?;
+            connection_from_info(pid, fd, &info)
+        })
+        .collect()
+}
+
+/// Describes a socket, or None if it isn't a TCP or UDP socket.
+fn connection_from_info(pid: u32, fd: i32, info: &socket_fdinfo) -> Option<SocketConnection> {
+    let socket = &info.psi;
+    if socket.soi_family != libc::AF_INET && socket.soi_family != libc::AF_INET6 {
+        return None;
+    }
+
+    let (protocol, endpoints, state) = match (socket.soi_kind, socket.soi_protocol) {
+        (SOCKINFO_TCP, _) => {
+            // SAFETY: the kernel fills in `pri_tcp` for sockets of this kind
+            let tcp = unsafe { socket.soi_proto.pri_tcp };
+            (SocketProtocol::Tcp, tcp.tcpsi_ini, TcpState::from_raw(tcp.tcpsi_state))
+        },
+        (SOCKINFO_IN, libc::IPPROTO_UDP) => {
+            // SAFETY: the kernel fills in `pri_in` for sockets of this kind
+            (SocketProtocol::Udp, unsafe { socket.soi_proto.pri_in }, None)
+        },
+        _ => return None,
+    };
+
+    let local = socket_addr(endpoints.insi_vflag, &endpoints.insi_laddr, endpoints.insi_lport);
+    let remote = socket_addr(endpoints.insi_vflag, &endpoints.insi_faddr, endpoints.insi_fport);
+    Some(SocketConnection {
+        pid,
+        fd,
+        protocol,
+        local,
+        remote: (!remote.ip().is_unspecified() || remote.port() != 0).then_some(remote),
+        state,
+        receive_queue: socket.soi_rcv.sbi_cc,
+        send_queue: socket.soi_snd.sbi_cc,
+    })
+}
+
+/// Converts an address and port of an `in_sockinfo`.
+///
+/// An IPv4 address is stored in the last 4 bytes, also in the IPv6 sockets that carry IPv4 traffic. The port is in
+/// network byte order.
+fn socket_addr(vflag: u8, addr: &[u8; 16], port: i32) -> SocketAddr {
+    let ip = if vflag & INI_IPV4 != 0 {
+        IpAddr::V4(Ipv4Addr::new(addr[12], addr[13], addr[14], addr[15]))
+    } else {
+        IpAddr::V6(Ipv6Addr::from(*addr))
+    };
+    SocketAddr::new(ip, u16::from_be(port as u16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::bindings::{in_sockinfo, tcp_sockinfo, INI_IPV6};
+
+    const HTTPS: i32 = 443u16.to_be() as i32;
+    const EPHEMERAL: i32 = 51234u16.to_be() as i32;
+
+    /// `2001:db8::1`, `fe80::1c2a:3ff:fe4b:5d6e` and `::ffff:192.0.2.7` as the kernel stores them
+    const DOCUMENTATION_V6: [u8; 16] =
+        [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01];
+    const LINK_LOCAL_V6: [u8; 16] =
+        [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0x1c, 0x2a, 0x03, 0xff, 0xfe, 0x4b, 0x5d, 0x6e];
+    const MAPPED_V4: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 192, 0, 2, 7];
+
+    fn socket(
+        family: i32,
+        kind: i32,
+        protocol: i32,
+        endpoints: in_sockinfo,
+        tcp_state: i32,
+    ) -> socket_fdinfo {
+        // SAFETY: the structure is plain integers and bytes, for which zero is valid
+        let mut info: socket_fdinfo = unsafe { std::mem::zeroed() };
+        info.psi.soi_family = family;
+        info.psi.soi_kind = kind;
+        info.psi.soi_protocol = protocol;
+        info.psi.soi_rcv.sbi_cc = 120;
+        info.psi.soi_snd.sbi_cc = 4096;
+        if kind == SOCKINFO_TCP {
+            info.psi.soi_proto.pri_tcp =
+                tcp_sockinfo { tcpsi_ini: endpoints, tcpsi_state: tcp_state, ..Default::default() };
+        } else {
+            info.psi.soi_proto.pri_in = endpoints;
+        }
+        info
+    }
+
+    fn endpoints(vflag: u8, local: ([u8; 16], i32), remote: ([u8; 16], i32)) -> in_sockinfo {
+        in_sockinfo {
+            insi_vflag: vflag,
+            insi_laddr: local.0,
+            insi_lport: local.1,
+            insi_faddr: remote.0,
+            insi_fport: remote.1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ipv6_addresses() {
+        assert_eq!(
+            socket_addr(INI_IPV6, &DOCUMENTATION_V6, HTTPS),
+            "[2001:db8::1]:443".parse().unwrap()
+        );
+        assert_eq!(
+            socket_addr(INI_IPV6, &LINK_LOCAL_V6, EPHEMERAL),
+            "[fe80::1c2a:3ff:fe4b:5d6e]:51234".parse().unwrap()
+        );
+        assert_eq!(socket_addr(INI_IPV6, &[0; 16], 0), "[::]:0".parse().unwrap());
+        assert_eq!(
+            socket_addr(INI_IPV6, &MAPPED_V4, HTTPS),
+            "[::ffff:192.0.2.7]:443".parse().unwrap(),
+            "Without the IPv4 flag the mapped address stays IPv6"
+        );
+        assert_eq!(
+            socket_addr(INI_IPV4, &MAPPED_V4, HTTPS),
+            "192.0.2.7:443".parse().unwrap(),
+            "IPv4 traffic of a dual-stack socket"
+        );
+    }
+
+    #[test]
+    fn test_tcp_v6_connection() {
+        let info = socket(
+            libc::AF_INET6,
+            SOCKINFO_TCP,
+            libc::IPPROTO_TCP,
+            endpoints(INI_IPV6, (LINK_LOCAL_V6, EPHEMERAL), (DOCUMENTATION_V6, HTTPS)),
+            4,
+        );
+
+        let connection = connection_from_info(42, 7, &info).unwrap();
+        assert_eq!(
+            connection,
+            SocketConnection {
+                pid: 42,
+                fd: 7,
+                protocol: SocketProtocol::Tcp,
+                local: "[fe80::1c2a:3ff:fe4b:5d6e]:51234".parse().unwrap(),
+                remote: Some("[2001:db8::1]:443".parse().unwrap()),
+                state: Some(TcpState::Established),
+                receive_queue: 120,
+                send_queue: 4096,
+            }
+        );
+    }
+
+    #[test]
+    fn test_listening_and_udp_sockets() {
+        let listening = socket(
+            libc::AF_INET6,
+            SOCKINFO_TCP,
+            libc::IPPROTO_TCP,
+            endpoints(INI_IPV4 | INI_IPV6, ([0; 16], HTTPS), ([0; 16], 0)),
+            1,
+        );
+        let connection = connection_from_info(1, 3, &listening).unwrap();
+        assert_eq!(connection.local, "0.0.0.0:443".parse().unwrap());
+        assert_eq!(connection.remote, None);
+        assert_eq!(connection.state, Some(TcpState::Listen));
+
+        let mut v4_addr = [0; 16];
+        v4_addr[12..].copy_from_slice(&[127, 0, 0, 1]);
+        let udp = socket(
+            libc::AF_INET,
+            SOCKINFO_IN,
+            libc::IPPROTO_UDP,
+            endpoints(INI_IPV4, (v4_addr, 53u16.to_be() as i32), ([0; 16], 0)),
+            0,
+        );
+        let connection = connection_from_info(1, 4, &udp).unwrap();
+        assert_eq!(connection.protocol, SocketProtocol::Udp);
+        assert_eq!(connection.local, "127.0.0.1:53".parse().unwrap());
+        assert_eq!(connection.state, None);
+    }
+
+    #[test]
+    fn test_other_sockets_are_skipped() {
+        let unix = socket(libc::AF_UNIX, 3, 0, in_sockinfo::default(), 0);
+        assert_eq!(connection_from_info(1, 3, &unix), None);
+
+        let raw = socket(libc::AF_INET, SOCKINFO_IN, libc::IPPROTO_ICMP, in_sockinfo::default(), 0);
+        assert_eq!(connection_from_info(1, 4, &raw), None);
+    }
+
+    #[test]
+    fn test_connections_of_missing_process() {
+        let monitor = ProcessNetworkMonitor::new();
+        assert!(monitor.connections(u32::MAX >> 1).is_err());
+        assert!(monitor.connections(std::process::id()).is_ok());
+    }
+}
@@ -3,12 +3,12 @@ use std::{
     ffi::CStr,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     ptr,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use crate::{
     error::{Error, Result},
-    network::{traffic::TrafficTracker, NetworkMetrics},
+    network::{link::read_links, traffic::TrafficTracker, NetworkMetrics},
     utils::bindings::{
         address_family, freeifaddrs, getifaddrs, if_flags, ifaddrs, sockaddr_dl, sockaddr_in,
         sockaddr_in6,
@@ -16,11 +16,13 @@ use crate::{
 };
 
 // Type aliases to reduce clippy::type_complexity warnings
-type NetworkAddressMap = HashMap<String, (u32, Option<String>, Vec<IpAddr>)>;
+type NetworkAddressMap =
+    HashMap<String, (u32, Option<String>, Vec<IpAddr>, HashMap<Ipv6Addr, u32>)>;
 type TrafficStatsMap = HashMap<String, (u64, u64, u64, u64, u64, u64, u64)>;
 
 /// Represents the type of network interface.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum InterfaceType {
     /// Ethernet interface
     Ethernet,
@@ -46,6 +48,23 @@ impl std::fmt::Display for InterfaceType {
     }
 }
 
+/// Stable identity of a network interface across updates.
+///
+/// macOS can renumber an interface when it is unplugged and replugged (a USB
+/// Ethernet adapter coming back as `en7` instead of `en5`), and can hand a
+/// freed name to a different device. Traffic history follows the composite of
+/// name and MAC address: an exact match continues the history, a known MAC
+/// under a new name is treated as the same device renamed, and a known name
+/// with a new MAC is treated as a different device.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InterfaceIdentity {
+    /// Name of the interface (e.g., "en0")
+    pub name: String,
+
+    /// MAC address, if the interface has one
+    pub mac_address: Option<String>,
+}
+
 /// Represents a network interface with its associated metrics and properties.
 ///
 /// This struct encapsulates all information about a single network interface on
@@ -62,6 +81,7 @@ impl std::fmt::Display for InterfaceType {
 ///
 /// The interface metrics are updated via the NetworkManager's update() method.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Interface {
     /// Name of the interface (e.g., "en0", "lo0")
     name: String,
@@ -78,11 +98,22 @@ pub struct Interface {
     /// IP addresses associated with this interface (both IPv4 and IPv6)
     addresses: Vec<IpAddr>,
 
+    /// Scope IDs of the scoped IPv6 addresses (link-local and interface-local)
+    scope_ids: HashMap<Ipv6Addr, u32>,
+
+    /// Maximum transmission unit in bytes, 0 if unknown
+    mtu: u32,
+
     /// Traffic statistics tracker for monitoring network activity
     traffic: TrafficTracker,
 
     /// Timestamp of the last update for calculating rates
+    #[cfg_attr(feature = "serde", serde(skip, default = "Instant::now"))]
     last_update: Instant,
+
+    /// When the interface was last observed transitioning to RUNNING
+    #[cfg_attr(feature = "serde", serde(skip))]
+    running_since: Option<Instant>,
 }
 
 impl Interface {
@@ -102,12 +133,16 @@ impl Interface {
         send_errors: u64,
         collisions: u64,
     ) -> Self {
+        let now = Instant::now();
         Self {
+            running_since: ((flags & if_flags::IFF_RUNNING) != 0).then_some(now),
             name,
             interface_type,
             flags,
             mac_address,
             addresses,
+            scope_ids: HashMap::new(),
+            mtu: 0,
             traffic: TrafficTracker::new(
                 bytes_received,
                 bytes_sent,
@@ -117,7 +152,7 @@ impl Interface {
                 send_errors,
                 collisions,
             ),
-            last_update: Instant::now(),
+            last_update: now,
         }
     }
 
@@ -136,6 +171,31 @@ impl Interface {
         self.mac_address.as_deref()
     }
 
+    /// Get the MAC address of this interface as bytes, if available
+    pub fn mac_address_bytes(&self) -> Option<[u8; 6]> {
+        parse_mac(self.mac_address.as_deref()?)
+    }
+
+    /// Get the stable identity (name and MAC address) of this interface
+    pub fn identity(&self) -> InterfaceIdentity {
+        InterfaceIdentity { name: self.name.clone(), mac_address: self.mac_address.clone() }
+    }
+
+    /// Get the traffic statistics tracker of this interface
+    pub fn traffic(&self) -> &TrafficTracker {
+        &self.traffic
+    }
+
+    /// Get how long the link has been up.
+    ///
+    /// Measured from the last observed transition to RUNNING, or from when
+    /// monitoring began if the interface was already running then. A counter
+    /// reset counts as a new link. Returns `None` while the interface is not
+    /// running.
+    pub fn link_uptime(&self) -> Option<Duration> {
+        self.running_since.map(|since| since.elapsed())
+    }
+
     /// Get the IP addresses associated with this interface
     pub fn addresses(&self) -> Option<&[IpAddr]> {
         if self.addresses.is_empty() {
@@ -145,6 +205,20 @@ impl Interface {
         }
     }
 
+    /// Get the scope ID of one of this interface's IPv6 addresses.
+    ///
+    /// Link-local addresses are only unique together with their scope, the
+    /// index of the interface they're on (`fe80::1%en0`). Returns `None` for
+    /// global addresses and addresses of other interfaces.
+    pub fn scope_id(&self, address: &Ipv6Addr) -> Option<u32> {
+        self.scope_ids.get(address).copied()
+    }
+
+    /// Get the maximum transmission unit in bytes, 0 if unknown
+    pub fn mtu(&self) -> u32 {
+        self.mtu
+    }
+
     /// Updates the traffic statistics for this interface.
     #[allow(clippy::too_many_arguments)]
     pub fn update_traffic(
@@ -169,6 +243,22 @@ impl Interface {
         self.last_update = Instant::now();
     }
 
+    /// Continues the traffic history and link state of `previous`, the same
+    /// interface as seen by an earlier update.
+    fn continue_from(&mut self, previous: Interface) {
+        let running = self.is_flag_set(if_flags::IFF_RUNNING);
+        let was_running = previous.is_flag_set(if_flags::IFF_RUNNING);
+
+        let latest = *self.traffic.latest();
+        self.traffic = previous.traffic;
+        self.traffic.record(latest);
+
+        self.running_since = match previous.running_since {
+            Some(since) if running && was_running && !self.traffic.is_reset() => Some(since),
+            _ => running.then_some(latest.timestamp),
+        };
+    }
+
     /// Determines if the interface is active based on its flags.
     fn is_flag_set(&self, flag: u32) -> bool {
         (self.flags & flag) == flag
@@ -194,6 +284,14 @@ impl Interface {
         self.traffic.send_error_rate()
     }
 
+    /// Gets whether the interface is up and its link is running.
+    ///
+    /// An interface configured up (`IFF_UP`) without a running link, such as
+    /// Ethernet without a cable, isn't.
+    pub fn is_up(&self) -> bool {
+        self.is_flag_set(if_flags::IFF_UP) && self.is_flag_set(if_flags::IFF_RUNNING)
+    }
+
     /// Gets whether this is a loopback interface.
     pub fn is_loopback(&self) -> bool {
         self.is_flag_set(if_flags::IFF_LOOPBACK)
@@ -314,8 +412,9 @@ impl NetworkManager {
     ///
     /// This method:
     /// 1. Retrieves the current state of all network interfaces
-    /// 2. Updates traffic statistics for existing interfaces
-    /// 3. Adds any new interfaces that were discovered
+    /// 2. Updates traffic statistics for existing interfaces, matched by name
+    ///    and MAC address (see [`InterfaceIdentity`])
+    /// 3. Adds any new interfaces that were discovered and drops vanished ones
     /// 4. Calculates current upload/download speeds based on changes since last
     ///    update
     ///
@@ -324,13 +423,54 @@ impl NetworkManager {
     pub fn update(&mut self) -> Result<()> {
         // Get network interfaces list
         let interfaces = self.get_interfaces()?;
+        self.merge_interfaces(interfaces);
+
+        Ok(())
+    }
 
-        for interface in interfaces {
-            let name = interface.name().to_string();
-            self.interfaces.insert(name, interface);
+    /// Replaces the known interfaces with a fresh reading, carrying traffic
+    /// history over by [`InterfaceIdentity`].
+    ///
+    /// Each fresh interface continues the history of the previous interface
+    /// with the same name and MAC address. Failing that, an interface with a
+    /// known MAC address continues the history of a vanished interface with
+    /// that MAC (the device was renamed). Anything else starts fresh.
+    /// Interfaces missing from the reading are dropped.
+    pub(crate) fn merge_interfaces(&mut self, fresh: Vec<Interface>) {
+        let mut previous: HashMap<InterfaceIdentity, Interface> = self
+            .interfaces
+            .drain()
+            .map(|(_, interface)| (interface.identity(), interface))
+            .collect();
+        let fresh_names: HashSet<String> =
+            fresh.iter().map(|interface| interface.name.clone()).collect();
+
+        let mut unmatched = Vec::new();
+        for mut interface in fresh {
+            match previous.remove(&interface.identity()) {
+                Some(known) => {
+                    interface.continue_from(known);
+                    self.interfaces.insert(interface.name.clone(), interface);
+                },
+                None => unmatched.push(interface),
+            }
         }
 
-        Ok(())
+        for mut interface in unmatched {
+            let renamed = interface.mac_address.as_ref().and_then(|mac| {
+                previous
+                    .keys()
+                    .find(|identity| {
+                        identity.mac_address.as_ref() == Some(mac)
+                            && !fresh_names.contains(&identity.name)
+                    })
+                    .cloned()
+            });
+            if let Some(known) = renamed.and_then(|identity| previous.remove(&identity)) {
+                interface.continue_from(known);
+            }
+            self.interfaces.insert(interface.name.clone(), interface);
+        }
     }
 
     /// Gets all discovered network interfaces.
@@ -376,7 +516,7 @@ impl NetworkManager {
         // Process all addresses
         for (name, data) in addresses {
             // If this interface is already in the map, update it
-            let (flags, mac_addr, ip_addrs) = data;
+            let (flags, mac_addr, ip_addrs, scope_ids) = data;
 
             if let Some(existing) = interface_map.get_mut(&name) {
                 // Only update mac_address if it's currently None and new address exists
@@ -390,6 +530,7 @@ impl NetworkManager {
                         existing.addresses.push(addr);
                     }
                 }
+                existing.scope_ids.extend(scope_ids);
             } else {
                 // Create new interface
                 let interface_type = Self::determine_interface_type(&name, flags);
@@ -409,10 +550,37 @@ impl NetworkManager {
                     0, // Initial traffic stats are 0
                 );
 
-                interface_map.insert(name, interface);
+                interface_map.insert(name, Interface { scope_ids, ..interface });
             }
         }
 
+        // The routing table lists every interface with its MTU, including
+        // those without any address
+        match read_links() {
+            Ok(links) => {
+                for (name, link) in links {
+                    let interface = interface_map.entry(name.clone()).or_insert_with(|| {
+                        Interface::new(
+                            name.clone(),
+                            Self::determine_interface_type(&name, link.flags),
+                            link.flags,
+                            link.mac_address.as_ref().map(format_mac),
+                            Vec::new(),
+                            0,
+                            0,
+                            0,
+                            0,
+                            0,
+                            0,
+                            0,
+                        )
+                    });
+                    interface.mtu = link.mtu;
+                }
+            },
+            Err(e) => tracing::debug!("Failed to read the interface MTUs: {}", e),
+        }
+
         // Get existing traffic data
         let existing_traffic = self.update_traffic_stats();
 
@@ -424,8 +592,9 @@ impl NetworkManager {
             ) in traffic_data
             {
                 if let Some(interface) = interface_map.get_mut(&name) {
-                    // Update with real traffic stats
-                    interface.update_traffic(
+                    // Start from the real traffic stats; rates come from merging
+                    // with the previous reading of this interface
+                    interface.traffic = TrafficTracker::new(
                         rx_bytes, tx_bytes, rx_packets, tx_packets, rx_errors, tx_errors,
                         collisions,
                     );
@@ -503,7 +672,9 @@ impl NetworkManager {
                 }
 
                 // Get or create entry in result map
-                let entry = result.entry(name).or_insert_with(|| (ifa.ifa_flags, None, Vec::new()));
+                let entry = result
+                    .entry(name)
+                    .or_insert_with(|| (ifa.ifa_flags, None, Vec::new(), HashMap::new()));
 
                 // Process address if available
                 if !ifa.ifa_addr.is_null() {
@@ -519,8 +690,12 @@ impl NetworkManager {
                         family if family == address_family::AF_INET6 => {
                             // IPv6 address
                             let addr_in6 = &*(ifa.ifa_addr as *mut sockaddr_in6);
-                            let ip = Ipv6Addr::from(addr_in6.sin6_addr.s6_addr);
+                            let (ip, scope_id) =
+                                split_scope(addr_in6.sin6_addr.s6_addr, addr_in6.sin6_scope_id);
                             entry.2.push(IpAddr::V6(ip));
+                            if let Some(scope_id) = scope_id {
+                                entry.3.insert(ip, scope_id);
+                            }
                         },
                         family if family == address_family::AF_LINK => {
                             // MAC address
@@ -534,18 +709,11 @@ impl NetworkManager {
                                 if offset + mac_len <= addr_dl.sdl_data.len() {
                                     let mac_bytes = &addr_dl.sdl_data[offset..offset + mac_len];
 
-                                    // Format MAC address
-                                    let mac = format!(
-                                        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-                                        mac_bytes[0] as u8,
-                                        mac_bytes[1] as u8,
-                                        mac_bytes[2] as u8,
-                                        mac_bytes[3] as u8,
-                                        mac_bytes[4] as u8,
-                                        mac_bytes[5] as u8
-                                    );
-
-                                    entry.1 = Some(mac);
+                                    let mut mac = [0u8; 6];
+                                    for (byte, &c) in mac.iter_mut().zip(mac_bytes) {
+                                        *byte = c as u8;
+                                    }
+                                    entry.1 = Some(format_mac(&mac));
                                 }
                             }
                         },
@@ -565,7 +733,7 @@ impl NetworkManager {
     /// This method tries two approaches in order:
     /// 1. Use sysctlbyname with 64-bit interface data (modern approach)
     /// 2. Fall back to netstat command-line tool if API approach fails
-    fn update_traffic_stats(&self) -> Option<TrafficStatsMap> {
+    pub(crate) fn update_traffic_stats(&self) -> Option<TrafficStatsMap> {
         // First try the native implementation using sysctlbyname
         if let Some(result) = self.update_traffic_stats_native() {
             return Some(result);
@@ -776,10 +944,7 @@ impl NetworkManager {
         .map_err(|e| Error::Network(format!("Task join error: {}", e)))??;
 
         // Update our interfaces with the results from the blocking task
-        for interface in interfaces {
-            let name = interface.name().to_string();
-            self.interfaces.insert(name, interface);
-        }
+        self.merge_interfaces(interfaces);
 
         Ok(())
     }
@@ -813,6 +978,48 @@ impl NetworkManager {
     }
 }
 
+/// Formats a MAC address as xx:xx:xx:xx:xx:xx.
+fn format_mac(mac: &[u8; 6]) -> String {
+    format!(
+        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    )
+}
+
+/// Parses a MAC address formatted as xx:xx:xx:xx:xx:xx.
+fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let mut bytes = [0u8; 6];
+    let mut parts = mac.split(':');
+    for byte in &mut bytes {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    parts.next().is_none().then_some(bytes)
+}
+
+/// Splits the scope ID off an IPv6 address from getifaddrs().
+///
+/// The macOS kernel embeds the scope of link-local and interface-local
+/// addresses in their second 16-bit word (`fe80:4::1` for `fe80::1%4`), which
+/// getifaddrs() passes on with a zero `sin6_scope_id`. The embedded scope is
+/// cleared from the address and returned instead.
+fn split_scope(octets: [u8; 16], scope_id: u32) -> (Ipv6Addr, Option<u32>) {
+    let mut octets = octets;
+    let mut scope_id = (scope_id != 0).then_some(scope_id);
+
+    let link_local = octets[0] == 0xfe && octets[1] & 0xc0 == 0x80;
+    let scoped_multicast = octets[0] == 0xff && matches!(octets[1] & 0x0f, 0x1 | 0x2);
+    if link_local || scoped_multicast {
+        let embedded = u16::from_be_bytes([octets[2], octets[3]]);
+        if embedded != 0 {
+            scope_id = scope_id.or(Some(u32::from(embedded)));
+            octets[2] = 0;
+            octets[3] = 0;
+        }
+    }
+
+    (Ipv6Addr::from(octets), scope_id)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1138,4 +1345,229 @@ mod tests {
         assert!(download > 0.0);
         assert!(upload > 0.0);
     }
+
+    const MAC_A: &str = "00:11:22:33:44:55";
+    const MAC_B: &str = "66:77:88:99:aa:bb";
+
+    fn reading(name: &str, mac: Option<&str>, running: bool, bytes: u64) -> Interface {
+        let flags =
+            if running { if_flags::IFF_UP | if_flags::IFF_RUNNING } else { if_flags::IFF_UP };
+        Interface::new(
+            name.to_string(),
+            InterfaceType::Ethernet,
+            flags,
+            mac.map(str::to_string),
+            vec![],
+            bytes,
+            bytes,
+            bytes / 100,
+            bytes / 100,
+            0,
+            0,
+            0,
+        )
+    }
+
+    fn assert_rates_non_negative(manager: &NetworkManager) {
+        for interface in manager.interfaces() {
+            for rate in [
+                interface.download_speed(),
+                interface.upload_speed(),
+                interface.packet_receive_rate(),
+                interface.packet_send_rate(),
+            ] {
+                assert!(rate.is_finite() && rate >= 0.0, "{} gave {rate}", interface.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_same_mac_new_name() {
+        let mut manager = NetworkManager { interfaces: HashMap::new() };
+        manager.merge_interfaces(vec![reading("en5", Some(MAC_A), true, 1_000)]);
+        std::thread::sleep(Duration::from_millis(10));
+        manager.merge_interfaces(vec![reading("en5", Some(MAC_A), true, 2_000)]);
+        let en5 = manager.get_interface("en5").unwrap();
+        assert!(en5.download_speed() > 0.0);
+        let uptime = en5.link_uptime().unwrap();
+
+        // Unplugged and replugged as en7, with counters starting over
+        std::thread::sleep(Duration::from_millis(10));
+        manager.merge_interfaces(vec![reading("en7", Some(MAC_A), true, 500)]);
+        assert!(manager.get_interface("en5").is_none());
+        let en7 = manager.get_interface("en7").unwrap();
+        assert!(en7.traffic().is_reset());
+        assert_eq!(en7.traffic().epoch(), 1);
+        assert_eq!(en7.download_speed(), 0.0);
+        assert!(en7.link_uptime().unwrap() < uptime);
+        assert_rates_non_negative(&manager);
+
+        // The history continues under the new name
+        std::thread::sleep(Duration::from_millis(10));
+        manager.merge_interfaces(vec![reading("en7", Some(MAC_A), true, 1_500)]);
+        let en7 = manager.get_interface("en7").unwrap();
+        assert!(!en7.traffic().is_reset());
+        assert_eq!(en7.traffic().epoch(), 1);
+        assert_eq!(en7.bytes_received(), 1_500);
+        assert!(en7.download_speed() > 0.0);
+        assert_rates_non_negative(&manager);
+    }
+
+    #[test]
+    fn test_merge_new_mac_same_name() {
+        let mut manager = NetworkManager { interfaces: HashMap::new() };
+        manager.merge_interfaces(vec![reading("en5", Some(MAC_A), true, 5_000)]);
+        std::thread::sleep(Duration::from_millis(10));
+
+        // A different adapter picked up the freed name
+        manager.merge_interfaces(vec![reading("en5", Some(MAC_B), true, 100)]);
+        let en5 = manager.get_interface("en5").unwrap();
+        assert_eq!(en5.mac_address(), Some(MAC_B));
+        assert_eq!(en5.bytes_received(), 100);
+        assert!(!en5.traffic().is_reset());
+        assert_eq!(en5.traffic().epoch(), 0);
+        assert_eq!(en5.download_speed(), 0.0);
+        assert_rates_non_negative(&manager);
+    }
+
+    #[test]
+    fn test_merge_counter_reset_without_rename() {
+        let mut manager = NetworkManager { interfaces: HashMap::new() };
+        manager.merge_interfaces(vec![reading("en0", Some(MAC_A), true, 5_000)]);
+        std::thread::sleep(Duration::from_millis(20));
+        manager.merge_interfaces(vec![reading("en0", Some(MAC_A), true, 6_000)]);
+        let uptime = manager.get_interface("en0").unwrap().link_uptime().unwrap();
+        assert!(uptime >= Duration::from_millis(20));
+
+        manager.merge_interfaces(vec![reading("en0", Some(MAC_A), true, 10)]);
+        let en0 = manager.get_interface("en0").unwrap();
+        assert!(en0.traffic().is_reset());
+        assert_eq!(en0.traffic().epoch(), 1);
+        assert_eq!(en0.bytes_received(), 10);
+        assert!(en0.link_uptime().unwrap() < uptime);
+        assert_rates_non_negative(&manager);
+    }
+
+    #[test]
+    fn test_merge_shared_mac_does_not_steal_history() {
+        // awdl0 and llw0 share a MAC address on recent macOS versions
+        let mut manager = NetworkManager { interfaces: HashMap::new() };
+        manager.merge_interfaces(vec![
+            reading("awdl0", Some(MAC_A), true, 1_000),
+            reading("llw0", Some(MAC_A), true, 9_000),
+        ]);
+        manager.merge_interfaces(vec![
+            reading("awdl0", Some(MAC_A), true, 2_000),
+            reading("llw0", Some(MAC_A), true, 10_000),
+        ]);
+
+        for name in ["awdl0", "llw0"] {
+            let interface = manager.get_interface(name).unwrap();
+            assert!(!interface.traffic().is_reset(), "{name} was flagged as reset");
+            assert_eq!(interface.traffic().epoch(), 0);
+        }
+    }
+
+    #[test]
+    fn test_link_uptime() {
+        let mut manager = NetworkManager { interfaces: HashMap::new() };
+        manager.merge_interfaces(vec![reading("en0", None, false, 0)]);
+        assert_eq!(manager.get_interface("en0").unwrap().link_uptime(), None);
+
+        manager.merge_interfaces(vec![reading("en0", None, true, 100)]);
+        let first = manager.get_interface("en0").unwrap().link_uptime().unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        // Still running: uptime keeps counting from the transition
+        manager.merge_interfaces(vec![reading("en0", None, true, 200)]);
+        let later = manager.get_interface("en0").unwrap().link_uptime().unwrap();
+        assert!(later >= first + Duration::from_millis(20));
+
+        // Link dropped, then came back
+        manager.merge_interfaces(vec![reading("en0", None, false, 200)]);
+        assert_eq!(manager.get_interface("en0").unwrap().link_uptime(), None);
+        manager.merge_interfaces(vec![reading("en0", None, true, 300)]);
+        let restarted = manager.get_interface("en0").unwrap().link_uptime().unwrap();
+        assert!(restarted < later);
+    }
+
+    #[test]
+    fn test_split_scope() {
+        // Link-local with the scope embedded by the kernel
+        let (ip, scope) =
+            split_scope("fe80:4::1c2a:3bff:fe4d:5e6f".parse::<Ipv6Addr>().unwrap().octets(), 0);
+        assert_eq!(ip, "fe80::1c2a:3bff:fe4d:5e6f".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(scope, Some(4));
+
+        // Link-local with the scope in sin6_scope_id
+        let (ip, scope) = split_scope("fe80::1".parse::<Ipv6Addr>().unwrap().octets(), 7);
+        assert_eq!(ip, "fe80::1".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(scope, Some(7));
+
+        // Global addresses are left alone
+        let global: Ipv6Addr = "2001:db8:4::1".parse().unwrap();
+        assert_eq!(split_scope(global.octets(), 0), (global, None));
+    }
+
+    #[test]
+    fn test_mac_address_bytes() {
+        assert_eq!(format_mac(&[0x00, 0x1b, 0x63, 0x84, 0x45, 0xe6]), "00:1b:63:84:45:e6");
+        assert_eq!(parse_mac("00:1b:63:84:45:e6"), Some([0x00, 0x1b, 0x63, 0x84, 0x45, 0xe6]));
+        assert_eq!(parse_mac("00:1b:63:84:45"), None);
+        assert_eq!(parse_mac("00:1b:63:84:45:e6:01"), None);
+        assert_eq!(parse_mac("zz:1b:63:84:45:e6"), None);
+
+        let interface = reading("en0", Some(MAC_A), true, 0);
+        assert_eq!(
+            interface.mac_address_bytes().map(|mac| format_mac(&mac)).as_deref(),
+            Some(MAC_A)
+        );
+        assert_eq!(reading("utun0", None, true, 0).mac_address_bytes(), None);
+    }
+
+    #[test]
+    fn test_is_up() {
+        assert!(reading("en0", None, true, 0).is_up());
+        assert!(!reading("en0", None, false, 0).is_up());
+
+        let configured_without_link = Interface::new(
+            "en1".to_string(),
+            InterfaceType::Ethernet,
+            if_flags::IFF_UP,
+            None,
+            Vec::new(),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+        assert!(!configured_without_link.is_up());
+        assert_eq!(configured_without_link.mtu(), 0);
+    }
+
+    #[test]
+    fn test_interface_details() {
+        let manager = NetworkManager::new().unwrap();
+        let loopback = manager.get_interface("lo0").expect("Every Mac has a loopback interface");
+
+        assert!(loopback.is_loopback());
+        assert!(loopback.is_up());
+        assert!(loopback.mtu() > 0);
+
+        let addresses = loopback.addresses().unwrap();
+        assert!(addresses.contains(&IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        let link_local: Ipv6Addr = "fe80::1".parse().unwrap();
+        if addresses.contains(&IpAddr::V6(link_local)) {
+            assert!(loopback.scope_id(&link_local).is_some());
+        }
+        assert_eq!(loopback.scope_id(&Ipv6Addr::LOCALHOST), None);
+
+        // Interfaces without addresses are listed too
+        for name in read_links().unwrap().keys() {
+            assert!(manager.get_interface(name).is_some(), "{name} is missing");
+        }
+    }
 }
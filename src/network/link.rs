@@ -0,0 +1,265 @@
+//! Link-level state of the network interfaces from the routing table.
+//!
+//! `sysctl(CTL_NET, PF_ROUTE, 0, 0, NET_RT_IFLIST2, 0)` returns one `RTM_IFINFO2` message per interface, whether or not
+//! it has addresses: an `if_msghdr2` with the flags and `if_data64` (MTU, baud rate and traffic counters),
+//! followed by a `sockaddr_dl` with the name and hardware address. Multicast membership messages in between are
+//! skipped.
+//!
+//! The messages are decoded by offset rather than through `repr(C)` structs, so a buffer from a different layout
+//! yields no interfaces instead of garbage.
+
+use std::collections::HashMap;
+
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{
+        address_family::AF_LINK,
+        sysctl,
+        sysctl_constants::{CTL_NET, NET_RT_IFLIST2, PF_ROUTE},
+    },
+};
+
+/// Message type of an `if_msghdr2`
+const RTM_IFINFO2: u8 = 0x12;
+/// Bit of `ifm_addrs` set when the message carries the `sockaddr_dl` of the interface
+const RTA_IFP: i32 = 0x10;
+/// Size of `if_msghdr2`, after which the `sockaddr_dl` follows
+const IF_MSGHDR2_SIZE: usize = 160;
+
+// Offsets in `if_msghdr2`
+const IFM_MSGLEN: usize = 0;
+const IFM_TYPE: usize = 3;
+const IFM_ADDRS: usize = 4;
+const IFM_FLAGS: usize = 8;
+/// Offset of `ifm_data`, the `if_data64`
+const IFM_DATA: usize = 32;
+const IFI_MTU: usize = IFM_DATA + 8;
+
+// Offsets in `sockaddr_dl`
+const SDL_FAMILY: usize = 1;
+const SDL_NLEN: usize = 5;
+const SDL_ALEN: usize = 6;
+const SDL_DATA: usize = 8;
+
+/// Link-level state of one interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LinkInfo {
+    /// Name of the interface
+    pub name: String,
+    /// Interface flags (IFF_UP, IFF_RUNNING, etc.)
+    pub flags: u32,
+    /// Maximum transmission unit in bytes
+    pub mtu: u32,
+    /// Hardware address, for interfaces with a 6 byte one
+    pub mac_address: Option<[u8; 6]>,
+}
+
+/// Reads the link-level state of every interface, by name.
+///
+/// # Errors
+///
+/// Returns an error if the interface list can't be read from the routing table.
+pub(crate) fn read_links() -> Result<HashMap<String, LinkInfo>> {
+    let mib = [CTL_NET, PF_ROUTE, 0, 0, NET_RT_IFLIST2, 0];
+
+    // Interfaces can appear between the size query and the read, so retry with some slack
+    for _ in 0..3 {
+        let mut size = 0usize;
+        // SAFETY: a null buffer asks sysctl for the required size only.
+        let result = unsafe {
+            sysctl(
+                mib.as_ptr(),
+                mib.len() as u32,
+                std::ptr::null_mut(),
+                &mut size,
+                std::ptr::null(),
+                0,
+            )
+        };
+        if result != 0 {
+            break;
+        }
+
+        size += size / 8;
+        let mut buffer = vec![0u8; size];
+        // SAFETY: the buffer is valid for writes of `size` bytes.
+        let result = unsafe {
+            sysctl(
+                mib.as_ptr(),
+                mib.len() as u32,
+                buffer.as_mut_ptr() as *mut std::ffi::c_void,
+                &mut size,
+                std::ptr::null(),
+                0,
+            )
+        };
+        if result == 0 {
+            buffer.truncate(size);
+            return Ok(parse_iflist2(&buffer)
+                .into_iter()
+                .map(|link| (link.name.clone(), link))
+                .collect());
+        }
+        if std::io::Error::last_os_error().raw_os_error() != Some(libc::ENOMEM) {
+            break;
+        }
+    }
+
+    Err(Error::os_error("Failed to read the interface list", std::io::Error::last_os_error()))
+}
+
+/// Decodes the `RTM_IFINFO2` messages of a `NET_RT_IFLIST2` buffer, stopping at the first truncated message.
+pub(crate) fn parse_iflist2(buffer: &[u8]) -> Vec<LinkInfo> {
+    let mut links = Vec::new();
+    let mut offset = 0;
+
+    while let Some(length) = read_u16(buffer, offset + IFM_MSGLEN).map(usize::from) {
+        let Some(message) = buffer.get(offset..offset + length).filter(|_| length > 0) else {
+            break;
+        };
+        offset += length;
+
+        if message.get(IFM_TYPE) == Some(&RTM_IFINFO2) {
+            links.extend(parse_ifinfo2(message));
+        }
+    }
+
+    links
+}
+
+/// Decodes one `RTM_IFINFO2` message, None if it's too short or has no `sockaddr_dl` with a name.
+fn parse_ifinfo2(message: &[u8]) -> Option<LinkInfo> {
+    if read_i32(message, IFM_ADDRS)? & RTA_IFP == 0 {
+        return None;
+    }
+
+    let link = message.get(IF_MSGHDR2_SIZE..)?;
+    if *link.get(SDL_FAMILY)? != AF_LINK {
+        return None;
+    }
+    let name_len = usize::from(*link.get(SDL_NLEN)?);
+    let address_len = usize::from(*link.get(SDL_ALEN)?);
+    let name = link.get(SDL_DATA..SDL_DATA + name_len)?;
+    if name.is_empty() {
+        return None;
+    }
+    let address = link.get(SDL_DATA + name_len..SDL_DATA + name_len + address_len)?;
+
+    Some(LinkInfo {
+        name: String::from_utf8_lossy(name).into_owned(),
+        flags: read_i32(message, IFM_FLAGS)? as u32,
+        mtu: read_u32(message, IFI_MTU)?,
+        mac_address: address.try_into().ok(),
+    })
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_ne_bytes(buffer.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(buffer.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_i32(buffer: &[u8], offset: usize) -> Option<i32> {
+    read_u32(buffer, offset).map(|value| value as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::bindings::if_flags;
+
+    /// An `RTM_IFINFO2` message for an interface
+    fn ifinfo2(name: &str, flags: u32, mtu: u32, mac: &[u8]) -> Vec<u8> {
+        let mut message = vec![0u8; IF_MSGHDR2_SIZE];
+        message[IFM_TYPE] = RTM_IFINFO2;
+        message[IFM_ADDRS..IFM_ADDRS + 4].copy_from_slice(&RTA_IFP.to_ne_bytes());
+        message[IFM_FLAGS..IFM_FLAGS + 4].copy_from_slice(&flags.to_ne_bytes());
+        message[IFI_MTU..IFI_MTU + 4].copy_from_slice(&mtu.to_ne_bytes());
+
+        // sockaddr_dl, padded to a multiple of 4 bytes like the kernel does
+        let mut link = vec![0u8; SDL_DATA];
+        link[SDL_FAMILY] = AF_LINK;
+        link[SDL_NLEN] = name.len() as u8;
+        link[SDL_ALEN] = mac.len() as u8;
+        link.extend_from_slice(name.as_bytes());
+        link.extend_from_slice(mac);
+        link.resize(link.len().next_multiple_of(4).max(20), 0);
+        link[0] = link.len() as u8;
+        message.extend(link);
+
+        let length = message.len() as u16;
+        message[IFM_MSGLEN..IFM_MSGLEN + 2].copy_from_slice(&length.to_ne_bytes());
+        message
+    }
+
+    /// A multicast membership message, which has to be skipped
+    fn newmaddr2() -> Vec<u8> {
+        let mut message = vec![0u8; 40];
+        message[IFM_MSGLEN..IFM_MSGLEN + 2].copy_from_slice(&40u16.to_ne_bytes());
+        message[IFM_TYPE] = 0x13;
+        message
+    }
+
+    #[test]
+    fn test_parse_iflist2() {
+        let up = if_flags::IFF_UP | if_flags::IFF_RUNNING;
+        let buffer = [
+            ifinfo2("lo0", up | if_flags::IFF_LOOPBACK, 16384, &[]),
+            newmaddr2(),
+            ifinfo2("en0", up, 1500, &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            ifinfo2("awdl0", if_flags::IFF_UP, 1484, &[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]),
+        ]
+        .concat();
+
+        let links = parse_iflist2(&buffer);
+        assert_eq!(links.len(), 3);
+
+        assert_eq!(links[0].name, "lo0");
+        assert_eq!(links[0].mtu, 16384);
+        assert_eq!(links[0].mac_address, None);
+        assert_ne!(links[0].flags & if_flags::IFF_LOOPBACK, 0);
+
+        assert_eq!(
+            links[1],
+            LinkInfo {
+                name: "en0".to_string(),
+                flags: up,
+                mtu: 1500,
+                mac_address: Some([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            }
+        );
+
+        assert_eq!(links[2].name, "awdl0");
+        assert_eq!(links[2].mtu, 1484);
+    }
+
+    #[test]
+    fn test_parse_iflist2_truncated() {
+        let mut buffer =
+            [ifinfo2("en0", 0, 1500, &[0; 6]), ifinfo2("en1", 0, 1500, &[0; 6])].concat();
+        buffer.truncate(buffer.len() - 10);
+
+        let links = parse_iflist2(&buffer);
+        assert_eq!(links.len(), 1, "The truncated message is dropped");
+        assert_eq!(links[0].name, "en0");
+
+        assert!(parse_iflist2(&[]).is_empty());
+        assert!(parse_iflist2(&[0, 0, 0, RTM_IFINFO2]).is_empty(), "A zero length ends the list");
+    }
+
+    #[test]
+    fn test_parse_ifinfo2_without_link_address() {
+        let mut message = ifinfo2("en0", 0, 1500, &[0; 6]);
+        message[IFM_ADDRS..IFM_ADDRS + 4].copy_from_slice(&0i32.to_ne_bytes());
+        assert!(parse_iflist2(&message).is_empty());
+    }
+
+    #[test]
+    fn test_read_links() {
+        let links = read_links().unwrap();
+        let loopback = links.get("lo0").expect("Every Mac has a loopback interface");
+        assert!(loopback.mtu > 0);
+    }
+}
@@ -27,6 +27,16 @@
 //! - **Interface Information**: Get MAC addresses, IP addresses, and interface
 //!   capabilities
 //! - **Speed Calculation**: Calculate real-time upload and download speeds
+//! - **Traffic Rates**: Byte and packet rates per interface across counter
+//!   wraparound and interfaces coming and going ([`NetworkTrafficTracker`])
+//! - **Per-Process Bandwidth**: Accumulate per-process bytes from flow events and
+//!   reconcile them against interface totals ([`bandwidth`])
+//! - **Socket Statistics**: Count host-wide TCP connections by state and list
+//!   listening ports ([`tcp_summary`])
+//! - **Process Connections**: List the TCP and UDP sockets of each process with
+//!   their endpoints and state ([`ProcessNetworkMonitor`])
+//! - **Wi-Fi Signal**: RSSI, noise, channel, PHY mode and transmit rate of the
+//!   Wi-Fi connection through CoreWLAN (`WifiMonitor`, `wifi` feature)
 //!
 //! ## Example
 //!
@@ -90,11 +100,24 @@
 //! - The API is not thread-safe by default; use mutex locks when sharing across
 //!   threads
 
+pub mod bandwidth;
+pub mod connections;
 pub mod interface;
+mod link;
+pub mod rates;
+pub mod tcp;
 pub mod traffic;
-
-pub use interface::{Interface, InterfaceType, NetworkManager};
+#[cfg(feature = "wifi")]
+pub mod wifi;
+
+pub use bandwidth::{ByteCounts, FlowEvent, ProcessBandwidthMonitor};
+pub use connections::{ProcessNetworkMonitor, SocketConnection, SocketProtocol};
+pub use interface::{Interface, InterfaceIdentity, InterfaceType, NetworkManager};
+pub use rates::{NetworkTrafficTracker, TrafficRates};
+pub use tcp::{tcp_summary, udp_socket_count, TcpState, TcpSummary};
 pub use traffic::TrafficData;
+#[cfg(feature = "wifi")]
+pub use wifi::{PhyMode, WifiBand, WifiChannel, WifiInfo, WifiMonitor};
 
 /// Trait defining the standard interface for accessing network metrics.
 ///
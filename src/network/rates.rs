@@ -0,0 +1,281 @@
+//! Per-interface byte and packet rates
+//!
+//! Interfaces report cumulative counters, so a rate needs two samples of the same interface. [`NetworkTrafficTracker`]
+//! keeps the previous sample of each interface and turns the next one into rates:
+//!
+//! - The first sample of an interface has no rate, nor does an interface that vanished and came back (VPN `utun`
+//!   devices come and go with the tunnel).
+//! - Some interfaces keep 32-bit counters, which wrap around after 4 GiB. A counter that went backwards from the 32-bit
+//!   range is counted across the wrap when the wrapped delta is plausible, i.e. less than half the 32-bit range.
+//! - Any other counter that went backwards was reset, so there is no rate for that interval.
+
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    error::{Error, Result},
+    network::{interface::NetworkManager, traffic::TrafficData},
+    utils::sanitize::sanitize_rate,
+};
+
+/// Byte and packet rates of an interface over one sampling interval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrafficRates {
+    /// Bytes received per second
+    pub rx_bytes_per_sec: f64,
+    /// Bytes sent per second
+    pub tx_bytes_per_sec: f64,
+    /// Packets received per second
+    pub rx_packets_per_sec: f64,
+    /// Packets sent per second
+    pub tx_packets_per_sec: f64,
+    /// Time between the two samples the rates were computed from
+    pub interval: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct InterfaceState {
+    previous: TrafficData,
+    rates: Option<TrafficRates>,
+}
+
+/// Tracks the byte and packet rates of every interface between samples.
+///
+/// ```rust,no_run
+/// use std::{thread::sleep, time::Duration};
+///
+/// use darwin_metrics::network::NetworkTrafficTracker;
+///
+/// fn main() -> darwin_metrics::error::Result<()> {
+///     let mut tracker = NetworkTrafficTracker::new();
+///     tracker.sample()?;
+///     sleep(Duration::from_secs(1));
+///     tracker.sample()?;
+///
+///     if let Some(rx) = tracker.rx_rate_bps("en0") {
+///         println!("en0 download: {:.1} KB/s", rx / 1024.0);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct NetworkTrafficTracker {
+    interfaces: HashMap<String, InterfaceState>,
+}
+
+impl NetworkTrafficTracker {
+    /// Creates a tracker without any samples.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the counters of every interface and updates the rates.
+    ///
+    /// Interfaces missing from the reading are forgotten.
+    pub fn sample(&mut self) -> Result<()> {
+        let readings = read_counters()?;
+        self.record_all(readings);
+        Ok(())
+    }
+
+    /// Reads the counters of every interface on a blocking task and updates the rates.
+    pub async fn sample_async(&mut self) -> Result<()> {
+        let readings = tokio::task::spawn_blocking(read_counters)
+            .await
+            .map_err(|e| Error::Network(format!("Task join error: {}", e)))??;
+        self.record_all(readings);
+        Ok(())
+    }
+
+    /// Records a reading of every interface, forgetting the interfaces missing from it.
+    pub fn record_all(&mut self, readings: impl IntoIterator<Item = (String, TrafficData)>) {
+        let readings: HashMap<String, TrafficData> = readings.into_iter().collect();
+        self.interfaces.retain(|name, _| readings.contains_key(name));
+        for (name, data) in readings {
+            self.record(name, data);
+        }
+    }
+
+    /// Records a sample of one interface and updates its rates.
+    pub fn record(&mut self, name: impl Into<String>, data: TrafficData) {
+        let name = name.into();
+        let rates =
+            self.interfaces.get(&name).and_then(|state| rates_between(&state.previous, &data));
+        self.interfaces.insert(name, InterfaceState { previous: data, rates });
+    }
+
+    /// Rates of `name` over the last interval, or None before its second sample or after a counter reset.
+    pub fn rates(&self, name: &str) -> Option<TrafficRates> {
+        self.interfaces.get(name)?.rates
+    }
+
+    /// Bytes received per second by `name`, see [`rates`](Self::rates).
+    pub fn rx_rate_bps(&self, name: &str) -> Option<f64> {
+        self.rates(name).map(|rates| rates.rx_bytes_per_sec)
+    }
+
+    /// Bytes sent per second by `name`, see [`rates`](Self::rates).
+    pub fn tx_rate_bps(&self, name: &str) -> Option<f64> {
+        self.rates(name).map(|rates| rates.tx_bytes_per_sec)
+    }
+
+    /// Packets received per second by `name`, see [`rates`](Self::rates).
+    pub fn rx_packet_rate(&self, name: &str) -> Option<f64> {
+        self.rates(name).map(|rates| rates.rx_packets_per_sec)
+    }
+
+    /// Packets sent per second by `name`, see [`rates`](Self::rates).
+    pub fn tx_packet_rate(&self, name: &str) -> Option<f64> {
+        self.rates(name).map(|rates| rates.tx_packets_per_sec)
+    }
+
+    /// Names of the interfaces seen in the last sample, in arbitrary order.
+    pub fn interfaces(&self) -> impl Iterator<Item = &str> {
+        self.interfaces.keys().map(String::as_str)
+    }
+
+    /// Forgets every sample.
+    pub fn clear(&mut self) {
+        self.interfaces.clear();
+    }
+}
+
+/// Reads the cumulative counters of every interface.
+fn read_counters() -> Result<Vec<(String, TrafficData)>> {
+    let manager = NetworkManager { interfaces: HashMap::new() };
+    let stats = manager
+        .update_traffic_stats()
+        .ok_or_else(|| Error::Network("Failed to read interface counters".to_string()))?;
+
+    Ok(stats
+        .into_iter()
+        .map(|(name, counters)| {
+            let (rx_bytes, tx_bytes, rx_packets, tx_packets, rx_errors, tx_errors, collisions) =
+                counters;
+            let data = TrafficData::new(
+                rx_bytes, tx_bytes, rx_packets, tx_packets, rx_errors, tx_errors, collisions,
+            );
+            (name, data)
+        })
+        .collect())
+}
+
+/// Rates between two samples of an interface, or None if a counter was reset.
+fn rates_between(previous: &TrafficData, current: &TrafficData) -> Option<TrafficRates> {
+    let interval = current.timestamp.checked_duration_since(previous.timestamp)?;
+    let secs = interval.as_secs_f64();
+    let rate = |counter: fn(&TrafficData) -> u64| {
+        counter_delta(counter(previous), counter(current))
+            .map(|delta| sanitize_rate(delta as f64, secs))
+    };
+
+    Some(TrafficRates {
+        rx_bytes_per_sec: rate(|data| data.bytes_received)?,
+        tx_bytes_per_sec: rate(|data| data.bytes_sent)?,
+        rx_packets_per_sec: rate(|data| data.packets_received)?,
+        tx_packets_per_sec: rate(|data| data.packets_sent)?,
+        interval,
+    })
+}
+
+/// Increase of a counter from `previous` to `current`, counting across a 32-bit wrap; None if it was reset.
+fn counter_delta(previous: u64, current: u64) -> Option<u64> {
+    if current >= previous {
+        return Some(current - previous);
+    }
+    if previous > u64::from(u32::MAX) {
+        return None;
+    }
+
+    let wrapped = (u64::from(u32::MAX) - previous) + current + 1;
+    (wrapped < 1 << 31).then_some(wrapped)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn reading(
+        at: Instant,
+        rx_bytes: u64,
+        tx_bytes: u64,
+        rx_packets: u64,
+        tx_packets: u64,
+    ) -> TrafficData {
+        TrafficData {
+            timestamp: at,
+            ..TrafficData::new(rx_bytes, tx_bytes, rx_packets, tx_packets, 0, 0, 0)
+        }
+    }
+
+    #[test]
+    fn test_counter_delta() {
+        assert_eq!(counter_delta(100, 250), Some(150));
+        assert_eq!(counter_delta(100, 100), Some(0));
+        // A 32-bit counter wrapping close to its end
+        assert_eq!(counter_delta(u64::from(u32::MAX) - 99, 50), Some(150));
+        // A 32-bit counter going back by more than half its range, or a 64-bit counter going back at all
+        assert_eq!(counter_delta(1_000, 10), None);
+        assert_eq!(counter_delta(u64::from(u32::MAX) + 10, 5), None);
+    }
+
+    #[test]
+    fn test_rates_need_two_samples() {
+        let start = Instant::now();
+        let mut tracker = NetworkTrafficTracker::new();
+
+        tracker.record("en0", reading(start, 1_000, 500, 10, 5));
+        assert_eq!(tracker.rates("en0"), None);
+        assert_eq!(tracker.rx_rate_bps("en0"), None);
+
+        tracker.record("en0", reading(start + Duration::from_secs(2), 5_000, 1_500, 30, 9));
+        assert_eq!(tracker.rx_rate_bps("en0"), Some(2_000.0));
+        assert_eq!(tracker.tx_rate_bps("en0"), Some(500.0));
+        assert_eq!(tracker.rx_packet_rate("en0"), Some(10.0));
+        assert_eq!(tracker.tx_packet_rate("en0"), Some(2.0));
+        assert_eq!(tracker.rates("en0").unwrap().interval, Duration::from_secs(2));
+        assert_eq!(tracker.rates("en1"), None);
+    }
+
+    #[test]
+    fn test_wraparound_and_reset() {
+        let start = Instant::now();
+        let near_end = u64::from(u32::MAX) - 999;
+        let mut tracker = NetworkTrafficTracker::new();
+
+        tracker.record("en0", reading(start, near_end, 0, 0, 0));
+        tracker.record("en0", reading(start + Duration::from_secs(1), 1_000, 0, 0, 0));
+        assert_eq!(tracker.rx_rate_bps("en0"), Some(2_000.0));
+
+        // The interface was re-created and its counters started over
+        tracker.record("en0", reading(start + Duration::from_secs(2), 10, 0, 0, 0));
+        assert_eq!(tracker.rx_rate_bps("en0"), None);
+        tracker.record("en0", reading(start + Duration::from_secs(3), 110, 0, 0, 0));
+        assert_eq!(tracker.rx_rate_bps("en0"), Some(100.0));
+    }
+
+    #[test]
+    fn test_interfaces_coming_and_going() {
+        let start = Instant::now();
+        let mut tracker = NetworkTrafficTracker::new();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        tracker.record_all([("en0".to_string(), reading(at(0), 0, 0, 0, 0))]);
+        tracker.record_all([
+            ("en0".to_string(), reading(at(1), 100, 0, 0, 0)),
+            ("utun3".to_string(), reading(at(1), 5_000_000, 0, 0, 0)),
+        ]);
+        assert_eq!(tracker.rx_rate_bps("en0"), Some(100.0));
+        assert_eq!(tracker.rx_rate_bps("utun3"), None, "A new interface has no rate yet");
+
+        // The tunnel went down, then came back with the same name
+        tracker.record_all([("en0".to_string(), reading(at(2), 200, 0, 0, 0))]);
+        assert_eq!(tracker.interfaces().collect::<Vec<_>>(), ["en0"]);
+        tracker.record_all([
+            ("en0".to_string(), reading(at(3), 300, 0, 0, 0)),
+            ("utun3".to_string(), reading(at(3), 6_000_000, 0, 0, 0)),
+        ]);
+        assert_eq!(tracker.rx_rate_bps("utun3"), None);
+    }
+}
@@ -0,0 +1,418 @@
+//! Host-wide TCP and UDP socket statistics
+//!
+//! The connection tables are read with the `net.inet.tcp.pcblist_n` and `net.inet.udp.pcblist_n` sysctls, which any
+//! user can read and which return every socket on the host in a single call. The buffer starts and ends with an
+//! `xinpgen` header; in between, each socket is described by a series of items (`xinpcb_n`, `xsocket_n`, …,
+//! `xtcpcb_n`), each starting with its length and kind and padded to 8 bytes.
+//!
+//! The kernel appends fields to these structures between macOS releases, so the parser walks the buffer by the
+//! embedded length fields and only reads fields at offsets that have been stable since they were introduced.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::CString,
+    os::raw::c_void,
+    ptr,
+};
+
+use crate::{
+    error::{Error, Result},
+    utils::bindings::sysctlbyname,
+};
+
+const TCP_PCBLIST: &str = "net.inet.tcp.pcblist_n";
+const UDP_PCBLIST: &str = "net.inet.udp.pcblist_n";
+
+/// Size of `struct xinpgen`; items this small or smaller mark the end of the list
+const XINPGEN_SIZE: usize = 24;
+/// Size of the `xi_len`/`xi_kind` prefix shared by all items
+const ITEM_HEADER_SIZE: usize = 8;
+
+/// `XSO_INPCB` item kind (`struct xinpcb_n`)
+const XSO_INPCB: u32 = 0x10;
+/// `XSO_TCPCB` item kind (`struct xtcpcb_n`)
+const XSO_TCPCB: u32 = 0x20;
+
+/// Offset of `inp_lport` (network byte order) in `struct xinpcb_n`
+const INP_LPORT_OFFSET: usize = 18;
+/// Offset of `t_state` in `struct xtcpcb_n`
+const T_STATE_OFFSET: usize = 36;
+
+/// Number of times the list is re-read if it grows between sizing the buffer and reading it
+const READ_ATTEMPTS: usize = 3;
+
+/// State of a TCP connection (`TCPS_*` in `<netinet/tcp_fsm.h>`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TcpState {
+    /// Closed
+    Closed,
+    /// Listening for connections
+    Listen,
+    /// Active open, SYN sent
+    SynSent,
+    /// SYN received, waiting for the final ACK
+    SynReceived,
+    /// Connection established
+    Established,
+    /// Remote side closed, waiting for the local application to close
+    CloseWait,
+    /// Closed locally, FIN sent
+    FinWait1,
+    /// Both sides closed simultaneously, waiting for the ACK
+    Closing,
+    /// Closed after the remote side, waiting for the ACK of our FIN
+    LastAck,
+    /// Closed locally, FIN acknowledged, waiting for the remote FIN
+    FinWait2,
+    /// Waiting after close for delayed segments to expire
+    TimeWait,
+}
+
+impl TcpState {
+    /// Converts a raw `t_state` value, returning `None` for unknown states.
+    pub fn from_raw(value: i32) -> Option<Self> {
+        Some(match value {
+            0 => Self::Closed,
+            1 => Self::Listen,
+            2 => Self::SynSent,
+            3 => Self::SynReceived,
+            4 => Self::Established,
+            5 => Self::CloseWait,
+            6 => Self::FinWait1,
+            7 => Self::Closing,
+            8 => Self::LastAck,
+            9 => Self::FinWait2,
+            10 => Self::TimeWait,
+            _ => return None,
+        })
+    }
+}
+
+/// Summary of the TCP connections on the host
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TcpSummary {
+    /// Number of connections in each state; states without connections are absent
+    pub by_state: HashMap<TcpState, u32>,
+    /// Total number of connections
+    pub total: u32,
+    /// Local ports with a socket in the `LISTEN` state, sorted and deduplicated
+    pub listening_ports: Vec<u16>,
+}
+
+impl TcpSummary {
+    /// Number of connections in `state`.
+    pub fn count(&self, state: TcpState) -> u32 {
+        self.by_state.get(&state).copied().unwrap_or(0)
+    }
+}
+
+/// Summarizes the TCP connections on the host by state.
+///
+/// A growing number of `CLOSE_WAIT` connections usually means a server isn't closing the sockets its clients
+/// have hung up on.
+///
+/// # Errors
+///
+/// Returns an error if the connection table can't be read or is malformed.
+pub fn tcp_summary() -> Result<TcpSummary> {
+    parse_tcp_pcblist(&read_pcblist(TCP_PCBLIST)?)
+}
+
+/// Returns the number of UDP sockets on the host.
+///
+/// # Errors
+///
+/// Returns an error if the socket table can't be read or is malformed.
+pub fn udp_socket_count() -> Result<u32> {
+    parse_udp_pcblist(&read_pcblist(UDP_PCBLIST)?)
+}
+
+/// A single item of a pcblist buffer
+#[derive(Debug, Clone, Copy)]
+struct PcbItem<'a> {
+    kind: u32,
+    data: &'a [u8],
+}
+
+/// Splits a pcblist buffer into its items, skipping the leading `xinpgen` header.
+fn pcb_items(buf: &[u8]) -> Result<Vec<PcbItem<'_>>> {
+    let header_len = read_u32(buf, 0)
+        .filter(|&len| len as usize >= XINPGEN_SIZE)
+        .ok_or_else(|| Error::invalid_data("pcblist buffer is missing its xinpgen header"))?;
+
+    let mut items = Vec::new();
+    let mut offset = round_up_64(header_len as usize);
+
+    while offset + ITEM_HEADER_SIZE <= buf.len() {
+        // The loop condition keeps both fields in bounds
+        let len = read_u32(buf, offset).unwrap_or_default() as usize;
+        let kind = read_u32(buf, offset + 4).unwrap_or_default();
+
+        // The trailing xinpgen ends the list
+        if len <= XINPGEN_SIZE {
+            break;
+        }
+        let data = buf.get(offset..offset + len).ok_or_else(|| {
+            Error::invalid_data(format!(
+                "pcblist item at offset {offset} claims {len} bytes, past the end of the buffer"
+            ))
+        })?;
+
+        items.push(PcbItem { kind, data });
+        offset += round_up_64(len);
+    }
+
+    Ok(items)
+}
+
+/// Parses the buffer returned by `net.inet.tcp.pcblist_n`.
+pub(crate) fn parse_tcp_pcblist(buf: &[u8]) -> Result<TcpSummary> {
+    let mut summary = TcpSummary::default();
+    let mut listening_ports = BTreeSet::new();
+    // The xinpcb_n of a socket precedes its xtcpcb_n
+    let mut local_port = None;
+
+    for item in pcb_items(buf)? {
+        match item.kind {
+            XSO_INPCB => local_port = read_port(item.data, INP_LPORT_OFFSET),
+            XSO_TCPCB => {
+                let Some(state) = read_i32(item.data, T_STATE_OFFSET).and_then(TcpState::from_raw)
+                else {
+                    continue;
+                };
+
+                *summary.by_state.entry(state).or_default() += 1;
+                summary.total += 1;
+
+                if state == TcpState::Listen {
+                    if let Some(port) = local_port {
+                        listening_ports.insert(port);
+                    }
+                }
+                local_port = None;
+            },
+            _ => {},
+        }
+    }
+
+    summary.listening_ports = listening_ports.into_iter().collect();
+    Ok(summary)
+}
+
+/// Parses the buffer returned by `net.inet.udp.pcblist_n`, counting its sockets.
+pub(crate) fn parse_udp_pcblist(buf: &[u8]) -> Result<u32> {
+    Ok(pcb_items(buf)?.iter().filter(|item| item.kind == XSO_INPCB).count() as u32)
+}
+
+/// Reads a pcblist sysctl into a buffer.
+fn read_pcblist(name: &str) -> Result<Vec<u8>> {
+    let c_name = CString::new(name)
+        .map_err(|_| Error::invalid_data(format!("Invalid sysctl name: {name}")))?;
+
+    for _ in 0..READ_ATTEMPTS {
+        let mut size = 0usize;
+        // SAFETY: a null buffer asks sysctlbyname for the required size only.
+        let result =
+            unsafe { sysctlbyname(c_name.as_ptr(), ptr::null_mut(), &mut size, ptr::null(), 0) };
+        if result != 0 {
+            return Err(Error::os_error(
+                format!("Failed to size {name}"),
+                std::io::Error::last_os_error(),
+            ));
+        }
+
+        // Leave room for sockets opened between the two calls
+        size += size / 8;
+        let mut buf = vec![0u8; size];
+        // SAFETY: `buf` is valid for writes of `size` bytes.
+        let result = unsafe {
+            sysctlbyname(
+                c_name.as_ptr(),
+                buf.as_mut_ptr() as *mut c_void,
+                &mut size,
+                ptr::null(),
+                0,
+            )
+        };
+
+        if result == 0 {
+            buf.truncate(size);
+            return Ok(buf);
+        }
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ENOMEM) {
+            return Err(Error::os_error(format!("Failed to read {name}"), err));
+        }
+    }
+
+    Err(Error::Network(format!("{name} kept growing while being read")))
+}
+
+fn round_up_64(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    buf.get(offset..offset + 4).map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_i32(buf: &[u8], offset: usize) -> Option<i32> {
+    read_u32(buf, offset).map(|value| value as i32)
+}
+
+/// Reads a port stored in network byte order.
+fn read_port(buf: &[u8], offset: usize) -> Option<u16> {
+    buf.get(offset..offset + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, UdpSocket};
+
+    use super::*;
+
+    /// `XSO_SOCKET` item kind, included in the fixtures so the parser has to skip it
+    const XSO_SOCKET: u32 = 0x01;
+
+    /// Builds pcblist buffers in the layout the kernel produces.
+    struct PcblistFixture {
+        buf: Vec<u8>,
+        inpcb_len: usize,
+        tcpcb_len: usize,
+        count: u32,
+    }
+
+    impl PcblistFixture {
+        /// Item sizes of `xinpcb_n` and `xtcpcb_n` as shipped in recent macOS releases
+        fn new() -> Self {
+            Self::with_sizes(104, 204)
+        }
+
+        fn with_sizes(inpcb_len: usize, tcpcb_len: usize) -> Self {
+            let mut fixture = Self { buf: Vec::new(), inpcb_len, tcpcb_len, count: 0 };
+            fixture.push_xinpgen();
+            fixture
+        }
+
+        fn push_xinpgen(&mut self) {
+            let mut header = vec![0u8; XINPGEN_SIZE];
+            header[0..4].copy_from_slice(&(XINPGEN_SIZE as u32).to_ne_bytes());
+            header[4..8].copy_from_slice(&self.count.to_ne_bytes());
+            self.buf.extend_from_slice(&header);
+        }
+
+        fn push_item(&mut self, kind: u32, len: usize, fields: &[(usize, &[u8])]) {
+            let mut item = vec![0u8; round_up_64(len)];
+            item[0..4].copy_from_slice(&(len as u32).to_ne_bytes());
+            item[4..8].copy_from_slice(&kind.to_ne_bytes());
+            for (offset, bytes) in fields {
+                item[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+            }
+            self.buf.extend_from_slice(&item);
+        }
+
+        fn push_inpcb(&mut self, local_port: u16) {
+            self.count += 1;
+            self.push_item(
+                XSO_INPCB,
+                self.inpcb_len,
+                &[(INP_LPORT_OFFSET, &local_port.to_be_bytes())],
+            );
+            self.push_item(XSO_SOCKET, 136, &[]);
+        }
+
+        fn push_tcp(&mut self, local_port: u16, state: i32) {
+            self.push_inpcb(local_port);
+            self.push_item(XSO_TCPCB, self.tcpcb_len, &[(T_STATE_OFFSET, &state.to_ne_bytes())]);
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            self.push_xinpgen();
+            self.buf
+        }
+    }
+
+    fn mixed_connections(mut fixture: PcblistFixture) -> Vec<u8> {
+        fixture.push_tcp(22, 1);
+        fixture.push_tcp(8080, 1);
+        fixture.push_tcp(8080, 1);
+        fixture.push_tcp(51_000, 4);
+        fixture.push_tcp(51_001, 4);
+        fixture.push_tcp(8080, 5);
+        fixture.push_tcp(51_002, 10);
+        fixture.finish()
+    }
+
+    #[test]
+    fn test_tcp_state_from_raw() {
+        assert_eq!(TcpState::from_raw(1), Some(TcpState::Listen));
+        assert_eq!(TcpState::from_raw(5), Some(TcpState::CloseWait));
+        assert_eq!(TcpState::from_raw(10), Some(TcpState::TimeWait));
+        assert_eq!(TcpState::from_raw(11), None);
+        assert_eq!(TcpState::from_raw(-1), None);
+    }
+
+    #[test]
+    fn test_parse_tcp_pcblist() {
+        let summary = parse_tcp_pcblist(&mixed_connections(PcblistFixture::new())).unwrap();
+
+        assert_eq!(summary.total, 7);
+        assert_eq!(summary.count(TcpState::Listen), 3);
+        assert_eq!(summary.count(TcpState::Established), 2);
+        assert_eq!(summary.count(TcpState::CloseWait), 1);
+        assert_eq!(summary.count(TcpState::TimeWait), 1);
+        assert_eq!(summary.count(TcpState::SynSent), 0);
+        assert_eq!(summary.listening_ports, vec![22, 8080]);
+    }
+
+    #[test]
+    fn test_parse_honors_item_lengths() {
+        // Larger structures (as on a newer macOS) and unaligned lengths parse the same way
+        let grown = mixed_connections(PcblistFixture::with_sizes(157, 301));
+        let current = mixed_connections(PcblistFixture::new());
+
+        assert_eq!(parse_tcp_pcblist(&grown).unwrap(), parse_tcp_pcblist(&current).unwrap());
+    }
+
+    #[test]
+    fn test_parse_empty_and_malformed_buffers() {
+        let empty = parse_tcp_pcblist(&PcblistFixture::new().finish()).unwrap();
+        assert_eq!(empty, TcpSummary::default());
+
+        assert!(parse_tcp_pcblist(&[]).is_err());
+        assert!(parse_tcp_pcblist(&[4, 0, 0, 0]).is_err());
+
+        // An item whose length runs past the end of the buffer
+        let mut truncated = mixed_connections(PcblistFixture::new());
+        truncated.truncate(XINPGEN_SIZE + 40);
+        assert!(parse_tcp_pcblist(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_parse_udp_pcblist() {
+        let mut fixture = PcblistFixture::new();
+        fixture.push_inpcb(53);
+        fixture.push_inpcb(5353);
+        fixture.push_inpcb(0);
+
+        assert_eq!(parse_udp_pcblist(&fixture.finish()).unwrap(), 3);
+        assert_eq!(parse_udp_pcblist(&PcblistFixture::new().finish()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_tcp_summary_includes_own_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let summary = tcp_summary().unwrap();
+        assert!(summary.listening_ports.contains(&port));
+        assert!(summary.count(TcpState::Listen) >= 1);
+        assert!(summary.total >= summary.count(TcpState::Listen));
+    }
+
+    #[test]
+    fn test_udp_socket_count_includes_own_socket() {
+        let _socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(udp_socket_count().unwrap() >= 1);
+    }
+}
@@ -1,9 +1,13 @@
 use std::time::Instant;
 
+use crate::utils::sanitize::sanitize_rate;
+
 /// Represents a network traffic data point with received and sent data.
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrafficData {
     /// Time when this data point was collected
+    #[cfg_attr(feature = "serde", serde(skip, default = "Instant::now"))]
     pub timestamp: Instant,
 
     /// Total bytes received
@@ -26,6 +30,10 @@ pub struct TrafficData {
 
     /// Total collisions
     pub collisions: u64,
+
+    /// Whether a counter went backwards since the previous data point,
+    /// starting a new counter epoch
+    pub reset: bool,
 }
 
 impl TrafficData {
@@ -49,18 +57,34 @@ impl TrafficData {
             receive_errors,
             send_errors,
             collisions,
+            reset: false,
         }
     }
+
+    /// Whether any counter in this data point is lower than in `previous`.
+    fn is_behind(&self, previous: &TrafficData) -> bool {
+        self.bytes_received < previous.bytes_received
+            || self.bytes_sent < previous.bytes_sent
+            || self.packets_received < previous.packets_received
+            || self.packets_sent < previous.packets_sent
+            || self.receive_errors < previous.receive_errors
+            || self.send_errors < previous.send_errors
+            || self.collisions < previous.collisions
+    }
 }
 
 /// Tracks network traffic statistics over time and calculates rates.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrafficTracker {
     /// Current network traffic data
     current: TrafficData,
 
     /// Previous network traffic data for rate calculations
     previous: Option<TrafficData>,
+
+    /// Number of counter resets observed since tracking began
+    epoch: u64,
 }
 
 impl TrafficTracker {
@@ -85,7 +109,7 @@ impl TrafficTracker {
             collisions,
         );
 
-        Self { current, previous: None }
+        Self { current, previous: None, epoch: 0 }
     }
 
     /// Updates the traffic data and shifts current data to previous.
@@ -100,8 +124,7 @@ impl TrafficTracker {
         send_errors: u64,
         collisions: u64,
     ) {
-        self.previous = Some(self.current);
-        self.current = TrafficData::new(
+        self.record(TrafficData::new(
             bytes_received,
             bytes_sent,
             packets_received,
@@ -109,7 +132,39 @@ impl TrafficTracker {
             receive_errors,
             send_errors,
             collisions,
-        );
+        ));
+    }
+
+    /// Records a new data point, shifting current data to previous.
+    ///
+    /// If any counter went backwards (the interface was re-created or its
+    /// counters were cleared), the data point is flagged as a reset and starts
+    /// a new epoch: the previous data point is dropped so no rate is computed
+    /// across the discontinuity.
+    pub(crate) fn record(&mut self, mut data: TrafficData) {
+        if data.is_behind(&self.current) {
+            data.reset = true;
+            self.previous = None;
+            self.epoch += 1;
+        } else {
+            self.previous = Some(self.current);
+        }
+        self.current = data;
+    }
+
+    /// Gets the most recent data point.
+    pub fn latest(&self) -> &TrafficData {
+        &self.current
+    }
+
+    /// Whether the most recent data point started a new counter epoch.
+    pub fn is_reset(&self) -> bool {
+        self.current.reset
+    }
+
+    /// Gets the number of counter resets observed since tracking began.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
     }
 
     /// Gets the current bytes received count.
@@ -150,84 +205,41 @@ impl TrafficTracker {
     /// Calculates the current download speed in bytes per second.
     /// Returns 0.0 if there's no previous data point for comparison.
     pub fn download_speed(&self) -> f64 {
-        if let Some(prev) = self.previous {
-            let bytes_diff = self.current.bytes_received.saturating_sub(prev.bytes_received);
-            let time_diff = self.current.timestamp.duration_since(prev.timestamp).as_secs_f64();
-
-            if time_diff > 0.0 {
-                bytes_diff as f64 / time_diff
-            } else {
-                0.0
-            }
-        } else {
-            0.0
-        }
+        self.per_second(|data| data.bytes_received)
     }
 
     /// Calculates the current upload speed in bytes per second.
     /// Returns 0.0 if there's no previous data point for comparison.
     pub fn upload_speed(&self) -> f64 {
-        if let Some(prev) = self.previous {
-            let bytes_diff = self.current.bytes_sent.saturating_sub(prev.bytes_sent);
-            let time_diff = self.current.timestamp.duration_since(prev.timestamp).as_secs_f64();
-
-            if time_diff > 0.0 {
-                bytes_diff as f64 / time_diff
-            } else {
-                0.0
-            }
-        } else {
-            0.0
-        }
+        self.per_second(|data| data.bytes_sent)
     }
 
     /// Calculates the packet receive rate (packets per second).
     pub fn packet_receive_rate(&self) -> f64 {
-        if let Some(prev) = self.previous {
-            let packets_diff = self.current.packets_received.saturating_sub(prev.packets_received);
-            let time_diff = self.current.timestamp.duration_since(prev.timestamp).as_secs_f64();
-
-            if time_diff > 0.0 {
-                packets_diff as f64 / time_diff
-            } else {
-                0.0
-            }
-        } else {
-            0.0
-        }
+        self.per_second(|data| data.packets_received)
     }
 
     /// Calculates the packet send rate (packets per second).
     pub fn packet_send_rate(&self) -> f64 {
-        if let Some(prev) = self.previous {
-            let packets_diff = self.current.packets_sent.saturating_sub(prev.packets_sent);
-            let time_diff = self.current.timestamp.duration_since(prev.timestamp).as_secs_f64();
-
-            if time_diff > 0.0 {
-                packets_diff as f64 / time_diff
-            } else {
-                0.0
-            }
-        } else {
-            0.0
-        }
+        self.per_second(|data| data.packets_sent)
     }
 
     /// Calculates the error rate for received packets.
     pub fn receive_error_rate(&self) -> f64 {
-        if self.current.packets_received > 0 {
-            self.current.receive_errors as f64 / self.current.packets_received as f64
-        } else {
-            0.0
-        }
+        sanitize_rate(self.current.receive_errors as f64, self.current.packets_received as f64)
     }
 
     /// Calculates the error rate for sent packets.
     pub fn send_error_rate(&self) -> f64 {
-        if self.current.packets_sent > 0 {
-            self.current.send_errors as f64 / self.current.packets_sent as f64
-        } else {
-            0.0
-        }
+        sanitize_rate(self.current.send_errors as f64, self.current.packets_sent as f64)
+    }
+
+    /// The per-second rate of a counter since the previous data point; 0.0 without one or if the counter was reset.
+    fn per_second(&self, counter: impl Fn(&TrafficData) -> u64) -> f64 {
+        self.previous.map_or(0.0, |prev| {
+            let diff = counter(&self.current).saturating_sub(counter(&prev));
+            let time_diff = self.current.timestamp.duration_since(prev.timestamp).as_secs_f64();
+            sanitize_rate(diff as f64, time_diff)
+        })
     }
 }
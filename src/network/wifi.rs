@@ -0,0 +1,611 @@
+//! Signal and connection details of the Wi-Fi interface.
+//!
+//! CoreWLAN's `CWInterface` reports the network the interface is associated with: its SSID and BSSID, the signal
+//! strength and noise, the channel, the PHY mode and the transmit rate. The interface is looked up by name through
+//! `CWWiFiClient` on every read, so a [`WifiMonitor`] follows the connection as it changes.
+//!
+//! Since macOS 14 the SSID and BSSID are only reported to processes the user allowed to use Location Services;
+//! without that permission they read as not available while the signal details still work.
+
+use objc2::{class, msg_send, rc::autoreleasepool, runtime::AnyObject};
+use objc2_foundation::NSString;
+
+use crate::error::{Error, Result};
+
+/// 802.11 PHY mode of the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PhyMode {
+    /// 802.11a
+    A,
+    /// 802.11b
+    B,
+    /// 802.11g
+    G,
+    /// 802.11n (Wi-Fi 4)
+    N,
+    /// 802.11ac (Wi-Fi 5)
+    Ac,
+    /// 802.11ax (Wi-Fi 6 and 6E)
+    Ax,
+    /// 802.11be (Wi-Fi 7)
+    Be,
+}
+
+/// Frequency band of a Wi-Fi channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WifiBand {
+    /// 2.4 GHz
+    Band2GHz,
+    /// 5 GHz
+    Band5GHz,
+    /// 6 GHz
+    Band6GHz,
+}
+
+/// Channel the Wi-Fi interface operates on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WifiChannel {
+    /// Channel number
+    pub number: u32,
+    /// Frequency band, None if CoreWLAN doesn't know it
+    pub band: Option<WifiBand>,
+    /// Channel width in MHz, None if CoreWLAN doesn't know it
+    pub width_mhz: Option<u32>,
+}
+
+/// Connection details of the Wi-Fi interface
+///
+/// Each field is None where it's not available, like the SSID without Location Services permission.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WifiInfo {
+    /// Name of the network
+    pub ssid: Option<String>,
+    /// MAC address of the access point
+    pub bssid: Option<String>,
+    /// Received signal strength in dBm
+    pub rssi_dbm: Option<i32>,
+    /// Noise level in dBm
+    pub noise_dbm: Option<i32>,
+    /// Channel of the connection
+    pub channel: Option<WifiChannel>,
+    /// PHY mode of the connection
+    pub phy_mode: Option<PhyMode>,
+    /// Transmit rate in Mbit/s
+    pub tx_rate_mbps: Option<f64>,
+    /// Country code the interface adopted from the access points around, e.g. "DE"
+    pub country_code: Option<String>,
+}
+
+/// `CWChannel` properties as CoreWLAN reports them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct RawChannel {
+    pub number: i64,
+    /// `CWChannelBand`
+    pub band: i64,
+    /// `CWChannelWidth`
+    pub width: i64,
+}
+
+/// `CWInterface` properties as CoreWLAN reports them, with 0 and None where it has no value
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct RawWifiState {
+    pub power_on: bool,
+    pub ssid: Option<String>,
+    pub bssid: Option<String>,
+    pub rssi: i64,
+    pub noise: i64,
+    pub channel: Option<RawChannel>,
+    /// `CWPHYMode`
+    pub phy_mode: i64,
+    pub transmit_rate: f64,
+    pub country_code: Option<String>,
+}
+
+impl RawWifiState {
+    /// Whether the interface is associated with a network, which the signal strength is only reported for.
+    fn is_associated(&self) -> bool {
+        self.power_on && self.rssi != 0
+    }
+
+    fn ssid(&self) -> Result<String> {
+        match &self.ssid {
+            Some(ssid) if !ssid.is_empty() => Ok(ssid.clone()),
+            _ if self.is_associated() => {
+                Err(Error::not_available("Wi-Fi SSID, which needs Location Services permission"))
+            },
+            _ => Err(not_associated()),
+        }
+    }
+
+    fn bssid(&self) -> Result<String> {
+        match &self.bssid {
+            Some(bssid) if !bssid.is_empty() => Ok(bssid.clone()),
+            _ if self.is_associated() => {
+                Err(Error::not_available("Wi-Fi BSSID, which needs Location Services permission"))
+            },
+            _ => Err(not_associated()),
+        }
+    }
+
+    fn rssi_dbm(&self) -> Result<i32> {
+        self.associated(self.rssi as i32)
+    }
+
+    fn noise_dbm(&self) -> Result<i32> {
+        if self.noise == 0 {
+            return Err(not_associated());
+        }
+        self.associated(self.noise as i32)
+    }
+
+    fn channel(&self) -> Result<WifiChannel> {
+        let channel =
+            self.channel.filter(|channel| channel.number > 0).ok_or_else(not_associated)?;
+        self.associated(WifiChannel {
+            number: channel.number as u32,
+            band: band_from_raw(channel.band),
+            width_mhz: width_from_raw(channel.width),
+        })
+    }
+
+    fn phy_mode(&self) -> Result<PhyMode> {
+        let mode = phy_mode_from_raw(self.phy_mode)
+            .ok_or_else(|| Error::not_available(format!("Wi-Fi PHY mode {}", self.phy_mode)))?;
+        self.associated(mode)
+    }
+
+    fn tx_rate_mbps(&self) -> Result<f64> {
+        if !self.transmit_rate.is_finite() || self.transmit_rate <= 0.0 {
+            return Err(not_associated());
+        }
+        self.associated(self.transmit_rate)
+    }
+
+    fn country_code(&self) -> Result<String> {
+        self.country_code
+            .clone()
+            .filter(|code| !code.is_empty())
+            .ok_or_else(|| Error::not_available("Wi-Fi country code"))
+    }
+
+    fn associated<T>(&self, value: T) -> Result<T> {
+        if self.is_associated() {
+            Ok(value)
+        } else {
+            Err(not_associated())
+        }
+    }
+}
+
+impl From<&RawWifiState> for WifiInfo {
+    fn from(state: &RawWifiState) -> Self {
+        Self {
+            ssid: state.ssid().ok(),
+            bssid: state.bssid().ok(),
+            rssi_dbm: state.rssi_dbm().ok(),
+            noise_dbm: state.noise_dbm().ok(),
+            channel: state.channel().ok(),
+            phy_mode: state.phy_mode().ok(),
+            tx_rate_mbps: state.tx_rate_mbps().ok(),
+            country_code: state.country_code().ok(),
+        }
+    }
+}
+
+fn not_associated() -> Error {
+    Error::not_available("Wi-Fi is off or not associated with a network")
+}
+
+/// The PHY mode of a `CWPHYMode`, None for `kCWPHYModeNone` and unknown values.
+pub(crate) fn phy_mode_from_raw(mode: i64) -> Option<PhyMode> {
+    match mode {
+        1 => Some(PhyMode::A),
+        2 => Some(PhyMode::B),
+        3 => Some(PhyMode::G),
+        4 => Some(PhyMode::N),
+        5 => Some(PhyMode::Ac),
+        6 => Some(PhyMode::Ax),
+        7 => Some(PhyMode::Be),
+        _ => None,
+    }
+}
+
+/// The band of a `CWChannelBand`, None for `kCWChannelBandUnknown`.
+pub(crate) fn band_from_raw(band: i64) -> Option<WifiBand> {
+    match band {
+        1 => Some(WifiBand::Band2GHz),
+        2 => Some(WifiBand::Band5GHz),
+        3 => Some(WifiBand::Band6GHz),
+        _ => None,
+    }
+}
+
+/// The width in MHz of a `CWChannelWidth`, None for `kCWChannelWidthUnknown`.
+pub(crate) fn width_from_raw(width: i64) -> Option<u32> {
+    match width {
+        1 => Some(20),
+        2 => Some(40),
+        3 => Some(80),
+        4 => Some(160),
+        _ => None,
+    }
+}
+
+/// Reads the state of a Wi-Fi interface
+pub(crate) trait WifiSource: Send + Sync {
+    /// Name of the interface, e.g. "en0"
+    fn interface_name(&self) -> &str;
+    /// Current state of the interface, None if it no longer exists
+    fn read(&self) -> Option<RawWifiState>;
+}
+
+/// Wi-Fi interface read through CoreWLAN
+#[derive(Debug, Clone)]
+struct CoreWlanInterface {
+    name: String,
+}
+
+impl CoreWlanInterface {
+    /// The default Wi-Fi interface, None on Macs without Wi-Fi.
+    fn default_interface() -> Option<Self> {
+        autoreleasepool(|_| unsafe {
+            let client: *mut AnyObject = msg_send![class!(CWWiFiClient), sharedWiFiClient];
+            if client.is_null() {
+                return None;
+            }
+            let interface: *mut AnyObject = msg_send![client, interface];
+            if interface.is_null() {
+                return None;
+            }
+            let name: *mut NSString = msg_send![interface, interfaceName];
+            Some(Self { name: string(name)? })
+        })
+    }
+
+    /// The Wi-Fi interface named `name`, None if there is no such Wi-Fi interface.
+    fn named(name: &str) -> Option<Self> {
+        let interface = Self { name: name.to_string() };
+        interface.read().map(|_| interface)
+    }
+}
+
+impl WifiSource for CoreWlanInterface {
+    fn interface_name(&self) -> &str {
+        &self.name
+    }
+
+    fn read(&self) -> Option<RawWifiState> {
+        autoreleasepool(|_| unsafe {
+            let client: *mut AnyObject = msg_send![class!(CWWiFiClient), sharedWiFiClient];
+            if client.is_null() {
+                return None;
+            }
+            let name = NSString::from_str(&self.name);
+            let interface: *mut AnyObject = msg_send![client, interfaceWithName: &*name];
+            if interface.is_null() {
+                return None;
+            }
+
+            let channel: *mut AnyObject = msg_send![interface, wlanChannel];
+            let channel = (!channel.is_null()).then(|| {
+                let number: isize = msg_send![channel, channelNumber];
+                let band: isize = msg_send![channel, channelBand];
+                let width: isize = msg_send![channel, channelWidth];
+                RawChannel { number: number as i64, band: band as i64, width: width as i64 }
+            });
+
+            let power_on: bool = msg_send![interface, powerOn];
+            let ssid: *mut NSString = msg_send![interface, ssid];
+            let bssid: *mut NSString = msg_send![interface, bssid];
+            let rssi: isize = msg_send![interface, rssiValue];
+            let noise: isize = msg_send![interface, noiseMeasurement];
+            let phy_mode: isize = msg_send![interface, activePHYMode];
+            let transmit_rate: f64 = msg_send![interface, transmitRate];
+            let country_code: *mut NSString = msg_send![interface, countryCode];
+
+            Some(RawWifiState {
+                power_on,
+                ssid: string(ssid),
+                bssid: string(bssid),
+                rssi: rssi as i64,
+                noise: noise as i64,
+                channel,
+                phy_mode: phy_mode as i64,
+                transmit_rate,
+                country_code: string(country_code),
+            })
+        })
+    }
+}
+
+/// Copies an autoreleased NSString, None for nil.
+///
+/// # Safety
+///
+/// `string` must be nil or point to an NSString that stays alive for the duration of the call.
+unsafe fn string(string: *mut NSString) -> Option<String> {
+    string.as_ref().map(|string| string.to_string())
+}
+
+/// Monitors the connection of a Wi-Fi interface
+///
+/// Every accessor reads the current state from CoreWLAN. It returns [`Error::NotAvailable`] when the interface is off
+/// or not associated, or when the value needs a permission the process doesn't have, which only affects that value.
+///
+/// ```rust,no_run
+/// use darwin_metrics::network::WifiMonitor;
+///
+/// # fn main() -> darwin_metrics::Result<()> {
+/// let wifi = WifiMonitor::new()?;
+/// println!("{}: {} dBm", wifi.interface_name(), wifi.rssi_dbm()?);
+/// match wifi.ssid() {
+///     Ok(ssid) => println!("Connected to {ssid}"),
+///     Err(e) => println!("SSID unavailable: {e}"),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct WifiMonitor {
+    source: Box<dyn WifiSource>,
+}
+
+impl std::fmt::Debug for WifiMonitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WifiMonitor").field("interface", &self.interface_name()).finish()
+    }
+}
+
+impl WifiMonitor {
+    /// Monitors the default Wi-Fi interface.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotAvailable`] on Macs without Wi-Fi.
+    pub fn new() -> Result<Self> {
+        let interface = CoreWlanInterface::default_interface()
+            .ok_or_else(|| Error::not_available("Wi-Fi interface"))?;
+        Ok(Self::with_source(interface))
+    }
+
+    /// Monitors the Wi-Fi interface named `name`, e.g. "en0".
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotAvailable`] if there is no Wi-Fi interface with that name.
+    pub fn with_interface(name: &str) -> Result<Self> {
+        let interface = CoreWlanInterface::named(name)
+            .ok_or_else(|| Error::not_available(format!("Wi-Fi interface {name}")))?;
+        Ok(Self::with_source(interface))
+    }
+
+    pub(crate) fn with_source(source: impl WifiSource + 'static) -> Self {
+        Self { source: Box::new(source) }
+    }
+
+    /// Name of the monitored interface.
+    pub fn interface_name(&self) -> &str {
+        self.source.interface_name()
+    }
+
+    /// Name of the network, which needs Location Services permission since macOS 14.
+    pub fn ssid(&self) -> Result<String> {
+        self.state()?.ssid()
+    }
+
+    /// MAC address of the access point, which needs Location Services permission since macOS 14.
+    pub fn bssid(&self) -> Result<String> {
+        self.state()?.bssid()
+    }
+
+    /// Received signal strength in dBm.
+    pub fn rssi_dbm(&self) -> Result<i32> {
+        self.state()?.rssi_dbm()
+    }
+
+    /// Noise level in dBm.
+    pub fn noise_dbm(&self) -> Result<i32> {
+        self.state()?.noise_dbm()
+    }
+
+    /// Channel of the connection.
+    pub fn channel(&self) -> Result<WifiChannel> {
+        self.state()?.channel()
+    }
+
+    /// PHY mode of the connection.
+    pub fn phy_mode(&self) -> Result<PhyMode> {
+        self.state()?.phy_mode()
+    }
+
+    /// Transmit rate in Mbit/s.
+    pub fn tx_rate_mbps(&self) -> Result<f64> {
+        self.state()?.tx_rate_mbps()
+    }
+
+    /// Country code the interface adopted from the access points around.
+    pub fn country_code(&self) -> Result<String> {
+        self.state()?.country_code()
+    }
+
+    /// Reads all details at once, leaving those that aren't available None.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotAvailable`] only if the interface no longer exists.
+    pub fn info(&self) -> Result<WifiInfo> {
+        Ok(WifiInfo::from(&self.state()?))
+    }
+
+    fn state(&self) -> Result<RawWifiState> {
+        self.source.read().ok_or_else(|| {
+            Error::not_available(format!("Wi-Fi interface {}", self.interface_name()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wi-Fi source returning a fixed state
+    struct FakeSource(Option<RawWifiState>);
+
+    impl WifiSource for FakeSource {
+        fn interface_name(&self) -> &str {
+            "en0"
+        }
+
+        fn read(&self) -> Option<RawWifiState> {
+            self.0.clone()
+        }
+    }
+
+    fn associated() -> RawWifiState {
+        RawWifiState {
+            power_on: true,
+            ssid: Some("Home".to_string()),
+            bssid: Some("a0:b1:c2:d3:e4:f5".to_string()),
+            rssi: -52,
+            noise: -94,
+            channel: Some(RawChannel { number: 36, band: 2, width: 3 }),
+            phy_mode: 6,
+            transmit_rate: 864.0,
+            country_code: Some("DE".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_associated() {
+        let wifi = WifiMonitor::with_source(FakeSource(Some(associated())));
+
+        assert_eq!(wifi.interface_name(), "en0");
+        assert_eq!(wifi.ssid().unwrap(), "Home");
+        assert_eq!(wifi.bssid().unwrap(), "a0:b1:c2:d3:e4:f5");
+        assert_eq!(wifi.rssi_dbm().unwrap(), -52);
+        assert_eq!(wifi.noise_dbm().unwrap(), -94);
+        assert_eq!(
+            wifi.channel().unwrap(),
+            WifiChannel { number: 36, band: Some(WifiBand::Band5GHz), width_mhz: Some(80) }
+        );
+        assert_eq!(wifi.phy_mode().unwrap(), PhyMode::Ax);
+        assert_eq!(wifi.tx_rate_mbps().unwrap(), 864.0);
+        assert_eq!(wifi.country_code().unwrap(), "DE");
+    }
+
+    #[test]
+    fn test_without_location_permission() {
+        let state = RawWifiState { ssid: None, bssid: None, ..associated() };
+        let wifi = WifiMonitor::with_source(FakeSource(Some(state)));
+
+        let error = wifi.ssid().unwrap_err();
+        assert!(error.is_not_available());
+        assert!(error.to_string().contains("Location Services"));
+        assert!(wifi.bssid().unwrap_err().is_not_available());
+
+        // The other details are still reported
+        assert_eq!(wifi.rssi_dbm().unwrap(), -52);
+        let info = wifi.info().unwrap();
+        assert_eq!(info.ssid, None);
+        assert_eq!(info.bssid, None);
+        assert_eq!(info.rssi_dbm, Some(-52));
+        assert_eq!(info.phy_mode, Some(PhyMode::Ax));
+    }
+
+    #[test]
+    fn test_not_associated() {
+        let state = RawWifiState {
+            power_on: true,
+            country_code: Some("DE".to_string()),
+            ..RawWifiState::default()
+        };
+        let wifi = WifiMonitor::with_source(FakeSource(Some(state)));
+
+        for error in [
+            wifi.ssid().unwrap_err(),
+            wifi.rssi_dbm().unwrap_err(),
+            wifi.noise_dbm().unwrap_err(),
+            wifi.channel().unwrap_err(),
+            wifi.phy_mode().unwrap_err(),
+            wifi.tx_rate_mbps().unwrap_err(),
+        ] {
+            assert!(error.is_not_available());
+        }
+        assert!(!wifi.ssid().unwrap_err().to_string().contains("Location Services"));
+        assert_eq!(wifi.country_code().unwrap(), "DE");
+    }
+
+    #[test]
+    fn test_powered_off() {
+        // CoreWLAN can keep the last values around while the interface is off
+        let state = RawWifiState { power_on: false, ..associated() };
+        let wifi = WifiMonitor::with_source(FakeSource(Some(state)));
+
+        assert!(wifi.rssi_dbm().unwrap_err().is_not_available());
+        assert_eq!(
+            wifi.info().unwrap(),
+            WifiInfo {
+                ssid: Some("Home".to_string()),
+                bssid: Some("a0:b1:c2:d3:e4:f5".to_string()),
+                country_code: Some("DE".to_string()),
+                ..WifiInfo::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_interface_gone() {
+        let wifi = WifiMonitor::with_source(FakeSource(None));
+        assert!(wifi.rssi_dbm().unwrap_err().is_not_available());
+        assert!(wifi.info().unwrap_err().is_not_available());
+    }
+
+    #[test]
+    fn test_raw_values() {
+        assert_eq!(phy_mode_from_raw(0), None);
+        assert_eq!(phy_mode_from_raw(1), Some(PhyMode::A));
+        assert_eq!(phy_mode_from_raw(4), Some(PhyMode::N));
+        assert_eq!(phy_mode_from_raw(5), Some(PhyMode::Ac));
+        assert_eq!(phy_mode_from_raw(7), Some(PhyMode::Be));
+        assert_eq!(phy_mode_from_raw(99), None);
+
+        assert_eq!(band_from_raw(0), None);
+        assert_eq!(band_from_raw(1), Some(WifiBand::Band2GHz));
+        assert_eq!(band_from_raw(3), Some(WifiBand::Band6GHz));
+
+        assert_eq!(width_from_raw(0), None);
+        assert_eq!(width_from_raw(1), Some(20));
+        assert_eq!(width_from_raw(4), Some(160));
+    }
+
+    #[test]
+    fn test_unknown_channel_details() {
+        let state = RawWifiState {
+            channel: Some(RawChannel { number: 6, band: 0, width: 0 }),
+            phy_mode: 0,
+            ..associated()
+        };
+        let wifi = WifiMonitor::with_source(FakeSource(Some(state)));
+
+        assert_eq!(wifi.channel().unwrap(), WifiChannel { number: 6, band: None, width_mhz: None });
+        assert!(wifi.phy_mode().unwrap_err().is_not_available());
+    }
+
+    #[test]
+    fn test_wifi_monitor() {
+        let wifi = match WifiMonitor::new() {
+            Ok(wifi) => wifi,
+            Err(e) => {
+                assert!(e.is_not_available(), "Macs without Wi-Fi report it as not available");
+                return;
+            },
+        };
+        assert!(!wifi.interface_name().is_empty());
+        if let Ok(rssi) = wifi.rssi_dbm() {
+            assert!(rssi < 0);
+        }
+    }
+}
@@ -0,0 +1,505 @@
+//! # Overhead Module
+//!
+//! Measures what collecting metrics costs the process doing it. An [`OverheadTracker`] wraps collection calls and
+//! records, per named collector, the wall time, the CPU time (from `getrusage(RUSAGE_SELF)` deltas) and the growth of
+//! the peak resident set size of each call. [`OverheadTracker::report`] summarizes the most recent calls as
+//! percentiles, and [`OverheadTracker::budget_exceeded`] checks them against an [`OverheadBudget`], e.g. in tests.
+//!
+//! CPU time and peak RSS are process-wide, so work done by other threads during a call is attributed to it. Measure
+//! from a quiet thread, or treat the values as upper bounds.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! use darwin_metrics::overhead::{OverheadBudget, OverheadTracker, SNAPSHOT};
+//!
+//! let tracker = OverheadTracker::new();
+//! for _ in 0..10 {
+//!     let _snapshot = tracker.snapshot();
+//! }
+//!
+//! let report = tracker.report();
+//! if let Some(snapshot) = report.get(SNAPSHOT) {
+//!     println!("snapshot p99: {:?} wall, {:?} CPU", snapshot.wall_time.p99, snapshot.cpu_time.p99);
+//! }
+//!
+//! let budget = OverheadBudget { wall_time_p99: Some(Duration::from_millis(50)), ..Default::default() };
+//! assert!(!tracker.budget_exceeded(&budget));
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    error::Result,
+    process::Process,
+    snapshot::{DerivedMetrics, MetricsSnapshot},
+};
+
+/// Number of recent calls per collector kept for the percentiles
+pub const DEFAULT_WINDOW: usize = 128;
+
+/// Collector name used by [`OverheadTracker::snapshot`] and [`OverheadTracker::snapshot_with`]
+pub const SNAPSHOT: &str = "snapshot";
+
+/// Collector name used by [`OverheadTracker::processes`]
+pub const PROCESSES: &str = "processes";
+
+/// Resources used by a single instrumented call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallCost {
+    /// Elapsed wall-clock time
+    pub wall_time: Duration,
+    /// User and system CPU time used by the process during the call
+    pub cpu_time: Duration,
+    /// Growth of the peak resident set size during the call, in bytes
+    pub peak_rss_growth: u64,
+}
+
+/// Percentiles of a duration over the recent calls of a collector
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Percentiles {
+    /// Median
+    pub p50: Duration,
+    /// 90th percentile
+    pub p90: Duration,
+    /// 99th percentile
+    pub p99: Duration,
+    /// Maximum
+    pub max: Duration,
+}
+
+impl Percentiles {
+    /// Computes nearest-rank percentiles of `values`, or all zeros if there are none.
+    pub fn from_values(values: &[Duration]) -> Self {
+        let mut sorted = values.to_vec();
+        sorted.sort_unstable();
+
+        Self {
+            p50: nearest_rank(&sorted, 50.0),
+            p90: nearest_rank(&sorted, 90.0),
+            p99: nearest_rank(&sorted, 99.0),
+            max: sorted.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// The value below which `percentile` percent of the sorted values fall, using the nearest-rank method.
+fn nearest_rank(sorted: &[Duration], percentile: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Overhead of one collector
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectorOverhead {
+    /// Collector name
+    pub name: String,
+    /// Total number of calls since the tracker was created or reset
+    pub calls: u64,
+    /// Number of recent calls the percentiles are computed over
+    pub window: usize,
+    /// Wall time percentiles
+    pub wall_time: Percentiles,
+    /// CPU time percentiles
+    pub cpu_time: Percentiles,
+    /// Largest growth of the peak RSS caused by a single recent call, in bytes
+    pub max_peak_rss_growth: u64,
+}
+
+/// Upper bounds on collection overhead
+///
+/// Bounds left as `None` aren't checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OverheadBudget {
+    /// Maximum 99th percentile wall time of any collector
+    pub wall_time_p99: Option<Duration>,
+    /// Maximum 99th percentile CPU time of any collector
+    pub cpu_time_p99: Option<Duration>,
+    /// Maximum peak RSS growth of a single call, in bytes
+    pub peak_rss_growth: Option<u64>,
+}
+
+impl OverheadBudget {
+    /// Whether `collector` stays within the budget.
+    pub fn allows(&self, collector: &CollectorOverhead) -> bool {
+        self.wall_time_p99.is_none_or(|max| collector.wall_time.p99 <= max)
+            && self.cpu_time_p99.is_none_or(|max| collector.cpu_time.p99 <= max)
+            && self.peak_rss_growth.is_none_or(|max| collector.max_peak_rss_growth <= max)
+    }
+}
+
+/// Overhead of every instrumented collector
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OverheadReport {
+    /// Collectors sorted by name
+    pub collectors: Vec<CollectorOverhead>,
+}
+
+impl OverheadReport {
+    /// The overhead of the collector named `name`, if it has been called.
+    pub fn get(&self, name: &str) -> Option<&CollectorOverhead> {
+        self.collectors.iter().find(|collector| collector.name == name)
+    }
+
+    /// The collectors that exceed `budget`.
+    pub fn over_budget<'a>(
+        &'a self,
+        budget: &'a OverheadBudget,
+    ) -> impl Iterator<Item = &'a CollectorOverhead> + 'a {
+        self.collectors.iter().filter(move |collector| !budget.allows(collector))
+    }
+}
+
+#[derive(Debug, Default)]
+struct CollectorRecord {
+    calls: u64,
+    recent: VecDeque<CallCost>,
+}
+
+/// Records the overhead of metric collection calls
+///
+/// Cloning a tracker is cheap; clones share the same records, so a tracker can be handed to several call sites or
+/// captured by a derived metric.
+#[derive(Debug, Clone)]
+pub struct OverheadTracker {
+    records: Arc<Mutex<HashMap<String, CollectorRecord>>>,
+    window: usize,
+}
+
+impl Default for OverheadTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OverheadTracker {
+    /// Creates a tracker keeping the last [`DEFAULT_WINDOW`] calls of each collector.
+    pub fn new() -> Self {
+        Self::with_window(DEFAULT_WINDOW)
+    }
+
+    /// Creates a tracker keeping the last `window` calls of each collector.
+    pub fn with_window(window: usize) -> Self {
+        Self { records: Arc::default(), window: window.max(1) }
+    }
+
+    /// Runs `f` and records its cost under `collector`.
+    pub fn measure<T>(&self, collector: &str, f: impl FnOnce() -> T) -> T {
+        let start = Measurement::start();
+        let result = f();
+        self.record(collector, start.finish());
+        result
+    }
+
+    /// Awaits `future` and records its cost under `collector`.
+    ///
+    /// CPU time covers everything the process did until the future completed, including other tasks.
+    pub async fn measure_async<T>(&self, collector: &str, future: impl Future<Output = T>) -> T {
+        let start = Measurement::start();
+        let result = future.await;
+        self.record(collector, start.finish());
+        result
+    }
+
+    /// Records the cost of a call measured elsewhere.
+    pub fn record(&self, collector: &str, cost: CallCost) {
+        let mut records = self.records.lock();
+        let record = records.entry(collector.to_string()).or_default();
+
+        record.calls += 1;
+        if record.recent.len() == self.window {
+            record.recent.pop_front();
+        }
+        record.recent.push_back(cost);
+    }
+
+    /// Collects a [`MetricsSnapshot`], recording the cost under [`SNAPSHOT`].
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.measure(SNAPSHOT, MetricsSnapshot::collect)
+    }
+
+    /// Collects a [`MetricsSnapshot`] with derived metrics, recording the cost under [`SNAPSHOT`].
+    ///
+    /// Overhead metrics registered with [`OverheadTracker::register_derived`] reflect the calls before this one.
+    pub fn snapshot_with(&self, derived: &DerivedMetrics) -> MetricsSnapshot {
+        self.measure(SNAPSHOT, || MetricsSnapshot::collect_with(derived))
+    }
+
+    /// Lists all processes with [`Process::get_all`], recording the cost under [`PROCESSES`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of [`Process::get_all`].
+    pub async fn processes(&self) -> Result<Vec<Process>> {
+        self.measure_async(PROCESSES, Process::get_all()).await
+    }
+
+    /// Summarizes the recent calls of every collector.
+    pub fn report(&self) -> OverheadReport {
+        let records = self.records.lock();
+
+        let mut collectors: Vec<CollectorOverhead> = records
+            .iter()
+            .map(|(name, record)| {
+                let wall: Vec<Duration> = record.recent.iter().map(|cost| cost.wall_time).collect();
+                let cpu: Vec<Duration> = record.recent.iter().map(|cost| cost.cpu_time).collect();
+
+                CollectorOverhead {
+                    name: name.clone(),
+                    calls: record.calls,
+                    window: record.recent.len(),
+                    wall_time: Percentiles::from_values(&wall),
+                    cpu_time: Percentiles::from_values(&cpu),
+                    max_peak_rss_growth: record
+                        .recent
+                        .iter()
+                        .map(|cost| cost.peak_rss_growth)
+                        .max()
+                        .unwrap_or(0),
+                }
+            })
+            .collect();
+        collectors.sort_by(|a, b| a.name.cmp(&b.name));
+
+        OverheadReport { collectors }
+    }
+
+    /// Whether any collector exceeds `budget`.
+    pub fn budget_exceeded(&self, budget: &OverheadBudget) -> bool {
+        self.report().over_budget(budget).next().is_some()
+    }
+
+    /// Discards all records.
+    pub fn reset(&self) {
+        self.records.lock().clear();
+    }
+
+    /// Registers derived metrics reporting the overhead of `collector`.
+    ///
+    /// Adds `overhead_<collector>_wall_ms_p99` and `overhead_<collector>_cpu_ms_p99`, in milliseconds, so agents can
+    /// alert on their own overhead. The metrics are omitted until the collector has been called.
+    pub fn register_derived(&self, derived: &mut DerivedMetrics, collector: &str) {
+        self.register_percentile(derived, collector, "wall", |c| c.wall_time.p99);
+        self.register_percentile(derived, collector, "cpu", |c| c.cpu_time.p99);
+    }
+
+    fn register_percentile(
+        &self,
+        derived: &mut DerivedMetrics,
+        collector: &str,
+        kind: &str,
+        percentile: fn(&CollectorOverhead) -> Duration,
+    ) {
+        let tracker = self.clone();
+        let name = collector.to_string();
+        derived.register(
+            format!("overhead_{collector}_{kind}_ms_p99"),
+            move |_: &MetricsSnapshot| {
+                let report = tracker.report();
+                report.get(&name).map(|c| percentile(c).as_secs_f64() * 1000.0)
+            },
+        );
+    }
+}
+
+/// Process resource usage at the start of a measured call
+struct Measurement {
+    started: Instant,
+    usage: Option<libc::rusage>,
+}
+
+impl Measurement {
+    fn start() -> Self {
+        Self { usage: self_usage(), started: Instant::now() }
+    }
+
+    fn finish(self) -> CallCost {
+        let wall_time = self.started.elapsed();
+        let (cpu_time, peak_rss_growth) = match (self.usage, self_usage()) {
+            (Some(before), Some(after)) => (
+                cpu_time(&after).saturating_sub(cpu_time(&before)),
+                // ru_maxrss is reported in bytes on macOS
+                (after.ru_maxrss - before.ru_maxrss).max(0) as u64,
+            ),
+            _ => (Duration::ZERO, 0),
+        };
+
+        CallCost { wall_time, cpu_time, peak_rss_growth }
+    }
+}
+
+fn self_usage() -> Option<libc::rusage> {
+    // SAFETY: rusage is plain data, so all zeros is a valid value for getrusage to overwrite.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    // SAFETY: `usage` is valid for writes.
+    (unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } == 0).then_some(usage)
+}
+
+fn cpu_time(usage: &libc::rusage) -> Duration {
+    timeval_to_duration(&usage.ru_utime) + timeval_to_duration(&usage.ru_stime)
+}
+
+fn timeval_to_duration(time: &libc::timeval) -> Duration {
+    Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(value: u64) -> Duration {
+        Duration::from_millis(value)
+    }
+
+    fn cost(wall_ms: u64, cpu_ms: u64) -> CallCost {
+        CallCost { wall_time: ms(wall_ms), cpu_time: ms(cpu_ms), peak_rss_growth: 0 }
+    }
+
+    #[test]
+    fn test_percentiles_nearest_rank() {
+        let values: Vec<Duration> = (1..=100).rev().map(ms).collect();
+        let percentiles = Percentiles::from_values(&values);
+
+        assert_eq!(percentiles.p50, ms(50));
+        assert_eq!(percentiles.p90, ms(90));
+        assert_eq!(percentiles.p99, ms(99));
+        assert_eq!(percentiles.max, ms(100));
+
+        let few = Percentiles::from_values(&[ms(30), ms(10), ms(20)]);
+        assert_eq!((few.p50, few.p90, few.p99, few.max), (ms(20), ms(30), ms(30), ms(30)));
+
+        assert_eq!(Percentiles::from_values(&[]), Percentiles::default());
+    }
+
+    #[test]
+    fn test_report_from_recorded_costs() {
+        let tracker = OverheadTracker::with_window(4);
+        for wall in [5, 1, 3, 2, 4] {
+            tracker.record("cpu", cost(wall, wall / 2));
+        }
+        tracker.record("memory", CallCost { peak_rss_growth: 4096, ..cost(7, 1) });
+
+        let report = tracker.report();
+        let names: Vec<&str> = report.collectors.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["cpu", "memory"]);
+
+        // The oldest call (5 ms) has left the window
+        let cpu = report.get("cpu").unwrap();
+        assert_eq!(cpu.calls, 5);
+        assert_eq!(cpu.window, 4);
+        assert_eq!(cpu.wall_time.p50, ms(2));
+        assert_eq!(cpu.wall_time.max, ms(4));
+        assert_eq!(cpu.cpu_time.max, ms(2));
+
+        assert_eq!(report.get("memory").unwrap().max_peak_rss_growth, 4096);
+        assert!(report.get("disk").is_none());
+    }
+
+    #[test]
+    fn test_budget() {
+        let tracker = OverheadTracker::new();
+        tracker.record("cpu", cost(10, 2));
+        tracker.record("memory", CallCost { peak_rss_growth: 1 << 20, ..cost(1, 1) });
+
+        assert!(!tracker.budget_exceeded(&OverheadBudget::default()));
+
+        let wall = OverheadBudget { wall_time_p99: Some(ms(5)), ..Default::default() };
+        assert!(tracker.budget_exceeded(&wall));
+        let report = tracker.report();
+        let over: Vec<&str> = report.over_budget(&wall).map(|c| c.name.as_str()).collect();
+        assert_eq!(over, vec!["cpu"]);
+
+        let cpu = OverheadBudget { cpu_time_p99: Some(ms(2)), ..Default::default() };
+        assert!(!tracker.budget_exceeded(&cpu));
+
+        let rss = OverheadBudget { peak_rss_growth: Some(1024), ..Default::default() };
+        assert!(tracker.budget_exceeded(&rss));
+
+        tracker.reset();
+        assert!(!tracker.budget_exceeded(&wall));
+    }
+
+    #[test]
+    fn test_measure_records_calls() {
+        let tracker = OverheadTracker::new();
+
+        let value = tracker.measure("sleep", || {
+            std::thread::sleep(ms(20));
+            42
+        });
+        assert_eq!(value, 42);
+
+        let busy = tracker.measure("busy", || {
+            let start = Instant::now();
+            let mut x = 0u64;
+            while start.elapsed() < ms(50) {
+                x = std::hint::black_box(x.wrapping_add(1));
+            }
+            x
+        });
+        assert!(busy > 0);
+
+        let report = tracker.report();
+        let sleep = report.get("sleep").unwrap();
+        assert_eq!(sleep.calls, 1);
+        assert!(sleep.wall_time.max >= ms(20));
+
+        // getrusage has a coarse resolution, so only require a fraction of the busy time
+        let busy = report.get("busy").unwrap();
+        assert!(busy.wall_time.max >= ms(50));
+        assert!(busy.cpu_time.max >= ms(10), "cpu time: {:?}", busy.cpu_time.max);
+    }
+
+    #[tokio::test]
+    async fn test_measure_async_records_calls() {
+        let tracker = OverheadTracker::new();
+        let value = tracker.measure_async("async", async { 7 }).await;
+
+        assert_eq!(value, 7);
+        assert_eq!(tracker.report().get("async").unwrap().calls, 1);
+    }
+
+    #[test]
+    fn test_register_derived() {
+        let tracker = OverheadTracker::new();
+        let mut derived = DerivedMetrics::new();
+        tracker.register_derived(&mut derived, SNAPSHOT);
+
+        let snapshot = MetricsSnapshot::default();
+        assert!(derived.evaluate(&snapshot).0.is_empty());
+
+        tracker.record(SNAPSHOT, cost(12, 3));
+        let (values, warnings) = derived.evaluate(&snapshot);
+        assert!(warnings.is_empty());
+        assert_eq!(values.get("overhead_snapshot_wall_ms_p99"), Some(&12.0));
+        assert_eq!(values.get("overhead_snapshot_cpu_ms_p99"), Some(&3.0));
+    }
+
+    #[test]
+    fn test_tracker_overhead_is_bounded() {
+        let tracker = OverheadTracker::new();
+        let calls = 10_000;
+
+        let start = Instant::now();
+        for _ in 0..calls {
+            tracker.measure("noop", || ());
+        }
+        let per_call = start.elapsed() / calls;
+
+        // Two getrusage calls and a map update; a coarse bound that still catches accidental blocking
+        assert!(per_call < Duration::from_micros(100), "tracker overhead per call: {per_call:?}");
+        assert_eq!(tracker.report().get("noop").unwrap().calls, calls as u64);
+    }
+}
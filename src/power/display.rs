@@ -0,0 +1,270 @@
+//! Display state and backlight brightness.
+//!
+//! The backlight is one of the largest power consumers of a laptop, so power draw is easier to interpret next to the
+//! screen state. Brightness is read from the `IODisplayParameters` of the backlight entries in the IORegistry:
+//! `AppleARMBacklight` on Apple Silicon and `AppleBacklightDisplay` on Intel. Whether the displays are asleep comes
+//! from the power state of the `IODisplayWrangler`, or per display from CoreGraphics with the `coregraphics` feature.
+
+use std::ffi::CString;
+
+use objc2::rc::autoreleasepool;
+use objc2_foundation::{NSDictionary, NSObject, NSString};
+
+use crate::{
+    error::{Error, Result},
+    hardware::iokit::{IOKit, IOKitImpl},
+    utils::{
+        bindings::{
+            IOIteratorNext, IOObjectRelease, IOServiceGetMatchingServices, IOServiceMatching,
+        },
+        property_utils::{PropertyAccessor, PropertyUtils},
+    },
+};
+
+/// Registry class of the built-in panel backlight on Apple Silicon
+const ARM_BACKLIGHT_CLASS: &str = "AppleARMBacklight";
+/// Registry class of the built-in panel on Intel Macs
+const BACKLIGHT_DISPLAY_CLASS: &str = "AppleBacklightDisplay";
+/// Registry class of external displays
+const EXTERNAL_DISPLAY_CLASS: &str = "AppleDisplay";
+/// Registry class of the service that powers the displays down when idle
+#[cfg(not(feature = "coregraphics"))]
+const DISPLAY_WRANGLER_CLASS: &str = "IODisplayWrangler";
+
+/// Registry property holding the adjustable display parameters
+const DISPLAY_PARAMETERS_KEY: &str = "IODisplayParameters";
+/// Display parameter holding the brightness as `min`, `max` and `value`
+const BRIGHTNESS_KEY: &str = "brightness";
+/// Registry property holding the power management state of a service
+#[cfg(not(feature = "coregraphics"))]
+const POWER_MANAGEMENT_KEY: &str = "IOPowerManagement";
+/// Power management property holding the current power state
+#[cfg(not(feature = "coregraphics"))]
+const CURRENT_POWER_STATE_KEY: &str = "CurrentPowerState";
+/// Display wrangler power states below this one (dimmed) mean the displays are asleep
+#[cfg(not(feature = "coregraphics"))]
+const DISPLAY_DIM_POWER_STATE: i64 = 3;
+
+/// Power-relevant state of a display
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayPower {
+    /// CoreGraphics display ID with the `coregraphics` feature, otherwise the position of the display in the
+    /// IORegistry
+    pub id: u32,
+    /// Whether the display is asleep
+    pub is_asleep: bool,
+    /// Backlight brightness between 0.0 and 1.0, or None if the panel doesn't expose it (e.g. external monitors)
+    pub brightness: Option<f32>,
+    /// Whether this is the built-in panel of a laptop or iMac
+    pub is_builtin: bool,
+}
+
+/// A display entry found in the IORegistry
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct RegistryDisplay {
+    pub is_builtin: bool,
+    pub brightness: Option<f32>,
+}
+
+impl RegistryDisplay {
+    /// Reads the brightness from the registry properties of a display or backlight entry.
+    pub(crate) fn from_properties(
+        properties: &NSDictionary<NSString, NSObject>,
+        is_builtin: bool,
+    ) -> Self {
+        let brightness = PropertyAccessor::get_dict_property(properties, DISPLAY_PARAMETERS_KEY)
+            .and_then(|parameters| PropertyAccessor::get_dict_property(&parameters, BRIGHTNESS_KEY))
+            .and_then(|brightness| {
+                normalize_brightness(
+                    PropertyAccessor::get_number_property(&brightness, "min")?,
+                    PropertyAccessor::get_number_property(&brightness, "max")?,
+                    PropertyAccessor::get_number_property(&brightness, "value")?,
+                )
+            });
+
+        Self { is_builtin, brightness }
+    }
+}
+
+/// A display reported online by the window server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OnlineDisplay {
+    pub id: u32,
+    pub is_builtin: bool,
+    pub is_asleep: bool,
+}
+
+/// Scales a raw brightness parameter into 0.0..=1.0, or None if the range is empty.
+pub(crate) fn normalize_brightness(min: f64, max: f64, value: f64) -> Option<f32> {
+    if max <= min {
+        return None;
+    }
+    Some(((value - min) / (max - min)).clamp(0.0, 1.0) as f32)
+}
+
+/// Whether the power management properties of the display wrangler mean the displays are asleep.
+#[cfg(not(feature = "coregraphics"))]
+pub(crate) fn wrangler_asleep(properties: &NSDictionary<NSString, NSObject>) -> Option<bool> {
+    let power_management = PropertyAccessor::get_dict_property(properties, POWER_MANAGEMENT_KEY)?;
+    let state = PropertyAccessor::get_number_property(&power_management, CURRENT_POWER_STATE_KEY)?;
+    Some((state as i64) < DISPLAY_DIM_POWER_STATE)
+}
+
+/// Attaches the registry brightness to the online displays.
+///
+/// The n-th built-in display gets the brightness of the n-th built-in registry entry. External displays report no
+/// brightness, as their backlight is controlled by the monitor itself.
+pub(crate) fn combine(online: &[OnlineDisplay], registry: &[RegistryDisplay]) -> Vec<DisplayPower> {
+    let mut builtin_brightness =
+        registry.iter().filter(|display| display.is_builtin).map(|display| display.brightness);
+
+    online
+        .iter()
+        .map(|display| DisplayPower {
+            id: display.id,
+            is_asleep: display.is_asleep,
+            brightness: if display.is_builtin { builtin_brightness.next().flatten() } else { None },
+            is_builtin: display.is_builtin,
+        })
+        .collect()
+}
+
+/// Returns the state of every display.
+///
+/// Without the `coregraphics` feature, displays are listed from the IORegistry and share the sleep state of the
+/// display wrangler. Headless Macs report an empty list.
+///
+/// # Errors
+///
+/// Returns an error if the IORegistry or the window server can't be queried.
+pub fn display_state() -> Result<Vec<DisplayPower>> {
+    let registry = registry_displays()?;
+    Ok(combine(&online_displays(&registry)?, &registry))
+}
+
+/// Returns the brightness of the built-in display between 0.0 and 1.0, or None if there is none or it isn't
+/// reported.
+pub fn builtin_display_brightness() -> Option<f32> {
+    registry_displays().ok()?.iter().find(|display| display.is_builtin)?.brightness
+}
+
+#[cfg(feature = "coregraphics")]
+fn online_displays(_registry: &[RegistryDisplay]) -> Result<Vec<OnlineDisplay>> {
+    use crate::utils::bindings::{CGDisplayIsAsleep, CGDisplayIsBuiltin, CGGetOnlineDisplayList};
+
+    /// More displays than any Mac can drive
+    const MAX_DISPLAYS: u32 = 32;
+
+    let mut ids = [0u32; MAX_DISPLAYS as usize];
+    let mut count = 0u32;
+    // SAFETY: the buffer has room for MAX_DISPLAYS IDs.
+    let result = unsafe { CGGetOnlineDisplayList(MAX_DISPLAYS, ids.as_mut_ptr(), &mut count) };
+    if result != 0 {
+        return Err(Error::system(format!("Failed to list online displays: CGError {result}")));
+    }
+
+    Ok(ids[..count as usize]
+        .iter()
+        .map(|&id| OnlineDisplay {
+            id,
+            // SAFETY: the IDs were just returned by CGGetOnlineDisplayList.
+            is_builtin: unsafe { CGDisplayIsBuiltin(id) } != 0,
+            is_asleep: unsafe { CGDisplayIsAsleep(id) } != 0,
+        })
+        .collect())
+}
+
+#[cfg(not(feature = "coregraphics"))]
+fn online_displays(registry: &[RegistryDisplay]) -> Result<Vec<OnlineDisplay>> {
+    let is_asleep = display_wrangler_asleep()?.unwrap_or(false);
+
+    Ok(registry
+        .iter()
+        .enumerate()
+        .map(|(index, display)| OnlineDisplay {
+            id: index as u32,
+            is_builtin: display.is_builtin,
+            is_asleep,
+        })
+        .collect())
+}
+
+/// Lists the built-in backlights followed by the external displays found in the IORegistry.
+fn registry_displays() -> Result<Vec<RegistryDisplay>> {
+    let mut displays = Vec::new();
+
+    for (class, is_builtin) in [
+        (ARM_BACKLIGHT_CLASS, true),
+        (BACKLIGHT_DISPLAY_CLASS, true),
+        (EXTERNAL_DISPLAY_CLASS, false),
+    ] {
+        for_each_service(class, |properties| {
+            displays.push(RegistryDisplay::from_properties(properties, is_builtin));
+        })?;
+    }
+
+    Ok(displays)
+}
+
+#[cfg(not(feature = "coregraphics"))]
+fn display_wrangler_asleep() -> Result<Option<bool>> {
+    use crate::utils::bindings::IOServiceGetMatchingService;
+
+    let class = CString::new(DISPLAY_WRANGLER_CLASS)
+        .map_err(|_| Error::invalid_data("Invalid registry class name"))?;
+
+    autoreleasepool(|_| unsafe {
+        // IOServiceGetMatchingService consumes the matching dictionary
+        let matching = IOServiceMatching(class.as_ptr());
+        if matching.is_null() {
+            return Err(Error::io_kit("Failed to create matching dictionary"));
+        }
+
+        let service = IOServiceGetMatchingService(0, matching);
+        if service == 0 {
+            // Headless Macs have no display wrangler
+            return Ok(None);
+        }
+        let properties = IOKitImpl::default().io_registry_entry_properties(service).ok();
+        IOObjectRelease(service);
+
+        Ok(properties.as_deref().and_then(wrangler_asleep))
+    })
+}
+
+/// Calls `f` with the registry properties of every service of `class`.
+pub(super) fn for_each_service(
+    class: &str,
+    mut f: impl FnMut(&NSDictionary<NSString, NSObject>),
+) -> Result<()> {
+    let class_name =
+        CString::new(class).map_err(|_| Error::invalid_data("Invalid registry class name"))?;
+    let io_kit = IOKitImpl::default();
+
+    autoreleasepool(|_| unsafe {
+        // IOServiceGetMatchingServices consumes the matching dictionary
+        let matching = IOServiceMatching(class_name.as_ptr());
+        if matching.is_null() {
+            return Err(Error::io_kit("Failed to create matching dictionary"));
+        }
+
+        let mut iterator = 0u32;
+        if IOServiceGetMatchingServices(0, matching, &mut iterator) != 0 {
+            return Err(Error::io_kit(format!("Failed to look up {class} services")));
+        }
+
+        loop {
+            let entry = IOIteratorNext(iterator);
+            if entry == 0 {
+                break;
+            }
+            if let Ok(properties) = io_kit.io_registry_entry_properties(entry) {
+                f(&properties);
+            }
+            IOObjectRelease(entry);
+        }
+        IOObjectRelease(iterator);
+
+        Ok(())
+    })
+}
@@ -0,0 +1,320 @@
+//! Approximate energy impact of processes.
+//!
+//! macOS doesn't publish the energy impact Activity Monitor shows, so [`ProcessPowerEstimator`] approximates it from
+//! what `proc_pid_rusage` accounts per process over a window:
+//!
+//! - CPU time, in percent of one core, weighted by [`CPU_WEIGHT`].
+//! - Wakeups from idle and interrupt wakeups per second, weighted by [`WAKEUP_WEIGHT`]: each one keeps the package
+//!   from its deepest idle states for a moment, so a process waking the CPU often costs energy while using little CPU.
+//! - Disk I/O in MiB per second, weighted by [`DISK_WEIGHT`].
+//!
+//! The package power read at the end of the window is split between the processes by their share of the CPU time
+//! used by all processes, which gives [`EnergyImpact::watts`]. Neither number is a measurement: both are meant for
+//! ranking processes, e.g. to find the one draining the battery.
+//!
+//! Processes started during the window count from zero. Processes that exited during it are left out, since what they
+//! used before exiting can't be read anymore. Processes owned by other users are only included when running as root.
+
+use std::{collections::HashMap, time::Duration};
+
+use libproc::pid_rusage::{pidrusage, RUsageInfoV4};
+
+use super::Power;
+use crate::{
+    error::Result,
+    utils::bindings::{extract_proc_name, list_kinfo_procs, mach_ticks_to_nanos},
+};
+
+/// Score per percent of one core of CPU usage
+pub const CPU_WEIGHT: f64 = 1.0;
+/// Score per wakeup per second, so that 50 wakeups per second weigh as much as 1% of a core
+pub const WAKEUP_WEIGHT: f64 = 0.02;
+/// Score per MiB per second of disk I/O
+pub const DISK_WEIGHT: f64 = 0.5;
+
+/// Cumulative counters of a process that feed into its energy impact
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ProcessEnergyCounters {
+    /// Process ID
+    pub pid: u32,
+    /// Start time of the process in Mach absolute time units, telling apart processes that reuse a PID
+    pub start_time: u64,
+    /// Process name
+    pub name: String,
+    /// User and system CPU time
+    pub cpu_time: Duration,
+    /// Wakeups from package idle and interrupt wakeups
+    pub wakeups: u64,
+    /// Bytes read from and written to disk
+    pub disk_bytes: u64,
+}
+
+/// Provides the cumulative energy counters of every process
+pub(crate) trait ProcessEnergySampler {
+    /// Reads the current counters.
+    fn sample(&mut self) -> Result<Vec<ProcessEnergyCounters>>;
+}
+
+/// Estimated energy impact of a process over a window
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnergyImpact {
+    /// Process name
+    pub name: String,
+    /// Combined score, see the [module documentation](self) for the weights; higher means more energy
+    pub score: f64,
+    /// CPU usage in percent of one core
+    pub cpu_percent: f64,
+    /// Share of the CPU time used by all processes during the window, between 0.0 and 1.0
+    pub cpu_share: f64,
+    /// Wakeups per second
+    pub wakeups_per_sec: f64,
+    /// Disk I/O in bytes per second
+    pub disk_bytes_per_sec: f64,
+    /// The share of the package power attributed to the process, or None if the package power couldn't be read
+    pub watts: Option<f64>,
+}
+
+/// Estimates the energy impact of every process over a window
+///
+/// Clones share the IOKit instance of the [`Power`] they read the package power from.
+#[derive(Clone, Default)]
+pub struct ProcessPowerEstimator {
+    power: Power,
+}
+
+impl ProcessPowerEstimator {
+    /// Creates an estimator that reads the package power through its own [`Power`] instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an estimator that reads the package power through `power`.
+    pub fn with_power(power: Power) -> Self {
+        Self { power }
+    }
+
+    /// Samples every process over `interval` and estimates its energy impact, highest impact first.
+    ///
+    /// Blocks the calling thread for `interval`. Processes without any CPU time, wakeups or disk I/O during the
+    /// interval are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the process table can't be read.
+    pub fn estimate_all(&self, interval: Duration) -> Result<Vec<(u32, EnergyImpact)>> {
+        estimate_with(
+            &mut RusageEnergySampler,
+            || self.power.get_power_consumption().ok().map(|power| f64::from(power.package)),
+            interval,
+            std::thread::sleep,
+        )
+    }
+}
+
+/// Samples the processes, waits for the interval with `wait`, samples them again and reads the package power.
+pub(crate) fn estimate_with<S>(
+    sampler: &mut S,
+    package_watts: impl FnOnce() -> Option<f64>,
+    interval: Duration,
+    wait: impl FnOnce(Duration),
+) -> Result<Vec<(u32, EnergyImpact)>>
+where
+    S: ProcessEnergySampler + ?Sized,
+{
+    let start = sampler.sample()?;
+    wait(interval);
+    let end = sampler.sample()?;
+    Ok(estimate(&start, &end, interval, package_watts()))
+}
+
+/// Aligns the counters by PID and start time and scores each process, highest impact first.
+pub(crate) fn estimate(
+    start: &[ProcessEnergyCounters],
+    end: &[ProcessEnergyCounters],
+    interval: Duration,
+    package_watts: Option<f64>,
+) -> Vec<(u32, EnergyImpact)> {
+    let secs = interval.as_secs_f64();
+    if secs <= 0.0 {
+        return Vec::new();
+    }
+
+    let start: HashMap<(u32, u64), &ProcessEnergyCounters> =
+        start.iter().map(|counters| ((counters.pid, counters.start_time), counters)).collect();
+    let deltas: Vec<(&ProcessEnergyCounters, Duration, u64, u64)> = end
+        .iter()
+        .map(|counters| {
+            let previous = start.get(&(counters.pid, counters.start_time));
+            let cpu_time = counters
+                .cpu_time
+                .saturating_sub(previous.map_or(Duration::ZERO, |previous| previous.cpu_time));
+            let wakeups =
+                counters.wakeups.saturating_sub(previous.map_or(0, |previous| previous.wakeups));
+            let disk_bytes = counters
+                .disk_bytes
+                .saturating_sub(previous.map_or(0, |previous| previous.disk_bytes));
+            (counters, cpu_time, wakeups, disk_bytes)
+        })
+        .filter(|(_, cpu_time, wakeups, disk_bytes)| {
+            !cpu_time.is_zero() || *wakeups > 0 || *disk_bytes > 0
+        })
+        .collect();
+
+    let total_cpu: f64 = deltas.iter().map(|(_, cpu_time, _, _)| cpu_time.as_secs_f64()).sum();
+
+    let mut impacts: Vec<(u32, EnergyImpact)> = deltas
+        .into_iter()
+        .map(|(counters, cpu_time, wakeups, disk_bytes)| {
+            let cpu_percent = cpu_time.as_secs_f64() / secs * 100.0;
+            let cpu_share = if total_cpu > 0.0 { cpu_time.as_secs_f64() / total_cpu } else { 0.0 };
+            let wakeups_per_sec = wakeups as f64 / secs;
+            let disk_bytes_per_sec = disk_bytes as f64 / secs;
+            let score = cpu_percent * CPU_WEIGHT
+                + wakeups_per_sec * WAKEUP_WEIGHT
+                + disk_bytes_per_sec / (1024.0 * 1024.0) * DISK_WEIGHT;
+
+            let impact = EnergyImpact {
+                name: counters.name.clone(),
+                score,
+                cpu_percent,
+                cpu_share,
+                wakeups_per_sec,
+                disk_bytes_per_sec,
+                watts: package_watts.map(|watts| watts.max(0.0) * cpu_share),
+            };
+            (counters.pid, impact)
+        })
+        .collect();
+
+    impacts.sort_by(|(a_pid, a), (b_pid, b)| {
+        b.score.total_cmp(&a.score).then_with(|| a_pid.cmp(b_pid))
+    });
+    impacts
+}
+
+/// Reads the energy counters of each process with `proc_pid_rusage`
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RusageEnergySampler;
+
+impl ProcessEnergySampler for RusageEnergySampler {
+    fn sample(&mut self) -> Result<Vec<ProcessEnergyCounters>> {
+        let processes = list_kinfo_procs()?;
+
+        Ok(processes
+            .iter()
+            .filter(|process| process.pid() > 0)
+            .filter_map(|process| {
+                // Processes of other users can't be inspected without root, and processes may exit in between
+                let usage = pidrusage::<RUsageInfoV4>(process.pid()).ok()?;
+                Some(ProcessEnergyCounters {
+                    pid: process.pid() as u32,
+                    start_time: usage.ri_proc_start_abstime,
+                    name: extract_proc_name(process),
+                    cpu_time: Duration::from_nanos(mach_ticks_to_nanos(
+                        usage.ri_user_time + usage.ri_system_time,
+                    )),
+                    wakeups: usage.ri_pkg_idle_wkups + usage.ri_interrupt_wkups,
+                    disk_bytes: usage.ri_diskio_bytesread + usage.ri_diskio_byteswritten,
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(
+        pid: u32,
+        start_time: u64,
+        cpu_ms: u64,
+        wakeups: u64,
+        disk_bytes: u64,
+    ) -> ProcessEnergyCounters {
+        ProcessEnergyCounters {
+            pid,
+            start_time,
+            name: format!("process-{pid}"),
+            cpu_time: Duration::from_millis(cpu_ms),
+            wakeups,
+            disk_bytes,
+        }
+    }
+
+    /// Replays a list of samples
+    struct ScriptedSampler(Vec<Vec<ProcessEnergyCounters>>);
+
+    impl ProcessEnergySampler for ScriptedSampler {
+        fn sample(&mut self) -> Result<Vec<ProcessEnergyCounters>> {
+            Ok(self.0.remove(0))
+        }
+    }
+
+    #[test]
+    fn test_estimate_scores_and_splits_power() {
+        let start = [counters(1, 10, 1_000, 100, 0), counters(2, 20, 5_000, 0, 0)];
+        let end = [counters(1, 10, 1_500, 300, 0), counters(2, 20, 6_500, 0, 2 * 1024 * 1024)];
+
+        let impacts = estimate(&start, &end, Duration::from_secs(2), Some(10.0));
+        let pids: Vec<u32> = impacts.iter().map(|(pid, _)| *pid).collect();
+        assert_eq!(pids, [2, 1]);
+
+        let (_, busy) = &impacts[0];
+        assert_eq!(busy.cpu_percent, 75.0);
+        assert_eq!(busy.cpu_share, 0.75);
+        assert_eq!(busy.disk_bytes_per_sec, 1024.0 * 1024.0);
+        assert_eq!(busy.score, 75.0 * CPU_WEIGHT + DISK_WEIGHT);
+        assert_eq!(busy.watts, Some(7.5));
+
+        let (_, waking) = &impacts[1];
+        assert_eq!(waking.wakeups_per_sec, 100.0);
+        assert_eq!(waking.score, 25.0 * CPU_WEIGHT + 100.0 * WAKEUP_WEIGHT);
+        assert_eq!(waking.watts, Some(2.5));
+    }
+
+    #[test]
+    fn test_estimate_handles_process_churn() {
+        let start = [
+            counters(1, 10, 1_000, 0, 0),
+            // Exits during the interval
+            counters(2, 20, 9_000, 0, 0),
+            // Exits, and its PID is reused by a new process
+            counters(3, 30, 9_000, 0, 0),
+        ];
+        let end = [
+            // Idle during the interval
+            counters(1, 10, 1_000, 0, 0),
+            counters(3, 31, 500, 0, 0),
+            counters(4, 40, 0, 10, 0),
+        ];
+
+        let impacts = estimate(&start, &end, Duration::from_secs(1), None);
+        let pids: Vec<u32> = impacts.iter().map(|(pid, _)| *pid).collect();
+        assert_eq!(pids, [3, 4]);
+        assert_eq!(impacts[0].1.cpu_percent, 50.0, "A reused PID counts from zero");
+        assert_eq!(impacts[0].1.watts, None);
+        assert_eq!(impacts[1].1.cpu_share, 0.0);
+    }
+
+    #[test]
+    fn test_estimate_with_samples_around_the_interval() {
+        let mut sampler =
+            ScriptedSampler(vec![vec![counters(1, 10, 0, 0, 0)], vec![counters(1, 10, 250, 0, 0)]]);
+        let mut waited = None;
+
+        let impacts = estimate_with(
+            &mut sampler,
+            || Some(4.0),
+            Duration::from_millis(500),
+            |interval| waited = Some(interval),
+        )
+        .unwrap();
+
+        assert_eq!(waited, Some(Duration::from_millis(500)));
+        assert_eq!(impacts.len(), 1);
+        assert_eq!(impacts[0].1.cpu_percent, 50.0);
+        assert_eq!(impacts[0].1.watts, Some(4.0));
+        assert!(estimate(&[], &[], Duration::ZERO, None).is_empty());
+    }
+}
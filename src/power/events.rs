@@ -0,0 +1,229 @@
+//! Sleep, wake and thermal throttling events.
+//!
+//! The kernel records the time of the last sleep and wake in `kern.sleeptime` and `kern.waketime`, so reading them
+//! needs neither a power notification port nor a run loop. Both are zero until the machine first sleeps after boot.
+//!
+//! Whether sleep is prevented comes from the power management assertions (`IOPMCopyAssertionsStatus`), the same ones
+//! `pmset -g assertions` lists: a process holding `PreventUserIdleSystemSleep` or `PreventSystemSleep` keeps the
+//! machine awake.
+//!
+//! Thermal events are counted by [`ThermalEventCounter`] as transitions into throttling between samples, so events
+//! shorter than the sampling interval are missed.
+
+use std::{
+    ffi::{c_void as ffi_c_void, CString},
+    ptr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use objc2::rc::{autoreleasepool, Retained};
+use objc2_foundation::{NSDictionary, NSNumber, NSObject, NSString};
+
+use super::Power;
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{sysctlbyname, IOPMCopyAssertionsStatus},
+};
+
+/// Assertion types that keep the system from sleeping
+const SLEEP_PREVENTING_ASSERTIONS: [&str; 2] = ["PreventUserIdleSystemSleep", "PreventSystemSleep"];
+
+/// Time of the last wake from sleep, or None if the machine hasn't slept since boot.
+///
+/// # Errors
+///
+/// Returns an error if `kern.waketime` can't be read.
+pub fn last_wake() -> Result<Option<SystemTime>> {
+    read_time("kern.waketime")
+}
+
+/// Time the machine last went to sleep, or None if it hasn't slept since boot.
+///
+/// # Errors
+///
+/// Returns an error if `kern.sleeptime` can't be read.
+pub fn last_sleep() -> Result<Option<SystemTime>> {
+    read_time("kern.sleeptime")
+}
+
+/// Time since the last wake from sleep, or None if the machine hasn't slept since boot.
+///
+/// # Errors
+///
+/// Returns an error if `kern.waketime` can't be read.
+pub fn time_since_wake() -> Result<Option<Duration>> {
+    Ok(last_wake()?.map(|wake| SystemTime::now().duration_since(wake).unwrap_or(Duration::ZERO)))
+}
+
+/// Whether a power management assertion currently keeps the system from sleeping.
+///
+/// Assertions that only keep the display on don't count.
+///
+/// # Errors
+///
+/// Returns an error if the assertion status can't be read.
+pub fn is_sleep_prevented() -> Result<bool> {
+    autoreleasepool(|_| unsafe {
+        let mut status: *mut ffi_c_void = ptr::null_mut();
+        let result = IOPMCopyAssertionsStatus(&mut status);
+        if result != 0 {
+            return Err(Error::io_return("Failed to read the power assertions", result));
+        }
+        // The status is returned with a +1 retain count and is toll-free bridged to NSDictionary
+        let Some(status) = Retained::from_raw(status as *mut NSDictionary<NSString, NSObject>)
+        else {
+            return Ok(false);
+        };
+
+        Ok(prevents_sleep(|assertion| {
+            let level = status.valueForKey(&NSString::from_str(assertion))?;
+            Some(level.downcast::<NSNumber>().ok()?.as_i64())
+        }))
+    })
+}
+
+/// Whether any sleep-preventing assertion has a level above off, given the level of each assertion type.
+fn prevents_sleep(level: impl Fn(&str) -> Option<i64>) -> bool {
+    SLEEP_PREVENTING_ASSERTIONS
+        .iter()
+        .any(|assertion| level(assertion).is_some_and(|level| level > 0))
+}
+
+/// Reads a `timeval` sysctl, None if it's zero.
+fn read_time(name: &str) -> Result<Option<SystemTime>> {
+    let c_name = CString::new(name)
+        .map_err(|_| Error::invalid_data(format!("Invalid sysctl name: {name}")))?;
+    let mut time = libc::timeval { tv_sec: 0, tv_usec: 0 };
+    let mut size = std::mem::size_of::<libc::timeval>();
+
+    // SAFETY: the buffer is a timeval and its size is passed along.
+    let result = unsafe {
+        sysctlbyname(
+            c_name.as_ptr(),
+            &mut time as *mut libc::timeval as *mut std::os::raw::c_void,
+            &mut size,
+            ptr::null(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(Error::os_error(
+            format!("Failed to read {name}"),
+            std::io::Error::last_os_error(),
+        ));
+    }
+
+    Ok(timeval_to_time(time.tv_sec, i64::from(time.tv_usec)))
+}
+
+/// Converts a `timeval` to a time, None for zero or negative values.
+fn timeval_to_time(secs: i64, micros: i64) -> Option<SystemTime> {
+    if secs <= 0 && micros <= 0 {
+        return None;
+    }
+    Some(
+        UNIX_EPOCH
+            + Duration::from_secs(secs.max(0) as u64)
+            + Duration::from_micros(micros.max(0) as u64),
+    )
+}
+
+/// Counts thermal throttling events across samples
+///
+/// An event is a sample that is throttled after one that wasn't. A throttled first sample counts as well, since the
+/// throttling started at some point before it.
+#[derive(Debug, Clone, Default)]
+pub struct ThermalEventCounter {
+    throttling: Option<bool>,
+    events: u64,
+}
+
+impl ThermalEventCounter {
+    /// Creates a counter without any samples.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads whether `power` is throttled and records it, returning whether a new event started.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the throttling state can't be read.
+    pub fn sample(&mut self, power: &Power) -> Result<bool> {
+        let throttling = power.is_power_throttling()?;
+        Ok(self.record(throttling))
+    }
+
+    /// Records whether the system is throttled, returning whether a new event started.
+    pub fn record(&mut self, throttling: bool) -> bool {
+        let started = throttling && self.throttling != Some(true);
+        if started {
+            self.events += 1;
+        }
+        self.throttling = Some(throttling);
+        started
+    }
+
+    /// Number of throttling events recorded so far.
+    pub fn event_count(&self) -> u64 {
+        self.events
+    }
+
+    /// Whether the last sample was throttled, or None before the first sample.
+    pub fn is_throttling(&self) -> Option<bool> {
+        self.throttling
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thermal_event_counter() {
+        let mut counter = ThermalEventCounter::new();
+        assert_eq!(counter.is_throttling(), None);
+
+        assert!(!counter.record(false));
+        assert!(counter.record(true));
+        assert!(!counter.record(true), "Still the same event");
+        assert!(!counter.record(false));
+        assert!(counter.record(true));
+        assert_eq!(counter.event_count(), 2);
+        assert_eq!(counter.is_throttling(), Some(true));
+
+        let mut counter = ThermalEventCounter::new();
+        assert!(counter.record(true), "Throttled from the first sample");
+        assert_eq!(counter.event_count(), 1);
+    }
+
+    #[test]
+    fn test_prevents_sleep() {
+        assert!(!prevents_sleep(|_| None));
+        assert!(!prevents_sleep(
+            |assertion| (assertion == "PreventUserIdleDisplaySleep").then_some(255)
+        ));
+        assert!(!prevents_sleep(|_| Some(0)));
+        assert!(prevents_sleep(
+            |assertion| (assertion == "PreventUserIdleSystemSleep").then_some(255)
+        ));
+        assert!(prevents_sleep(|assertion| (assertion == "PreventSystemSleep").then_some(1)));
+    }
+
+    #[test]
+    fn test_timeval_to_time() {
+        assert_eq!(timeval_to_time(0, 0), None);
+        assert_eq!(
+            timeval_to_time(1_700_000_000, 500_000),
+            Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_500))
+        );
+    }
+
+    #[test]
+    fn test_wake_times() {
+        // Both are None on a machine that hasn't slept since boot, so only their consistency is checked
+        let wake = last_wake().unwrap();
+        assert!(last_sleep().is_ok());
+        assert_eq!(time_since_wake().unwrap().is_some(), wake.is_some());
+    }
+}
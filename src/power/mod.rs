@@ -1,13 +1,56 @@
-use std::os::raw::c_char;
+//! # Power Module
+//!
+//! Power consumption of the SoC or package and its components, power throttling, the power source and battery charge
+//! ([`Power::power_source`]), the power state of the built-in display ([`display_state`],
+//! [`builtin_display_brightness`]), sleep, wake and throttling events ([`last_wake`], [`is_sleep_prevented`],
+//! [`ThermalEventCounter`]), the thermal pressure, Low Power Mode and power profile ([`Power::power_mode`],
+//! [`PowerModeWatcher`]), and the approximate energy impact of each process ([`ProcessPowerEstimator`]).
+//!
+//! [`Power::new`] reads through its own IOKit instance; [`Power::with_shared_iokit`] takes one shared with other
+//! monitors or a mock:
+//!
+//! ```rust
+//! # fn main() -> darwin_metrics::Result<()> {
+//! use darwin_metrics::{doctest_support::mock_iokit_with_defaults, power::Power};
+//!
+//! let power = Power::with_shared_iokit(mock_iokit_with_defaults());
+//!
+//! let consumption = power.get_power_consumption()?;
+//! assert!(consumption.package > 0.0);
+//! assert!(consumption.cores <= consumption.package);
+//! assert!(!power.is_power_throttling()?);
+//! # Ok(())
+//! # }
+//! ```
+
+mod display;
+mod energy;
+mod events;
+mod mode;
+mod peripherals;
+mod source;
+
+use std::{os::raw::c_char, sync::Arc};
 
 use crate::{
-    error::{Error, Result},
-    hardware::iokit::{IOKit, IOKitImpl},
+    error::{Error, ErrorKind, Result},
+    hardware::{
+        cpu::ThermalState,
+        iokit::{IOKit, IOKitImpl},
+    },
 };
 
 use thiserror::Error;
 
+pub use display::{builtin_display_brightness, display_state, DisplayPower};
+pub use energy::{EnergyImpact, ProcessPowerEstimator, CPU_WEIGHT, DISK_WEIGHT, WAKEUP_WEIGHT};
+pub use events::{is_sleep_prevented, last_sleep, last_wake, time_since_wake, ThermalEventCounter};
+pub use mode::{is_low_power_mode, PowerMode, PowerModeWatcher, PowerProfile};
+pub use peripherals::{peripheral_summary, PeripheralPowerSummary, TbDevice};
+pub use source::PowerSourceInfo;
+
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum PowerError {
     #[error("System call failed")]
     SystemCallFailed,
@@ -17,6 +60,30 @@ pub enum PowerError {
     NotSupported,
     #[error("Service error: {0}")]
     ServiceError(String),
+    /// A crate error that none of the other variants describes, kept with its kind and OS code
+    #[error(transparent)]
+    Source(Error),
+}
+
+impl PowerError {
+    /// What kind of error this is, see [`Error::kind`]
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            PowerError::SystemCallFailed => ErrorKind::System,
+            PowerError::InvalidData => ErrorKind::InvalidData,
+            PowerError::NotSupported => ErrorKind::Unsupported,
+            PowerError::ServiceError(_) => ErrorKind::ServiceNotFound,
+            PowerError::Source(err) => err.kind(),
+        }
+    }
+
+    /// The `errno` or `IOReturn` the error was caused by, see [`Error::os_code`]
+    pub fn os_code(&self) -> Option<i32> {
+        match self {
+            PowerError::Source(err) => err.os_code(),
+            _ => None,
+        }
+    }
 }
 
 impl From<Error> for PowerError {
@@ -24,13 +91,15 @@ impl From<Error> for PowerError {
         match err {
             Error::InvalidData(_) => PowerError::InvalidData,
             Error::ServiceNotFound(msg) => PowerError::ServiceError(msg),
-            _ => PowerError::SystemCallFailed,
+            Error::NotImplemented(_) => PowerError::NotSupported,
+            _ => PowerError::Source(err),
         }
     }
 }
 
 /// Power state of the system
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PowerState {
     /// Device is running on battery power
     Battery,
@@ -44,6 +113,7 @@ pub enum PowerState {
 
 /// Represents the power consumption of the system components in watts
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PowerConsumption {
     /// Total package power (entire SoC for Apple Silicon, package for Intel)
     pub package: f32,
@@ -61,25 +131,49 @@ pub struct PowerConsumption {
     pub battery_percentage: Option<f32>,
     /// Power impact scoring (higher means more power drain)
     pub power_impact: Option<f32>,
+    /// Brightness of the built-in display between 0.0 and 1.0, if there is one and it reports it
+    pub display_brightness: Option<f32>,
+}
+
+impl PowerConsumption {
+    /// Total package power in milliwatts
+    pub fn package_mw(&self) -> u32 {
+        milliwatts(f64::from(self.package))
+    }
+
+    /// CPU cores power consumption in milliwatts
+    pub fn cores_mw(&self) -> u32 {
+        milliwatts(f64::from(self.cores))
+    }
+
+    /// GPU power consumption in milliwatts, if available
+    pub fn gpu_mw(&self) -> Option<u32> {
+        self.gpu.map(|watts| milliwatts(f64::from(watts)))
+    }
 }
 
-use crate::utils::bindings::{
-    SMC_KEY_CPU_POWER, SMC_KEY_CPU_THROTTLE, SMC_KEY_DRAM_POWER, SMC_KEY_GPU_POWER,
-    SMC_KEY_NEURAL_POWER, SMC_KEY_PACKAGE_POWER,
+use crate::utils::{
+    bindings::{
+        SMC_KEY_CPU_POWER, SMC_KEY_CPU_THROTTLE, SMC_KEY_DRAM_POWER, SMC_KEY_GPU_POWER,
+        SMC_KEY_NEURAL_POWER, SMC_KEY_PACKAGE_POWER,
+    },
+    fixed::milliwatts,
 };
 
 /// Provides power consumption information for the system
+///
+/// Clones share the IOKit instance of the original.
+#[derive(Clone)]
 pub struct Power {
     #[cfg(not(test))]
-    #[allow(dead_code)]
-    iokit: Box<dyn IOKit>,
+    iokit: Arc<dyn IOKit>,
     #[cfg(test)]
-    pub iokit: Box<dyn IOKit>,
+    pub iokit: Arc<dyn IOKit>,
 }
 
 impl Default for Power {
     fn default() -> Self {
-        Self { iokit: Box::new(IOKitImpl) }
+        Self::with_shared_iokit(Arc::new(IOKitImpl::default()))
     }
 }
 
@@ -89,6 +183,11 @@ impl Power {
         Self::default()
     }
 
+    /// Creates a Power instance that reads through `iokit`, shared with the other monitors holding it.
+    pub fn with_shared_iokit(iokit: Arc<dyn IOKit>) -> Self {
+        Self { iokit }
+    }
+
     /// Returns the power consumption for system components
     pub fn get_power_consumption(&self) -> Result<PowerConsumption> {
         // Get power values using the safe mock implementation This avoids any segmentation faults while still providing
@@ -118,10 +217,10 @@ impl Power {
             Err(_) => Some(0.7), // Fallback value
         };
 
-        // Use a safe implementation for battery state For the example we'll just assume AC power with a high battery
-        // level
-        let power_state = PowerState::AC;
-        let battery_percentage = Some(95.0);
+        // A battery that can't be read leaves the state unknown rather than failing the whole reading
+        let source = self.power_source().unwrap_or_default();
+        let power_state = source.state;
+        let battery_percentage = source.battery_percentage;
 
         // Calculate power impact score
         let power_impact = if package > 0.0 {
@@ -132,6 +231,8 @@ impl Power {
             Some(15.0) // Fallback value
         };
 
+        let display_brightness = builtin_display_brightness();
+
         Ok(PowerConsumption {
             package,
             cores,
@@ -141,6 +242,7 @@ impl Power {
             power_state,
             battery_percentage,
             power_impact,
+            display_brightness,
         })
     }
 
@@ -155,6 +257,47 @@ impl Power {
             .map_err(|_| Error::system("Async task failed"))?
     }
 
+    /// Returns the power source and the state of the battery
+    ///
+    /// Without a battery the state is [`PowerState::Unknown`], see [`PowerSourceInfo`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the battery properties can't be read.
+    pub fn power_source(&self) -> Result<PowerSourceInfo> {
+        PowerSourceInfo::read(&self.iokit)
+    }
+
+    /// Asynchronous version of power_source
+    pub async fn power_source_async(&self) -> Result<PowerSourceInfo> {
+        use tokio::task;
+
+        let iokit_clone = self.clone();
+        task::spawn_blocking(move || iokit_clone.power_source())
+            .await
+            .map_err(|_| Error::system("Async task failed"))?
+    }
+
+    /// Returns the thermal pressure of the system, or None if it couldn't be determined
+    pub fn thermal_pressure(&self) -> Option<ThermalState> {
+        ThermalState::current()
+    }
+
+    /// Returns the power profile of the power source the machine runs on, see [`PowerProfile`]
+    ///
+    /// # Errors
+    ///
+    /// Returns a not available error if the power management settings have no power profile, or an error if the
+    /// battery properties can't be read.
+    pub fn power_profile(&self) -> Result<PowerProfile> {
+        mode::read_profile(mode::preferences_source(self.power_source()?.state))
+    }
+
+    /// Returns the thermal pressure, whether Low Power Mode is enabled and the power profile
+    pub fn power_mode(&self) -> PowerMode {
+        PowerMode::read(self)
+    }
+
     /// Determines if the system is throttling power due to thermal constraints
     pub fn is_power_throttling(&self) -> Result<bool> {
         // Use our safe mock implementation
@@ -201,12 +344,6 @@ impl Power {
     }
 }
 
-impl Clone for Power {
-    fn clone(&self) -> Self {
-        Self { iokit: Box::new(IOKitImpl) }
-    }
-}
-
 /// Convenience function to get current power consumption
 pub fn get_power_consumption() -> Result<PowerConsumption> {
     let power = Power::new();
@@ -221,7 +358,36 @@ pub async fn get_power_consumption_async() -> Result<PowerConsumption> {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
+    use crate::{
+        hardware::iokit::MockIOKit,
+        utils::test_utils::{create_test_dictionary, create_test_service},
+    };
+
+    /// An IOKit mock whose battery entry reports `bools` and `numbers`, or that has no battery entry if `bools` is empty
+    fn battery_iokit(
+        bools: &'static [(&'static str, bool)],
+        numbers: &'static [(&'static str, i64)],
+    ) -> MockIOKit {
+        let mut iokit = MockIOKit::new();
+        iokit.expect_io_service_matching().returning(|_| create_test_dictionary());
+        let present = !bools.is_empty();
+        iokit
+            .expect_io_service_get_matching_service()
+            .returning(move |_| present.then(create_test_service));
+        iokit
+            .expect_io_registry_entry_create_cf_properties()
+            .returning(|_| Ok(create_test_dictionary()));
+        iokit.expect_get_bool_property().returning(move |_, key| {
+            bools.iter().find(|(name, _)| *name == key).map(|(_, value)| *value)
+        });
+        iokit.expect_get_number_property().returning(move |_, key| {
+            numbers.iter().find(|(name, _)| *name == key).map(|(_, value)| *value)
+        });
+        iokit
+    }
 
     #[test]
     fn test_power_new() {
@@ -229,6 +395,34 @@ mod tests {
         // No assertion needed - test passes if it doesn't panic
     }
 
+    #[test]
+    fn test_power_consumption_milliwatts() {
+        let consumption = Power::new().get_power_consumption().unwrap();
+        assert_eq!(consumption.package_mw(), 12_300);
+        assert_eq!(consumption.cores_mw(), 8500);
+        assert_eq!(consumption.gpu_mw(), Some(2800));
+
+        let consumption = PowerConsumption { package: -1.0, gpu: None, ..consumption };
+        assert_eq!(consumption.package_mw(), 0);
+        assert_eq!(consumption.gpu_mw(), None);
+    }
+
+    #[tokio::test]
+    async fn test_power_shares_one_iokit() {
+        let iokit: Arc<dyn IOKit> = Arc::new(battery_iokit(&[], &[]));
+        let power = Power::with_shared_iokit(Arc::clone(&iokit));
+        let clone = power.clone();
+
+        // The async methods read through a clone, which must not open an instance of its own
+        power.get_power_consumption_async().await.unwrap();
+        power.is_power_throttling_async().await.unwrap();
+        power.power_source_async().await.unwrap();
+
+        assert!(Arc::ptr_eq(&power.iokit, &iokit));
+        assert!(Arc::ptr_eq(&clone.iokit, &iokit));
+        assert_eq!(Arc::strong_count(&iokit), 3);
+    }
+
     #[test]
     fn test_power_consumption() {
         let power = Power::new();
@@ -241,7 +435,61 @@ mod tests {
         assert!(consumption.gpu.is_some(), "GPU power should be present");
         assert!(consumption.dram.is_some(), "DRAM power should be present");
         assert!(consumption.neural_engine.is_some(), "Neural engine power should be present");
-        assert_eq!(consumption.power_state, PowerState::AC, "Power state should be AC");
+    }
+
+    #[test]
+    fn test_power_source_on_battery() {
+        let iokit = battery_iokit(
+            &[("BatteryInstalled", true), ("IsCharging", false), ("ExternalConnected", false)],
+            &[("CurrentCapacity", 42), ("MaxCapacity", 100), ("AvgTimeToEmpty", 135)],
+        );
+        let power = Power::with_shared_iokit(Arc::new(iokit));
+
+        let source = power.power_source().unwrap();
+        assert_eq!(source.state, PowerState::Battery);
+        assert!(source.is_on_battery());
+        assert_eq!(source.battery_percentage, Some(42.0));
+        assert_eq!(source.time_remaining, Some(Duration::from_secs(135 * 60)));
+
+        let consumption = power.get_power_consumption().unwrap();
+        assert_eq!(consumption.power_state, PowerState::Battery);
+        assert_eq!(consumption.battery_percentage, Some(42.0));
+    }
+
+    #[test]
+    fn test_power_source_on_ac() {
+        let charging = battery_iokit(
+            &[("BatteryInstalled", true), ("IsCharging", true), ("ExternalConnected", true)],
+            &[("CurrentCapacity", 80), ("MaxCapacity", 100), ("AvgTimeToFull", 65535)],
+        );
+        let source = Power::with_shared_iokit(Arc::new(charging)).power_source().unwrap();
+        assert_eq!(source.state, PowerState::Charging);
+        assert_eq!(source.time_remaining, None, "Still calculating");
+
+        let full = battery_iokit(
+            &[("BatteryInstalled", true), ("IsCharging", false), ("ExternalConnected", true)],
+            &[("CurrentCapacity", 100), ("MaxCapacity", 100), ("TimeRemaining", 0)],
+        );
+        let source = Power::with_shared_iokit(Arc::new(full)).power_source().unwrap();
+        assert_eq!(source.state, PowerState::AC);
+        assert!(!source.is_on_battery());
+        assert_eq!(source.battery_percentage, Some(100.0));
+        assert_eq!(source.time_remaining, None);
+    }
+
+    #[test]
+    fn test_power_source_without_battery() {
+        let power = Power::with_shared_iokit(Arc::new(battery_iokit(&[], &[])));
+        assert_eq!(power.power_source().unwrap(), PowerSourceInfo::default());
+
+        let consumption = power.get_power_consumption().unwrap();
+        assert_eq!(consumption.power_state, PowerState::Unknown);
+        assert_eq!(consumption.battery_percentage, None);
+
+        // A desktop can also report an entry without an installed battery
+        let iokit = battery_iokit(&[("BatteryInstalled", false)], &[]);
+        let source = Power::with_shared_iokit(Arc::new(iokit)).power_source().unwrap();
+        assert_eq!(source.state, PowerState::Unknown);
     }
 
     #[test]
@@ -336,6 +584,7 @@ mod tests {
             power_state: PowerState::Battery,
             battery_percentage: Some(75.0),
             power_impact: Some(12.5),
+            display_brightness: Some(0.5),
         };
 
         assert_eq!(consumption.package, 10.0);
@@ -346,6 +595,7 @@ mod tests {
         assert_eq!(consumption.power_state, PowerState::Battery);
         assert_eq!(consumption.battery_percentage, Some(75.0));
         assert_eq!(consumption.power_impact, Some(12.5));
+        assert_eq!(consumption.display_brightness, Some(0.5));
     }
 
     #[test]
@@ -356,10 +606,392 @@ mod tests {
 
         let system_err = Error::system("test error");
         let power_err = PowerError::from(system_err);
-        assert!(matches!(power_err, PowerError::SystemCallFailed));
+        assert!(matches!(power_err, PowerError::Source(_)));
+        assert_eq!(power_err.kind(), ErrorKind::System);
 
         let service_err = Error::service_not_found("test service");
         let power_err = PowerError::from(service_err);
         assert!(matches!(power_err, PowerError::ServiceError(_)));
+        assert_eq!(power_err.kind(), ErrorKind::ServiceNotFound);
+
+        let power_err = PowerError::from(Error::not_implemented("test feature"));
+        assert!(matches!(power_err, PowerError::NotSupported));
+        assert_eq!(power_err.kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_power_error_preserves_kind_and_code() {
+        let denied = Error::io_return("test", crate::utils::bindings::IO_RETURN_NOT_PRIVILEGED);
+        let power_err = PowerError::from(denied.clone());
+        assert_eq!(power_err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(power_err.os_code(), denied.os_code());
+        assert_eq!(power_err.to_string(), denied.to_string());
+
+        let power_err =
+            PowerError::from(Error::from(std::io::Error::from_raw_os_error(libc::EPERM)));
+        assert_eq!(power_err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(power_err.os_code(), Some(libc::EPERM));
+
+        let power_err = PowerError::from(Error::not_available("test feature"));
+        assert_eq!(power_err.kind(), ErrorKind::NotAvailable);
+        assert_eq!(power_err.os_code(), None);
+    }
+
+    mod display {
+        use std::ffi::CString;
+
+        use objc2::rc::Retained;
+        use objc2_foundation::{NSDictionary, NSNumber, NSObject, NSString};
+
+        use crate::power::display::{
+            combine, normalize_brightness, OnlineDisplay, RegistryDisplay,
+        };
+        use crate::power::{display_state, DisplayPower};
+        use crate::utils::bindings::{
+            IOObjectRelease, IOServiceGetMatchingService, IOServiceMatching,
+        };
+
+        fn number(value: i64) -> Retained<NSObject> {
+            Retained::into_super(Retained::into_super(NSNumber::new_i64(value)))
+        }
+
+        fn dictionary(
+            entries: &[(&str, Retained<NSObject>)],
+        ) -> Retained<NSDictionary<NSString, NSObject>> {
+            let keys: Vec<Retained<NSString>> =
+                entries.iter().map(|(key, _)| NSString::from_str(key)).collect();
+            let keys: Vec<&NSString> = keys.iter().map(|key| &**key).collect();
+            let values: Vec<&NSObject> = entries.iter().map(|(_, value)| &**value).collect();
+            NSDictionary::from_slices(&keys, &values)
+        }
+
+        fn nested(entries: &[(&str, Retained<NSObject>)]) -> Retained<NSObject> {
+            Retained::into_super(dictionary(entries))
+        }
+
+        fn range(min: i64, max: i64, value: i64) -> Retained<NSObject> {
+            nested(&[("min", number(min)), ("max", number(max)), ("value", number(value))])
+        }
+
+        /// Properties of an `AppleARMBacklight` registry entry, trimmed to the display parameters
+        fn arm_backlight_fixture(value: i64) -> Retained<NSDictionary<NSString, NSObject>> {
+            dictionary(&[
+                (
+                    "IODisplayParameters",
+                    nested(&[
+                        ("brightness", range(0, 65536, value)),
+                        ("linear-brightness", range(0, 1024, 512)),
+                        ("commit", nested(&[("reg", number(0))])),
+                    ]),
+                ),
+                ("IOPowerManagement", nested(&[("CurrentPowerState", number(4))])),
+            ])
+        }
+
+        #[test]
+        fn test_backlight_brightness_from_registry() {
+            let display = RegistryDisplay::from_properties(&arm_backlight_fixture(16384), true);
+            assert_eq!(display, RegistryDisplay { is_builtin: true, brightness: Some(0.25) });
+
+            let full = RegistryDisplay::from_properties(&arm_backlight_fixture(65536), true);
+            assert_eq!(full.brightness, Some(1.0));
+        }
+
+        #[test]
+        fn test_display_without_brightness() {
+            // External monitors expose other parameters but no brightness
+            let external =
+                dictionary(&[("IODisplayParameters", nested(&[("contrast", range(0, 100, 50))]))]);
+            assert_eq!(RegistryDisplay::from_properties(&external, false).brightness, None);
+
+            let no_parameters = dictionary(&[("IOPowerManagement", nested(&[]))]);
+            assert_eq!(RegistryDisplay::from_properties(&no_parameters, false).brightness, None);
+
+            // A parameter that isn't a dictionary is ignored
+            let malformed = dictionary(&[("IODisplayParameters", number(1))]);
+            assert_eq!(RegistryDisplay::from_properties(&malformed, true).brightness, None);
+        }
+
+        #[test]
+        fn test_normalize_brightness() {
+            assert_eq!(normalize_brightness(0.0, 1024.0, 256.0), Some(0.25));
+            assert_eq!(normalize_brightness(100.0, 200.0, 150.0), Some(0.5));
+            assert_eq!(normalize_brightness(0.0, 100.0, 120.0), Some(1.0));
+            assert_eq!(normalize_brightness(0.0, 100.0, -5.0), Some(0.0));
+            assert_eq!(normalize_brightness(0.0, 0.0, 0.0), None);
+        }
+
+        #[cfg(not(feature = "coregraphics"))]
+        #[test]
+        fn test_display_wrangler_power_state() {
+            use crate::power::display::wrangler_asleep;
+
+            let wrangler = |state| {
+                dictionary(&[(
+                    "IOPowerManagement",
+                    nested(&[("CurrentPowerState", number(state))]),
+                )])
+            };
+            assert_eq!(wrangler_asleep(&wrangler(4)), Some(false));
+            // Dimmed displays are still on
+            assert_eq!(wrangler_asleep(&wrangler(3)), Some(false));
+            assert_eq!(wrangler_asleep(&wrangler(1)), Some(true));
+            assert_eq!(wrangler_asleep(&dictionary(&[])), None);
+        }
+
+        #[test]
+        fn test_combine_assigns_brightness_to_builtin_displays() {
+            let online = [
+                OnlineDisplay { id: 1, is_builtin: true, is_asleep: false },
+                OnlineDisplay { id: 7, is_builtin: false, is_asleep: true },
+            ];
+            let registry = [
+                RegistryDisplay { is_builtin: true, brightness: Some(0.25) },
+                RegistryDisplay { is_builtin: false, brightness: Some(0.9) },
+            ];
+
+            assert_eq!(
+                combine(&online, &registry),
+                vec![
+                    DisplayPower {
+                        id: 1,
+                        is_asleep: false,
+                        brightness: Some(0.25),
+                        is_builtin: true
+                    },
+                    DisplayPower { id: 7, is_asleep: true, brightness: None, is_builtin: false },
+                ]
+            );
+
+            // A built-in display without a backlight entry has no brightness
+            assert_eq!(combine(&online[..1], &[])[0].brightness, None);
+        }
+
+        /// Whether the machine has an internal battery, i.e. is a laptop
+        fn has_internal_battery() -> bool {
+            let class = CString::new("AppleSmartBattery").unwrap();
+            unsafe {
+                let service = IOServiceGetMatchingService(0, IOServiceMatching(class.as_ptr()));
+                if service == 0 {
+                    return false;
+                }
+                IOObjectRelease(service);
+                true
+            }
+        }
+
+        #[test]
+        fn test_display_state_smoke() {
+            // Headless Macs report no displays, but never an error
+            let displays = display_state().expect("display state should be readable");
+
+            if has_internal_battery() {
+                let builtin = displays.iter().find(|display| display.is_builtin);
+                assert!(builtin.is_some(), "laptops should report their built-in display");
+            }
+            for display in &displays {
+                if let Some(brightness) = display.brightness {
+                    assert!((0.0..=1.0).contains(&brightness));
+                }
+            }
+        }
+    }
+
+    mod peripherals {
+        use std::collections::HashMap;
+
+        use objc2::rc::Retained;
+        use objc2_foundation::{NSDictionary, NSNumber, NSObject, NSString};
+
+        use crate::error::{Error, Result};
+        use crate::power::peripherals::{
+            adapter_watts, summarize, usb_requested_ma, PeripheralRegistry, ThunderboltSwitch,
+        };
+        use crate::power::{peripheral_summary, PeripheralPowerSummary, TbDevice};
+
+        type Properties = Retained<NSDictionary<NSString, NSObject>>;
+
+        fn number(value: i64) -> Retained<NSObject> {
+            Retained::into_super(Retained::into_super(NSNumber::new_i64(value)))
+        }
+
+        fn string(value: &str) -> Retained<NSObject> {
+            Retained::into_super(NSString::from_str(value))
+        }
+
+        fn dictionary(entries: &[(&str, Retained<NSObject>)]) -> Properties {
+            let keys: Vec<Retained<NSString>> =
+                entries.iter().map(|(key, _)| NSString::from_str(key)).collect();
+            let keys: Vec<&NSString> = keys.iter().map(|key| &**key).collect();
+            let values: Vec<&NSObject> = entries.iter().map(|(_, value)| &**value).collect();
+            NSDictionary::from_slices(&keys, &values)
+        }
+
+        fn switch(depth: i64, model: &str, vendor: &str) -> Properties {
+            dictionary(&[
+                ("Depth", number(depth)),
+                ("Device Model Name", string(model)),
+                ("Device Vendor Name", string(vendor)),
+            ])
+        }
+
+        fn battery(watts: i64) -> Properties {
+            dictionary(&[
+                ("AdapterDetails", Retained::into_super(dictionary(&[("Watts", number(watts))]))),
+                ("ExternalConnected", number(1)),
+            ])
+        }
+
+        /// Scripted registry entries by class
+        struct FakeRegistry {
+            services: HashMap<&'static str, Vec<Properties>>,
+            external_displays: u32,
+        }
+
+        impl PeripheralRegistry for FakeRegistry {
+            fn services(&self, class: &str) -> Result<Vec<Properties>> {
+                Ok(self.services.get(class).cloned().unwrap_or_default())
+            }
+
+            fn external_displays(&self) -> Result<u32> {
+                Ok(self.external_displays)
+            }
+        }
+
+        /// A laptop on a Thunderbolt dock driving two displays, with a keyboard, a webcam and a disk on the dock
+        fn docked_laptop() -> FakeRegistry {
+            FakeRegistry {
+                services: HashMap::from([
+                    (
+                        "IOThunderboltSwitch",
+                        vec![
+                            switch(0, "iOS", "Apple Inc."),
+                            switch(0, "iOS", "Apple Inc."),
+                            switch(1, "TS4", "CalDigit, Inc."),
+                        ],
+                    ),
+                    (
+                        "IOUSBHostDevice",
+                        vec![
+                            dictionary(&[("UsbPowerSinkAllocation", number(100))]),
+                            dictionary(&[("UsbPowerSinkAllocation", number(500))]),
+                            // Legacy units of 2 mA
+                            dictionary(&[("Requested Power", number(448))]),
+                        ],
+                    ),
+                    ("AppleSmartBattery", vec![battery(96)]),
+                ]),
+                external_displays: 2,
+            }
+        }
+
+        #[test]
+        fn test_summarize_docked_laptop() {
+            assert_eq!(
+                summarize(&docked_laptop()).unwrap(),
+                PeripheralPowerSummary {
+                    thunderbolt_devices: vec![TbDevice {
+                        name: "TS4".to_string(),
+                        vendor: Some("CalDigit, Inc.".to_string()),
+                    }],
+                    external_displays: 2,
+                    usb_devices_high_power: 2,
+                    adapter_watts_negotiated: Some(96),
+                }
+            );
+        }
+
+        #[test]
+        fn test_summarize_empty_registry() {
+            let registry = FakeRegistry { services: HashMap::new(), external_displays: 0 };
+            assert_eq!(summarize(&registry).unwrap(), PeripheralPowerSummary::default());
+        }
+
+        #[test]
+        fn test_summarize_propagates_registry_errors() {
+            struct Failing;
+
+            impl PeripheralRegistry for Failing {
+                fn services(&self, class: &str) -> Result<Vec<Properties>> {
+                    Err(Error::io_kit(format!("Failed to look up {class} services")))
+                }
+
+                fn external_displays(&self) -> Result<u32> {
+                    Ok(0)
+                }
+            }
+
+            assert!(summarize(&Failing).is_err());
+        }
+
+        #[test]
+        fn test_thunderbolt_switch_from_registry() {
+            let host = ThunderboltSwitch::from_properties(&switch(0, "iOS", "Apple Inc."));
+            assert_eq!(host.depth, Some(0));
+
+            // A device without a model name, and a switch without a depth
+            let unnamed = ThunderboltSwitch::from_properties(&dictionary(&[
+                ("Depth", number(2)),
+                ("Device Model Name", string(" ")),
+            ]));
+            assert_eq!(unnamed, ThunderboltSwitch { depth: Some(2), model: None, vendor: None });
+            let no_depth = ThunderboltSwitch::from_properties(&dictionary(&[]));
+            assert_eq!(no_depth.depth, None);
+
+            let registry = FakeRegistry {
+                services: HashMap::from([(
+                    "IOThunderboltSwitch",
+                    vec![
+                        dictionary(&[("Depth", number(2))]),
+                        dictionary(&[("Device Model Name", string("Dock"))]),
+                    ],
+                )]),
+                external_displays: 0,
+            };
+            let devices = summarize(&registry).unwrap().thunderbolt_devices;
+            assert_eq!(
+                devices,
+                vec![TbDevice { name: "Thunderbolt device".to_string(), vendor: None }]
+            );
+        }
+
+        #[test]
+        fn test_usb_requested_current() {
+            assert_eq!(
+                usb_requested_ma(&dictionary(&[("UsbPowerSinkAllocation", number(900))])),
+                Some(900)
+            );
+            assert_eq!(
+                usb_requested_ma(&dictionary(&[("Requested Power", number(50))])),
+                Some(100)
+            );
+            // The current allocation is preferred over the legacy request
+            let both = dictionary(&[
+                ("UsbPowerSinkAllocation", number(0)),
+                ("Requested Power", number(250)),
+            ]);
+            assert_eq!(usb_requested_ma(&both), Some(0));
+            assert_eq!(usb_requested_ma(&dictionary(&[("USB Product Name", string("Hub"))])), None);
+        }
+
+        #[test]
+        fn test_adapter_watts() {
+            assert_eq!(adapter_watts(&battery(140)), Some(140));
+            // Without an adapter the battery reports zero watts or no details
+            assert_eq!(adapter_watts(&battery(0)), None);
+            assert_eq!(adapter_watts(&dictionary(&[("ExternalConnected", number(0))])), None);
+        }
+
+        #[test]
+        fn test_peripheral_summary_smoke() {
+            let summary = peripheral_summary().expect("peripherals should be readable");
+
+            for device in &summary.thunderbolt_devices {
+                assert!(!device.name.is_empty());
+            }
+            if let Some(watts) = summary.adapter_watts_negotiated {
+                assert!(watts > 0);
+            }
+        }
     }
 }
@@ -0,0 +1,314 @@
+//! Thermal pressure, Low Power Mode and the power profile.
+//!
+//! - The thermal pressure is `NSProcessInfo.thermalState`, the level macOS itself acts on, see [`ThermalState`].
+//! - Low Power Mode is `NSProcessInfo.isLowPowerModeEnabled`, which exists from macOS 12 on.
+//! - The power profile is the setting `pmset` stores for the current power source, read with
+//!   `IOPMCopyActivePMPreferences`: `PowerMode` (0 automatic, 1 low power, 2 high power) on recent macOS versions,
+//!   `LowPowerMode` and `HighPowerMode` on older ones. High Power Mode only exists on some MacBook Pro models.
+//!
+//! The power profile has no change notification of its own, so [`PowerModeWatcher`] samples all three in a background
+//! task and wakes its callers when one of them changes.
+
+use std::{sync::Arc, time::Duration};
+
+use objc2::{
+    class, msg_send,
+    rc::{autoreleasepool, Retained},
+    runtime::AnyObject,
+    sel,
+};
+use objc2_foundation::{NSDictionary, NSNumber, NSObject, NSObjectNSKeyValueCoding, NSString};
+use tokio::{sync::watch, task::JoinHandle, time::MissedTickBehavior};
+
+use super::{Power, PowerState};
+use crate::{
+    error::{Error, Result},
+    hardware::cpu::ThermalState,
+    shutdown,
+    utils::bindings::IOPMCopyActivePMPreferences,
+};
+
+/// Name of the sampling task in the shutdown registry
+pub(crate) const POWER_MODE_WATCHER: &str = "power-mode-watcher";
+
+/// Power profile chosen in the Battery settings, or with `pmset powermode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PowerProfile {
+    /// macOS balances performance and energy use
+    Automatic,
+    /// Low Power Mode: the system reduces performance to save energy
+    LowPower,
+    /// High Power Mode: the fans run faster to sustain performance
+    HighPower,
+}
+
+/// Thermal pressure and power settings of the system
+///
+/// Each field is None where it can't be read, like Low Power Mode before macOS 12.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PowerMode {
+    /// Thermal pressure as reported by `NSProcessInfo`
+    pub thermal_state: Option<ThermalState>,
+    /// Whether Low Power Mode is enabled
+    pub low_power_mode: Option<bool>,
+    /// Power profile of the current power source
+    pub profile: Option<PowerProfile>,
+}
+
+impl PowerMode {
+    /// Reads the thermal pressure and the power settings, the profile of the power source `power` runs on.
+    pub fn read(power: &Power) -> Self {
+        Self {
+            thermal_state: ThermalState::current(),
+            low_power_mode: is_low_power_mode(),
+            profile: power.power_profile().ok(),
+        }
+    }
+}
+
+/// Whether Low Power Mode is enabled, or None before macOS 12 or if it couldn't be determined.
+pub fn is_low_power_mode() -> Option<bool> {
+    autoreleasepool(|_| unsafe {
+        let info: *mut AnyObject = msg_send![class!(NSProcessInfo), processInfo];
+        if info.is_null() {
+            return None;
+        }
+
+        let available: bool = msg_send![info, respondsToSelector: sel!(isLowPowerModeEnabled)];
+        if !available {
+            return None;
+        }
+        let enabled: bool = msg_send![info, isLowPowerModeEnabled];
+        Some(enabled)
+    })
+}
+
+/// Reads the power profile stored for the power source named `source`, e.g. `AC Power`.
+pub(super) fn read_profile(source: &str) -> Result<PowerProfile> {
+    autoreleasepool(|_| unsafe {
+        // The preferences are returned with a +1 retain count and are toll-free bridged to NSDictionary
+        let preferences = IOPMCopyActivePMPreferences();
+        let Some(preferences) =
+            Retained::from_raw(preferences as *mut NSDictionary<NSString, NSObject>)
+        else {
+            return Err(Error::not_available("Power management preferences"));
+        };
+
+        profile_from_settings(|setting| {
+            let path = NSString::from_str(&format!("{source}.{setting}"));
+            let value = preferences.valueForKeyPath(&path)?;
+            Some(value.downcast::<NSNumber>().ok()?.as_i64())
+        })
+        .ok_or_else(|| Error::not_available(format!("Power profile of {source}")))
+    })
+}
+
+/// Name of the power source the preferences of `state` are stored under
+pub(super) fn preferences_source(state: PowerState) -> &'static str {
+    match state {
+        PowerState::Battery => "Battery Power",
+        _ => "AC Power",
+    }
+}
+
+/// The power profile given the value of each setting, None if none of the settings exists.
+fn profile_from_settings(setting: impl Fn(&str) -> Option<i64>) -> Option<PowerProfile> {
+    if let Some(mode) = setting("PowerMode") {
+        return Some(match mode {
+            1 => PowerProfile::LowPower,
+            2 => PowerProfile::HighPower,
+            _ => PowerProfile::Automatic,
+        });
+    }
+
+    match (setting("LowPowerMode"), setting("HighPowerMode")) {
+        (None, None) => None,
+        (Some(low), _) if low > 0 => Some(PowerProfile::LowPower),
+        (_, Some(high)) if high > 0 => Some(PowerProfile::HighPower),
+        _ => Some(PowerProfile::Automatic),
+    }
+}
+
+/// Watches the [`PowerMode`] for changes
+///
+/// A background task on the tokio runtime samples the power mode every interval, so changes shorter than the interval
+/// are missed. The task stops when the watcher is dropped or the crate is [shut down](crate::shutdown()).
+///
+/// ```rust,no_run
+/// # async fn example() -> darwin_metrics::Result<()> {
+/// use std::time::Duration;
+///
+/// use darwin_metrics::power::{Power, PowerModeWatcher};
+///
+/// let mut watcher = PowerModeWatcher::spawn(Power::new(), Duration::from_secs(1));
+/// loop {
+///     let mode = watcher.changed().await?;
+///     println!("Thermal state {:?}, Low Power Mode {:?}", mode.thermal_state, mode.low_power_mode);
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PowerModeWatcher {
+    receiver: watch::Receiver<PowerMode>,
+    task: JoinHandle<()>,
+}
+
+impl PowerModeWatcher {
+    /// Starts sampling the power mode of `power` every `interval`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn spawn(power: Power, interval: Duration) -> Self {
+        Self::spawn_with(interval, move || PowerMode::read(&power))
+    }
+
+    /// Starts calling `sample` every `interval`, publishing the power modes that differ from the previous one.
+    pub(crate) fn spawn_with(
+        interval: Duration,
+        sample: impl Fn() -> PowerMode + Send + 'static,
+    ) -> Self {
+        let (sender, receiver) = watch::channel(sample());
+        let component = shutdown::register(POWER_MODE_WATCHER);
+        let task_component = Arc::clone(&component);
+
+        let task = tokio::spawn(async move {
+            let _running = task_component.running();
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // The first tick completes immediately, and the initial mode was just sampled
+            ticker.tick().await;
+
+            while !sender.is_closed() && !task_component.is_stop_requested() {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = task_component.stop_requested() => break,
+                }
+                let mode = sample();
+                sender.send_if_modified(|current| {
+                    let changed = *current != mode;
+                    *current = mode;
+                    changed
+                });
+            }
+        });
+        let abort = task.abort_handle();
+        component.set_abort(move || abort.abort());
+
+        Self { receiver, task }
+    }
+
+    /// The most recently sampled power mode.
+    pub fn current(&self) -> PowerMode {
+        *self.receiver.borrow()
+    }
+
+    /// Waits until the power mode changes and returns the new one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sampling task stopped.
+    pub async fn changed(&mut self) -> Result<PowerMode> {
+        self.receiver
+            .changed()
+            .await
+            .map_err(|_| Error::system("The power mode watcher stopped"))?;
+        Ok(*self.receiver.borrow_and_update())
+    }
+}
+
+impl Drop for PowerModeWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    fn settings(values: &'static [(&'static str, i64)]) -> impl Fn(&str) -> Option<i64> {
+        move |setting| values.iter().find(|(name, _)| *name == setting).map(|(_, value)| *value)
+    }
+
+    #[test]
+    fn test_profile_from_settings() {
+        assert_eq!(profile_from_settings(settings(&[])), None);
+        assert_eq!(
+            profile_from_settings(settings(&[("PowerMode", 0)])),
+            Some(PowerProfile::Automatic)
+        );
+        assert_eq!(
+            profile_from_settings(settings(&[("PowerMode", 1)])),
+            Some(PowerProfile::LowPower)
+        );
+        assert_eq!(
+            profile_from_settings(settings(&[("PowerMode", 2), ("LowPowerMode", 1)])),
+            Some(PowerProfile::HighPower),
+            "PowerMode takes precedence over the older settings"
+        );
+        assert_eq!(
+            profile_from_settings(settings(&[("LowPowerMode", 0)])),
+            Some(PowerProfile::Automatic)
+        );
+        assert_eq!(
+            profile_from_settings(settings(&[("LowPowerMode", 1), ("HighPowerMode", 0)])),
+            Some(PowerProfile::LowPower)
+        );
+        assert_eq!(
+            profile_from_settings(settings(&[("LowPowerMode", 0), ("HighPowerMode", 1)])),
+            Some(PowerProfile::HighPower)
+        );
+    }
+
+    #[test]
+    fn test_preferences_source() {
+        assert_eq!(preferences_source(PowerState::Battery), "Battery Power");
+        assert_eq!(preferences_source(PowerState::AC), "AC Power");
+        assert_eq!(preferences_source(PowerState::Charging), "AC Power");
+        assert_eq!(preferences_source(PowerState::Unknown), "AC Power");
+    }
+
+    #[tokio::test]
+    async fn test_watcher_reports_changes() {
+        let _serial = crate::shutdown::TEST_SERIAL.lock().await;
+
+        let nominal =
+            PowerMode { thermal_state: Some(ThermalState::Nominal), ..PowerMode::default() };
+        let serious =
+            PowerMode { thermal_state: Some(ThermalState::Serious), ..PowerMode::default() };
+        let low_power = PowerMode { low_power_mode: Some(true), ..serious };
+        let samples = Arc::new(Mutex::new(VecDeque::from([
+            nominal, nominal, serious, serious, serious, low_power,
+        ])));
+
+        let mut watcher = PowerModeWatcher::spawn_with(Duration::from_millis(5), move || {
+            let mut samples = samples.lock().unwrap();
+            if samples.len() > 1 {
+                samples.pop_front().unwrap()
+            } else {
+                samples[0]
+            }
+        });
+        assert_eq!(watcher.current(), nominal);
+
+        let changed = tokio::time::timeout(Duration::from_secs(5), watcher.changed());
+        assert_eq!(changed.await.unwrap().unwrap(), serious);
+        let changed = tokio::time::timeout(Duration::from_secs(5), watcher.changed());
+        assert_eq!(changed.await.unwrap().unwrap(), low_power);
+        assert_eq!(watcher.current(), low_power);
+    }
+
+    #[test]
+    fn test_power_mode() {
+        let mode = PowerMode::read(&Power::new());
+        assert!(mode.thermal_state.is_some(), "NSProcessInfo reports a thermal state");
+    }
+}
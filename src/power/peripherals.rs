@@ -0,0 +1,179 @@
+//! Connected peripherals that add to the power draw.
+//!
+//! Docks, external displays and bus-powered USB devices raise the power a laptop draws without showing up in the
+//! readings of its own components. The summary lists them from the IORegistry so that higher draw can be explained:
+//!
+//! - Thunderbolt devices are the `IOThunderboltSwitch` entries below the host controllers, which sit at depth 0.
+//! - USB devices are the `IOUSBHostDevice` entries; the current a device requested from the bus is read from
+//!   `UsbPowerSinkAllocation` in mA or, on older releases, from `Requested Power` in units of 2 mA.
+//! - External displays are counted from [`display_state`](super::display_state).
+//! - The negotiated adapter budget is the `Watts` of the `AdapterDetails` of the `AppleSmartBattery` service.
+//!
+//! Nothing here measures the power a device actually draws: the summary reports counts and negotiated budgets only.
+
+use objc2::{rc::Retained, Message};
+use objc2_foundation::{NSDictionary, NSObject, NSString};
+
+use super::display::for_each_service;
+use crate::{
+    error::Result,
+    utils::property_utils::{PropertyAccessor, PropertyUtils},
+};
+
+/// Registry class of Thunderbolt and USB4 switches, one per device and one per host controller
+const THUNDERBOLT_SWITCH_CLASS: &str = "IOThunderboltSwitch";
+/// Registry class of USB devices
+const USB_DEVICE_CLASS: &str = "IOUSBHostDevice";
+/// Registry class of the battery, which reports the connected power adapter
+const BATTERY_CLASS: &str = "AppleSmartBattery";
+
+/// Thunderbolt switch property holding the number of hops from the host controller
+const DEPTH_KEY: &str = "Depth";
+/// Thunderbolt switch property holding the model name of the device
+const MODEL_NAME_KEY: &str = "Device Model Name";
+/// Thunderbolt switch property holding the vendor name of the device
+const VENDOR_NAME_KEY: &str = "Device Vendor Name";
+/// USB device property holding the current allocated to the device from the bus, in mA
+const POWER_SINK_ALLOCATION_KEY: &str = "UsbPowerSinkAllocation";
+/// Legacy USB device property holding the current the device requested, in units of 2 mA
+const REQUESTED_POWER_KEY: &str = "Requested Power";
+/// Battery property describing the connected power adapter
+const ADAPTER_DETAILS_KEY: &str = "AdapterDetails";
+/// Adapter property holding the negotiated power in watts
+const ADAPTER_WATTS_KEY: &str = "Watts";
+
+/// Current of one USB 2 unit load; devices requesting more are high-power devices in the terms of the USB spec
+const USB_UNIT_LOAD_MA: u32 = 100;
+
+/// A Thunderbolt or USB4 device, such as a dock or a storage enclosure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TbDevice {
+    /// Model name reported by the device
+    pub name: String,
+    /// Vendor name reported by the device, if any
+    pub vendor: Option<String>,
+}
+
+/// Peripherals connected to the machine that add to its power draw
+///
+/// Only counts and negotiated budgets are reported; the power each peripheral draws isn't measured.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeripheralPowerSummary {
+    /// Connected Thunderbolt and USB4 devices, docks included
+    pub thunderbolt_devices: Vec<TbDevice>,
+    /// Number of connected external displays
+    pub external_displays: u32,
+    /// Number of USB devices that requested more than one unit load (100 mA) from the bus
+    pub usb_devices_high_power: u32,
+    /// Power negotiated with the connected adapter in watts, or None without a battery or adapter
+    pub adapter_watts_negotiated: Option<u32>,
+}
+
+/// A Thunderbolt switch entry of the IORegistry
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ThunderboltSwitch {
+    pub depth: Option<u32>,
+    pub model: Option<String>,
+    pub vendor: Option<String>,
+}
+
+impl ThunderboltSwitch {
+    pub(crate) fn from_properties(properties: &NSDictionary<NSString, NSObject>) -> Self {
+        Self {
+            depth: PropertyAccessor::get_number_property(properties, DEPTH_KEY)
+                .map(|depth| depth as u32),
+            model: string_value(properties, MODEL_NAME_KEY),
+            vendor: string_value(properties, VENDOR_NAME_KEY),
+        }
+    }
+
+    /// The device behind the switch, or None for the switch of a host controller
+    fn device(self) -> Option<TbDevice> {
+        // A switch without a depth can't be told apart from a host controller
+        (self.depth? > 0).then(|| TbDevice {
+            name: self.model.unwrap_or_else(|| "Thunderbolt device".to_string()),
+            vendor: self.vendor,
+        })
+    }
+}
+
+/// Current a USB device requested from the bus in mA, if its registry entry reports it.
+pub(crate) fn usb_requested_ma(properties: &NSDictionary<NSString, NSObject>) -> Option<u32> {
+    PropertyAccessor::get_number_property(properties, POWER_SINK_ALLOCATION_KEY)
+        .or_else(|| {
+            PropertyAccessor::get_number_property(properties, REQUESTED_POWER_KEY)
+                .map(|units| units * 2.0)
+        })
+        .map(|ma| ma as u32)
+}
+
+/// Power negotiated with the adapter in watts from the properties of the battery, or None if no adapter is connected.
+pub(crate) fn adapter_watts(properties: &NSDictionary<NSString, NSObject>) -> Option<u32> {
+    let adapter = PropertyAccessor::get_dict_property(properties, ADAPTER_DETAILS_KEY)?;
+    PropertyAccessor::get_number_property(&adapter, ADAPTER_WATTS_KEY)
+        .filter(|&watts| watts > 0.0)
+        .map(|watts| watts as u32)
+}
+
+/// The parts of the IORegistry the summary reads
+pub(crate) trait PeripheralRegistry {
+    /// Properties of every service of `class`, subclasses included
+    fn services(&self, class: &str) -> Result<Vec<Retained<NSDictionary<NSString, NSObject>>>>;
+    fn external_displays(&self) -> Result<u32>;
+}
+
+/// Summarizes the peripherals connected to the machine.
+///
+/// # Errors
+///
+/// Returns an error if the IORegistry can't be queried. A machine without Thunderbolt, USB devices or external
+/// displays reports an empty summary.
+pub fn peripheral_summary() -> Result<PeripheralPowerSummary> {
+    summarize(&Registry)
+}
+
+/// Builds the summary from the entries of `registry`.
+pub(crate) fn summarize(registry: &dyn PeripheralRegistry) -> Result<PeripheralPowerSummary> {
+    let thunderbolt_devices = registry
+        .services(THUNDERBOLT_SWITCH_CLASS)?
+        .iter()
+        .filter_map(|properties| ThunderboltSwitch::from_properties(properties).device())
+        .collect();
+
+    let usb_devices_high_power = registry
+        .services(USB_DEVICE_CLASS)?
+        .iter()
+        .filter(|properties| usb_requested_ma(properties).is_some_and(|ma| ma > USB_UNIT_LOAD_MA))
+        .count() as u32;
+
+    Ok(PeripheralPowerSummary {
+        thunderbolt_devices,
+        external_displays: registry.external_displays()?,
+        usb_devices_high_power,
+        adapter_watts_negotiated: registry
+            .services(BATTERY_CLASS)?
+            .iter()
+            .find_map(|properties| adapter_watts(properties)),
+    })
+}
+
+/// The IORegistry of this machine
+struct Registry;
+
+impl PeripheralRegistry for Registry {
+    fn services(&self, class: &str) -> Result<Vec<Retained<NSDictionary<NSString, NSObject>>>> {
+        let mut services = Vec::new();
+        for_each_service(class, |properties| services.push(properties.retain()))?;
+        Ok(services)
+    }
+
+    fn external_displays(&self) -> Result<u32> {
+        Ok(super::display_state()?.iter().filter(|display| !display.is_builtin).count() as u32)
+    }
+}
+
+fn string_value(dict: &NSDictionary<NSString, NSObject>, key: &str) -> Option<String> {
+    PropertyAccessor::get_string_property(dict, key)
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
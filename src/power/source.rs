@@ -0,0 +1,73 @@
+//! Source of the power the machine runs on.
+//!
+//! The state is read from the `AppleSmartBattery` registry entry through the [`IOKit`] trait, with the same readings
+//! as [`Battery`]:
+//!
+//! - A battery that is charging makes the state [`PowerState::Charging`], otherwise a connected adapter makes it
+//!   [`PowerState::AC`] and no adapter [`PowerState::Battery`].
+//! - Without a battery, as on desktop Macs, the state is [`PowerState::Unknown`] and there is no charge or time
+//!   remaining.
+
+use std::{sync::Arc, time::Duration};
+
+use super::PowerState;
+use crate::{
+    battery::{Battery, PowerSource},
+    error::{Error, Result},
+    hardware::iokit::IOKit,
+};
+
+/// Power source of the machine and the state of its battery
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerSourceInfo {
+    /// What the machine runs on
+    pub state: PowerState,
+    /// Battery charge in percent, or None without a battery
+    pub battery_percentage: Option<f32>,
+    /// Time until the battery is empty while discharging, or full while charging; None otherwise, without a battery,
+    /// or while macOS is still calculating it
+    pub time_remaining: Option<Duration>,
+}
+
+impl Default for PowerSourceInfo {
+    fn default() -> Self {
+        Self { state: PowerState::Unknown, battery_percentage: None, time_remaining: None }
+    }
+}
+
+impl PowerSourceInfo {
+    /// Whether the machine runs on its battery
+    pub fn is_on_battery(&self) -> bool {
+        self.state == PowerState::Battery
+    }
+
+    /// Reads the power source from the battery registry entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the battery entry exists but its properties can't be read. A missing entry means there is no
+    /// battery and isn't an error.
+    pub(crate) fn read(iokit: &Arc<dyn IOKit>) -> Result<Self> {
+        match Battery::with_shared_iokit(Arc::clone(iokit)) {
+            Ok(mut battery) => Self::from_battery(&mut battery),
+            Err(Error::ServiceNotFound(_)) => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn from_battery(battery: &mut Battery) -> Result<Self> {
+        if !battery.is_present {
+            return Ok(Self::default());
+        }
+
+        let (state, time_remaining) = if battery.is_charging {
+            (PowerState::Charging, battery.time_to_full()?)
+        } else if battery.power_source == PowerSource::AC {
+            (PowerState::AC, None)
+        } else {
+            (PowerState::Battery, battery.time_to_empty()?)
+        };
+
+        Ok(Self { state, battery_percentage: Some(battery.percentage as f32), time_remaining })
+    }
+}
@@ -0,0 +1,85 @@
+//! Commonly used types and traits
+//!
+//! ```ignore
+//! use darwin_metrics::prelude::*;
+//! ```
+//!
+//! brings the monitors for each subsystem and the traits needed to call their methods into scope. [`Result`] isn't
+//! included so it doesn't shadow `std::result::Result`; use `darwin_metrics::Result` for it.
+//!
+//! [`Result`]: crate::Result
+
+/// Re-exports each path, and in tests records the list so changes to the prelude are caught by a snapshot test.
+macro_rules! prelude {
+    ($($($segment:ident)::+),* $(,)?) => {
+        $(pub use $($segment)::+;)*
+
+        #[cfg(test)]
+        const EXPORTS: &[&str] = &[$(stringify!($($segment)::+)),*];
+    };
+}
+
+prelude! {
+    crate::error::Error,
+    crate::battery::Battery,
+    crate::disk::Disk,
+    crate::hardware::cpu::CPU,
+    crate::hardware::cpu::CpuMetrics,
+    crate::hardware::gpu::Gpu,
+    crate::hardware::memory::Memory,
+    crate::hardware::memory::PressureLevel,
+    crate::hardware::temperature::Temperature,
+    crate::network::NetworkManager,
+    crate::network::NetworkMetrics,
+    crate::power::Power,
+    crate::process::Process,
+    crate::process::ProcessInfo,
+    crate::process::ProcessMetricsStream,
+    crate::snapshot::MetricsSnapshot,
+    crate::system::SystemMetrics,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The public surface of the prelude. Update this list deliberately when adding or removing exports.
+    const EXPECTED: &[&str] = &[
+        "crate::error::Error",
+        "crate::battery::Battery",
+        "crate::disk::Disk",
+        "crate::hardware::cpu::CPU",
+        "crate::hardware::cpu::CpuMetrics",
+        "crate::hardware::gpu::Gpu",
+        "crate::hardware::memory::Memory",
+        "crate::hardware::memory::PressureLevel",
+        "crate::hardware::temperature::Temperature",
+        "crate::network::NetworkManager",
+        "crate::network::NetworkMetrics",
+        "crate::power::Power",
+        "crate::process::Process",
+        "crate::process::ProcessInfo",
+        "crate::process::ProcessMetricsStream",
+        "crate::snapshot::MetricsSnapshot",
+        "crate::system::SystemMetrics",
+    ];
+
+    #[test]
+    fn test_prelude_exports_snapshot() {
+        let exports: Vec<String> = EXPORTS.iter().map(|path| path.replace(' ', "")).collect();
+        assert_eq!(exports, EXPECTED);
+    }
+
+    #[test]
+    fn test_prelude_names_resolve() {
+        fn type_name<T: ?Sized>() -> &'static str {
+            std::any::type_name::<T>()
+        }
+
+        assert!(type_name::<Battery>().ends_with("Battery"));
+        assert!(type_name::<Power>().ends_with("Power"));
+        assert!(type_name::<ProcessMetricsStream>().ends_with("ProcessMetricsStream"));
+        assert!(type_name::<dyn CpuMetrics>().ends_with("CpuMetrics"));
+        assert!(type_name::<dyn NetworkMetrics>().ends_with("NetworkMetrics"));
+    }
+}
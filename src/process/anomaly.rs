@@ -0,0 +1,318 @@
+//! Detection of processes that consume abnormally for their age
+//!
+//! A process that was started a minute ago and already keeps a core busy is more likely a runaway than a long-running
+//! service with the same load. Every process found in one bulk pass over the process table is scored from its CPU
+//! time, memory footprint and age, and the processes matching one of the [`AnomalyKind`]s are returned ranked by
+//! score. There is no learning involved; all thresholds are plain values in [`AnomalyOptions`].
+//!
+//! # Scoring
+//!
+//! For a process of age `age` (seconds since it started), which has used `cpu_time` seconds of CPU time:
+//!
+//! - `lifetime_cpu = cpu_time / age * 100`, the average CPU usage in percent of one core since the process started
+//! - `interval_cpu`, the CPU usage in percent of one core since the baseline was taken, or `lifetime_cpu` without a
+//!   baseline
+//! - `memory = resident_bytes / host_memory_bytes * 100`, the share of the physical memory the process holds
+//! - `youth = young_age / (young_age + age)`, which is 1 for a process that has just started, 0.5 for one as old as
+//!   [`AnomalyOptions::young_age`] and approaches 0 for long-running processes
+//!
+//! `score = (0.5 * interval_cpu + 0.25 * lifetime_cpu + memory) * (1 + youth)`
+//!
+//! The score ranks processes that consume a lot, and up to twice as much when they are young; its absolute value has
+//! no meaning beyond comparing processes.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant, SystemTime},
+};
+
+use libproc::{file_info::ListFDs, proc_pid, task_info::TaskAllInfo};
+
+use super::ProcessIdentity;
+use crate::{
+    error::Result,
+    utils::{
+        bindings::{extract_proc_name, list_kinfo_procs, mach_ticks_to_nanos},
+        sanitize::{sanitize_rate, Percentage},
+    },
+};
+
+/// Thresholds of the anomaly detection
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnomalyOptions {
+    /// Processes younger than this are considered young (default: 10 minutes)
+    pub young_age: Duration,
+    /// CPU usage in percent of one core above which a young process is considered hot (default: 80)
+    pub hot_cpu_percent: f64,
+    /// Growth of the resident memory since the baseline, in bytes, above which memory is considered growing rapidly
+    /// (default: 256 MiB)
+    pub memory_growth_bytes: u64,
+    /// Growth of the resident memory since the baseline, relative to its size then, that must also be exceeded for
+    /// memory to be considered growing rapidly (default: 0.5, i.e. 50%)
+    pub memory_growth_ratio: f64,
+    /// Number of threads above which a process is flagged (default: 1000)
+    pub max_threads: u32,
+    /// Number of open file descriptors above which a process is flagged (default: 5000)
+    pub max_fds: u32,
+}
+
+impl Default for AnomalyOptions {
+    fn default() -> Self {
+        Self {
+            young_age: Duration::from_secs(10 * 60),
+            hot_cpu_percent: 80.0,
+            memory_growth_bytes: 256 * 1024 * 1024,
+            memory_growth_ratio: 0.5,
+            max_threads: 1000,
+            max_fds: 5000,
+        }
+    }
+}
+
+/// Why a process was flagged
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnomalyKind {
+    /// The process is younger than [`AnomalyOptions::young_age`] and uses more CPU than
+    /// [`AnomalyOptions::hot_cpu_percent`]
+    YoungAndHot {
+        /// Age of the process
+        age: Duration,
+        /// CPU usage in percent of one core
+        cpu_percent: f64,
+    },
+    /// The resident memory grew faster than allowed since the baseline
+    MemoryGrowth {
+        /// Resident memory when the baseline was taken in bytes
+        previous_bytes: u64,
+        /// Resident memory now in bytes
+        current_bytes: u64,
+    },
+    /// The process has more threads than [`AnomalyOptions::max_threads`]
+    ThreadExplosion {
+        /// Number of threads
+        threads: u32,
+    },
+    /// The process has more open file descriptors than [`AnomalyOptions::max_fds`]
+    FdExplosion {
+        /// Number of open file descriptors
+        fds: u32,
+    },
+}
+
+/// A process flagged by [`anomalies`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessAnomaly {
+    /// Identity of the process
+    pub identity: ProcessIdentity,
+    /// Process name
+    pub name: String,
+    /// Ranking score, see the [module documentation](self)
+    pub score: f64,
+    /// Average CPU usage since the process started, in percent of one core
+    pub lifetime_cpu_percent: f64,
+    /// CPU usage since the baseline, in percent of one core, if the process was in the baseline
+    pub interval_cpu_percent: Option<f64>,
+    /// Share of the physical memory the process holds in percent
+    pub memory_percent: f64,
+    /// Age of the process
+    pub age: Duration,
+    /// Every reason the process was flagged for
+    pub kinds: Vec<AnomalyKind>,
+}
+
+/// State of all processes at one pass, to compare the next pass against
+///
+/// Returned by [`anomalies_with_baseline`]; pass it to the next call to detect memory growth and measure the CPU
+/// usage over the interval between the calls. Processes are tracked by identity, so a process reusing the PID of one
+/// in the baseline is treated as new.
+#[derive(Debug, Clone, Default)]
+pub struct AnomalyBaseline {
+    taken_at: Option<Instant>,
+    processes: HashMap<ProcessIdentity, BaselineEntry>,
+}
+
+impl AnomalyBaseline {
+    /// Number of processes in the baseline
+    pub fn len(&self) -> usize {
+        self.processes.len()
+    }
+
+    /// Whether the baseline holds no processes
+    pub fn is_empty(&self) -> bool {
+        self.processes.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BaselineEntry {
+    cpu_time: Duration,
+    resident_bytes: u64,
+}
+
+/// Readings of one process in a pass
+#[derive(Debug, Clone)]
+pub(crate) struct ProcessSample {
+    pub(crate) identity: ProcessIdentity,
+    pub(crate) name: String,
+    pub(crate) age: Duration,
+    /// User and system CPU time used since the process started
+    pub(crate) cpu_time: Duration,
+    pub(crate) resident_bytes: u64,
+    pub(crate) threads: Option<u32>,
+    pub(crate) fds: Option<u32>,
+}
+
+/// Flags the processes that consume abnormally for their age, ranked by score.
+///
+/// Without a baseline, memory growth can't be detected and the current CPU usage is the lifetime average; use
+/// [`anomalies_with_baseline`] to compare successive calls.
+///
+/// # Errors
+///
+/// Returns an error if the process table or the host memory size can't be read. Processes that can't be inspected,
+/// e.g. because they exited during the pass, are skipped.
+pub fn anomalies(options: AnomalyOptions) -> Result<Vec<ProcessAnomaly>> {
+    anomalies_with_baseline(options, None).map(|(anomalies, _)| anomalies)
+}
+
+/// Flags the processes that consume abnormally for their age, comparing them against an earlier pass.
+///
+/// Returns the anomalies ranked by score along with the baseline to pass to the next call. The first call takes
+/// `None` and can only flag young and hot processes and thread or file descriptor explosions.
+///
+/// # Errors
+///
+/// See [`anomalies`].
+pub fn anomalies_with_baseline(
+    options: AnomalyOptions,
+    baseline: Option<&AnomalyBaseline>,
+) -> Result<(Vec<ProcessAnomaly>, AnomalyBaseline)> {
+    let host_memory = crate::init::system_info()?.memory_bytes;
+    let samples = sample_processes()?;
+    Ok(evaluate(&samples, host_memory, &options, baseline, Instant::now()))
+}
+
+/// Reads every process in one pass over the process table.
+pub(crate) fn sample_processes() -> Result<Vec<ProcessSample>> {
+    let now = SystemTime::now();
+
+    let samples = list_kinfo_procs()?
+        .iter()
+        .filter(|proc_info| proc_info.pid() > 0)
+        .filter_map(|proc_info| {
+            let pid = proc_info.pid();
+            // Processes may exit between listing and inspection
+            let info = proc_pid::pidinfo::<TaskAllInfo>(pid, 0).ok()?;
+            let identity = ProcessIdentity::from_task_info(pid as u32, &info);
+            let cpu_ticks = info.ptinfo.pti_total_user + info.ptinfo.pti_total_system;
+            // Listing descriptors of other users' processes needs root
+            let fds = proc_pid::listpidinfo::<ListFDs>(pid, info.pbsd.pbi_nfiles as usize)
+                .ok()
+                .map(|fds| fds.len() as u32);
+
+            Some(ProcessSample {
+                identity,
+                name: extract_proc_name(proc_info),
+                age: now.duration_since(identity.start_time()).unwrap_or(Duration::ZERO),
+                cpu_time: Duration::from_nanos(mach_ticks_to_nanos(cpu_ticks)),
+                resident_bytes: info.ptinfo.pti_resident_size,
+                threads: Some(info.ptinfo.pti_threadnum as u32),
+                fds,
+            })
+        })
+        .collect();
+
+    Ok(samples)
+}
+
+/// Scores the samples of a pass and flags the anomalies, returning them ranked along with the baseline of this pass.
+pub(crate) fn evaluate(
+    samples: &[ProcessSample],
+    host_memory: u64,
+    options: &AnomalyOptions,
+    baseline: Option<&AnomalyBaseline>,
+    now: Instant,
+) -> (Vec<ProcessAnomaly>, AnomalyBaseline) {
+    let interval = baseline
+        .and_then(|baseline| baseline.taken_at)
+        .map(|taken_at| now.saturating_duration_since(taken_at))
+        .filter(|interval| !interval.is_zero());
+
+    let mut anomalies: Vec<ProcessAnomaly> = samples
+        .iter()
+        .filter_map(|sample| {
+            let previous = baseline.and_then(|baseline| baseline.processes.get(&sample.identity));
+            let interval_cpu_percent = previous.zip(interval).map(|(previous, interval)| {
+                let cpu_time = sample.cpu_time.saturating_sub(previous.cpu_time);
+                sanitize_rate(cpu_time.as_secs_f64(), interval.as_secs_f64()) * 100.0
+            });
+            score(sample, previous, interval_cpu_percent, host_memory, options)
+        })
+        .collect();
+    anomalies.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    let processes = samples
+        .iter()
+        .map(|sample| {
+            let entry =
+                BaselineEntry { cpu_time: sample.cpu_time, resident_bytes: sample.resident_bytes };
+            (sample.identity, entry)
+        })
+        .collect();
+
+    (anomalies, AnomalyBaseline { taken_at: Some(now), processes })
+}
+
+/// Scores one process, returning it if it matches any anomaly kind.
+fn score(
+    sample: &ProcessSample,
+    previous: Option<&BaselineEntry>,
+    interval_cpu_percent: Option<f64>,
+    host_memory: u64,
+    options: &AnomalyOptions,
+) -> Option<ProcessAnomaly> {
+    let age = sample.age.as_secs_f64();
+    let lifetime_cpu_percent = sanitize_rate(sample.cpu_time.as_secs_f64(), age) * 100.0;
+    let cpu_percent = interval_cpu_percent.unwrap_or(lifetime_cpu_percent);
+    let memory_percent =
+        Percentage::from_ratio(sample.resident_bytes as f64, host_memory as f64).value();
+    let young_age = options.young_age.as_secs_f64();
+    let youth = if young_age > 0.0 { young_age / (young_age + age) } else { 0.0 };
+
+    let mut kinds = Vec::new();
+    if sample.age < options.young_age && cpu_percent > options.hot_cpu_percent {
+        kinds.push(AnomalyKind::YoungAndHot { age: sample.age, cpu_percent });
+    }
+    if let Some(previous) = previous {
+        let growth = sample.resident_bytes.saturating_sub(previous.resident_bytes);
+        if growth > options.memory_growth_bytes
+            && growth as f64 > previous.resident_bytes as f64 * options.memory_growth_ratio
+        {
+            kinds.push(AnomalyKind::MemoryGrowth {
+                previous_bytes: previous.resident_bytes,
+                current_bytes: sample.resident_bytes,
+            });
+        }
+    }
+    if let Some(threads) = sample.threads.filter(|&threads| threads > options.max_threads) {
+        kinds.push(AnomalyKind::ThreadExplosion { threads });
+    }
+    if let Some(fds) = sample.fds.filter(|&fds| fds > options.max_fds) {
+        kinds.push(AnomalyKind::FdExplosion { fds });
+    }
+
+    if kinds.is_empty() {
+        return None;
+    }
+
+    Some(ProcessAnomaly {
+        identity: sample.identity,
+        name: sample.name.clone(),
+        score: (0.5 * cpu_percent + 0.25 * lifetime_cpu_percent + memory_percent) * (1.0 + youth),
+        lifetime_cpu_percent,
+        interval_cpu_percent,
+        memory_percent,
+        age: sample.age,
+        kinds,
+    })
+}
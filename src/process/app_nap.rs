@@ -0,0 +1,57 @@
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{
+        proc_bsd_short_info, PROC_FLAG_APPLICATION, PROC_FLAG_DARWINBG, PROC_FLAG_EXT_DARWINBG,
+    },
+};
+
+/// Whether a process is throttled by App Nap or background suppression
+///
+/// A throttled process reports low CPU usage, but may use much more as soon as it's brought back to the foreground.
+///
+/// The state is derived from the darwin background flags the kernel reports for every process, which don't need the
+/// task port. App Nap applies the darwin background state to applications from outside; how consistently this shows up
+/// in the flags differs between macOS versions, and timer throttling on its own isn't reported at all. When the flags
+/// can't be read the state is [`AppNapState::Unknown`] instead of a guess.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AppNapState {
+    /// The process runs unthrottled
+    Active,
+    /// The process is an application put into the background by the system, i.e. App Nap
+    Napping,
+    /// The process runs in the darwin background state for another reason, e.g. it lowered its own priority or is a
+    /// background daemon
+    Suppressed,
+    /// The state couldn't be determined
+    Unknown,
+}
+
+impl AppNapState {
+    /// Whether the process is napping or suppressed, so its CPU usage understates its demand
+    pub fn is_throttled(&self) -> bool {
+        matches!(self, Self::Napping | Self::Suppressed)
+    }
+
+    /// Derives the state from the `PROC_FLAG_*` bits of a process.
+    pub(crate) fn from_flags(flags: u32) -> Self {
+        if flags & (PROC_FLAG_DARWINBG | PROC_FLAG_EXT_DARWINBG) == 0 {
+            Self::Active
+        } else if flags & PROC_FLAG_APPLICATION != 0 && flags & PROC_FLAG_EXT_DARWINBG != 0 {
+            Self::Napping
+        } else {
+            Self::Suppressed
+        }
+    }
+}
+
+/// Reads the App Nap state of `pid`.
+pub(crate) fn app_nap_state(pid: u32) -> Result<AppNapState> {
+    match proc_bsd_short_info(pid) {
+        Ok(info) => Ok(AppNapState::from_flags(info.pbsi_flags)),
+        Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {
+            Err(Error::process_error(format!("No process with PID {pid}")))
+        },
+        Err(_) => Ok(AppNapState::Unknown),
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy as SyncLazy;
+use parking_lot::{Mutex, MutexGuard};
+
+use super::ProcessIdentity;
+
+/// Static cache for tracking CPU usage calculations between calls
+static CPU_HISTORY: SyncLazy<Mutex<CpuHistoryTracker>> =
+    SyncLazy::new(|| Mutex::new(CpuHistoryTracker::new(CpuHistoryConfig::default())));
+
+/// Get CPU history tracking map
+pub(super) fn get_cpu_history() -> MutexGuard<'static, CpuHistoryTracker> {
+    CPU_HISTORY.lock()
+}
+
+/// Limits of the CPU time history kept to calculate the CPU usage of processes between readings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuHistoryConfig {
+    /// Maximum number of tracked processes; the least recently read ones are dropped beyond it (default: 1000)
+    ///
+    /// While more processes are running than this, listing them all with [`Process::get_all`] raises the limit to
+    /// their number, so that every listed process keeps its baseline.
+    ///
+    /// [`Process::get_all`]: super::Process::get_all
+    pub capacity: usize,
+    /// Readings older than this are dropped, so the next reading of the process starts from a fresh baseline
+    /// (default: 10 minutes)
+    pub max_age: Duration,
+    /// Maximum number of tracked processes checked for having exited per reading (default: 16)
+    pub checks_per_update: usize,
+}
+
+impl Default for CpuHistoryConfig {
+    fn default() -> Self {
+        Self { capacity: 1000, max_age: Duration::from_secs(10 * 60), checks_per_update: 16 }
+    }
+}
+
+/// Size of the CPU time history, for diagnostics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuHistoryStats {
+    /// Number of processes currently tracked
+    pub tracked: usize,
+    /// Number of entries dropped since the program started, because they aged out, exceeded the capacity or their
+    /// process exited
+    pub evicted_total: u64,
+}
+
+/// Sets the limits of the CPU time history, dropping entries beyond the new capacity right away.
+pub fn set_cpu_history_config(config: CpuHistoryConfig) {
+    get_cpu_history().set_config(config, Instant::now());
+}
+
+/// Returns the size of the CPU time history.
+pub fn cpu_history_stats() -> CpuHistoryStats {
+    get_cpu_history().stats()
+}
+
+/// Previous CPU time reading of a process, used to calculate its CPU usage
+#[derive(Debug, Clone, Copy)]
+pub(super) struct CpuHistoryEntry {
+    /// The process the reading belongs to, so a process reusing the PID starts from a fresh baseline
+    pub(super) identity: ProcessIdentity,
+    pub(super) sampled_at: Instant,
+    pub(super) cpu_time: u64,
+    /// Position of the reading in the order of all readings, the key of the entry in the recency index
+    read_seq: u64,
+}
+
+/// Bounded history of CPU time readings by PID
+///
+/// Cleanup never inspects every tracked process at once: entries past the maximum age are dropped without any
+/// syscall, and the survivors are checked for having exited a few at a time on each update, with a `kill(pid, 0)`
+/// probe. When the caller has just listed every process anyway, [`CpuHistoryTracker::retain_live`] checks them all
+/// against that listing instead, and the capacity grows to the size of the listing.
+///
+/// Entries over the capacity are evicted least recently read first, through an index ordered by reading.
+pub(super) struct CpuHistoryTracker {
+    entries: HashMap<u32, CpuHistoryEntry>,
+    /// PIDs of the entries by the `read_seq` of their reading, oldest first
+    by_recency: BTreeMap<u64, u32>,
+    next_read_seq: u64,
+    /// Number of processes in the last listing passed to [`CpuHistoryTracker::retain_live`]
+    listed: usize,
+    config: CpuHistoryConfig,
+    evicted_total: u64,
+    /// PIDs still to be checked for having exited in the current sweep
+    pending_checks: VecDeque<u32>,
+    exists: Box<dyn Fn(u32) -> bool + Send>,
+}
+
+impl CpuHistoryTracker {
+    pub(super) fn new(config: CpuHistoryConfig) -> Self {
+        Self::with_checker(config, Box::new(pid_exists))
+    }
+
+    /// Creates a tracker that checks whether a process still exists with `exists`.
+    pub(super) fn with_checker(
+        config: CpuHistoryConfig,
+        exists: Box<dyn Fn(u32) -> bool + Send>,
+    ) -> Self {
+        Self {
+            entries: HashMap::new(),
+            by_recency: BTreeMap::new(),
+            next_read_seq: 0,
+            listed: 0,
+            config,
+            evicted_total: 0,
+            pending_checks: VecDeque::new(),
+            exists,
+        }
+    }
+
+    /// Records a reading, returning the previous reading of the same process, if any.
+    pub(super) fn update(
+        &mut self,
+        identity: ProcessIdentity,
+        sampled_at: Instant,
+        cpu_time: u64,
+    ) -> Option<CpuHistoryEntry> {
+        let previous = self.record(identity, sampled_at, cpu_time);
+        self.check_exited(identity.pid(), sampled_at);
+        previous
+    }
+
+    /// Records a reading like [`CpuHistoryTracker::update`], without checking other processes for having exited.
+    pub(super) fn record(
+        &mut self,
+        identity: ProcessIdentity,
+        sampled_at: Instant,
+        cpu_time: u64,
+    ) -> Option<CpuHistoryEntry> {
+        let pid = identity.pid();
+        let read_seq = self.next_read_seq;
+        self.next_read_seq += 1;
+        self.by_recency.insert(read_seq, pid);
+
+        let replaced =
+            self.entries.insert(pid, CpuHistoryEntry { identity, sampled_at, cpu_time, read_seq });
+        if let Some(entry) = &replaced {
+            self.by_recency.remove(&entry.read_seq);
+        }
+        let previous =
+            replaced.filter(|entry| entry.identity.is_same_process(&identity)).filter(|entry| {
+                sampled_at.saturating_duration_since(entry.sampled_at) <= self.config.max_age
+            });
+
+        self.enforce_capacity(sampled_at);
+
+        previous
+    }
+
+    /// Drops the entry of `pid`.
+    pub(super) fn remove(&mut self, pid: &u32) -> Option<CpuHistoryEntry> {
+        let entry = self.entries.remove(pid)?;
+        self.by_recency.remove(&entry.read_seq);
+        Some(entry)
+    }
+
+    /// Drops aged entries, then the entries of processes missing from `live`, the PIDs of every running process.
+    ///
+    /// This completes the current sweep without probing any process. Until the next listing, the capacity is at least
+    /// the number of listed processes, so that readings of all of them fit.
+    pub(super) fn retain_live(&mut self, live: &HashSet<u32>, now: Instant) {
+        self.listed = live.len();
+        self.evict_aged(now);
+        self.retain(|pid, _| live.contains(&pid));
+        self.pending_checks.clear();
+    }
+
+    /// Returns the previous reading of `pid`, if any.
+    #[cfg(test)]
+    pub(super) fn get(&self, pid: &u32) -> Option<&CpuHistoryEntry> {
+        self.entries.get(pid)
+    }
+
+    /// Drops every entry, without counting them as evicted.
+    #[cfg(test)]
+    pub(super) fn clear(&mut self) {
+        self.entries.clear();
+        self.by_recency.clear();
+        self.pending_checks.clear();
+    }
+
+    pub(super) fn stats(&self) -> CpuHistoryStats {
+        CpuHistoryStats { tracked: self.entries.len(), evicted_total: self.evicted_total }
+    }
+
+    /// The capacity in effect, raised to the size of the last listing.
+    fn capacity(&self) -> usize {
+        self.config.capacity.max(self.listed)
+    }
+
+    pub(super) fn set_config(&mut self, config: CpuHistoryConfig, now: Instant) {
+        self.config = config;
+        self.enforce_capacity(now);
+    }
+
+    /// Drops aged entries, then the least recently read ones, until the capacity is respected.
+    fn enforce_capacity(&mut self, now: Instant) {
+        let capacity = self.capacity();
+        if self.entries.len() <= capacity {
+            return;
+        }
+
+        self.evict_aged(now);
+        while self.entries.len() > capacity {
+            let Some((_, oldest)) = self.by_recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+            self.evicted_total += 1;
+        }
+    }
+
+    fn evict_aged(&mut self, now: Instant) {
+        let max_age = self.config.max_age;
+        self.retain(|_, entry| now.saturating_duration_since(entry.sampled_at) <= max_age);
+    }
+
+    /// Keeps the entries `keep` returns true for, counting the others as evicted.
+    fn retain(&mut self, mut keep: impl FnMut(u32, &CpuHistoryEntry) -> bool) {
+        let by_recency = &mut self.by_recency;
+        let before = self.entries.len();
+        self.entries.retain(|&pid, entry| {
+            let kept = keep(pid, entry);
+            if !kept {
+                by_recency.remove(&entry.read_seq);
+            }
+            kept
+        });
+        self.evicted_total += (before - self.entries.len()) as u64;
+    }
+
+    /// Checks up to `checks_per_update` tracked processes for having exited, skipping `current`, which was just read.
+    ///
+    /// A sweep starts by dropping aged entries and then works through the PIDs that survived over the following
+    /// updates.
+    fn check_exited(&mut self, current: u32, now: Instant) {
+        if self.pending_checks.is_empty() {
+            self.evict_aged(now);
+            self.pending_checks.extend(self.entries.keys().copied().filter(|&pid| pid != current));
+        }
+
+        for _ in 0..self.config.checks_per_update {
+            let Some(pid) = self.pending_checks.pop_front() else {
+                break;
+            };
+            if pid == current || !self.entries.contains_key(&pid) {
+                continue;
+            }
+            if !(self.exists)(pid) {
+                self.remove(&pid);
+                self.evicted_total += 1;
+            }
+        }
+    }
+}
+
+/// Whether a process with the PID exists, probed with `kill(pid, 0)`, which sends no signal
+fn pid_exists(pid: u32) -> bool {
+    // Larger values would address process groups
+    let Ok(pid) = i32::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+
+    // SAFETY: signal 0 only checks whether the process exists and may be signalled
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    // The process exists but belongs to another user
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
@@ -0,0 +1,113 @@
+//! Open file descriptors of a process
+//!
+//! Descriptors are listed with `proc_pidinfo(PROC_PIDLISTFDS)`, which needs the process to belong to the caller's
+//! user unless the caller is root. Paths are resolved for vnode descriptors only.
+
+use libproc::{bsd_info::BSDInfo, file_info::ListFDs, proc_pid};
+
+use crate::utils::bindings::{proc_bsd_short_info, proc_fd_vnode_path};
+
+/// Kind of object an open file descriptor refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FdType {
+    /// A file, directory or device
+    Vnode,
+    /// A network or Unix domain socket
+    Socket,
+    /// A pipe
+    Pipe,
+    /// A kernel event queue
+    Kqueue,
+    /// A POSIX shared memory object
+    SharedMemory,
+    /// A POSIX semaphore
+    Semaphore,
+    /// A file system events stream
+    FsEvents,
+    /// Any other kind, with its raw `PROX_FDTYPE_*` value
+    Other(u32),
+}
+
+impl FdType {
+    /// Converts a raw `PROX_FDTYPE_*` value.
+    pub fn from_raw(raw: u32) -> Self {
+        match raw as libc::c_int {
+            libc::PROX_FDTYPE_VNODE => Self::Vnode,
+            libc::PROX_FDTYPE_SOCKET => Self::Socket,
+            libc::PROX_FDTYPE_PIPE => Self::Pipe,
+            libc::PROX_FDTYPE_KQUEUE => Self::Kqueue,
+            libc::PROX_FDTYPE_PSHM => Self::SharedMemory,
+            libc::PROX_FDTYPE_PSEM => Self::Semaphore,
+            libc::PROX_FDTYPE_FSEVENTS => Self::FsEvents,
+            _ => Self::Other(raw),
+        }
+    }
+}
+
+/// An open file descriptor of a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenFileInfo {
+    /// Descriptor number
+    pub fd: i32,
+    /// Kind of object the descriptor refers to
+    pub fd_type: FdType,
+    /// Path of the file for vnode descriptors, if it could be resolved
+    pub path: Option<String>,
+}
+
+/// Number of open descriptors in total and of sockets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct FdCounts {
+    pub open: u32,
+    pub sockets: u32,
+}
+
+impl FdCounts {
+    pub(crate) fn from_types(types: impl IntoIterator<Item = FdType>) -> Self {
+        types.into_iter().fold(Self::default(), |counts, fd_type| Self {
+            open: counts.open + 1,
+            sockets: counts.sockets + u32::from(fd_type == FdType::Socket),
+        })
+    }
+}
+
+/// Lists the descriptors of a process as pairs of number and type, or None if they can't be listed.
+///
+/// `max_fds` is a hint for the buffer size, e.g. `pbi_nfiles` of the BSD info of the process.
+fn list_fds(pid: u32, max_fds: usize) -> Option<Vec<(i32, FdType)>> {
+    let fds = proc_pid::listpidinfo::<ListFDs>(pid as i32, max_fds).ok()?;
+    Some(fds.iter().map(|fd| (fd.proc_fd, FdType::from_raw(fd.proc_fdtype))).collect())
+}
+
+/// Counts the open descriptors of a process, or None if they can't be listed, e.g. for lack of permission.
+pub(crate) fn fd_counts(pid: u32, max_fds: usize) -> Option<FdCounts> {
+    list_fds(pid, max_fds)
+        .map(|fds| FdCounts::from_types(fds.into_iter().map(|(_, fd_type)| fd_type)))
+}
+
+/// Lists the descriptors of a process sized by its BSD info, or None if they can't be listed.
+pub(crate) fn process_fds(pid: u32) -> Option<Vec<(i32, FdType)>> {
+    let info = proc_pid::pidinfo::<BSDInfo>(pid as i32, 0).ok()?;
+    list_fds(pid, info.pbi_nfiles as usize)
+}
+
+/// Lists the open descriptors of a process, see [`Process::get_open_files`](super::Process::get_open_files).
+pub(crate) fn open_files(pid: u32) -> crate::Result<Vec<OpenFileInfo>> {
+    let Some(fds) = process_fds(pid) else {
+        // The short BSD info is readable for processes of other users, so it tells a missing process from one the
+        // caller may not inspect
+        proc_bsd_short_info(pid).map_err(|e| {
+            crate::Error::process_error(format!("Failed to get process info: {}", e))
+        })?;
+        return Ok(Vec::new());
+    };
+
+    Ok(fds
+        .into_iter()
+        .map(|(fd, fd_type)| OpenFileInfo {
+            fd,
+            fd_type,
+            path: (fd_type == FdType::Vnode).then(|| proc_fd_vnode_path(pid, fd)).flatten(),
+        })
+        .collect())
+}
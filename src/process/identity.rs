@@ -0,0 +1,93 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libproc::{proc_pid, task_info::TaskAllInfo};
+
+use crate::{
+    error::{Error, Result},
+    utils::bindings::proc_unique_id,
+};
+
+/// Identity of a process that tells it apart from later processes reusing its PID
+///
+/// The kernel recycles PIDs once a process exits, so a PID alone can't tell whether fresh metrics still belong to the
+/// process that was being monitored. A process is identified by its PID together with its start time, at microsecond
+/// precision, and the 64-bit unique ID the kernel assigns to every process and never reuses while the system is up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProcessIdentity {
+    pid: u32,
+    start_time: SystemTime,
+    unique_id: Option<u64>,
+}
+
+impl ProcessIdentity {
+    /// Creates an identity from its parts.
+    pub fn new(pid: u32, start_time: SystemTime, unique_id: Option<u64>) -> Self {
+        Self { pid, start_time, unique_id }
+    }
+
+    /// Reads the identity of the process currently running as `pid`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no process has the PID or it can't be inspected.
+    pub fn of(pid: u32) -> Result<Self> {
+        let info = proc_pid::pidinfo::<TaskAllInfo>(pid as i32, 0)
+            .map_err(|e| Error::process_error(format!("Failed to get process info: {}", e)))?;
+
+        Ok(Self::from_task_info(pid, &info))
+    }
+
+    /// Builds the identity of `pid` from task info that was already read.
+    pub(crate) fn from_task_info(pid: u32, info: &TaskAllInfo) -> Self {
+        let start_time = UNIX_EPOCH
+            + Duration::from_secs(info.pbsd.pbi_start_tvsec)
+            + Duration::from_micros(info.pbsd.pbi_start_tvusec);
+
+        Self { pid, start_time, unique_id: proc_unique_id(pid) }
+    }
+
+    /// The process ID.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// When the process started.
+    pub fn start_time(&self) -> SystemTime {
+        self.start_time
+    }
+
+    /// The kernel's unique ID of the process, if it could be read.
+    pub fn unique_id(&self) -> Option<u64> {
+        self.unique_id
+    }
+
+    /// Whether `other` identifies the same process.
+    ///
+    /// The unique IDs are only compared when both identities have one.
+    pub fn is_same_process(&self, other: &Self) -> bool {
+        let same_unique_id = match (self.unique_id, other.unique_id) {
+            (Some(ours), Some(theirs)) => ours == theirs,
+            _ => true,
+        };
+
+        self.pid == other.pid && self.start_time == other.start_time && same_unique_id
+    }
+
+    /// Checks that the PID still belongs to this process.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::PidReused`] if another process now runs with the PID, or a process error if none does.
+    pub fn verify(&self) -> Result<()> {
+        self.check(&Self::of(self.pid)?)
+    }
+
+    /// Checks that `current` identifies this process.
+    pub(crate) fn check(&self, current: &Self) -> Result<()> {
+        if self.is_same_process(current) {
+            Ok(())
+        } else {
+            Err(Error::PidReused { pid: self.pid })
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Disk I/O rates of a process
+//!
+//! `proc_pid_rusage` only reports the bytes a process read and wrote since it started. [`ProcessIOMonitor`] keeps the
+//! previous reading of each counter and turns the increase since then into bytes per second. It pins the
+//! [`ProcessIdentity`] of the process, so the counters of a process that reused the PID aren't mistaken for its own.
+
+use std::time::{Duration, Instant};
+
+use libproc::pid_rusage::{pidrusage, RUsageInfoV4};
+use parking_lot::Mutex;
+
+use super::ProcessIdentity;
+use crate::{
+    error::{Error, Result},
+    utils::sanitize::sanitize_rate,
+};
+
+/// Readings closer together than this give no rate, as the counters barely moved
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Previous reading of a cumulative byte counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct CounterBaseline {
+    pub(super) sampled_at: Instant,
+    pub(super) bytes: u64,
+}
+
+impl CounterBaseline {
+    pub(super) fn new(sampled_at: Instant, bytes: u64) -> Self {
+        Self { sampled_at, bytes }
+    }
+
+    /// Returns the bytes per second since the baseline and makes `bytes` the new baseline.
+    ///
+    /// Returns None, keeping the baseline, if less than [`MIN_INTERVAL`] passed. A counter that went backwards, e.g.
+    /// because the PID now belongs to another process, gives None as well and restarts from `bytes`.
+    pub(super) fn advance(&mut self, now: Instant, bytes: u64) -> Option<f64> {
+        let elapsed = now.saturating_duration_since(self.sampled_at);
+        if elapsed < MIN_INTERVAL {
+            return None;
+        }
+
+        let delta = bytes.checked_sub(self.bytes);
+        *self = Self::new(now, bytes);
+        delta.map(|delta| sanitize_rate(delta as f64, elapsed.as_secs_f64()))
+    }
+}
+
+/// Disk read and write rates of a single process
+///
+/// Each rate keeps its own baseline, which every call moves forward, so a rate covers the time since the previous
+/// call of the same method, or since the monitor was created.
+///
+/// ```rust,no_run
+/// use darwin_metrics::process::ProcessIOMonitor;
+///
+/// let monitor = ProcessIOMonitor::new(std::process::id())?;
+/// std::thread::sleep(std::time::Duration::from_secs(1));
+/// if let Some(rate) = monitor.read_rate()? {
+///     println!("Reading {rate:.0} bytes/s");
+/// }
+/// # Ok::<(), darwin_metrics::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct ProcessIOMonitor {
+    identity: ProcessIdentity,
+    read: Mutex<CounterBaseline>,
+    write: Mutex<CounterBaseline>,
+}
+
+impl ProcessIOMonitor {
+    /// Creates a monitor of the process `pid`, starting from its current counters.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the process doesn't exist or belongs to another user and the caller isn't root.
+    pub fn new(pid: u32) -> Result<Self> {
+        Self::with_identity(ProcessIdentity::of(pid)?)
+    }
+
+    /// Creates a monitor of the process `identity`, starting from its current counters.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::PidReused`] if another process now runs with the PID, or an error like [`new`](Self::new).
+    pub fn with_identity(identity: ProcessIdentity) -> Result<Self> {
+        let (read_bytes, write_bytes) = disk_io_bytes(&identity)?;
+        let now = Instant::now();
+
+        Ok(Self {
+            identity,
+            read: Mutex::new(CounterBaseline::new(now, read_bytes)),
+            write: Mutex::new(CounterBaseline::new(now, write_bytes)),
+        })
+    }
+
+    /// The PID of the monitored process
+    pub fn pid(&self) -> u32 {
+        self.identity.pid()
+    }
+
+    /// The identity of the monitored process
+    pub fn identity(&self) -> &ProcessIdentity {
+        &self.identity
+    }
+
+    /// Bytes read from disk per second since the previous call
+    ///
+    /// Returns None if the previous call was less than 100 ms ago, or if the counter went backwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::PidReused`] if another process now runs with the PID, or an error if the process can no longer
+    /// be read, e.g. because it exited.
+    pub fn read_rate(&self) -> Result<Option<f64>> {
+        let (read_bytes, _) = disk_io_bytes(&self.identity)?;
+        Ok(self.read.lock().advance(Instant::now(), read_bytes))
+    }
+
+    /// Bytes written to disk per second since the previous call
+    ///
+    /// Returns None like [`ProcessIOMonitor::read_rate`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error like [`ProcessIOMonitor::read_rate`].
+    pub fn write_rate(&self) -> Result<Option<f64>> {
+        let (_, write_bytes) = disk_io_bytes(&self.identity)?;
+        Ok(self.write.lock().advance(Instant::now(), write_bytes))
+    }
+}
+
+/// Reads the bytes the process `identity` read from and wrote to disk since it started.
+///
+/// The identity is checked after the read, so counters of a process that took over the PID are never returned.
+fn disk_io_bytes(identity: &ProcessIdentity) -> Result<(u64, u64)> {
+    let pid = identity.pid();
+    let usage = pidrusage::<RUsageInfoV4>(pid as i32).map_err(|e| {
+        Error::process_error(format!("Failed to get the resource usage of process {pid}: {e}"))
+    })?;
+    identity.verify()?;
+    Ok((usage.ri_diskio_bytesread, usage.ri_diskio_byteswritten))
+}
@@ -0,0 +1,117 @@
+//! Finding processes by name or executable path.
+//!
+//! The process table lists every process in a single sysctl call, but only with the first `MAXCOMLEN` (16) bytes of
+//! its name. A name that fills the whole field may have been cut off, so the full name of those processes is read with
+//! `proc_name` before comparing. The executable path comes from `proc_pidpath`, one call per process.
+//!
+//! Only the processes that match are read in full.
+
+use std::path::{Path, PathBuf};
+
+use libproc::proc_pid;
+
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{extract_proc_name, list_kinfo_procs, MAXCOMLEN},
+};
+
+/// How a process name is compared with the name looked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NameMatch {
+    /// The whole name is equal
+    Exact,
+    /// The name contains the one looked for
+    Contains,
+}
+
+impl NameMatch {
+    fn matches(self, name: &str, query: &str) -> bool {
+        match self {
+            Self::Exact => name == query,
+            Self::Contains => name.contains(query),
+        }
+    }
+
+    /// Whether a process listed as `short` needs its full name read before it can be compared with `query`.
+    fn needs_full_name(self, short: &str, query: &str) -> bool {
+        if !is_truncated(short) {
+            return false;
+        }
+        match self {
+            // The full name starts with the listed one, up to a character cut in half
+            Self::Exact => query.starts_with(short.trim_end_matches(char::REPLACEMENT_CHARACTER)),
+            // The name looked for may straddle the cut
+            Self::Contains => !short.contains(query),
+        }
+    }
+}
+
+/// Whether a name from the process table may have been cut off.
+///
+/// Names of exactly `MAXCOMLEN` bytes can't be told apart from longer ones, and a multi-byte character cut in half
+/// decodes to a replacement character.
+pub(crate) fn is_truncated(short: &str) -> bool {
+    short.len() >= MAXCOMLEN || short.ends_with(char::REPLACEMENT_CHARACTER)
+}
+
+/// The PIDs of `processes`, pairs of PID and process table name, whose name matches `query`.
+///
+/// `full_name` reads the full name of a process whose listed name may be truncated; the listed name is used where it
+/// fails, e.g. because the process exited.
+pub(crate) fn matching_pids(
+    processes: impl IntoIterator<Item = (u32, String)>,
+    query: &str,
+    mode: NameMatch,
+    full_name: impl Fn(u32) -> Option<String>,
+) -> Vec<u32> {
+    processes
+        .into_iter()
+        .filter(|(pid, short)| {
+            if mode.needs_full_name(short, query) {
+                let name = full_name(*pid);
+                mode.matches(name.as_deref().unwrap_or(short), query)
+            } else {
+                mode.matches(short, query)
+            }
+        })
+        .map(|(pid, _)| pid)
+        .collect()
+}
+
+/// The PIDs of the running processes whose name matches `query`.
+pub(crate) fn find_pids_by_name(query: &str, mode: NameMatch) -> Result<Vec<u32>> {
+    let processes = list_kinfo_procs()?
+        .iter()
+        .filter(|proc_info| proc_info.pid() > 0)
+        .map(|proc_info| (proc_info.pid() as u32, extract_proc_name(proc_info)))
+        .collect::<Vec<_>>();
+
+    Ok(matching_pids(processes, query, mode, |pid| proc_pid::name(pid as i32).ok()))
+}
+
+/// The PIDs of the running processes whose executable is `path`.
+///
+/// The kernel reports executable paths with symbolic links resolved, so `path` is compared both as given and
+/// canonicalized. Processes whose path can't be read, e.g. those of other users without root, never match.
+pub(crate) fn find_pids_by_path(path: &Path) -> Result<Vec<u32>> {
+    let canonical = path.canonicalize().ok();
+
+    Ok(list_kinfo_procs()?
+        .iter()
+        .map(|proc_info| proc_info.pid())
+        .filter(|&pid| pid > 0)
+        .map(|pid| pid as u32)
+        .filter(|&pid| {
+            executable_path(pid).is_ok_and(|executable| {
+                executable == path || canonical.as_deref() == Some(executable.as_path())
+            })
+        })
+        .collect())
+}
+
+/// The path of the executable of `pid`.
+pub(crate) fn executable_path(pid: u32) -> Result<PathBuf> {
+    proc_pid::pidpath(pid as i32)
+        .map(PathBuf::from)
+        .map_err(|e| Error::process_error(format!("Failed to get executable path: {e}")))
+}
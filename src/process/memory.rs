@@ -0,0 +1,91 @@
+//! Memory use of a process beyond its resident size.
+//!
+//! The resident size leaves out memory the system compressed and counts shared pages, so it's not what Activity
+//! Monitor shows. Its Memory column is the physical footprint the kernel accounts to the process, which
+//! `proc_pid_rusage` reports for any process of the same user (or any process as root).
+//!
+//! The compressed memory is only in `task_vm_info`, which needs the task port of the process: the own process always
+//! has it, other processes only as root or with the `com.apple.security.cs.debugger` entitlement.
+//!
+//! macOS doesn't account swap per process. The compressor swaps out compressed memory, which the compressed size
+//! still includes.
+
+use libproc::{
+    pid_rusage::{pidrusage, RUsageInfoV4},
+    proc_pid::pidinfo,
+    task_info::TaskInfo,
+};
+
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{
+        mach_port_deallocate, mach_task_self, TaskVmInfo, KERN_SUCCESS, TASK_VM_INFO,
+        TASK_VM_INFO_REV1_COUNT,
+    },
+};
+
+/// Memory use of a process
+///
+/// Fields that need privileges the caller doesn't have are None.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessMemoryInfo {
+    /// Resident memory in bytes
+    pub resident: u64,
+    /// Physical footprint in bytes, as shown by Activity Monitor; None for processes of other users without root
+    pub footprint: Option<u64>,
+    /// Highest physical footprint since the process started, in bytes; None like `footprint`
+    pub peak_footprint: Option<u64>,
+    /// Compressed memory in bytes, including what was swapped out; None for other processes without root or the
+    /// debugger entitlement
+    pub compressed: Option<u64>,
+}
+
+/// Reads the memory use of the process `pid`.
+pub(crate) fn memory_info(pid: u32) -> Result<ProcessMemoryInfo> {
+    let usage = pidrusage::<RUsageInfoV4>(pid as i32).ok();
+    let resident = match &usage {
+        Some(usage) => usage.ri_resident_size,
+        None => {
+            pidinfo::<TaskInfo>(pid as i32, 0)
+                .map_err(|e| Error::process_error(format!("Failed to get process info: {e}")))?
+                .pti_resident_size
+        },
+    };
+
+    Ok(ProcessMemoryInfo {
+        resident,
+        footprint: usage.as_ref().map(|usage| usage.ri_phys_footprint),
+        peak_footprint: usage.as_ref().map(|usage| usage.ri_lifetime_max_phys_footprint),
+        compressed: compressed_memory(pid),
+    })
+}
+
+/// Reads the compressed memory of `pid` from `task_vm_info`, None without its task port.
+fn compressed_memory(pid: u32) -> Option<u64> {
+    let own = pid == std::process::id();
+    let task = if own {
+        mach_task_self()
+    } else {
+        let mut task: libc::mach_port_t = 0;
+        // SAFETY: `task` is valid for writes.
+        let result = unsafe { libc::task_for_pid(mach_task_self(), pid as libc::pid_t, &mut task) };
+        if result != KERN_SUCCESS {
+            return None;
+        }
+        task
+    };
+
+    let mut info = TaskVmInfo::default();
+    let mut count = TASK_VM_INFO_REV1_COUNT;
+    // SAFETY: `info` is valid for writes of `count` natural_t units.
+    let result = unsafe {
+        libc::task_info(task, TASK_VM_INFO, &mut info as *mut TaskVmInfo as *mut i32, &mut count)
+    };
+    if !own {
+        // SAFETY: the port was acquired by task_for_pid above and is released once.
+        unsafe { mach_port_deallocate(mach_task_self(), task) };
+    }
+
+    (result == KERN_SUCCESS).then_some(info.compressed)
+}
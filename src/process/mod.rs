@@ -1,7 +1,36 @@
+//! # Process Module
+//!
+//! Enumeration of processes and their resource usage, lookup by name or executable path
+//! ([`Process::find_by_name`]), process identities that survive PID reuse, App Nap state, and anomaly detection
+//! ([`anomalies`]).
+//!
+//! Rankings such as [`Process::top_by_cpu`] leave out the processes a [`ProcessFilter`] rejects:
+//!
+//! ```rust
+//! # fn main() {
+//! use darwin_metrics::{
+//!     doctest_support::mock_processes,
+//!     process::{AppNapState, ProcessFilter},
+//! };
+//!
+//! let filter = ProcessFilter { exclude_napping: true };
+//! let processes = mock_processes();
+//! let awake: Vec<_> = processes.iter().filter(|process| filter.matches(process)).collect();
+//!
+//! assert_eq!(awake.len(), processes.len() - 1);
+//! assert!(awake.iter().all(|process| process.app_nap_state != Some(AppNapState::Napping)));
+//! # }
+//! ```
+
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt,
+    path::{Path, PathBuf},
     pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
     time::{Duration, Instant, SystemTime},
 };
@@ -9,12 +38,55 @@ use std::{
 use async_trait::async_trait;
 use futures::{Future, Stream};
 use libproc::{pid_rusage, proc_pid, task_info};
+use tokio::time::MissedTickBehavior;
 
 // Use the bindings from utils
-use crate::utils::bindings::{
-    extract_proc_name, is_system_process, kinfo_proc, sysctl,
-    sysctl_constants::{CTL_KERN, KERN_PROC, KERN_PROC_ALL},
+use crate::utils::{
+    bindings::{extract_proc_name, is_system_process, list_kinfo_procs, proc_bsd_short_info},
+    byte_size::ByteSize,
+    sanitize::{sanitize_rate, Percentage},
+};
+
+mod anomaly;
+mod app_nap;
+mod cpu_history;
+mod files;
+mod identity;
+mod io;
+mod lookup;
+mod memory;
+#[cfg(feature = "profiling")]
+pub mod sampler;
+mod state;
+mod tree;
+
+pub use anomaly::{
+    anomalies, anomalies_with_baseline, AnomalyBaseline, AnomalyKind, AnomalyOptions,
+    ProcessAnomaly,
 };
+pub(crate) use anomaly::{sample_processes, ProcessSample};
+pub use app_nap::AppNapState;
+pub use cpu_history::{
+    cpu_history_stats, set_cpu_history_config, CpuHistoryConfig, CpuHistoryStats,
+};
+use cpu_history::{get_cpu_history, CpuHistoryEntry};
+pub(crate) use files::process_fds;
+pub use files::{FdType, OpenFileInfo};
+pub use identity::ProcessIdentity;
+pub use io::ProcessIOMonitor;
+use lookup::NameMatch;
+pub use memory::ProcessMemoryInfo;
+pub use state::ProcessState;
+pub use tree::ProcessTree;
+
+/// CPU usage in percent of one core from `cpu_time_delta` microseconds of CPU time used over `elapsed_secs`.
+///
+/// Capped at 100% per logical CPU, the most a process can use.
+pub(crate) fn cpu_usage_percent(cpu_time_delta: u64, elapsed_secs: f64) -> f64 {
+    let logical_cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let usage = sanitize_rate(cpu_time_delta as f64 / 1_000_000.0, elapsed_secs) * 100.0;
+    Percentage::new_clamped_to(usage, logical_cpus as f64 * 100.0).value()
+}
 
 #[async_trait]
 pub trait ProcessInfo {
@@ -22,6 +94,7 @@ pub trait ProcessInfo {
 }
 
 #[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ProcessIOStats {
     pub read_bytes: u64,
     pub write_bytes: u64,
@@ -40,6 +113,29 @@ impl fmt::Debug for ProcessIOStats {
     }
 }
 
+impl ProcessIOStats {
+    /// Bytes read from disk as a [`ByteSize`]; named apart from the `read_bytes` field
+    pub fn read_size(&self) -> ByteSize {
+        ByteSize::from_bytes(self.read_bytes)
+    }
+
+    /// Bytes written to disk as a [`ByteSize`]; named apart from the `write_bytes` field
+    pub fn write_size(&self) -> ByteSize {
+        ByteSize::from_bytes(self.write_bytes)
+    }
+
+    fn from_rusage(usage: &pid_rusage::RUsageInfoV4) -> Self {
+        Self {
+            read_bytes: usage.ri_diskio_bytesread,
+            write_bytes: usage.ri_diskio_byteswritten,
+            read_count: usage.ri_diskio_bytesread / 4096, /* Approximation by bytes read /
+                                                           * typical block size */
+            write_count: usage.ri_diskio_byteswritten / 4096, /* Approximation by bytes written /
+                                                               * typical block size */
+        }
+    }
+}
+
 impl Clone for ProcessIOStats {
     fn clone(&self) -> Self {
         Self {
@@ -51,29 +147,46 @@ impl Clone for ProcessIOStats {
     }
 }
 
-use std::sync::Mutex;
-
-use once_cell::sync::Lazy as SyncLazy;
-
-/// Static cache for tracking CPU usage calculations between calls
-static CPU_HISTORY: SyncLazy<Mutex<HashMap<u32, (Instant, u64)>>> =
-    SyncLazy::new(|| Mutex::new(HashMap::new()));
+/// Criteria for the processes to include in rankings such as [`Process::top_by_cpu`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessFilter {
+    /// Leave out processes napping under App Nap, whose CPU usage understates what they use once focused and
+    /// distorts views of an idle machine
+    pub exclude_napping: bool,
+}
 
-/// Get CPU history tracking map
-#[allow(clippy::disallowed_methods)]
-fn get_cpu_history() -> std::sync::MutexGuard<'static, HashMap<u32, (Instant, u64)>> {
-    CPU_HISTORY.lock().unwrap()
+impl ProcessFilter {
+    /// Whether `process` passes the filter
+    pub fn matches(&self, process: &Process) -> bool {
+        !(self.exclude_napping && process.app_nap_state == Some(AppNapState::Napping))
+    }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Process {
     pub pid: u32,
     pub name: String,
     pub cpu_usage: f64,
     pub memory_usage: u64,
+    /// Physical footprint in bytes, as shown by Activity Monitor, or None if it couldn't be read, e.g. for a process
+    /// of another user without root
+    pub footprint: Option<u64>,
+    /// Time since the process started, serialized as seconds
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_secs"))]
     pub uptime: Duration,
     pub io_stats: ProcessIOStats,
     pub thread_count: u32,
+    /// Whether the process is stopped, e.g. by `SIGSTOP` or a debugger
     pub is_suspended: bool,
+    /// Scheduling state, or None if it wasn't read or is unknown
+    pub state: Option<ProcessState>,
+    /// Whether the process is throttled by App Nap, if it was read
+    pub app_nap_state: Option<AppNapState>,
+    /// Number of open file descriptors, or None if they couldn't be listed, e.g. for a process of another user
+    pub open_fd_count: Option<u32>,
+    /// Number of open sockets, or None if the descriptors couldn't be listed
+    pub socket_count: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pending_future: Option<Pin<Box<dyn Future<Output = crate::Result<Process>> + Send>>>,
 }
 
@@ -84,15 +197,23 @@ impl Process {
             name: name.into(),
             cpu_usage: 0.0,
             memory_usage: 0,
+            footprint: None,
             uptime: Duration::default(),
             io_stats: ProcessIOStats::default(),
             thread_count: 0,
             is_suspended: false,
+            state: None,
+            app_nap_state: None,
+            open_fd_count: None,
+            socket_count: None,
             pending_future: None,
         }
     }
 
     /// Get all processes using the sysctl API for better efficiency (based on Bottom's approach)
+    ///
+    /// Every process is read in full, which costs a few syscalls per process: expect tens of milliseconds for a few
+    /// hundred processes. Use [`Process::get_all_basic`] when only PIDs and names are needed.
     pub async fn get_all() -> crate::Result<Vec<Self>> {
         // Try to use sysctl first for bulk retrieval
         match Self::get_all_via_sysctl().await {
@@ -104,78 +225,137 @@ impl Process {
         }
     }
 
+    /// Get the PID and name of all processes, without their resource usage
+    ///
+    /// This takes a single `sysctl` call however many processes run, and is orders of magnitude cheaper than
+    /// [`Process::get_all`]. Every other field keeps its default, and names are truncated to 16 bytes by the kernel.
+    pub fn get_all_basic() -> crate::Result<Vec<Self>> {
+        Ok(list_kinfo_procs()?
+            .iter()
+            .filter(|proc_info| proc_info.pid() > 0)
+            .map(|proc_info| Process::new(proc_info.pid() as u32, extract_proc_name(proc_info)))
+            .collect())
+    }
+
     /// Get all processes using the sysctl API for efficient bulk retrieval
+    ///
+    /// The names come from the process table, the task info of each process is read once, and the CPU history is
+    /// locked once for the whole batch.
     async fn get_all_via_sysctl() -> crate::Result<Vec<Self>> {
-        use std::{mem, os::raw::c_void, ptr};
-
-        unsafe {
-            // First call to get the size of the buffer needed
-            let mut mib = [CTL_KERN, KERN_PROC, KERN_PROC_ALL, 0, 0, 0];
-            let mut size: usize = 0;
-
-            if sysctl(mib.as_mut_ptr(), 3, ptr::null_mut(), &mut size, ptr::null(), 0) < 0 {
-                return Err(crate::Error::process_error("Failed to get process list size"));
-            }
-
-            // Calculate number of processes
-            let count = size / mem::size_of::<kinfo_proc>();
+        let mut processes = Self::get_all_basic()?;
+
+        // Processes may exit or be off-limits between listing and inspection; they keep their basic information
+        let detailed: Vec<(usize, task_info::TaskAllInfo, SystemTime)> = processes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, process)| {
+                let (info, start_time) = Self::read_task_info(process.pid).ok()?;
+                Some((index, info, start_time))
+            })
+            .collect();
+
+        let readings: Vec<(ProcessIdentity, u64)> = detailed
+            .iter()
+            .map(|(index, info, _)| {
+                let identity = ProcessIdentity::from_task_info(processes[*index].pid, info);
+                (identity, info.ptinfo.pti_total_user + info.ptinfo.pti_total_system)
+            })
+            .collect();
+        // The listing doubles as the set of running processes, so exited ones leave the history without a probe each
+        let live: HashSet<u32> = processes.iter().map(|process| process.pid).collect();
+        let cpu_usages = Self::calculate_cpu_usages_with_live(&readings, Some(&live));
+
+        for ((index, info, start_time), cpu_usage) in detailed.iter().zip(cpu_usages) {
+            processes[*index].fill_details(info, *start_time, cpu_usage).await;
+        }
 
-            // Allocate buffer
-            let mut processes = Vec::<kinfo_proc>::with_capacity(count);
-            let processes_ptr = processes.as_mut_ptr() as *mut c_void;
+        Ok(processes)
+    }
 
-            // Second call to actually get the data
-            if sysctl(mib.as_mut_ptr(), 3, processes_ptr, &mut size, ptr::null(), 0) < 0 {
-                return Err(crate::Error::process_error("Failed to get process information"));
-            }
+    /// Get all processes belonging to a login session, i.e. whose real user ID is `uid`
+    ///
+    /// Use [`crate::system::sessions`] to find the uid of each logged-in user.
+    pub async fn by_session(uid: u32) -> crate::Result<Vec<Self>> {
+        let pids: Vec<u32> = list_kinfo_procs()?
+            .iter()
+            .filter(|proc_info| proc_info.pid() > 0 && proc_info.ruid() == uid)
+            .map(|proc_info| proc_info.pid() as u32)
+            .collect();
+
+        Ok(Self::get_by_pids(pids).await)
+    }
 
-            // Set the length of the vector
-            let count = size / mem::size_of::<kinfo_proc>();
-            processes.set_len(count);
+    /// Get the processes named exactly `name`
+    ///
+    /// The process table holds only the first 16 bytes of each name, so the full name is read for the processes
+    /// whose listed name may be truncated. Only the matching processes are read in full.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the processes can't be listed.
+    pub async fn find_by_name(name: &str) -> crate::Result<Vec<Self>> {
+        Ok(Self::get_by_pids(lookup::find_pids_by_name(name, NameMatch::Exact)?).await)
+    }
 
-            // Convert to our Process struct format
-            let mut result = Vec::with_capacity(count);
+    /// Get the processes whose name contains `pattern`
+    ///
+    /// Names are compared like in [`Process::find_by_name`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the processes can't be listed.
+    pub async fn find_by_name_contains(pattern: &str) -> crate::Result<Vec<Self>> {
+        Ok(Self::get_by_pids(lookup::find_pids_by_name(pattern, NameMatch::Contains)?).await)
+    }
 
-            for proc_info in processes {
-                let pid = proc_info.kp_proc.p_pid;
-                if pid <= 0 {
-                    continue;
-                }
+    /// Get the processes running the executable at `path`
+    ///
+    /// Symbolic links in `path` are resolved like the kernel does for executable paths. The paths of other users'
+    /// processes can only be read as root, so without it they're never found.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the processes can't be listed.
+    pub async fn find_by_path(path: &Path) -> crate::Result<Vec<Self>> {
+        Ok(Self::get_by_pids(lookup::find_pids_by_path(path)?).await)
+    }
 
-                // Use the helper function from bindings
-                let name = extract_proc_name(&proc_info);
-
-                // Create a basic process with the information we have
-                let process = Process {
-                    pid: pid as u32,
-                    name,
-                    cpu_usage: 0.0,  // Will populate with more detailed info later
-                    memory_usage: 0, // Will populate with more detailed info later
-                    uptime: Duration::default(),
-                    io_stats: ProcessIOStats::default(),
-                    thread_count: 0,
-                    is_suspended: false,
-                    pending_future: None,
-                };
+    /// Get the path of the executable the given process runs
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the process doesn't exist or may not be inspected, e.g. one of another user without root.
+    pub fn executable_path(pid: u32) -> crate::Result<PathBuf> {
+        lookup::executable_path(pid)
+    }
 
-                result.push(process);
+    /// Reads the processes `pids` in full, leaving out those that exited in the meantime
+    async fn get_by_pids(pids: Vec<u32>) -> Vec<Self> {
+        let mut processes = Vec::with_capacity(pids.len());
+        for pid in pids {
+            // Processes may exit between listing and inspection
+            if let Ok(process) = Self::get_by_pid(pid).await {
+                processes.push(process);
             }
+        }
+        processes
+    }
 
-            // Populate more detailed information for each process
-            for process in &mut result {
-                // Try to get additional information via libproc, but don't fail if we can't
-                if let Ok(detailed) = Self::get_by_pid(process.pid).await {
-                    process.cpu_usage = detailed.cpu_usage;
-                    process.memory_usage = detailed.memory_usage;
-                    process.uptime = detailed.uptime;
-                    process.io_stats = detailed.io_stats;
-                    process.thread_count = detailed.thread_count;
-                    process.is_suspended = detailed.is_suspended;
-                }
-            }
+    /// Get the `n` processes using the most CPU that pass `filter`, busiest first
+    ///
+    /// CPU usage is measured since the previous reading of each process, so the first call in a program ranks every
+    /// process at zero usage.
+    pub async fn top_by_cpu(n: usize, filter: &ProcessFilter) -> crate::Result<Vec<Self>> {
+        Ok(Self::rank_by_cpu(Self::get_all().await?, n, filter))
+    }
 
-            Ok(result)
-        }
+    /// Keeps the `n` processes using the most CPU that pass `filter`, busiest first
+    fn rank_by_cpu(processes: Vec<Self>, n: usize, filter: &ProcessFilter) -> Vec<Self> {
+        let mut processes: Vec<Self> =
+            processes.into_iter().filter(|process| filter.matches(process)).collect();
+        processes.sort_by(|a, b| b.cpu_usage.total_cmp(&a.cpu_usage));
+        processes.truncate(n);
+        processes
     }
 
     /// Fallback method using libproc (the original implementation)
@@ -202,10 +382,39 @@ impl Process {
     }
 
     pub async fn get_by_pid(pid: u32) -> crate::Result<Self> {
+        Self::fetch(pid).await.map(|(process, _)| process)
+    }
+
+    /// Get a process by its identity
+    ///
+    /// Unlike [`Process::get_by_pid`], this fails with [`crate::Error::PidReused`] instead of returning an unrelated
+    /// process when the original one has exited and its PID was reused.
+    pub async fn get_by_identity(identity: &ProcessIdentity) -> crate::Result<Self> {
+        let (process, current) = Self::fetch(identity.pid()).await?;
+        identity.check(&current)?;
+        Ok(process)
+    }
+
+    /// Reads a process along with the identity of the process the readings came from
+    async fn fetch(pid: u32) -> crate::Result<(Self, ProcessIdentity)> {
         let name = libproc::proc_pid::name(pid as i32).map_err(|e| {
             crate::Error::process_error(format!("Failed to get process name: {}", e))
         })?;
+        let (proc_info, start_time) = Self::read_task_info(pid)?;
 
+        // Calculate CPU usage with history for more accurate rate calculation
+        let identity = ProcessIdentity::from_task_info(pid, &proc_info);
+        let total_cpu_time = proc_info.ptinfo.pti_total_user + proc_info.ptinfo.pti_total_system;
+        let cpu_usage = Self::calculate_cpu_usage(&identity, total_cpu_time);
+
+        let mut process = Process::new(pid, name);
+        process.fill_details(&proc_info, start_time, cpu_usage).await;
+
+        Ok((process, identity))
+    }
+
+    /// Reads the task info of a process along with its start time, checking that the start time is plausible
+    fn read_task_info(pid: u32) -> crate::Result<(task_info::TaskAllInfo, SystemTime)> {
         let proc_info = libproc::proc_pid::pidinfo::<task_info::TaskAllInfo>(pid as i32, 0)
             .map_err(|e| {
                 crate::Error::process_error(format!("Failed to get process info: {}", e))
@@ -240,52 +449,88 @@ impl Process {
             },
         }
 
-        // Calculate memory usage
-        let memory_usage = proc_info.ptinfo.pti_resident_size;
-
-        // Calculate CPU usage with history for more accurate rate calculation
-        let total_cpu_time = proc_info.ptinfo.pti_total_user + proc_info.ptinfo.pti_total_system;
-        let cpu_usage = Self::calculate_cpu_usage(pid, total_cpu_time);
+        Ok((proc_info, start_time))
+    }
 
+    /// Fills in the resource usage of the process from its task info and the readings that need further syscalls
+    async fn fill_details(
+        &mut self,
+        proc_info: &task_info::TaskAllInfo,
+        start_time: SystemTime,
+        cpu_usage: f64,
+    ) {
+        let pid = self.pid;
+
+        self.cpu_usage = cpu_usage;
+        self.memory_usage = proc_info.ptinfo.pti_resident_size;
+        self.uptime = SystemTime::now().duration_since(start_time).unwrap_or(Duration::ZERO);
         // Get thread count (convert from i32 to u32)
-        let thread_count = proc_info.ptinfo.pti_threadnum as u32;
-
-        // Check if process is suspended Use a heuristic since TaskInfo doesn't have pti_suspend_count in this version
-        let is_suspended = false; // We can't easily determine if a process is suspended
+        self.thread_count = proc_info.ptinfo.pti_threadnum as u32;
+        self.state = ProcessState::from_raw(proc_info.pbsd.pbi_status);
+        self.is_suspended = self.state == Some(ProcessState::Stopped);
+
+        // The I/O statistics and the footprint both come from the resource usage
+        let usage = pid_rusage::pidrusage::<pid_rusage::RUsageInfoV4>(pid as i32).ok();
+        self.io_stats = usage.as_ref().map(ProcessIOStats::from_rusage).unwrap_or_default();
+        self.footprint = usage.map(|usage| usage.ri_phys_footprint);
+        self.app_nap_state = app_nap::app_nap_state(pid).ok();
+
+        // Listing descriptors of other users' processes needs root
+        let fd_counts = files::fd_counts(pid, proc_info.pbsd.pbi_nfiles as usize);
+        self.open_fd_count = fd_counts.map(|counts| counts.open);
+        self.socket_count = fd_counts.map(|counts| counts.sockets);
+    }
 
-        // Get I/O statistics
-        let io_stats = (Self::get_process_io_stats(pid).await).unwrap_or_default();
+    /// Calculate CPU usage as a percentage, using history to calculate the rate of change
+    fn calculate_cpu_usage(identity: &ProcessIdentity, current_cpu_time: u64) -> f64 {
+        Self::calculate_cpu_usages(&[(*identity, current_cpu_time)])[0]
+    }
 
-        Ok(Process {
-            pid,
-            name,
-            cpu_usage,
-            memory_usage,
-            uptime: SystemTime::now().duration_since(start_time).unwrap_or(Duration::ZERO),
-            io_stats,
-            thread_count,
-            is_suspended,
-            pending_future: None,
-        })
+    /// Calculate the CPU usage of a batch of processes from their CPU times, locking the history once
+    fn calculate_cpu_usages(readings: &[(ProcessIdentity, u64)]) -> Vec<f64> {
+        Self::calculate_cpu_usages_with_live(readings, None)
     }
 
-    /// Calculate CPU usage as a percentage, using history to calculate the rate of change
-    fn calculate_cpu_usage(pid: u32, current_cpu_time: u64) -> f64 {
-        let mut history = get_cpu_history();
+    /// Calculate the CPU usage of a batch of processes like [`Process::calculate_cpu_usages`]
+    ///
+    /// With `live`, the PIDs of every running process, the history drops the processes missing from it in one pass
+    /// instead of probing tracked processes one by one.
+    fn calculate_cpu_usages_with_live(
+        readings: &[(ProcessIdentity, u64)],
+        live: Option<&HashSet<u32>>,
+    ) -> Vec<f64> {
         let now = Instant::now();
+        let mut history = get_cpu_history();
+        if let Some(live) = live {
+            history.retain_live(live, now);
+        }
 
-        // Get previous measurement if available
-        let cpu_usage = if let Some(&(prev_time, prev_cpu_time)) = history.get(&pid) {
-            let time_delta = now.duration_since(prev_time).as_secs_f64();
+        readings
+            .iter()
+            .map(|(identity, current_cpu_time)| {
+                // Get previous measurement if available, ignoring one taken of an earlier process with the same PID
+                let previous = if live.is_some() {
+                    history.record(*identity, now, *current_cpu_time)
+                } else {
+                    history.update(*identity, now, *current_cpu_time)
+                };
+                Self::cpu_usage_since(previous, now, *current_cpu_time)
+            })
+            .collect()
+    }
+
+    fn cpu_usage_since(
+        previous: Option<CpuHistoryEntry>,
+        now: Instant,
+        current_cpu_time: u64,
+    ) -> f64 {
+        if let Some(previous) = previous {
+            let time_delta = now.duration_since(previous.sampled_at).as_secs_f64();
 
             // Only calculate if we have a meaningful time difference
             if time_delta >= 0.1 {
-                // Calculate CPU usage as percentage
-                let cpu_time_delta = current_cpu_time.saturating_sub(prev_cpu_time) as f64;
-                let usage = (cpu_time_delta / time_delta / 1_000_000.0) * 100.0;
-
-                // Cap at 100% per logical CPU (though could be higher for multi-threaded processes)
-                usage.min(800.0) // 800% cap assuming 8 cores max utilization
+                let cpu_time_delta = current_cpu_time.saturating_sub(previous.cpu_time);
+                cpu_usage_percent(cpu_time_delta, time_delta)
             } else {
                 // Time delta too small, just return previous value or 0
                 0.0
@@ -293,43 +538,18 @@ impl Process {
         } else {
             // First measurement, can't calculate rate yet
             0.0
-        };
-
-        // Update history
-        history.insert(pid, (now, current_cpu_time));
-
-        // Clean up old history entries This is a simple approach - in a production system, you might want a more
-        // sophisticated cleanup
-        if history.len() > 1000 {
-            // Get all PIDs we're tracking
-            let pids: Vec<u32> = history.keys().copied().collect();
-
-            // Check if each process still exists
-            for pid in pids {
-                if proc_pid::pidinfo::<task_info::TaskAllInfo>(pid as i32, 0).is_err() {
-                    history.remove(&pid);
-                }
-            }
         }
-
-        cpu_usage
     }
 
-    async fn get_process_io_stats(pid: u32) -> crate::Result<ProcessIOStats> {
-        use pid_rusage::RUsageInfoV4;
-
-        let usage = pid_rusage::pidrusage::<RUsageInfoV4>(pid as i32).map_err(|e| {
-            crate::Error::process_error(format!("Failed to get process I/O stats: {}", e))
-        })?;
-
-        Ok(ProcessIOStats {
-            read_bytes: usage.ri_diskio_bytesread,
-            write_bytes: usage.ri_diskio_byteswritten,
-            read_count: usage.ri_diskio_bytesread / 4096, /* Approximation by bytes read /
-                                                           * typical block size */
-            write_count: usage.ri_diskio_byteswritten / 4096, /* Approximation by bytes written /
-                                                               * typical block size */
-        })
+    /// Get the memory use of the given process, including its footprint and compressed memory
+    ///
+    /// See [`ProcessMemoryInfo`] for the fields that need privileges; they're None without them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not even the resident size of the process can be read, e.g. because it exited.
+    pub fn memory_info(pid: u32) -> crate::Result<ProcessMemoryInfo> {
+        memory::memory_info(pid)
     }
 
     pub async fn get_process_start_time(pid: u32) -> crate::Result<SystemTime> {
@@ -374,6 +594,48 @@ impl Process {
         ProcessMetricsStream::new(pid, interval)
     }
 
+    /// Get whether the given process is throttled by App Nap or background suppression
+    ///
+    /// See [`AppNapState`] for how reliable the state is.
+    pub fn app_nap_state(pid: u32) -> crate::Result<AppNapState> {
+        app_nap::app_nap_state(pid)
+    }
+
+    /// Get the open file descriptors of the given process, with the path of each open file where it resolves
+    ///
+    /// Returns an empty list if the caller may not inspect the process, e.g. one of another user without root.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the process doesn't exist.
+    pub fn get_open_files(pid: u32) -> crate::Result<Vec<OpenFileInfo>> {
+        files::open_files(pid)
+    }
+
+    /// Stops the process `pid` with `SIGSTOP`, like `kill -STOP`, until it's resumed.
+    ///
+    /// Stopping the calling process blocks it until another process resumes it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`crate::Error::PermissionDenied`] for a process of another user unless the caller is root, and an error
+    /// if there is no process `pid`.
+    #[cfg(feature = "control")]
+    pub fn suspend(pid: u32) -> crate::Result<()> {
+        state::send_signal(pid, libc::SIGSTOP)
+    }
+
+    /// Continues the process `pid` with `SIGCONT`, like `kill -CONT`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`crate::Error::PermissionDenied`] for a process of another user unless the caller is root, and an error
+    /// if there is no process `pid`.
+    #[cfg(feature = "control")]
+    pub fn resume(pid: u32) -> crate::Result<()> {
+        state::send_signal(pid, libc::SIGCONT)
+    }
+
     /// Get the parent process ID for the given process
     pub async fn get_parent_pid(pid: u32) -> crate::Result<Option<u32>> {
         // Special case for PID 0 and 1
@@ -397,19 +659,11 @@ impl Process {
     }
 
     /// Get all child processes for the given process
+    ///
+    /// Use [`Process::get_process_tree`] for grandchildren and further descendants.
     pub async fn get_child_processes(pid: u32) -> crate::Result<Vec<Self>> {
-        let all_processes = Self::get_all().await?;
-
-        let mut children = Vec::new();
-        for process in all_processes {
-            if let Ok(Some(parent_pid)) = Self::get_parent_pid(process.pid).await {
-                if parent_pid == pid {
-                    children.push(process);
-                }
-            }
-        }
-
-        Ok(children)
+        let tree = Self::get_process_tree(ProcessTree::SYNTHETIC_ROOT).await?;
+        Ok(tree.children(pid).into_iter().cloned().collect())
     }
 
     /// Check if this process is a system process (running as root with PID < 1000)
@@ -418,71 +672,21 @@ impl Process {
         is_system_process(self.pid, &self.name)
     }
 
-    pub async fn get_process_tree() -> crate::Result<Vec<(Self, usize)>> {
-        // Start with all processes - use libproc directly to avoid sysctl errors
-        let all_processes = Self::get_all_via_libproc().await?;
-
-        // Use a scope to limit the lifetime of temporary data structures
-        let result = {
-            // Create a map of PID to process
-            let mut pid_to_process = std::collections::HashMap::with_capacity(all_processes.len());
-            for process in all_processes {
-                pid_to_process.insert(process.pid, process);
-            }
-
-            // Create a map of parent PID to child PIDs
-            let mut parent_to_children = std::collections::HashMap::new();
-            for &pid in pid_to_process.keys() {
-                if let Ok(Some(parent_pid)) = Self::get_parent_pid(pid).await {
-                    parent_to_children.entry(parent_pid).or_insert_with(Vec::new).push(pid);
-                }
-            }
-
-            // Find root processes (usually PID 1 or processes with no parent)
-            let mut root_pids = Vec::new();
-            for &pid in pid_to_process.keys() {
-                if let Ok(ppid) = Self::get_parent_pid(pid).await {
-                    if let Some(parent_pid) = ppid {
-                        if parent_pid == 0 || !pid_to_process.contains_key(&parent_pid) {
-                            root_pids.push(pid);
-                        }
-                    } else {
-                        // No parent pid (ppid is None)
-                        root_pids.push(pid);
-                    }
-                }
-            }
-
-            // Build the tree using depth-first traversal
-            let mut result = Vec::with_capacity(pid_to_process.len());
-            let mut stack = Vec::new();
-
-            // Push root processes to the stack with depth 0
-            for pid in root_pids {
-                if let Some(process) = pid_to_process.get(&pid) {
-                    stack.push((process.clone(), 0));
-                }
-            }
-
-            // Perform depth-first traversal
-            while let Some((process, depth)) = stack.pop() {
-                result.push((process.clone(), depth));
-
-                // Push children to the stack with increased depth
-                if let Some(children) = parent_to_children.get(&process.pid) {
-                    // Push in reverse order so they are processed in the original order
-                    for &child_pid in children.iter().rev() {
-                        if let Some(child) = pid_to_process.get(&child_pid) {
-                            stack.push((child.clone(), depth + 1));
-                        }
-                    }
-                }
-            }
-
-            result
-        }; // End of scope - all temporary structures are dropped here
-
-        Ok(result)
+    /// Get the tree of processes below `root_pid`, or of every process with [`ProcessTree::SYNTHETIC_ROOT`]
+    ///
+    /// The tree is built from a single snapshot of the process table, see [`ProcessTree`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the processes can't be listed or `root_pid` isn't running.
+    pub async fn get_process_tree(root_pid: u32) -> crate::Result<ProcessTree> {
+        let parent_pids: HashMap<u32, u32> = list_kinfo_procs()?
+            .iter()
+            .filter(|proc_info| proc_info.pid() > 0 && proc_info.ppid() >= 0)
+            .map(|proc_info| (proc_info.pid() as u32, proc_info.ppid() as u32))
+            .collect();
+
+        ProcessTree::from_parts(Self::get_all().await?, &parent_pids, root_pid)
     }
 }
 
@@ -493,10 +697,15 @@ impl fmt::Debug for Process {
             .field("name", &self.name)
             .field("cpu_usage", &self.cpu_usage)
             .field("memory_usage", &self.memory_usage)
+            .field("footprint", &self.footprint)
             .field("uptime", &self.uptime)
             .field("io_stats", &self.io_stats)
             .field("thread_count", &self.thread_count)
             .field("is_suspended", &self.is_suspended)
+            .field("state", &self.state)
+            .field("app_nap_state", &self.app_nap_state)
+            .field("open_fd_count", &self.open_fd_count)
+            .field("socket_count", &self.socket_count)
             .field("pending_future", &self.pending_future.as_ref().map(|_| "Future"))
             .finish()
     }
@@ -509,24 +718,134 @@ impl Clone for Process {
             name: self.name.clone(),
             cpu_usage: self.cpu_usage,
             memory_usage: self.memory_usage,
+            footprint: self.footprint,
             uptime: self.uptime,
             io_stats: self.io_stats.clone(),
             thread_count: self.thread_count,
             is_suspended: self.is_suspended,
+            state: self.state,
+            app_nap_state: self.app_nap_state,
+            open_fd_count: self.open_fd_count,
+            socket_count: self.socket_count,
             pending_future: None,
         }
     }
 }
 
+/// Stream of metrics of a single process
+///
+/// The stream is pinned to the identity of the process running as `pid` when it's created. If that process exits and
+/// its PID is reused, the stream yields [`crate::Error::PidReused`] instead of the metrics of the new process.
+///
+/// Ticks missed while the consumer is busy are skipped rather than queued, so a slow consumer gets the latest metrics
+/// at most once per interval instead of a burst of stale ones. The interval can be changed while the stream runs
+/// through [`set_interval`](ProcessMetricsStream::set_interval) or an [`interval_handle`](Self::interval_handle).
 pub struct ProcessMetricsStream {
     pid: u32,
+    identity: Option<ProcessIdentity>,
     interval: tokio::time::Interval,
+    period: StreamIntervalHandle,
+    ends_on_exit: bool,
+    finished: bool,
     pending_future: Option<Pin<Box<dyn Future<Output = crate::Result<Process>> + Send>>>,
 }
 
+/// Handle to change the interval of a [`ProcessMetricsStream`] from outside the stream, e.g. from another task
+#[derive(Debug, Clone)]
+pub struct StreamIntervalHandle(Arc<AtomicU64>);
+
+impl StreamIntervalHandle {
+    fn new(interval: Duration) -> Self {
+        Self(Arc::new(AtomicU64::new(interval.as_nanos() as u64)))
+    }
+
+    /// Changes the interval, taking effect from the next tick on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero, like [`tokio::time::interval`].
+    pub fn set(&self, interval: Duration) {
+        assert!(!interval.is_zero(), "The interval of a process metrics stream must be non-zero");
+        self.0.store(interval.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// The current interval.
+    pub fn get(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Ticks every `period`, skipping the ticks missed while the stream wasn't polled
+fn skipping_interval(start: tokio::time::Instant, period: Duration) -> tokio::time::Interval {
+    let mut interval = tokio::time::interval_at(start, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    interval
+}
+
+/// Whether the error of a reading means the process is gone for good
+fn has_exited(pid: u32, error: &crate::Error) -> bool {
+    error.is_pid_reused()
+        || proc_bsd_short_info(pid).is_err_and(|e| e.raw_os_error() == Some(libc::ESRCH))
+}
+
 impl ProcessMetricsStream {
     pub fn new(pid: u32, interval: Duration) -> Self {
-        Self { pid, interval: tokio::time::interval(interval), pending_future: None }
+        Self::with_parts(pid, ProcessIdentity::of(pid).ok(), interval)
+    }
+
+    /// Creates a stream for the process with the given identity
+    pub fn with_identity(identity: ProcessIdentity, interval: Duration) -> Self {
+        Self::with_parts(identity.pid(), Some(identity), interval)
+    }
+
+    fn with_parts(pid: u32, identity: Option<ProcessIdentity>, interval: Duration) -> Self {
+        Self {
+            pid,
+            identity,
+            interval: skipping_interval(tokio::time::Instant::now(), interval),
+            period: StreamIntervalHandle::new(interval),
+            ends_on_exit: false,
+            finished: false,
+            pending_future: None,
+        }
+    }
+
+    /// The identity of the monitored process, if the process existed when it was last looked up
+    pub fn identity(&self) -> Option<&ProcessIdentity> {
+        self.identity.as_ref()
+    }
+
+    /// Changes the interval, taking effect from the next tick on
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.period.set(interval);
+    }
+
+    /// Handle to change the interval once the stream has been moved, e.g. into [`into_stream`](Self::into_stream)
+    pub fn interval_handle(&self) -> StreamIntervalHandle {
+        self.period.clone()
+    }
+
+    /// Converts into a stream that ends when the process exits
+    ///
+    /// The stream yields the metrics of the process on every tick. Once the process has exited, or its PID was reused,
+    /// the stream yields that error as its last item and ends. Other errors, e.g. a transient failure to read the
+    /// process, are yielded without ending the stream.
+    pub fn into_stream(mut self) -> impl Stream<Item = crate::Result<Process>> + Unpin {
+        self.ends_on_exit = true;
+        self
+    }
+
+    /// Yields the result of a reading, ending the stream after it if the process has exited
+    fn finish(&mut self, result: crate::Result<Process>) -> Poll<Option<crate::Result<Process>>> {
+        self.pending_future = None;
+        if let Err(error) = &result {
+            self.finished = self.ends_on_exit && has_exited(self.pid, error);
+        }
+        Poll::Ready(Some(result))
     }
 }
 
@@ -534,7 +853,10 @@ impl fmt::Debug for ProcessMetricsStream {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ProcessMetricsStream")
             .field("pid", &self.pid)
+            .field("identity", &self.identity)
             .field("interval", &self.interval)
+            .field("ends_on_exit", &self.ends_on_exit)
+            .field("finished", &self.finished)
             .field("pending_future", &self.pending_future.as_ref().map(|_| "Future"))
             .finish()
     }
@@ -543,9 +865,8 @@ impl fmt::Debug for ProcessMetricsStream {
 impl Clone for ProcessMetricsStream {
     fn clone(&self) -> Self {
         Self {
-            pid: self.pid,
-            interval: tokio::time::interval(self.interval.period()),
-            pending_future: None,
+            ends_on_exit: self.ends_on_exit,
+            ..Self::with_parts(self.pid, self.identity, self.period.get())
         }
     }
 }
@@ -556,31 +877,45 @@ impl Stream for ProcessMetricsStream {
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
 
+        if this.finished {
+            return Poll::Ready(None);
+        }
+
         if let Some(fut) = &mut this.pending_future {
-            match fut.as_mut().poll(cx) {
-                Poll::Ready(result) => {
-                    this.pending_future = None;
-                    return Poll::Ready(Some(result));
-                },
-                Poll::Pending => return Poll::Pending,
-            }
+            return match fut.as_mut().poll(cx) {
+                Poll::Ready(result) => this.finish(result),
+                Poll::Pending => Poll::Pending,
+            };
+        }
+
+        let period = this.period.get();
+        if period != this.interval.period() {
+            this.interval = skipping_interval(tokio::time::Instant::now() + period, period);
         }
 
         match this.interval.poll_tick(cx) {
             Poll::Ready(_) => {
+                // A process that didn't exist when the stream was created is pinned once it appears
+                if this.identity.is_none() {
+                    this.identity = ProcessIdentity::of(this.pid).ok();
+                }
+
                 let pid = this.pid;
-                this.pending_future = Some(Box::pin(async move { Process::get_by_pid(pid).await }));
-
-                if let Some(fut) = &mut this.pending_future {
-                    match fut.as_mut().poll(cx) {
-                        Poll::Ready(result) => {
-                            this.pending_future = None;
-                            Poll::Ready(Some(result))
-                        },
-                        Poll::Pending => Poll::Pending,
-                    }
-                } else {
-                    Poll::Ready(None)
+                let identity = this.identity;
+                let mut fut: Pin<Box<dyn Future<Output = crate::Result<Process>> + Send>> =
+                    Box::pin(async move {
+                        match identity {
+                            Some(identity) => Process::get_by_identity(&identity).await,
+                            None => Process::get_by_pid(pid).await,
+                        }
+                    });
+
+                match fut.as_mut().poll(cx) {
+                    Poll::Ready(result) => this.finish(result),
+                    Poll::Pending => {
+                        this.pending_future = Some(fut);
+                        Poll::Pending
+                    },
                 }
             },
             Poll::Pending => Poll::Pending,
@@ -0,0 +1,479 @@
+//! Lightweight thread state sampling of a process.
+//!
+//! A [`Sampler`] periodically reads the thread list of a process and records each thread's run state and CPU time,
+//! producing a "time in state" histogram per thread. This answers what a hot process is doing (spinning, blocked,
+//! waiting) without symbolication; stack unwinding is out of scope.
+//!
+//! # Permissions
+//!
+//! Sampling another process requires its Mach task port, which `task_for_pid` only hands out to root or to binaries
+//! signed with the `com.apple.security.cs.debugger` entitlement (and not at all for processes protected by System
+//! Integrity Protection). Sampling the current process always works.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! use darwin_metrics::process::sampler::Sampler;
+//!
+//! fn main() -> darwin_metrics::Result<()> {
+//!     let mut sampler = Sampler::attach(std::process::id(), Duration::from_millis(10))?;
+//!     let report = sampler.run_for(Duration::from_secs(1));
+//!     if let Some(thread) = report.busiest_thread() {
+//!         println!("Busiest thread {}: {:?} CPU", thread.thread_id, thread.cpu_time());
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+use std::{
+    collections::HashMap,
+    mem, thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{mach_port_deallocate, mach_task_self, KERN_SUCCESS},
+};
+
+/// Run state of a thread (`TH_STATE_*` in `<mach/thread_info.h>`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThreadRunState {
+    /// Running or runnable
+    Running,
+    /// Stopped, e.g. by a debugger
+    Stopped,
+    /// Waiting for an event
+    Waiting,
+    /// Waiting uninterruptibly, typically for I/O
+    Uninterruptible,
+    /// Halted at a clean point
+    Halted,
+    /// A state unknown to this version of the crate
+    Unknown,
+}
+
+impl ThreadRunState {
+    /// Converts a raw `TH_STATE_*` value.
+    pub fn from_raw(value: i32) -> Self {
+        match value {
+            libc::TH_STATE_RUNNING => Self::Running,
+            libc::TH_STATE_STOPPED => Self::Stopped,
+            libc::TH_STATE_WAITING => Self::Waiting,
+            libc::TH_STATE_UNINTERRUPTIBLE => Self::Uninterruptible,
+            libc::TH_STATE_HALTED => Self::Halted,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// A single observation of a thread
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ThreadObservation {
+    pub thread_id: u64,
+    pub run_state: ThreadRunState,
+    pub user_time: Duration,
+    pub system_time: Duration,
+}
+
+/// Sampling statistics of a single thread
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadSampleStats {
+    /// System-wide unique thread ID, as returned by `pthread_threadid_np`
+    pub thread_id: u64,
+    /// Number of samples the thread was seen in
+    pub samples: u64,
+    /// Number of samples the thread was seen in each run state
+    pub state_counts: HashMap<ThreadRunState, u64>,
+    /// User CPU time consumed between the first and the last sample
+    pub user_time: Duration,
+    /// System CPU time consumed between the first and the last sample
+    pub system_time: Duration,
+}
+
+impl ThreadSampleStats {
+    fn new(thread_id: u64) -> Self {
+        Self {
+            thread_id,
+            samples: 0,
+            state_counts: HashMap::new(),
+            user_time: Duration::ZERO,
+            system_time: Duration::ZERO,
+        }
+    }
+
+    /// Total CPU time consumed during sampling.
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+
+    /// Fraction of samples (0.0-1.0) in which the thread was in `state`.
+    pub fn state_fraction(&self, state: ThreadRunState) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        self.state_counts.get(&state).copied().unwrap_or(0) as f64 / self.samples as f64
+    }
+}
+
+/// Result of a sampling run
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleReport {
+    /// Statistics of every thread seen during the run
+    pub per_thread: Vec<ThreadSampleStats>,
+    /// Time between attaching and the last sample
+    pub duration: Duration,
+    /// Number of samples taken
+    pub samples_taken: u64,
+    /// Whether the target exited before the run finished, making the report partial
+    pub target_exited: bool,
+}
+
+impl SampleReport {
+    /// The thread that consumed the most CPU time, with the number of running samples as a tie breaker.
+    pub fn busiest_thread(&self) -> Option<&ThreadSampleStats> {
+        self.per_thread.iter().max_by_key(|stats| {
+            (
+                stats.cpu_time(),
+                stats.state_counts.get(&ThreadRunState::Running).copied().unwrap_or(0),
+            )
+        })
+    }
+}
+
+/// Accumulates observations into per-thread statistics
+#[derive(Debug, Default)]
+pub(crate) struct SampleAccumulator {
+    threads: HashMap<u64, (ThreadSampleStats, ThreadObservation)>,
+    samples_taken: u64,
+}
+
+impl SampleAccumulator {
+    /// Records the observations of one sample.
+    pub(crate) fn record(&mut self, observations: &[ThreadObservation]) {
+        self.samples_taken += 1;
+
+        for observation in observations {
+            let (stats, baseline) = self
+                .threads
+                .entry(observation.thread_id)
+                .or_insert_with(|| (ThreadSampleStats::new(observation.thread_id), *observation));
+
+            stats.samples += 1;
+            *stats.state_counts.entry(observation.run_state).or_insert(0) += 1;
+            // CPU times are cumulative, so the consumption is the difference to the first observation
+            stats.user_time = observation.user_time.saturating_sub(baseline.user_time);
+            stats.system_time = observation.system_time.saturating_sub(baseline.system_time);
+        }
+    }
+
+    pub(crate) fn report(&self, duration: Duration, target_exited: bool) -> SampleReport {
+        let mut per_thread: Vec<_> =
+            self.threads.values().map(|(stats, _)| stats.clone()).collect();
+        per_thread.sort_by_key(|stats| stats.thread_id);
+
+        SampleReport { per_thread, duration, samples_taken: self.samples_taken, target_exited }
+    }
+}
+
+/// Samples the thread states of a process at a fixed interval
+///
+/// The task port acquired by [`attach`](Sampler::attach) is released when the sampler is dropped.
+#[derive(Debug)]
+pub struct Sampler {
+    pid: u32,
+    interval: Duration,
+    task: libc::mach_port_t,
+    started: Instant,
+    last_sample: Option<Instant>,
+    accumulator: SampleAccumulator,
+    target_exited: bool,
+}
+
+impl Sampler {
+    /// Attaches to a process.
+    ///
+    /// # Errors
+    ///
+    /// Returns a permission error if the task port of `pid` can't be acquired, which is the case for other processes
+    /// unless running as root or with the debugger entitlement.
+    pub fn attach(pid: u32, interval: Duration) -> Result<Self> {
+        let mut task: libc::mach_port_t = 0;
+        let result = unsafe { libc::task_for_pid(mach_task_self(), pid as libc::pid_t, &mut task) };
+
+        if result != KERN_SUCCESS {
+            return Err(Error::permission_denied(format!(
+                "task_for_pid({pid}) failed with kern_return_t {result}; sampling other processes requires root \
+                 or the com.apple.security.cs.debugger entitlement"
+            )));
+        }
+
+        Ok(Self {
+            pid,
+            interval,
+            task,
+            started: Instant::now(),
+            last_sample: None,
+            accumulator: SampleAccumulator::default(),
+            target_exited: false,
+        })
+    }
+
+    /// The sampled process ID.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// The interval used by [`run_for`](Sampler::run_for).
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Takes a single sample.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the target is still alive; once it has exited, further samples are no-ops
+    pub fn sample(&mut self) -> bool {
+        if self.target_exited {
+            return false;
+        }
+
+        match self.observe_threads() {
+            Some(observations) => {
+                self.accumulator.record(&observations);
+                self.last_sample = Some(Instant::now());
+                true
+            },
+            None => {
+                self.target_exited = true;
+                false
+            },
+        }
+    }
+
+    /// Samples at the configured interval for `duration`, or until the target exits.
+    ///
+    /// This blocks the calling thread for the duration of the run.
+    pub fn run_for(&mut self, duration: Duration) -> SampleReport {
+        let deadline = Instant::now() + duration;
+
+        while self.sample() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            thread::sleep(self.interval.min(deadline - now));
+        }
+
+        self.report()
+    }
+
+    /// The statistics collected so far.
+    pub fn report(&self) -> SampleReport {
+        let duration = self.last_sample.map_or(Duration::ZERO, |at| at - self.started);
+        self.accumulator.report(duration, self.target_exited)
+    }
+
+    /// Reads the state of every thread of the target, or None if the target has exited.
+    fn observe_threads(&self) -> Option<Vec<ThreadObservation>> {
+        let mut threads: libc::thread_act_array_t = std::ptr::null_mut();
+        let mut count: libc::mach_msg_type_number_t = 0;
+
+        // Fails once the task is gone
+        if unsafe { libc::task_threads(self.task, &mut threads, &mut count) } != KERN_SUCCESS {
+            return None;
+        }
+
+        let mut observations = Vec::with_capacity(count as usize);
+        for i in 0..count as usize {
+            let thread = unsafe { *threads.add(i) };
+            // Threads may exit between listing and inspection
+            if let Some(observation) = observe_thread(thread) {
+                observations.push(observation);
+            }
+            unsafe { mach_port_deallocate(mach_task_self(), thread) };
+        }
+
+        unsafe {
+            libc::vm_deallocate(
+                mach_task_self(),
+                threads as libc::vm_address_t,
+                count as libc::vm_size_t * mem::size_of::<libc::thread_act_t>(),
+            );
+        }
+
+        Some(observations)
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe { mach_port_deallocate(mach_task_self(), self.task) };
+    }
+}
+
+fn observe_thread(thread: libc::thread_act_t) -> Option<ThreadObservation> {
+    let mut basic: libc::thread_basic_info = unsafe { mem::zeroed() };
+    let mut count = libc::THREAD_BASIC_INFO_COUNT;
+    let result = unsafe {
+        libc::thread_info(
+            thread,
+            libc::THREAD_BASIC_INFO as libc::thread_flavor_t,
+            &mut basic as *mut _ as libc::thread_info_t,
+            &mut count,
+        )
+    };
+    if result != KERN_SUCCESS {
+        return None;
+    }
+
+    let mut identifier: libc::thread_identifier_info = unsafe { mem::zeroed() };
+    let mut count = libc::THREAD_IDENTIFIER_INFO_COUNT;
+    let result = unsafe {
+        libc::thread_info(
+            thread,
+            libc::THREAD_IDENTIFIER_INFO as libc::thread_flavor_t,
+            &mut identifier as *mut _ as libc::thread_info_t,
+            &mut count,
+        )
+    };
+    if result != KERN_SUCCESS {
+        return None;
+    }
+
+    Some(ThreadObservation {
+        thread_id: identifier.thread_id,
+        run_state: ThreadRunState::from_raw(basic.run_state),
+        user_time: time_value_to_duration(basic.user_time),
+        system_time: time_value_to_duration(basic.system_time),
+    })
+}
+
+fn time_value_to_duration(value: libc::time_value_t) -> Duration {
+    Duration::from_secs(value.seconds.max(0) as u64)
+        + Duration::from_micros(value.microseconds.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    };
+
+    use super::*;
+
+    fn observation(thread_id: u64, run_state: ThreadRunState, user_ms: u64) -> ThreadObservation {
+        ThreadObservation {
+            thread_id,
+            run_state,
+            user_time: Duration::from_millis(user_ms),
+            system_time: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_run_state_from_raw() {
+        assert_eq!(ThreadRunState::from_raw(libc::TH_STATE_RUNNING), ThreadRunState::Running);
+        assert_eq!(ThreadRunState::from_raw(libc::TH_STATE_WAITING), ThreadRunState::Waiting);
+        assert_eq!(ThreadRunState::from_raw(42), ThreadRunState::Unknown);
+    }
+
+    #[test]
+    fn test_accumulator_builds_state_histogram() {
+        let mut accumulator = SampleAccumulator::default();
+        accumulator.record(&[
+            observation(1, ThreadRunState::Running, 100),
+            observation(2, ThreadRunState::Waiting, 500),
+        ]);
+        accumulator.record(&[
+            observation(1, ThreadRunState::Running, 150),
+            observation(2, ThreadRunState::Waiting, 501),
+        ]);
+        // Thread 2 exits, thread 3 starts
+        accumulator.record(&[
+            observation(1, ThreadRunState::Waiting, 160),
+            observation(3, ThreadRunState::Running, 0),
+        ]);
+
+        let report = accumulator.report(Duration::from_millis(30), false);
+        assert_eq!(report.samples_taken, 3);
+        assert_eq!(report.per_thread.len(), 3);
+
+        let thread1 = &report.per_thread[0];
+        assert_eq!(thread1.samples, 3);
+        assert_eq!(thread1.user_time, Duration::from_millis(60));
+        assert!((thread1.state_fraction(ThreadRunState::Running) - 2.0 / 3.0).abs() < 1e-9);
+
+        // CPU time before the first sample doesn't count
+        assert_eq!(report.per_thread[1].user_time, Duration::from_millis(1));
+        assert_eq!(report.busiest_thread().map(|t| t.thread_id), Some(1));
+    }
+
+    #[test]
+    fn test_attach_to_other_process_fails_cleanly() {
+        // launchd can't be sampled without root, and PID 0 is the kernel
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+
+        let err = Sampler::attach(1, Duration::from_millis(10)).unwrap_err();
+        assert!(matches!(err, Error::PermissionDenied(_)), "{err:?}");
+        assert!(err.to_string().contains("entitlement"));
+    }
+
+    #[test]
+    fn test_busy_thread_is_busiest() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+
+        let busy = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let mut id = 0u64;
+                unsafe { libc::pthread_threadid_np(0, &mut id) };
+                tx.send(id).unwrap();
+
+                let mut counter = 0u64;
+                while !stop.load(Ordering::Relaxed) {
+                    counter = std::hint::black_box(counter.wrapping_add(1));
+                }
+            })
+        };
+        let busy_id = rx.recv().unwrap();
+
+        let mut sampler = Sampler::attach(std::process::id(), Duration::from_millis(10)).unwrap();
+        let report = sampler.run_for(Duration::from_millis(300));
+
+        stop.store(true, Ordering::Relaxed);
+        busy.join().unwrap();
+
+        assert!(report.samples_taken > 1);
+        assert!(!report.target_exited);
+        let busiest = report.busiest_thread().expect("threads should be sampled");
+        assert_eq!(busiest.thread_id, busy_id);
+        assert!(busiest.state_fraction(ThreadRunState::Running) > 0.5);
+    }
+
+    #[test]
+    fn test_partial_report_when_target_exits() {
+        let mut child = std::process::Command::new("sleep").arg("10").spawn().unwrap();
+
+        // Sampling a child requires the same privileges as any other process
+        let Ok(mut sampler) = Sampler::attach(child.id(), Duration::from_millis(5)) else {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return;
+        };
+
+        assert!(sampler.sample());
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        let report = sampler.run_for(Duration::from_secs(5));
+        assert!(report.target_exited);
+        assert!(report.samples_taken >= 1);
+        assert!(!sampler.sample());
+    }
+}
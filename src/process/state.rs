@@ -0,0 +1,65 @@
+//! Scheduling state of a process, and stopping and continuing processes with the `control` feature
+//!
+//! The state is the `p_stat` of the process, reported as `pbi_status` in its BSD info. macOS only distinguishes the
+//! states of a whole process here; a process whose threads are all waiting is usually still [`ProcessState::Running`].
+
+#[cfg(feature = "control")]
+use crate::error::{Error, Result};
+use crate::utils::bindings::proc_state::{SIDL, SRUN, SSLEEP, SSTOP, SZOMB};
+
+/// Scheduling state of a process (`p_stat` in `<sys/proc.h>`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProcessState {
+    /// Being created by `fork`
+    Idle,
+    /// Runnable
+    Running,
+    /// Sleeping on an address
+    Sleeping,
+    /// Stopped by a signal such as `SIGSTOP`, or by a debugger
+    Stopped,
+    /// Exited and waiting for its parent to collect its status
+    Zombie,
+}
+
+impl ProcessState {
+    /// Converts a raw `p_stat` or `pbi_status` value, returning `None` for unknown states.
+    pub fn from_raw(status: u32) -> Option<Self> {
+        Some(match u8::try_from(status).ok()? {
+            SIDL => Self::Idle,
+            SRUN => Self::Running,
+            SSLEEP => Self::Sleeping,
+            SSTOP => Self::Stopped,
+            SZOMB => Self::Zombie,
+            _ => return None,
+        })
+    }
+}
+
+/// Sends `signal` to the process `pid`, and only to that process.
+#[cfg(feature = "control")]
+pub(crate) fn send_signal(pid: u32, signal: libc::c_int) -> Result<()> {
+    // `kill` treats 0 and negative PIDs as process groups
+    if pid == 0 || pid > i32::MAX as u32 {
+        return Err(Error::process_error(format!("Invalid PID {pid}")));
+    }
+
+    // SAFETY: `kill` has no memory safety requirements
+    if unsafe { libc::kill(pid as i32, signal) } == 0 {
+        return Ok(());
+    }
+    Err(signal_error(pid, std::io::Error::last_os_error()))
+}
+
+/// Converts the error of `kill` for the process `pid`.
+#[cfg(feature = "control")]
+pub(crate) fn signal_error(pid: u32, error: std::io::Error) -> Error {
+    match error.raw_os_error() {
+        Some(libc::EPERM) => {
+            Error::permission_denied(format!("Not allowed to signal process {pid}"))
+        },
+        Some(libc::ESRCH) => Error::process_error(format!("No process with PID {pid}")),
+        _ => Error::from(error),
+    }
+}
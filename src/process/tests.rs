@@ -2,6 +2,8 @@ use std::process::Command;
 use std::time::Duration;
 use std::time::Instant;
 
+use futures::StreamExt;
+
 use super::*;
 
 #[tokio::test]
@@ -14,8 +16,12 @@ async fn test_get_current_process() {
     assert_eq!(process.pid, current_pid);
     assert!(!process.name.is_empty(), "Process name should not be empty");
     assert!(process.memory_usage > 0, "Process should have non-zero memory usage");
-    // Suspended check is always false due to API limitations assert!(!process.is_suspended, "Current process should not
-    // be suspended");
+    assert!(
+        process.footprint.is_some_and(|footprint| footprint > 0),
+        "Own footprint should be readable"
+    );
+    assert!(!process.is_suspended, "Current process should not be suspended");
+    assert_eq!(process.state, Some(ProcessState::Running));
     assert!(process.thread_count > 0, "Process should have at least one thread");
 }
 
@@ -48,6 +54,52 @@ async fn test_get_all_processes() {
     }
 }
 
+#[test]
+fn test_get_all_basic() {
+    let processes = Process::get_all_basic().unwrap();
+    let current = processes
+        .iter()
+        .find(|process| process.pid == std::process::id())
+        .expect("The current process should be listed");
+
+    assert!(!current.name.is_empty());
+    // No details are read
+    assert_eq!(current.memory_usage, 0);
+    assert_eq!(current.thread_count, 0);
+    assert!(processes.iter().all(|process| process.pid > 0));
+}
+
+#[tokio::test]
+async fn test_get_all_reads_details_in_bulk() {
+    let processes = Process::get_all_via_sysctl().await.unwrap();
+    let current = processes
+        .iter()
+        .find(|process| process.pid == std::process::id())
+        .expect("The current process should be listed");
+
+    assert!(current.memory_usage > 0);
+    assert!(current.thread_count > 0);
+    assert!(current.open_fd_count.is_some());
+}
+
+#[test]
+fn test_cpu_usages_of_batch() {
+    // PIDs that aren't in use, so other tests don't touch their entries
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let first = ProcessIdentity::new(u32::MAX - 2, start, Some(3));
+    let second = ProcessIdentity::new(u32::MAX - 3, start, Some(4));
+
+    assert_eq!(Process::calculate_cpu_usages(&[(first, 1_000), (second, 1_000)]), [0.0, 0.0]);
+    std::thread::sleep(Duration::from_millis(150));
+
+    let usages = Process::calculate_cpu_usages(&[(first, 1_000), (second, 50_000)]);
+    assert_eq!(usages[0], 0.0);
+    assert!(usages[1] > 0.0);
+
+    get_cpu_history().remove(&(u32::MAX - 2));
+    get_cpu_history().remove(&(u32::MAX - 3));
+}
+
 #[tokio::test]
 async fn test_parent_child_relationship() {
     // Create a child process using the command line
@@ -81,7 +133,7 @@ async fn test_parent_child_relationship() {
 async fn test_process_tree() {
     // Try to get process tree, if this fails due to permissions, just make the test pass This is common when running in
     // CI or restricted environments
-    let tree = match Process::get_process_tree().await {
+    let tree = match Process::get_process_tree(ProcessTree::SYNTHETIC_ROOT).await {
         Ok(t) => t,
         Err(e) => {
             println!("Note: get_process_tree() failed but we're allowing this test to pass: {e}");
@@ -96,11 +148,12 @@ async fn test_process_tree() {
     }
 
     // The first process should be at depth 0 (root process, usually launchd on macOS)
-    assert_eq!(tree[0].1, 0, "First process should be at depth 0");
+    assert_eq!(tree.iter().next().unwrap().1, 0, "First process should be at depth 0");
+    assert_eq!(tree.iter().count(), tree.len(), "Every process should appear once");
 
     // Check if our process is in the tree, but don't fail if it's not
     let current_pid = std::process::id();
-    if !tree.iter().any(|(p, _)| p.pid == current_pid) {
+    if !tree.contains(current_pid) {
         println!("Note: Current process not found in process tree, this may be due to permissions");
     }
 
@@ -114,6 +167,24 @@ async fn test_process_tree() {
     drop(tree);
 }
 
+#[tokio::test]
+async fn test_process_tree_of_child() {
+    let mut child = Command::new("sleep").arg("5").spawn().expect("Failed to spawn child process");
+    let current_pid = std::process::id();
+
+    let tree = Process::get_process_tree(current_pid).await.unwrap();
+    assert_eq!(tree.root(), current_pid);
+    assert_eq!(tree.depth(current_pid), Some(0));
+    assert_eq!(tree.depth(child.id()), Some(1));
+    assert_eq!(tree.parent(child.id()), Some(current_pid));
+    assert!(tree.children(current_pid).iter().any(|process| process.pid == child.id()));
+    assert!(!tree.contains(1), "launchd is outside the subtree");
+
+    let _ = child.kill();
+    let _ = child.wait();
+    assert!(Process::get_process_tree(u32::MAX / 2).await.is_err());
+}
+
 // New tests to improve coverage
 
 #[test]
@@ -126,11 +197,49 @@ fn test_process_new() {
     assert_eq!(process.name, name);
     assert_eq!(process.cpu_usage, 0.0);
     assert_eq!(process.memory_usage, 0);
+    assert_eq!(process.footprint, None);
     assert_eq!(process.uptime, Duration::default());
     assert_eq!(process.io_stats.read_bytes, 0);
     assert_eq!(process.io_stats.write_bytes, 0);
     assert_eq!(process.thread_count, 0);
     assert!(!process.is_suspended);
+    assert_eq!(process.state, None);
+}
+
+#[test]
+fn test_process_state_from_raw() {
+    assert_eq!(ProcessState::from_raw(1), Some(ProcessState::Idle));
+    assert_eq!(ProcessState::from_raw(2), Some(ProcessState::Running));
+    assert_eq!(ProcessState::from_raw(3), Some(ProcessState::Sleeping));
+    assert_eq!(ProcessState::from_raw(4), Some(ProcessState::Stopped));
+    assert_eq!(ProcessState::from_raw(5), Some(ProcessState::Zombie));
+    assert_eq!(ProcessState::from_raw(0), None);
+    assert_eq!(ProcessState::from_raw(0x104), None, "Out of range values don't wrap");
+}
+
+#[test]
+fn test_memory_info_of_current_process() {
+    let info = Process::memory_info(std::process::id()).unwrap();
+
+    assert!(info.resident > 0);
+    let footprint = info.footprint.expect("Own footprint should be readable");
+    assert!(footprint > 0);
+    assert!(info.peak_footprint.is_some_and(|peak| peak >= footprint));
+    assert!(info.compressed.is_some(), "Own task port is always available");
+}
+
+#[test]
+fn test_memory_info_of_other_process() {
+    // launchd belongs to root, so without root only the resident size may be readable
+    match Process::memory_info(1) {
+        Ok(info) => {
+            if info.footprint.is_none() {
+                assert_eq!(info.peak_footprint, None);
+            }
+        },
+        Err(err) => println!("launchd not readable: {err}"),
+    }
+    assert!(Process::memory_info(u32::MAX / 2).is_err(), "No such process");
 }
 
 #[test]
@@ -336,16 +445,17 @@ fn test_cpu_history() {
     // Insert a test entry
     {
         let mut history = get_cpu_history();
-        history.insert(12345, (Instant::now(), 1000));
+        let identity = ProcessIdentity::new(12345, SystemTime::UNIX_EPOCH, None);
+        history.record(identity, Instant::now(), 1000);
     }
 
     // Verify the entry was inserted
     {
         let history = get_cpu_history();
-        assert!(history.contains_key(&12345), "History should contain our test entry");
+        assert!(history.get(&12345).is_some(), "History should contain our test entry");
 
-        if let Some((_, cpu_time)) = history.get(&12345) {
-            assert_eq!(*cpu_time, 1000, "CPU time should match what we inserted");
+        if let Some(entry) = history.get(&12345) {
+            assert_eq!(entry.cpu_time, 1000, "CPU time should match what we inserted");
         } else {
             panic!("Test entry not found in history");
         }
@@ -357,3 +467,1039 @@ fn test_cpu_history() {
         history.clear();
     }
 }
+
+#[tokio::test]
+async fn test_by_session_returns_own_processes() {
+    let uid = unsafe { libc::getuid() };
+    let processes = Process::by_session(uid).await.unwrap();
+
+    let current_pid = std::process::id();
+    assert!(
+        processes.iter().any(|p| p.pid == current_pid),
+        "Processes of the current user should include this process"
+    );
+}
+
+/// Identity of the current process with its start time shifted, as if a new process had reused the PID
+fn forged_identity() -> ProcessIdentity {
+    let real = ProcessIdentity::of(std::process::id()).unwrap();
+    ProcessIdentity::new(real.pid(), real.start_time() - Duration::from_secs(1), real.unique_id())
+}
+
+#[test]
+fn test_identity_of_current_process() {
+    let pid = std::process::id();
+    let identity = ProcessIdentity::of(pid).unwrap();
+
+    assert_eq!(identity.pid(), pid);
+    assert!(identity.unique_id().is_some(), "The unique ID of our own process should be readable");
+    assert!(identity.start_time() <= SystemTime::now());
+    assert!(identity.verify().is_ok());
+    assert!(identity.is_same_process(&ProcessIdentity::of(pid).unwrap()));
+}
+
+#[test]
+fn test_identity_comparison() {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let identity = ProcessIdentity::new(100, start, Some(7));
+
+    assert!(identity.is_same_process(&ProcessIdentity::new(100, start, Some(7))));
+    // A missing unique ID falls back to the start time
+    assert!(identity.is_same_process(&ProcessIdentity::new(100, start, None)));
+    assert!(!identity.is_same_process(&ProcessIdentity::new(100, start, Some(8))));
+    assert!(!identity.is_same_process(&ProcessIdentity::new(
+        100,
+        start + Duration::from_micros(1),
+        Some(7)
+    )));
+    assert!(!identity.is_same_process(&ProcessIdentity::new(101, start, Some(7))));
+}
+
+#[tokio::test]
+async fn test_forged_identity_is_detected() {
+    let forged = forged_identity();
+
+    let err = forged.verify().unwrap_err();
+    assert!(err.is_pid_reused(), "Expected a PID reuse error, got {:?}", err);
+
+    let err = Process::get_by_identity(&forged).await.unwrap_err();
+    assert!(err.is_pid_reused(), "Expected a PID reuse error, got {:?}", err);
+
+    let real = ProcessIdentity::of(std::process::id()).unwrap();
+    let process = Process::get_by_identity(&real).await.unwrap();
+    assert_eq!(process.pid, std::process::id());
+}
+
+#[tokio::test]
+async fn test_stream_reports_pid_reuse() {
+    let mut stream =
+        ProcessMetricsStream::with_identity(forged_identity(), Duration::from_millis(10));
+
+    let result = stream.next().await.expect("The stream should yield an item");
+    assert!(result.unwrap_err().is_pid_reused());
+
+    let mut stream = ProcessMetricsStream::new(std::process::id(), Duration::from_millis(10));
+    assert!(stream.identity().is_some());
+    assert!(stream.next().await.expect("The stream should yield an item").is_ok());
+}
+
+#[tokio::test]
+async fn test_stream_ends_when_process_exits() {
+    let mut child = Command::new("sleep").arg("30").spawn().expect("Failed to spawn child process");
+    let mut stream = ProcessMetricsStream::new(child.id(), Duration::from_millis(20)).into_stream();
+
+    let first = stream.next().await.expect("The stream should yield an item");
+    assert_eq!(first.unwrap().pid, child.id());
+
+    child.kill().expect("Failed to kill child process");
+    child.wait().expect("Failed to reap child process");
+
+    let remaining: Vec<_> =
+        tokio::time::timeout(Duration::from_secs(5), stream.collect::<Vec<_>>())
+            .await
+            .expect("The stream should end once the process exited");
+    let last = remaining.last().expect("The stream should yield the exit as an error");
+    assert!(last.is_err());
+}
+
+#[tokio::test]
+async fn test_stream_interval_change() {
+    let mut stream = ProcessMetricsStream::new(std::process::id(), Duration::from_secs(3600));
+    let handle = stream.interval_handle();
+    assert!(stream.next().await.unwrap().is_ok());
+
+    handle.set(Duration::from_millis(10));
+    assert_eq!(handle.get(), Duration::from_millis(10));
+    let next = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+    assert!(next.expect("The new interval should apply to the next tick").unwrap().is_ok());
+
+    stream.set_interval(Duration::from_secs(3600));
+    assert_eq!(handle.get(), Duration::from_secs(3600));
+    assert!(tokio::time::timeout(Duration::from_millis(200), stream.next()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_stream_skips_missed_ticks() {
+    let mut stream = ProcessMetricsStream::new(std::process::id(), Duration::from_millis(20));
+    assert!(stream.next().await.unwrap().is_ok());
+
+    // A slow consumer misses ten ticks, which must not be delivered as a burst afterwards
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let start = std::time::Instant::now();
+    for _ in 0..3 {
+        assert!(stream.next().await.unwrap().is_ok());
+    }
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
+
+#[test]
+fn test_cpu_history_resets_on_pid_reuse() {
+    // A PID that isn't in use, so other tests don't touch its entry
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let original = ProcessIdentity::new(u32::MAX - 1, start, Some(1));
+    let reused = ProcessIdentity::new(u32::MAX - 1, start + Duration::from_secs(60), Some(2));
+
+    Process::calculate_cpu_usage(&original, 1_000);
+    std::thread::sleep(Duration::from_millis(150));
+
+    // The new process has its own CPU time, which must not be diffed against the old one
+    assert_eq!(Process::calculate_cpu_usage(&reused, 50_000_000_000), 0.0);
+
+    get_cpu_history().remove(&(u32::MAX - 1));
+}
+
+mod anomaly {
+    use super::*;
+    use crate::process::anomaly::{evaluate, ProcessSample};
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+    const HOST_MEMORY: u64 = 16 * GIB;
+
+    fn sample(pid: u32, age_secs: u64, cpu_secs: u64, resident_bytes: u64) -> ProcessSample {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        ProcessSample {
+            identity: ProcessIdentity::new(pid, start, Some(pid as u64)),
+            name: format!("proc{pid}"),
+            age: Duration::from_secs(age_secs),
+            cpu_time: Duration::from_secs(cpu_secs),
+            resident_bytes,
+            threads: Some(8),
+            fds: Some(32),
+        }
+    }
+
+    #[test]
+    fn test_no_anomalies() {
+        // A long-running busy service and an idle young process
+        let samples = [sample(100, 86_400, 43_200, GIB), sample(101, 30, 1, 50 * 1024 * 1024)];
+
+        let (anomalies, baseline) =
+            evaluate(&samples, HOST_MEMORY, &AnomalyOptions::default(), None, Instant::now());
+
+        assert!(anomalies.is_empty(), "Unexpected anomalies: {anomalies:?}");
+        assert_eq!(baseline.len(), 2);
+    }
+
+    #[test]
+    fn test_young_and_hot() {
+        // Two minutes old with 100 seconds of CPU time, versus a day-old process at the same lifetime average
+        let samples = [sample(200, 120, 100, GIB), sample(201, 86_400, 72_000, GIB)];
+
+        let (anomalies, _) =
+            evaluate(&samples, HOST_MEMORY, &AnomalyOptions::default(), None, Instant::now());
+
+        assert_eq!(anomalies.len(), 1);
+        let anomaly = &anomalies[0];
+        assert_eq!(anomaly.identity.pid(), 200);
+        assert!((anomaly.lifetime_cpu_percent - 83.33).abs() < 0.01);
+        assert_eq!(anomaly.interval_cpu_percent, None);
+        assert_eq!(
+            anomaly.kinds,
+            vec![AnomalyKind::YoungAndHot {
+                age: Duration::from_secs(120),
+                cpu_percent: anomaly.lifetime_cpu_percent
+            }]
+        );
+
+        // (0.5 * 83.33 + 0.25 * 83.33 + 6.25) * (1 + 600 / 720)
+        assert!((anomaly.score - 126.04).abs() < 0.01, "Unexpected score {}", anomaly.score);
+    }
+
+    #[test]
+    fn test_interval_cpu_uses_baseline() {
+        let start = Instant::now();
+        let (_, baseline) = evaluate(
+            &[sample(300, 60, 1, GIB)],
+            HOST_MEMORY,
+            &AnomalyOptions::default(),
+            None,
+            start,
+        );
+
+        // Idle for its first minute, then a full core for ten seconds
+        let (anomalies, _) = evaluate(
+            &[sample(300, 70, 11, GIB)],
+            HOST_MEMORY,
+            &AnomalyOptions::default(),
+            Some(&baseline),
+            start + Duration::from_secs(10),
+        );
+
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].interval_cpu_percent, Some(100.0));
+        assert!(matches!(anomalies[0].kinds[..], [AnomalyKind::YoungAndHot { .. }]));
+    }
+
+    #[test]
+    fn test_memory_growth() {
+        let start = Instant::now();
+        let options = AnomalyOptions::default();
+        let previous = [sample(400, 86_400, 0, GIB), sample(401, 86_400, 0, 4 * GIB)];
+        let (anomalies, baseline) = evaluate(&previous, HOST_MEMORY, &options, None, start);
+        assert!(anomalies.is_empty(), "Growth needs a baseline");
+
+        // 400 doubles, 401 grows by 512 MiB, which is only 12.5% of its size
+        let current = [sample(400, 86_401, 0, 2 * GIB), sample(401, 86_401, 0, 4 * GIB + GIB / 2)];
+        let (anomalies, _) = evaluate(
+            &current,
+            HOST_MEMORY,
+            &options,
+            Some(&baseline),
+            start + Duration::from_secs(1),
+        );
+
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].identity.pid(), 400);
+        assert_eq!(
+            anomalies[0].kinds,
+            vec![AnomalyKind::MemoryGrowth { previous_bytes: GIB, current_bytes: 2 * GIB }]
+        );
+    }
+
+    #[test]
+    fn test_memory_growth_ignores_reused_pid() {
+        let start = Instant::now();
+        let options = AnomalyOptions::default();
+        let (_, baseline) =
+            evaluate(&[sample(500, 86_400, 0, GIB)], HOST_MEMORY, &options, None, start);
+
+        // A new process with the same PID is not compared against the old one
+        let mut reused = sample(500, 5, 0, 8 * GIB);
+        reused.identity = ProcessIdentity::new(500, SystemTime::now(), Some(9_999));
+        let (anomalies, _) = evaluate(
+            &[reused],
+            HOST_MEMORY,
+            &options,
+            Some(&baseline),
+            start + Duration::from_secs(5),
+        );
+
+        assert!(anomalies.is_empty(), "Unexpected anomalies: {anomalies:?}");
+    }
+
+    #[test]
+    fn test_thread_and_fd_explosions() {
+        let mut threads = sample(600, 86_400, 0, GIB);
+        threads.threads = Some(5_000);
+        let mut fds = sample(601, 86_400, 0, GIB);
+        fds.fds = Some(10_000);
+        let mut unknown = sample(602, 86_400, 0, GIB);
+        unknown.threads = None;
+        unknown.fds = None;
+
+        let (anomalies, _) = evaluate(
+            &[threads, fds, unknown],
+            HOST_MEMORY,
+            &AnomalyOptions::default(),
+            None,
+            Instant::now(),
+        );
+
+        let kinds: Vec<_> = anomalies.iter().map(|anomaly| anomaly.kinds.clone()).collect();
+        assert_eq!(kinds.len(), 2);
+        assert!(kinds.contains(&vec![AnomalyKind::ThreadExplosion { threads: 5_000 }]));
+        assert!(kinds.contains(&vec![AnomalyKind::FdExplosion { fds: 10_000 }]));
+
+        // Raised limits flag nothing
+        let options = AnomalyOptions { max_threads: 10_000, max_fds: 20_000, ..Default::default() };
+        let mut threads = sample(600, 86_400, 0, GIB);
+        threads.threads = Some(5_000);
+        let (anomalies, _) = evaluate(&[threads], HOST_MEMORY, &options, None, Instant::now());
+        assert!(anomalies.is_empty());
+    }
+
+    #[test]
+    fn test_ranking() {
+        // Same CPU and memory, but the younger process ranks higher
+        let mut young = sample(700, 60, 30, GIB);
+        young.threads = Some(2_000);
+        let mut old = sample(701, 86_400, 43_200, GIB);
+        old.threads = Some(2_000);
+
+        let (anomalies, _) =
+            evaluate(&[old, young], HOST_MEMORY, &AnomalyOptions::default(), None, Instant::now());
+
+        let pids: Vec<u32> = anomalies.iter().map(|anomaly| anomaly.identity.pid()).collect();
+        assert_eq!(pids, vec![700, 701]);
+    }
+
+    #[test]
+    fn test_anomalies_live() {
+        // Only checks that a real pass works; which processes are flagged depends on the machine
+        let (_, baseline) = anomalies_with_baseline(AnomalyOptions::default(), None).unwrap();
+        assert!(!baseline.is_empty());
+
+        let (anomalies, _) =
+            anomalies_with_baseline(AnomalyOptions::default(), Some(&baseline)).unwrap();
+        assert!(anomalies.windows(2).all(|pair| pair[0].score >= pair[1].score));
+    }
+}
+
+mod app_nap {
+    use super::*;
+    use crate::utils::bindings::{
+        PROC_FLAG_APPLICATION, PROC_FLAG_DARWINBG, PROC_FLAG_EXT_DARWINBG,
+    };
+
+    // Unrelated flags that must not affect the state (PROC_FLAG_LP64 and PROC_FLAG_EXEC)
+    const OTHER_FLAGS: u32 = 0x10 | 0x4000;
+
+    #[test]
+    fn test_state_from_flags() {
+        assert_eq!(AppNapState::from_flags(0), AppNapState::Active);
+        assert_eq!(
+            AppNapState::from_flags(PROC_FLAG_APPLICATION | OTHER_FLAGS),
+            AppNapState::Active
+        );
+        assert_eq!(
+            AppNapState::from_flags(PROC_FLAG_APPLICATION | PROC_FLAG_EXT_DARWINBG | OTHER_FLAGS),
+            AppNapState::Napping
+        );
+        // An application backgrounding itself isn't napping
+        assert_eq!(
+            AppNapState::from_flags(PROC_FLAG_APPLICATION | PROC_FLAG_DARWINBG),
+            AppNapState::Suppressed
+        );
+        assert_eq!(AppNapState::from_flags(PROC_FLAG_EXT_DARWINBG), AppNapState::Suppressed);
+        assert_eq!(AppNapState::from_flags(PROC_FLAG_DARWINBG), AppNapState::Suppressed);
+
+        assert!(!AppNapState::Active.is_throttled());
+        assert!(AppNapState::Napping.is_throttled());
+        assert!(AppNapState::Suppressed.is_throttled());
+        assert!(!AppNapState::Unknown.is_throttled());
+    }
+
+    fn fixture(pid: u32, cpu_usage: f64, app_nap_state: Option<AppNapState>) -> Process {
+        let mut process = Process::new(pid, format!("proc{pid}"));
+        process.cpu_usage = cpu_usage;
+        process.app_nap_state = app_nap_state;
+        process
+    }
+
+    fn fixtures() -> Vec<Process> {
+        vec![
+            fixture(1, 5.0, Some(AppNapState::Active)),
+            fixture(2, 40.0, Some(AppNapState::Napping)),
+            fixture(3, 20.0, Some(AppNapState::Suppressed)),
+            fixture(4, 30.0, None),
+            fixture(5, 10.0, Some(AppNapState::Unknown)),
+        ]
+    }
+
+    fn pids(processes: &[Process]) -> Vec<u32> {
+        processes.iter().map(|process| process.pid).collect()
+    }
+
+    #[test]
+    fn test_rank_by_cpu_excludes_napping() {
+        let all = Process::rank_by_cpu(fixtures(), 3, &ProcessFilter::default());
+        assert_eq!(pids(&all), vec![2, 4, 3]);
+
+        let filter = ProcessFilter { exclude_napping: true };
+        let awake = Process::rank_by_cpu(fixtures(), 3, &filter);
+        assert_eq!(pids(&awake), vec![4, 3, 5]);
+
+        let everything = Process::rank_by_cpu(fixtures(), 10, &filter);
+        assert_eq!(pids(&everything), vec![4, 3, 5, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_current_process_state() {
+        let state = Process::app_nap_state(std::process::id()).unwrap();
+        assert_ne!(state, AppNapState::Unknown, "Own flags should be readable");
+
+        let process = Process::get_by_pid(std::process::id()).await.unwrap();
+        assert!(process.app_nap_state.is_some());
+        assert!(Process::new(1, "launchd").app_nap_state.is_none());
+
+        // PIDs on macOS stay below 99999
+        assert!(Process::app_nap_state(99_999).is_err(), "A missing process should fail");
+    }
+}
+
+mod cpu_history {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::process::cpu_history::CpuHistoryTracker;
+
+    fn identity(pid: u32) -> ProcessIdentity {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        ProcessIdentity::new(pid, start, Some(pid as u64))
+    }
+
+    fn config(capacity: usize, max_age_secs: u64, checks_per_update: usize) -> CpuHistoryConfig {
+        CpuHistoryConfig { capacity, max_age: Duration::from_secs(max_age_secs), checks_per_update }
+    }
+
+    /// A tracker whose existence checks report every process as alive and are counted
+    fn counting_tracker(config: CpuHistoryConfig) -> (CpuHistoryTracker, Arc<AtomicUsize>) {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&checks);
+        let tracker = CpuHistoryTracker::with_checker(
+            config,
+            Box::new(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                true
+            }),
+        );
+        (tracker, checks)
+    }
+
+    #[test]
+    fn test_previous_reading() {
+        let start = Instant::now();
+        let (mut tracker, _) = counting_tracker(config(10, 60, 0));
+
+        assert!(tracker.update(identity(1), start, 100).is_none());
+        let previous = tracker.update(identity(1), start + Duration::from_secs(1), 200).unwrap();
+        assert_eq!(previous.cpu_time, 100);
+
+        // A reading older than the maximum age is no baseline
+        assert!(tracker.update(identity(1), start + Duration::from_secs(120), 300).is_none());
+
+        // Neither is one of an earlier process with the same PID
+        let reused = ProcessIdentity::new(1, SystemTime::now(), Some(42));
+        assert!(tracker.update(reused, start + Duration::from_secs(121), 10).is_none());
+    }
+
+    #[test]
+    fn test_eviction_order() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let (mut tracker, _) = counting_tracker(config(3, 60, 0));
+
+        tracker.update(identity(1), at(0), 0);
+        tracker.update(identity(2), at(1), 0);
+        tracker.update(identity(3), at(2), 0);
+        tracker.update(identity(1), at(3), 0);
+
+        // Over capacity without aged entries, the least recently read process goes
+        tracker.update(identity(4), at(4), 0);
+        assert!(tracker.remove(&2).is_none(), "PID 2 should have been evicted");
+        assert_eq!(tracker.stats(), CpuHistoryStats { tracked: 3, evicted_total: 1 });
+
+        // Aged entries all go first, even when dropping one would be enough
+        tracker.update(identity(5), at(100), 0);
+        tracker.update(identity(6), at(101), 0);
+        assert!(tracker.remove(&5).is_some());
+        assert!(tracker.remove(&6).is_some());
+        assert_eq!(tracker.stats(), CpuHistoryStats { tracked: 0, evicted_total: 4 });
+    }
+
+    #[test]
+    fn test_exit_checks_are_amortized() {
+        let start = Instant::now();
+        let checks = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&checks);
+        // Even PIDs have exited
+        let mut tracker = CpuHistoryTracker::with_checker(
+            config(1000, 600, 4),
+            Box::new(move |pid| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                pid % 2 == 1
+            }),
+        );
+
+        for pid in 1..=100 {
+            let before = checks.load(Ordering::SeqCst);
+            tracker.update(identity(pid), start, 0);
+            assert!(checks.load(Ordering::SeqCst) - before <= 4, "Too many checks in one update");
+        }
+        assert!(checks.load(Ordering::SeqCst) <= 400);
+
+        // Keep reading one process until every other one has been checked
+        for secs in 1..=100 {
+            tracker.update(identity(1), start + Duration::from_secs(secs), 0);
+        }
+        assert_eq!(tracker.stats(), CpuHistoryStats { tracked: 50, evicted_total: 50 });
+    }
+
+    #[test]
+    fn test_aged_entries_are_dropped_without_checks() {
+        let start = Instant::now();
+        let (mut tracker, checks) = counting_tracker(config(1000, 60, 16));
+
+        for pid in 1..=10 {
+            tracker.update(identity(pid), start, 0);
+        }
+        // Every update drained its sweep, so the next one starts by aging
+        let before = checks.load(Ordering::SeqCst);
+        tracker.update(identity(1), start + Duration::from_secs(120), 0);
+
+        assert_eq!(tracker.stats().tracked, 1);
+        assert_eq!(checks.load(Ordering::SeqCst), before, "Aged entries shouldn't be checked");
+    }
+
+    #[test]
+    fn test_burst_stays_bounded() {
+        let start = Instant::now();
+        let (mut tracker, _) = counting_tracker(config(100, 600, 16));
+
+        for pid in 0..10_000 {
+            tracker.update(identity(pid), start + Duration::from_millis(pid as u64), 0);
+            assert!(tracker.stats().tracked <= 100);
+        }
+
+        assert_eq!(tracker.stats(), CpuHistoryStats { tracked: 100, evicted_total: 9_900 });
+
+        // Lowering the capacity applies right away
+        tracker.set_config(config(10, 600, 16), start + Duration::from_secs(10));
+        assert_eq!(tracker.stats().tracked, 10);
+    }
+
+    #[test]
+    fn test_retain_live() {
+        let start = Instant::now();
+        let (mut tracker, checks) = counting_tracker(config(1000, 60, 0));
+
+        for pid in 1..=10 {
+            tracker.update(identity(pid), start, 0);
+        }
+        tracker.update(identity(11), start + Duration::from_secs(90), 0);
+        tracker.update(identity(12), start + Duration::from_secs(90), 0);
+
+        // PIDs 1 to 10 have aged out whether they run or not, and 12 has exited
+        let live: HashSet<u32> = [1, 2, 11].into_iter().collect();
+        tracker.retain_live(&live, start + Duration::from_secs(100));
+
+        assert_eq!(tracker.stats(), CpuHistoryStats { tracked: 1, evicted_total: 11 });
+        assert!(tracker.get(&11).is_some());
+        assert_eq!(checks.load(Ordering::SeqCst), 0, "The listing replaces the probes");
+
+        // Readings recorded against a listing don't probe other processes either
+        let (mut tracker, checks) = counting_tracker(config(1000, 60, 16));
+        for pid in 1..=10 {
+            tracker.record(identity(pid), start, 0);
+        }
+        assert_eq!(checks.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_listing_larger_than_capacity_keeps_every_baseline() {
+        let start = Instant::now();
+        let (mut tracker, _) = counting_tracker(config(10, 60, 16));
+        let live: HashSet<u32> = (1..=50).collect();
+
+        for secs in 0..3 {
+            let now = start + Duration::from_secs(secs);
+            tracker.retain_live(&live, now);
+            let baselines =
+                live.iter().filter(|&&pid| tracker.record(identity(pid), now, 0).is_some()).count();
+            assert_eq!(baselines, if secs == 0 { 0 } else { 50 }, "Reading {secs}");
+        }
+        assert_eq!(tracker.stats(), CpuHistoryStats { tracked: 50, evicted_total: 0 });
+
+        // A smaller listing lowers the capacity again
+        let live: HashSet<u32> = (1..=5).collect();
+        tracker.retain_live(&live, start + Duration::from_secs(3));
+        for pid in 100..120 {
+            tracker.update(identity(pid), start + Duration::from_secs(4), 0);
+        }
+        assert_eq!(tracker.stats().tracked, 10);
+    }
+
+    #[test]
+    fn test_concurrent_readings_during_cleanup() {
+        // PIDs that aren't in use, so other tests don't touch their entries
+        const FIRST_PID: u32 = u32::MAX - 10_000;
+        const THREADS: u32 = 8;
+        const PIDS_PER_THREAD: u32 = 100;
+
+        // The fake PIDs of this and the other tests pass for running processes, so cleaning up leaves them alone
+        let live: HashSet<u32> = crate::utils::bindings::list_kinfo_procs()
+            .unwrap()
+            .iter()
+            .map(|proc_info| proc_info.pid() as u32)
+            .chain(FIRST_PID..=u32::MAX)
+            .chain([12345])
+            .collect();
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let cleaner = {
+            let done = Arc::clone(&done);
+            std::thread::spawn(move || {
+                let mut sweeps = 0;
+                while !done.load(Ordering::SeqCst) {
+                    get_cpu_history().retain_live(&live, Instant::now());
+                    sweeps += 1;
+                    std::thread::yield_now();
+                }
+                sweeps
+            })
+        };
+
+        let readers: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                std::thread::spawn(move || {
+                    let first = FIRST_PID + thread * PIDS_PER_THREAD;
+                    for round in 0..20u64 {
+                        for pid in first..first + PIDS_PER_THREAD {
+                            let usage = Process::calculate_cpu_usage(&identity(pid), round * 1_000);
+                            assert!(usage.is_finite() && usage >= 0.0);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.join().expect("A reader panicked");
+        }
+        done.store(true, Ordering::SeqCst);
+        assert!(cleaner.join().expect("The cleaner panicked") > 0);
+
+        let mut history = get_cpu_history();
+        for pid in FIRST_PID..FIRST_PID + THREADS * PIDS_PER_THREAD {
+            history.remove(&pid);
+        }
+        assert!(history.stats().tracked <= CpuHistoryConfig::default().capacity);
+    }
+
+    #[tokio::test]
+    async fn test_global_stats() {
+        Process::get_by_pid(std::process::id()).await.unwrap();
+        let stats = cpu_history_stats();
+        assert!(stats.tracked >= 1 && stats.tracked <= CpuHistoryConfig::default().capacity);
+    }
+}
+
+mod io {
+    use super::*;
+    use crate::process::io::CounterBaseline;
+
+    #[test]
+    fn test_rate_between_samples() {
+        let start = Instant::now();
+        let mut baseline = CounterBaseline::new(start, 1_000);
+
+        assert_eq!(baseline.advance(start + Duration::from_secs(2), 5_000), Some(2_000.0));
+        // The second rate starts from the second sample, not from the first one
+        assert_eq!(baseline.advance(start + Duration::from_secs(3), 5_500), Some(500.0));
+        assert_eq!(baseline.advance(start + Duration::from_secs(4), 5_500), Some(0.0));
+    }
+
+    #[test]
+    fn test_samples_too_close_keep_the_baseline() {
+        let start = Instant::now();
+        let mut baseline = CounterBaseline::new(start, 0);
+
+        assert_eq!(baseline.advance(start + Duration::from_millis(10), 100), None);
+        assert_eq!(baseline, CounterBaseline::new(start, 0));
+        assert_eq!(baseline.advance(start + Duration::from_millis(500), 100), Some(200.0));
+    }
+
+    #[test]
+    fn test_counter_going_backwards_restarts() {
+        let start = Instant::now();
+        let mut baseline = CounterBaseline::new(start, 10_000);
+
+        assert_eq!(baseline.advance(start + Duration::from_secs(1), 400), None);
+        assert_eq!(baseline.advance(start + Duration::from_secs(2), 1_400), Some(1_000.0));
+    }
+
+    #[test]
+    fn test_monitor_of_current_process() {
+        let monitor = ProcessIOMonitor::new(std::process::id()).unwrap();
+        assert_eq!(monitor.pid(), std::process::id());
+
+        // Right after creation there is no meaningful interval yet
+        assert_eq!(monitor.read_rate().unwrap(), None);
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(monitor.write_rate().unwrap().is_some_and(|rate| rate >= 0.0));
+
+        assert!(ProcessIOMonitor::new(99_999).is_err(), "A missing process should fail");
+    }
+
+    #[test]
+    fn test_monitor_detects_pid_reuse() {
+        let err = ProcessIOMonitor::with_identity(forged_identity()).unwrap_err();
+        assert!(err.is_pid_reused(), "Expected a PID reuse error, got {:?}", err);
+
+        let identity = ProcessIdentity::of(std::process::id()).unwrap();
+        let monitor = ProcessIOMonitor::with_identity(identity).unwrap();
+        assert_eq!(monitor.identity(), &identity);
+        assert!(monitor.read_rate().is_ok());
+    }
+}
+
+mod files {
+    use std::{io::Write, net::TcpListener};
+
+    use super::*;
+    use crate::process::files::FdCounts;
+
+    #[test]
+    fn test_fd_type_from_raw() {
+        assert_eq!(FdType::from_raw(libc::PROX_FDTYPE_VNODE as u32), FdType::Vnode);
+        assert_eq!(FdType::from_raw(libc::PROX_FDTYPE_SOCKET as u32), FdType::Socket);
+        assert_eq!(FdType::from_raw(libc::PROX_FDTYPE_PIPE as u32), FdType::Pipe);
+        assert_eq!(FdType::from_raw(libc::PROX_FDTYPE_KQUEUE as u32), FdType::Kqueue);
+        assert_eq!(FdType::from_raw(99), FdType::Other(99));
+    }
+
+    #[test]
+    fn test_fd_counts() {
+        assert_eq!(FdCounts::from_types([]), FdCounts::default());
+
+        let types = [FdType::Vnode, FdType::Socket, FdType::Pipe, FdType::Socket, FdType::Kqueue];
+        assert_eq!(FdCounts::from_types(types), FdCounts { open: 5, sockets: 2 });
+    }
+
+    #[test]
+    fn test_open_files_of_current_process() {
+        let path = std::env::temp_dir().join(format!("darwin-metrics-fd-{}", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(b"open").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let open_files = Process::get_open_files(std::process::id()).unwrap();
+        let canonical = path.canonicalize().unwrap();
+        assert!(
+            open_files.iter().any(|file| {
+                file.fd_type == FdType::Vnode
+                    && file.path.as_deref().map(std::path::Path::new) == Some(canonical.as_path())
+            }),
+            "The temporary file is missing from {:?}",
+            open_files
+        );
+        assert!(open_files
+            .iter()
+            .any(|file| file.fd_type == FdType::Socket && file.path.is_none()));
+
+        drop((file, listener));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fd_counts_of_current_process() {
+        let _listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let process = Process::get_by_pid(std::process::id()).await.unwrap();
+        // stdin, stdout and stderr at least
+        assert!(process.open_fd_count.is_some_and(|count| count >= 3));
+        assert!(process.socket_count.is_some_and(|count| count >= 1));
+        assert!(process.socket_count <= process.open_fd_count);
+    }
+
+    #[test]
+    fn test_open_files_of_missing_process() {
+        assert!(Process::get_open_files(u32::MAX / 2).is_err());
+    }
+}
+
+mod lookup {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::process::lookup::{is_truncated, matching_pids, NameMatch};
+
+    /// Processes as the process table lists them, with the full names `proc_name` reports
+    fn table() -> (Vec<(u32, String)>, HashMap<u32, &'static str>) {
+        let processes = [
+            (1, "launchd"),
+            (2, "com.apple.WebKit"),
+            (3, "com.apple.WebKit"),
+            (4, "com.apple.WebKit"),
+            (5, "Safari"),
+        ]
+        .map(|(pid, name)| (pid, name.to_string()))
+        .to_vec();
+        let full_names = HashMap::from([
+            (1, "launchd"),
+            (2, "com.apple.WebKit.Networking"),
+            (3, "com.apple.WebKit.GPU"),
+            (5, "Safari"),
+        ]);
+        (processes, full_names)
+    }
+
+    #[test]
+    fn test_is_truncated() {
+        assert!(!is_truncated("launchd"));
+        assert!(!is_truncated("fifteen_chars_x"));
+        assert!(is_truncated("com.apple.WebKit"));
+        assert!(is_truncated("Grüße\u{FFFD}"));
+    }
+
+    #[test]
+    fn test_exact_match_reads_full_names() {
+        let (processes, full_names) = table();
+        let full_name = |pid: u32| full_names.get(&pid).map(|name| name.to_string());
+
+        let pids = matching_pids(
+            processes.clone(),
+            "com.apple.WebKit.Networking",
+            NameMatch::Exact,
+            full_name,
+        );
+        assert_eq!(pids, [2]);
+
+        // The process whose full name can't be read keeps its listed name
+        let pids =
+            matching_pids(processes.clone(), "com.apple.WebKit", NameMatch::Exact, full_name);
+        assert_eq!(pids, [4]);
+
+        assert_eq!(matching_pids(processes.clone(), "Safari", NameMatch::Exact, full_name), [5]);
+        assert!(matching_pids(processes, "Safar", NameMatch::Exact, full_name).is_empty());
+    }
+
+    #[test]
+    fn test_contains_match_across_truncation() {
+        let (processes, full_names) = table();
+        let full_name = |pid: u32| full_names.get(&pid).map(|name| name.to_string());
+
+        assert_eq!(matching_pids(processes.clone(), "GPU", NameMatch::Contains, full_name), [3]);
+        assert_eq!(
+            matching_pids(processes.clone(), "WebKit", NameMatch::Contains, full_name),
+            [2, 3, 4]
+        );
+        assert_eq!(matching_pids(processes, "a", NameMatch::Contains, full_name), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_full_names_only_read_when_needed() {
+        let (processes, _) = table();
+        let reads = std::cell::Cell::new(0);
+        let full_name = |_| {
+            reads.set(reads.get() + 1);
+            None
+        };
+
+        matching_pids(processes.clone(), "launchd", NameMatch::Exact, full_name);
+        assert_eq!(reads.get(), 0, "No listed name is a prefix of the query");
+
+        matching_pids(processes, "WebKit", NameMatch::Contains, full_name);
+        assert_eq!(reads.get(), 0, "The listed names already contain the query");
+    }
+
+    #[tokio::test]
+    async fn test_find_current_process() {
+        let pid = std::process::id();
+        let name = libproc::proc_pid::name(pid as i32).unwrap();
+
+        let found = Process::find_by_name(&name).await.unwrap();
+        assert!(found.iter().any(|process| process.pid == pid));
+        assert!(found.iter().all(|process| process.name == name));
+        let current = found.iter().find(|process| process.pid == pid).unwrap();
+        assert!(current.memory_usage > 0, "Matches are read in full");
+
+        let pattern = &name[name.len() / 2..];
+        let found = Process::find_by_name_contains(pattern).await.unwrap();
+        assert!(found.iter().any(|process| process.pid == pid));
+    }
+
+    #[tokio::test]
+    async fn test_find_by_path() {
+        let pid = std::process::id();
+        let executable = Process::executable_path(pid).unwrap();
+        assert_eq!(executable, std::env::current_exe().unwrap().canonicalize().unwrap());
+
+        let found = Process::find_by_path(&executable).await.unwrap();
+        assert!(found.iter().any(|process| process.pid == pid));
+
+        let missing = std::path::Path::new("/nonexistent/darwin-metrics");
+        assert!(Process::find_by_path(missing).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_executable_path_of_missing_process() {
+        assert!(Process::executable_path(u32::MAX / 2).is_err());
+    }
+}
+
+mod tree {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn snapshot(parents: &[(u32, u32)]) -> (Vec<Process>, HashMap<u32, u32>) {
+        let processes =
+            parents.iter().map(|&(pid, _)| Process::new(pid, format!("proc{pid}"))).collect();
+        (processes, parents.iter().copied().collect())
+    }
+
+    fn pids<'a>(processes: impl IntoIterator<Item = &'a Process>) -> Vec<u32> {
+        processes.into_iter().map(|process| process.pid).collect()
+    }
+
+    #[test]
+    fn test_tree_of_every_process() {
+        // launchd with two children, one of which has children of its own
+        let (processes, parents) =
+            snapshot(&[(1, 0), (30, 1), (20, 1), (31, 30), (32, 30), (33, 31)]);
+        let tree =
+            ProcessTree::from_parts(processes, &parents, ProcessTree::SYNTHETIC_ROOT).unwrap();
+
+        assert_eq!(tree.len(), 6);
+        let order: Vec<(u32, usize)> =
+            tree.iter().map(|(process, depth)| (process.pid, depth)).collect();
+        assert_eq!(order, [(1, 0), (20, 1), (30, 1), (31, 2), (33, 3), (32, 2)]);
+
+        assert_eq!(pids(tree.children(1)), [20, 30]);
+        assert_eq!(pids(tree.children(33)), Vec::<u32>::new());
+        assert_eq!(pids(tree.descendants(30)), [31, 33, 32]);
+        assert_eq!(tree.depth(33), Some(3));
+        assert_eq!(tree.depth(99), None);
+        assert_eq!(tree.parent(31), Some(30));
+        assert_eq!(tree.parent(1), Some(ProcessTree::SYNTHETIC_ROOT));
+    }
+
+    #[test]
+    fn test_tree_rooted_at_process() {
+        let (processes, parents) =
+            snapshot(&[(1, 0), (30, 1), (20, 1), (31, 30), (32, 30), (33, 31)]);
+        let tree = ProcessTree::from_parts(processes, &parents, 30).unwrap();
+
+        assert_eq!(tree.root(), 30);
+        assert_eq!(tree.len(), 4);
+        let order: Vec<(u32, usize)> =
+            tree.iter().map(|(process, depth)| (process.pid, depth)).collect();
+        assert_eq!(order, [(30, 0), (31, 1), (33, 2), (32, 1)]);
+        assert_eq!(tree.parent(30), None);
+        assert!(!tree.contains(20));
+        assert_eq!(tree.get(33).unwrap().name, "proc33");
+
+        let (processes, parents) = snapshot(&[(1, 0)]);
+        assert!(ProcessTree::from_parts(processes, &parents, 2).is_err());
+    }
+
+    #[test]
+    fn test_orphans_hang_below_synthetic_root() {
+        // 40's parent exited, 41's parent is unknown, and 42 claims to be its own parent
+        let (mut processes, mut parents) = snapshot(&[(1, 0), (40, 7), (42, 42)]);
+        processes.push(Process::new(41, "proc41"));
+        parents.insert(50, 1);
+
+        let tree =
+            ProcessTree::from_parts(processes, &parents, ProcessTree::SYNTHETIC_ROOT).unwrap();
+        assert_eq!(pids(tree.children(ProcessTree::SYNTHETIC_ROOT)), [1, 40, 41, 42]);
+        assert!(tree.iter().all(|(_, depth)| depth == 0));
+        assert!(!tree.contains(50));
+    }
+
+    #[test]
+    fn test_parent_cycles_are_cut() {
+        // PID reuse raced with the snapshot, so 60 and 61 claim each other as parent
+        let (processes, parents) = snapshot(&[(1, 0), (60, 61), (61, 60), (62, 61)]);
+        let tree =
+            ProcessTree::from_parts(processes, &parents, ProcessTree::SYNTHETIC_ROOT).unwrap();
+
+        assert_eq!(tree.iter().count(), 4);
+        assert_eq!(tree.parent(60), Some(ProcessTree::SYNTHETIC_ROOT));
+        assert_eq!(pids(tree.descendants(60)), [61, 62]);
+        assert_eq!(tree.depth(62), Some(2));
+    }
+}
+
+#[cfg(feature = "control")]
+mod control {
+    use super::*;
+    use crate::process::state::signal_error;
+
+    /// Polls the state of `pid` until `done` accepts it
+    async fn wait_for_state(pid: u32, done: impl Fn(&Process) -> bool) -> Process {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let process = Process::get_by_pid(pid).await.unwrap();
+            if done(&process) || Instant::now() > deadline {
+                return process;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_suspend_and_resume_child() {
+        let mut child =
+            Command::new("sleep").arg("30").spawn().expect("Failed to spawn child process");
+        let pid = child.id();
+
+        Process::suspend(pid).unwrap();
+        let stopped = wait_for_state(pid, |process| process.is_suspended).await;
+        assert_eq!(stopped.state, Some(ProcessState::Stopped));
+        assert!(stopped.is_suspended);
+
+        Process::resume(pid).unwrap();
+        let resumed = wait_for_state(pid, |process| !process.is_suspended).await;
+        assert_ne!(resumed.state, Some(ProcessState::Stopped));
+        assert!(!resumed.is_suspended);
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_signal_errors() {
+        assert!(Process::suspend(0).is_err(), "PID 0 would signal the process group");
+        assert!(Process::resume(u32::MAX).is_err(), "Negative PIDs would signal process groups");
+        assert!(!Process::resume(99_999).unwrap_err().is_permission_error());
+
+        let denied = signal_error(1, std::io::Error::from_raw_os_error(libc::EPERM));
+        assert!(denied.is_permission_error(), "{denied:?}");
+        let missing = signal_error(99_999, std::io::Error::from_raw_os_error(libc::ESRCH));
+        assert!(!missing.is_permission_error(), "{missing:?}");
+    }
+}
@@ -0,0 +1,193 @@
+//! Process trees built from one snapshot of the process table
+
+use std::collections::{HashMap, HashSet};
+
+use super::Process;
+use crate::error::{Error, Result};
+
+/// Parent-child hierarchy of processes, rooted at one process or at [`ProcessTree::SYNTHETIC_ROOT`]
+///
+/// Every process hangs below its parent. Processes whose parent isn't in the snapshot, because it exited or the
+/// snapshot raced with PID reuse, hang below the synthetic root, as does `launchd`, whose parent is the kernel. Parent
+/// links that would form a cycle are cut the same way, so every process appears exactly once.
+///
+/// Depths are counted from the root of the tree, which is at depth 0. The synthetic root isn't a process, so the
+/// processes directly below it are at depth 0 instead.
+#[derive(Debug, Clone)]
+pub struct ProcessTree {
+    root: u32,
+    processes: HashMap<u32, Process>,
+    parents: HashMap<u32, u32>,
+    children: HashMap<u32, Vec<u32>>,
+    /// PIDs and depths of the processes in depth-first order, the root first if it's a process
+    order: Vec<(u32, usize)>,
+}
+
+impl ProcessTree {
+    /// PID of the root that processes without a parent in the snapshot hang below; no process has it
+    pub const SYNTHETIC_ROOT: u32 = 0;
+
+    /// Builds the tree below `root` from a snapshot of processes and the parent PID of each.
+    ///
+    /// Pass [`ProcessTree::SYNTHETIC_ROOT`] for the tree of every process.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `root` isn't in the snapshot.
+    pub fn from_parts(
+        processes: Vec<Process>,
+        parent_pids: &HashMap<u32, u32>,
+        root: u32,
+    ) -> Result<Self> {
+        let processes: HashMap<u32, Process> = processes
+            .into_iter()
+            .filter(|process| process.pid != Self::SYNTHETIC_ROOT)
+            .map(|process| (process.pid, process))
+            .collect();
+        if root != Self::SYNTHETIC_ROOT && !processes.contains_key(&root) {
+            return Err(Error::process_error(format!("Process {} not found", root)));
+        }
+
+        let mut parents: HashMap<u32, u32> = processes
+            .keys()
+            .map(|&pid| {
+                let parent = parent_pids
+                    .get(&pid)
+                    .copied()
+                    .filter(|parent| *parent != pid && processes.contains_key(parent))
+                    .unwrap_or(Self::SYNTHETIC_ROOT);
+                (pid, parent)
+            })
+            .collect();
+
+        let mut children = children_of(&parents);
+        let mut order = Vec::with_capacity(processes.len());
+        let mut visited = HashSet::with_capacity(processes.len());
+        walk(Self::SYNTHETIC_ROOT, 0, &children, &mut visited, &mut order);
+
+        // Processes out of reach of the synthetic root form cycles; cut each cycle at its lowest PID
+        while visited.len() < processes.len() {
+            let Some(&pid) = processes.keys().filter(|pid| !visited.contains(*pid)).min() else {
+                break;
+            };
+            parents.insert(pid, Self::SYNTHETIC_ROOT);
+            children = children_of(&parents);
+            order.clear();
+            visited.clear();
+            walk(Self::SYNTHETIC_ROOT, 0, &children, &mut visited, &mut order);
+        }
+
+        let mut tree = Self { root: Self::SYNTHETIC_ROOT, processes, parents, children, order };
+        if root != Self::SYNTHETIC_ROOT {
+            tree.restrict_to(root);
+        }
+        Ok(tree)
+    }
+
+    /// Drops every process outside the subtree of `root`.
+    fn restrict_to(&mut self, root: u32) {
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        order.push((root, 0));
+        visited.insert(root);
+        walk(root, 1, &self.children, &mut visited, &mut order);
+
+        self.processes.retain(|pid, _| visited.contains(pid));
+        self.parents.retain(|pid, _| *pid != root && visited.contains(pid));
+        self.children.retain(|pid, _| visited.contains(pid));
+        self.order = order;
+        self.root = root;
+    }
+
+    /// PID of the root of the tree.
+    pub fn root(&self) -> u32 {
+        self.root
+    }
+
+    /// The process with `pid`, if it's in the tree.
+    pub fn get(&self, pid: u32) -> Option<&Process> {
+        self.processes.get(&pid)
+    }
+
+    /// Whether the process with `pid` is in the tree.
+    pub fn contains(&self, pid: u32) -> bool {
+        self.processes.contains_key(&pid)
+    }
+
+    /// PID of the parent of `pid`, or None for the root and processes outside the tree.
+    pub fn parent(&self, pid: u32) -> Option<u32> {
+        self.parents.get(&pid).copied()
+    }
+
+    /// The direct children of `pid`, ordered by PID.
+    pub fn children(&self, pid: u32) -> Vec<&Process> {
+        self.children
+            .get(&pid)
+            .into_iter()
+            .flatten()
+            .filter_map(|child| self.processes.get(child))
+            .collect()
+    }
+
+    /// Every process below `pid`, in depth-first order.
+    pub fn descendants(&self, pid: u32) -> Vec<&Process> {
+        let mut order = Vec::new();
+        walk(pid, 0, &self.children, &mut HashSet::new(), &mut order);
+        order.iter().filter_map(|(descendant, _)| self.processes.get(descendant)).collect()
+    }
+
+    /// Depth of `pid` below the root of the tree, or None if it isn't in the tree.
+    pub fn depth(&self, pid: u32) -> Option<usize> {
+        self.order.iter().find(|(other, _)| *other == pid).map(|(_, depth)| *depth)
+    }
+
+    /// The processes with their depth, in depth-first order with children ordered by PID.
+    pub fn iter(&self) -> impl Iterator<Item = (&Process, usize)> {
+        self.order.iter().filter_map(|(pid, depth)| Some((self.processes.get(pid)?, *depth)))
+    }
+
+    /// Number of processes in the tree.
+    pub fn len(&self) -> usize {
+        self.processes.len()
+    }
+
+    /// Whether the tree holds no processes.
+    pub fn is_empty(&self) -> bool {
+        self.processes.is_empty()
+    }
+}
+
+/// Children of each parent, ordered by PID
+fn children_of(parents: &HashMap<u32, u32>) -> HashMap<u32, Vec<u32>> {
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (&pid, &parent) in parents {
+        children.entry(parent).or_default().push(pid);
+    }
+    for pids in children.values_mut() {
+        pids.sort_unstable();
+    }
+    children
+}
+
+/// Appends the descendants of `pid` to `order` in depth-first order, the children at `depth`.
+fn walk(
+    pid: u32,
+    depth: usize,
+    children: &HashMap<u32, Vec<u32>>,
+    visited: &mut HashSet<u32>,
+    order: &mut Vec<(u32, usize)>,
+) {
+    let mut stack: Vec<(u32, usize)> =
+        children.get(&pid).into_iter().flatten().rev().map(|&child| (child, depth)).collect();
+
+    while let Some((pid, depth)) = stack.pop() {
+        // A process reached twice would mean a cycle; each is visited once
+        if !visited.insert(pid) {
+            continue;
+        }
+        order.push((pid, depth));
+        stack.extend(
+            children.get(&pid).into_iter().flatten().rev().map(|&child| (child, depth + 1)),
+        );
+    }
+}
@@ -0,0 +1,509 @@
+//! # Report Module
+//!
+//! A [`SupportBundle`] gathers the data a support engineer needs to look into a problem report into one JSON
+//! document that users can paste into a ticket: system information, detected capabilities, thermal sensors and fans,
+//! battery health, the busiest processes, disk space per volume, and the diagnostics of the crate's initialization.
+//!
+//! ## Privacy
+//!
+//! The set of collected data is fixed and reviewed, and the privacy defaults are part of the contract:
+//!
+//! - The hostname and the serial number are only included when opted into with [`SupportBundleOptions`]
+//! - Processes are reported by name and PID only; their arguments and environment are never read
+//! - User names in home directory paths are replaced with `<redacted>`
+//!
+//! Every redaction is listed in [`SupportBundle::redactions_applied`], so the reader of a bundle knows what's missing.
+//!
+//! Like a [`crate::snapshot::MetricsSnapshot`], sections that can't be collected don't fail the bundle; they are left
+//! empty and the reason is recorded in [`SupportBundle::warnings`].
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! let bundle = darwin_metrics::report::support_bundle()?;
+//! println!("{}", bundle.to_pretty_json()?);
+//! # Ok::<(), darwin_metrics::Error>(())
+//! ```
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    ffi::{c_void as ffi_c_void, CString},
+    ptr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use objc2::rc::{autoreleasepool, Retained};
+use objc2_foundation::{NSDictionary, NSObject, NSString};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battery::Battery,
+    disk::Disk,
+    error::{Error, Result},
+    hardware::iokit::{CpuTemperatureSource, FanInfo, IOKit, IOKitImpl, ThermalInfo},
+    init::{self, sysctl_string},
+    process::{sample_processes, ProcessSample},
+    system::uptime,
+    utils::{
+        bindings::{
+            IOObjectRelease, IORegistryEntryCreateCFProperties, IOServiceGetMatchingService,
+            IOServiceMatching,
+        },
+        sanitize::sanitize_rate,
+    },
+};
+
+/// Version of the bundle layout, increased whenever fields are renamed or removed
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Number of processes in each of the process rankings
+const TOP_PROCESSES: usize = 10;
+/// Registry class of the platform expert, which holds the serial number
+const PLATFORM_EXPERT_CLASS: &str = "IOPlatformExpertDevice";
+/// Registry property holding the serial number
+const SERIAL_NUMBER_KEY: &str = "IOPlatformSerialNumber";
+/// Parent directory of the home directories, whose names are user names
+const HOMES_DIR: &str = "/Users/";
+/// Replacement of redacted values
+const REDACTED: &str = "<redacted>";
+
+/// Redaction noted when the hostname is left out
+pub const REDACTION_HOSTNAME: &str = "hostname";
+/// Redaction noted when the serial number is left out
+pub const REDACTION_SERIAL_NUMBER: &str = "serial_number";
+/// Redaction noted for process arguments and environment, which are never collected
+pub const REDACTION_PROCESS_ARGUMENTS: &str = "process_arguments_and_environment";
+/// Redaction noted for user names in home directory paths
+pub const REDACTION_USER_PATHS: &str = "user_names_in_paths";
+
+/// Opt-ins to personally identifying data in a [`SupportBundle`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SupportBundleOptions {
+    /// Include the hostname (default: false)
+    pub include_hostname: bool,
+    /// Include the hardware serial number (default: false)
+    pub include_serial_number: bool,
+}
+
+/// System section of a [`SupportBundle`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SystemSummary {
+    /// Hardware model identifier (e.g. "Mac14,10")
+    pub model: String,
+    /// macOS version (e.g. "14.5")
+    pub os_version: String,
+    /// macOS build (e.g. "23F79")
+    pub os_build: String,
+    /// CPU architecture
+    pub architecture: String,
+    /// CPU model name (e.g. "Apple M2 Pro")
+    pub cpu_model: String,
+    /// Number of physical CPU cores
+    pub physical_cores: u32,
+    /// Number of logical CPU cores
+    pub logical_cores: u32,
+    /// Installed physical memory in bytes
+    pub memory_bytes: u64,
+    /// Time since boot in seconds
+    pub uptime_secs: u64,
+    /// Hostname, only if opted into
+    pub hostname: Option<String>,
+    /// Hardware serial number, only if opted into
+    pub serial_number: Option<String>,
+}
+
+/// Capabilities section of a [`SupportBundle`], see [`crate::init::Capabilities`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapabilitySummary {
+    /// Whether the System Management Controller is reachable
+    pub smc: bool,
+    /// Whether a GPU driver is loaded
+    pub gpu: bool,
+    /// Whether the machine has a battery
+    pub battery: bool,
+}
+
+/// A temperature sensor in a [`ThermalSummary`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SensorSummary {
+    /// Sensor name (e.g. "cpu")
+    pub name: String,
+    /// Temperature in degrees Celsius
+    pub celsius: f64,
+    /// Where the reading came from: "smc", "ioregistry" or "hid"
+    pub source: String,
+}
+
+/// Thermal section of a [`SupportBundle`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ThermalSummary {
+    /// The sensors that reported a temperature
+    pub sensors: Vec<SensorSummary>,
+    /// Whether the system is thermal throttling
+    pub is_throttling: bool,
+    /// CPU power consumption in watts, if reported
+    pub cpu_power_watts: Option<f64>,
+}
+
+/// A fan in a [`SupportBundle`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FanSummary {
+    /// Fan name (e.g. "Fan 0")
+    pub name: String,
+    /// Current speed in RPM
+    pub speed_rpm: u32,
+    /// Minimum speed in RPM
+    pub min_rpm: u32,
+    /// Maximum speed in RPM
+    pub max_rpm: u32,
+    /// Current utilization between 0 and 100
+    pub percentage: f64,
+}
+
+/// Battery section of a [`SupportBundle`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatterySummary {
+    /// Whether a battery is installed
+    pub is_present: bool,
+    /// Number of charge cycles
+    pub cycle_count: u32,
+    /// Maximum capacity relative to the design capacity, between 0 and 100
+    pub health_percentage: f64,
+    /// Whether the health is poor enough to recommend service
+    pub is_health_poor: bool,
+    /// Current power source
+    pub power_source: String,
+}
+
+/// A process in a [`SupportBundle`], by name and PID only
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessSummary {
+    /// Process ID
+    pub pid: u32,
+    /// Name of the executable, without arguments
+    pub name: String,
+}
+
+/// Disk space of a mounted volume in a [`SupportBundle`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeSummary {
+    /// Mount point, with user names redacted
+    pub mount_point: String,
+    /// Filesystem type (e.g. "apfs")
+    pub fs_type: String,
+    /// Total capacity in bytes
+    pub total_bytes: u64,
+    /// Used space in bytes
+    pub used_bytes: u64,
+    /// Available space in bytes
+    pub available_bytes: u64,
+}
+
+/// Data for a support ticket, collected by [`support_bundle`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SupportBundle {
+    /// Layout version, see [`SCHEMA_VERSION`]
+    pub schema_version: u32,
+    /// When the bundle was collected, in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    /// System information, or None if it couldn't be read
+    pub system: Option<SystemSummary>,
+    /// Hardware the metrics depend on
+    pub capabilities: CapabilitySummary,
+    /// Temperature sensors, or None if they couldn't be read
+    pub thermal: Option<ThermalSummary>,
+    /// Fans, empty on fanless machines
+    pub fans: Vec<FanSummary>,
+    /// Battery health, or None if it couldn't be read
+    pub battery: Option<BatterySummary>,
+    /// The processes with the highest average CPU usage since they started, busiest first
+    pub top_processes_by_cpu: Vec<ProcessSummary>,
+    /// The processes with the most resident memory, largest first
+    pub top_processes_by_memory: Vec<ProcessSummary>,
+    /// Disk space per mounted volume
+    pub volumes: Vec<VolumeSummary>,
+    /// Missing capabilities that limit the available metrics, as in [`crate::InitReport::degraded`]
+    pub diagnostics: Vec<String>,
+    /// Problems encountered while collecting the bundle
+    pub warnings: Vec<String>,
+    /// The kinds of data left out or redacted for privacy
+    pub redactions_applied: Vec<String>,
+}
+
+impl SupportBundle {
+    /// Serializes the bundle as indented JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bundle can't be serialized.
+    pub fn to_pretty_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| Error::invalid_data(format!("Failed to serialize support bundle: {}", e)))
+    }
+}
+
+/// Collects a support bundle with the privacy defaults.
+///
+/// # Errors
+///
+/// Never fails at the moment; sections that can't be collected are recorded in [`SupportBundle::warnings`]. The
+/// `Result` leaves room for failures of the bundle as a whole.
+pub fn support_bundle() -> Result<SupportBundle> {
+    support_bundle_with(SupportBundleOptions::default())
+}
+
+/// Collects a support bundle, including the identifying data opted into with `options`.
+///
+/// # Errors
+///
+/// See [`support_bundle`].
+pub fn support_bundle_with(options: SupportBundleOptions) -> Result<SupportBundle> {
+    let mut bundle = SupportBundle {
+        schema_version: SCHEMA_VERSION,
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+        ..SupportBundle::default()
+    };
+
+    match collect_system() {
+        Ok(system) => bundle.system = Some(system),
+        Err(e) => bundle.warnings.push(format!("system: {e}")),
+    }
+
+    let capabilities = init::capabilities();
+    bundle.capabilities = CapabilitySummary {
+        smc: capabilities.smc,
+        gpu: capabilities.gpu,
+        battery: capabilities.battery,
+    };
+    bundle.diagnostics = capabilities.degraded();
+
+    match IOKitImpl::default().get_thermal_info() {
+        Ok(info) => bundle.thermal = Some(thermal_summary(&info)),
+        Err(e) => bundle.warnings.push(format!("thermal: {e}")),
+    }
+
+    match IOKitImpl::default().get_all_fans() {
+        Ok(fans) => bundle.fans = fan_summaries(&fans),
+        Err(e) => bundle.warnings.push(format!("fans: {e}")),
+    }
+
+    if capabilities.battery {
+        match Battery::new() {
+            Ok(battery) => bundle.battery = Some(battery_summary(&battery)),
+            Err(e) => bundle.warnings.push(format!("battery: {e}")),
+        }
+    }
+
+    match sample_processes() {
+        Ok(samples) => {
+            bundle.top_processes_by_cpu = top_by_cpu(&samples, TOP_PROCESSES);
+            bundle.top_processes_by_memory = top_by_memory(&samples, TOP_PROCESSES);
+        },
+        Err(e) => bundle.warnings.push(format!("processes: {e}")),
+    }
+
+    match Disk::get_all() {
+        Ok(disks) => bundle.volumes = disks.iter().map(volume_summary).collect(),
+        Err(e) => bundle.warnings.push(format!("volumes: {e}")),
+    }
+
+    let identifiers = Identifiers {
+        hostname: options.include_hostname.then(|| sysctl_string("kern.hostname").ok()).flatten(),
+        serial_number: options.include_serial_number.then(serial_number).flatten(),
+    };
+    apply_privacy(&mut bundle, identifiers, &options);
+
+    Ok(bundle)
+}
+
+/// Identifying data, collected only when opted into
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Identifiers {
+    pub(crate) hostname: Option<String>,
+    pub(crate) serial_number: Option<String>,
+}
+
+/// Adds the identifiers that were opted into, redacts user names and records every redaction.
+pub(crate) fn apply_privacy(
+    bundle: &mut SupportBundle,
+    identifiers: Identifiers,
+    options: &SupportBundleOptions,
+) {
+    let mut redactions = Vec::new();
+
+    if let Some(system) = &mut bundle.system {
+        system.hostname = identifiers.hostname.filter(|_| options.include_hostname);
+        system.serial_number = identifiers.serial_number.filter(|_| options.include_serial_number);
+    }
+    if !options.include_hostname {
+        redactions.push(REDACTION_HOSTNAME);
+    }
+    if !options.include_serial_number {
+        redactions.push(REDACTION_SERIAL_NUMBER);
+    }
+    redactions.push(REDACTION_PROCESS_ARGUMENTS);
+
+    for volume in &mut bundle.volumes {
+        volume.mount_point = redact_user_names(&volume.mount_point);
+    }
+    redactions.push(REDACTION_USER_PATHS);
+
+    bundle.redactions_applied = redactions.into_iter().map(str::to_string).collect();
+}
+
+/// Replaces the user name in a path below the home directories with `<redacted>`.
+pub(crate) fn redact_user_names(path: &str) -> String {
+    let Some(rest) = path.strip_prefix(HOMES_DIR) else {
+        return path.to_string();
+    };
+    // The shared home directory doesn't belong to a user
+    if rest == "Shared" || rest.starts_with("Shared/") {
+        return path.to_string();
+    }
+
+    match rest.find('/') {
+        Some(end) => format!("{HOMES_DIR}{REDACTED}{}", &rest[end..]),
+        None => format!("{HOMES_DIR}{REDACTED}"),
+    }
+}
+
+fn collect_system() -> Result<SystemSummary> {
+    let info = init::system_info()?;
+
+    Ok(SystemSummary {
+        model: sysctl_string("hw.model")?,
+        os_version: sysctl_string("kern.osproductversion")?,
+        os_build: sysctl_string("kern.osversion")?,
+        architecture: format!("{:?}", info.architecture),
+        cpu_model: info.cpu_model.clone(),
+        physical_cores: info.physical_cores,
+        logical_cores: info.logical_cores,
+        memory_bytes: info.memory_bytes,
+        uptime_secs: uptime()?.as_secs(),
+        hostname: None,
+        serial_number: None,
+    })
+}
+
+pub(crate) fn thermal_summary(info: &ThermalInfo) -> ThermalSummary {
+    let cpu_source = match info.cpu_temp_source {
+        CpuTemperatureSource::Smc => "smc",
+        CpuTemperatureSource::IORegistry => "ioregistry",
+        CpuTemperatureSource::Hid => "hid",
+    };
+
+    // Every sensor except the CPU is read from the SMC
+    let readings = [
+        ("cpu", Some(info.cpu_temp), cpu_source),
+        ("gpu", info.gpu_temp, "smc"),
+        ("heatsink", info.heatsink_temp, "smc"),
+        ("ambient", info.ambient_temp, "smc"),
+        ("battery", info.battery_temp, "smc"),
+    ];
+
+    ThermalSummary {
+        sensors: readings
+            .into_iter()
+            .filter_map(|(name, celsius, source)| {
+                celsius.map(|celsius| SensorSummary {
+                    name: name.to_string(),
+                    celsius,
+                    source: source.to_string(),
+                })
+            })
+            .collect(),
+        is_throttling: info.is_throttling,
+        cpu_power_watts: info.cpu_power,
+    }
+}
+
+fn fan_summaries(fans: &[FanInfo]) -> Vec<FanSummary> {
+    fans.iter()
+        .enumerate()
+        .map(|(i, fan)| FanSummary {
+            name: fan.name.clone().unwrap_or_else(|| format!("Fan {}", i)),
+            speed_rpm: fan.speed_rpm,
+            min_rpm: fan.min_speed,
+            max_rpm: fan.max_speed,
+            percentage: fan.percentage,
+        })
+        .collect()
+}
+
+fn battery_summary(battery: &Battery) -> BatterySummary {
+    BatterySummary {
+        is_present: battery.is_present,
+        cycle_count: battery.cycle_count,
+        health_percentage: battery.health_percentage,
+        is_health_poor: battery.is_health_poor(),
+        power_source: battery.power_source_display().to_string(),
+    }
+}
+
+/// The `n` processes with the highest average CPU usage since they started, busiest first
+pub(crate) fn top_by_cpu(samples: &[ProcessSample], n: usize) -> Vec<ProcessSummary> {
+    let average_cpu = |sample: &ProcessSample| {
+        sanitize_rate(sample.cpu_time.as_secs_f64(), sample.age.as_secs_f64())
+    };
+
+    let mut ranked: Vec<&ProcessSample> = samples.iter().collect();
+    ranked.sort_by(|a, b| average_cpu(b).total_cmp(&average_cpu(a)));
+    ranked.into_iter().take(n).map(process_summary).collect()
+}
+
+/// The `n` processes with the most resident memory, largest first
+pub(crate) fn top_by_memory(samples: &[ProcessSample], n: usize) -> Vec<ProcessSummary> {
+    let mut ranked: Vec<&ProcessSample> = samples.iter().collect();
+    ranked.sort_by_key(|sample| std::cmp::Reverse(sample.resident_bytes));
+    ranked.into_iter().take(n).map(process_summary).collect()
+}
+
+fn process_summary(sample: &ProcessSample) -> ProcessSummary {
+    ProcessSummary { pid: sample.identity.pid(), name: sample.name.clone() }
+}
+
+fn volume_summary(disk: &Disk) -> VolumeSummary {
+    VolumeSummary {
+        mount_point: disk.mount_point.clone(),
+        fs_type: disk.fs_type.clone(),
+        total_bytes: disk.total,
+        used_bytes: disk.used,
+        available_bytes: disk.available,
+    }
+}
+
+/// Reads the hardware serial number from the platform expert.
+fn serial_number() -> Option<String> {
+    let class = CString::new(PLATFORM_EXPERT_CLASS).ok()?;
+
+    autoreleasepool(|_| unsafe {
+        // IOServiceGetMatchingService consumes the matching dictionary
+        let matching = IOServiceMatching(class.as_ptr());
+        if matching.is_null() {
+            return None;
+        }
+        let service = IOServiceGetMatchingService(0, matching);
+        if service == 0 {
+            return None;
+        }
+
+        let mut props: *mut ffi_c_void = ptr::null_mut();
+        let result = IORegistryEntryCreateCFProperties(service, &mut props, ptr::null_mut(), 0);
+        IOObjectRelease(service);
+        if result != 0 {
+            return None;
+        }
+        // The properties are returned with a +1 retain count and are toll-free bridged to NSDictionary
+        let properties: Retained<NSDictionary<NSString, NSObject>> =
+            Retained::from_raw(props as *mut NSDictionary<NSString, NSObject>)?;
+
+        let key = NSString::from_str(SERIAL_NUMBER_KEY);
+        let serial = properties.valueForKey(&key)?.downcast::<NSString>().ok()?;
+        Some(serial.to_string())
+    })
+}
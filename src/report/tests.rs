@@ -0,0 +1,237 @@
+use std::time::Duration;
+
+use super::*;
+use crate::process::ProcessIdentity;
+
+const HOSTNAME: &str = "jappleseeds-macbook-pro";
+const SERIAL_NUMBER: &str = "C02XK1ABJGH5";
+const USER_NAME: &str = "jappleseed";
+
+fn sample(
+    pid: u32,
+    name: &str,
+    cpu_secs: u64,
+    age_secs: u64,
+    resident_bytes: u64,
+) -> ProcessSample {
+    let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    ProcessSample {
+        identity: ProcessIdentity::new(pid, start, Some(pid as u64)),
+        name: name.to_string(),
+        age: Duration::from_secs(age_secs),
+        cpu_time: Duration::from_secs(cpu_secs),
+        resident_bytes,
+        threads: Some(4),
+        fds: Some(16),
+    }
+}
+
+fn fixture_samples() -> Vec<ProcessSample> {
+    vec![
+        sample(100, "WindowServer", 3_600, 86_400, 800 * 1024 * 1024),
+        sample(200, "python3", 50, 100, 100 * 1024 * 1024),
+        sample(300, "Safari", 600, 3_600, 2 * 1024 * 1024 * 1024),
+        sample(400, "launchd", 10, 86_400, 20 * 1024 * 1024),
+    ]
+}
+
+fn fixture_bundle() -> SupportBundle {
+    let samples = fixture_samples();
+    SupportBundle {
+        schema_version: SCHEMA_VERSION,
+        timestamp_ms: 1_700_000_000_000,
+        system: Some(SystemSummary {
+            model: "Mac14,10".to_string(),
+            os_version: "14.5".to_string(),
+            os_build: "23F79".to_string(),
+            architecture: "AppleSilicon".to_string(),
+            cpu_model: "Apple M2 Pro".to_string(),
+            physical_cores: 12,
+            logical_cores: 12,
+            memory_bytes: 32 * 1024 * 1024 * 1024,
+            uptime_secs: 86_400,
+            hostname: None,
+            serial_number: None,
+        }),
+        capabilities: CapabilitySummary { smc: true, gpu: true, battery: true },
+        top_processes_by_cpu: top_by_cpu(&samples, TOP_PROCESSES),
+        top_processes_by_memory: top_by_memory(&samples, TOP_PROCESSES),
+        volumes: vec![
+            VolumeSummary {
+                mount_point: "/".to_string(),
+                fs_type: "apfs".to_string(),
+                total_bytes: 500,
+                used_bytes: 200,
+                available_bytes: 300,
+            },
+            VolumeSummary {
+                mount_point: format!("/Users/{USER_NAME}/Library/Mounts/backup"),
+                fs_type: "smbfs".to_string(),
+                total_bytes: 1000,
+                used_bytes: 100,
+                available_bytes: 900,
+            },
+        ],
+        ..SupportBundle::default()
+    }
+}
+
+fn identifiers() -> Identifiers {
+    Identifiers {
+        hostname: Some(HOSTNAME.to_string()),
+        serial_number: Some(SERIAL_NUMBER.to_string()),
+    }
+}
+
+#[test]
+fn test_default_bundle_has_no_personal_data() {
+    let mut bundle = fixture_bundle();
+    apply_privacy(&mut bundle, identifiers(), &SupportBundleOptions::default());
+    let json = bundle.to_pretty_json().unwrap();
+
+    assert!(!json.contains(HOSTNAME), "The hostname leaked: {json}");
+    assert!(!json.contains(SERIAL_NUMBER), "The serial number leaked: {json}");
+    assert!(!json.contains(USER_NAME), "The user name leaked: {json}");
+    // Processes are listed by executable name only
+    assert!(!json.contains("python3 "), "Process arguments leaked: {json}");
+
+    assert_eq!(
+        bundle.redactions_applied,
+        vec![
+            REDACTION_HOSTNAME,
+            REDACTION_SERIAL_NUMBER,
+            REDACTION_PROCESS_ARGUMENTS,
+            REDACTION_USER_PATHS
+        ]
+    );
+    assert_eq!(bundle.volumes[1].mount_point, "/Users/<redacted>/Library/Mounts/backup");
+}
+
+#[test]
+fn test_opt_in_identifiers() {
+    let mut bundle = fixture_bundle();
+    let options = SupportBundleOptions { include_hostname: true, include_serial_number: true };
+    apply_privacy(&mut bundle, identifiers(), &options);
+
+    let system = bundle.system.as_ref().unwrap();
+    assert_eq!(system.hostname.as_deref(), Some(HOSTNAME));
+    assert_eq!(system.serial_number.as_deref(), Some(SERIAL_NUMBER));
+    assert_eq!(bundle.redactions_applied, vec![REDACTION_PROCESS_ARGUMENTS, REDACTION_USER_PATHS]);
+
+    let mut bundle = fixture_bundle();
+    let options = SupportBundleOptions { include_hostname: true, ..Default::default() };
+    apply_privacy(&mut bundle, identifiers(), &options);
+    let json = bundle.to_pretty_json().unwrap();
+    assert!(json.contains(HOSTNAME));
+    assert!(!json.contains(SERIAL_NUMBER));
+}
+
+#[test]
+fn test_schema_is_stable() {
+    let mut bundle = fixture_bundle();
+    apply_privacy(&mut bundle, identifiers(), &SupportBundleOptions::default());
+    let value: serde_json::Value = serde_json::from_str(&bundle.to_pretty_json().unwrap()).unwrap();
+
+    // Renaming or removing any of these breaks the tools that parse bundles and requires a new SCHEMA_VERSION
+    let mut fields: Vec<&str> = value.as_object().unwrap().keys().map(String::as_str).collect();
+    fields.sort_unstable();
+    assert_eq!(
+        fields,
+        vec![
+            "battery",
+            "capabilities",
+            "diagnostics",
+            "fans",
+            "redactions_applied",
+            "schema_version",
+            "system",
+            "thermal",
+            "timestamp_ms",
+            "top_processes_by_cpu",
+            "top_processes_by_memory",
+            "volumes",
+            "warnings",
+        ]
+    );
+    assert_eq!(value["schema_version"], 1);
+
+    let deserialized: SupportBundle = serde_json::from_value(value).unwrap();
+    assert_eq!(deserialized, bundle);
+}
+
+#[test]
+fn test_process_rankings() {
+    let samples = fixture_samples();
+
+    let by_cpu: Vec<u32> = top_by_cpu(&samples, 3).iter().map(|p| p.pid).collect();
+    assert_eq!(by_cpu, vec![200, 300, 100]);
+
+    let by_memory = top_by_memory(&samples, 2);
+    assert_eq!(
+        by_memory,
+        vec![
+            ProcessSummary { pid: 300, name: "Safari".to_string() },
+            ProcessSummary { pid: 100, name: "WindowServer".to_string() },
+        ]
+    );
+}
+
+#[test]
+fn test_redact_user_names() {
+    assert_eq!(redact_user_names("/"), "/");
+    assert_eq!(redact_user_names("/Volumes/Backup"), "/Volumes/Backup");
+    assert_eq!(redact_user_names("/Users/jappleseed"), "/Users/<redacted>");
+    assert_eq!(redact_user_names("/Users/jappleseed/mnt/nas"), "/Users/<redacted>/mnt/nas");
+    assert_eq!(redact_user_names("/Users/Shared/disk"), "/Users/Shared/disk");
+}
+
+#[test]
+fn test_thermal_sources() {
+    let info = ThermalInfo {
+        cpu_temp: 52.0,
+        gpu_temp: None,
+        heatsink_temp: None,
+        ambient_temp: Some(30.0),
+        battery_temp: None,
+        is_throttling: false,
+        cpu_power: Some(12.5),
+        cpu_temp_source: CpuTemperatureSource::IORegistry,
+        ..Default::default()
+    };
+
+    let thermal = thermal_summary(&info);
+
+    assert_eq!(
+        thermal.sensors,
+        vec![
+            SensorSummary {
+                name: "cpu".to_string(),
+                celsius: 52.0,
+                source: "ioregistry".to_string()
+            },
+            SensorSummary { name: "ambient".to_string(), celsius: 30.0, source: "smc".to_string() },
+        ]
+    );
+    assert_eq!(thermal.cpu_power_watts, Some(12.5));
+}
+
+#[test]
+fn test_support_bundle() {
+    let bundle = support_bundle().unwrap();
+    let json = bundle.to_pretty_json().unwrap();
+
+    assert_eq!(bundle.schema_version, SCHEMA_VERSION);
+    assert!(bundle.system.is_some(), "System info should be collected: {:?}", bundle.warnings);
+    assert!(!bundle.top_processes_by_cpu.is_empty());
+    assert!(bundle.top_processes_by_memory.len() <= TOP_PROCESSES);
+
+    if let Ok(hostname) = sysctl_string("kern.hostname") {
+        assert!(hostname.is_empty() || !json.contains(&hostname), "The hostname leaked");
+    }
+    if let Some(serial) = serial_number() {
+        assert!(!json.contains(&serial), "The serial number leaked");
+    }
+    if let Ok(user) = std::env::var("USER") {
+        assert!(!json.contains(&format!("/Users/{user}")), "A home directory path leaked");
+    }
+}
@@ -1,99 +1,370 @@
+//! # Resource Module
+//!
+//! Building blocks the monitors share: a bounded [`ResourcePool`] of reusable resources, a [`Cache`] whose entries
+//! expire after a time-to-live, and a [`ResourceManager`] that tracks and broadcasts resource usage.
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use darwin_metrics::resource::{Cache, ResourcePool};
+//!
+//! let cache = Cache::with_capacity(Duration::from_secs(60), 2);
+//! assert_eq!(cache.get_or_insert_with("cpu", || 42), 42);
+//! assert_eq!(cache.get_or_insert_with("cpu", || 0), 42);
+//! assert_eq!((cache.hit_count(), cache.miss_count()), (1, 1));
+//!
+//! let pool = ResourcePool::new(4);
+//! {
+//!     let mut buffer = pool.acquire_or_else(|| Vec::<u8>::with_capacity(1024));
+//!     buffer.push(1);
+//! }
+//! // The buffer went back to the pool when its guard was dropped
+//! assert_eq!(pool.available(), 1);
+//! ```
+
 use crate::Error;
-use parking_lot::RwLock;
-use std::collections::HashMap;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, Notify};
 
 struct CacheEntry<T> {
     value: T,
     expires_at: Instant,
+    /// Insertion number, which tells entries expiring at the same instant apart
+    seq: u64,
 }
 
 impl<T> CacheEntry<T> {
-    fn new(value: T, ttl: Duration) -> Self {
-        Self {
-            value,
-            expires_at: Instant::now() + ttl,
+    fn is_expired(&self) -> bool {
+        Instant::now() > self.expires_at
+    }
+
+    fn expiry_key(&self) -> (Instant, u64) {
+        (self.expires_at, self.seq)
+    }
+}
+
+/// Entries of a [`Cache`] with an index of their keys by expiry
+struct CacheEntries<K, V> {
+    map: HashMap<K, CacheEntry<V>>,
+    by_expiry: BTreeMap<(Instant, u64), K>,
+    next_seq: u64,
+}
+
+impl<K, V> CacheEntries<K, V>
+where
+    K: Eq + std::hash::Hash + Clone,
+{
+    fn new() -> Self {
+        Self { map: HashMap::new(), by_expiry: BTreeMap::new(), next_seq: 0 }
+    }
+
+    fn insert(&mut self, key: K, value: V, ttl: Duration) {
+        let entry = CacheEntry { value, expires_at: Instant::now() + ttl, seq: self.next_seq };
+        self.next_seq += 1;
+
+        self.by_expiry.insert(entry.expiry_key(), key.clone());
+        if let Some(previous) = self.map.insert(key, entry) {
+            self.by_expiry.remove(&previous.expiry_key());
         }
     }
 
-    fn is_expired(&self) -> bool {
-        Instant::now() > self.expires_at
+    fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.map.remove(key) {
+            self.by_expiry.remove(&entry.expiry_key());
+        }
+    }
+
+    /// Removes the entry that expires first, returning whether there was one.
+    fn pop_first_expiring(&mut self) -> bool {
+        match self.by_expiry.pop_first() {
+            Some((_, key)) => {
+                self.map.remove(&key);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Removes the expired entries, returning how many there were.
+    fn purge_expired(&mut self) -> usize {
+        let now = Instant::now();
+        let mut purged = 0;
+        while self.by_expiry.first_key_value().is_some_and(|((expires_at, _), _)| now > *expires_at)
+        {
+            self.pop_first_expiring();
+            purged += 1;
+        }
+        purged
+    }
+
+    /// Removes the entries that expire first until at most `max_entries` are left, returning how many were removed.
+    fn evict_to(&mut self, max_entries: usize) -> usize {
+        let mut evicted = 0;
+        while self.map.len() > max_entries && self.pop_first_expiring() {
+            evicted += 1;
+        }
+        evicted
     }
 }
 
+/// A bounded pool of reusable resources
+///
+/// The lock is only held to push or pop a resource, never across an await, so it's a synchronous lock that a
+/// [`PoolGuard`] can also take when it's dropped. It doesn't poison: a thread panicking while it holds the lock leaves
+/// the pool usable for every other thread, and no method panics because of it.
 pub struct ResourcePool<T> {
-    resources: Arc<tokio::sync::Mutex<Vec<T>>>,
+    resources: Arc<Mutex<Vec<T>>>,
     max_size: usize,
+    /// Notified whenever a resource is returned to the pool
+    released: Arc<Notify>,
 }
 
 impl<T> ResourcePool<T> {
     pub fn new(max_size: usize) -> Self {
         Self {
-            resources: Arc::new(tokio::sync::Mutex::new(Vec::with_capacity(max_size))),
+            resources: Arc::new(Mutex::new(Vec::with_capacity(max_size))),
             max_size,
+            released: Arc::new(Notify::new()),
         }
     }
 
     pub async fn acquire(&self) -> Option<T> {
-        let mut resources = self.resources.lock().await;
+        let mut resources = self.resources.lock();
         resources.pop()
     }
 
     pub async fn try_acquire(&self) -> Result<Option<T>, Error> {
         let resources = self.resources.try_lock();
         match resources {
-            Ok(mut res) => Ok(res.pop()),
-            Err(_) => Err(Error::system("Failed to acquire resource: mutex busy")),
+            Some(mut res) => Ok(res.pop()),
+            None => Err(Error::system("Failed to acquire resource: mutex busy")),
         }
     }
 
     pub async fn release(&self, resource: T) -> Result<(), Error> {
-        let mut resources = self.resources.lock().await;
+        self.put_back(resource).map_err(|_| Error::system("Resource pool is full"))
+    }
+
+    /// Takes a resource from the pool, returning it to the pool when the guard is dropped.
+    pub fn acquire_guard(&self) -> Option<PoolGuard<'_, T>> {
+        let resource = self.resources.lock().pop()?;
+        Some(PoolGuard::new(self, resource))
+    }
+
+    /// Takes a resource from the pool, or creates one with `create` if the pool is empty.
+    ///
+    /// The created resource joins the pool when the guard is dropped, if there's room.
+    pub fn acquire_or_else<F>(&self, create: F) -> PoolGuard<'_, T>
+    where
+        F: FnOnce() -> T,
+    {
+        self.acquire_guard().unwrap_or_else(|| PoolGuard::new(self, create()))
+    }
+
+    /// Takes a resource from the pool, waiting up to `timeout` for one to be returned if the pool is empty.
+    pub async fn acquire_timeout(&self, timeout: Duration) -> Option<PoolGuard<'_, T>> {
+        let wait = async {
+            loop {
+                if let Some(guard) = self.acquire_guard() {
+                    return guard;
+                }
+                // A resource returned since the check left a permit, so this doesn't miss it
+                self.released.notified().await;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.ok()
+    }
+
+    /// Number of resources in the pool.
+    pub fn available(&self) -> usize {
+        self.resources.lock().len()
+    }
+
+    /// Returns a resource to the pool, or hands it back if the pool is full.
+    fn put_back(&self, resource: T) -> Result<(), T> {
+        let mut resources = self.resources.lock();
         if resources.len() >= self.max_size {
-            return Err(Error::system("Resource pool is full"));
+            return Err(resource);
         }
         resources.push(resource);
+        drop(resources);
+        self.released.notify_one();
         Ok(())
     }
 }
 
+/// A resource taken from a [`ResourcePool`], returned to it on drop
+///
+/// If the pool filled up in the meantime, the resource is dropped instead.
+pub struct PoolGuard<'a, T> {
+    pool: &'a ResourcePool<T>,
+    resource: Option<T>,
+}
+
+impl<'a, T> PoolGuard<'a, T> {
+    fn new(pool: &'a ResourcePool<T>, resource: T) -> Self {
+        Self { pool, resource: Some(resource) }
+    }
+
+    /// Takes the resource out of the guard, so it isn't returned to the pool.
+    pub fn into_inner(mut self) -> T {
+        self.resource.take().expect("PoolGuard holds its resource until dropped")
+    }
+}
+
+impl<T> Deref for PoolGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.resource.as_ref().expect("PoolGuard holds its resource until dropped")
+    }
+}
+
+impl<T> DerefMut for PoolGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.resource.as_mut().expect("PoolGuard holds its resource until dropped")
+    }
+}
+
+impl<T> Drop for PoolGuard<'_, T> {
+    fn drop(&mut self) {
+        if let Some(resource) = self.resource.take() {
+            // A full pool drops the resource
+            let _ = self.pool.put_back(resource);
+        }
+    }
+}
+
+/// Lookup counters of a [`Cache`]
+#[derive(Default)]
+struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    expired: AtomicU64,
+    evicted: AtomicU64,
+}
+
+/// A map whose entries expire after a time-to-live
+///
+/// A cache created with [`with_capacity`](Self::with_capacity) holds at most `max_entries`: inserting into a full
+/// cache first drops the expired entries, then the entries that expire soonest. Both take O(log n) per entry removed.
 pub struct Cache<K, V>
 where
-    K: Eq + std::hash::Hash,
+    K: Eq + std::hash::Hash + Clone,
     V: Clone,
 {
-    entries: Arc<RwLock<HashMap<K, CacheEntry<V>>>>,
+    entries: Arc<RwLock<CacheEntries<K, V>>>,
     ttl: Duration,
+    max_entries: Option<usize>,
+    stats: CacheStats,
 }
 
 impl<K, V> Cache<K, V>
 where
-    K: Eq + std::hash::Hash,
+    K: Eq + std::hash::Hash + Clone,
     V: Clone,
 {
     pub fn new(ttl: Duration) -> Self {
         Self {
-            entries: Arc::new(RwLock::new(HashMap::new())),
+            entries: Arc::new(RwLock::new(CacheEntries::new())),
             ttl,
+            max_entries: None,
+            stats: CacheStats::default(),
         }
     }
 
+    /// Creates a cache that holds at most `max_entries` entries, at least one.
+    pub fn with_capacity(ttl: Duration, max_entries: usize) -> Self {
+        Self { max_entries: Some(max_entries.max(1)), ..Self::new(ttl) }
+    }
+
     pub fn get(&self, key: &K) -> Option<V> {
         let entries = self.entries.read();
-        entries.get(key).and_then(|entry| {
-            if entry.is_expired() {
+        let value = match entries.map.get(key) {
+            Some(entry) if entry.is_expired() => {
+                self.stats.expired.fetch_add(1, Ordering::Relaxed);
                 None
-            } else {
-                Some(entry.value.clone())
-            }
-        })
+            },
+            Some(entry) => Some(entry.value.clone()),
+            None => None,
+        };
+
+        let counter = if value.is_some() { &self.stats.hits } else { &self.stats.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
     }
 
     pub fn set(&self, key: K, value: V) {
+        self.set_with_ttl(key, value, self.ttl);
+    }
+
+    /// Inserts a value that expires after `ttl` instead of the cache's default TTL.
+    pub fn set_with_ttl(&self, key: K, value: V, ttl: Duration) {
         let mut entries = self.entries.write();
-        entries.insert(key, CacheEntry::new(value, self.ttl));
+        entries.insert(key, value, ttl);
+        self.enforce_capacity(&mut entries);
+    }
+
+    /// Returns the fresh value for `key`, or computes, inserts and returns it.
+    ///
+    /// The lock isn't held while `compute` runs, so it may use the cache itself. Threads that miss at the same time
+    /// may each compute a value, but the check for a fresh entry and the insert happen under one write lock: the first
+    /// value inserted wins and every caller gets it.
+    pub fn get_or_insert_with<F>(&self, key: K, compute: F) -> V
+    where
+        F: FnOnce() -> V,
+    {
+        if let Some(value) = self.get(&key) {
+            return value;
+        }
+        let value = compute();
+        self.insert_if_absent(key, value)
+    }
+
+    /// Like [`get_or_insert_with`](Self::get_or_insert_with), for computations that need to await.
+    ///
+    /// No lock is held across the await.
+    pub async fn get_or_insert_with_async<F, Fut>(&self, key: K, compute: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        if let Some(value) = self.get(&key) {
+            return value;
+        }
+        let value = compute().await;
+        self.insert_if_absent(key, value)
+    }
+
+    /// Inserts `value` unless another caller inserted a fresh value for `key` first, and returns the value kept.
+    fn insert_if_absent(&self, key: K, value: V) -> V {
+        let mut entries = self.entries.write();
+        match entries.map.get(&key) {
+            Some(entry) if !entry.is_expired() => entry.value.clone(),
+            _ => {
+                entries.insert(key, value.clone(), self.ttl);
+                self.enforce_capacity(&mut entries);
+                value
+            },
+        }
+    }
+
+    /// Brings a cache that went over its capacity back to it, expired entries first.
+    fn enforce_capacity(&self, entries: &mut CacheEntries<K, V>) {
+        let Some(max_entries) = self.max_entries else {
+            return;
+        };
+        if entries.map.len() > max_entries {
+            entries.purge_expired();
+            let evicted = entries.evict_to(max_entries);
+            self.stats.evicted.fetch_add(evicted as u64, Ordering::Relaxed);
+        }
     }
 
     pub fn remove(&self, key: &K) {
@@ -101,10 +372,59 @@ where
     }
 
     pub fn clear_expired(&self) {
-        self.entries.write().retain(|_, entry| !entry.is_expired());
+        self.entries.write().purge_expired();
+    }
+
+    /// Purges the expired entries and evicts any over the capacity, returning how many entries were removed.
+    ///
+    /// Meant to be called periodically, e.g. from a maintenance task.
+    pub fn maintain(&self) -> usize {
+        let mut entries = self.entries.write();
+        let before = entries.map.len();
+        entries.purge_expired();
+        self.enforce_capacity(&mut entries);
+        before - entries.map.len()
+    }
+
+    /// Maximum number of entries held, if the cache is bounded.
+    pub fn max_entries(&self) -> Option<usize> {
+        self.max_entries
+    }
+
+    /// Number of entries that haven't expired.
+    pub fn len(&self) -> usize {
+        self.entries.read().map.values().filter(|entry| !entry.is_expired()).count()
+    }
+
+    /// Whether every entry has expired or been removed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of lookups that found a fresh value.
+    pub fn hit_count(&self) -> u64 {
+        self.stats.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of lookups that found no fresh value, including those that found an expired one.
+    pub fn miss_count(&self) -> u64 {
+        self.stats.misses.load(Ordering::Relaxed)
+    }
+
+    /// Number of lookups that found an expired value.
+    pub fn expired_count(&self) -> u64 {
+        self.stats.expired.load(Ordering::Relaxed)
+    }
+
+    /// Number of unexpired entries evicted to stay within the capacity.
+    pub fn eviction_count(&self) -> u64 {
+        self.stats.evicted.load(Ordering::Relaxed)
     }
 }
 
+/// Maximum number of metrics cached by a [`ResourceManager`]
+const METRIC_CACHE_MAX_ENTRIES: usize = 1024;
+
 #[derive(Clone)]
 pub struct ResourceManager {
     metric_cache: Arc<Cache<String, Vec<u8>>>,
@@ -130,7 +450,10 @@ impl ResourceManager {
         let (usage_tx, _) = broadcast::channel(100);
 
         Self {
-            metric_cache: Arc::new(Cache::new(Duration::from_secs(60))),
+            metric_cache: Arc::new(Cache::with_capacity(
+                Duration::from_secs(60),
+                METRIC_CACHE_MAX_ENTRIES,
+            )),
             usage_tx,
             usage_state: Arc::new(RwLock::new(Default::default())),
         }
@@ -149,17 +472,9 @@ impl ResourceManager {
 
         {
             let mut state = self.usage_state.write();
-            *state
-                .active_resources
-                .entry(resource_type.to_string())
-                .or_insert(0) += 1;
-            *state
-                .peak_usage
-                .entry(resource_type.to_string())
-                .or_insert(0.0) = f64::max(
-                state.peak_usage.get(resource_type).unwrap_or(&0.0),
-                usage.usage_percent,
-            );
+            *state.active_resources.entry(resource_type.to_string()).or_insert(0) += 1;
+            let peak = state.peak_usage.entry(resource_type.to_string()).or_insert(0.0);
+            *peak = peak.max(usage.usage_percent);
         }
 
         let _ = self.usage_tx.send(usage);
@@ -174,7 +489,7 @@ impl ResourceManager {
         let result = async { self.track_resource_usage(resource_type, usage).await };
         tokio::time::timeout(timeout, result)
             .await
-            .map_err(|_| Error::system("Resource tracking timeout"))?
+            .map_err(|_| Error::system("Resource tracking timeout"))
     }
 
     pub fn get_cached_metric(&self, key: &str) -> Option<Vec<u8>> {
@@ -190,7 +505,7 @@ impl ResourceManager {
     }
 
     pub fn cleanup_cache(&self) {
-        self.metric_cache.clear_expired();
+        self.metric_cache.maintain();
     }
 }
 
@@ -224,4 +539,191 @@ mod tests {
         let cached = manager.get_cached_metric("test");
         assert_eq!(cached, Some(vec![1, 2, 3]));
     }
+
+    #[test]
+    fn test_cache_get_or_insert_with() {
+        let cache = Cache::new(Duration::from_secs(60));
+        let mut computed = 0;
+
+        assert_eq!(
+            cache.get_or_insert_with("a", || {
+                computed += 1;
+                1
+            }),
+            1
+        );
+        assert_eq!(
+            cache.get_or_insert_with("a", || {
+                computed += 1;
+                2
+            }),
+            1
+        );
+        assert_eq!(computed, 1);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.hit_count(), 1);
+        assert_eq!(cache.miss_count(), 1);
+    }
+
+    #[test]
+    fn test_cache_compute_can_use_cache() {
+        let cache = Cache::new(Duration::from_secs(60));
+        cache.set("base", 20);
+
+        let value = cache.get_or_insert_with("derived", || cache.get(&"base").unwrap_or(0) + 1);
+        assert_eq!(value, 21);
+        let nested =
+            cache.get_or_insert_with("outer", || cache.get_or_insert_with("inner", || 5) * 2);
+        assert_eq!(nested, 10);
+        assert_eq!(cache.get(&"inner"), Some(5));
+    }
+
+    #[test]
+    fn test_cache_per_entry_ttl() {
+        let cache = Cache::new(Duration::from_secs(60));
+        cache.set_with_ttl("short", 1, Duration::ZERO);
+        cache.set("long", 2);
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(cache.get(&"short"), None);
+        assert_eq!(cache.get(&"long"), Some(2));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.expired_count(), 1);
+        assert_eq!(cache.miss_count(), 1);
+
+        assert_eq!(cache.get_or_insert_with("short", || 3), 3);
+        assert_eq!(cache.get(&"short"), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_cache_get_or_insert_with_async() {
+        let cache = Cache::new(Duration::from_secs(60));
+        let value = cache
+            .get_or_insert_with_async("a", || async {
+                tokio::task::yield_now().await;
+                7
+            })
+            .await;
+        assert_eq!(value, 7);
+        assert_eq!(cache.get_or_insert_with_async("a", || async { 8 }).await, 7);
+    }
+
+    #[test]
+    fn test_cache_capacity() {
+        let cache = Cache::with_capacity(Duration::from_secs(60), 2);
+        cache.set_with_ttl("a", 1, Duration::from_secs(30));
+        cache.set("b", 2);
+        cache.set("c", 3);
+
+        // "a" expires first, so it makes room for "c"
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.get(&"b"), Some(2));
+        assert_eq!(cache.get(&"c"), Some(3));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.eviction_count(), 1);
+
+        // Replacing an entry doesn't evict anything
+        cache.set("c", 4);
+        assert_eq!(cache.get(&"b"), Some(2));
+        assert_eq!(cache.eviction_count(), 1);
+    }
+
+    #[test]
+    fn test_cache_evicts_expired_entries_first() {
+        let cache = Cache::with_capacity(Duration::from_secs(60), 2);
+        cache.set("a", 1);
+        cache.set_with_ttl("b", 2, Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(5));
+        cache.set("c", 3);
+
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"c"), Some(3));
+        assert_eq!(cache.eviction_count(), 0);
+    }
+
+    #[test]
+    fn test_cache_maintain() {
+        let cache = Cache::new(Duration::from_secs(60));
+        cache.set("a", 1);
+        cache.set_with_ttl("b", 2, Duration::ZERO);
+        cache.set_with_ttl("c", 3, Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(cache.maintain(), 2);
+        assert_eq!(cache.maintain(), 0);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.max_entries(), None);
+    }
+
+    #[tokio::test]
+    async fn test_pool_guard_returns_resource() {
+        let pool = ResourcePool::new(2);
+        pool.release(1).await.expect("Failed to release resource");
+
+        {
+            let mut guard = pool.acquire_guard().expect("Pool should have a resource");
+            *guard += 10;
+            assert_eq!(pool.available(), 0);
+            assert!(pool.acquire_guard().is_none());
+        }
+        assert_eq!(pool.available(), 1);
+        assert_eq!(pool.acquire().await, Some(11));
+
+        let guard = pool.acquire_or_else(|| 5);
+        assert_eq!(*guard, 5);
+        assert_eq!(guard.into_inner(), 5);
+        assert_eq!(pool.available(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_pool_guard_drops_resource_when_full() {
+        let pool = ResourcePool::new(1);
+        let created = pool.acquire_or_else(|| 1);
+        pool.release(2).await.expect("Failed to release resource");
+
+        drop(created);
+        assert_eq!(pool.available(), 1);
+        assert_eq!(pool.acquire().await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_pool_acquire_timeout() {
+        let pool = Arc::new(ResourcePool::new(1));
+        assert!(pool.acquire_timeout(Duration::from_millis(10)).await.is_none());
+
+        let releaser = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                pool.release(7).await.expect("Failed to release resource");
+            }
+        });
+        let guard = pool
+            .acquire_timeout(Duration::from_secs(5))
+            .await
+            .expect("A released resource should wake the waiter");
+        assert_eq!(*guard, 7);
+        releaser.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pool_survives_panic_while_locked() {
+        let pool = Arc::new(ResourcePool::new(2));
+        pool.release(1).await.expect("Failed to release resource");
+
+        let panicked = std::thread::spawn({
+            let pool = pool.clone();
+            move || {
+                let _resources = pool.resources.lock();
+                panic!("Panicking while holding the pool lock");
+            }
+        })
+        .join();
+        assert!(panicked.is_err());
+
+        assert_eq!(pool.acquire().await, Some(1));
+        pool.release(2).await.expect("Failed to release resource");
+        assert_eq!(pool.try_acquire().await.unwrap(), Some(2));
+        assert!(pool.acquire_guard().is_none());
+    }
 }
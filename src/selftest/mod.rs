@@ -0,0 +1,325 @@
+//! # Self-Test Module
+//!
+//! Compares the readings of this crate against Apple's `powermetrics` tool, as a way to check that the numbers
+//! reported on a given machine are plausible. This is a diagnostic aid for development and bug reports, not a
+//! monitoring API: it spawns `/usr/bin/powermetrics`, which requires root.
+//!
+//! For the duration of the test, CPU power, GPU power, CPU frequency and CPU temperature are sampled through the crate
+//! at the same interval as a `powermetrics` run. Samples are paired in order, and each metric is summarized by the mean
+//! absolute difference between the paired readings and its deviation as a percentage of the `powermetrics` mean.
+//! Metrics that one of the sides doesn't report (such as the CPU temperature on Apple Silicon, which `powermetrics`
+//! doesn't expose) are left out of the report.
+//!
+//! This module is only available with the `selftest` feature.
+//!
+//! ## Example
+//!
+//! ```rust,no_run,ignore
+//! use std::time::Duration;
+//!
+//! use darwin_metrics::selftest::compare_with_powermetrics;
+//!
+//! // powermetrics requires root, so run this with sudo
+//! let report = compare_with_powermetrics(Duration::from_secs(5))?;
+//! for comparison in report.comparisons() {
+//!     println!(
+//!         "{:?}: ours {:.2}, powermetrics {:.2} ({:.1}% deviation){}",
+//!         comparison.metric,
+//!         comparison.ours_mean,
+//!         comparison.reference_mean,
+//!         comparison.deviation_percent,
+//!         if comparison.exceeds_threshold { " !" } else { "" }
+//!     );
+//! }
+//! ```
+
+mod plist;
+mod powermetrics;
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+pub use plist::PlistValue;
+pub use powermetrics::{parse_output, Readings, POWERMETRICS_PATH};
+
+use crate::{
+    error::{Error, Result},
+    hardware::{
+        cpu::CPU,
+        iokit::{IOKit, IOKitImpl},
+        temperature::{Temperature, TemperatureConfig},
+    },
+    power::Power,
+};
+
+/// Default interval between samples
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A metric compared by the self-test
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SelfTestMetric {
+    /// CPU power in watts
+    CpuPower,
+    /// GPU power in watts
+    GpuPower,
+    /// Mean CPU frequency in MHz
+    CpuFrequency,
+    /// CPU temperature in degrees Celsius
+    CpuTemperature,
+}
+
+impl SelfTestMetric {
+    /// All compared metrics, in report order.
+    pub const ALL: [SelfTestMetric; 4] =
+        [Self::CpuPower, Self::GpuPower, Self::CpuFrequency, Self::CpuTemperature];
+}
+
+/// Maximum accepted deviation of each metric from `powermetrics`, in percent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviationThresholds {
+    /// Threshold for [`SelfTestMetric::CpuPower`]
+    pub cpu_power: f64,
+    /// Threshold for [`SelfTestMetric::GpuPower`]
+    pub gpu_power: f64,
+    /// Threshold for [`SelfTestMetric::CpuFrequency`]
+    pub cpu_frequency: f64,
+    /// Threshold for [`SelfTestMetric::CpuTemperature`]
+    pub cpu_temperature: f64,
+}
+
+impl Default for DeviationThresholds {
+    fn default() -> Self {
+        // Power and frequency fluctuate within a sampling interval, so they're given more slack than temperature
+        Self { cpu_power: 20.0, gpu_power: 30.0, cpu_frequency: 15.0, cpu_temperature: 10.0 }
+    }
+}
+
+impl DeviationThresholds {
+    /// The threshold for `metric`.
+    pub fn for_metric(&self, metric: SelfTestMetric) -> f64 {
+        match metric {
+            SelfTestMetric::CpuPower => self.cpu_power,
+            SelfTestMetric::GpuPower => self.gpu_power,
+            SelfTestMetric::CpuFrequency => self.cpu_frequency,
+            SelfTestMetric::CpuTemperature => self.cpu_temperature,
+        }
+    }
+}
+
+/// Options of a self-test run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelfTestOptions {
+    /// Interval between samples
+    pub interval: Duration,
+    /// Deviations above which a metric is flagged
+    pub thresholds: DeviationThresholds,
+}
+
+impl Default for SelfTestOptions {
+    fn default() -> Self {
+        Self { interval: DEFAULT_INTERVAL, thresholds: DeviationThresholds::default() }
+    }
+}
+
+/// Comparison of one metric between the crate and `powermetrics`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetricComparison {
+    /// The compared metric
+    pub metric: SelfTestMetric,
+    /// Number of paired samples
+    pub samples: usize,
+    /// Mean of the crate's readings
+    pub ours_mean: f64,
+    /// Mean of the `powermetrics` readings
+    pub reference_mean: f64,
+    /// Mean absolute difference between paired readings
+    pub mean_abs_diff: f64,
+    /// Mean absolute difference as a percentage of the `powermetrics` mean
+    pub deviation_percent: f64,
+    /// Whether the deviation exceeds the configured threshold
+    pub exceeds_threshold: bool,
+}
+
+/// Result of comparing the crate's readings against `powermetrics`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComparisonReport {
+    comparisons: Vec<MetricComparison>,
+    duration: Duration,
+}
+
+impl ComparisonReport {
+    /// Builds a report from the paired samples of both sides.
+    pub fn from_samples(
+        ours: &[Readings],
+        reference: &[Readings],
+        thresholds: &DeviationThresholds,
+        duration: Duration,
+    ) -> Self {
+        let comparisons = SelfTestMetric::ALL
+            .iter()
+            .filter_map(|&metric| {
+                let value = |sample: &Readings| match metric {
+                    SelfTestMetric::CpuPower => sample.cpu_power,
+                    SelfTestMetric::GpuPower => sample.gpu_power,
+                    SelfTestMetric::CpuFrequency => sample.cpu_frequency,
+                    SelfTestMetric::CpuTemperature => sample.cpu_temperature,
+                };
+                let pairs: Vec<(f64, f64)> = ours
+                    .iter()
+                    .zip(reference)
+                    .filter_map(|(a, b)| Some((value(a)?, value(b)?)))
+                    .collect();
+                compare(metric, &pairs, thresholds.for_metric(metric))
+            })
+            .collect();
+
+        Self { comparisons, duration }
+    }
+
+    /// The metrics both sides reported, in [`SelfTestMetric::ALL`] order.
+    pub fn comparisons(&self) -> &[MetricComparison] {
+        &self.comparisons
+    }
+
+    /// The comparison of `metric`, if both sides reported it.
+    pub fn get(&self, metric: SelfTestMetric) -> Option<&MetricComparison> {
+        self.comparisons.iter().find(|comparison| comparison.metric == metric)
+    }
+
+    /// The metrics whose deviation exceeds its threshold.
+    pub fn flagged(&self) -> impl Iterator<Item = &MetricComparison> {
+        self.comparisons.iter().filter(|comparison| comparison.exceeds_threshold)
+    }
+
+    /// Whether every compared metric is within its threshold.
+    pub fn passed(&self) -> bool {
+        self.flagged().next().is_none()
+    }
+
+    /// How long the comparison ran.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+/// Summarizes `(ours, reference)` pairs of a metric, or returns `None` if there are none.
+pub(crate) fn compare(
+    metric: SelfTestMetric,
+    pairs: &[(f64, f64)],
+    threshold_percent: f64,
+) -> Option<MetricComparison> {
+    if pairs.is_empty() {
+        return None;
+    }
+
+    let count = pairs.len() as f64;
+    let ours_mean = pairs.iter().map(|(ours, _)| ours).sum::<f64>() / count;
+    let reference_mean = pairs.iter().map(|(_, reference)| reference).sum::<f64>() / count;
+    let mean_abs_diff =
+        pairs.iter().map(|(ours, reference)| (ours - reference).abs()).sum::<f64>() / count;
+
+    let deviation_percent = if reference_mean.abs() > f64::EPSILON {
+        mean_abs_diff / reference_mean.abs() * 100.0
+    } else if mean_abs_diff > f64::EPSILON {
+        // Any reading is infinitely far off a reference of zero
+        f64::INFINITY
+    } else {
+        0.0
+    };
+
+    Some(MetricComparison {
+        metric,
+        samples: pairs.len(),
+        ours_mean,
+        reference_mean,
+        mean_abs_diff,
+        deviation_percent,
+        exceeds_threshold: deviation_percent > threshold_percent,
+    })
+}
+
+/// Compares the crate's readings against `powermetrics` for `duration`, with the default options.
+///
+/// # Errors
+///
+/// See [`compare_with_powermetrics_with`].
+pub fn compare_with_powermetrics(duration: Duration) -> Result<ComparisonReport> {
+    compare_with_powermetrics_with(duration, &SelfTestOptions::default())
+}
+
+/// Compares the crate's readings against `powermetrics` for `duration`.
+///
+/// # Errors
+///
+/// Returns [`Error::PermissionDenied`] when not running as root, [`Error::NotAvailable`] if `powermetrics` isn't
+/// installed, and an error if `powermetrics` fails or its output can't be parsed.
+pub fn compare_with_powermetrics_with(
+    duration: Duration,
+    options: &SelfTestOptions,
+) -> Result<ComparisonReport> {
+    // SAFETY: geteuid has no preconditions.
+    if unsafe { libc::geteuid() } != 0 {
+        return Err(Error::permission_denied(format!(
+            "{POWERMETRICS_PATH} requires root; re-run the self-test with sudo"
+        )));
+    }
+    if options.interval.is_zero() {
+        return Err(Error::invalid_data("Self-test interval must be greater than zero"));
+    }
+
+    let count = (duration.as_millis() / options.interval.as_millis()).max(1) as usize;
+    let start = Instant::now();
+    let child = powermetrics::spawn(options.interval, count)?;
+
+    let ours = sample_crate(options.interval, count);
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| Error::system(format!("Failed to read powermetrics output: {e}")))?;
+    if !output.status.success() {
+        return Err(Error::system(format!(
+            "powermetrics exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let reference = parse_output(&output.stdout)?;
+
+    Ok(ComparisonReport::from_samples(&ours, &reference, &options.thresholds, start.elapsed()))
+}
+
+/// Takes `count` readings through the crate, `interval` apart.
+///
+/// Like `powermetrics`, each reading is taken at the end of its interval. Readings the crate can't take on this
+/// machine are left empty.
+fn sample_crate(interval: Duration, count: usize) -> Vec<Readings> {
+    // One IOKit instance shared by every monitor taking readings
+    let iokit: Arc<dyn IOKit> = Arc::new(IOKitImpl::default());
+    let power = Power::with_shared_iokit(Arc::clone(&iokit));
+    let mut cpu = CPU::with_shared_iokit(Arc::clone(&iokit)).ok();
+    let mut temperature = Temperature::with_iokit(iokit, TemperatureConfig::default());
+
+    (0..count)
+        .map(|_| {
+            std::thread::sleep(interval);
+
+            let consumption = power.get_power_consumption().ok();
+            let cpu_frequency = cpu.as_mut().and_then(|cpu| {
+                cpu.update().ok()?;
+                Some(cpu.frequency_mhz())
+            });
+
+            Readings {
+                cpu_power: consumption.as_ref().map(|c| c.cores as f64),
+                gpu_power: consumption.as_ref().and_then(|c| c.gpu).map(f64::from),
+                cpu_frequency,
+                cpu_temperature: temperature.cpu_temperature().ok(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,256 @@
+//! Minimal parser for XML property lists
+//!
+//! Only the subset that `powermetrics -f plist` emits is supported: dictionaries, arrays, strings, numbers, booleans,
+//! dates and data. Keeping it in Rust lets the fixture tests run without Foundation.
+
+use crate::error::{Error, Result};
+
+/// A value of a property list
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlistValue {
+    /// `<dict>`, with its entries in document order
+    Dict(Vec<(String, PlistValue)>),
+    /// `<array>`
+    Array(Vec<PlistValue>),
+    /// `<string>`
+    String(String),
+    /// `<integer>`
+    Integer(i64),
+    /// `<real>`
+    Real(f64),
+    /// `<true/>` or `<false/>`
+    Bool(bool),
+    /// `<date>`, as its ISO 8601 text
+    Date(String),
+    /// `<data>`, as its base64 text
+    Data(String),
+}
+
+impl PlistValue {
+    /// Looks up `key` if this is a dictionary.
+    pub fn get(&self, key: &str) -> Option<&PlistValue> {
+        match self {
+            Self::Dict(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Follows a path of dictionary keys.
+    pub fn path(&self, keys: &[&str]) -> Option<&PlistValue> {
+        keys.iter().try_fold(self, |value, key| value.get(key))
+    }
+
+    /// The value as a number, if it's an integer or a real.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Integer(value) => Some(*value as f64),
+            Self::Real(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The value as a string slice, if it's a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The elements, if this is an array.
+    pub fn as_array(&self) -> Option<&[PlistValue]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Parses an XML property list document.
+///
+/// # Errors
+///
+/// Returns an error if the document is malformed or uses elements outside the supported subset.
+pub fn parse(document: &str) -> Result<PlistValue> {
+    let mut parser = Parser { tokens: tokenize(document)?.into_iter().peekable() };
+
+    match parser.next() {
+        Some(Token::Open(tag)) if tag == "plist" => {},
+        _ => return Err(Error::invalid_data("Property list is missing its <plist> element")),
+    }
+    let value = parser.value()?;
+    parser.expect_close("plist")?;
+
+    Ok(value)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open(String),
+    Close(String),
+    Empty(String),
+    Text(String),
+}
+
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        self.tokens.next()
+    }
+
+    fn value(&mut self) -> Result<PlistValue> {
+        match self.next() {
+            Some(Token::Open(tag)) => self.element(&tag),
+            Some(Token::Empty(tag)) => match tag.as_str() {
+                "true" => Ok(PlistValue::Bool(true)),
+                "false" => Ok(PlistValue::Bool(false)),
+                "dict" => Ok(PlistValue::Dict(Vec::new())),
+                "array" => Ok(PlistValue::Array(Vec::new())),
+                "string" => Ok(PlistValue::String(String::new())),
+                "data" => Ok(PlistValue::Data(String::new())),
+                _ => {
+                    Err(Error::invalid_data(format!("Unsupported property list element <{tag}/>")))
+                },
+            },
+            other => {
+                Err(Error::invalid_data(format!("Expected a property list value, found {other:?}")))
+            },
+        }
+    }
+
+    fn element(&mut self, tag: &str) -> Result<PlistValue> {
+        let value = match tag {
+            "dict" => {
+                let mut entries = Vec::new();
+                while !matches!(self.tokens.peek(), Some(Token::Close(_))) {
+                    self.expect_open("key")?;
+                    let key = self.text();
+                    self.expect_close("key")?;
+                    entries.push((key, self.value()?));
+                }
+                PlistValue::Dict(entries)
+            },
+            "array" => {
+                let mut values = Vec::new();
+                while !matches!(self.tokens.peek(), Some(Token::Close(_))) {
+                    values.push(self.value()?);
+                }
+                PlistValue::Array(values)
+            },
+            "string" => PlistValue::String(self.text()),
+            "date" => PlistValue::Date(self.text()),
+            "data" => PlistValue::Data(self.text().split_whitespace().collect()),
+            "integer" => {
+                let text = self.text();
+                PlistValue::Integer(text.trim().parse().map_err(|_| {
+                    Error::invalid_data(format!("Invalid property list integer: {text}"))
+                })?)
+            },
+            "real" => {
+                let text = self.text();
+                PlistValue::Real(text.trim().parse().map_err(|_| {
+                    Error::invalid_data(format!("Invalid property list real: {text}"))
+                })?)
+            },
+            _ => {
+                return Err(Error::invalid_data(format!(
+                    "Unsupported property list element <{tag}>"
+                )))
+            },
+        };
+
+        self.expect_close(tag)?;
+        Ok(value)
+    }
+
+    /// Consumes the text content of an element, which is empty if the element closes right away.
+    fn text(&mut self) -> String {
+        match self.tokens.peek() {
+            Some(Token::Text(_)) => match self.next() {
+                Some(Token::Text(text)) => text,
+                _ => unreachable!("peeked a text token"),
+            },
+            _ => String::new(),
+        }
+    }
+
+    fn expect_open(&mut self, expected: &str) -> Result<()> {
+        match self.next() {
+            Some(Token::Open(tag)) if tag == expected => Ok(()),
+            other => Err(Error::invalid_data(format!("Expected <{expected}>, found {other:?}"))),
+        }
+    }
+
+    fn expect_close(&mut self, expected: &str) -> Result<()> {
+        match self.next() {
+            Some(Token::Close(tag)) if tag == expected => Ok(()),
+            other => Err(Error::invalid_data(format!("Expected </{expected}>, found {other:?}"))),
+        }
+    }
+}
+
+/// Splits a document into tags and text, dropping the prolog, comments and whitespace between elements.
+fn tokenize(document: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = document;
+
+    while let Some(start) = rest.find('<') {
+        let text = &rest[..start];
+        if !text.trim().is_empty() {
+            tokens.push(Token::Text(unescape(text)));
+        }
+        rest = &rest[start..];
+
+        // Prolog, doctype and comments carry no values
+        let skip_until = if rest.starts_with("<?") {
+            Some("?>")
+        } else if rest.starts_with("<!--") {
+            Some("-->")
+        } else if rest.starts_with("<!") {
+            Some(">")
+        } else {
+            None
+        };
+        if let Some(terminator) = skip_until {
+            let end = rest
+                .find(terminator)
+                .ok_or_else(|| Error::invalid_data("Unterminated property list declaration"))?;
+            rest = &rest[end + terminator.len()..];
+            continue;
+        }
+
+        let end =
+            rest.find('>').ok_or_else(|| Error::invalid_data("Unterminated property list tag"))?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            tokens.push(Token::Close(name.trim().to_string()));
+        } else if let Some(tag) = tag.strip_suffix('/') {
+            tokens.push(Token::Empty(tag_name(tag)));
+        } else {
+            tokens.push(Token::Open(tag_name(tag)));
+        }
+    }
+
+    if !rest.trim().is_empty() {
+        return Err(Error::invalid_data("Unexpected text after the property list"));
+    }
+    Ok(tokens)
+}
+
+/// The element name of a tag, without its attributes.
+fn tag_name(tag: &str) -> String {
+    tag.split_whitespace().next().unwrap_or_default().to_string()
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
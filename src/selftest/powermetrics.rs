@@ -0,0 +1,108 @@
+//! Running `powermetrics` and extracting the metrics the self-test compares
+
+use std::{
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use super::plist::{self, PlistValue};
+use crate::error::{Error, Result};
+
+/// Location of the `powermetrics` tool
+pub const POWERMETRICS_PATH: &str = "/usr/bin/powermetrics";
+
+/// Samplers providing CPU/GPU power and frequency on every Mac
+const SAMPLERS: &str = "cpu_power,gpu_power";
+/// Intel Macs also report the CPU die temperature through the SMC sampler
+#[cfg(target_arch = "x86_64")]
+const SMC_SAMPLER: &str = ",smc";
+#[cfg(not(target_arch = "x86_64"))]
+const SMC_SAMPLER: &str = "";
+
+/// Readings of the compared metrics at one point in time, in the units the crate reports them in
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Readings {
+    /// CPU power in watts
+    pub cpu_power: Option<f64>,
+    /// GPU power in watts
+    pub gpu_power: Option<f64>,
+    /// Mean CPU frequency across cores in MHz
+    pub cpu_frequency: Option<f64>,
+    /// CPU die temperature in degrees Celsius (Intel only)
+    pub cpu_temperature: Option<f64>,
+}
+
+impl Readings {
+    /// Extracts the compared metrics from a parsed `powermetrics` sample.
+    ///
+    /// Apple Silicon reports power in milliwatts per component and frequencies per core, while Intel reports the
+    /// package power in watts, a single package frequency and, with the `smc` sampler, the die temperature.
+    pub fn from_plist(sample: &PlistValue) -> Self {
+        let number = |keys: &[&str]| sample.path(keys).and_then(PlistValue::as_f64);
+
+        let cpu_power = number(&["processor", "cpu_power"])
+            .map(|milliwatts| milliwatts / 1000.0)
+            .or_else(|| number(&["processor", "package_watts"]));
+        let gpu_power = number(&["processor", "gpu_power"]).map(|milliwatts| milliwatts / 1000.0);
+
+        let core_frequencies: Vec<f64> = sample
+            .path(&["processor", "clusters"])
+            .and_then(PlistValue::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|cluster| cluster.get("cpus").and_then(PlistValue::as_array))
+            .flatten()
+            .filter_map(|cpu| cpu.get("freq_hz").and_then(PlistValue::as_f64))
+            .collect();
+        let cpu_frequency = if core_frequencies.is_empty() {
+            number(&["processor", "freq_hz"])
+        } else {
+            Some(core_frequencies.iter().sum::<f64>() / core_frequencies.len() as f64)
+        }
+        .map(|hz| hz / 1_000_000.0);
+
+        let cpu_temperature = number(&["smc", "cpu_die"]);
+
+        Self { cpu_power, gpu_power, cpu_frequency, cpu_temperature }
+    }
+}
+
+/// Parses the output of `powermetrics -f plist`, which is a series of plist documents separated by NUL bytes.
+///
+/// # Errors
+///
+/// Returns an error if the output isn't UTF-8 or any of the documents is malformed.
+pub fn parse_output(output: &[u8]) -> Result<Vec<Readings>> {
+    let output = std::str::from_utf8(output)
+        .map_err(|_| Error::invalid_data("powermetrics output is not valid UTF-8"))?;
+
+    output
+        .split('\0')
+        .filter(|document| !document.trim().is_empty())
+        .map(|document| plist::parse(document).map(|sample| Readings::from_plist(&sample)))
+        .collect()
+}
+
+/// Spawns `powermetrics` to take `count` samples, `interval` apart, in plist format.
+///
+/// # Errors
+///
+/// Returns an error if `powermetrics` isn't installed or can't be started.
+pub(crate) fn spawn(interval: Duration, count: usize) -> Result<std::process::Child> {
+    Command::new(POWERMETRICS_PATH)
+        .args(["-f", "plist", "--samplers"])
+        .arg(format!("{SAMPLERS}{SMC_SAMPLER}"))
+        .arg("-i")
+        .arg(interval.as_millis().to_string())
+        .arg("-n")
+        .arg(count.to_string())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                Error::not_available(format!("{POWERMETRICS_PATH} is not installed"))
+            },
+            _ => Error::system(format!("Failed to start {POWERMETRICS_PATH}: {e}")),
+        })
+}
@@ -0,0 +1,206 @@
+use std::time::Duration;
+
+use super::*;
+
+const APPLE_SILICON_OUTPUT: &str = include_str!("fixtures/apple_silicon.plist");
+const INTEL_OUTPUT: &str = include_str!("fixtures/intel.plist");
+
+fn assert_close(actual: Option<f64>, expected: f64) {
+    let actual = actual.expect("metric should be present");
+    assert!((actual - expected).abs() < 1e-9, "expected {expected}, got {actual}");
+}
+
+fn readings(cpu_power: f64, gpu_power: Option<f64>) -> Readings {
+    Readings { cpu_power: Some(cpu_power), gpu_power, ..Readings::default() }
+}
+
+#[test]
+fn test_plist_parses_values() {
+    let document = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <!-- comments are ignored -->
+    <key>name</key>
+    <string>P&amp;E &lt;cluster&gt;</string>
+    <key>empty</key>
+    <string></string>
+    <key>count</key>
+    <integer>-3</integer>
+    <key>ratio</key>
+    <real>0.25</real>
+    <key>enabled</key>
+    <true/>
+    <key>list</key>
+    <array>
+        <integer>1</integer>
+        <dict/>
+    </array>
+</dict>
+</plist>"#;
+
+    let value = plist::parse(document).unwrap();
+    assert_eq!(value.get("name").and_then(PlistValue::as_str), Some("P&E <cluster>"));
+    assert_eq!(value.get("empty").and_then(PlistValue::as_str), Some(""));
+    assert_eq!(value.get("count"), Some(&PlistValue::Integer(-3)));
+    assert_eq!(value.get("ratio").and_then(PlistValue::as_f64), Some(0.25));
+    assert_eq!(value.get("enabled"), Some(&PlistValue::Bool(true)));
+    assert_eq!(
+        value.get("list").and_then(PlistValue::as_array),
+        Some(&[PlistValue::Integer(1), PlistValue::Dict(Vec::new())][..])
+    );
+    assert_eq!(value.path(&["list", "missing"]), None);
+}
+
+#[test]
+fn test_plist_rejects_malformed_documents() {
+    assert!(plist::parse("").is_err());
+    assert!(plist::parse("<dict></dict>").is_err());
+    assert!(plist::parse("<plist><dict><key>a</key></dict></plist>").is_err());
+    assert!(plist::parse("<plist><integer>ten</integer></plist>").is_err());
+    assert!(plist::parse("<plist><string>open</plist>").is_err());
+    assert!(plist::parse("<plist><set/></plist>").is_err());
+}
+
+#[test]
+fn test_parse_apple_silicon_output() {
+    let samples = parse_output(APPLE_SILICON_OUTPUT.as_bytes()).unwrap();
+    assert_eq!(samples.len(), 2);
+
+    // Power is reported in milliwatts, frequencies per core in Hz
+    assert_close(samples[0].cpu_power, 1.25);
+    assert_close(samples[0].gpu_power, 0.0625);
+    assert_close(samples[0].cpu_frequency, (1020.0 + 2424.0) / 2.0);
+    assert_eq!(samples[0].cpu_temperature, None);
+
+    assert_close(samples[1].cpu_power, 2.75);
+    assert_close(samples[1].cpu_frequency, (972.0 + 3204.0) / 2.0);
+}
+
+#[test]
+fn test_parse_intel_output() {
+    let samples = parse_output(INTEL_OUTPUT.as_bytes()).unwrap();
+    assert_eq!(samples.len(), 2);
+
+    assert_close(samples[0].cpu_power, 8.25);
+    assert_eq!(samples[0].gpu_power, None);
+    assert_close(samples[0].cpu_frequency, 2600.0);
+    assert_close(samples[0].cpu_temperature, 61.5);
+    assert_close(samples[1].cpu_temperature, 62.5);
+}
+
+#[test]
+fn test_parse_output_errors() {
+    assert!(parse_output(&[0xff, 0xfe]).is_err());
+    assert!(parse_output(b"<plist><dict>\0").is_err());
+    assert_eq!(parse_output(b"\0\n").unwrap(), Vec::new());
+}
+
+#[test]
+fn test_compare_math() {
+    let comparison = compare(SelfTestMetric::CpuPower, &[(1.0, 2.0), (3.0, 2.0)], 20.0).unwrap();
+
+    assert_eq!(comparison.samples, 2);
+    assert_eq!(comparison.ours_mean, 2.0);
+    assert_eq!(comparison.reference_mean, 2.0);
+    // Differences that cancel out in the means still count
+    assert_eq!(comparison.mean_abs_diff, 1.0);
+    assert_eq!(comparison.deviation_percent, 50.0);
+    assert!(comparison.exceeds_threshold);
+
+    let close = compare(SelfTestMetric::CpuPower, &[(2.1, 2.0)], 20.0).unwrap();
+    assert!((close.deviation_percent - 5.0).abs() < 1e-9);
+    assert!(!close.exceeds_threshold);
+
+    assert_eq!(compare(SelfTestMetric::CpuPower, &[], 20.0), None);
+}
+
+#[test]
+fn test_compare_zero_reference() {
+    let idle = compare(SelfTestMetric::GpuPower, &[(0.0, 0.0)], 30.0).unwrap();
+    assert_eq!(idle.deviation_percent, 0.0);
+    assert!(!idle.exceeds_threshold);
+
+    let off = compare(SelfTestMetric::GpuPower, &[(0.5, 0.0)], 30.0).unwrap();
+    assert!(off.deviation_percent.is_infinite());
+    assert!(off.exceeds_threshold);
+}
+
+#[test]
+fn test_report_from_fixture() {
+    let reference = parse_output(APPLE_SILICON_OUTPUT.as_bytes()).unwrap();
+    // GPU power is missing from our first sample, and the CPU temperature from powermetrics entirely
+    let ours = vec![
+        Readings { cpu_temperature: Some(45.0), ..readings(1.5, None) },
+        Readings { cpu_temperature: Some(46.0), ..readings(2.5, Some(0.04)) },
+    ];
+
+    let report = ComparisonReport::from_samples(
+        &ours,
+        &reference,
+        &DeviationThresholds::default(),
+        Duration::from_secs(2),
+    );
+
+    let metrics: Vec<_> = report.comparisons().iter().map(|c| c.metric).collect();
+    assert_eq!(metrics, vec![SelfTestMetric::CpuPower, SelfTestMetric::GpuPower]);
+
+    let cpu = report.get(SelfTestMetric::CpuPower).unwrap();
+    assert_eq!(cpu.samples, 2);
+    assert!((cpu.mean_abs_diff - 0.25).abs() < 1e-9);
+    assert!((cpu.deviation_percent - 12.5).abs() < 1e-9);
+    assert!(!cpu.exceeds_threshold);
+
+    let gpu = report.get(SelfTestMetric::GpuPower).unwrap();
+    assert_eq!(gpu.samples, 1);
+    assert!((gpu.mean_abs_diff - 0.0025).abs() < 1e-9);
+    assert!(!gpu.exceeds_threshold);
+
+    assert_eq!(report.get(SelfTestMetric::CpuTemperature), None);
+    assert_eq!(report.duration(), Duration::from_secs(2));
+}
+
+#[test]
+fn test_report_flags_metrics_over_threshold() {
+    let reference = vec![readings(10.0, Some(1.0))];
+    let ours = vec![readings(10.5, Some(2.0))];
+    let thresholds = DeviationThresholds { gpu_power: 150.0, ..DeviationThresholds::default() };
+
+    let strict = ComparisonReport::from_samples(
+        &ours,
+        &reference,
+        &DeviationThresholds::default(),
+        Duration::ZERO,
+    );
+    assert!(!strict.passed());
+    let flagged: Vec<_> = strict.flagged().map(|c| c.metric).collect();
+    assert_eq!(flagged, vec![SelfTestMetric::GpuPower]);
+
+    let relaxed = ComparisonReport::from_samples(&ours, &reference, &thresholds, Duration::ZERO);
+    assert!(relaxed.passed());
+}
+
+#[test]
+#[ignore = "requires root and runs powermetrics"]
+fn test_compare_with_powermetrics_end_to_end() {
+    let options =
+        SelfTestOptions { interval: Duration::from_millis(500), ..SelfTestOptions::default() };
+    let report = compare_with_powermetrics_with(Duration::from_secs(2), &options).unwrap();
+
+    assert!(report.get(SelfTestMetric::CpuPower).is_some());
+    for comparison in report.comparisons() {
+        assert!(comparison.samples > 0);
+        assert!(comparison.reference_mean.is_finite());
+    }
+}
+
+#[test]
+fn test_compare_requires_root() {
+    // SAFETY: geteuid has no preconditions.
+    if unsafe { libc::geteuid() } == 0 {
+        return;
+    }
+
+    let err = compare_with_powermetrics(Duration::from_secs(1)).unwrap_err();
+    assert!(err.is_permission_error());
+}
@@ -0,0 +1,386 @@
+//! # Shutdown
+//!
+//! Some features keep working in the background after the call that started them returns: the memory pressure monitor
+//! of [`Memory::start_monitoring`], [`TemperatureWatcher`], [`PowerModeWatcher`] and [`GpuSampler`] run as tokio tasks,
+//! the `metrics-facade` integration samples on its own thread, the `DiskWatcher` of the `disk-events` feature runs both
+//! a task and a DiskArbitration thread, and [`Battery::subscribe_events`] listens for power source notifications on a
+//! thread. [`shutdown`] stops all of them at once, so an application can wind the crate down before it exits instead of
+//! racing detached threads against process teardown:
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! let report = darwin_metrics::shutdown(Duration::from_secs(2));
+//! if !report.all_stopped() {
+//!     eprintln!("{report:?}");
+//! }
+//! ```
+//!
+//! [`ShutdownGuard`] does the same when it goes out of scope.
+//!
+//! Shutting down doesn't disable the crate; components started afterwards run normally and are stopped by the next
+//! shutdown.
+//!
+//! ## Background components
+//!
+//! Every thread or task the crate spawns must register itself, so that a shutdown reaches it:
+//!
+//! 1. Before spawning, create a handle with `shutdown::register(name)`. The registry only keeps a weak reference, so a
+//!    component that ended and dropped its handle disappears from it.
+//! 2. Move the handle into the thread or task. Threads wait with `ComponentHandle::wait_for_stop` instead of sleeping,
+//!    tasks select on `ComponentHandle::stop_requested` next to their timer. Either returns as soon as a stop is
+//!    requested.
+//! 3. Hold the guard of `ComponentHandle::running` for as long as the loop runs; dropping it marks the component as
+//!    stopped, also when the loop panics.
+//! 4. Tasks pass their abort handle to `ComponentHandle::set_abort`, so that a shutdown can abort them when they don't
+//!    stop in time. Threads can't be aborted and are reported as [`ShutdownOutcome::TimedOut`] instead.
+//!
+//! The user-facing handle of the component, e.g. `MetricsFacadeHandle`, should stop it through the same
+//! `ComponentHandle::request_stop`.
+//!
+//! [`Memory::start_monitoring`]: crate::hardware::memory::Memory::start_monitoring
+//! [`TemperatureWatcher`]: crate::hardware::temperature::TemperatureWatcher
+//! [`PowerModeWatcher`]: crate::power::PowerModeWatcher
+//! [`GpuSampler`]: crate::hardware::gpu::GpuSampler
+//! [`Battery::subscribe_events`]: crate::battery::Battery::subscribe_events
+
+use std::{
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use parking_lot::{Condvar, Mutex};
+use tokio::sync::Notify;
+
+/// Serializes tests that start background components against the tests that shut them all down
+#[cfg(test)]
+pub(crate) static TEST_SERIAL: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Background components that are running or were running when last checked
+static REGISTRY: Lazy<Mutex<Vec<Weak<ComponentHandle>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// How a background component ended during a [`shutdown`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownOutcome {
+    /// The component stopped within the timeout
+    Stopped,
+    /// The component didn't stop within the timeout and its task was aborted
+    Aborted,
+    /// The component didn't stop within the timeout and can't be aborted; it's still running
+    TimedOut,
+}
+
+/// Outcome of one background component
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentReport {
+    /// Name of the component, e.g. `memory-pressure-monitor`
+    pub name: String,
+    /// How the component ended
+    pub outcome: ShutdownOutcome,
+}
+
+/// Result of a [`shutdown`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Every component that was running, in the order they were started
+    pub components: Vec<ComponentReport>,
+    /// Time the shutdown took
+    pub elapsed: Duration,
+}
+
+impl ShutdownReport {
+    /// Whether every component stopped on its own within the timeout.
+    pub fn all_stopped(&self) -> bool {
+        self.components.iter().all(|component| component.outcome == ShutdownOutcome::Stopped)
+    }
+}
+
+/// Stops every background component started by the crate.
+///
+/// Asks all components to stop, waits up to `timeout` for them, and aborts the tasks that are still running. Safe to
+/// call any number of times and from any thread; with nothing running it returns an empty report right away.
+pub fn shutdown(timeout: Duration) -> ShutdownReport {
+    let start = Instant::now();
+    let deadline = start + timeout;
+
+    let components: Vec<Arc<ComponentHandle>> = {
+        let mut registry = REGISTRY.lock();
+        registry.retain(|component| component.strong_count() > 0);
+        registry
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|component| !component.is_stopped())
+            .collect()
+    };
+
+    for component in &components {
+        component.request_stop();
+    }
+
+    let components = components
+        .iter()
+        .map(|component| {
+            let outcome = if component.wait_until_stopped(deadline) {
+                ShutdownOutcome::Stopped
+            } else if component.abort() {
+                ShutdownOutcome::Aborted
+            } else {
+                tracing::warn!(
+                    "Background component {} didn't stop within {timeout:?}",
+                    component.name
+                );
+                ShutdownOutcome::TimedOut
+            };
+            ComponentReport { name: component.name.clone(), outcome }
+        })
+        .collect();
+
+    ShutdownReport { components, elapsed: start.elapsed() }
+}
+
+/// Calls [`shutdown`] when dropped
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// use darwin_metrics::ShutdownGuard;
+///
+/// let _guard = ShutdownGuard::new(Duration::from_secs(2));
+/// // start monitors; they're stopped when `_guard` goes out of scope
+/// ```
+#[derive(Debug)]
+#[must_use = "the shutdown runs when the guard is dropped"]
+pub struct ShutdownGuard {
+    timeout: Duration,
+}
+
+impl ShutdownGuard {
+    /// Creates a guard that waits up to `timeout` for the components to stop.
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        let report = shutdown(self.timeout);
+        if !report.all_stopped() {
+            tracing::warn!("Not every background component stopped: {:?}", report.components);
+        }
+    }
+}
+
+/// Registers a background component, returning the handle to move into it.
+pub(crate) fn register(name: impl Into<String>) -> Arc<ComponentHandle> {
+    let component = Arc::new(ComponentHandle {
+        name: name.into(),
+        stop: Mutex::new(false),
+        stop_changed: Condvar::new(),
+        stop_notify: Notify::new(),
+        stopped: Mutex::new(false),
+        stopped_changed: Condvar::new(),
+        abort: Mutex::new(None),
+    });
+
+    let mut registry = REGISTRY.lock();
+    registry.retain(|component| component.strong_count() > 0);
+    registry.push(Arc::downgrade(&component));
+
+    component
+}
+
+/// Number of registered background components that are still alive.
+pub(crate) fn registered_components() -> usize {
+    REGISTRY.lock().iter().filter(|component| component.strong_count() > 0).count()
+}
+
+/// Shared state of a registered background component, see the [module documentation](self)
+pub(crate) struct ComponentHandle {
+    name: String,
+    stop: Mutex<bool>,
+    stop_changed: Condvar,
+    stop_notify: Notify,
+    stopped: Mutex<bool>,
+    stopped_changed: Condvar,
+    abort: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+}
+
+impl std::fmt::Debug for ComponentHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ComponentHandle")
+            .field("name", &self.name)
+            .field("stop_requested", &self.is_stop_requested())
+            .field("stopped", &self.is_stopped())
+            .finish()
+    }
+}
+
+impl ComponentHandle {
+    /// Asks the component to stop.
+    pub(crate) fn request_stop(&self) {
+        *self.stop.lock() = true;
+        self.stop_changed.notify_all();
+        self.stop_notify.notify_waiters();
+    }
+
+    /// Whether the component was asked to stop.
+    pub(crate) fn is_stop_requested(&self) -> bool {
+        *self.stop.lock()
+    }
+
+    /// Waits up to `timeout` for a stop request, returning whether one was made.
+    // Only threads wait this way, and the only thread is the sampler of the metrics facade
+    #[cfg_attr(not(feature = "metrics-facade"), allow(dead_code))]
+    pub(crate) fn wait_for_stop(&self, timeout: Duration) -> bool {
+        let mut stop = self.stop.lock();
+        if !*stop {
+            self.stop_changed.wait_for(&mut stop, timeout);
+        }
+        *stop
+    }
+
+    /// Completes once the component is asked to stop.
+    pub(crate) async fn stop_requested(&self) {
+        // Registering interest before checking the flag can't miss a request made in between
+        let notified = self.stop_notify.notified();
+        if self.is_stop_requested() {
+            return;
+        }
+        notified.await;
+    }
+
+    /// Marks the component as running until the returned guard is dropped.
+    pub(crate) fn running(self: &Arc<Self>) -> RunningGuard {
+        RunningGuard(Arc::clone(self))
+    }
+
+    /// Sets how to abort the component if it doesn't stop in time.
+    pub(crate) fn set_abort(&self, abort: impl FnOnce() + Send + 'static) {
+        *self.abort.lock() = Some(Box::new(abort));
+    }
+
+    fn is_stopped(&self) -> bool {
+        *self.stopped.lock()
+    }
+
+    fn wait_until_stopped(&self, deadline: Instant) -> bool {
+        let mut stopped = self.stopped.lock();
+        while !*stopped {
+            if self.stopped_changed.wait_until(&mut stopped, deadline).timed_out() {
+                break;
+            }
+        }
+        *stopped
+    }
+
+    /// Aborts the component, returning whether it could be aborted.
+    fn abort(&self) -> bool {
+        match self.abort.lock().take() {
+            Some(abort) => {
+                abort();
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+/// Marks a component as stopped when dropped, see [`ComponentHandle::running`]
+pub(crate) struct RunningGuard(Arc<ComponentHandle>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        *self.0.stopped.lock() = true;
+        self.0.stopped_changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::hardware::memory::{Memory, MEMORY_MONITOR};
+
+    /// A component running on a thread until it's asked to stop
+    fn spawn_fake(name: &str) -> thread::JoinHandle<()> {
+        let component = register(name);
+        thread::spawn(move || {
+            let _running = component.running();
+            while !component.wait_for_stop(Duration::from_secs(60)) {}
+        })
+    }
+
+    fn outcome(report: &ShutdownReport, name: &str) -> Option<ShutdownOutcome> {
+        report.components.iter().find(|c| c.name == name).map(|c| c.outcome)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shutdown_stops_registered_components() {
+        let _serial = TEST_SERIAL.lock().await;
+
+        let memory = Memory::new().unwrap();
+        let monitor = memory.start_monitoring(60_000).await.unwrap();
+        let fake = spawn_fake("fake-component");
+
+        let timeout = Duration::from_secs(5);
+        let report = tokio::task::spawn_blocking(move || shutdown(timeout)).await.unwrap();
+
+        assert!(report.elapsed < timeout, "{report:?}");
+        assert_eq!(outcome(&report, "fake-component"), Some(ShutdownOutcome::Stopped));
+        assert_eq!(outcome(&report, MEMORY_MONITOR), Some(ShutdownOutcome::Stopped));
+        assert!(!monitor.is_active());
+        fake.join().unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_twice_and_with_nothing_registered() {
+        let _serial = TEST_SERIAL.blocking_lock();
+
+        shutdown(Duration::from_secs(1));
+        let report = shutdown(Duration::from_secs(1));
+        assert!(report.components.is_empty(), "{report:?}");
+        assert!(report.all_stopped());
+
+        let fake = spawn_fake("fake-twice");
+        let first = shutdown(Duration::from_secs(5));
+        assert_eq!(outcome(&first, "fake-twice"), Some(ShutdownOutcome::Stopped));
+        fake.join().unwrap();
+
+        let second = shutdown(Duration::from_secs(5));
+        assert_eq!(outcome(&second, "fake-twice"), None);
+    }
+
+    #[test]
+    fn test_stragglers_are_aborted_or_time_out() {
+        let _serial = TEST_SERIAL.blocking_lock();
+
+        let stubborn = register("stubborn-thread");
+        let abortable = register("abortable-task");
+        let aborted = Arc::new(Mutex::new(false));
+        {
+            let aborted = Arc::clone(&aborted);
+            abortable.set_abort(move || *aborted.lock() = true);
+        }
+        let _running = (stubborn.running(), abortable.running());
+
+        let report = shutdown(Duration::from_millis(50));
+
+        assert_eq!(outcome(&report, "stubborn-thread"), Some(ShutdownOutcome::TimedOut));
+        assert_eq!(outcome(&report, "abortable-task"), Some(ShutdownOutcome::Aborted));
+        assert!(*aborted.lock());
+        assert!(!report.all_stopped());
+    }
+
+    #[test]
+    fn test_guard_shuts_down_on_drop() {
+        let _serial = TEST_SERIAL.blocking_lock();
+
+        let fake = spawn_fake("fake-guarded");
+        {
+            let _guard = ShutdownGuard::new(Duration::from_secs(5));
+        }
+        fake.join().unwrap();
+    }
+}
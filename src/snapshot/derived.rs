@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+use super::MetricsSnapshot;
+
+/// Name of the built-in [`memory_pressure_score`] metric
+pub const MEMORY_PRESSURE_SCORE: &str = "memory_pressure_score";
+
+/// Name of the built-in [`cpu_saturation`] metric
+pub const CPU_SATURATION: &str = "cpu_saturation";
+
+/// Combined swap-in and swap-out rate (pages/sec) at which swap activity counts as fully saturated.
+const SWAP_RATE_SATURATION: f64 = 1000.0;
+
+/// A closure computing a derived metric from a snapshot.
+///
+/// Returning None means the metric can't be computed from this snapshot (for example because an input section is
+/// missing); nothing is recorded in that case.
+pub type DerivedFn = dyn Fn(&MetricsSnapshot) -> Option<f64> + Send + Sync;
+
+/// A registry of user-defined metrics computed from each [`MetricsSnapshot`].
+///
+/// Every registered closure is evaluated against the collected snapshot and its result is stored in
+/// [`MetricsSnapshot::derived`] under the registered name. Evaluation is isolated per metric:
+///
+/// * A closure that panics doesn't affect the other metrics; the panic is recorded in
+///   [`MetricsSnapshot::warnings`] and the metric is omitted.
+/// * Non-finite results (NaN or infinity) are omitted with a warning.
+/// * All closures see the snapshot as collected, with an empty `derived` map, so the result doesn't depend on
+///   registration order. Derived metrics can't depend on other derived metrics.
+#[derive(Clone, Default)]
+pub struct DerivedMetrics {
+    metrics: Vec<(String, Arc<DerivedFn>)>,
+}
+
+impl fmt::Debug for DerivedMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DerivedMetrics").field("names", &self.names().collect::<Vec<_>>()).finish()
+    }
+}
+
+impl DerivedMetrics {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry containing the built-in [`memory_pressure_score`] and [`cpu_saturation`] metrics.
+    pub fn with_builtins() -> Self {
+        let mut metrics = Self::new();
+        metrics.register(MEMORY_PRESSURE_SCORE, memory_pressure_score);
+        metrics.register(CPU_SATURATION, cpu_saturation);
+        metrics
+    }
+
+    /// Registers a derived metric, replacing any existing metric with the same name.
+    pub fn register<F>(&mut self, name: impl Into<String>, metric: F) -> &mut Self
+    where
+        F: Fn(&MetricsSnapshot) -> Option<f64> + Send + Sync + 'static,
+    {
+        let name = name.into();
+        let metric: Arc<DerivedFn> = Arc::new(metric);
+
+        match self.metrics.iter_mut().find(|(existing, _)| *existing == name) {
+            Some(entry) => entry.1 = metric,
+            None => self.metrics.push((name, metric)),
+        }
+        self
+    }
+
+    /// Removes a derived metric, returning whether it was registered.
+    pub fn unregister(&mut self, name: &str) -> bool {
+        let len = self.metrics.len();
+        self.metrics.retain(|(existing, _)| existing != name);
+        self.metrics.len() != len
+    }
+
+    /// Names of the registered metrics in registration order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.metrics.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Number of registered metrics.
+    pub fn len(&self) -> usize {
+        self.metrics.len()
+    }
+
+    /// Whether no metrics are registered.
+    pub fn is_empty(&self) -> bool {
+        self.metrics.is_empty()
+    }
+
+    /// Evaluates every registered metric against the snapshot.
+    ///
+    /// # Returns
+    ///
+    /// * `(HashMap<String, f64>, Vec<String>)` - The computed values and a warning for each metric that panicked or
+    ///   produced a non-finite value
+    pub fn evaluate(&self, snapshot: &MetricsSnapshot) -> (HashMap<String, f64>, Vec<String>) {
+        let mut values = HashMap::with_capacity(self.metrics.len());
+        let mut warnings = Vec::new();
+
+        for (name, metric) in &self.metrics {
+            match panic::catch_unwind(AssertUnwindSafe(|| metric(snapshot))) {
+                Ok(Some(value)) if value.is_finite() => {
+                    values.insert(name.clone(), value);
+                },
+                Ok(Some(value)) => warnings
+                    .push(format!("derived metric '{name}' produced a non-finite value: {value}")),
+                Ok(None) => {},
+                Err(payload) => warnings.push(format!(
+                    "derived metric '{name}' panicked: {}",
+                    panic_message(payload.as_ref())
+                )),
+            }
+        }
+
+        (values, warnings)
+    }
+
+    /// Evaluates every registered metric and stores the results in the snapshot.
+    ///
+    /// Any previously derived values are discarded first, so derived metrics never see each other's results.
+    pub fn apply(&self, snapshot: &mut MetricsSnapshot) {
+        snapshot.derived.clear();
+        let (values, warnings) = self.evaluate(snapshot);
+        snapshot.derived = values;
+        snapshot.warnings.extend(warnings);
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
+
+/// Memory pressure score between 0 and 100.
+///
+/// Weighted combination of the memory pressure (50%), swap activity relative to 1000 pages/sec (30%), and the
+/// fraction of physical memory held by the compressor (20%). Returns None if the snapshot has no memory section.
+pub fn memory_pressure_score(snapshot: &MetricsSnapshot) -> Option<f64> {
+    let memory = snapshot.memory.as_ref()?;
+
+    let pressure = memory.pressure.clamp(0.0, 1.0);
+    let swap_activity = ((memory.swap_ins_per_sec + memory.swap_outs_per_sec)
+        / SWAP_RATE_SATURATION)
+        .clamp(0.0, 1.0);
+    let compression = memory.compression_ratio().unwrap_or(0.0).clamp(0.0, 1.0);
+
+    Some((pressure * 0.5 + swap_activity * 0.3 + compression * 0.2) * 100.0)
+}
+
+/// CPU saturation as the 1 minute load average divided by the number of logical cores.
+///
+/// Values above 1.0 mean there is more runnable work than cores. Returns None if the load average or core count is
+/// unavailable.
+pub fn cpu_saturation(snapshot: &MetricsSnapshot) -> Option<f64> {
+    let cpu = snapshot.cpu.as_ref()?;
+    let [load1, _, _] = cpu.load_average?;
+
+    (cpu.logical_cores > 0).then(|| load1 / cpu.logical_cores as f64)
+}
@@ -0,0 +1,311 @@
+use std::{collections::HashMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use super::{CpuTimes, MetricsSnapshot, ProcessSnapshot};
+use crate::disk::Disk;
+
+/// Number of processes [`SnapshotDiff`] lists in its `Display` output
+const DISPLAY_TOP_PROCESSES: usize = 10;
+
+/// What changed between two [`MetricsSnapshot`]s, see [`MetricsSnapshot::diff`]
+///
+/// Every field is None if either snapshot lacks its section or a counter went backwards, e.g. because it wrapped or an
+/// interface was removed. Counter deltas are the amount consumed between the snapshots; the other byte deltas are
+/// negative if the value shrank.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotDiff {
+    /// Wall time between the snapshots in milliseconds, or None if the later snapshot was taken first
+    pub elapsed_ms: Option<u64>,
+    /// CPU time all processors spent in each mode
+    pub cpu_time: Option<CpuTimeDelta>,
+    /// Change of memory use by category
+    pub memory: Option<MemoryDelta>,
+    /// Growth of the used swap space in bytes
+    pub swap_growth_bytes: Option<i64>,
+    /// Processes present in both snapshots, started and exited in between
+    pub processes: Option<ProcessesDiff>,
+    /// Bytes read from disk
+    pub disk_bytes_read: Option<u64>,
+    /// Bytes written to disk
+    pub disk_bytes_written: Option<u64>,
+    /// Bytes received over the network
+    pub network_bytes_received: Option<u64>,
+    /// Bytes sent over the network
+    pub network_bytes_sent: Option<u64>,
+    /// Lowest and highest CPU temperature of the two snapshots
+    pub cpu_temperature: Option<TemperatureRange>,
+    /// Lowest and highest GPU temperature of the two snapshots
+    pub gpu_temperature: Option<TemperatureRange>,
+}
+
+/// CPU time spent in each mode between two snapshots, in seconds
+///
+/// A mode is None if its counter wrapped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CpuTimeDelta {
+    /// Seconds running user code
+    pub user_secs: Option<f64>,
+    /// Seconds running kernel code
+    pub system_secs: Option<f64>,
+    /// Seconds idle
+    pub idle_secs: Option<f64>,
+    /// Seconds running user code at a lowered priority
+    pub nice_secs: Option<f64>,
+}
+
+/// Change of memory use between two snapshots, in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryDelta {
+    /// Change of used memory
+    pub used_bytes: i64,
+    /// Change of available memory
+    pub available_bytes: i64,
+    /// Change of wired memory
+    pub wired_bytes: i64,
+    /// Change of memory held by the compressor
+    pub compressed_bytes: i64,
+}
+
+/// Processes compared between two snapshots by PID and start time
+///
+/// A PID that was reused in between counts as one process that exited and another that started.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessesDiff {
+    /// Processes present in both snapshots, the most CPU time first
+    pub changed: Vec<ProcessDelta>,
+    /// Processes only in the later snapshot, by PID
+    pub started: Vec<ProcessSnapshot>,
+    /// Processes only in the earlier snapshot, by PID
+    pub exited: Vec<ProcessSnapshot>,
+}
+
+/// Change of a process present in both snapshots
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessDelta {
+    /// Process ID
+    pub pid: u32,
+    /// Executable name in the later snapshot
+    pub name: String,
+    /// CPU time used in between in seconds, or None if it went backwards
+    pub cpu_secs: Option<f64>,
+    /// Change of resident memory in bytes
+    pub resident_bytes: i64,
+}
+
+/// Lowest and highest temperature, in degrees Celsius
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TemperatureRange {
+    /// Lowest temperature
+    pub min_celsius: f64,
+    /// Highest temperature
+    pub max_celsius: f64,
+}
+
+impl MetricsSnapshot {
+    /// Compares this snapshot with one taken later.
+    ///
+    /// Sections missing from either snapshot and counters that went backwards leave their fields None instead of
+    /// failing the whole comparison.
+    pub fn diff(&self, later: &MetricsSnapshot) -> SnapshotDiff {
+        let memory = self.memory.as_ref().zip(later.memory.as_ref());
+        let disk_io = self.disk_io.zip(later.disk_io);
+        let network = self.network.zip(later.network);
+        let temperature = self.temperature.zip(later.temperature);
+
+        SnapshotDiff {
+            elapsed_ms: later.timestamp_ms.checked_sub(self.timestamp_ms),
+            cpu_time: self
+                .cpu
+                .as_ref()
+                .and_then(|cpu| cpu.times)
+                .zip(later.cpu.as_ref().and_then(|cpu| cpu.times))
+                .map(|(earlier, later)| cpu_time_delta(&earlier, &later)),
+            memory: memory.map(|(earlier, later)| MemoryDelta {
+                used_bytes: signed_delta(earlier.used, later.used),
+                available_bytes: signed_delta(earlier.available, later.available),
+                wired_bytes: signed_delta(earlier.wired, later.wired),
+                compressed_bytes: signed_delta(earlier.compressed, later.compressed),
+            }),
+            swap_growth_bytes: memory
+                .map(|(earlier, later)| signed_delta(earlier.swap_used, later.swap_used)),
+            processes: self
+                .processes
+                .as_deref()
+                .zip(later.processes.as_deref())
+                .map(|(earlier, later)| processes_diff(earlier, later)),
+            disk_bytes_read: disk_io
+                .and_then(|(earlier, later)| later.bytes_read.checked_sub(earlier.bytes_read)),
+            disk_bytes_written: disk_io.and_then(|(earlier, later)| {
+                later.bytes_written.checked_sub(earlier.bytes_written)
+            }),
+            network_bytes_received: network.and_then(|(earlier, later)| {
+                later.bytes_received.checked_sub(earlier.bytes_received)
+            }),
+            network_bytes_sent: network
+                .and_then(|(earlier, later)| later.bytes_sent.checked_sub(earlier.bytes_sent)),
+            cpu_temperature: temperature
+                .map(|(earlier, later)| temperature_range(earlier.cpu_celsius, later.cpu_celsius)),
+            gpu_temperature: temperature.and_then(|(earlier, later)| {
+                earlier.gpu_celsius.zip(later.gpu_celsius).map(|(a, b)| temperature_range(a, b))
+            }),
+        }
+    }
+}
+
+fn cpu_time_delta(earlier: &CpuTimes, later: &CpuTimes) -> CpuTimeDelta {
+    CpuTimeDelta {
+        user_secs: seconds_delta(earlier.user_secs, later.user_secs),
+        system_secs: seconds_delta(earlier.system_secs, later.system_secs),
+        idle_secs: seconds_delta(earlier.idle_secs, later.idle_secs),
+        nice_secs: seconds_delta(earlier.nice_secs, later.nice_secs),
+    }
+}
+
+fn processes_diff(earlier: &[ProcessSnapshot], later: &[ProcessSnapshot]) -> ProcessesDiff {
+    let identity = |process: &ProcessSnapshot| (process.pid, process.start_time_us);
+    let earlier_by_identity: HashMap<_, _> =
+        earlier.iter().map(|process| (identity(process), process)).collect();
+    let later_by_identity: HashMap<_, _> =
+        later.iter().map(|process| (identity(process), process)).collect();
+
+    let mut changed: Vec<ProcessDelta> = later
+        .iter()
+        .filter_map(|process| {
+            let previous = earlier_by_identity.get(&identity(process))?;
+            Some(ProcessDelta {
+                pid: process.pid,
+                name: process.name.clone(),
+                cpu_secs: seconds_delta(previous.cpu_secs, process.cpu_secs),
+                resident_bytes: signed_delta(previous.resident_bytes, process.resident_bytes),
+            })
+        })
+        .collect();
+    changed.sort_by(|a, b| {
+        b.cpu_secs.unwrap_or(0.0).total_cmp(&a.cpu_secs.unwrap_or(0.0)).then(a.pid.cmp(&b.pid))
+    });
+
+    let only_in = |processes: &[ProcessSnapshot], other: &HashMap<(u32, u64), &ProcessSnapshot>| {
+        let mut only: Vec<ProcessSnapshot> = processes
+            .iter()
+            .filter(|process| !other.contains_key(&identity(process)))
+            .cloned()
+            .collect();
+        only.sort_by_key(|process| process.pid);
+        only
+    };
+
+    ProcessesDiff {
+        changed,
+        started: only_in(later, &earlier_by_identity),
+        exited: only_in(earlier, &later_by_identity),
+    }
+}
+
+/// Time a counter advanced, or None if it went backwards.
+fn seconds_delta(earlier: f64, later: f64) -> Option<f64> {
+    (later >= earlier).then_some(later - earlier)
+}
+
+/// Change of a value, saturating at the bounds of i64.
+fn signed_delta(earlier: u64, later: u64) -> i64 {
+    let delta = later as i128 - earlier as i128;
+    delta.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+fn temperature_range(a: f64, b: f64) -> TemperatureRange {
+    TemperatureRange { min_celsius: a.min(b), max_celsius: a.max(b) }
+}
+
+/// Formats a value, or `n/a` if it's missing.
+fn or_na<T>(value: Option<T>, format: impl FnOnce(T) -> String) -> String {
+    value.map_or_else(|| "n/a".to_string(), format)
+}
+
+fn signed_bytes(delta: i64) -> String {
+    let sign = if delta < 0 { '-' } else { '+' };
+    format!("{sign}{}", Disk::format_bytes(delta.unsigned_abs()))
+}
+
+fn seconds(secs: f64) -> String {
+    format!("{secs:.2} s")
+}
+
+fn temperature(range: TemperatureRange) -> String {
+    format!("{:.1} to {:.1} °C", range.min_celsius, range.max_celsius)
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Elapsed: {}", or_na(self.elapsed_ms, |ms| seconds(ms as f64 / 1000.0)))?;
+
+        match &self.cpu_time {
+            Some(cpu) => writeln!(
+                f,
+                "CPU time: user {}, system {}, idle {}, nice {}",
+                or_na(cpu.user_secs, seconds),
+                or_na(cpu.system_secs, seconds),
+                or_na(cpu.idle_secs, seconds),
+                or_na(cpu.nice_secs, seconds)
+            )?,
+            None => writeln!(f, "CPU time: n/a")?,
+        }
+
+        match &self.memory {
+            Some(memory) => writeln!(
+                f,
+                "Memory: used {}, available {}, wired {}, compressed {}",
+                signed_bytes(memory.used_bytes),
+                signed_bytes(memory.available_bytes),
+                signed_bytes(memory.wired_bytes),
+                signed_bytes(memory.compressed_bytes)
+            )?,
+            None => writeln!(f, "Memory: n/a")?,
+        }
+        writeln!(f, "Swap: {}", or_na(self.swap_growth_bytes, signed_bytes))?;
+
+        writeln!(
+            f,
+            "Disk: read {}, written {}",
+            or_na(self.disk_bytes_read, Disk::format_bytes),
+            or_na(self.disk_bytes_written, Disk::format_bytes)
+        )?;
+        writeln!(
+            f,
+            "Network: received {}, sent {}",
+            or_na(self.network_bytes_received, Disk::format_bytes),
+            or_na(self.network_bytes_sent, Disk::format_bytes)
+        )?;
+
+        writeln!(f, "CPU temperature: {}", or_na(self.cpu_temperature, temperature))?;
+        writeln!(f, "GPU temperature: {}", or_na(self.gpu_temperature, temperature))?;
+
+        let Some(processes) = &self.processes else {
+            return write!(f, "Processes: n/a");
+        };
+        write!(
+            f,
+            "Processes: {} in both, {} started, {} exited",
+            processes.changed.len(),
+            processes.started.len(),
+            processes.exited.len()
+        )?;
+        for process in processes.changed.iter().take(DISPLAY_TOP_PROCESSES) {
+            write!(
+                f,
+                "\n  {} {}: {} CPU, {}",
+                process.pid,
+                process.name,
+                or_na(process.cpu_secs, seconds),
+                signed_bytes(process.resident_bytes)
+            )?;
+        }
+        for (label, list) in [("started", &processes.started), ("exited", &processes.exited)] {
+            for process in list.iter().take(DISPLAY_TOP_PROCESSES) {
+                write!(f, "\n  {label}: {} {}", process.pid, process.name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+//! Integer-only form of a [`MetricsSnapshot`]
+//!
+//! Every real-valued metric is converted with [`crate::utils::fixed`], and derived metrics are kept in key order, so
+//! serializing the same snapshot always produces the same bytes.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    CpuSnapshot, CpuTimes, DiskIoSnapshot, MemorySnapshot, MetricsSnapshot, NetworkSnapshot,
+    PowerSnapshot, ProcessSnapshot, SystemSnapshot, TemperatureSnapshot, VolumeSnapshot,
+};
+use crate::{
+    error::{Error, Result},
+    utils::fixed::{basis_points, kilohertz, millicelsius, thousandths},
+};
+
+/// How [`MetricsSnapshot::to_json`] writes real-valued metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// Floating-point numbers in the units of [`MetricsSnapshot`]
+    #[default]
+    Float,
+    /// Integers in the fixed-point units of [`FixedMetricsSnapshot`]
+    Fixed,
+}
+
+/// Basis points of a fraction between 0.0 and 1.0
+fn fraction_basis_points(fraction: f64) -> u16 {
+    basis_points(fraction * 100.0)
+}
+
+/// Thousandths of a value that can't be negative, e.g. seconds in milliseconds
+fn unsigned_thousandths(value: f64) -> u64 {
+    u64::try_from(thousandths(value)).unwrap_or(0)
+}
+
+/// CPU section of a [`FixedMetricsSnapshot`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixedCpuSnapshot {
+    /// Number of physical cores
+    pub physical_cores: u32,
+    /// Number of logical cores
+    pub logical_cores: u32,
+    /// Average CPU usage in basis points
+    pub usage_bp: u16,
+    /// Current frequency in kHz
+    pub frequency_khz: u32,
+    /// 1, 5, and 15 minute load averages in thousandths, if available
+    pub load_average_milli: Option<[u64; 3]>,
+    /// Time all processors spent in each mode since boot, if available
+    pub times: Option<FixedCpuTimes>,
+}
+
+/// Time all processors together spent in each mode since boot, in milliseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixedCpuTimes {
+    /// Milliseconds running user code
+    pub user_ms: u64,
+    /// Milliseconds running kernel code
+    pub system_ms: u64,
+    /// Milliseconds idle
+    pub idle_ms: u64,
+    /// Milliseconds running user code at a lowered priority
+    pub nice_ms: u64,
+}
+
+/// Memory section of a [`FixedMetricsSnapshot`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixedMemorySnapshot {
+    /// Total physical memory in bytes
+    pub total: u64,
+    /// Used memory in bytes
+    pub used: u64,
+    /// Available memory in bytes
+    pub available: u64,
+    /// Wired memory in bytes
+    pub wired: u64,
+    /// Memory held by the compressor in bytes
+    pub compressed: u64,
+    /// Memory pressure in basis points
+    pub pressure_bp: u16,
+    /// Total swap space in bytes
+    pub swap_total: u64,
+    /// Used swap space in bytes
+    pub swap_used: u64,
+    /// Rate of pages swapped in, in thousandths of a page per second
+    pub swap_ins_milli_per_sec: u64,
+    /// Rate of pages swapped out, in thousandths of a page per second
+    pub swap_outs_milli_per_sec: u64,
+}
+
+/// A process in a [`FixedMetricsSnapshot`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixedProcessSnapshot {
+    /// Process ID
+    pub pid: u32,
+    /// When the process started, in microseconds since the Unix epoch
+    pub start_time_us: u64,
+    /// Executable name
+    pub name: String,
+    /// User and system CPU time used since the process started, in milliseconds
+    pub cpu_ms: u64,
+    /// Resident memory in bytes
+    pub resident_bytes: u64,
+}
+
+/// Temperature section of a [`FixedMetricsSnapshot`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixedTemperatureSnapshot {
+    /// CPU temperature in millidegrees Celsius
+    pub cpu_millic: i32,
+    /// GPU temperature in millidegrees Celsius, or None if the GPU reports none
+    pub gpu_millic: Option<i32>,
+}
+
+/// Power section of a [`FixedMetricsSnapshot`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixedPowerSnapshot {
+    /// What the machine runs on: "Battery", "AC", "Charging" or "Unknown"
+    pub state: String,
+    /// Battery charge in basis points, or None without a battery
+    pub battery_bp: Option<u16>,
+    /// Seconds until the battery is empty or full, or None if macOS doesn't know yet or there is no battery
+    pub time_remaining_secs: Option<u64>,
+    /// Whether Low Power Mode is on, or None before macOS 12
+    pub low_power_mode: Option<bool>,
+}
+
+/// A [`MetricsSnapshot`] with every metric as an integer, for consumers that can't use floating point
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixedMetricsSnapshot {
+    /// When the snapshot was taken, in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    /// System information, or None if the snapshot didn't read it
+    pub system: Option<SystemSnapshot>,
+    /// CPU metrics, or None if they couldn't be collected
+    pub cpu: Option<FixedCpuSnapshot>,
+    /// Memory metrics, or None if they couldn't be collected
+    pub memory: Option<FixedMemorySnapshot>,
+    /// Brightness of the built-in display in basis points, or None if there is none or it isn't reported
+    pub display_brightness_bp: Option<u16>,
+    /// Running processes, or None if the snapshot didn't list them
+    pub processes: Option<Vec<FixedProcessSnapshot>>,
+    /// The busiest processes, or None if the snapshot didn't rank them
+    pub top_processes: Option<Vec<FixedProcessSnapshot>>,
+    /// Disk I/O counters, or None if they couldn't be collected
+    pub disk_io: Option<DiskIoSnapshot>,
+    /// Disk space per mounted volume, or None if the snapshot didn't list them
+    pub volumes: Option<Vec<VolumeSnapshot>>,
+    /// Network counters, or None if they couldn't be collected
+    pub network: Option<NetworkSnapshot>,
+    /// Temperatures, or None if they couldn't be collected
+    pub temperature: Option<FixedTemperatureSnapshot>,
+    /// Power source, or None if the snapshot didn't read it
+    pub power: Option<FixedPowerSnapshot>,
+    /// Derived metrics in thousandths, ordered by metric name
+    pub derived_milli: BTreeMap<String, i64>,
+    /// Problems encountered while collecting the snapshot
+    pub warnings: Vec<String>,
+}
+
+impl CpuSnapshot {
+    /// Average CPU usage in basis points
+    pub fn usage_bp(&self) -> u16 {
+        fraction_basis_points(self.usage)
+    }
+
+    /// Current frequency in kHz
+    pub fn frequency_khz(&self) -> u32 {
+        kilohertz(self.frequency_mhz)
+    }
+}
+
+impl MemorySnapshot {
+    /// Memory pressure in basis points
+    pub fn pressure_bp(&self) -> u16 {
+        fraction_basis_points(self.pressure)
+    }
+}
+
+impl TemperatureSnapshot {
+    /// CPU temperature in millidegrees Celsius
+    pub fn cpu_millic(&self) -> i32 {
+        millicelsius(self.cpu_celsius)
+    }
+
+    /// GPU temperature in millidegrees Celsius, or None if the GPU reports none
+    pub fn gpu_millic(&self) -> Option<i32> {
+        self.gpu_celsius.map(millicelsius)
+    }
+}
+
+impl From<&CpuTimes> for FixedCpuTimes {
+    fn from(times: &CpuTimes) -> Self {
+        Self {
+            user_ms: unsigned_thousandths(times.user_secs),
+            system_ms: unsigned_thousandths(times.system_secs),
+            idle_ms: unsigned_thousandths(times.idle_secs),
+            nice_ms: unsigned_thousandths(times.nice_secs),
+        }
+    }
+}
+
+impl From<&CpuSnapshot> for FixedCpuSnapshot {
+    fn from(cpu: &CpuSnapshot) -> Self {
+        Self {
+            physical_cores: cpu.physical_cores,
+            logical_cores: cpu.logical_cores,
+            usage_bp: cpu.usage_bp(),
+            frequency_khz: cpu.frequency_khz(),
+            load_average_milli: cpu.load_average.map(|load| load.map(unsigned_thousandths)),
+            times: cpu.times.as_ref().map(FixedCpuTimes::from),
+        }
+    }
+}
+
+impl From<&MemorySnapshot> for FixedMemorySnapshot {
+    fn from(memory: &MemorySnapshot) -> Self {
+        Self {
+            total: memory.total,
+            used: memory.used,
+            available: memory.available,
+            wired: memory.wired,
+            compressed: memory.compressed,
+            pressure_bp: memory.pressure_bp(),
+            swap_total: memory.swap_total,
+            swap_used: memory.swap_used,
+            swap_ins_milli_per_sec: unsigned_thousandths(memory.swap_ins_per_sec),
+            swap_outs_milli_per_sec: unsigned_thousandths(memory.swap_outs_per_sec),
+        }
+    }
+}
+
+impl From<&ProcessSnapshot> for FixedProcessSnapshot {
+    fn from(process: &ProcessSnapshot) -> Self {
+        Self {
+            pid: process.pid,
+            start_time_us: process.start_time_us,
+            name: process.name.clone(),
+            cpu_ms: unsigned_thousandths(process.cpu_secs),
+            resident_bytes: process.resident_bytes,
+        }
+    }
+}
+
+impl From<&TemperatureSnapshot> for FixedTemperatureSnapshot {
+    fn from(temperature: &TemperatureSnapshot) -> Self {
+        Self { cpu_millic: temperature.cpu_millic(), gpu_millic: temperature.gpu_millic() }
+    }
+}
+
+impl From<&PowerSnapshot> for FixedPowerSnapshot {
+    fn from(power: &PowerSnapshot) -> Self {
+        Self {
+            state: power.state.clone(),
+            battery_bp: power
+                .battery_percentage
+                .map(|percentage| basis_points(f64::from(percentage))),
+            time_remaining_secs: power.time_remaining_secs,
+            low_power_mode: power.low_power_mode,
+        }
+    }
+}
+
+impl From<&MetricsSnapshot> for FixedMetricsSnapshot {
+    fn from(snapshot: &MetricsSnapshot) -> Self {
+        Self {
+            timestamp_ms: snapshot.timestamp_ms,
+            system: snapshot.system.clone(),
+            cpu: snapshot.cpu.as_ref().map(FixedCpuSnapshot::from),
+            memory: snapshot.memory.as_ref().map(FixedMemorySnapshot::from),
+            display_brightness_bp: snapshot
+                .display_brightness
+                .map(|brightness| fraction_basis_points(f64::from(brightness))),
+            processes: snapshot
+                .processes
+                .as_ref()
+                .map(|processes| processes.iter().map(FixedProcessSnapshot::from).collect()),
+            top_processes: snapshot
+                .top_processes
+                .as_ref()
+                .map(|processes| processes.iter().map(FixedProcessSnapshot::from).collect()),
+            disk_io: snapshot.disk_io,
+            volumes: snapshot.volumes.clone(),
+            network: snapshot.network,
+            temperature: snapshot.temperature.as_ref().map(FixedTemperatureSnapshot::from),
+            power: snapshot.power.as_ref().map(FixedPowerSnapshot::from),
+            derived_milli: snapshot
+                .derived
+                .iter()
+                .map(|(name, value)| (name.clone(), thousandths(*value)))
+                .collect(),
+            warnings: snapshot.warnings.clone(),
+        }
+    }
+}
+
+impl MetricsSnapshot {
+    /// The snapshot with every metric as an integer, see [`FixedMetricsSnapshot`].
+    pub fn to_fixed(&self) -> FixedMetricsSnapshot {
+        FixedMetricsSnapshot::from(self)
+    }
+
+    /// Serializes the snapshot to JSON, with real-valued metrics written as `format` asks.
+    ///
+    /// With [`NumberFormat::Fixed`] the output contains no floating-point numbers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot can't be serialized.
+    pub fn to_json(&self, format: NumberFormat) -> Result<String> {
+        match format {
+            NumberFormat::Float => serde_json::to_string(self),
+            NumberFormat::Fixed => serde_json::to_string(&self.to_fixed()),
+        }
+        .map_err(|e| Error::invalid_data(format!("Failed to serialize snapshot: {}", e)))
+    }
+}
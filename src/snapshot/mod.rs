@@ -0,0 +1,581 @@
+//! # Snapshot Module
+//!
+//! A [`MetricsSnapshot`] captures a consistent set of metrics at a single point in time, so that values which are
+//! compared or combined with each other were sampled together. Snapshots are plain data and implement
+//! `Serialize`/`Deserialize`, which makes them suitable for exporting (see [`crate::export`]) or logging.
+//!
+//! Subsystems that fail to collect don't fail the whole snapshot; their section is left as `None` and the error is
+//! recorded in [`MetricsSnapshot::warnings`].
+//!
+//! [`snapshot()`](crate::snapshot()) collects every section in one call, including the system information, disk space,
+//! power source and, with [`SnapshotOptions::top_processes`], the busiest processes, and serializes to a single JSON
+//! document:
+//!
+//! ```rust,no_run
+//! use darwin_metrics::snapshot::NumberFormat;
+//!
+//! let snapshot = darwin_metrics::snapshot();
+//! println!("{}", snapshot.to_json(NumberFormat::Float)?);
+//! # Ok::<(), darwin_metrics::Error>(())
+//! ```
+//!
+//! ## Derived metrics
+//!
+//! Composite values can be computed inside the collection process with a [`DerivedMetrics`] registry. Each registered
+//! closure is evaluated after collection and its result is stored in [`MetricsSnapshot::derived`]:
+//!
+//! ```rust,no_run
+//! use darwin_metrics::snapshot::{DerivedMetrics, MetricsSnapshot};
+//!
+//! let mut derived = DerivedMetrics::with_builtins();
+//! derived.register("swap_used_ratio", |snapshot: &MetricsSnapshot| {
+//!     let memory = snapshot.memory.as_ref()?;
+//!     (memory.swap_total > 0).then(|| memory.swap_used as f64 / memory.swap_total as f64)
+//! });
+//!
+//! let snapshot = MetricsSnapshot::collect_with(&derived);
+//! println!("{:?}", snapshot.derived);
+//! ```
+//!
+//! ## Before and after
+//!
+//! [`MetricsSnapshot::diff`] compares two snapshots, e.g. taken around a benchmark, and reports what the workload
+//! consumed and changed. Per-process changes need snapshots taken with [`MetricsSnapshot::collect_full`]:
+//!
+//! ```rust,no_run
+//! use darwin_metrics::snapshot::MetricsSnapshot;
+//!
+//! let before = MetricsSnapshot::collect_full();
+//! // run the workload
+//! let after = MetricsSnapshot::collect_full();
+//! println!("{}", before.diff(&after));
+//! ```
+//!
+//! ## Integer-only output
+//!
+//! For consumers that can't use floating point, [`MetricsSnapshot::to_fixed`] converts every real-valued metric to an
+//! integer in a fixed-point unit, such as basis points or millidegrees Celsius (see [`crate::utils::fixed`] for the
+//! units and rounding), and `to_json(NumberFormat::Fixed)` serializes that form, so the JSON has no floats and equal
+//! snapshots produce equal bytes.
+
+mod derived;
+mod diff;
+mod fixed;
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+pub use derived::{
+    cpu_saturation, memory_pressure_score, DerivedFn, DerivedMetrics, CPU_SATURATION,
+    MEMORY_PRESSURE_SCORE,
+};
+pub use diff::{
+    CpuTimeDelta, MemoryDelta, ProcessDelta, ProcessesDiff, SnapshotDiff, TemperatureRange,
+};
+pub use fixed::{
+    FixedCpuSnapshot, FixedCpuTimes, FixedMemorySnapshot, FixedMetricsSnapshot, FixedPowerSnapshot,
+    FixedProcessSnapshot, FixedTemperatureSnapshot, NumberFormat,
+};
+
+use crate::{
+    disk::{BlockStorageSampler, DeviceIoSampler, Disk},
+    error::{Error, Result},
+    hardware::{
+        cpu::{CpuMetrics, CPU},
+        iokit::{IOKit, IOKitImpl},
+        memory::Memory,
+    },
+    init::{self, sysctl_string},
+    network::{NetworkManager, NetworkMetrics},
+    power::{builtin_display_brightness, is_low_power_mode, Power},
+    process::{sample_processes, ProcessSample},
+    system::uptime,
+    utils::{
+        bindings::{
+            getloadavg, host_cpu_load_info, host_statistics, mach_host_self, HostInfoT,
+            CPU_STATE_IDLE, CPU_STATE_NICE, CPU_STATE_SYSTEM, CPU_STATE_USER, HOST_CPU_LOAD_INFO,
+            HOST_CPU_LOAD_INFO_COUNT, KERN_SUCCESS,
+        },
+        sanitize::sanitize_rate,
+    },
+};
+
+/// Rate of the CPU tick counters on macOS, in ticks per second
+const CPU_TICKS_PER_SEC: f64 = 100.0;
+
+/// What [`MetricsSnapshot::collect_with_options`] collects besides the sections of [`MetricsSnapshot::collect`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SnapshotOptions {
+    /// Number of processes with the highest average CPU usage to include, none if 0 (default: 0)
+    pub top_processes: usize,
+}
+
+/// System section of a [`MetricsSnapshot`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SystemSnapshot {
+    /// Hardware model identifier (e.g. "Mac14,10")
+    pub model: String,
+    /// macOS version (e.g. "14.5")
+    pub os_version: String,
+    /// macOS build (e.g. "23F79")
+    pub os_build: String,
+    /// CPU architecture
+    pub architecture: String,
+    /// CPU model name (e.g. "Apple M2 Pro")
+    pub cpu_model: String,
+    /// Time since boot in seconds
+    pub uptime_secs: u64,
+}
+
+/// CPU section of a [`MetricsSnapshot`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CpuSnapshot {
+    /// Number of physical cores
+    pub physical_cores: u32,
+    /// Number of logical cores
+    pub logical_cores: u32,
+    /// Average CPU usage between 0.0 and 1.0
+    pub usage: f64,
+    /// Current frequency in MHz
+    pub frequency_mhz: f64,
+    /// 1, 5, and 15 minute load averages, if available
+    pub load_average: Option<[f64; 3]>,
+    /// Time all processors spent in each mode since boot, if available
+    pub times: Option<CpuTimes>,
+}
+
+/// Time all processors together spent in each mode since boot
+///
+/// The kernel counts in 32-bit ticks that wrap, after about 500 days of processor time at 100 ticks per second.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CpuTimes {
+    /// Seconds running user code
+    pub user_secs: f64,
+    /// Seconds running kernel code
+    pub system_secs: f64,
+    /// Seconds idle
+    pub idle_secs: f64,
+    /// Seconds running user code at a lowered priority
+    pub nice_secs: f64,
+}
+
+/// Memory section of a [`MetricsSnapshot`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemorySnapshot {
+    /// Total physical memory in bytes
+    pub total: u64,
+    /// Used memory in bytes
+    pub used: u64,
+    /// Available memory in bytes
+    pub available: u64,
+    /// Wired memory in bytes
+    pub wired: u64,
+    /// Memory held by the compressor in bytes
+    pub compressed: u64,
+    /// Memory pressure between 0.0 and 1.0
+    pub pressure: f64,
+    /// Total swap space in bytes
+    pub swap_total: u64,
+    /// Used swap space in bytes
+    pub swap_used: u64,
+    /// Rate of pages swapped in per second
+    pub swap_ins_per_sec: f64,
+    /// Rate of pages swapped out per second
+    pub swap_outs_per_sec: f64,
+}
+
+impl MemorySnapshot {
+    /// Fraction of physical memory held by the compressor, or None if the total is unknown
+    pub fn compression_ratio(&self) -> Option<f64> {
+        (self.total > 0).then(|| self.compressed as f64 / self.total as f64)
+    }
+}
+
+impl From<&Memory> for MemorySnapshot {
+    fn from(memory: &Memory) -> Self {
+        Self {
+            total: memory.total,
+            used: memory.used,
+            available: memory.available,
+            wired: memory.wired,
+            compressed: memory.page_states.compressed,
+            pressure: memory.pressure,
+            swap_total: memory.swap_usage.total,
+            swap_used: memory.swap_usage.used,
+            swap_ins_per_sec: memory.swap_usage.ins,
+            swap_outs_per_sec: memory.swap_usage.outs,
+        }
+    }
+}
+
+/// A process in a [`MetricsSnapshot`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessSnapshot {
+    /// Process ID
+    pub pid: u32,
+    /// When the process started, in microseconds since the Unix epoch; together with the PID, it identifies the process
+    pub start_time_us: u64,
+    /// Executable name
+    pub name: String,
+    /// User and system CPU time used since the process started, in seconds
+    pub cpu_secs: f64,
+    /// Resident memory in bytes
+    pub resident_bytes: u64,
+}
+
+/// Disk I/O section of a [`MetricsSnapshot`], summed over all block storage devices
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskIoSnapshot {
+    /// Total bytes read since boot
+    pub bytes_read: u64,
+    /// Total bytes written since boot
+    pub bytes_written: u64,
+}
+
+/// Disk space of a mounted volume in a [`MetricsSnapshot`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeSnapshot {
+    /// Mount point
+    pub mount_point: String,
+    /// Filesystem type (e.g. "apfs")
+    pub fs_type: String,
+    /// Total capacity in bytes
+    pub total_bytes: u64,
+    /// Used space in bytes
+    pub used_bytes: u64,
+    /// Available space in bytes
+    pub available_bytes: u64,
+}
+
+impl From<&Disk> for VolumeSnapshot {
+    fn from(disk: &Disk) -> Self {
+        Self {
+            mount_point: disk.mount_point.clone(),
+            fs_type: disk.fs_type.clone(),
+            total_bytes: disk.total,
+            used_bytes: disk.used,
+            available_bytes: disk.available,
+        }
+    }
+}
+
+/// Power section of a [`MetricsSnapshot`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PowerSnapshot {
+    /// What the machine runs on: "Battery", "AC", "Charging" or "Unknown"
+    pub state: String,
+    /// Battery charge in percent, or None without a battery
+    pub battery_percentage: Option<f32>,
+    /// Seconds until the battery is empty or full, or None if macOS doesn't know yet or there is no battery
+    pub time_remaining_secs: Option<u64>,
+    /// Whether Low Power Mode is on, or None before macOS 12
+    pub low_power_mode: Option<bool>,
+}
+
+/// Network section of a [`MetricsSnapshot`], summed over all interfaces except loopback
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkSnapshot {
+    /// Total bytes received
+    pub bytes_received: u64,
+    /// Total bytes sent
+    pub bytes_sent: u64,
+}
+
+/// Temperature section of a [`MetricsSnapshot`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TemperatureSnapshot {
+    /// CPU temperature in degrees Celsius
+    pub cpu_celsius: f64,
+    /// GPU temperature in degrees Celsius, or None if the GPU reports none
+    pub gpu_celsius: Option<f64>,
+}
+
+/// Metrics collected at a single point in time
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    /// When the snapshot was taken, in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    /// System information, or None if the snapshot wasn't collected with [`MetricsSnapshot::collect_with_options`] or
+    /// it couldn't be read
+    pub system: Option<SystemSnapshot>,
+    /// CPU metrics, or None if they couldn't be collected
+    pub cpu: Option<CpuSnapshot>,
+    /// Memory metrics, or None if they couldn't be collected
+    pub memory: Option<MemorySnapshot>,
+    /// Brightness of the built-in display between 0.0 and 1.0, or None if there is none or it isn't reported
+    pub display_brightness: Option<f32>,
+    /// Running processes, or None if the snapshot wasn't collected with [`MetricsSnapshot::collect_full`] or the process
+    /// table couldn't be read
+    pub processes: Option<Vec<ProcessSnapshot>>,
+    /// The processes with the highest average CPU usage since they started, busiest first, or None if none were asked
+    /// for with [`SnapshotOptions::top_processes`] or the process table couldn't be read
+    pub top_processes: Option<Vec<ProcessSnapshot>>,
+    /// Disk I/O counters, or None if they couldn't be collected
+    pub disk_io: Option<DiskIoSnapshot>,
+    /// Disk space per mounted volume, or None if the snapshot wasn't collected with
+    /// [`MetricsSnapshot::collect_with_options`] or the volumes couldn't be listed
+    pub volumes: Option<Vec<VolumeSnapshot>>,
+    /// Network counters, or None if they couldn't be collected
+    pub network: Option<NetworkSnapshot>,
+    /// Temperatures, or None if they couldn't be collected
+    pub temperature: Option<TemperatureSnapshot>,
+    /// Power source, or None if the snapshot wasn't collected with [`MetricsSnapshot::collect_with_options`] or it
+    /// couldn't be read
+    pub power: Option<PowerSnapshot>,
+    /// Values computed by a [`DerivedMetrics`] registry, keyed by metric name
+    pub derived: HashMap<String, f64>,
+    /// Problems encountered while collecting the snapshot
+    pub warnings: Vec<String>,
+}
+
+impl MetricsSnapshot {
+    /// Collects a new snapshot of the current system state.
+    ///
+    /// Sections that can't be collected are left as None and the reason is added to `warnings`.
+    pub fn collect() -> Self {
+        let mut snapshot = Self { timestamp_ms: now_ms(), ..Self::default() };
+
+        match collect_cpu() {
+            Ok(cpu) => snapshot.cpu = Some(cpu),
+            Err(e) => snapshot.warnings.push(format!("cpu: {e}")),
+        }
+
+        match Memory::new() {
+            Ok(memory) => snapshot.memory = Some(MemorySnapshot::from(&memory)),
+            Err(e) => snapshot.warnings.push(format!("memory: {e}")),
+        }
+
+        snapshot.display_brightness = builtin_display_brightness();
+
+        match collect_disk_io() {
+            Ok(disk_io) => snapshot.disk_io = Some(disk_io),
+            Err(e) => snapshot.warnings.push(format!("disk_io: {e}")),
+        }
+
+        match collect_network() {
+            Ok(network) => snapshot.network = Some(network),
+            Err(e) => snapshot.warnings.push(format!("network: {e}")),
+        }
+
+        match collect_temperature() {
+            Ok(temperature) => snapshot.temperature = Some(temperature),
+            Err(e) => snapshot.warnings.push(format!("temperature: {e}")),
+        }
+
+        snapshot
+    }
+
+    /// Collects a new snapshot that also lists every running process, for comparing with [`MetricsSnapshot::diff`].
+    ///
+    /// The process list makes the snapshot considerably slower to collect and larger to export than [`collect`].
+    ///
+    /// [`collect`]: MetricsSnapshot::collect
+    pub fn collect_full() -> Self {
+        let mut snapshot = Self::collect();
+        match collect_processes() {
+            Ok(processes) => snapshot.processes = Some(processes),
+            Err(e) => snapshot.warnings.push(format!("processes: {e}")),
+        }
+        snapshot
+    }
+
+    /// Collects a new snapshot and evaluates the given derived metrics on it.
+    pub fn collect_with(derived: &DerivedMetrics) -> Self {
+        let mut snapshot = Self::collect();
+        derived.apply(&mut snapshot);
+        snapshot
+    }
+
+    /// Collects a new snapshot that also holds the system information, the disk space of each volume, the power
+    /// source, and the processes `options` asks for.
+    pub fn collect_with_options(options: &SnapshotOptions) -> Self {
+        let mut snapshot = Self::collect();
+
+        match collect_system() {
+            Ok(system) => snapshot.system = Some(system),
+            Err(e) => snapshot.warnings.push(format!("system: {e}")),
+        }
+
+        match Disk::get_all() {
+            Ok(disks) => snapshot.volumes = Some(disks.iter().map(VolumeSnapshot::from).collect()),
+            Err(e) => snapshot.warnings.push(format!("volumes: {e}")),
+        }
+
+        match collect_power() {
+            Ok(power) => snapshot.power = Some(power),
+            Err(e) => snapshot.warnings.push(format!("power: {e}")),
+        }
+
+        if options.top_processes > 0 {
+            match sample_processes() {
+                Ok(samples) => {
+                    snapshot.top_processes = Some(top_by_cpu(samples, options.top_processes))
+                },
+                Err(e) => snapshot.warnings.push(format!("top_processes: {e}")),
+            }
+        }
+
+        snapshot
+    }
+}
+
+/// Collects a [`MetricsSnapshot`] of every section except the processes, see
+/// [`MetricsSnapshot::collect_with_options`].
+///
+/// Sections that can't be collected are left as None and the reason is added to [`MetricsSnapshot::warnings`].
+pub fn snapshot() -> MetricsSnapshot {
+    MetricsSnapshot::collect_with_options(&SnapshotOptions::default())
+}
+
+/// Collects a [`MetricsSnapshot`] like [`snapshot()`] on a blocking task.
+///
+/// # Errors
+///
+/// Returns an error if the collecting task panicked.
+pub async fn snapshot_async() -> Result<MetricsSnapshot> {
+    tokio::task::spawn_blocking(snapshot)
+        .await
+        .map_err(|e| Error::system(format!("Task join error: {}", e)))
+}
+
+fn collect_system() -> Result<SystemSnapshot> {
+    let info = init::system_info()?;
+
+    Ok(SystemSnapshot {
+        model: sysctl_string("hw.model")?,
+        os_version: sysctl_string("kern.osproductversion")?,
+        os_build: sysctl_string("kern.osversion")?,
+        architecture: format!("{:?}", info.architecture),
+        cpu_model: info.cpu_model.clone(),
+        uptime_secs: uptime()?.as_secs(),
+    })
+}
+
+fn collect_power() -> Result<PowerSnapshot> {
+    let source = Power::new().power_source()?;
+
+    Ok(PowerSnapshot {
+        state: format!("{:?}", source.state),
+        battery_percentage: source.battery_percentage,
+        time_remaining_secs: source.time_remaining.map(|remaining| remaining.as_secs()),
+        low_power_mode: is_low_power_mode(),
+    })
+}
+
+fn collect_cpu() -> Result<CpuSnapshot> {
+    let cpu = CPU::new()?;
+
+    Ok(CpuSnapshot {
+        physical_cores: cpu.physical_cores(),
+        logical_cores: cpu.logical_cores(),
+        usage: cpu.get_cpu_usage(),
+        frequency_mhz: cpu.frequency_mhz(),
+        load_average: load_average(),
+        times: cpu_times(),
+    })
+}
+
+fn cpu_times() -> Option<CpuTimes> {
+    let mut info = host_cpu_load_info::default();
+    let mut count = HOST_CPU_LOAD_INFO_COUNT;
+
+    let result = unsafe {
+        host_statistics(
+            mach_host_self(),
+            HOST_CPU_LOAD_INFO,
+            (&mut info as *mut host_cpu_load_info) as HostInfoT,
+            &mut count,
+        )
+    };
+    if result != KERN_SUCCESS {
+        return None;
+    }
+
+    let secs = |state: usize| info.cpu_ticks[state] as f64 / CPU_TICKS_PER_SEC;
+    Some(CpuTimes {
+        user_secs: secs(CPU_STATE_USER),
+        system_secs: secs(CPU_STATE_SYSTEM),
+        idle_secs: secs(CPU_STATE_IDLE),
+        nice_secs: secs(CPU_STATE_NICE),
+    })
+}
+
+fn collect_processes() -> Result<Vec<ProcessSnapshot>> {
+    Ok(sample_processes()?.into_iter().map(process_snapshot).collect())
+}
+
+/// The `n` processes with the highest average CPU usage since they started, busiest first
+pub(crate) fn top_by_cpu(samples: Vec<ProcessSample>, n: usize) -> Vec<ProcessSnapshot> {
+    let average_cpu = |sample: &ProcessSample| {
+        sanitize_rate(sample.cpu_time.as_secs_f64(), sample.age.as_secs_f64())
+    };
+
+    let mut ranked = samples;
+    ranked.sort_by(|a, b| average_cpu(b).total_cmp(&average_cpu(a)));
+    ranked.into_iter().take(n).map(process_snapshot).collect()
+}
+
+fn process_snapshot(sample: ProcessSample) -> ProcessSnapshot {
+    ProcessSnapshot {
+        pid: sample.identity.pid(),
+        start_time_us: sample
+            .identity
+            .start_time()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0),
+        name: sample.name,
+        cpu_secs: sample.cpu_time.as_secs_f64(),
+        resident_bytes: sample.resident_bytes,
+    }
+}
+
+fn collect_disk_io() -> Result<DiskIoSnapshot> {
+    let devices = BlockStorageSampler.sample()?;
+    if devices.is_empty() {
+        return Err(Error::not_available("No block storage devices found"));
+    }
+
+    Ok(devices.iter().fold(DiskIoSnapshot::default(), |total, device| DiskIoSnapshot {
+        bytes_read: total.bytes_read.saturating_add(device.bytes_read),
+        bytes_written: total.bytes_written.saturating_add(device.bytes_written),
+    }))
+}
+
+fn collect_network() -> Result<NetworkSnapshot> {
+    let manager = NetworkManager::new()?;
+    let interfaces: Vec<_> =
+        manager.interfaces().into_iter().filter(|interface| !interface.is_loopback()).collect();
+    if interfaces.is_empty() {
+        return Err(Error::not_available("No network interfaces found"));
+    }
+
+    Ok(interfaces.iter().fold(NetworkSnapshot::default(), |total, interface| NetworkSnapshot {
+        bytes_received: total.bytes_received.saturating_add(interface.bytes_received()),
+        bytes_sent: total.bytes_sent.saturating_add(interface.bytes_sent()),
+    }))
+}
+
+fn collect_temperature() -> Result<TemperatureSnapshot> {
+    let info = IOKitImpl::default().get_thermal_info()?;
+
+    Ok(TemperatureSnapshot { cpu_celsius: info.cpu_temp, gpu_celsius: info.gpu_temp })
+}
+
+fn load_average() -> Option<[f64; 3]> {
+    let mut loads = [0.0f64; 3];
+    // SAFETY: the buffer has room for the three requested values.
+    let count = unsafe { getloadavg(loads.as_mut_ptr(), 3) };
+    (count == 3).then_some(loads)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
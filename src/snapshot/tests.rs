@@ -0,0 +1,497 @@
+use super::*;
+
+fn fixture_snapshot() -> MetricsSnapshot {
+    MetricsSnapshot {
+        timestamp_ms: 1_700_000_000_000,
+        cpu: Some(CpuSnapshot {
+            physical_cores: 8,
+            logical_cores: 8,
+            usage: 0.5,
+            frequency_mhz: 3200.0,
+            load_average: Some([4.0, 3.0, 2.0]),
+            times: None,
+        }),
+        memory: Some(MemorySnapshot {
+            total: 16 * 1024 * 1024 * 1024,
+            used: 12 * 1024 * 1024 * 1024,
+            available: 4 * 1024 * 1024 * 1024,
+            wired: 2 * 1024 * 1024 * 1024,
+            compressed: 4 * 1024 * 1024 * 1024,
+            pressure: 0.6,
+            swap_total: 2 * 1024 * 1024 * 1024,
+            swap_used: 1024 * 1024 * 1024,
+            swap_ins_per_sec: 250.0,
+            swap_outs_per_sec: 250.0,
+        }),
+        display_brightness: Some(0.5),
+        ..MetricsSnapshot::default()
+    }
+}
+
+#[test]
+fn test_builtin_cpu_saturation() {
+    let snapshot = fixture_snapshot();
+    assert_eq!(cpu_saturation(&snapshot), Some(0.5));
+
+    let mut no_load = snapshot.clone();
+    no_load.cpu.as_mut().unwrap().load_average = None;
+    assert_eq!(cpu_saturation(&no_load), None);
+
+    let mut no_cores = snapshot;
+    no_cores.cpu.as_mut().unwrap().logical_cores = 0;
+    assert_eq!(cpu_saturation(&no_cores), None);
+}
+
+#[test]
+fn test_builtin_memory_pressure_score() {
+    let snapshot = fixture_snapshot();
+    // 0.6 * 0.5 + (500 / 1000) * 0.3 + 0.25 * 0.2 = 0.5
+    let score = memory_pressure_score(&snapshot).unwrap();
+    assert!((score - 50.0).abs() < 1e-9, "unexpected score {score}");
+
+    assert_eq!(memory_pressure_score(&MetricsSnapshot::default()), None);
+}
+
+#[test]
+fn test_register_replaces_existing_metric() {
+    let mut derived = DerivedMetrics::with_builtins();
+    assert_eq!(derived.len(), 2);
+
+    derived.register(CPU_SATURATION, |_| Some(42.0));
+    assert_eq!(derived.len(), 2);
+
+    let mut snapshot = fixture_snapshot();
+    derived.apply(&mut snapshot);
+    assert_eq!(snapshot.derived.get(CPU_SATURATION), Some(&42.0));
+
+    assert!(derived.unregister(CPU_SATURATION));
+    assert!(!derived.unregister(CPU_SATURATION));
+    assert_eq!(derived.names().collect::<Vec<_>>(), vec![MEMORY_PRESSURE_SCORE]);
+}
+
+#[test]
+fn test_panicking_metric_is_isolated() {
+    let mut derived = DerivedMetrics::new();
+    derived.register("before", |_| Some(1.0));
+    derived.register("boom", |_| panic!("user closure failed"));
+    derived.register("after", |_| Some(2.0));
+
+    let mut snapshot = fixture_snapshot();
+    derived.apply(&mut snapshot);
+
+    assert_eq!(snapshot.derived.len(), 2);
+    assert_eq!(snapshot.derived.get("before"), Some(&1.0));
+    assert_eq!(snapshot.derived.get("after"), Some(&2.0));
+    assert!(!snapshot.derived.contains_key("boom"));
+
+    assert_eq!(snapshot.warnings.len(), 1);
+    assert!(snapshot.warnings[0].contains("'boom' panicked"));
+    assert!(snapshot.warnings[0].contains("user closure failed"));
+}
+
+#[test]
+fn test_missing_and_non_finite_values() {
+    let mut derived = DerivedMetrics::new();
+    derived.register("missing", |_| None);
+    derived.register("nan", |_| Some(f64::NAN));
+
+    let mut snapshot = fixture_snapshot();
+    derived.apply(&mut snapshot);
+
+    assert!(snapshot.derived.is_empty());
+    assert_eq!(snapshot.warnings.len(), 1);
+    assert!(snapshot.warnings[0].contains("'nan' produced a non-finite value"));
+}
+
+#[test]
+fn test_evaluation_is_order_independent() {
+    let mut forward = DerivedMetrics::new();
+    forward.register("a", |_| Some(1.0));
+    // Derived metrics never observe each other, regardless of registration order
+    forward.register("b", |s: &MetricsSnapshot| Some(s.derived.len() as f64));
+
+    let mut reverse = DerivedMetrics::new();
+    reverse.register("b", |s: &MetricsSnapshot| Some(s.derived.len() as f64));
+    reverse.register("a", |_| Some(1.0));
+
+    let mut first = fixture_snapshot();
+    forward.apply(&mut first);
+    let mut second = fixture_snapshot();
+    reverse.apply(&mut second);
+
+    assert_eq!(first.derived, second.derived);
+    assert_eq!(first.derived.get("b"), Some(&0.0));
+
+    // Re-applying discards previous values instead of feeding them back in
+    forward.apply(&mut first);
+    assert_eq!(first.derived, second.derived);
+}
+
+#[test]
+fn test_derived_section_serialization() {
+    let mut snapshot = fixture_snapshot();
+    DerivedMetrics::with_builtins().apply(&mut snapshot);
+
+    let json = serde_json::to_value(&snapshot).unwrap();
+    assert_eq!(json["derived"][CPU_SATURATION], 0.5);
+    let score = json["derived"][MEMORY_PRESSURE_SCORE].as_f64().unwrap();
+    assert!((score - 50.0).abs() < 1e-9);
+
+    let restored: MetricsSnapshot = serde_json::from_value(json).unwrap();
+    assert_eq!(restored, snapshot);
+}
+
+#[test]
+fn test_display_brightness_is_optional_when_deserializing() {
+    let mut json = serde_json::to_value(fixture_snapshot()).unwrap();
+    assert_eq!(json["display_brightness"], 0.5);
+
+    // Snapshots serialized before the field existed still deserialize
+    json.as_object_mut().unwrap().remove("display_brightness");
+    let restored: MetricsSnapshot = serde_json::from_value(json).unwrap();
+    assert_eq!(restored.display_brightness, None);
+}
+
+const MIB: u64 = 1024 * 1024;
+
+fn process(
+    pid: u32,
+    start_time_us: u64,
+    name: &str,
+    cpu_secs: f64,
+    resident_mib: u64,
+) -> ProcessSnapshot {
+    ProcessSnapshot {
+        pid,
+        start_time_us,
+        name: name.to_string(),
+        cpu_secs,
+        resident_bytes: resident_mib * MIB,
+    }
+}
+
+/// Snapshots taken before and after a workload, with every section present
+fn fixture_pair() -> (MetricsSnapshot, MetricsSnapshot) {
+    let mut before = fixture_snapshot();
+    before.cpu.as_mut().unwrap().times =
+        Some(CpuTimes { user_secs: 1000.0, system_secs: 500.0, idle_secs: 8000.0, nice_secs: 2.0 });
+    before.processes = Some(vec![
+        process(100, 1_000, "WindowServer", 3600.0, 800),
+        process(200, 2_000, "python3", 10.0, 100),
+        process(300, 3_000, "Safari", 600.0, 2048),
+    ]);
+    before.disk_io = Some(DiskIoSnapshot { bytes_read: 100 * MIB, bytes_written: 50 * MIB });
+    before.network = Some(NetworkSnapshot { bytes_received: 10 * MIB, bytes_sent: 5 * MIB });
+    before.temperature = Some(TemperatureSnapshot { cpu_celsius: 45.0, gpu_celsius: Some(40.0) });
+    before.system = Some(SystemSnapshot {
+        model: "Mac14,10".to_string(),
+        os_version: "14.5".to_string(),
+        os_build: "23F79".to_string(),
+        architecture: "AppleSilicon".to_string(),
+        cpu_model: "Apple M2 Pro".to_string(),
+        uptime_secs: 86_400,
+    });
+    before.volumes = Some(vec![VolumeSnapshot {
+        mount_point: "/".to_string(),
+        fs_type: "apfs".to_string(),
+        total_bytes: 500 * 1024 * MIB,
+        used_bytes: 200 * 1024 * MIB,
+        available_bytes: 300 * 1024 * MIB,
+    }]);
+    before.power = Some(PowerSnapshot {
+        state: "Battery".to_string(),
+        battery_percentage: Some(87.5),
+        time_remaining_secs: Some(5400),
+        low_power_mode: Some(false),
+    });
+
+    let mut after = before.clone();
+    after.timestamp_ms += 60_500;
+    after.cpu.as_mut().unwrap().times =
+        Some(CpuTimes { user_secs: 1030.0, system_secs: 510.0, idle_secs: 8400.0, nice_secs: 2.0 });
+    let memory = after.memory.as_mut().unwrap();
+    memory.used += 1024 * MIB;
+    memory.available -= 1024 * MIB;
+    memory.compressed += 512 * MIB;
+    memory.swap_used += 256 * MIB;
+    after.processes = Some(vec![
+        process(100, 1_000, "WindowServer", 3601.5, 790),
+        process(200, 2_000, "python3", 55.25, 356),
+        // PID 300 was reused by another process
+        process(300, 9_000, "cargo", 4.0, 64),
+        process(400, 8_000, "rustc", 12.0, 512),
+    ]);
+    after.top_processes = Some(vec![process(200, 2_000, "python3", 55.25, 356)]);
+    after.disk_io = Some(DiskIoSnapshot { bytes_read: 110 * MIB, bytes_written: 55 * MIB });
+    after.network = Some(NetworkSnapshot { bytes_received: 11 * MIB, bytes_sent: 5 * MIB + 2048 });
+    after.temperature = Some(TemperatureSnapshot { cpu_celsius: 70.0, gpu_celsius: Some(38.5) });
+
+    (before, after)
+}
+
+#[test]
+fn test_diff_every_section() {
+    let (before, after) = fixture_pair();
+    let diff = before.diff(&after);
+
+    assert_eq!(diff.elapsed_ms, Some(60_500));
+    assert_eq!(
+        diff.cpu_time,
+        Some(CpuTimeDelta {
+            user_secs: Some(30.0),
+            system_secs: Some(10.0),
+            idle_secs: Some(400.0),
+            nice_secs: Some(0.0),
+        })
+    );
+    assert_eq!(
+        diff.memory,
+        Some(MemoryDelta {
+            used_bytes: 1024 * MIB as i64,
+            available_bytes: -1024 * MIB as i64,
+            wired_bytes: 0,
+            compressed_bytes: 512 * MIB as i64,
+        })
+    );
+    assert_eq!(diff.swap_growth_bytes, Some(256 * MIB as i64));
+    assert_eq!(diff.disk_bytes_read, Some(10 * MIB));
+    assert_eq!(diff.disk_bytes_written, Some(5 * MIB));
+    assert_eq!(diff.network_bytes_received, Some(MIB));
+    assert_eq!(diff.network_bytes_sent, Some(2048));
+    assert_eq!(
+        diff.cpu_temperature,
+        Some(TemperatureRange { min_celsius: 45.0, max_celsius: 70.0 })
+    );
+    assert_eq!(
+        diff.gpu_temperature,
+        Some(TemperatureRange { min_celsius: 38.5, max_celsius: 40.0 })
+    );
+
+    let processes = diff.processes.unwrap();
+    assert_eq!(
+        processes.changed,
+        vec![
+            ProcessDelta {
+                pid: 200,
+                name: "python3".to_string(),
+                cpu_secs: Some(45.25),
+                resident_bytes: 256 * MIB as i64,
+            },
+            ProcessDelta {
+                pid: 100,
+                name: "WindowServer".to_string(),
+                cpu_secs: Some(1.5),
+                resident_bytes: -10 * MIB as i64,
+            },
+        ]
+    );
+    // Processes are matched by PID and start time, so the reused PID 300 is a different process
+    assert_eq!(
+        processes.started,
+        vec![process(300, 9_000, "cargo", 4.0, 64), process(400, 8_000, "rustc", 12.0, 512)]
+    );
+    assert_eq!(processes.exited, vec![process(300, 3_000, "Safari", 600.0, 2048)]);
+}
+
+#[test]
+fn test_diff_missing_sections_and_wrapped_counters() {
+    let (before, mut after) = fixture_pair();
+    after.timestamp_ms = before.timestamp_ms - 1;
+    after.cpu.as_mut().unwrap().times.as_mut().unwrap().user_secs = 5.0;
+    after.memory = None;
+    after.processes = None;
+    after.disk_io.as_mut().unwrap().bytes_read = 0;
+    after.network = None;
+    after.temperature.as_mut().unwrap().gpu_celsius = None;
+
+    let diff = before.diff(&after);
+
+    assert_eq!(diff.elapsed_ms, None);
+    let cpu_time = diff.cpu_time.unwrap();
+    assert_eq!(cpu_time.user_secs, None);
+    assert_eq!(cpu_time.system_secs, Some(10.0));
+    assert_eq!(diff.memory, None);
+    assert_eq!(diff.swap_growth_bytes, None);
+    assert_eq!(diff.processes, None);
+    assert_eq!(diff.disk_bytes_read, None);
+    assert_eq!(diff.disk_bytes_written, Some(5 * MIB));
+    assert_eq!(diff.network_bytes_received, None);
+    assert_eq!(diff.network_bytes_sent, None);
+    assert!(diff.cpu_temperature.is_some());
+    assert_eq!(diff.gpu_temperature, None);
+
+    let empty = MetricsSnapshot::default().diff(&MetricsSnapshot::default());
+    assert_eq!(empty, SnapshotDiff { elapsed_ms: Some(0), ..SnapshotDiff::default() });
+}
+
+#[test]
+fn test_diff_display() {
+    let (before, after) = fixture_pair();
+
+    assert_eq!(
+        before.diff(&after).to_string(),
+        "\
+Elapsed: 60.50 s
+CPU time: user 30.00 s, system 10.00 s, idle 400.00 s, nice 0.00 s
+Memory: used +1.0 GB, available -1.0 GB, wired +0 bytes, compressed +512.0 MB
+Swap: +256.0 MB
+Disk: read 10.0 MB, written 5.0 MB
+Network: received 1.0 MB, sent 2.0 KB
+CPU temperature: 45.0 to 70.0 °C
+GPU temperature: 38.5 to 40.0 °C
+Processes: 2 in both, 2 started, 1 exited
+  200 python3: 45.25 s CPU, +256.0 MB
+  100 WindowServer: 1.50 s CPU, -10.0 MB
+  started: 300 cargo
+  started: 400 rustc
+  exited: 300 Safari"
+    );
+
+    let missing =
+        MetricsSnapshot::default().diff(&MetricsSnapshot { timestamp_ms: 1, ..Default::default() });
+    assert_eq!(
+        missing.to_string(),
+        "\
+Elapsed: 0.00 s
+CPU time: n/a
+Memory: n/a
+Swap: n/a
+Disk: read n/a, written n/a
+Network: received n/a, sent n/a
+CPU temperature: n/a
+GPU temperature: n/a
+Processes: n/a"
+    );
+}
+
+#[test]
+fn test_diff_serialization() {
+    let (before, after) = fixture_pair();
+    let diff = before.diff(&after);
+
+    let json = serde_json::to_value(&diff).unwrap();
+    assert_eq!(json["elapsed_ms"], 60_500);
+    assert_eq!(json["processes"]["exited"][0]["name"], "Safari");
+
+    let restored: SnapshotDiff = serde_json::from_value(json).unwrap();
+    assert_eq!(restored, diff);
+}
+
+#[test]
+fn test_new_sections_are_optional_when_deserializing() {
+    let (_, after) = fixture_pair();
+    let mut json = serde_json::to_value(&after).unwrap();
+
+    for section in [
+        "system",
+        "processes",
+        "top_processes",
+        "disk_io",
+        "volumes",
+        "network",
+        "temperature",
+        "power",
+    ] {
+        json.as_object_mut().unwrap().remove(section);
+    }
+    json["cpu"].as_object_mut().unwrap().remove("times");
+    let restored: MetricsSnapshot = serde_json::from_value(json).unwrap();
+    assert_eq!(restored.processes, None);
+    assert_eq!(restored.power, None);
+    assert_eq!(restored.cpu.unwrap().times, None);
+}
+
+#[test]
+fn test_fixed_snapshot() {
+    let (_, mut snapshot) = fixture_pair();
+    snapshot.derived.insert("swap_used_ratio".to_string(), 0.625);
+    snapshot.temperature = Some(TemperatureSnapshot { cpu_celsius: -3.2, gpu_celsius: None });
+    let fixed = snapshot.to_fixed();
+
+    let cpu = fixed.cpu.unwrap();
+    assert_eq!(cpu.usage_bp, 5000);
+    assert_eq!(cpu.frequency_khz, 3_200_000);
+    assert_eq!(cpu.load_average_milli, Some([4000, 3000, 2000]));
+    assert_eq!(cpu.times.unwrap().user_ms, 1_030_000);
+    assert_eq!(fixed.memory.unwrap().pressure_bp, 6000);
+    assert_eq!(fixed.display_brightness_bp, Some(5000));
+    assert_eq!(fixed.processes.unwrap()[1].cpu_ms, 55_250);
+    assert_eq!(fixed.disk_io, snapshot.disk_io);
+    assert_eq!(
+        fixed.temperature,
+        Some(FixedTemperatureSnapshot { cpu_millic: -3200, gpu_millic: None })
+    );
+    assert_eq!(fixed.derived_milli["swap_used_ratio"], 625);
+    assert_eq!(fixed.top_processes.unwrap()[0].cpu_ms, 55_250);
+    assert_eq!(fixed.volumes, snapshot.volumes);
+    assert_eq!(
+        fixed.power,
+        Some(FixedPowerSnapshot {
+            state: "Battery".to_string(),
+            battery_bp: Some(8750),
+            time_remaining_secs: Some(5400),
+            low_power_mode: Some(false),
+        })
+    );
+}
+
+#[test]
+fn test_to_json_number_formats() {
+    let (_, mut snapshot) = fixture_pair();
+    for (i, name) in ["c", "a", "b", "d"].into_iter().enumerate() {
+        snapshot.derived.insert(name.to_string(), i as f64 / 4.0);
+    }
+
+    let float: MetricsSnapshot =
+        serde_json::from_str(&snapshot.to_json(NumberFormat::Float).unwrap()).unwrap();
+    assert_eq!(float, snapshot);
+
+    let json = snapshot.to_json(NumberFormat::Fixed).unwrap();
+    fn assert_no_floats(value: &serde_json::Value) {
+        match value {
+            serde_json::Value::Number(number) => assert!(!number.is_f64(), "{number} is a float"),
+            serde_json::Value::Array(values) => values.iter().for_each(assert_no_floats),
+            serde_json::Value::Object(map) => map.values().for_each(assert_no_floats),
+            _ => {},
+        }
+    }
+    assert_no_floats(&serde_json::from_str(&json).unwrap());
+    let fixed: FixedMetricsSnapshot = serde_json::from_str(&json).unwrap();
+    assert_eq!(fixed, snapshot.to_fixed());
+
+    // Equal snapshots serialize to equal bytes, whatever the order derived metrics were inserted in
+    let mut reordered = snapshot.clone();
+    let mut entries: Vec<_> = snapshot.derived.clone().into_iter().collect();
+    entries.reverse();
+    reordered.derived = entries.into_iter().collect();
+    assert_eq!(reordered.to_json(NumberFormat::Fixed).unwrap(), json);
+}
+
+#[test]
+fn test_top_by_cpu() {
+    let sample = |pid: u32, name: &str, cpu_secs: u64, age_secs: u64| ProcessSample {
+        identity: crate::process::ProcessIdentity::new(
+            pid,
+            UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000),
+            Some(pid as u64),
+        ),
+        name: name.to_string(),
+        age: std::time::Duration::from_secs(age_secs),
+        cpu_time: std::time::Duration::from_secs(cpu_secs),
+        resident_bytes: 4096,
+        threads: None,
+        fds: None,
+    };
+    let samples = vec![
+        sample(100, "WindowServer", 3600, 36_000),
+        sample(200, "python3", 50, 60),
+        sample(300, "Safari", 600, 1200),
+        sample(400, "launchd", 0, 0),
+    ];
+
+    let top = top_by_cpu(samples, 3);
+    assert_eq!(top.iter().map(|p| p.pid).collect::<Vec<_>>(), vec![200, 300, 100]);
+    assert_eq!(top[0].name, "python3");
+    assert_eq!(top[0].cpu_secs, 50.0);
+    assert_eq!(top[0].start_time_us, 1_700_000_000_000_000);
+}
@@ -0,0 +1,425 @@
+//! Camera and microphone in-use indicators.
+//!
+//! CoreMediaIO and CoreAudio report for each device whether any process is running it
+//! (`kCMIODevicePropertyDeviceIsRunningSomewhere`, `kAudioDevicePropertyDeviceIsRunningSomewhere`). Reading the
+//! property needs no TCC permission, and neither framework says which process is using the device.
+
+use std::{ffi::c_void as ffi_c_void, mem, ptr};
+
+use objc2::rc::{autoreleasepool, Retained};
+use objc2_foundation::NSString;
+
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{
+        av_properties::{
+            DEVICE_IS_RUNNING_SOMEWHERE, DEVICE_PROPERTY_STREAMS, ELEMENT_MAIN,
+            HARDWARE_PROPERTY_DEVICES, OBJECT_PROPERTY_NAME, SCOPE_GLOBAL, SCOPE_INPUT,
+            SYSTEM_OBJECT,
+        },
+        AudioObjectGetPropertyData, AudioObjectGetPropertyDataSize, CMIOObjectGetPropertyData,
+        CMIOObjectGetPropertyDataSize, ObjectPropertyAddress,
+    },
+};
+
+/// Kind of a capture device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AvDeviceKind {
+    /// Video capture device, listed by CoreMediaIO
+    Camera,
+    /// Audio input device, listed by CoreAudio
+    Microphone,
+}
+
+/// A camera or microphone and whether any process is using it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvDevice {
+    /// Name of the device (e.g. "FaceTime HD Camera")
+    pub name: String,
+    /// Whether the device is a camera or a microphone
+    pub kind: AvDeviceKind,
+    /// Whether any process is running the device
+    pub in_use: bool,
+}
+
+/// Camera and microphone activity of the machine
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AvActivity {
+    /// Whether any camera is in use; None if there is no camera or the devices can't be listed
+    pub camera_in_use: Option<bool>,
+    /// Whether any microphone is in use; None if there is no microphone or the devices can't be listed
+    pub microphone_in_use: Option<bool>,
+    /// The cameras followed by the microphones
+    pub devices: Vec<AvDevice>,
+}
+
+/// Property queries of the capture devices of one framework
+pub(crate) trait AvDeviceSource {
+    /// Kind of the devices this source lists
+    fn kind(&self) -> AvDeviceKind;
+    /// IDs of the devices that capture, e.g. audio devices with input streams
+    fn device_ids(&self) -> Result<Vec<u32>>;
+    /// Name of a device
+    fn name(&self, device: u32) -> Option<String>;
+    /// Whether any process is running a device
+    fn is_running_somewhere(&self, device: u32) -> Result<bool>;
+}
+
+/// Reports which cameras and microphones are in use.
+///
+/// A framework query that fails leaves its `*_in_use` field None instead of failing the call, as does a machine
+/// without such a device. A device whose in-use state can't be read is listed as not in use.
+pub fn av_activity() -> Result<AvActivity> {
+    Ok(collect_activity(&[&CoreMediaIoCameras, &CoreAudioMicrophones]))
+}
+
+/// Collects the activity of the devices of `sources`.
+pub(crate) fn collect_activity(sources: &[&dyn AvDeviceSource]) -> AvActivity {
+    let mut activity = AvActivity::default();
+
+    for source in sources {
+        let kind = source.kind();
+        let ids = match source.device_ids() {
+            Ok(ids) => ids,
+            Err(e) => {
+                tracing::debug!("Failed to list {kind:?} devices: {e}");
+                continue;
+            },
+        };
+
+        let devices: Vec<_> = ids
+            .into_iter()
+            .map(|id| AvDevice {
+                name: source.name(id).unwrap_or_else(|| format!("Device {id}")),
+                kind,
+                in_use: source.is_running_somewhere(id).unwrap_or(false),
+            })
+            .collect();
+
+        let in_use = (!devices.is_empty()).then(|| devices.iter().any(|device| device.in_use));
+        match kind {
+            AvDeviceKind::Camera => activity.camera_in_use = in_use,
+            AvDeviceKind::Microphone => activity.microphone_in_use = in_use,
+        }
+        activity.devices.extend(devices);
+    }
+
+    activity
+}
+
+fn address(selector: u32, scope: u32) -> ObjectPropertyAddress {
+    ObjectPropertyAddress { selector, scope, element: ELEMENT_MAIN }
+}
+
+/// Video devices listed by CoreMediaIO
+#[derive(Debug, Clone, Copy, Default)]
+struct CoreMediaIoCameras;
+
+impl CoreMediaIoCameras {
+    /// Reads a property of `object` into `data`, returning the number of bytes written.
+    fn read(&self, object: u32, address: &ObjectPropertyAddress, data: &mut [u8]) -> Result<u32> {
+        let mut used = 0u32;
+        // SAFETY: the buffer is valid for its length, which is passed along.
+        let status = unsafe {
+            CMIOObjectGetPropertyData(
+                object,
+                address,
+                0,
+                ptr::null(),
+                data.len() as u32,
+                &mut used,
+                data.as_mut_ptr() as *mut ffi_c_void,
+            )
+        };
+        if status != 0 {
+            return Err(Error::system(format!("CMIOObjectGetPropertyData failed: {status}")));
+        }
+        Ok(used)
+    }
+}
+
+impl AvDeviceSource for CoreMediaIoCameras {
+    fn kind(&self) -> AvDeviceKind {
+        AvDeviceKind::Camera
+    }
+
+    fn device_ids(&self) -> Result<Vec<u32>> {
+        let address = address(HARDWARE_PROPERTY_DEVICES, SCOPE_GLOBAL);
+        let mut size = 0u32;
+        // SAFETY: the size is written to a live u32.
+        let status = unsafe {
+            CMIOObjectGetPropertyDataSize(SYSTEM_OBJECT, &address, 0, ptr::null(), &mut size)
+        };
+        if status != 0 {
+            return Err(Error::system(format!("CMIOObjectGetPropertyDataSize failed: {status}")));
+        }
+
+        let mut buffer = vec![0u8; size as usize];
+        let used = self.read(SYSTEM_OBJECT, &address, &mut buffer)? as usize;
+        Ok(device_ids_from_bytes(&buffer[..used.min(buffer.len())]))
+    }
+
+    fn name(&self, device: u32) -> Option<String> {
+        let mut name: *mut ffi_c_void = ptr::null_mut();
+        let mut used = 0u32;
+        // SAFETY: the name is written to a live pointer-sized buffer; it's returned with a +1 retain count.
+        let status = unsafe {
+            CMIOObjectGetPropertyData(
+                device,
+                &address(OBJECT_PROPERTY_NAME, SCOPE_GLOBAL),
+                0,
+                ptr::null(),
+                mem::size_of::<*mut ffi_c_void>() as u32,
+                &mut used,
+                &mut name as *mut *mut ffi_c_void as *mut ffi_c_void,
+            )
+        };
+        if status != 0 {
+            return None;
+        }
+        cf_string(name)
+    }
+
+    fn is_running_somewhere(&self, device: u32) -> Result<bool> {
+        let mut buffer = [0u8; 4];
+        self.read(device, &address(DEVICE_IS_RUNNING_SOMEWHERE, SCOPE_GLOBAL), &mut buffer)?;
+        Ok(u32::from_ne_bytes(buffer) != 0)
+    }
+}
+
+/// Audio input devices listed by CoreAudio
+#[derive(Debug, Clone, Copy, Default)]
+struct CoreAudioMicrophones;
+
+impl CoreAudioMicrophones {
+    fn data_size(&self, object: u32, address: &ObjectPropertyAddress) -> Result<u32> {
+        let mut size = 0u32;
+        // SAFETY: the size is written to a live u32.
+        let status =
+            unsafe { AudioObjectGetPropertyDataSize(object, address, 0, ptr::null(), &mut size) };
+        if status != 0 {
+            return Err(Error::system(format!("AudioObjectGetPropertyDataSize failed: {status}")));
+        }
+        Ok(size)
+    }
+
+    /// Reads a property of `object` into `data`, returning the number of bytes written.
+    fn read(&self, object: u32, address: &ObjectPropertyAddress, data: &mut [u8]) -> Result<u32> {
+        let mut size = data.len() as u32;
+        // SAFETY: the buffer is valid for `size` bytes.
+        let status = unsafe {
+            AudioObjectGetPropertyData(
+                object,
+                address,
+                0,
+                ptr::null(),
+                &mut size,
+                data.as_mut_ptr() as *mut ffi_c_void,
+            )
+        };
+        if status != 0 {
+            return Err(Error::system(format!("AudioObjectGetPropertyData failed: {status}")));
+        }
+        Ok(size)
+    }
+
+    /// Whether an audio device has input streams.
+    fn has_input(&self, device: u32) -> bool {
+        self.data_size(device, &address(DEVICE_PROPERTY_STREAMS, SCOPE_INPUT))
+            .is_ok_and(|size| size > 0)
+    }
+}
+
+impl AvDeviceSource for CoreAudioMicrophones {
+    fn kind(&self) -> AvDeviceKind {
+        AvDeviceKind::Microphone
+    }
+
+    fn device_ids(&self) -> Result<Vec<u32>> {
+        let address = address(HARDWARE_PROPERTY_DEVICES, SCOPE_GLOBAL);
+        let mut buffer = vec![0u8; self.data_size(SYSTEM_OBJECT, &address)? as usize];
+        let used = self.read(SYSTEM_OBJECT, &address, &mut buffer)? as usize;
+
+        Ok(device_ids_from_bytes(&buffer[..used.min(buffer.len())])
+            .into_iter()
+            .filter(|&device| self.has_input(device))
+            .collect())
+    }
+
+    fn name(&self, device: u32) -> Option<String> {
+        let mut name: *mut ffi_c_void = ptr::null_mut();
+        let mut size = mem::size_of::<*mut ffi_c_void>() as u32;
+        // SAFETY: the name is written to a live pointer-sized buffer; it's returned with a +1 retain count.
+        let status = unsafe {
+            AudioObjectGetPropertyData(
+                device,
+                &address(OBJECT_PROPERTY_NAME, SCOPE_GLOBAL),
+                0,
+                ptr::null(),
+                &mut size,
+                &mut name as *mut *mut ffi_c_void as *mut ffi_c_void,
+            )
+        };
+        if status != 0 {
+            return None;
+        }
+        cf_string(name)
+    }
+
+    fn is_running_somewhere(&self, device: u32) -> Result<bool> {
+        let mut buffer = [0u8; 4];
+        self.read(device, &address(DEVICE_IS_RUNNING_SOMEWHERE, SCOPE_GLOBAL), &mut buffer)?;
+        Ok(u32::from_ne_bytes(buffer) != 0)
+    }
+}
+
+/// Splits a property value holding an array of object IDs.
+pub(crate) fn device_ids_from_bytes(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(mem::size_of::<u32>())
+        .map(|chunk| u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+/// Takes ownership of a CFString returned with a +1 retain count.
+fn cf_string(string: *mut ffi_c_void) -> Option<String> {
+    autoreleasepool(|_| {
+        // SAFETY: CFString is toll-free bridged to NSString, and the caller passes on its reference.
+        let string = unsafe { Retained::from_raw(string as *mut NSString) }?;
+        Some(string.to_string()).filter(|name| !name.trim().is_empty())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A device of a [`FakeSource`]: ID, name and in-use state, None where the query fails
+    type FakeDevice = (u32, Option<&'static str>, Option<bool>);
+
+    /// Device source answering from a table; None devices means the framework can't be queried
+    struct FakeSource {
+        kind: AvDeviceKind,
+        devices: Option<Vec<FakeDevice>>,
+    }
+
+    impl FakeSource {
+        fn device(&self, id: u32) -> Option<&FakeDevice> {
+            self.devices.as_ref()?.iter().find(|device| device.0 == id)
+        }
+    }
+
+    impl AvDeviceSource for FakeSource {
+        fn kind(&self) -> AvDeviceKind {
+            self.kind
+        }
+
+        fn device_ids(&self) -> Result<Vec<u32>> {
+            let devices =
+                self.devices.as_ref().ok_or_else(|| Error::system("No such framework"))?;
+            Ok(devices.iter().map(|device| device.0).collect())
+        }
+
+        fn name(&self, device: u32) -> Option<String> {
+            self.device(device)?.1.map(str::to_string)
+        }
+
+        fn is_running_somewhere(&self, device: u32) -> Result<bool> {
+            self.device(device)
+                .and_then(|device| device.2)
+                .ok_or_else(|| Error::system("Property query failed"))
+        }
+    }
+
+    fn cameras(devices: Vec<FakeDevice>) -> FakeSource {
+        FakeSource { kind: AvDeviceKind::Camera, devices: Some(devices) }
+    }
+
+    fn microphones(devices: Vec<FakeDevice>) -> FakeSource {
+        FakeSource { kind: AvDeviceKind::Microphone, devices: Some(devices) }
+    }
+
+    #[test]
+    fn test_collect_activity() {
+        let cameras = cameras(vec![(10, Some("FaceTime HD Camera"), Some(true))]);
+        let microphones = microphones(vec![
+            (20, Some("MacBook Pro Microphone"), Some(false)),
+            (21, Some("External Microphone"), Some(false)),
+        ]);
+
+        let activity = collect_activity(&[&cameras, &microphones]);
+
+        assert_eq!(activity.camera_in_use, Some(true));
+        assert_eq!(activity.microphone_in_use, Some(false));
+        assert_eq!(
+            activity.devices,
+            [
+                AvDevice {
+                    name: "FaceTime HD Camera".to_string(),
+                    kind: AvDeviceKind::Camera,
+                    in_use: true
+                },
+                AvDevice {
+                    name: "MacBook Pro Microphone".to_string(),
+                    kind: AvDeviceKind::Microphone,
+                    in_use: false
+                },
+                AvDevice {
+                    name: "External Microphone".to_string(),
+                    kind: AvDeviceKind::Microphone,
+                    in_use: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collect_activity_any_device_in_use() {
+        let microphones =
+            microphones(vec![(20, Some("Built-in"), Some(false)), (21, Some("USB"), Some(true))]);
+        assert_eq!(collect_activity(&[&microphones]).microphone_in_use, Some(true));
+    }
+
+    #[test]
+    fn test_collect_activity_no_devices() {
+        // A Mac mini without a camera
+        let cameras = cameras(vec![]);
+        let microphones = FakeSource { kind: AvDeviceKind::Microphone, devices: None };
+
+        let activity = collect_activity(&[&cameras, &microphones]);
+
+        assert_eq!(activity, AvActivity::default());
+    }
+
+    #[test]
+    fn test_collect_activity_failed_property_queries() {
+        let cameras = cameras(vec![(10, None, None)]);
+
+        let activity = collect_activity(&[&cameras]);
+
+        assert_eq!(activity.camera_in_use, Some(false));
+        assert_eq!(activity.devices[0].name, "Device 10");
+        assert!(!activity.devices[0].in_use);
+    }
+
+    #[test]
+    fn test_device_ids_from_bytes() {
+        let bytes: Vec<u8> = [38u32, 45, 71].iter().flat_map(|id| id.to_ne_bytes()).collect();
+        assert_eq!(device_ids_from_bytes(&bytes), [38, 45, 71]);
+        // A trailing partial ID is ignored
+        assert_eq!(device_ids_from_bytes(&bytes[..6]), [38]);
+        assert!(device_ids_from_bytes(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_av_activity() {
+        let activity = av_activity().unwrap();
+
+        for device in &activity.devices {
+            assert!(!device.name.is_empty());
+        }
+        let has = |kind| activity.devices.iter().any(|device| device.kind == kind);
+        assert_eq!(activity.camera_in_use.is_some(), has(AvDeviceKind::Camera));
+        assert_eq!(activity.microphone_in_use.is_some(), has(AvDeviceKind::Microphone));
+    }
+}
@@ -0,0 +1,248 @@
+//! Firmware and security versions for inventory.
+//!
+//! The boot ROM version comes from the device tree: iBoot publishes `firmware-version` under `/chosen` on Apple
+//! Silicon, and Intel Macs publish the boot ROM version under `/rom`. The SMC version is a property of the `AppleSMC`
+//! service on Intel Macs; Apple Silicon has no separate SMC firmware. The build and the Rapid Security Response suffix
+//! come from `SystemVersion.plist`, and System Integrity Protection from `csr_get_active_config`.
+
+use std::{fs, path::Path};
+
+use objc2::rc::autoreleasepool;
+use objc2_foundation::{NSData, NSString};
+
+use super::{detect_architecture, Architecture};
+use crate::{
+    error::Result,
+    hardware::iokit::{IOKit, IOKitImpl},
+    init::sysctl_string,
+    utils::bindings::csr_get_active_config,
+};
+
+/// Property list describing the installed macOS version
+const SYSTEM_VERSION_PLIST: &str = "/System/Library/CoreServices/SystemVersion.plist";
+/// Key of the build in `SystemVersion.plist`
+const BUILD_KEY: &str = "ProductBuildVersion";
+/// Key of the Rapid Security Response suffix in `SystemVersion.plist`
+const RSR_KEY: &str = "ProductVersionExtra";
+
+/// Registry entries and properties that may hold the boot ROM version, in lookup order
+const BOOT_ROM_SOURCES: [(RegistryEntry, &str); 3] = [
+    (RegistryEntry::Path("IODeviceTree:/chosen"), "firmware-version"),
+    (RegistryEntry::Path("IODeviceTree:/rom"), "version"),
+    (RegistryEntry::Class("IOPlatformExpertDevice"), "firmware-version"),
+];
+/// Registry class of the SMC driver
+const SMC_CLASS: &str = "AppleSMC";
+/// Registry property holding the SMC firmware version
+const SMC_VERSION_KEY: &str = "smc-version";
+
+/// SIP configuration flag allowing unrestricted file system access, the protection `csrutil` reports on
+const CSR_ALLOW_UNRESTRICTED_FS: u32 = 1 << 1;
+
+/// Firmware and security-relevant versions of the machine
+///
+/// Every field but the build is read independently and is None where the machine doesn't provide it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirmwareInfo {
+    /// Boot ROM version (e.g. "iBoot-10151.121.1" on Apple Silicon)
+    pub boot_rom_version: Option<String>,
+    /// SMC firmware version (e.g. "2.46f12"); None on Apple Silicon
+    pub smc_version: Option<String>,
+    /// macOS build (e.g. "23F79")
+    pub os_build: String,
+    /// Rapid Security Response suffix (e.g. "(a)"), if one is installed
+    pub rsr_suffix: Option<String>,
+    /// Whether System Integrity Protection is enabled, if the configuration can be read
+    pub sip_enabled: Option<bool>,
+}
+
+/// The fields of `SystemVersion.plist` that [`FirmwareInfo`] reports
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SystemVersion {
+    pub build: Option<String>,
+    pub rsr_suffix: Option<String>,
+}
+
+/// A registry entry, found by class or by path
+#[derive(Debug, Clone, Copy)]
+enum RegistryEntry {
+    Class(&'static str),
+    Path(&'static str),
+}
+
+/// Collects the firmware, SMC and OS security versions.
+///
+/// # Errors
+///
+/// Returns an error if the macOS build can't be read from `SystemVersion.plist` or `kern.osversion`.
+pub fn firmware_info() -> Result<FirmwareInfo> {
+    let version = fs::read_to_string(Path::new(SYSTEM_VERSION_PLIST))
+        .map(|document| parse_system_version(&document))
+        .unwrap_or_default();
+    let os_build = match version.build {
+        Some(build) => build,
+        None => sysctl_string("kern.osversion")?,
+    };
+
+    let smc_version = match detect_architecture() {
+        Ok(Architecture::AppleSilicon) => None,
+        _ => registry_string(RegistryEntry::Class(SMC_CLASS), SMC_VERSION_KEY),
+    };
+
+    Ok(FirmwareInfo {
+        boot_rom_version: BOOT_ROM_SOURCES
+            .into_iter()
+            .find_map(|(entry, key)| registry_string(entry, key)),
+        smc_version,
+        os_build,
+        rsr_suffix: version.rsr_suffix,
+        sip_enabled: sip_enabled(),
+    })
+}
+
+/// Extracts the build and Rapid Security Response suffix from a `SystemVersion.plist` document.
+///
+/// The document is a flat dictionary of strings, so only `<key>` elements directly followed by a `<string>` are
+/// read. Missing or empty values are None.
+pub(crate) fn parse_system_version(document: &str) -> SystemVersion {
+    let string = |key: &str| {
+        let after_key = document.split(&format!("<key>{key}</key>")).nth(1)?;
+        let value = after_key.trim_start().strip_prefix("<string>")?;
+        let value = value[..value.find("</string>")?].trim();
+        (!value.is_empty()).then(|| unescape_xml(value))
+    };
+
+    SystemVersion { build: string(BUILD_KEY), rsr_suffix: string(RSR_KEY) }
+}
+
+/// Replaces the predefined XML entities.
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Decodes a registry property published as bytes, such as device tree strings.
+///
+/// Device tree strings are NUL-terminated; the text up to the first NUL is used. Returns None if the text is empty
+/// or not UTF-8.
+pub(crate) fn parse_property_bytes(bytes: &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    let text = std::str::from_utf8(&bytes[..end]).ok()?.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Whether a SIP configuration leaves the file system protected.
+pub(crate) fn sip_enabled_from_config(config: u32) -> bool {
+    config & CSR_ALLOW_UNRESTRICTED_FS == 0
+}
+
+/// Reads whether SIP is enabled, or None if the configuration can't be read.
+fn sip_enabled() -> Option<bool> {
+    let mut config = 0u32;
+    // SAFETY: the pointer refers to a live u32 the call writes the configuration to.
+    let result = unsafe { csr_get_active_config(&mut config) };
+    (result == 0).then(|| sip_enabled_from_config(config))
+}
+
+/// Reads a string property of a registry entry, published either as a string or as bytes.
+fn registry_string(entry: RegistryEntry, key: &str) -> Option<String> {
+    let io_kit = IOKitImpl::default();
+    let service = match entry {
+        RegistryEntry::Class(class) => io_kit.get_service(class).ok()?,
+        RegistryEntry::Path(path) => io_kit.find_service_by_path(path)?,
+    };
+
+    autoreleasepool(|_| {
+        let properties = io_kit.io_registry_entry_create_cf_properties(&service).ok()?;
+
+        let value = properties.valueForKey(&NSString::from_str(key))?;
+        match value.downcast::<NSString>() {
+            Ok(string) => Some(string.to_string()).filter(|s| !s.trim().is_empty()),
+            Err(value) => parse_property_bytes(&value.downcast::<NSData>().ok()?.to_vec()),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYSTEM_VERSION: &str = include_str!("fixtures/SystemVersion.plist");
+    const SYSTEM_VERSION_RSR: &str = include_str!("fixtures/SystemVersion-rsr.plist");
+
+    #[test]
+    fn test_parse_system_version() {
+        let version = parse_system_version(SYSTEM_VERSION);
+        assert_eq!(version.build.as_deref(), Some("23F79"));
+        assert_eq!(version.rsr_suffix, None);
+
+        let version = parse_system_version(SYSTEM_VERSION_RSR);
+        assert_eq!(version.build.as_deref(), Some("22F770820d"));
+        assert_eq!(version.rsr_suffix.as_deref(), Some("(c)"));
+    }
+
+    #[test]
+    fn test_parse_system_version_malformed() {
+        assert_eq!(parse_system_version(""), SystemVersion::default());
+        assert_eq!(parse_system_version("not a property list"), SystemVersion::default());
+
+        // A key followed by something other than a string, an empty string, and an unterminated string
+        let document = "<dict><key>ProductBuildVersion</key><integer>1</integer>\
+                        <key>ProductVersionExtra</key><string> </string></dict>";
+        assert_eq!(parse_system_version(document), SystemVersion::default());
+        let document = "<dict><key>ProductBuildVersion</key><string>23F79";
+        assert_eq!(parse_system_version(document).build, None);
+
+        let document = "<key>ProductVersionExtra</key>\n\t<string>(a&amp;b)</string>";
+        assert_eq!(parse_system_version(document).rsr_suffix.as_deref(), Some("(a&b)"));
+    }
+
+    #[test]
+    fn test_parse_property_bytes() {
+        // Device tree strings as published by iBoot and Intel boot ROMs
+        assert_eq!(
+            parse_property_bytes(b"iBoot-10151.121.1\0").as_deref(),
+            Some("iBoot-10151.121.1")
+        );
+        assert_eq!(
+            parse_property_bytes(b"MBP161.88Z.F000.B00.2306120941\0\0\0").as_deref(),
+            Some("MBP161.88Z.F000.B00.2306120941")
+        );
+        assert_eq!(parse_property_bytes(b"2.46f12").as_deref(), Some("2.46f12"));
+        assert_eq!(parse_property_bytes(b""), None);
+        assert_eq!(parse_property_bytes(b"\0iBoot"), None);
+        assert_eq!(parse_property_bytes(b"\xff\xfe\0"), None);
+    }
+
+    #[test]
+    fn test_sip_enabled_from_config() {
+        assert!(sip_enabled_from_config(0));
+        // Only kext signing relaxed (CSR_ALLOW_UNTRUSTED_KEXTS)
+        assert!(sip_enabled_from_config(0x1));
+        // `csrutil disable`
+        assert!(!sip_enabled_from_config(0x7f));
+        assert!(!sip_enabled_from_config(CSR_ALLOW_UNRESTRICTED_FS));
+    }
+
+    #[test]
+    fn test_sip_enabled() {
+        assert!(sip_enabled().is_some(), "csr_get_active_config should succeed on real hardware");
+    }
+
+    #[test]
+    fn test_firmware_info() {
+        let info = firmware_info().unwrap();
+
+        assert!(!info.os_build.is_empty());
+        assert!(info.sip_enabled.is_some());
+        if detect_architecture().unwrap() == Architecture::AppleSilicon {
+            assert_eq!(info.smc_version, None);
+        }
+        if let Some(suffix) = info.rsr_suffix {
+            assert!(suffix.starts_with('('), "Unexpected RSR suffix {suffix}");
+        }
+    }
+}
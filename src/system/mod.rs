@@ -1,12 +1,48 @@
+//! # System Module
+//!
+//! Overall system information: the architecture, firmware and security versions ([`firmware_info`]), login sessions,
+//! reliability data such as uptime, the last shutdown cause and recent panics ([`reliability()`]), and the hostname,
+//! load and thread count, refreshed only once they're stale ([`System`]).
+//!
+//! ```rust
+//! # fn main() -> darwin_metrics::Result<()> {
+//! use darwin_metrics::system::{detect_architecture, firmware_info, Architecture};
+//!
+//! assert_ne!(detect_architecture()?, Architecture::Unknown);
+//! assert!(!firmware_info()?.os_build.is_empty());
+//! # Ok(())
+//! # }
+//! ```
+
 use std::ffi::c_void;
 
 use thiserror::Error;
 
+#[cfg(feature = "av-status")]
+mod av;
+mod firmware;
+mod reliability;
+mod sessions;
+mod state;
+
+#[cfg(feature = "av-status")]
+pub use av::{av_activity, AvActivity, AvDevice, AvDeviceKind};
+pub use firmware::{firmware_info, FirmwareInfo};
+pub(crate) use reliability::uptime;
+pub use reliability::{
+    reliability, shutdown_cause_description, PanicReportSummary, ReliabilityInfo,
+};
+pub use sessions::{console_user, sessions, LoginSession};
+pub use state::{thread_count, System, SystemState, DEFAULT_MAX_STALENESS};
+
 use crate::{
     error::{Error, Result},
-    utils::bindings::{
-        sysctl,
-        sysctl_constants::{CTL_HW, HW_MACHINE},
+    utils::{
+        bindings::{
+            sysctl,
+            sysctl_constants::{CTL_HW, HW_MACHINE},
+        },
+        retry::{with_retry, RetryPolicy},
     },
 };
 
@@ -24,7 +60,8 @@ impl From<ArchitectureError> for Error {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Architecture {
     Intel,
     AppleSilicon,
@@ -33,11 +70,15 @@ pub enum Architecture {
 
 pub fn detect_architecture() -> Result<Architecture> {
     let mut mib = [CTL_HW, HW_MACHINE];
-    let mut size = 0;
 
-    unsafe {
+    // Reading the size and then the data can race with the value growing, which fails with ENOMEM and is retried
+    let buffer = with_retry(RetryPolicy::default(), || unsafe {
+        let mut size = 0;
         if sysctl(mib.as_mut_ptr(), 2, std::ptr::null_mut(), &mut size, std::ptr::null(), 0) != 0 {
-            return Err(Error::system("Failed to get architecture information"));
+            return Err(Error::os_error(
+                "Failed to get architecture information",
+                std::io::Error::last_os_error(),
+            ));
         }
 
         let mut buffer = vec![0u8; size];
@@ -50,27 +91,33 @@ pub fn detect_architecture() -> Result<Architecture> {
             0,
         ) != 0
         {
-            return Err(Error::system("Failed to retrieve architecture data"));
+            return Err(Error::os_error(
+                "Failed to retrieve architecture data",
+                std::io::Error::last_os_error(),
+            ));
         }
-
-        let cstr = std::ffi::CStr::from_bytes_with_nul(&buffer)
-            .map_err(|_| ArchitectureError::InvalidStringEncoding)?;
-        let arch = cstr.to_str().map_err(|_| ArchitectureError::InvalidStringEncoding)?;
-
-        Ok(match arch {
-            "arm64" => Architecture::AppleSilicon,
-            "x86_64" => Architecture::Intel,
-            _ => Architecture::Unknown,
-        })
-    }
+        buffer.truncate(size);
+        Ok(buffer)
+    })?;
+
+    let cstr = std::ffi::CStr::from_bytes_with_nul(&buffer)
+        .map_err(|_| ArchitectureError::InvalidStringEncoding)?;
+    let arch = cstr.to_str().map_err(|_| ArchitectureError::InvalidStringEncoding)?;
+
+    Ok(match arch {
+        "arm64" => Architecture::AppleSilicon,
+        "x86_64" => Architecture::Intel,
+        _ => Architecture::Unknown,
+    })
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SystemMetrics {
     pub architecture: Architecture,
 }
 
 pub fn get_system_metrics() -> Result<SystemMetrics> {
-    let architecture = detect_architecture()?;
+    let architecture = crate::init::system_info()?.architecture;
     Ok(SystemMetrics { architecture })
 }
 
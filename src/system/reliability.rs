@@ -0,0 +1,412 @@
+//! Shutdown cause and kernel panic history.
+//!
+//! The cause of the last shutdown is the integer code the power management firmware hands to the `IOPMrootDomain`
+//! at boot, the same value `powerd` logs as "Previous shutdown cause". Kernel panics leave a report under
+//! `/Library/Logs/DiagnosticReports`: a `.panic` file on older systems, an `.ips` file with `bug_type` 210 on newer
+//! ones. Only the JSON header line of each report is read, which is world-readable.
+
+use std::{
+    ffi::{c_void as ffi_c_void, CString},
+    fs::{self, File},
+    io::{BufRead, BufReader, Read},
+    os::raw::c_int,
+    path::Path,
+    ptr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use objc2::rc::{autoreleasepool, Retained};
+use objc2_foundation::{NSDictionary, NSNumber, NSObject, NSString};
+
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{
+        sysctl,
+        sysctl_constants::{CTL_KERN, KERN_BOOTTIME},
+        IOObjectRelease, IORegistryEntryCreateCFProperties, IOServiceGetMatchingService,
+        IOServiceMatching,
+    },
+};
+
+/// Directory the system writes diagnostic reports to
+const DIAGNOSTIC_REPORTS_DIR: &str = "/Library/Logs/DiagnosticReports";
+/// `bug_type` of full kernel panic reports
+const PANIC_BUG_TYPE: &str = "210";
+/// Upper bound on the header line, so a malformed report isn't read whole
+const MAX_HEADER_LEN: u64 = 16 * 1024;
+
+/// Registry class of the power management root domain
+const ROOT_DOMAIN_CLASS: &str = "IOPMrootDomain";
+/// Root domain property holding the shutdown cause code
+const SHUTDOWN_CAUSE_KEY: &str = "ShutdownCause";
+
+/// Reliability indicators of the machine
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReliabilityInfo {
+    /// Code of the cause of the last shutdown, if the firmware reports it
+    pub last_shutdown_cause: Option<i32>,
+    /// Description of `last_shutdown_cause`, if the code is a well-known one
+    pub last_shutdown_cause_description: Option<String>,
+    /// Kernel panic reports still on disk, newest first
+    pub recent_panics: Vec<PanicReportSummary>,
+    /// Time since boot when the information was collected
+    pub uptime_at_collection: Duration,
+}
+
+/// Summary of a kernel panic report, taken from its header line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicReportSummary {
+    /// When the panic happened, or when the report was written if the header has no timestamp
+    pub timestamp: SystemTime,
+    /// File name of the report in the diagnostic reports directory
+    pub filename: String,
+    /// Name of the process the header attributes the report to, if any
+    pub process_hint: Option<String>,
+}
+
+/// The fields of a diagnostic report header line
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ReportHeader {
+    pub bug_type: Option<String>,
+    pub timestamp: Option<SystemTime>,
+    pub process: Option<String>,
+}
+
+/// Collects the shutdown cause, panic history and uptime.
+///
+/// The shutdown cause is None where the firmware doesn't report it, and the panic list is empty if the reports
+/// directory can't be read; neither is an error.
+///
+/// # Errors
+///
+/// Returns an error if the boot time can't be read.
+pub fn reliability() -> Result<ReliabilityInfo> {
+    let last_shutdown_cause = last_shutdown_cause();
+
+    Ok(ReliabilityInfo {
+        last_shutdown_cause,
+        last_shutdown_cause_description: last_shutdown_cause
+            .and_then(shutdown_cause_description)
+            .map(str::to_string),
+        recent_panics: panic_reports(Path::new(DIAGNOSTIC_REPORTS_DIR)),
+        uptime_at_collection: uptime()?,
+    })
+}
+
+/// Describes a well-known shutdown cause code, or returns None for unknown codes.
+pub fn shutdown_cause_description(code: i32) -> Option<&'static str> {
+    Some(match code {
+        5 => "Normal shutdown",
+        3 => "Hard power off (power button held)",
+        0 => "Power lost",
+        -3 => "Multiple temperature sensors exceeded their limit",
+        -60 => "Bad master directory block",
+        -61 => "Watchdog timer detected an unresponsive application",
+        -62 => "Watchdog timer detected an unresponsive system",
+        -74 => "Battery temperature exceeded its limit",
+        -86 => "Proximity temperature exceeded its limit",
+        -95 => "CPU temperature exceeded its limit",
+        -100 => "Power supply temperature exceeded its limit",
+        -103 => "Battery empty",
+        -128 => "Kernel panic",
+        _ => return None,
+    })
+}
+
+/// Lists the kernel panic reports in `dir`, newest first.
+///
+/// Returns an empty list if the directory can't be read. Reports that can't be read are skipped.
+pub(crate) fn panic_reports(dir: &Path) -> Vec<PanicReportSummary> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut reports: Vec<_> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let filename = entry.file_name().to_string_lossy().into_owned();
+            let is_panic_file = filename.ends_with(".panic");
+            if !is_panic_file && !filename.ends_with(".ips") {
+                return None;
+            }
+
+            let header =
+                read_header_line(&entry.path()).and_then(|line| parse_report_header(&line));
+            let is_panic = is_panic_file
+                || header.as_ref().and_then(|h| h.bug_type.as_deref()) == Some(PANIC_BUG_TYPE);
+            if !is_panic {
+                return None;
+            }
+
+            let header = header.unwrap_or_default();
+            let timestamp = header
+                .timestamp
+                .or_else(|| entry.metadata().and_then(|m| m.modified()).ok())
+                .unwrap_or(UNIX_EPOCH);
+
+            Some(PanicReportSummary { timestamp, filename, process_hint: header.process })
+        })
+        .collect();
+
+    reports.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| a.filename.cmp(&b.filename)));
+    reports
+}
+
+/// Reads the first line of a report.
+fn read_header_line(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+    let mut line = String::new();
+    BufReader::new(file.take(MAX_HEADER_LEN)).read_line(&mut line).ok()?;
+    Some(line)
+}
+
+/// Parses the JSON header line of a diagnostic report.
+///
+/// Returns None if the line isn't a JSON object.
+pub(crate) fn parse_report_header(line: &str) -> Option<ReportHeader> {
+    let value: serde_json::Value = serde_json::from_str(line.trim()).ok()?;
+    let header = value.as_object()?;
+    let string = |key: &str| header.get(key).and_then(|v| v.as_str()).map(str::to_string);
+
+    Some(ReportHeader {
+        bug_type: string("bug_type"),
+        timestamp: string("timestamp").as_deref().and_then(parse_report_timestamp),
+        process: ["name", "app_name", "procName"]
+            .into_iter()
+            .find_map(string)
+            .filter(|name| !name.is_empty()),
+    })
+}
+
+/// Parses a report timestamp such as `2024-03-12 10:15:20.00 +0100`.
+pub(crate) fn parse_report_timestamp(timestamp: &str) -> Option<SystemTime> {
+    let mut parts = timestamp.split_whitespace();
+    let (date, time, offset) = (parts.next()?, parts.next()?, parts.next());
+
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let (clock, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut clock = clock.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (clock.next()?.ok()?, clock.next()?.ok()?, clock.next()?.ok()?);
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        let digits = &fraction[..fraction.len().min(9)];
+        digits.parse::<u32>().ok()? * 10u32.pow(9 - digits.len() as u32)
+    };
+
+    let offset_seconds = match offset {
+        Some(offset) => parse_utc_offset(offset)?,
+        None => 0,
+    };
+
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - offset_seconds;
+    let seconds = u64::try_from(seconds).ok()?;
+    Some(UNIX_EPOCH + Duration::new(seconds, nanos))
+}
+
+/// Parses a UTC offset such as `+0100` or `-0530` into seconds.
+fn parse_utc_offset(offset: &str) -> Option<i64> {
+    let (sign, digits) = match offset.as_bytes().first()? {
+        b'+' => (1, &offset[1..]),
+        b'-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Days between 1970-01-01 and the given date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Time since boot, from `kern.boottime`.
+pub(crate) fn uptime() -> Result<Duration> {
+    let mut mib = [CTL_KERN, KERN_BOOTTIME];
+    let mut boot_time = libc::timeval { tv_sec: 0, tv_usec: 0 };
+    let mut size = std::mem::size_of::<libc::timeval>();
+
+    // SAFETY: the buffer is a timeval and its size is passed along.
+    let result = unsafe {
+        sysctl(
+            mib.as_mut_ptr(),
+            mib.len() as u32,
+            &mut boot_time as *mut libc::timeval as *mut std::ffi::c_void,
+            &mut size,
+            ptr::null(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(Error::system("Failed to read the boot time"));
+    }
+
+    let boot_time = UNIX_EPOCH
+        + Duration::from_secs(boot_time.tv_sec as u64)
+        + Duration::from_micros(boot_time.tv_usec as u64);
+    Ok(SystemTime::now().duration_since(boot_time).unwrap_or(Duration::ZERO))
+}
+
+/// Reads the shutdown cause code from the power management root domain.
+fn last_shutdown_cause() -> Option<i32> {
+    let class = CString::new(ROOT_DOMAIN_CLASS).ok()?;
+
+    autoreleasepool(|_| unsafe {
+        // IOServiceGetMatchingService consumes the matching dictionary
+        let matching = IOServiceMatching(class.as_ptr());
+        if matching.is_null() {
+            return None;
+        }
+        let service = IOServiceGetMatchingService(0, matching);
+        if service == 0 {
+            return None;
+        }
+
+        let mut props: *mut ffi_c_void = ptr::null_mut();
+        let result = IORegistryEntryCreateCFProperties(service, &mut props, ptr::null_mut(), 0);
+        IOObjectRelease(service);
+        if result != 0 {
+            return None;
+        }
+        // The properties are returned with a +1 retain count and are toll-free bridged to NSDictionary
+        let properties: Retained<NSDictionary<NSString, NSObject>> =
+            Retained::from_raw(props as *mut NSDictionary<NSString, NSObject>)?;
+
+        let key = NSString::from_str(SHUTDOWN_CAUSE_KEY);
+        let cause = properties.valueForKey(&key)?.downcast::<NSNumber>().ok()?;
+        c_int::try_from(cause.as_i64()).ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const PANIC_HEADER: &str = include_str!("fixtures/panic-full-2024-03-12-101520.0002.panic");
+    const PANIC_IPS_HEADER: &str = include_str!("fixtures/panic-full-2025-01-07-231102.000.ips");
+    const CRASH_IPS_HEADER: &str = include_str!("fixtures/Safari-2025-01-08-090000.ips");
+
+    fn fixtures() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("src/system/fixtures")
+    }
+
+    fn first_line(report: &str) -> &str {
+        report.lines().next().unwrap()
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn test_shutdown_cause_description() {
+        assert_eq!(shutdown_cause_description(5), Some("Normal shutdown"));
+        assert_eq!(shutdown_cause_description(3), Some("Hard power off (power button held)"));
+        assert_eq!(shutdown_cause_description(-128), Some("Kernel panic"));
+        assert_eq!(shutdown_cause_description(-95), Some("CPU temperature exceeded its limit"));
+        assert_eq!(shutdown_cause_description(42), None);
+    }
+
+    #[test]
+    fn test_parse_panic_header() {
+        let header = parse_report_header(first_line(PANIC_HEADER)).unwrap();
+
+        assert_eq!(header.bug_type.as_deref(), Some("210"));
+        // 2024-03-12 10:15:20.00 +0100
+        assert_eq!(header.timestamp, Some(at(1_710_234_920)));
+        assert_eq!(header.process, None);
+    }
+
+    #[test]
+    fn test_parse_ips_header() {
+        let header = parse_report_header(first_line(PANIC_IPS_HEADER)).unwrap();
+        assert_eq!(header.bug_type.as_deref(), Some("210"));
+        // 2025-01-07 23:11:02.00 -0800
+        assert_eq!(header.timestamp, Some(at(1_736_320_262)));
+
+        let header = parse_report_header(first_line(CRASH_IPS_HEADER)).unwrap();
+        assert_eq!(header.bug_type.as_deref(), Some("309"));
+        assert_eq!(header.process.as_deref(), Some("Safari"));
+    }
+
+    #[test]
+    fn test_parse_invalid_header() {
+        assert_eq!(parse_report_header("Anonymous UUID: 1234"), None);
+        assert_eq!(parse_report_header("[1, 2]"), None);
+        assert_eq!(parse_report_header(""), None);
+    }
+
+    #[test]
+    fn test_parse_report_timestamp() {
+        assert_eq!(parse_report_timestamp("1970-01-01 00:00:00.00 +0000"), Some(UNIX_EPOCH));
+        assert_eq!(
+            parse_report_timestamp("2000-02-29 12:00:00.50 +0000"),
+            Some(at(951_825_600) + Duration::from_millis(500))
+        );
+        assert_eq!(parse_report_timestamp("2000-02-29 12:00:00 +0530"), Some(at(951_805_800)));
+        assert_eq!(parse_report_timestamp("2000-13-01 00:00:00 +0000"), None);
+        assert_eq!(parse_report_timestamp("2000-01-01 25:00:00 +0000"), None);
+        assert_eq!(parse_report_timestamp("2000-01-01 00:00:00 0100"), None);
+        assert_eq!(parse_report_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_panic_reports() {
+        let reports = panic_reports(&fixtures());
+        let names: Vec<_> = reports.iter().map(|r| r.filename.as_str()).collect();
+
+        // The app crash report and the file that isn't a report are left out. The malformed report falls back to
+        // its modification time, which is newer than the fixture timestamps.
+        assert_eq!(
+            names,
+            [
+                "panic-full-malformed.panic",
+                "panic-full-2025-01-07-231102.000.ips",
+                "panic-full-2024-03-12-101520.0002.panic",
+            ]
+        );
+        assert_eq!(reports[1].timestamp, at(1_736_320_262));
+        assert!(reports.iter().all(|r| r.process_hint.is_none()));
+    }
+
+    #[test]
+    fn test_panic_reports_unreadable_directory() {
+        assert!(panic_reports(Path::new("/nonexistent/DiagnosticReports")).is_empty());
+    }
+
+    #[test]
+    fn test_reliability() {
+        let info = reliability().unwrap();
+
+        assert!(info.uptime_at_collection > Duration::ZERO);
+        if let Some(code) = info.last_shutdown_cause {
+            assert_eq!(
+                info.last_shutdown_cause_description.as_deref(),
+                shutdown_cause_description(code)
+            );
+        }
+        for pair in info.recent_panics.windows(2) {
+            assert!(pair[0].timestamp >= pair[1].timestamp, "Reports should be newest first");
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    collections::HashSet,
+    ffi::CString,
+    os::raw::c_char,
+    ptr,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use objc2::rc::{autoreleasepool, Retained};
+use objc2_foundation::NSString;
+
+use crate::{
+    error::{Error, Result},
+    utils::bindings::{list_kinfo_procs, SCDynamicStoreCopyConsoleUser},
+};
+
+/// Line name utmpx uses for the graphical console session
+const CONSOLE_LINE: &str = "console";
+
+/// The utmpx database cursor is process-global, so iteration has to be serialized
+static UTMPX_LOCK: Mutex<()> = Mutex::new(());
+
+/// A user logged in to the system
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginSession {
+    /// User ID of the logged-in user
+    pub uid: u32,
+    /// Short name of the logged-in user
+    pub username: String,
+    /// Whether this is the session currently shown on the console
+    pub is_console: bool,
+    /// When the session started, if recorded
+    pub login_time: Option<SystemTime>,
+    /// Terminal line of the session (e.g. `console` or `ttys001`), if any
+    pub tty: Option<String>,
+}
+
+/// A `USER_PROCESS` entry of the utmpx database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UtmpxRecord {
+    pub user: String,
+    pub line: String,
+    pub pid: i32,
+    pub login_time: Option<SystemTime>,
+}
+
+impl UtmpxRecord {
+    /// Parses a raw utmpx entry, returning None for entries that aren't active user logins.
+    pub(crate) fn from_raw(entry: &libc::utmpx) -> Option<Self> {
+        if entry.ut_type != libc::USER_PROCESS {
+            return None;
+        }
+
+        let user = c_chars_to_string(&entry.ut_user);
+        if user.is_empty() {
+            return None;
+        }
+
+        let login_time = match entry.ut_tv.tv_sec {
+            secs if secs > 0 => Some(
+                SystemTime::UNIX_EPOCH
+                    + Duration::from_secs(secs as u64)
+                    + Duration::from_micros(entry.ut_tv.tv_usec.max(0) as u64),
+            ),
+            _ => None,
+        };
+
+        Some(Self { user, line: c_chars_to_string(&entry.ut_line), pid: entry.ut_pid, login_time })
+    }
+}
+
+/// Processes alive at the time sessions are collected
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LiveProcesses {
+    pub pids: HashSet<u32>,
+    pub uids: HashSet<u32>,
+}
+
+impl LiveProcesses {
+    fn collect() -> Result<Self> {
+        let mut live = Self::default();
+        for process in list_kinfo_procs()? {
+            if process.pid() > 0 {
+                live.pids.insert(process.pid() as u32);
+                live.uids.insert(process.ruid());
+            }
+        }
+        Ok(live)
+    }
+
+    /// utmpx entries aren't always removed on logout; a session is only live while its login process is, or, for
+    /// entries without one, while the user has any process left.
+    fn is_live(&self, record: &UtmpxRecord, uid: u32) -> bool {
+        if record.pid > 0 {
+            self.pids.contains(&(record.pid as u32))
+        } else {
+            self.uids.contains(&uid)
+        }
+    }
+}
+
+/// Combines utmpx records and the console user into the list of live login sessions.
+pub(crate) fn build_sessions(
+    records: &[UtmpxRecord],
+    console: Option<&(u32, String)>,
+    live: &LiveProcesses,
+    resolve_uid: impl Fn(&str) -> Option<u32>,
+) -> Vec<LoginSession> {
+    let mut sessions: Vec<LoginSession> = records
+        .iter()
+        .filter_map(|record| {
+            let uid = resolve_uid(&record.user)?;
+            if !live.is_live(record, uid) {
+                return None;
+            }
+
+            let is_console = record.line == CONSOLE_LINE
+                && console.is_some_and(|(console_uid, _)| *console_uid == uid);
+
+            Some(LoginSession {
+                uid,
+                username: record.user.clone(),
+                is_console,
+                login_time: record.login_time,
+                tty: (!record.line.is_empty()).then(|| record.line.clone()),
+            })
+        })
+        .collect();
+
+    // The console user may be missing from utmpx, e.g. right after fast user switching
+    if let Some((uid, username)) = console {
+        if !sessions.iter().any(|session| session.is_console) {
+            sessions.push(LoginSession {
+                uid: *uid,
+                username: username.clone(),
+                is_console: true,
+                login_time: None,
+                tty: None,
+            });
+        }
+    }
+
+    sessions
+}
+
+/// Returns the users currently logged in to the system.
+///
+/// Sessions are read from the utmpx database, with stale entries of users that have logged out filtered out, and the
+/// session shown on the console is marked using the console user reported by SystemConfiguration.
+///
+/// # Errors
+///
+/// Returns an error if the process list needed to filter stale entries can't be read.
+pub fn sessions() -> Result<Vec<LoginSession>> {
+    let records = read_utmpx();
+    let console = console_user()?;
+    let live = LiveProcesses::collect()?;
+    Ok(build_sessions(&records, console.as_ref(), &live, uid_for_user))
+}
+
+/// Returns the user logged in at the console.
+///
+/// # Returns
+///
+/// * `Result<Option<(u32, String)>>` - The uid and short name of the console user, or None at the login window
+///
+/// # Errors
+///
+/// Returns an error if the console user can't be converted to a string.
+pub fn console_user() -> Result<Option<(u32, String)>> {
+    autoreleasepool(|_| unsafe {
+        let mut uid = 0u32;
+        let mut gid = 0u32;
+        let name = SCDynamicStoreCopyConsoleUser(ptr::null_mut(), &mut uid, &mut gid);
+        if name.is_null() {
+            return Ok(None);
+        }
+
+        // The name is returned with a +1 retain count and is toll-free bridged to NSString
+        let name = Retained::from_raw(name as *mut NSString)
+            .ok_or_else(|| Error::system("Failed to retain console user name"))?
+            .to_string();
+
+        // SystemConfiguration reports the login window itself as the console user when nobody is logged in
+        if name == "loginwindow" || name.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some((uid, name)))
+    })
+}
+
+/// Reads all user login entries from the utmpx database.
+fn read_utmpx() -> Vec<UtmpxRecord> {
+    let _guard = UTMPX_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut records = Vec::new();
+
+    unsafe {
+        libc::setutxent();
+        loop {
+            let entry = libc::getutxent();
+            if entry.is_null() {
+                break;
+            }
+            if let Some(record) = UtmpxRecord::from_raw(&*entry) {
+                records.push(record);
+            }
+        }
+        libc::endutxent();
+    }
+
+    records
+}
+
+/// Looks up the uid of a user by short name.
+fn uid_for_user(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = ptr::null_mut();
+    let mut buf = vec![0 as c_char; 4096];
+
+    let status = unsafe {
+        libc::getpwnam_r(name.as_ptr(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    Some(passwd.pw_uid)
+}
+
+fn c_chars_to_string(chars: &[c_char]) -> String {
+    // utmpx fields are only NUL-terminated if they're shorter than the field
+    let bytes: Vec<u8> = chars.iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(user: &str, line: &str, pid: i32, kind: libc::c_short, secs: i64) -> libc::utmpx {
+        let mut entry: libc::utmpx = unsafe { std::mem::zeroed() };
+        for (dst, src) in entry.ut_user.iter_mut().zip(user.bytes()) {
+            *dst = src as c_char;
+        }
+        for (dst, src) in entry.ut_line.iter_mut().zip(line.bytes()) {
+            *dst = src as c_char;
+        }
+        entry.ut_pid = pid;
+        entry.ut_type = kind;
+        entry.ut_tv.tv_sec = secs as _;
+        entry
+    }
+
+    fn record(user: &str, line: &str, pid: i32) -> UtmpxRecord {
+        UtmpxRecord { user: user.to_string(), line: line.to_string(), pid, login_time: None }
+    }
+
+    fn resolve(name: &str) -> Option<u32> {
+        match name {
+            "alice" => Some(501),
+            "bob" => Some(502),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_utmpx_record_parsing() {
+        let parsed = UtmpxRecord::from_raw(&fixture(
+            "alice",
+            "ttys001",
+            4242,
+            libc::USER_PROCESS,
+            1_700_000_000,
+        ))
+        .unwrap();
+        assert_eq!(parsed.user, "alice");
+        assert_eq!(parsed.line, "ttys001");
+        assert_eq!(parsed.pid, 4242);
+        assert_eq!(
+            parsed.login_time,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+
+        let no_time =
+            UtmpxRecord::from_raw(&fixture("alice", "console", 1, libc::USER_PROCESS, 0)).unwrap();
+        assert_eq!(no_time.login_time, None);
+
+        // Logouts, boot records and nameless entries aren't sessions
+        assert!(
+            UtmpxRecord::from_raw(&fixture("alice", "ttys001", 1, libc::DEAD_PROCESS, 1)).is_none()
+        );
+        assert!(UtmpxRecord::from_raw(&fixture("", "ttys001", 1, libc::USER_PROCESS, 1)).is_none());
+        assert!(UtmpxRecord::from_raw(&fixture("reboot", "~", 0, libc::BOOT_TIME, 1)).is_none());
+    }
+
+    #[test]
+    fn test_utmpx_record_fills_whole_field() {
+        let long_name = "a".repeat(libc::_UTX_LINESIZE);
+        let parsed =
+            UtmpxRecord::from_raw(&fixture("bob", &long_name, 7, libc::USER_PROCESS, 1)).unwrap();
+        assert_eq!(parsed.line, long_name);
+    }
+
+    #[test]
+    fn test_build_sessions_filters_stale_entries() {
+        let records = vec![record("alice", "console", 100), record("bob", "ttys000", 200)];
+        let live = LiveProcesses {
+            pids: [100].into_iter().collect(),
+            uids: [501, 502].into_iter().collect(),
+        };
+
+        let sessions = build_sessions(&records, None, &live, resolve);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].username, "alice");
+        assert_eq!(sessions[0].tty.as_deref(), Some("console"));
+        assert!(!sessions[0].is_console);
+    }
+
+    #[test]
+    fn test_build_sessions_without_login_pid_uses_user_processes() {
+        let records = vec![record("alice", "ttys000", 0), record("bob", "ttys001", 0)];
+        let live = LiveProcesses { pids: HashSet::new(), uids: [502].into_iter().collect() };
+
+        let sessions = build_sessions(&records, None, &live, resolve);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].uid, 502);
+    }
+
+    #[test]
+    fn test_build_sessions_marks_console() {
+        let records = vec![
+            record("alice", "console", 100),
+            record("bob", "console", 200),
+            record("bob", "ttys003", 300),
+            record("ghost", "ttys004", 400),
+        ];
+        let live = LiveProcesses {
+            pids: [100, 200, 300, 400].into_iter().collect(),
+            uids: HashSet::new(),
+        };
+        let console = (502, "bob".to_string());
+
+        let sessions = build_sessions(&records, Some(&console), &live, resolve);
+        assert_eq!(sessions.len(), 3, "unknown users are skipped");
+
+        let console_sessions: Vec<_> = sessions.iter().filter(|s| s.is_console).collect();
+        assert_eq!(console_sessions.len(), 1);
+        assert_eq!(console_sessions[0].uid, 502);
+        assert_eq!(console_sessions[0].tty.as_deref(), Some("console"));
+    }
+
+    #[test]
+    fn test_build_sessions_adds_missing_console_user() {
+        let console = (501, "alice".to_string());
+        let sessions = build_sessions(&[], Some(&console), &LiveProcesses::default(), resolve);
+
+        assert_eq!(
+            sessions,
+            vec![LoginSession {
+                uid: 501,
+                username: "alice".to_string(),
+                is_console: true,
+                login_time: None,
+                tty: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_console_user_matches_console_device_owner() {
+        let Some((uid, username)) = console_user().unwrap() else {
+            return;
+        };
+        assert!(!username.is_empty());
+
+        // The console device is owned by the user logged in at the console
+        let path = CString::new("/dev/console").unwrap();
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        assert_eq!(unsafe { libc::stat(path.as_ptr(), &mut stat) }, 0);
+        assert_eq!(stat.st_uid, uid);
+        assert_eq!(uid_for_user(&username), Some(uid));
+    }
+
+    #[test]
+    fn test_sessions_include_console_user() {
+        let sessions = sessions().unwrap();
+        for session in &sessions {
+            assert!(!session.username.is_empty());
+            assert_eq!(uid_for_user(&session.username), Some(session.uid));
+        }
+
+        if let Some((uid, _)) = console_user().unwrap() {
+            assert!(sessions.iter().any(|session| session.is_console && session.uid == uid));
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! System state that changes while the machine is running, refreshed at most once per staleness period.
+//!
+//! The architecture, core counts, installed memory and CPU model never change and are read once per process by
+//! [`crate::init::system_info`]. [`System`] pairs them with the state that does change — the hostname, uptime, load
+//! average and the number of processes and threads — and only reads that again once it's older than the maximum
+//! staleness, so several monitors updating a shared or cloned `System` don't repeat the same sysctl calls.
+//!
+//! The threads are counted from `proc_pidinfo` of every process. Without root, processes of other users can't be
+//! inspected and their threads aren't counted.
+
+use std::time::{Duration, Instant};
+
+use libproc::{proc_pid, task_info::TaskInfo};
+
+use super::uptime;
+use crate::{
+    error::{Error, Result},
+    init::{self, sysctl_string, SystemInfo},
+    utils::bindings::getloadavg,
+};
+
+/// Age from which [`System::update`] reads the changing state again
+pub const DEFAULT_MAX_STALENESS: Duration = Duration::from_secs(1);
+
+/// System state that changes while the machine is running
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SystemState {
+    /// Hostname of the machine
+    pub hostname: String,
+    /// Time since boot
+    pub uptime: Duration,
+    /// 1, 5 and 15 minute load averages, or None if they couldn't be read
+    pub load_average: Option<[f64; 3]>,
+    /// Number of running processes
+    pub process_count: u32,
+    /// Number of threads of the processes that could be inspected
+    pub thread_count: u64,
+}
+
+impl SystemState {
+    /// Reads the current state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the hostname, the boot time or the process list can't be read.
+    pub fn read() -> Result<Self> {
+        let (process_count, thread_count) = count_processes_and_threads()?;
+        Ok(Self {
+            hostname: sysctl_string("kern.hostname")?,
+            uptime: uptime()?,
+            load_average: load_average(),
+            process_count,
+            thread_count,
+        })
+    }
+}
+
+/// Static system information and the changing system state
+#[derive(Debug, Clone)]
+pub struct System {
+    info: &'static SystemInfo,
+    state: SystemState,
+    last_update: Instant,
+    max_staleness: Duration,
+}
+
+impl System {
+    /// Reads the system information and state, refreshed by [`update`](Self::update) once they're older than
+    /// [`DEFAULT_MAX_STALENESS`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the static information or the state can't be read.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            info: init::system_info()?,
+            state: SystemState::read()?,
+            last_update: Instant::now(),
+            max_staleness: DEFAULT_MAX_STALENESS,
+        })
+    }
+
+    /// Sets the age from which [`update`](Self::update) reads the state again.
+    pub fn with_max_staleness(mut self, max_staleness: Duration) -> Self {
+        self.max_staleness = max_staleness;
+        self
+    }
+
+    /// The static system information, read once per process.
+    pub fn info(&self) -> &'static SystemInfo {
+        self.info
+    }
+
+    /// The system state as of [`last_update`](Self::last_update).
+    pub fn state(&self) -> &SystemState {
+        &self.state
+    }
+
+    /// When the state was last read.
+    pub fn last_update(&self) -> Instant {
+        self.last_update
+    }
+
+    /// The age from which [`update`](Self::update) reads the state again.
+    pub fn max_staleness(&self) -> Duration {
+        self.max_staleness
+    }
+
+    /// Whether the state is older than the maximum staleness.
+    pub fn is_stale(&self) -> bool {
+        self.is_stale_at(Instant::now())
+    }
+
+    /// Reads the state again if it's older than the maximum staleness, returning whether it did.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`refresh_dynamic`](Self::refresh_dynamic).
+    pub fn update(&mut self) -> Result<bool> {
+        if !self.is_stale() {
+            return Ok(false);
+        }
+        self.refresh_dynamic()?;
+        Ok(true)
+    }
+
+    /// Reads the state again regardless of its age, leaving the static information as it is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state can't be read, which leaves the previous state in place.
+    pub fn refresh_dynamic(&mut self) -> Result<()> {
+        self.state = SystemState::read()?;
+        self.last_update = Instant::now();
+        Ok(())
+    }
+
+    pub(crate) fn is_stale_at(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_update) >= self.max_staleness
+    }
+}
+
+/// Number of threads of all processes that can be inspected.
+///
+/// Without root, processes of other users can't be inspected and their threads aren't counted.
+///
+/// # Errors
+///
+/// Returns an error if the processes can't be listed.
+pub fn thread_count() -> Result<u64> {
+    Ok(count_processes_and_threads()?.1)
+}
+
+/// Counts the running processes and the threads of those that can be inspected.
+fn count_processes_and_threads() -> Result<(u32, u64)> {
+    #[allow(deprecated)]
+    let pids = proc_pid::listpids(proc_pid::ProcType::ProcAllPIDS)
+        .map_err(|e| Error::process_error(format!("Failed to list process IDs: {e}")))?;
+
+    let threads = pids
+        .iter()
+        .filter(|&&pid| pid != 0)
+        .filter_map(|&pid| proc_pid::pidinfo::<TaskInfo>(pid as i32, 0).ok())
+        .map(|info| info.pti_threadnum.max(0) as u64)
+        .sum();
+    Ok((pids.len() as u32, threads))
+}
+
+fn load_average() -> Option<[f64; 3]> {
+    let mut loads = [0.0f64; 3];
+    // SAFETY: the buffer has room for the three requested values.
+    let count = unsafe { getloadavg(loads.as_mut_ptr(), 3) };
+    (count == 3).then_some(loads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_staleness() {
+        let mut system = System::new().unwrap().with_max_staleness(Duration::from_secs(10));
+        let updated = system.last_update();
+
+        assert!(!system.is_stale_at(updated));
+        assert!(!system.is_stale_at(updated + Duration::from_secs(9)));
+        assert!(system.is_stale_at(updated + Duration::from_secs(10)));
+
+        assert!(!system.update().unwrap(), "A fresh state isn't read again");
+        assert_eq!(system.last_update(), updated);
+
+        system.refresh_dynamic().unwrap();
+        assert!(system.last_update() > updated);
+    }
+
+    #[test]
+    fn test_update_when_stale() {
+        let mut system = System::new().unwrap().with_max_staleness(Duration::ZERO);
+        let updated = system.last_update();
+        assert!(system.update().unwrap());
+        assert!(system.last_update() > updated);
+    }
+
+    #[test]
+    fn test_system_state() {
+        let system = System::new().unwrap();
+        assert!(system.info().logical_cores > 0);
+
+        let state = system.state();
+        assert!(!state.hostname.is_empty());
+        assert!(state.uptime > Duration::ZERO);
+        assert!(state.process_count > 0);
+        assert!(state.thread_count > 0, "At least the threads of this process are counted");
+    }
+
+    #[test]
+    fn test_thread_count() {
+        assert!(thread_count().unwrap() >= 1);
+    }
+}
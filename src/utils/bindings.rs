@@ -14,6 +14,8 @@ use std::{
     os::raw::{c_char, c_int, c_uint, c_void},
 };
 
+use crate::error::{Error, Result};
+
 //------------------------------------------------------------------------------
 // sysctl FFI bindings for macOS
 //------------------------------------------------------------------------------
@@ -35,6 +37,7 @@ pub mod sysctl_constants {
     pub const KERN_PROC_TTY: c_int = 3;
     pub const KERN_PROC_UID: c_int = 4;
     pub const KERN_PROC_RUID: c_int = 5;
+    pub const KERN_BOOTTIME: c_int = 21;
 
     // Hardware-related
     pub const HW_MACHINE: c_int = 1;
@@ -42,34 +45,636 @@ pub mod sysctl_constants {
 
     // VM-related
     pub const VM_SWAPUSAGE: c_int = 5;
+
+    // Network-related
+    pub const CTL_NET: c_int = 4;
+    pub const PF_ROUTE: c_int = 17;
+    pub const NET_RT_IFLIST2: c_int = 6;
 }
 
-/// Process information structure from sysctl/kern_proc.h
+/// Process information structure returned by `sysctl(CTL_KERN, KERN_PROC, ...)` (`<sys/sysctl.h>`)
+///
+/// The layout mirrors the macOS SDK exactly; `sysctl` fills a buffer of these back to back, so any size or offset
+/// mistake here corrupts every entry after the first. The layout is verified at compile time by the assertions in
+/// [`kinfo_proc_layout`] and at runtime by [`kinfo_proc_count`].
 #[allow(non_camel_case_types)]
 #[repr(C)]
+#[derive(Debug, Copy, Clone)]
 pub struct kinfo_proc {
-    pub kp_proc: proc_info,
-    pub kp_eproc: extern_proc,
+    pub kp_proc: extern_proc,
+    pub kp_eproc: eproc,
+}
+
+impl Default for kinfo_proc {
+    fn default() -> Self {
+        // SAFETY: kinfo_proc only contains integers, arrays of integers, and raw pointers, all of which are valid
+        // when zeroed.
+        unsafe { std::mem::zeroed() }
+    }
 }
 
-/// Basic process information structure
+impl kinfo_proc {
+    /// Process ID
+    pub fn pid(&self) -> c_int {
+        self.kp_proc.p_pid
+    }
+
+    /// Parent process ID
+    pub fn ppid(&self) -> c_int {
+        self.kp_eproc.e_ppid
+    }
+
+    /// Effective user ID
+    pub fn uid(&self) -> u32 {
+        self.kp_eproc.e_ucred.cr_uid
+    }
+
+    /// Real user ID
+    pub fn ruid(&self) -> u32 {
+        self.kp_eproc.e_pcred.p_ruid
+    }
+
+    /// Process state (one of the [`proc_state`] constants)
+    pub fn stat(&self) -> u8 {
+        self.kp_proc.p_stat as u8
+    }
+}
+
+/// Process structure embedded in [`kinfo_proc`] (`struct extern_proc` in `<sys/proc.h>`)
+///
+/// The `p_un` union of the SDK definition (two list pointers or the start time) is represented by its
+/// `p_starttime` member, which has the same size.
 #[allow(non_camel_case_types)]
 #[repr(C)]
-pub struct proc_info {
+#[derive(Debug, Copy, Clone)]
+pub struct extern_proc {
+    pub p_starttime: timeval,
+    pub p_vmspace: *mut c_void,
+    pub p_sigacts: *mut c_void,
     pub p_flag: c_int,
+    pub p_stat: c_char,
     pub p_pid: c_int,
-    pub p_ppid: c_int,
-    pub p_stat: c_int,
-    // More fields exist but aren't needed for basic functionality
+    pub p_oppid: c_int,
+    pub p_dupfd: c_int,
+    pub user_stack: *mut c_char,
+    pub exit_thread: *mut c_void,
+    pub p_debugger: c_int,
+    pub sigwait: c_int,
+    pub p_estcpu: c_uint,
+    pub p_cpticks: c_int,
+    pub p_pctcpu: u32,
+    pub p_wchan: *mut c_void,
+    pub p_wmesg: *mut c_char,
+    pub p_swtime: c_uint,
+    pub p_slptime: c_uint,
+    pub p_realtimer: itimerval,
+    pub p_rtime: timeval,
+    pub p_uticks: u64,
+    pub p_sticks: u64,
+    pub p_iticks: u64,
+    pub p_traceflag: c_int,
+    pub p_tracep: *mut c_void,
+    pub p_siglist: c_int,
+    pub p_textvp: *mut c_void,
+    pub p_holdcnt: c_int,
+    pub p_sigmask: u32,
+    pub p_sigignore: u32,
+    pub p_sigcatch: u32,
+    pub p_priority: u8,
+    pub p_usrpri: u8,
+    pub p_nice: c_char,
+    pub p_comm: [u8; MAXCOMLEN + 1],
+    pub p_pgrp: *mut c_void,
+    pub p_addr: *mut c_void,
+    pub p_xstat: u16,
+    pub p_acflag: u16,
+    pub p_ru: *mut c_void,
 }
 
-/// Extended process information structure
+/// Maximum length of a process command name (`MAXCOMLEN` in `<sys/param.h>`)
+pub const MAXCOMLEN: usize = 16;
+
+/// Extra process information embedded in [`kinfo_proc`] (`struct eproc` in `<sys/sysctl.h>`)
 #[allow(non_camel_case_types)]
 #[repr(C)]
-pub struct extern_proc {
-    pub p_starttime: timeval,
-    pub p_comm: [u8; 16], /* MAXCOMLEN
-                           * More fields exist but aren't needed for basic functionality */
+#[derive(Debug, Copy, Clone)]
+pub struct eproc {
+    pub e_paddr: *mut c_void,
+    pub e_sess: *mut c_void,
+    pub e_pcred: pcred,
+    pub e_ucred: ucred,
+    pub e_vm: vmspace,
+    pub e_ppid: c_int,
+    pub e_pgid: c_int,
+    pub e_jobc: i16,
+    pub e_tdev: i32,
+    pub e_tpgid: c_int,
+    pub e_tsess: *mut c_void,
+    pub e_wmesg: [c_char; 8],
+    pub e_xsize: i32,
+    pub e_xrssize: i16,
+    pub e_xccount: i16,
+    pub e_xswrss: i16,
+    pub e_flag: i32,
+    pub e_login: [c_char; 12],
+    pub e_spare: [i32; 4],
+}
+
+/// Process credentials (`struct _pcred` in `<sys/proc.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct pcred {
+    pub pc_lock: [c_char; 72],
+    pub pc_ucred: *mut c_void,
+    pub p_ruid: u32,
+    pub p_svuid: u32,
+    pub p_rgid: u32,
+    pub p_svgid: u32,
+    pub p_refcnt: c_int,
+}
+
+/// User credentials (`struct _ucred` in `<sys/proc.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ucred {
+    pub cr_ref: i32,
+    pub cr_uid: u32,
+    pub cr_ngroups: i16,
+    pub cr_groups: [u32; 16],
+}
+
+/// Legacy address space description (`struct vmspace` in `<sys/vm.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct vmspace {
+    pub vm_refcnt: i32,
+    pub vm_shm: *mut c_char,
+    pub vm_rssize: i32,
+    pub vm_swrss: i32,
+    pub vm_tsize: i32,
+    pub vm_dsize: i32,
+    pub vm_ssize: i32,
+    pub vm_taddr: *mut c_char,
+    pub vm_daddr: *mut c_char,
+    pub vm_maxsaddr: *mut c_char,
+}
+
+/// Interval timer value (`struct itimerval` in `<sys/time.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct itimerval {
+    pub it_interval: timeval,
+    pub it_value: timeval,
+}
+
+/// Compile-time checks of the process structure layouts against the macOS SDK
+///
+/// The expected values were taken from the SDK headers for each supported architecture. x86_64 and arm64 currently
+/// share the same LP64 layout, but the values are listed per architecture so that a divergence in a future SDK only
+/// needs a change in one place.
+pub mod kinfo_proc_layout {
+    use std::mem::size_of;
+
+    use super::{eproc, extern_proc, kinfo_proc, ucred};
+
+    /// Computes the offset of a field in a `repr(C)` struct in a const context
+    macro_rules! offset_of {
+        ($ty:ty, $field:ident) => {{
+            let uninit = std::mem::MaybeUninit::<$ty>::uninit();
+            let base = uninit.as_ptr();
+            // SAFETY: only the address of the field is computed; the uninitialized memory is never read.
+            #[allow(unused_unsafe)]
+            unsafe {
+                (std::ptr::addr_of!((*base).$field) as *const u8).offset_from(base as *const u8) as usize
+            }
+        }};
+    }
+
+    /// Expected layout values from the macOS SDK
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub mod expected {
+        pub const KINFO_PROC_SIZE: usize = 648;
+        pub const EXTERN_PROC_SIZE: usize = 296;
+        pub const EPROC_SIZE: usize = 352;
+        pub const KP_EPROC_OFFSET: usize = 296;
+        pub const P_FLAG_OFFSET: usize = 32;
+        pub const P_STAT_OFFSET: usize = 36;
+        pub const P_PID_OFFSET: usize = 40;
+        pub const P_COMM_OFFSET: usize = 243;
+        pub const E_PCRED_OFFSET: usize = 16;
+        pub const E_UCRED_OFFSET: usize = 120;
+        pub const E_PPID_OFFSET: usize = 264;
+        pub const E_PGID_OFFSET: usize = 268;
+        pub const E_TDEV_OFFSET: usize = 276;
+        pub const E_FLAG_OFFSET: usize = 316;
+        pub const CR_UID_OFFSET: usize = 4;
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    compile_error!("kinfo_proc layout has only been verified for x86_64 and aarch64");
+
+    pub const KINFO_PROC_SIZE: usize = size_of::<kinfo_proc>();
+    pub const P_PID_OFFSET: usize = offset_of!(extern_proc, p_pid);
+    pub const P_COMM_OFFSET: usize = offset_of!(extern_proc, p_comm);
+    pub const E_PPID_OFFSET: usize = offset_of!(eproc, e_ppid);
+
+    const _: () = {
+        assert!(size_of::<kinfo_proc>() == expected::KINFO_PROC_SIZE);
+        assert!(size_of::<extern_proc>() == expected::EXTERN_PROC_SIZE);
+        assert!(size_of::<eproc>() == expected::EPROC_SIZE);
+        assert!(offset_of!(kinfo_proc, kp_eproc) == expected::KP_EPROC_OFFSET);
+        assert!(offset_of!(extern_proc, p_flag) == expected::P_FLAG_OFFSET);
+        assert!(offset_of!(extern_proc, p_stat) == expected::P_STAT_OFFSET);
+        assert!(offset_of!(extern_proc, p_pid) == expected::P_PID_OFFSET);
+        assert!(offset_of!(extern_proc, p_comm) == expected::P_COMM_OFFSET);
+        assert!(offset_of!(eproc, e_pcred) == expected::E_PCRED_OFFSET);
+        assert!(offset_of!(eproc, e_ucred) == expected::E_UCRED_OFFSET);
+        assert!(offset_of!(eproc, e_ppid) == expected::E_PPID_OFFSET);
+        assert!(offset_of!(eproc, e_pgid) == expected::E_PGID_OFFSET);
+        assert!(offset_of!(eproc, e_tdev) == expected::E_TDEV_OFFSET);
+        assert!(offset_of!(eproc, e_flag) == expected::E_FLAG_OFFSET);
+        assert!(offset_of!(ucred, cr_uid) == expected::CR_UID_OFFSET);
+    };
+}
+
+/// Converts the byte length returned by `sysctl(KERN_PROC_ALL)` into a number of [`kinfo_proc`] entries
+///
+/// # Errors
+///
+/// Returns an error if the length isn't a multiple of `size_of::<kinfo_proc>()`, which means the kernel's struct
+/// layout doesn't match ours and every entry would be parsed from the wrong offset.
+pub fn kinfo_proc_count(buffer_len: usize) -> Result<usize> {
+    let entry_size = std::mem::size_of::<kinfo_proc>();
+    if !buffer_len.is_multiple_of(entry_size) {
+        return Err(Error::system(format!(
+            "kinfo_proc layout mismatch: sysctl returned {} bytes, which is not a multiple of the {} byte entry size",
+            buffer_len, entry_size
+        )));
+    }
+    Ok(buffer_len / entry_size)
+}
+
+/// Reads the kernel process table with `sysctl(CTL_KERN, KERN_PROC, KERN_PROC_ALL)`
+///
+/// # Errors
+///
+/// Returns an error if the sysctl fails or if the returned buffer doesn't match the [`kinfo_proc`] layout.
+pub fn list_kinfo_procs() -> Result<Vec<kinfo_proc>> {
+    use sysctl_constants::{CTL_KERN, KERN_PROC, KERN_PROC_ALL};
+
+    let mib = [CTL_KERN, KERN_PROC, KERN_PROC_ALL];
+    let entry_size = std::mem::size_of::<kinfo_proc>();
+
+    // The process table can grow between the size query and the read, so retry a few times with some slack.
+    for _ in 0..3 {
+        let mut size: usize = 0;
+        // SAFETY: a null buffer asks sysctl for the required size only.
+        let result = unsafe {
+            sysctl(mib.as_ptr(), 3, std::ptr::null_mut(), &mut size, std::ptr::null(), 0)
+        };
+        if result < 0 {
+            return Err(Error::process_error("Failed to get process list size"));
+        }
+
+        let capacity = kinfo_proc_count(size)? + 16;
+        let mut processes = vec![kinfo_proc::default(); capacity];
+        size = capacity * entry_size;
+
+        // SAFETY: the buffer holds `capacity` zero-initialized entries and `size` is its length in bytes.
+        let result = unsafe {
+            sysctl(
+                mib.as_ptr(),
+                3,
+                processes.as_mut_ptr() as *mut c_void,
+                &mut size,
+                std::ptr::null(),
+                0,
+            )
+        };
+
+        if result < 0 {
+            if std::io::Error::last_os_error().raw_os_error() == Some(libc::ENOMEM) {
+                continue;
+            }
+            return Err(Error::process_error("Failed to get process information"));
+        }
+
+        processes.truncate(kinfo_proc_count(size)?);
+        return Ok(processes);
+    }
+
+    Err(Error::process_error("Process table kept growing while it was being read"))
+}
+
+/// `proc_pidinfo` flavor returning [`proc_uniqidentifierinfo`]
+pub const PROC_PIDUNIQIDENTIFIERINFO: c_int = 17;
+
+/// Unique identifiers of a process (`struct proc_uniqidentifierinfo` in `<sys/proc_info.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct proc_uniqidentifierinfo {
+    /// UUID of the main executable
+    pub p_uuid: [u8; 16],
+    /// 64-bit identifier that isn't reused while the system is up, unlike the PID
+    pub p_uniqueid: u64,
+    /// Unique identifier of the parent process
+    pub p_puniqueid: u64,
+    /// PID version
+    pub p_idversion: i32,
+    pub p_reserve2: u32,
+    pub p_reserve3: u64,
+    pub p_reserve4: u64,
+}
+
+/// Reads the unique ID of a process, or `None` if the process doesn't exist or can't be inspected
+pub fn proc_unique_id(pid: u32) -> Option<u64> {
+    let mut info = proc_uniqidentifierinfo::default();
+    let size = std::mem::size_of::<proc_uniqidentifierinfo>() as c_int;
+
+    // SAFETY: `info` is valid for writes of `size` bytes.
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid as c_int,
+            PROC_PIDUNIQIDENTIFIERINFO,
+            0,
+            &mut info as *mut _ as *mut c_void,
+            size,
+        )
+    };
+
+    (written == size).then_some(info.p_uniqueid)
+}
+
+/// `proc_pidinfo` flavor returning [`proc_bsdshortinfo`], which unlike the full BSD info is readable for processes of
+/// other users
+pub const PROC_PIDT_SHORTBSDINFO: c_int = 13;
+
+/// The process is an application, as registered by LaunchServices
+pub const PROC_FLAG_APPLICATION: u32 = 0x80000;
+/// The process has put itself into the darwin background state
+pub const PROC_FLAG_DARWINBG: u32 = 0x8000;
+/// The darwin background state was applied to the process from outside, e.g. by App Nap
+pub const PROC_FLAG_EXT_DARWINBG: u32 = 0x10000;
+
+/// Short BSD information of a process (`struct proc_bsdshortinfo` in `<sys/proc_info.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct proc_bsdshortinfo {
+    pub pbsi_pid: u32,
+    pub pbsi_ppid: u32,
+    pub pbsi_pgid: u32,
+    pub pbsi_status: u32,
+    pub pbsi_comm: [u8; 16],
+    /// `PROC_FLAG_*` bits
+    pub pbsi_flags: u32,
+    pub pbsi_uid: u32,
+    pub pbsi_gid: u32,
+    pub pbsi_ruid: u32,
+    pub pbsi_rgid: u32,
+    pub pbsi_svuid: u32,
+    pub pbsi_svgid: u32,
+    pub pbsi_rfu: u32,
+}
+
+/// Reads the short BSD information of a process
+///
+/// # Errors
+///
+/// Returns the OS error if the process doesn't exist or can't be inspected.
+pub fn proc_bsd_short_info(pid: u32) -> std::io::Result<proc_bsdshortinfo> {
+    let mut info = proc_bsdshortinfo::default();
+    let size = std::mem::size_of::<proc_bsdshortinfo>() as c_int;
+
+    // SAFETY: `info` is valid for writes of `size` bytes.
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid as c_int,
+            PROC_PIDT_SHORTBSDINFO,
+            0,
+            &mut info as *mut _ as *mut c_void,
+            size,
+        )
+    };
+
+    if written == size {
+        Ok(info)
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// `proc_pidfdinfo` flavor returning [`vnode_fdinfowithpath`]
+pub const PROC_PIDFDVNODEPATHINFO: c_int = 2;
+
+/// Open file state of a descriptor (`struct proc_fileinfo` in `<sys/proc_info.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct proc_fileinfo {
+    pub fi_openflags: u32,
+    pub fi_status: u32,
+    pub fi_offset: libc::off_t,
+    pub fi_type: i32,
+    pub fi_guardflags: u32,
+}
+
+/// A vnode descriptor along with the path of the vnode (`struct vnode_fdinfowithpath` in `<sys/proc_info.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct vnode_fdinfowithpath {
+    pub pfi: proc_fileinfo,
+    pub pvip: libc::vnode_info_path,
+}
+
+/// Reads the path of the vnode open as descriptor `fd` of a process, or `None` if the descriptor isn't a vnode, has
+/// been closed, or can't be inspected
+pub fn proc_fd_vnode_path(pid: u32, fd: i32) -> Option<String> {
+    // SAFETY: the structure is plain integers and bytes, for which zero is valid
+    let mut info: vnode_fdinfowithpath = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<vnode_fdinfowithpath>() as c_int;
+
+    // SAFETY: `info` is valid for writes of `size` bytes.
+    let written = unsafe {
+        libc::proc_pidfdinfo(
+            pid as c_int,
+            fd,
+            PROC_PIDFDVNODEPATHINFO,
+            &mut info as *mut _ as *mut c_void,
+            size,
+        )
+    };
+    if written != size {
+        return None;
+    }
+
+    let path: Vec<u8> =
+        info.pvip.vip_path.iter().flatten().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+    (!path.is_empty()).then(|| String::from_utf8_lossy(&path).into_owned())
+}
+
+/// `proc_pidfdinfo` flavor returning [`socket_fdinfo`]
+pub const PROC_PIDFDSOCKETINFO: c_int = 3;
+
+/// `soi_kind` of an IP socket other than TCP, with [`socket_info_proto::pri_in`]
+pub const SOCKINFO_IN: c_int = 1;
+/// `soi_kind` of a TCP socket, with [`socket_info_proto::pri_tcp`]
+pub const SOCKINFO_TCP: c_int = 2;
+
+/// `insi_vflag` bit of a socket with IPv4 addresses
+pub const INI_IPV4: u8 = 0x1;
+/// `insi_vflag` bit of a socket with IPv6 addresses
+pub const INI_IPV6: u8 = 0x2;
+
+/// Buffer of a socket (`struct sockbuf_info` in `<sys/proc_info.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct sockbuf_info {
+    /// Bytes in the buffer
+    pub sbi_cc: u32,
+    /// Size of the buffer, `SO_RCVBUF` or `SO_SNDBUF`
+    pub sbi_hiwat: u32,
+    pub sbi_mbcnt: u32,
+    pub sbi_mbmax: u32,
+    pub sbi_lowat: u32,
+    pub sbi_flags: i16,
+    pub sbi_timeo: i16,
+}
+
+/// IPv4 part of [`in_sockinfo`]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct in_sockinfo_v4 {
+    pub in4_tos: u8,
+}
+
+/// IPv6 part of [`in_sockinfo`]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct in_sockinfo_v6 {
+    pub in6_hlim: u8,
+    pub in6_cksum: c_int,
+    pub in6_ifindex: u16,
+    pub in6_hops: i16,
+}
+
+/// Endpoints of an IP socket (`struct in_sockinfo` in `<sys/proc_info.h>`)
+///
+/// The addresses are `in6_addr`s, or `in4in6_addr`s holding the IPv4 address in their last 4 bytes, depending on
+/// `insi_vflag`. The ports are in network byte order.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct in_sockinfo {
+    pub insi_fport: c_int,
+    pub insi_lport: c_int,
+    pub insi_gencnt: u64,
+    pub insi_flags: u32,
+    pub insi_flow: u32,
+    /// [`INI_IPV4`] and [`INI_IPV6`] bits
+    pub insi_vflag: u8,
+    pub insi_ip_ttl: u8,
+    pub rfu_1: u32,
+    pub insi_faddr: [u8; 16],
+    pub insi_laddr: [u8; 16],
+    pub insi_v4: in_sockinfo_v4,
+    pub insi_v6: in_sockinfo_v6,
+}
+
+/// A TCP socket (`struct tcp_sockinfo` in `<sys/proc_info.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct tcp_sockinfo {
+    pub tcpsi_ini: in_sockinfo,
+    /// `TCPS_*` state
+    pub tcpsi_state: c_int,
+    pub tcpsi_timer: [c_int; 4],
+    pub tcpsi_mss: c_int,
+    pub tcpsi_flags: u32,
+    pub rfu_1: u32,
+    pub tcpsi_tp: u64,
+}
+
+/// Protocol part of [`socket_info`], selected by `soi_kind`
+///
+/// Only the IP variants are declared; the padding covers the largest one, `un_sockinfo`.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union socket_info_proto {
+    pub pri_in: in_sockinfo,
+    pub pri_tcp: tcp_sockinfo,
+    _size: [u64; 66],
+}
+
+/// A socket (`struct socket_info` in `<sys/proc_info.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct socket_info {
+    pub soi_stat: libc::vinfo_stat,
+    pub soi_so: u64,
+    pub soi_pcb: u64,
+    pub soi_type: c_int,
+    pub soi_protocol: c_int,
+    pub soi_family: c_int,
+    pub soi_options: i16,
+    pub soi_linger: i16,
+    pub soi_state: i16,
+    pub soi_qlen: i16,
+    pub soi_incqlen: i16,
+    pub soi_qlimit: i16,
+    pub soi_timeo: i16,
+    pub soi_error: u16,
+    pub soi_oobmark: u32,
+    pub soi_rcv: sockbuf_info,
+    pub soi_snd: sockbuf_info,
+    /// `SOCKINFO_*` kind, which selects the variant of `soi_proto`
+    pub soi_kind: c_int,
+    pub rfu_1: u32,
+    pub soi_proto: socket_info_proto,
+}
+
+/// A socket descriptor along with the socket (`struct socket_fdinfo` in `<sys/proc_info.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct socket_fdinfo {
+    pub pfi: proc_fileinfo,
+    pub psi: socket_info,
+}
+
+// The size `proc_pidfdinfo` expects, from the SDK headers
+const _: () = assert!(std::mem::size_of::<socket_fdinfo>() == 792);
+
+/// Reads the socket open as descriptor `fd` of a process, or `None` if the descriptor isn't a socket, has been closed,
+/// or can't be inspected
+pub fn proc_fd_socket_info(pid: u32, fd: i32) -> Option<socket_fdinfo> {
+    // SAFETY: the structure is plain integers and bytes, for which zero is valid
+    let mut info: socket_fdinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<socket_fdinfo>() as c_int;
+
+    // SAFETY: `info` is valid for writes of `size` bytes.
+    let written = unsafe {
+        libc::proc_pidfdinfo(
+            pid as c_int,
+            fd,
+            PROC_PIDFDSOCKETINFO,
+            &mut info as *mut _ as *mut c_void,
+            size,
+        )
+    };
+
+    (written == size).then_some(info)
 }
 
 /// Time value structure used in BSD APIs
@@ -106,6 +711,14 @@ extern "C" {
 pub const KERN_SUCCESS: i32 = 0;
 pub const HOST_VM_INFO64: i32 = 4;
 pub const HOST_VM_INFO64_COUNT: u32 = 38;
+pub const HOST_CPU_LOAD_INFO: i32 = 3;
+pub const HOST_CPU_LOAD_INFO_COUNT: u32 = 4;
+
+// Indices into host_cpu_load_info::cpu_ticks
+pub const CPU_STATE_USER: usize = 0;
+pub const CPU_STATE_SYSTEM: usize = 1;
+pub const CPU_STATE_IDLE: usize = 2;
+pub const CPU_STATE_NICE: usize = 3;
 
 pub type HostInfoT = *mut i32;
 pub type MachPortT = u32;
@@ -139,12 +752,94 @@ pub struct vm_statistics64 {
     pub total_uncompressed_pages_in_compressor: u64,
 }
 
+/// Ticks spent in each CPU state by all processors since boot; the 32-bit counters wrap
 #[repr(C)]
 #[derive(Debug, Default)]
+pub struct host_cpu_load_info {
+    pub cpu_ticks: [u32; 4],
+}
+
+/// `host_processor_info` flavor returning one [`processor_cpu_load_info`] per logical CPU
+pub const PROCESSOR_CPU_LOAD_INFO: i32 = 2;
+
+/// Ticks spent in each CPU state by one processor since boot, indexed like [`host_cpu_load_info`]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct processor_cpu_load_info {
+    pub cpu_ticks: [u32; 4],
+}
+
+/// Swap usage returned by `sysctl(CTL_VM, VM_SWAPUSAGE)` (`<sys/sysctl.h>`)
+///
+/// The kernel only fills a buffer of exactly this size, so a missing field makes the sysctl fail.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct xsw_usage {
+    /// Total swap space in bytes
     pub xsu_total: u64,
-    pub xsu_used: u64,
+    /// Free swap space in bytes
     pub xsu_avail: u64,
+    /// Used swap space in bytes
+    pub xsu_used: u64,
+    /// Page size of the swap files in bytes
+    pub xsu_pagesize: u32,
+    /// Whether the swap files are encrypted (`boolean_t`)
+    pub xsu_encrypted: i32,
+}
+
+/// Size of [`xsw_usage`] in the macOS SDK
+pub const XSW_USAGE_SIZE: usize = 32;
+
+const _: () = assert!(std::mem::size_of::<xsw_usage>() == XSW_USAGE_SIZE);
+
+/// Decodes the buffer filled by `sysctl(CTL_VM, VM_SWAPUSAGE)`
+///
+/// # Errors
+///
+/// Returns an error if the buffer isn't the size of [`xsw_usage`], which means the kernel's struct layout doesn't match
+/// ours.
+pub fn decode_xsw_usage(buffer: &[u8]) -> Result<xsw_usage> {
+    if buffer.len() != XSW_USAGE_SIZE {
+        return Err(Error::system(format!(
+            "xsw_usage layout mismatch: sysctl returned {} bytes instead of {XSW_USAGE_SIZE}",
+            buffer.len()
+        )));
+    }
+    // SAFETY: the buffer holds exactly one xsw_usage, which has no invalid bit patterns, and is read unaligned.
+    Ok(unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const xsw_usage) })
+}
+
+/// Reads the swap usage with `sysctl(CTL_VM, VM_SWAPUSAGE)`
+///
+/// # Errors
+///
+/// Returns an error if the sysctl fails or returns a buffer that doesn't match the [`xsw_usage`] layout.
+pub fn read_xsw_usage() -> Result<xsw_usage> {
+    use sysctl_constants::{CTL_VM, VM_SWAPUSAGE};
+
+    let mib = [CTL_VM, VM_SWAPUSAGE];
+    let mut buffer = [0u8; XSW_USAGE_SIZE];
+    let mut size = buffer.len();
+    // SAFETY: the buffer is valid for writes of `size` bytes.
+    let result = unsafe {
+        sysctl(
+            mib.as_ptr(),
+            mib.len() as u32,
+            buffer.as_mut_ptr() as *mut c_void,
+            &mut size,
+            std::ptr::null(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(Error::system(format!(
+            "Failed to read vm.swapusage: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    decode_xsw_usage(&buffer[..size.min(XSW_USAGE_SIZE)])
 }
 
 // Mach host functions
@@ -158,7 +853,182 @@ extern "C" {
         host_info_outCnt: *mut u32,
     ) -> i32;
 
+    pub fn host_statistics(
+        host_priv: MachPortT,
+        flavor: i32,
+        host_info_out: HostInfoT,
+        host_info_outCnt: *mut u32,
+    ) -> i32;
+
     pub fn mach_host_self() -> MachPortT;
+
+    /// Returns an array of per-processor information allocated out of line in this task, which has to be deallocated
+    /// with `vm_deallocate`
+    pub fn host_processor_info(
+        host: MachPortT,
+        flavor: i32,
+        out_processor_count: *mut u32,
+        out_processor_info: *mut *mut i32,
+        out_processor_info_cnt: *mut u32,
+    ) -> i32;
+}
+
+/// `processor_set_statistics` flavor of [`processor_set_load_info`]
+pub const PROCESSOR_SET_LOAD_INFO: i32 = 4;
+/// Size of [`processor_set_load_info`] in `natural_t` units
+pub const PROCESSOR_SET_LOAD_INFO_COUNT: u32 = 4;
+/// Scale of the fixed-point load average and Mach factor (`LOAD_SCALE` of `<mach/machine/vm_types.h>`)
+pub const LOAD_SCALE: i32 = 1000;
+
+/// Scheduler load of a processor set (`processor_set_load_info` of `<mach/processor_info.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct processor_set_load_info {
+    pub task_count: i32,
+    pub thread_count: i32,
+    /// Runnable threads times [`LOAD_SCALE`], averaged by the scheduler over the last few seconds
+    pub load_average: i32,
+    pub mach_factor: i32,
+}
+
+// Mach processor set functions
+extern "C" {
+    /// Looks up the name port of the default processor set, which has to be deallocated
+    pub fn processor_set_default(host: MachPortT, default_set: *mut MachPortT) -> i32;
+
+    pub fn processor_set_statistics(
+        pset: MachPortT,
+        flavor: i32,
+        info_out: *mut i32,
+        info_out_count: *mut u32,
+    ) -> i32;
+}
+
+// Mach task and port functions
+extern "C" {
+    static mach_task_self_: MachPortT;
+
+    pub fn mach_port_deallocate(task: MachPortT, name: MachPortT) -> i32;
+}
+
+/// `task_info` flavor of [`TaskVmInfo`]
+pub const TASK_VM_INFO: u32 = 22;
+
+/// Virtual memory statistics of a task (`task_vm_info` of `<mach/task_info.h>`), up to revision 1
+///
+/// The kernel fills as much of the structure as the count passed to `task_info` covers.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TaskVmInfo {
+    pub virtual_size: u64,
+    pub region_count: i32,
+    pub page_size: i32,
+    pub resident_size: u64,
+    pub resident_size_peak: u64,
+    pub device: u64,
+    pub device_peak: u64,
+    pub internal: u64,
+    pub internal_peak: u64,
+    pub external: u64,
+    pub external_peak: u64,
+    pub reusable: u64,
+    pub reusable_peak: u64,
+    pub purgeable_volatile_pmap: u64,
+    pub purgeable_volatile_resident: u64,
+    pub purgeable_volatile_virtual: u64,
+    /// Memory held in the compressor, including compressed memory swapped out
+    pub compressed: u64,
+    pub compressed_peak: u64,
+    pub compressed_lifetime: u64,
+    /// Added in revision 1
+    pub phys_footprint: u64,
+}
+
+/// Size of [`TaskVmInfo`] in `natural_t` units, the count `task_info` takes (`TASK_VM_INFO_REV1_COUNT`)
+pub const TASK_VM_INFO_REV1_COUNT: u32 =
+    (std::mem::size_of::<TaskVmInfo>() / std::mem::size_of::<u32>()) as u32;
+
+/// Ratio converting Mach absolute time units to nanoseconds (`mach_timebase_info_data_t` of `<mach/mach_time.h>`)
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct mach_timebase_info_data {
+    pub numer: u32,
+    pub denom: u32,
+}
+
+// Mach time functions
+extern "C" {
+    pub fn mach_timebase_info(info: *mut mach_timebase_info_data) -> i32;
+}
+
+/// Converts Mach absolute time units, such as the CPU times in task info, to nanoseconds
+///
+/// The units are nanoseconds on Intel Macs but counter ticks on Apple Silicon.
+pub fn mach_ticks_to_nanos(ticks: u64) -> u64 {
+    static TIMEBASE: once_cell::sync::OnceCell<mach_timebase_info_data> =
+        once_cell::sync::OnceCell::new();
+
+    let timebase = TIMEBASE.get_or_init(|| {
+        let mut info = mach_timebase_info_data::default();
+        // SAFETY: `info` is valid for writes
+        let result = unsafe { mach_timebase_info(&mut info) };
+        if result != 0 || info.denom == 0 {
+            mach_timebase_info_data { numer: 1, denom: 1 }
+        } else {
+            info
+        }
+    });
+
+    (ticks as u128 * timebase.numer as u128 / timebase.denom as u128) as u64
+}
+
+/// Returns the task port of the current process (the `mach_task_self()` macro of `<mach/mach_init.h>`)
+pub fn mach_task_self() -> MachPortT {
+    // SAFETY: the variable is initialized by libSystem before any Rust code runs and never changes afterwards
+    unsafe { mach_task_self_ }
+}
+
+/// Maximum length of a kernel zone name (`ZONE_NAME_MAX_LEN` of `<mach_debug/zone_info.h>`)
+#[cfg(feature = "zones")]
+pub const ZONE_NAME_MAX_LEN: usize = 80;
+
+/// Name of a kernel zone (`mach_zone_name_t` of `<mach_debug/zone_info.h>`)
+#[cfg(feature = "zones")]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mach_zone_name {
+    pub mzn_name: [c_char; ZONE_NAME_MAX_LEN],
+}
+
+/// Usage of a kernel zone (`mach_zone_info_t` of `<mach_debug/zone_info.h>`)
+#[cfg(feature = "zones")]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct mach_zone_info {
+    pub mzi_count: u64,       // Number of elements in use
+    pub mzi_cur_size: u64,    // Current memory utilization in bytes
+    pub mzi_max_size: u64,    // Size the zone may grow to
+    pub mzi_elem_size: u64,   // Size of an element
+    pub mzi_alloc_size: u64,  // Size of each chunk the zone grows by
+    pub mzi_sum_size: u64,    // Sum of all allocations over the life of the zone
+    pub mzi_exhaustible: u64, // Whether allocations fail instead of growing when the zone is full
+    pub mzi_collectable: u64, // Whether the zone is garbage collected
+}
+
+// Kernel zone introspection; the arrays are returned as out-of-line memory the caller must deallocate
+#[cfg(feature = "zones")]
+extern "C" {
+    pub fn mach_zone_info(
+        host: MachPortT,
+        names: *mut *mut mach_zone_name,
+        names_count: *mut u32,
+        info: *mut *mut mach_zone_info,
+        info_count: *mut u32,
+    ) -> i32;
 }
 
 //------------------------------------------------------------------------------
@@ -166,12 +1036,47 @@ extern "C" {
 //------------------------------------------------------------------------------
 
 // IOKit constants
+/// `kIOMasterPortDefault` (`kIOMainPortDefault` since macOS 12): `MACH_PORT_NULL`, which IOKit replaces with the
+/// default main port
+pub const IO_MASTER_PORT_DEFAULT: u32 = 0;
 pub const KERNEL_INDEX_SMC: u32 = 2;
 pub const SMC_CMD_READ_BYTES: u8 = 5;
+pub const SMC_CMD_READ_INDEX: u8 = 8;
 pub const SMC_CMD_READ_KEYINFO: u8 = 9;
 pub const IO_RETURN_SUCCESS: i32 = 0; // Renamed from kIOReturnSuccess to follow Rust naming convention
+/// `kIOReturnNoDevice`: the service went away
+pub const IO_RETURN_NO_DEVICE: i32 = 0xE000_02C0_u32 as i32;
+/// `kIOReturnIPCError`: the message couldn't be delivered
+pub const IO_RETURN_IPC_ERROR: i32 = 0xE000_02CA_u32 as i32;
+/// `kIOReturnNotOpen`: the connection was closed
+pub const IO_RETURN_NOT_OPEN: i32 = 0xE000_02CD_u32 as i32;
+/// `kIOReturnNotPrivileged`: the caller isn't privileged enough
+pub const IO_RETURN_NOT_PRIVILEGED: i32 = 0xE000_02C1_u32 as i32;
+/// `kIOReturnBadArgument`: an argument was invalid
+pub const IO_RETURN_BAD_ARGUMENT: i32 = 0xE000_02C2_u32 as i32;
+/// `kIOReturnUnsupported`: the driver doesn't support the call
+pub const IO_RETURN_UNSUPPORTED: i32 = 0xE000_02C7_u32 as i32;
+/// `kIOReturnTimeout`: the call timed out
+pub const IO_RETURN_TIMEOUT: i32 = 0xE000_02D6_u32 as i32;
+/// `kIOReturnBusy`: the device is busy
+pub const IO_RETURN_BUSY: i32 = 0xE000_02D5_u32 as i32;
+/// `kIOReturnNotReady`: the device isn't ready yet
+pub const IO_RETURN_NOT_READY: i32 = 0xE000_02D8_u32 as i32;
+/// `kIOReturnNotPermitted`: the call isn't permitted
+pub const IO_RETURN_NOT_PERMITTED: i32 = 0xE000_02E2_u32 as i32;
+/// `kIOReturnNotFound`: the data the call asked for wasn't found
+pub const IO_RETURN_NOT_FOUND: i32 = 0xE000_02F0_u32 as i32;
+/// `MACH_SEND_INVALID_DEST`: the port of the connection is dead
+pub const MACH_SEND_INVALID_DEST: i32 = 0x1000_0003;
 
 // IOKit basic types
+/// Handle of an open connection to a driver's user client (`<IOKit/IOTypes.h>`)
+#[allow(non_camel_case_types)]
+pub type io_connect_t = u32;
+/// Handle of a service in the IORegistry (`<IOKit/IOTypes.h>`)
+#[allow(non_camel_case_types)]
+pub type io_service_t = u32;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct IOByteCount(pub usize);
@@ -187,6 +1092,37 @@ pub const SMC_KEY_CPU_TEMP: [c_char; 4] =
 pub const SMC_KEY_GPU_TEMP: [c_char; 4] =
     [b'T' as c_char, b'G' as c_char, b'0' as c_char, b'P' as c_char]; // GPU Temp (TG0P)
 
+/// GPU sensors of Intel Macs without `TG0P`, tried in order: the die of the integrated or only GPU (`TG0D`, most
+/// models), the die of the discrete GPU of the 2019 16-inch MacBook Pro (`TGDD`), the second GPU die of dual-GPU
+/// MacBook Pros (`TG1D`) and the GPU heatsink (`TG0H`, iMacs)
+pub const SMC_KEYS_GPU_TEMP_INTEL: [[c_char; 4]; 4] = [
+    smc_key_chars(b"TG0D"),
+    smc_key_chars(b"TGDD"),
+    smc_key_chars(b"TG1D"),
+    smc_key_chars(b"TG0H"),
+];
+
+/// GPU die sensors of Apple Silicon, which has no `TG0P`, tried in order: M1 and its Pro/Max/Ultra variants (`Tg05`,
+/// `Tg0D`, `Tg0L`, `Tg0T`), M2 (`Tg0f`, `Tg0j`) and M3 (`Tf14` to `Tf2A`)
+///
+/// Sampled from the sensor tables of the Stats app, which lists the keys each generation reports.
+pub const SMC_KEYS_GPU_TEMP_APPLE_SILICON: [[c_char; 4]; 14] = [
+    smc_key_chars(b"Tg05"),
+    smc_key_chars(b"Tg0D"),
+    smc_key_chars(b"Tg0L"),
+    smc_key_chars(b"Tg0T"),
+    smc_key_chars(b"Tg0f"),
+    smc_key_chars(b"Tg0j"),
+    smc_key_chars(b"Tf14"),
+    smc_key_chars(b"Tf18"),
+    smc_key_chars(b"Tf19"),
+    smc_key_chars(b"Tf1A"),
+    smc_key_chars(b"Tf24"),
+    smc_key_chars(b"Tf28"),
+    smc_key_chars(b"Tf29"),
+    smc_key_chars(b"Tf2A"),
+];
+
 // Fan speed keys
 pub const SMC_KEY_FAN_NUM: [c_char; 4] =
     [b'F' as c_char, b'N' as c_char, b'u' as c_char, b'm' as c_char]; // Number of fans (FNum)
@@ -342,12 +1278,17 @@ impl Default for SMCKeyData_t {
     }
 }
 
+/// Search options of `IORegistryEntrySearchCFProperty`
+pub const IO_REGISTRY_ITERATE_RECURSIVELY: u32 = 1;
+pub const IO_REGISTRY_ITERATE_PARENTS: u32 = 2;
+
 // IOKit function declarations
 #[link(name = "IOKit", kind = "framework")]
 extern "C" {
     // IOService functions
     pub fn IOServiceGetMatchingService(masterPort: u32, matchingDict: *const ffi_c_void) -> u32;
     pub fn IOServiceMatching(serviceName: *const c_char) -> *mut ffi_c_void;
+    pub fn IORegistryEntryIDMatching(entryID: u64) -> *mut ffi_c_void;
     pub fn IOServiceOpen(service: u32, owningTask: u32, type_: u32, handle: *mut u32) -> i32;
     pub fn IOServiceClose(handle: u32) -> i32;
     pub fn IORegistryEntryCreateCFProperties(
@@ -356,6 +1297,38 @@ extern "C" {
         allocator: *mut ffi_c_void,
         options: u32,
     ) -> i32;
+    pub fn IOBSDNameMatching(
+        mainPort: u32,
+        options: u32,
+        bsdName: *const c_char,
+    ) -> *mut ffi_c_void;
+    pub fn IOObjectRelease(object: u32) -> i32;
+    pub fn IOServiceGetMatchingServices(
+        mainPort: u32,
+        matching: *const ffi_c_void,
+        existing: *mut u32,
+    ) -> i32;
+    pub fn IOIteratorNext(iterator: u32) -> u32;
+    pub fn IORegistryEntryGetName(entry: u32, name: *mut c_char) -> i32;
+    pub fn IORegistryEntryGetChildIterator(
+        entry: u32,
+        plane: *const c_char,
+        iterator: *mut u32,
+    ) -> i32;
+    pub fn IORegistryEntryCreateIterator(
+        entry: u32,
+        plane: *const c_char,
+        options: u32,
+        iterator: *mut u32,
+    ) -> i32;
+    pub fn IORegistryEntryFromPath(mainPort: u32, path: *const c_char) -> u32;
+    pub fn IORegistryEntrySearchCFProperty(
+        entry: u32,
+        plane: *const c_char,
+        key: *const ffi_c_void,
+        allocator: *const ffi_c_void,
+        options: u32,
+    ) -> *mut ffi_c_void;
 
     // SMC specific functions
     pub fn IOConnectCallStructMethod(
@@ -368,6 +1341,364 @@ extern "C" {
     ) -> i32;
 }
 
+/// The 16 bytes of a CoreFoundation UUID, passed by value to `QueryInterface`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CFUUIDBytes {
+    pub bytes: [u8; 16],
+}
+
+/// `kIOCFPlugInInterfaceID`, the interface every IOKit plug-in is created with
+pub const IO_CF_PLUGIN_INTERFACE_ID: CFUUIDBytes = CFUUIDBytes {
+    bytes: [
+        0xC2, 0x44, 0xE8, 0x58, 0x10, 0x9C, 0x11, 0xD4, 0x91, 0xD4, 0x00, 0x50, 0xE4, 0xC6, 0x42,
+        0x6F,
+    ],
+};
+
+/// The `IUnknown` functions every COM-style plug-in interface starts with
+#[repr(C)]
+pub struct IUnknownVTbl {
+    pub _reserved: *mut ffi_c_void,
+    pub query_interface: unsafe extern "C" fn(
+        this: *mut ffi_c_void,
+        iid: CFUUIDBytes,
+        interface: *mut *mut ffi_c_void,
+    ) -> i32,
+    pub add_ref: unsafe extern "C" fn(this: *mut ffi_c_void) -> u32,
+    pub release: unsafe extern "C" fn(this: *mut ffi_c_void) -> u32,
+}
+
+/// `IOCFPlugInInterface`, up to the fields that are used
+#[repr(C)]
+pub struct IOCFPlugInInterface {
+    pub iunknown: IUnknownVTbl,
+    pub version: u16,
+    pub revision: u16,
+}
+
+// IOKit plug-in functions, used to open the user clients of drivers that are only reachable through a plug-in
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    pub fn IOCreatePlugInInterfaceForService(
+        service: u32,
+        plugin_type: *const ffi_c_void,
+        interface_type: *const ffi_c_void,
+        the_interface: *mut *mut *mut IOCFPlugInInterface,
+        the_score: *mut i32,
+    ) -> i32;
+    pub fn IODestroyPlugInInterface(interface: *mut *mut IOCFPlugInInterface) -> i32;
+}
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    /// Creates a UUID from its bytes, returned with a +1 retain count
+    pub fn CFUUIDCreateFromUUIDBytes(
+        allocator: *const ffi_c_void,
+        bytes: CFUUIDBytes,
+    ) -> *mut ffi_c_void;
+}
+
+// IOKit power management functions
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    /// Copies the level of each power management assertion type, as a dictionary of assertion type to number
+    pub fn IOPMCopyAssertionsStatus(assertions_status: *mut *mut ffi_c_void) -> i32;
+
+    /// Copies the power management settings of each power source, as a dictionary of power source name to settings,
+    /// or returns null if they can't be read
+    pub fn IOPMCopyActivePMPreferences() -> *mut ffi_c_void;
+
+    /// Creates a run loop source, with a +1 retain count, that calls `callback` whenever a power source changes, e.g.
+    /// the adapter is plugged in or the battery charge changes; null on failure
+    pub fn IOPSNotificationCreateRunLoopSource(
+        callback: IOPowerSourceCallback,
+        context: *mut ffi_c_void,
+    ) -> *mut ffi_c_void;
+}
+
+/// `IOPowerSourceCallbackType`
+pub type IOPowerSourceCallback = unsafe extern "C" fn(context: *mut ffi_c_void);
+
+// IOReport, the private library powermetrics reads residency counters from. Channels, samples and subscriptions are
+// CoreFoundation objects; the Copy and Create functions return them with a +1 retain count.
+#[link(name = "IOReport", kind = "dylib")]
+extern "C" {
+    /// Copies the channels of `group`, optionally narrowed to `subgroup`, as a mutable dictionary
+    pub fn IOReportCopyChannelsInGroup(
+        group: *const ffi_c_void,
+        subgroup: *const ffi_c_void,
+        a: u64,
+        b: u64,
+        c: u64,
+    ) -> *mut ffi_c_void;
+
+    /// Subscribes to `desired_channels`, returning the subscription and the channels it covers
+    pub fn IOReportCreateSubscription(
+        a: *const ffi_c_void,
+        desired_channels: *mut ffi_c_void,
+        subscribed_channels: *mut *mut ffi_c_void,
+        channel_id: u64,
+        b: *const ffi_c_void,
+    ) -> *mut ffi_c_void;
+
+    /// Samples the counters of the subscribed channels, as a dictionary with the channels under `IOReportChannels`
+    pub fn IOReportCreateSamples(
+        subscription: *mut ffi_c_void,
+        subscribed_channels: *mut ffi_c_void,
+        a: *const ffi_c_void,
+    ) -> *mut ffi_c_void;
+
+    /// Computes the counters accumulated between two samples
+    pub fn IOReportCreateSamplesDelta(
+        previous: *const ffi_c_void,
+        current: *const ffi_c_void,
+        a: *const ffi_c_void,
+    ) -> *mut ffi_c_void;
+
+    pub fn IOReportChannelGetGroup(channel: *const ffi_c_void) -> *const ffi_c_void;
+    pub fn IOReportChannelGetSubGroup(channel: *const ffi_c_void) -> *const ffi_c_void;
+    pub fn IOReportChannelGetChannelName(channel: *const ffi_c_void) -> *const ffi_c_void;
+
+    /// Number of states of a state channel
+    pub fn IOReportStateGetCount(channel: *const ffi_c_void) -> i32;
+    pub fn IOReportStateGetNameForIndex(
+        channel: *const ffi_c_void,
+        index: i32,
+    ) -> *const ffi_c_void;
+    /// Time spent in a state, in the unit of the channel
+    pub fn IOReportStateGetResidency(channel: *const ffi_c_void, index: i32) -> i64;
+}
+
+/// HID event type of temperature events (`kIOHIDEventTypeTemperature`)
+#[cfg(feature = "hid-sensors")]
+pub const IOHID_EVENT_TYPE_TEMPERATURE: i64 = 15;
+
+/// Vendor usage page and usage under which the thermal sensors of Apple Silicon are published
+#[cfg(feature = "hid-sensors")]
+pub const IOHID_THERMAL_USAGE_PAGE: i32 = 0xff00;
+#[cfg(feature = "hid-sensors")]
+pub const IOHID_THERMAL_USAGE: i32 = 5;
+
+// Private IOHIDEventSystem functions used to read the thermal sensors of Apple Silicon
+#[cfg(feature = "hid-sensors")]
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    pub fn IOHIDEventSystemClientCreate(allocator: *const ffi_c_void) -> *mut ffi_c_void;
+    pub fn IOHIDEventSystemClientSetMatching(
+        client: *mut ffi_c_void,
+        matching: *const ffi_c_void,
+    ) -> i32;
+    pub fn IOHIDEventSystemClientCopyServices(client: *mut ffi_c_void) -> *mut ffi_c_void;
+    pub fn IOHIDServiceClientCopyProperty(
+        service: *mut ffi_c_void,
+        key: *const ffi_c_void,
+    ) -> *mut ffi_c_void;
+    pub fn IOHIDServiceClientCopyEvent(
+        service: *mut ffi_c_void,
+        event_type: i64,
+        options: i32,
+        timeout: i64,
+    ) -> *mut ffi_c_void;
+    pub fn IOHIDEventGetFloatValue(event: *mut ffi_c_void, field: i32) -> f64;
+}
+
+// CoreGraphics display functions used to list the online displays and their sleep state
+#[cfg(feature = "coregraphics")]
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    pub fn CGGetOnlineDisplayList(
+        max_displays: u32,
+        online_displays: *mut u32,
+        display_count: *mut u32,
+    ) -> i32;
+    pub fn CGDisplayIsAsleep(display: u32) -> u32;
+    pub fn CGDisplayIsBuiltin(display: u32) -> u32;
+}
+
+/// Address of an audio or CoreMediaIO object property (`AudioObjectPropertyAddress`, `CMIOObjectPropertyAddress`)
+#[cfg(feature = "av-status")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectPropertyAddress {
+    pub selector: u32,
+    pub scope: u32,
+    pub element: u32,
+}
+
+/// Builds a four-character code such as `'dev#'`
+#[cfg(feature = "av-status")]
+pub const fn four_char_code(code: &[u8; 4]) -> u32 {
+    u32::from_be_bytes(*code)
+}
+
+/// Constants shared by CoreAudio and CoreMediaIO objects
+#[cfg(feature = "av-status")]
+pub mod av_properties {
+    use super::four_char_code;
+
+    /// ID of the system object of both frameworks
+    pub const SYSTEM_OBJECT: u32 = 1;
+    /// `kAudioHardwarePropertyDevices`, `kCMIOHardwarePropertyDevices`
+    pub const HARDWARE_PROPERTY_DEVICES: u32 = four_char_code(b"dev#");
+    /// `kAudioDevicePropertyDeviceIsRunningSomewhere`, `kCMIODevicePropertyDeviceIsRunningSomewhere`
+    pub const DEVICE_IS_RUNNING_SOMEWHERE: u32 = four_char_code(b"gone");
+    /// `kAudioObjectPropertyName`, `kCMIOObjectPropertyName`
+    pub const OBJECT_PROPERTY_NAME: u32 = four_char_code(b"lnam");
+    /// `kAudioDevicePropertyStreams`
+    pub const DEVICE_PROPERTY_STREAMS: u32 = four_char_code(b"stm#");
+    /// `kAudioObjectPropertyScopeGlobal`, `kCMIOObjectPropertyScopeGlobal`
+    pub const SCOPE_GLOBAL: u32 = four_char_code(b"glob");
+    /// `kAudioObjectPropertyScopeInput`
+    pub const SCOPE_INPUT: u32 = four_char_code(b"inpt");
+    /// `kAudioObjectPropertyElementMain`, `kCMIOObjectPropertyElementMain`
+    pub const ELEMENT_MAIN: u32 = 0;
+}
+
+// CoreAudio property functions used to list audio devices and whether they are in use
+#[cfg(feature = "av-status")]
+#[link(name = "CoreAudio", kind = "framework")]
+extern "C" {
+    pub fn AudioObjectGetPropertyDataSize(
+        object: u32,
+        address: *const ObjectPropertyAddress,
+        qualifier_size: u32,
+        qualifier: *const ffi_c_void,
+        data_size: *mut u32,
+    ) -> i32;
+    pub fn AudioObjectGetPropertyData(
+        object: u32,
+        address: *const ObjectPropertyAddress,
+        qualifier_size: u32,
+        qualifier: *const ffi_c_void,
+        data_size: *mut u32,
+        data: *mut ffi_c_void,
+    ) -> i32;
+}
+
+// CoreMediaIO property functions used to list video devices and whether they are in use
+#[cfg(feature = "av-status")]
+#[link(name = "CoreMediaIO", kind = "framework")]
+extern "C" {
+    pub fn CMIOObjectGetPropertyDataSize(
+        object: u32,
+        address: *const ObjectPropertyAddress,
+        qualifier_size: u32,
+        qualifier: *const ffi_c_void,
+        data_size: *mut u32,
+    ) -> i32;
+    pub fn CMIOObjectGetPropertyData(
+        object: u32,
+        address: *const ObjectPropertyAddress,
+        qualifier_size: u32,
+        qualifier: *const ffi_c_void,
+        data_size: u32,
+        data_used: *mut u32,
+        data: *mut ffi_c_void,
+    ) -> i32;
+}
+
+// CoreWLAN has no C functions; linking it registers the CWWiFiClient and CWInterface classes with the runtime
+#[cfg(feature = "wifi")]
+#[link(name = "CoreWLAN", kind = "framework")]
+extern "C" {}
+
+/// `DADiskAppearedCallback` and `DADiskDisappearedCallback`
+#[cfg(feature = "disk-events")]
+pub type DADiskCallback = unsafe extern "C" fn(disk: *mut ffi_c_void, context: *mut ffi_c_void);
+
+/// `DADiskDescriptionChangedCallback`, which also receives the array of changed description keys
+#[cfg(feature = "disk-events")]
+pub type DADiskDescriptionChangedCallback =
+    unsafe extern "C" fn(disk: *mut ffi_c_void, keys: *const ffi_c_void, context: *mut ffi_c_void);
+
+// DiskArbitration session functions used to watch disks appear, disappear and change. Sessions and descriptions are
+// CoreFoundation objects; DASessionCreate and DADiskCopyDescription return them with a +1 retain count.
+#[cfg(feature = "disk-events")]
+#[link(name = "DiskArbitration", kind = "framework")]
+extern "C" {
+    /// Key of the mount point in a disk description, a file URL
+    pub static kDADiskDescriptionVolumePathKey: *const ffi_c_void;
+
+    pub fn DASessionCreate(allocator: *const ffi_c_void) -> *mut ffi_c_void;
+    pub fn DASessionScheduleWithRunLoop(
+        session: *mut ffi_c_void,
+        run_loop: *mut ffi_c_void,
+        mode: *const ffi_c_void,
+    );
+    pub fn DASessionUnscheduleFromRunLoop(
+        session: *mut ffi_c_void,
+        run_loop: *mut ffi_c_void,
+        mode: *const ffi_c_void,
+    );
+    pub fn DARegisterDiskAppearedCallback(
+        session: *mut ffi_c_void,
+        matching: *const ffi_c_void,
+        callback: DADiskCallback,
+        context: *mut ffi_c_void,
+    );
+    pub fn DARegisterDiskDisappearedCallback(
+        session: *mut ffi_c_void,
+        matching: *const ffi_c_void,
+        callback: DADiskCallback,
+        context: *mut ffi_c_void,
+    );
+    pub fn DARegisterDiskDescriptionChangedCallback(
+        session: *mut ffi_c_void,
+        matching: *const ffi_c_void,
+        watch: *const ffi_c_void,
+        callback: DADiskDescriptionChangedCallback,
+        context: *mut ffi_c_void,
+    );
+    pub fn DAUnregisterCallback(
+        session: *mut ffi_c_void,
+        callback: *mut ffi_c_void,
+        context: *mut ffi_c_void,
+    );
+    /// Returns the BSD name of the disk, e.g. `disk4s1`, or null for disks without one
+    pub fn DADiskGetBSDName(disk: *mut ffi_c_void) -> *const c_char;
+    pub fn DADiskCopyDescription(disk: *mut ffi_c_void) -> *mut ffi_c_void;
+}
+
+// CoreFoundation run loop functions the notification threads run on, and the value functions the DiskArbitration
+// callbacks read descriptions with
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    pub static kCFRunLoopDefaultMode: *const ffi_c_void;
+
+    pub fn CFRunLoopGetCurrent() -> *mut ffi_c_void;
+    pub fn CFRunLoopAddSource(
+        run_loop: *mut ffi_c_void,
+        source: *mut ffi_c_void,
+        mode: *const ffi_c_void,
+    );
+    pub fn CFRunLoopRemoveSource(
+        run_loop: *mut ffi_c_void,
+        source: *mut ffi_c_void,
+        mode: *const ffi_c_void,
+    );
+    /// Runs the run loop of the current thread for up to `seconds`, or until `CFRunLoopStop` is called on it from any
+    /// thread. Returns `kCFRunLoopRunFinished` (1) right away if the run loop has no sources.
+    pub fn CFRunLoopRunInMode(
+        mode: *const ffi_c_void,
+        seconds: f64,
+        return_after_source_handled: u8,
+    ) -> i32;
+    pub fn CFRunLoopStop(run_loop: *mut ffi_c_void);
+    pub fn CFRetain(cf: *const ffi_c_void) -> *const ffi_c_void;
+    /// Returns the value of `key` without retaining it, or null
+    pub fn CFDictionaryGetValue(
+        dict: *const ffi_c_void,
+        key: *const ffi_c_void,
+    ) -> *const ffi_c_void;
+    pub fn CFURLGetFileSystemRepresentation(
+        url: *const ffi_c_void,
+        resolve_against_base: u8,
+        buffer: *mut u8,
+        max_len: isize,
+    ) -> u8;
+}
+
 //------------------------------------------------------------------------------
 // Process state constants
 //------------------------------------------------------------------------------
@@ -432,6 +1763,9 @@ extern "C" {
     /// Creates and returns the default system Metal device Used to access GPU information including name and
     /// capabilities
     pub fn MTLCreateSystemDefaultDevice() -> MTLDeviceRef;
+
+    /// Returns an array of all Metal devices in the system, with a +1 retain count
+    pub fn MTLCopyAllDevices() -> *mut c_void;
 }
 
 //------------------------------------------------------------------------------
@@ -625,6 +1959,9 @@ extern "C" {
     pub fn getifaddrs(ifap: *mut *mut ifaddrs) -> c_int;
     pub fn freeifaddrs(ifp: *mut ifaddrs) -> c_void;
 
+    // System Integrity Protection configuration
+    pub fn csr_get_active_config(config: *mut u32) -> c_int;
+
     // sysctl functions for network statistics
     pub fn sysctlbyname(
         name: *const c_char,
@@ -659,6 +1996,13 @@ extern "C" {
         value: *mut *mut ffi_c_void,
     ) -> *mut ffi_c_void;
 
+    // Returns the user logged in at the console, or NULL at the login window
+    pub fn SCDynamicStoreCopyConsoleUser(
+        store: *mut ffi_c_void,
+        uid: *mut u32,
+        gid: *mut u32,
+    ) -> *mut ffi_c_void;
+
     // Network reachability functions
     pub fn SCNetworkReachabilityCreateWithAddress(
         allocator: *mut ffi_c_void,
@@ -676,7 +2020,7 @@ extern "C" {
 
 /// Extract the process name from a kinfo_proc structure
 pub fn extract_proc_name(proc_info: &kinfo_proc) -> String {
-    let raw_name = &proc_info.kp_eproc.p_comm;
+    let raw_name = &proc_info.kp_proc.p_comm;
     let end = raw_name.iter().position(|&c| c == 0).unwrap_or(raw_name.len());
     let name_slice = &raw_name[0..end];
     String::from_utf8_lossy(name_slice).to_string()
@@ -696,6 +2040,11 @@ pub fn is_system_process(pid: u32, name: &str) -> bool {
             .contains(&name)
 }
 
+/// The SMC key named by the four bytes `name`, e.g. `smc_key_chars(b"TG0D")`
+pub const fn smc_key_chars(name: &[u8; 4]) -> [c_char; 4] {
+    [name[0] as c_char, name[1] as c_char, name[2] as c_char, name[3] as c_char]
+}
+
 /// Convert a char array to an SMC key integer
 pub fn smc_key_from_chars(key: [c_char; 4]) -> u32 {
     let mut result: u32 = 0;
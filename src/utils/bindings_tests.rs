@@ -3,26 +3,23 @@ use std::ffi::c_char;
 
 use crate::error::Error;
 use crate::utils::bindings::{
-    address_family, extern_proc, extract_proc_name, get_network_stats_native, getloadavg,
-    if_data64, if_flags, is_system_process, kinfo_proc, proc_info, proc_state, reachability_flags,
-    smc_key_from_chars, sysctl_constants, timeval, MTLCreateSystemDefaultDevice, MTLDeviceRef,
-    Statfs, SMC_KEY_AMBIENT_TEMP, SMC_KEY_BATTERY_TEMP, SMC_KEY_CPU_POWER, SMC_KEY_CPU_TEMP,
-    SMC_KEY_CPU_THROTTLE, SMC_KEY_FAN_NUM, SMC_KEY_GPU_TEMP,
+    address_family, decode_xsw_usage, extract_proc_name, get_network_stats_native, getloadavg,
+    if_data64, if_flags, is_system_process, kinfo_proc, kinfo_proc_count, kinfo_proc_layout,
+    proc_state, reachability_flags, smc_key_from_chars, sysctl_constants, timeval, xsw_usage,
+    MTLCreateSystemDefaultDevice, MTLDeviceRef, Statfs, SMC_KEY_AMBIENT_TEMP, SMC_KEY_BATTERY_TEMP,
+    SMC_KEY_CPU_POWER, SMC_KEY_CPU_TEMP, SMC_KEY_CPU_THROTTLE, SMC_KEY_FAN_NUM, SMC_KEY_GPU_TEMP,
 };
 
 #[test]
 fn test_extract_proc_name() {
     // Create a kinfo_proc structure with a process name
-    let mut proc_info = kinfo_proc {
-        kp_proc: proc_info { p_flag: 0, p_pid: 123, p_ppid: 1, p_stat: 0 },
-        kp_eproc: extern_proc { p_starttime: timeval { tv_sec: 0, tv_usec: 0 }, p_comm: [0; 16] },
-    };
+    let mut proc_info = kinfo_proc::default();
 
     // Set a process name
     let test_name = b"test_process\0";
     for (i, &byte) in test_name.iter().enumerate() {
-        if i < proc_info.kp_eproc.p_comm.len() {
-            proc_info.kp_eproc.p_comm[i] = byte;
+        if i < proc_info.kp_proc.p_comm.len() {
+            proc_info.kp_proc.p_comm[i] = byte;
         }
     }
 
@@ -34,16 +31,13 @@ fn test_extract_proc_name() {
 #[test]
 fn test_extract_proc_name_no_null_terminator() {
     // Create a kinfo_proc structure with a process name that fills the entire buffer
-    let mut proc_info = kinfo_proc {
-        kp_proc: proc_info { p_flag: 0, p_pid: 123, p_ppid: 1, p_stat: 0 },
-        kp_eproc: extern_proc { p_starttime: timeval { tv_sec: 0, tv_usec: 0 }, p_comm: [0; 16] },
-    };
+    let mut proc_info = kinfo_proc::default();
 
     // Set a process name that fills the entire buffer (no null terminator)
     let test_name = b"sixteencharname"; // Exactly 16 characters
     for (i, &byte) in test_name.iter().enumerate() {
-        if i < proc_info.kp_eproc.p_comm.len() {
-            proc_info.kp_eproc.p_comm[i] = byte;
+        if i < proc_info.kp_proc.p_comm.len() {
+            proc_info.kp_proc.p_comm[i] = byte;
         }
     }
 
@@ -55,10 +49,7 @@ fn test_extract_proc_name_no_null_terminator() {
 #[test]
 fn test_extract_proc_name_empty() {
     // Create a kinfo_proc structure with an empty process name
-    let proc_info = kinfo_proc {
-        kp_proc: proc_info { p_flag: 0, p_pid: 123, p_ppid: 1, p_stat: 0 },
-        kp_eproc: extern_proc { p_starttime: timeval { tv_sec: 0, tv_usec: 0 }, p_comm: [0; 16] },
-    };
+    let proc_info = kinfo_proc::default();
 
     // Extract the name (should be empty)
     let extracted_name = extract_proc_name(&proc_info);
@@ -68,16 +59,13 @@ fn test_extract_proc_name_empty() {
 #[test]
 fn test_extract_proc_name_non_ascii() {
     // Create a kinfo_proc structure with a non-ASCII process name
-    let mut proc_info = kinfo_proc {
-        kp_proc: proc_info { p_flag: 0, p_pid: 123, p_ppid: 1, p_stat: 0 },
-        kp_eproc: extern_proc { p_starttime: timeval { tv_sec: 0, tv_usec: 0 }, p_comm: [0; 16] },
-    };
+    let mut proc_info = kinfo_proc::default();
 
     // Set a process name with non-ASCII characters Using UTF-8 bytes for "café" (with an accent)
     let test_name = b"caf\xC3\xA9\0";
     for (i, &byte) in test_name.iter().enumerate() {
-        if i < proc_info.kp_eproc.p_comm.len() {
-            proc_info.kp_eproc.p_comm[i] = byte;
+        if i < proc_info.kp_proc.p_comm.len() {
+            proc_info.kp_proc.p_comm[i] = byte;
         }
     }
 
@@ -86,6 +74,59 @@ fn test_extract_proc_name_non_ascii() {
     assert_eq!(extracted_name, "café");
 }
 
+#[test]
+fn test_kinfo_proc_layout() {
+    use std::mem::size_of;
+
+    assert_eq!(size_of::<kinfo_proc>(), kinfo_proc_layout::expected::KINFO_PROC_SIZE);
+    assert_eq!(kinfo_proc_layout::P_PID_OFFSET, kinfo_proc_layout::expected::P_PID_OFFSET);
+    assert_eq!(kinfo_proc_layout::P_COMM_OFFSET, kinfo_proc_layout::expected::P_COMM_OFFSET);
+    assert_eq!(kinfo_proc_layout::E_PPID_OFFSET, kinfo_proc_layout::expected::E_PPID_OFFSET);
+}
+
+#[test]
+fn test_kinfo_proc_count() {
+    let entry_size = std::mem::size_of::<kinfo_proc>();
+
+    assert_eq!(kinfo_proc_count(0).unwrap(), 0);
+    assert_eq!(kinfo_proc_count(entry_size * 3).unwrap(), 3);
+
+    // A length that isn't a multiple of the entry size means the layout doesn't match the kernel's
+    let err = kinfo_proc_count(entry_size * 3 + 8).unwrap_err();
+    assert!(matches!(err, Error::System(_)));
+}
+
+#[test]
+fn test_kinfo_proc_matches_libproc() {
+    use libproc::{bsd_info::BSDInfo, proc_pid::pidinfo};
+
+    let processes =
+        crate::utils::bindings::list_kinfo_procs().expect("failed to read process table");
+    assert!(!processes.is_empty());
+
+    // Our own process must be in the table with the correct parent
+    let own_pid = std::process::id() as i32;
+    let own = processes.iter().find(|p| p.pid() == own_pid).expect("current process not found");
+    assert_eq!(own.ppid(), std::os::unix::process::parent_id() as i32);
+
+    // Cross-check a spread of processes against libproc, skipping any that exit in the meantime
+    let step = (processes.len() / 10).max(1);
+    let mut checked = 0;
+    for process in processes.iter().step_by(step).take(10).filter(|p| p.pid() > 0) {
+        let Ok(info) = pidinfo::<BSDInfo>(process.pid(), 0) else {
+            continue;
+        };
+
+        let libproc_name: Vec<u8> =
+            info.pbi_comm.iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+        assert_eq!(extract_proc_name(process).as_bytes(), libproc_name.as_slice());
+        assert_eq!(process.ppid() as u32, info.pbi_ppid);
+        checked += 1;
+    }
+
+    assert!(checked > 0, "no processes could be cross-checked");
+}
+
 #[test]
 fn test_is_system_process() {
     // Test system processes
@@ -204,6 +245,55 @@ fn test_sysctl_constants() {
     assert_eq!(sysctl_constants::HW_MEMSIZE, 24);
 
     assert_eq!(sysctl_constants::VM_SWAPUSAGE, 5);
+
+    assert_eq!(sysctl_constants::CTL_NET, 4);
+    assert_eq!(sysctl_constants::PF_ROUTE, 17);
+    assert_eq!(sysctl_constants::NET_RT_IFLIST2, 6);
+}
+
+/// An `xsw_usage` buffer as the kernel fills it
+fn xsw_usage_buffer(total: u64, avail: u64, used: u64, pagesize: u32, encrypted: i32) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(&total.to_ne_bytes());
+    buffer.extend_from_slice(&avail.to_ne_bytes());
+    buffer.extend_from_slice(&used.to_ne_bytes());
+    buffer.extend_from_slice(&pagesize.to_ne_bytes());
+    buffer.extend_from_slice(&encrypted.to_ne_bytes());
+    buffer
+}
+
+#[test]
+fn test_decode_xsw_usage() {
+    const GIB: u64 = 1 << 30;
+    let buffer = xsw_usage_buffer(2 * GIB, GIB / 2, 3 * GIB / 2, 16384, 1);
+    assert_eq!(buffer.len(), std::mem::size_of::<xsw_usage>());
+
+    let usage = decode_xsw_usage(&buffer).unwrap();
+    assert_eq!(
+        usage,
+        xsw_usage {
+            xsu_total: 2 * GIB,
+            xsu_avail: GIB / 2,
+            xsu_used: 3 * GIB / 2,
+            xsu_pagesize: 16384,
+            xsu_encrypted: 1,
+        }
+    );
+}
+
+#[test]
+fn test_decode_xsw_usage_without_swap() {
+    let usage = decode_xsw_usage(&xsw_usage_buffer(0, 0, 0, 16384, 1)).unwrap();
+    assert_eq!(usage.xsu_total, 0);
+    assert_eq!(usage.xsu_used, 0);
+}
+
+#[test]
+fn test_decode_xsw_usage_size_mismatch() {
+    let buffer = xsw_usage_buffer(1, 2, 3, 4, 0);
+    assert!(matches!(decode_xsw_usage(&buffer[..24]), Err(Error::System(_))));
+    assert!(decode_xsw_usage(&[buffer.clone(), vec![0; 8]].concat()).is_err());
+    assert!(decode_xsw_usage(&[]).is_err());
 }
 
 #[test]
@@ -0,0 +1,222 @@
+//! A number of bytes that formats and parses itself
+//!
+//! The memory, disk and process APIs report sizes as `u64` byte counts. [`ByteSize`] wraps such a count to print it in
+//! binary units, e.g. `15.6 GiB`, and to read sizes from configuration, e.g. `512MB` or `1.5 GiB`.
+//!
+//! Parsing accepts both families of units, case-insensitively:
+//!
+//! - SI units are powers of 1000: `kB`, `MB`, `GB`, `TB`, `PB`, `EB`
+//! - Binary units are powers of 1024: `KiB`, `MiB`, `GiB`, `TiB`, `PiB`, `EiB`, and the bare prefixes `K`, `M`, `G`,
+//!   ... that `du -h` and `df -h` print
+//!
+//! A number without a unit, or with `B`, counts bytes. Fractional sizes are rounded to the nearest byte.
+
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Div, Mul, Sub, SubAssign},
+    str::FromStr,
+};
+
+use crate::error::{Error, Result};
+
+/// Binary units from the largest down, with their size in bytes
+const BINARY_UNITS: [(&str, u64); 6] = [
+    ("EiB", ByteSize::PIB * 1024),
+    ("PiB", ByteSize::PIB),
+    ("TiB", ByteSize::TIB),
+    ("GiB", ByteSize::GIB),
+    ("MiB", ByteSize::MIB),
+    ("KiB", ByteSize::KIB),
+];
+
+/// A size in bytes
+///
+/// Formatting uses binary units with one decimal, or as many as the format precision asks for, e.g.
+/// `format!("{:.2}", size)`. Sizes below 1 KiB are printed as whole bytes. With the `serde` feature, a size serializes
+/// as its plain byte count.
+///
+/// Adding, subtracting and multiplying saturate instead of overflowing; dividing by zero panics like it does for
+/// `u64`.
+///
+/// ```rust
+/// use darwin_metrics::ByteSize;
+///
+/// let size: ByteSize = "1.5 GiB".parse()?;
+/// assert_eq!(size, ByteSize::from_mib(1536));
+/// assert_eq!(size.to_string(), "1.5 GiB");
+/// # Ok::<(), darwin_metrics::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct ByteSize(u64);
+
+impl ByteSize {
+    /// Bytes in a kibibyte
+    pub const KIB: u64 = 1024;
+    /// Bytes in a mebibyte
+    pub const MIB: u64 = Self::KIB * 1024;
+    /// Bytes in a gibibyte
+    pub const GIB: u64 = Self::MIB * 1024;
+    /// Bytes in a tebibyte
+    pub const TIB: u64 = Self::GIB * 1024;
+    /// Bytes in a pebibyte
+    pub const PIB: u64 = Self::TIB * 1024;
+
+    /// A size of `bytes` bytes.
+    pub const fn from_bytes(bytes: u64) -> Self {
+        Self(bytes)
+    }
+
+    /// A size of `kib` kibibytes, saturating at `u64::MAX` bytes.
+    pub const fn from_kib(kib: u64) -> Self {
+        Self(kib.saturating_mul(Self::KIB))
+    }
+
+    /// A size of `mib` mebibytes, saturating at `u64::MAX` bytes.
+    pub const fn from_mib(mib: u64) -> Self {
+        Self(mib.saturating_mul(Self::MIB))
+    }
+
+    /// A size of `gib` gibibytes, saturating at `u64::MAX` bytes.
+    pub const fn from_gib(gib: u64) -> Self {
+        Self(gib.saturating_mul(Self::GIB))
+    }
+
+    /// The size in bytes.
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match BINARY_UNITS.iter().find(|(_, size)| self.0 >= *size) {
+            Some((unit, size)) => {
+                let precision = f.precision().unwrap_or(1);
+                write!(f, "{:.*} {unit}", precision, self.0 as f64 / *size as f64)
+            },
+            None => write!(f, "{} B", self.0),
+        }
+    }
+}
+
+/// The size in bytes of the unit `unit`, None if it isn't one
+fn unit_size(unit: &str) -> Option<u64> {
+    let unit = unit.to_ascii_lowercase();
+    if matches!(unit.as_str(), "" | "b" | "byte" | "bytes") {
+        return Some(1);
+    }
+
+    let mut chars = unit.chars();
+    let exponent = match chars.next()? {
+        'k' => 1,
+        'm' => 2,
+        'g' => 3,
+        't' => 4,
+        'p' => 5,
+        'e' => 6,
+        _ => return None,
+    };
+    let base: u64 = match chars.as_str() {
+        "" | "i" | "ib" => 1024,
+        "b" => 1000,
+        _ => return None,
+    };
+    Some(base.pow(exponent))
+}
+
+impl FromStr for ByteSize {
+    type Err = Error;
+
+    /// Parses a number with an optional unit, e.g. `4096`, `512MB` or `1.5 GiB`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidData`] if the string isn't a non-negative number followed by a known unit, or if the
+    /// size doesn't fit in a `u64`.
+    fn from_str(s: &str) -> Result<Self> {
+        let trimmed = s.trim();
+        let invalid = || Error::invalid_data(format!("Invalid byte size: {s:?}"));
+
+        let split =
+            trimmed.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(trimmed.len());
+        let (number, unit) = trimmed.split_at(split);
+        let unit_size = unit_size(unit.trim_start()).ok_or_else(invalid)?;
+
+        // Whole numbers are multiplied exactly, which floating point can't do for the largest sizes
+        if let Ok(count) = number.parse::<u64>() {
+            return count.checked_mul(unit_size).map(Self).ok_or_else(invalid);
+        }
+
+        let count: f64 = number.parse().map_err(|_| invalid())?;
+        let bytes = (count * unit_size as f64).round();
+        // `u64::MAX as f64` rounds up to 2^64, which no longer fits
+        if !bytes.is_finite() || bytes >= u64::MAX as f64 {
+            return Err(invalid());
+        }
+        Ok(Self(bytes as u64))
+    }
+}
+
+impl From<u64> for ByteSize {
+    fn from(bytes: u64) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<ByteSize> for u64 {
+    fn from(size: ByteSize) -> Self {
+        size.0
+    }
+}
+
+impl Add for ByteSize {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+}
+
+impl AddAssign for ByteSize {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for ByteSize {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+impl SubAssign for ByteSize {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+impl Mul<u64> for ByteSize {
+    type Output = Self;
+
+    fn mul(self, factor: u64) -> Self {
+        Self(self.0.saturating_mul(factor))
+    }
+}
+
+impl Div<u64> for ByteSize {
+    type Output = Self;
+
+    fn div(self, divisor: u64) -> Self {
+        Self(self.0 / divisor)
+    }
+}
+
+impl Sum for ByteSize {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
@@ -0,0 +1,113 @@
+use crate::utils::byte_size::ByteSize;
+
+fn parse(s: &str) -> ByteSize {
+    s.parse().unwrap_or_else(|e| panic!("Failed to parse {s:?}: {e}"))
+}
+
+#[test]
+fn test_constructors() {
+    assert_eq!(ByteSize::from_kib(1).as_u64(), 1024);
+    assert_eq!(ByteSize::from_mib(3).as_u64(), 3 * 1024 * 1024);
+    assert_eq!(ByteSize::from_gib(16).as_u64(), 16 << 30);
+    assert_eq!(ByteSize::from_gib(u64::MAX).as_u64(), u64::MAX, "Saturates");
+    assert_eq!(u64::from(ByteSize::from(42)), 42);
+}
+
+#[test]
+fn test_display_uses_binary_units() {
+    assert_eq!(ByteSize::from_bytes(0).to_string(), "0 B");
+    assert_eq!(ByteSize::from_bytes(1023).to_string(), "1023 B");
+    assert_eq!(ByteSize::from_kib(1).to_string(), "1.0 KiB");
+    assert_eq!(ByteSize::from_bytes(16_750_372_454).to_string(), "15.6 GiB");
+    assert_eq!(ByteSize::from_gib(2048).to_string(), "2.0 TiB");
+    assert_eq!(ByteSize::from_bytes(u64::MAX).to_string(), "16.0 EiB");
+    assert_eq!(format!("{:.2}", ByteSize::from_mib(1536)), "1.50 GiB");
+    assert_eq!(format!("{:.0}", ByteSize::from_mib(1536)), "2 GiB");
+}
+
+#[test]
+fn test_parse_units() {
+    assert_eq!(parse("4096"), ByteSize::from_bytes(4096));
+    assert_eq!(parse("12 B"), ByteSize::from_bytes(12));
+    assert_eq!(parse("512MB"), ByteSize::from_bytes(512_000_000));
+    assert_eq!(parse("512MiB"), ByteSize::from_mib(512));
+    assert_eq!(parse("1.5 GiB"), ByteSize::from_mib(1536));
+    assert_eq!(parse("1.5 gb"), ByteSize::from_bytes(1_500_000_000));
+    assert_eq!(parse("16G"), ByteSize::from_gib(16), "Bare prefixes are binary, like `df -h`");
+    assert_eq!(parse("  2 kIb "), ByteSize::from_kib(2));
+    assert_eq!(parse("0.5k"), ByteSize::from_bytes(512));
+    assert_eq!(parse(".5 KB"), ByteSize::from_bytes(500));
+}
+
+#[test]
+fn test_parse_edge_cases() {
+    assert_eq!(parse("0"), ByteSize::default());
+    assert_eq!(parse("0 TiB"), ByteSize::default());
+    assert_eq!(parse("3 PB"), ByteSize::from_bytes(3_000_000_000_000_000));
+    assert_eq!(parse("2 PiB"), ByteSize::from_bytes(2 * ByteSize::PIB));
+    assert_eq!(parse("15 EiB"), ByteSize::from_bytes(15 << 60));
+    assert_eq!(parse("18446744073709551615"), ByteSize::from_bytes(u64::MAX));
+    assert_eq!(parse("1.0000000001 B"), ByteSize::from_bytes(1), "Rounded to whole bytes");
+}
+
+#[test]
+fn test_parse_rejects_malformed_sizes() {
+    for malformed in [
+        "",
+        "   ",
+        "GiB",
+        "-1 KiB",
+        "1.2.3 MB",
+        "12 XB",
+        "12 GiBs",
+        "1e3",
+        "NaN",
+        "inf",
+        "16 EiB",
+        "18446744073709551616",
+        "99999999999999999999999 B",
+    ] {
+        assert!(malformed.parse::<ByteSize>().is_err(), "{malformed:?} was parsed");
+    }
+}
+
+#[test]
+fn test_display_round_trip() {
+    for size in [
+        ByteSize::from_bytes(0),
+        ByteSize::from_bytes(999),
+        ByteSize::from_kib(7),
+        ByteSize::from_mib(1536),
+        ByteSize::from_gib(16),
+        ByteSize::from_bytes(5 * ByteSize::PIB),
+    ] {
+        assert_eq!(parse(&size.to_string()), size);
+    }
+}
+
+#[test]
+fn test_arithmetic_saturates() {
+    let size = ByteSize::from_gib(1);
+    assert_eq!(size + ByteSize::from_gib(1), ByteSize::from_gib(2));
+    assert_eq!(size - ByteSize::from_gib(2), ByteSize::default());
+    assert_eq!(ByteSize::from_bytes(u64::MAX) + size, ByteSize::from_bytes(u64::MAX));
+    assert_eq!(size * 3, ByteSize::from_gib(3));
+    assert_eq!(size / 4, ByteSize::from_mib(256));
+
+    let mut total = size;
+    total += ByteSize::from_mib(512);
+    total -= ByteSize::from_mib(256);
+    assert_eq!(total, ByteSize::from_mib(1280));
+
+    let sum: ByteSize = [size, size, size].into_iter().sum();
+    assert_eq!(sum, ByteSize::from_gib(3));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serializes_as_an_integer() {
+    let size = ByteSize::from_mib(3);
+    let json = serde_json::to_string(&size).unwrap();
+    assert_eq!(json, "3145728");
+    assert_eq!(serde_json::from_str::<ByteSize>(&json).unwrap(), size);
+}
@@ -0,0 +1,73 @@
+//! Fixed-point integer representations of metrics
+//!
+//! Consumers that can't use floating point, e.g. because they hash serialized metrics and need the bytes to be
+//! deterministic, can convert the crate's `f64` values with these functions. Each unit is an integer count of a fixed
+//! fraction of the float unit:
+//!
+//! | Metric      | Float unit | Fixed unit             | Type  |
+//! |-------------|------------|------------------------|-------|
+//! | Percentage  | %          | basis points (0.01 %)  | `u16` |
+//! | Temperature | °C         | millidegrees Celsius   | `i32` |
+//! | Power       | W          | milliwatts             | `u32` |
+//! | Frequency   | MHz        | kilohertz              | `u32` |
+//!
+//! The conversions round half up, i.e. towards positive infinity, so -0.5 m°C becomes 0 and 2.5 bp becomes 3. Values
+//! outside the range of the integer type saturate at its bounds, and NaN becomes 0. A percentage above 100 %, such as
+//! the CPU usage of a process using several cores, keeps its value up to 655.35 %.
+//!
+//! Converting a fixed value to a float and back yields the same fixed value.
+
+/// Basis points per percent
+const BASIS_POINTS_PER_PERCENT: f64 = 100.0;
+/// Millidegrees per degree, milliwatts per watt, and kilohertz per megahertz
+const MILLI: f64 = 1000.0;
+
+/// Scales `value` and rounds it half up; the result still has to be cast to the integer type, which saturates.
+fn scale_half_up(value: f64, scale: f64) -> f64 {
+    (value * scale + 0.5).floor()
+}
+
+/// A percentage in basis points, e.g. 42.5 % is 4250.
+pub fn basis_points(percent: f64) -> u16 {
+    scale_half_up(percent, BASIS_POINTS_PER_PERCENT) as u16
+}
+
+/// A temperature in degrees Celsius in millidegrees, e.g. -3.2 °C is -3200.
+pub fn millicelsius(celsius: f64) -> i32 {
+    scale_half_up(celsius, MILLI) as i32
+}
+
+/// A power in watts in milliwatts, e.g. 12.3 W is 12300.
+pub fn milliwatts(watts: f64) -> u32 {
+    scale_half_up(watts, MILLI) as u32
+}
+
+/// A frequency in megahertz in kilohertz, e.g. 3228.0 MHz is 3228000.
+pub fn kilohertz(mhz: f64) -> u32 {
+    scale_half_up(mhz, MILLI) as u32
+}
+
+/// A value in thousandths of its unit, e.g. seconds in milliseconds or a load average of 1.25 as 1250.
+pub fn thousandths(value: f64) -> i64 {
+    scale_half_up(value, MILLI) as i64
+}
+
+/// The percentage a number of basis points stands for.
+pub fn from_basis_points(basis_points: u16) -> f64 {
+    f64::from(basis_points) / BASIS_POINTS_PER_PERCENT
+}
+
+/// The temperature in degrees Celsius a number of millidegrees stands for.
+pub fn from_millicelsius(millicelsius: i32) -> f64 {
+    f64::from(millicelsius) / MILLI
+}
+
+/// The power in watts a number of milliwatts stands for.
+pub fn from_milliwatts(milliwatts: u32) -> f64 {
+    f64::from(milliwatts) / MILLI
+}
+
+/// The frequency in megahertz a number of kilohertz stands for.
+pub fn from_kilohertz(kilohertz: u32) -> f64 {
+    f64::from(kilohertz) / MILLI
+}
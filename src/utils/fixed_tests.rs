@@ -0,0 +1,115 @@
+use crate::utils::fixed::{
+    basis_points, from_basis_points, from_kilohertz, from_millicelsius, from_milliwatts, kilohertz,
+    millicelsius, milliwatts, thousandths,
+};
+use crate::utils::sanitize::Percentage;
+
+#[test]
+fn test_basis_points() {
+    assert_eq!(basis_points(0.0), 0);
+    assert_eq!(basis_points(42.5), 4250);
+    assert_eq!(basis_points(100.0), 10_000);
+    assert_eq!(Percentage::new_clamped(12.34).basis_points(), 1234);
+    assert_eq!(Percentage::new_clamped(150.0).basis_points(), 10_000);
+}
+
+#[test]
+fn test_basis_points_above_100_percent() {
+    // CPU usage of a process using four cores
+    assert_eq!(basis_points(400.0), 40_000);
+    assert_eq!(Percentage::new_clamped_to(750.0, 800.0).basis_points(), 65_535);
+    assert_eq!(basis_points(655.35), 65_535);
+    assert_eq!(basis_points(655.36), u16::MAX);
+}
+
+#[test]
+fn test_rounding_half_up() {
+    // Multiples of 1/16 scale to exact halves
+    assert_eq!(basis_points(0.125), 13);
+    assert_eq!(basis_points(0.0625), 6);
+    assert_eq!(basis_points(0.004), 0);
+    assert_eq!(milliwatts(0.0625), 63);
+    assert_eq!(milliwatts(0.0624), 62);
+    assert_eq!(kilohertz(0.0625), 63);
+    assert_eq!(kilohertz(3228.0), 3_228_000);
+    assert_eq!(thousandths(1.0625), 1063);
+    assert_eq!(thousandths(-1.0625), -1062);
+}
+
+#[test]
+fn test_negative_temperatures() {
+    assert_eq!(millicelsius(-3.2), -3200);
+    assert_eq!(millicelsius(-40.0), -40_000);
+    // Half up rounds towards positive infinity
+    assert_eq!(millicelsius(-0.0005), 0);
+    assert_eq!(millicelsius(-0.0625), -62);
+    assert_eq!(millicelsius(-0.0626), -63);
+    assert_eq!(millicelsius(-0.0), 0);
+    assert_eq!(millicelsius(0.0625), 63);
+}
+
+#[test]
+fn test_saturation() {
+    assert_eq!(basis_points(-1.0), 0);
+    assert_eq!(basis_points(f64::MAX), u16::MAX);
+    assert_eq!(basis_points(f64::INFINITY), u16::MAX);
+    assert_eq!(basis_points(f64::NEG_INFINITY), 0);
+
+    assert_eq!(millicelsius(f64::MAX), i32::MAX);
+    assert_eq!(millicelsius(f64::MIN), i32::MIN);
+    assert_eq!(millicelsius(3_000_000.0), i32::MAX);
+    assert_eq!(millicelsius(-3_000_000.0), i32::MIN);
+
+    assert_eq!(milliwatts(-5.0), 0);
+    assert_eq!(milliwatts(5_000_000.0), u32::MAX);
+    assert_eq!(kilohertz(-1.0), 0);
+    assert_eq!(kilohertz(f64::INFINITY), u32::MAX);
+
+    assert_eq!(thousandths(f64::MAX), i64::MAX);
+    assert_eq!(thousandths(f64::MIN), i64::MIN);
+}
+
+#[test]
+fn test_nan_is_zero() {
+    assert_eq!(basis_points(f64::NAN), 0);
+    assert_eq!(millicelsius(f64::NAN), 0);
+    assert_eq!(milliwatts(f64::NAN), 0);
+    assert_eq!(kilohertz(f64::NAN), 0);
+    assert_eq!(thousandths(f64::NAN), 0);
+}
+
+#[test]
+fn test_from_fixed() {
+    assert_eq!(from_basis_points(4250), 42.5);
+    assert_eq!(from_millicelsius(-3200), -3.2);
+    assert_eq!(from_milliwatts(12_300), 12.3);
+    assert_eq!(from_kilohertz(3_228_000), 3228.0);
+}
+
+#[test]
+fn test_round_trip_basis_points() {
+    for bp in 0..=u16::MAX {
+        assert_eq!(basis_points(from_basis_points(bp)), bp);
+    }
+}
+
+#[test]
+fn test_round_trip_millicelsius() {
+    // Every value around zero and the bounds, and a stride through the whole range
+    let values = (-1000..=1000)
+        .chain(i32::MIN..=i32::MIN + 1000)
+        .chain(i32::MAX - 1000..=i32::MAX)
+        .chain((i32::MIN..=i32::MAX).step_by(65_537));
+    for mc in values {
+        assert_eq!(millicelsius(from_millicelsius(mc)), mc);
+    }
+}
+
+#[test]
+fn test_round_trip_milliwatts_and_kilohertz() {
+    let values = (0..=1000).chain(u32::MAX - 1000..=u32::MAX).chain((0..=u32::MAX).step_by(65_537));
+    for value in values {
+        assert_eq!(milliwatts(from_milliwatts(value)), value);
+        assert_eq!(kilohertz(from_kilohertz(value)), value);
+    }
+}
@@ -3,20 +3,39 @@
 /// This module contains various utilities used throughout the crate, including:
 ///
 /// - `bindings`: FFI bindings for macOS system APIs (sysctl, IOKit, etc.)
+/// - `byte_size`: Sizes in bytes that format and parse themselves
 /// - `property_utils`: Utilities for working with property lists and dictionaries
+/// - `retry`: Retrying IOKit and sysctl calls that fail transiently
 /// - `test_utils`: Utilities for testing
 /// - `mock_dictionary`: A pure Rust mock dictionary for testing
 /// - `dictionary_access`: A trait for abstracting dictionary access operations
+/// - `sanitize`: Range checks keeping computed percentages and rates finite
+/// - `fixed`: Fixed-point integer representations of metrics
+/// - `serde_secs`: Durations serialized as seconds (requires the `serde` feature)
 pub mod bindings;
 #[cfg(test)]
 mod bindings_tests;
+pub mod byte_size;
 pub mod dictionary_access;
+pub mod fixed;
 pub mod mock_dictionary;
 pub mod property_utils;
+pub mod retry;
+pub mod sanitize;
+#[cfg(feature = "serde")]
+pub mod serde_secs;
 pub mod test_utils;
 
+#[cfg(test)]
+mod byte_size_tests;
+#[cfg(test)]
+mod fixed_tests;
 #[cfg(test)]
 mod property_utils_tests;
+#[cfg(test)]
+mod retry_tests;
+#[cfg(test)]
+mod sanitize_tests;
 
 use std::{
     ffi::{c_char, CStr},
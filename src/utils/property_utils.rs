@@ -1,3 +1,4 @@
+use objc2::{class, msg_send, rc::Retained};
 use objc2_foundation::{NSDictionary, NSNumber, NSObject, NSString};
 
 /// Trait for common property access patterns in IOKit and Foundation
@@ -25,6 +26,17 @@ pub trait PropertyUtils {
             .and_then(|obj| obj.downcast::<NSNumber>().ok())
             .map(|n| n.as_bool())
     }
+
+    /// Get a nested dictionary property from a dictionary
+    fn get_dict_property(
+        dict: &NSDictionary<NSString, NSObject>,
+        key: &str,
+    ) -> Option<Retained<NSDictionary<NSString, NSObject>>> {
+        let obj = dict.valueForKey(&NSString::from_str(key))?;
+        let is_dict: bool = unsafe { msg_send![&obj, isKindOfClass: class!(NSDictionary)] };
+        // SAFETY: the object was just checked to be a dictionary, and property list dictionaries have string keys
+        is_dict.then(|| unsafe { Retained::cast_unchecked(obj) })
+    }
 }
 
 /// Default implementation of PropertyUtils
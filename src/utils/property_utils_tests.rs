@@ -25,11 +25,13 @@ fn test_property_utils_trait() {
     let string_result = MockPropertyUtils::get_string_property(&dict, "test_key");
     let number_result = MockPropertyUtils::get_number_property(&dict, "test_key");
     let bool_result = MockPropertyUtils::get_bool_property(&dict, "test_key");
+    let dict_result = MockPropertyUtils::get_dict_property(&dict, "test_key");
 
     // Since our test dictionary is empty, all results should be None
     assert_eq!(string_result, None);
     assert_eq!(number_result, None);
     assert_eq!(bool_result, None);
+    assert!(dict_result.is_none());
 }
 
 #[test]
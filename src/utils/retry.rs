@@ -0,0 +1,199 @@
+//! Retrying transient failures
+//!
+//! SMC reads, registry lookups and sysctl calls occasionally fail for reasons that go away by themselves: a busy or
+//! not yet ready driver, an SMC connection that went stale, or a sysctl value that grew between reading its size and
+//! reading its data. [`with_retry`] runs such an operation again, after a delay, as long as a [`RetryPolicy`] allows.
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use darwin_metrics::utils::retry::{with_retry, Backoff, RetryPolicy};
+//!
+//! let policy = RetryPolicy {
+//!     max_attempts: 5,
+//!     backoff: Backoff::Fixed(Duration::from_millis(1)),
+//!     ..RetryPolicy::default()
+//! };
+//!
+//! let mut attempts = 0;
+//! let value = with_retry(policy, || {
+//!     attempts += 1;
+//!     if attempts < 3 {
+//!         Err(darwin_metrics::Error::from(std::io::Error::from_raw_os_error(libc::EAGAIN)))
+//!     } else {
+//!         Ok(42)
+//!     }
+//! })?;
+//! assert_eq!((value, attempts), (42, 3));
+//! # Ok::<(), darwin_metrics::Error>(())
+//! ```
+
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use crate::{
+    error::{Error, ErrorKind, Result},
+    utils::bindings::{
+        IO_RETURN_BUSY, IO_RETURN_IPC_ERROR, IO_RETURN_NOT_OPEN, IO_RETURN_NOT_READY,
+        MACH_SEND_INVALID_DEST,
+    },
+};
+
+/// `errno`s of calls worth repeating; sysctl reports a value that grew past the buffer with `ENOMEM`
+const TRANSIENT_ERRNOS: [i32; 4] = [libc::EAGAIN, libc::EINTR, libc::EBUSY, libc::ENOMEM];
+
+/// `IOReturn`s of calls worth repeating: a busy or not yet ready driver, or an SMC connection that went stale and is
+/// reopened by the next read
+const TRANSIENT_IO_RETURNS: [i32; 5] = [
+    IO_RETURN_BUSY,
+    IO_RETURN_NOT_READY,
+    IO_RETURN_NOT_OPEN,
+    IO_RETURN_IPC_ERROR,
+    MACH_SEND_INVALID_DEST,
+];
+
+/// How long to wait before each retry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// The same delay before every retry
+    Fixed(Duration),
+    /// A delay starting at `initial` and doubling with every retry, up to `max`
+    Exponential {
+        /// Delay before the first retry
+        initial: Duration,
+        /// Longest delay
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// The delay before retry number `retry`, counted from 1, without jitter.
+    pub fn delay(&self, retry: u32) -> Duration {
+        match *self {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => {
+                let factor = 1u32.checked_shl(retry.saturating_sub(1)).unwrap_or(u32::MAX);
+                initial.saturating_mul(factor).min(max)
+            },
+        }
+    }
+}
+
+/// When and how often [`with_retry`] runs an operation again
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Number of attempts, including the first one; 0 and 1 both run the operation once
+    pub max_attempts: u32,
+    /// Delay between two attempts
+    pub backoff: Backoff,
+    /// Share of each delay, between 0.0 and 1.0, that is randomly taken off, so callers failing together don't retry
+    /// together
+    pub jitter: f64,
+    /// Whether an error is worth retrying, usually decided from its [`kind`](Error::kind)
+    pub retry_if: fn(&Error) -> bool,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, 10 ms and then 20 ms apart minus up to a quarter of jitter, retrying [transient](is_transient)
+    /// errors.
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Backoff::Exponential {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(100),
+            },
+            jitter: 0.25,
+            retry_if: is_transient,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retry number `retry`, counted from 1, with jitter applied.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self.backoff.delay(retry);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return delay;
+        }
+        // Every RandomState is seeded differently, which is random enough to spread retries
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        delay.mul_f64(1.0 - jitter * random)
+    }
+
+    /// Whether a failed attempt number `attempt` with `err` is followed by another one.
+    fn should_retry(&self, attempt: u32, err: &Error) -> bool {
+        attempt < self.max_attempts && (self.retry_if)(err)
+    }
+}
+
+/// Whether `err` is likely to go away when the call is repeated: timeouts, IO errors with `EAGAIN`, `EINTR`, `EBUSY`
+/// or `ENOMEM`, and IOKit calls failing because the driver is busy or not ready, or the connection went stale.
+pub fn is_transient(err: &Error) -> bool {
+    match (err.kind(), err.os_code()) {
+        (ErrorKind::Timeout, _) => true,
+        (ErrorKind::System, Some(code)) => TRANSIENT_ERRNOS.contains(&code),
+        (ErrorKind::Ffi, Some(code)) => TRANSIENT_IO_RETURNS.contains(&code),
+        _ => false,
+    }
+}
+
+/// Runs `op` until it succeeds, fails with an error `policy` doesn't retry, or runs out of attempts, sleeping between
+/// attempts.
+///
+/// # Errors
+///
+/// Returns the error of the last attempt.
+pub fn with_retry<T, F>(policy: RetryPolicy, op: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    retry_blocking(policy, op, std::thread::sleep)
+}
+
+/// Runs `op` like [`with_retry`], sleeping asynchronously between attempts.
+///
+/// # Errors
+///
+/// Returns the error of the last attempt.
+pub async fn with_retry_async<T, F, Fut>(policy: RetryPolicy, mut op: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(err) if policy.should_retry(attempt, &err) => {
+                tracing::debug!(attempt, error = %err, "Retrying after a transient failure");
+                tokio::time::sleep(policy.delay(attempt)).await;
+                attempt += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+/// Runs `op` like [`with_retry`], waiting between attempts with `sleep`.
+pub(super) fn retry_blocking<T, F, S>(policy: RetryPolicy, mut op: F, mut sleep: S) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+    S: FnMut(Duration),
+{
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(err) if policy.should_retry(attempt, &err) => {
+                tracing::debug!(attempt, error = %err, "Retrying after a transient failure");
+                sleep(policy.delay(attempt));
+                attempt += 1;
+            },
+            result => return result,
+        }
+    }
+}
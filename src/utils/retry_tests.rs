@@ -0,0 +1,178 @@
+use std::{cell::Cell, io, time::Duration};
+
+use crate::{
+    error::Error,
+    utils::{
+        bindings::{IO_RETURN_BUSY, IO_RETURN_NOT_PRIVILEGED, IO_RETURN_NO_DEVICE},
+        retry::{is_transient, retry_blocking, with_retry, with_retry_async, Backoff, RetryPolicy},
+    },
+};
+
+fn policy(max_attempts: u32, backoff: Backoff) -> RetryPolicy {
+    RetryPolicy { max_attempts, backoff, jitter: 0.0, ..RetryPolicy::default() }
+}
+
+fn exponential() -> Backoff {
+    Backoff::Exponential { initial: Duration::from_millis(10), max: Duration::from_millis(50) }
+}
+
+/// Whether `elapsed` is `expected`, give or take the millisecond resolution of the tokio timer
+fn about(elapsed: Duration, expected: Duration) -> bool {
+    elapsed >= expected && elapsed <= expected + Duration::from_millis(1)
+}
+
+fn busy() -> Error {
+    Error::from(io::Error::from_raw_os_error(libc::EBUSY))
+}
+
+/// An operation that fails `failures` times with `error`, then returns the number of calls
+fn failing(
+    failures: u32,
+    error: fn() -> Error,
+    calls: &Cell<u32>,
+) -> impl FnMut() -> crate::Result<u32> + '_ {
+    move || {
+        calls.set(calls.get() + 1);
+        if calls.get() <= failures {
+            Err(error())
+        } else {
+            Ok(calls.get())
+        }
+    }
+}
+
+#[test]
+fn test_backoff_delays() {
+    let fixed = Backoff::Fixed(Duration::from_millis(5));
+    assert_eq!(fixed.delay(1), Duration::from_millis(5));
+    assert_eq!(fixed.delay(7), Duration::from_millis(5));
+
+    let delays: Vec<_> = (1..=5).map(|retry| exponential().delay(retry).as_millis()).collect();
+    assert_eq!(delays, [10, 20, 40, 50, 50]);
+    assert_eq!(exponential().delay(u32::MAX), Duration::from_millis(50), "Doesn't overflow");
+}
+
+#[test]
+fn test_jitter_shortens_delays() {
+    let policy =
+        RetryPolicy { jitter: 0.5, ..policy(3, Backoff::Fixed(Duration::from_millis(100))) };
+    for _ in 0..100 {
+        let delay = policy.delay(1);
+        assert!(
+            delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100),
+            "{delay:?}"
+        );
+    }
+}
+
+#[test]
+fn test_retries_until_success() {
+    let calls = Cell::new(0);
+    let mut sleeps = Vec::new();
+    let result =
+        retry_blocking(policy(5, exponential()), failing(3, busy, &calls), |d| sleeps.push(d));
+
+    assert_eq!(result.unwrap(), 4);
+    assert_eq!(sleeps, [10, 20, 40].map(Duration::from_millis));
+}
+
+#[test]
+fn test_gives_up_after_max_attempts() {
+    let calls = Cell::new(0);
+    let mut sleeps = Vec::new();
+    let result =
+        retry_blocking(policy(3, exponential()), failing(5, busy, &calls), |d| sleeps.push(d));
+
+    assert_eq!(result.unwrap_err().os_code(), Some(libc::EBUSY));
+    assert_eq!(calls.get(), 3);
+    assert_eq!(sleeps.len(), 2);
+
+    calls.set(0);
+    assert!(retry_blocking(policy(0, exponential()), failing(1, busy, &calls), |_| {}).is_err());
+    assert_eq!(calls.get(), 1, "No attempts still runs the operation once");
+}
+
+#[test]
+fn test_permanent_errors_are_not_retried() {
+    let calls = Cell::new(0);
+    let result =
+        with_retry(policy(5, exponential()), failing(1, || Error::not_available("test"), &calls));
+
+    assert!(result.unwrap_err().is_not_available());
+    assert_eq!(calls.get(), 1);
+
+    let retry_all = RetryPolicy { retry_if: |_| true, ..policy(5, Backoff::Fixed(Duration::ZERO)) };
+    calls.set(0);
+    assert_eq!(
+        with_retry(retry_all, failing(2, || Error::not_available("test"), &calls)).unwrap(),
+        3
+    );
+}
+
+#[test]
+fn test_transient_errors() {
+    assert!(is_transient(&busy()));
+    assert!(is_transient(&Error::from(io::Error::from_raw_os_error(libc::ENOMEM))));
+    assert!(is_transient(&Error::from(io::Error::new(io::ErrorKind::TimedOut, "test"))));
+    assert!(is_transient(&Error::io_return("test", IO_RETURN_BUSY)));
+
+    assert!(!is_transient(&Error::from(io::Error::from_raw_os_error(libc::EPERM))));
+    assert!(!is_transient(&Error::io_return("test", IO_RETURN_NOT_PRIVILEGED)));
+    assert!(!is_transient(&Error::io_return("test", IO_RETURN_NO_DEVICE)));
+    assert!(!is_transient(&Error::io_return("test", libc::EBUSY)), "An errno isn't an IOReturn");
+    assert!(!is_transient(&Error::invalid_data("test")));
+}
+
+#[tokio::test]
+async fn test_async_backoff_timing() {
+    tokio::time::pause();
+    let start = tokio::time::Instant::now();
+    let attempts = Cell::new(Vec::new());
+
+    let result = with_retry_async(policy(4, exponential()), || {
+        let mut times = attempts.take();
+        times.push(start.elapsed());
+        let failed = times.len() < 4;
+        attempts.set(times);
+        async move {
+            if failed {
+                Err(busy())
+            } else {
+                Ok(())
+            }
+        }
+    })
+    .await;
+
+    assert!(result.is_ok());
+    let times = attempts.take();
+    let gaps: Vec<_> = times.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    assert_eq!(times[0], Duration::ZERO);
+    for (gap, expected) in gaps.iter().zip([10, 20, 40].map(Duration::from_millis)) {
+        assert!(about(*gap, expected), "Waited {gap:?} instead of {expected:?}");
+    }
+    assert_eq!(gaps.len(), 3);
+}
+
+#[tokio::test]
+async fn test_async_gives_up_after_max_attempts() {
+    tokio::time::pause();
+    let start = tokio::time::Instant::now();
+    let calls = Cell::new(0);
+
+    let mut op = failing(10, busy, &calls);
+    let result = with_retry_async(policy(3, Backoff::Fixed(Duration::from_secs(1))), || {
+        let result = op();
+        async move { result }
+    })
+    .await;
+
+    assert_eq!(result.unwrap_err().os_code(), Some(libc::EBUSY));
+    assert_eq!(calls.get(), 3);
+    // Two retries, each of which the timer can round up
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed >= Duration::from_secs(2) && elapsed <= Duration::from_millis(2002),
+        "Waited {elapsed:?}"
+    );
+}
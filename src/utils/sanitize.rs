@@ -0,0 +1,76 @@
+//! Range checks for computed percentages and rates
+//!
+//! Every percentage and rate the crate computes goes through these helpers, so no public API returns NaN or an
+//! infinity. The policy is:
+//!
+//! - A percentage is clamped to its range. A NaN percentage becomes 0.0 and is logged at debug level, since it can
+//!   only come from a corrupt reading. A corrupt sensor stays corrupt, so a warning would repeat on every reading.
+//! - A rate over a zero, negative or non-finite interval is 0.0 without being logged; two readings at the same instant
+//!   are expected, e.g. right after startup. A rate that isn't finite over a valid interval becomes 0.0 and is logged
+//!   at debug level.
+//! - APIs that return an `Option` return `None` instead of a value that isn't finite.
+
+/// A percentage clamped to its valid range
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct Percentage(f64);
+
+impl Percentage {
+    /// Creates a percentage clamped to 0..=100; NaN becomes 0.
+    pub fn new_clamped(value: f64) -> Self {
+        Self::new_clamped_to(value, 100.0)
+    }
+
+    /// Creates a percentage clamped to 0..=`max`, e.g. `100 * cores` for the CPU usage of a multi-threaded process;
+    /// NaN becomes 0.
+    pub fn new_clamped_to(value: f64, max: f64) -> Self {
+        if value.is_nan() {
+            tracing::debug!("Replaced a NaN percentage with 0");
+            return Self(0.0);
+        }
+        // `f64::max` ignores a NaN bound
+        Self(value.clamp(0.0, max.max(0.0)))
+    }
+
+    /// Creates the percentage `part` makes of `whole`, clamped to 0..=100; 0 if `whole` is zero.
+    pub fn from_ratio(part: f64, whole: f64) -> Self {
+        Self::new_clamped(sanitize_rate(part, whole) * 100.0)
+    }
+
+    /// The percentage as a number between 0 and its maximum.
+    pub fn value(self) -> f64 {
+        self.0
+    }
+
+    /// The percentage in basis points, see [`basis_points`](super::fixed::basis_points).
+    pub fn basis_points(self) -> u16 {
+        super::fixed::basis_points(self.0)
+    }
+}
+
+impl From<Percentage> for f64 {
+    fn from(percentage: Percentage) -> Self {
+        percentage.value()
+    }
+}
+
+/// Divides `numerator` by `denominator`, e.g. a counter delta by the elapsed seconds.
+///
+/// Returns 0.0 if the denominator is zero, negative or not finite, or if the result isn't finite.
+pub fn sanitize_rate(numerator: f64, denominator: f64) -> f64 {
+    if !denominator.is_finite() || denominator <= 0.0 {
+        return 0.0;
+    }
+
+    let rate = numerator / denominator;
+    if rate.is_finite() {
+        rate
+    } else {
+        tracing::debug!(numerator, denominator, "Replaced a non-finite rate with 0");
+        0.0
+    }
+}
+
+/// The value if it's finite, otherwise `None`.
+pub fn finite(value: f64) -> Option<f64> {
+    value.is_finite().then_some(value)
+}
@@ -0,0 +1,181 @@
+use crate::hardware::iokit::{fan_percentage, gpu_utilization};
+use crate::network::traffic::TrafficTracker;
+use crate::process::cpu_usage_percent;
+use crate::utils::sanitize::{finite, sanitize_rate, Percentage};
+
+const ADVERSARIAL_F64: [f64; 12] = [
+    0.0,
+    -0.0,
+    1.0,
+    -1.0,
+    1e-300,
+    -1e-300,
+    f64::MAX,
+    f64::MIN,
+    f64::MIN_POSITIVE,
+    f64::NAN,
+    f64::INFINITY,
+    f64::NEG_INFINITY,
+];
+
+const ADVERSARIAL_U32: [u32; 6] = [0, 1, 1200, 6000, u32::MAX - 1, u32::MAX];
+
+const ADVERSARIAL_U64: [u64; 5] = [0, 1, 1_000_000, u64::MAX - 1, u64::MAX];
+
+fn assert_in_range(value: f64, max: f64, context: &str) {
+    assert!(value.is_finite(), "{context} is not finite: {value}");
+    assert!((0.0..=max).contains(&value), "{context} is out of range: {value}");
+}
+
+#[test]
+fn test_percentage_new_clamped() {
+    assert_eq!(Percentage::new_clamped(42.5).value(), 42.5);
+    assert_eq!(Percentage::new_clamped(150.0).value(), 100.0);
+    assert_eq!(Percentage::new_clamped(-3.0).value(), 0.0);
+    assert_eq!(Percentage::new_clamped(f64::NAN).value(), 0.0);
+    assert_eq!(Percentage::new_clamped(f64::INFINITY).value(), 100.0);
+    assert_eq!(Percentage::new_clamped(f64::NEG_INFINITY).value(), 0.0);
+    assert_eq!(Percentage::new_clamped_to(750.0, 400.0).value(), 400.0);
+    assert_eq!(f64::from(Percentage::new_clamped(12.0)), 12.0);
+
+    for value in ADVERSARIAL_F64 {
+        assert_in_range(Percentage::new_clamped(value).value(), 100.0, "new_clamped");
+        for max in ADVERSARIAL_F64 {
+            let percentage = Percentage::new_clamped_to(value, max).value();
+            assert!(!percentage.is_nan(), "new_clamped_to({value}, {max}) is NaN");
+            assert!(percentage >= 0.0, "new_clamped_to({value}, {max}) is negative");
+        }
+    }
+}
+
+#[test]
+fn test_percentage_from_ratio() {
+    assert_eq!(Percentage::from_ratio(1.0, 4.0).value(), 25.0);
+    assert_eq!(Percentage::from_ratio(5.0, 4.0).value(), 100.0);
+    assert_eq!(Percentage::from_ratio(1.0, 0.0).value(), 0.0);
+
+    for part in ADVERSARIAL_F64 {
+        for whole in ADVERSARIAL_F64 {
+            let percentage = Percentage::from_ratio(part, whole).value();
+            assert_in_range(percentage, 100.0, &format!("from_ratio({part}, {whole})"));
+        }
+    }
+}
+
+#[test]
+fn test_sanitize_rate() {
+    assert_eq!(sanitize_rate(10.0, 2.0), 5.0);
+    assert_eq!(sanitize_rate(10.0, 0.0), 0.0);
+    assert_eq!(sanitize_rate(10.0, -1.0), 0.0);
+    assert_eq!(sanitize_rate(10.0, f64::NAN), 0.0);
+    assert_eq!(sanitize_rate(f64::MAX, f64::MIN_POSITIVE), 0.0);
+
+    for numerator in ADVERSARIAL_F64 {
+        for denominator in ADVERSARIAL_F64 {
+            let rate = sanitize_rate(numerator, denominator);
+            assert!(rate.is_finite(), "sanitize_rate({numerator}, {denominator}) is {rate}");
+        }
+    }
+}
+
+#[test]
+fn test_finite() {
+    assert_eq!(finite(1.5), Some(1.5));
+    assert_eq!(finite(f64::NAN), None);
+    assert_eq!(finite(f64::INFINITY), None);
+    assert_eq!(finite(f64::NEG_INFINITY), None);
+}
+
+#[test]
+fn test_fan_percentage() {
+    assert_eq!(fan_percentage(3600, 1200, 6000), 50.0);
+    // Below the minimum used to underflow the u32 subtraction
+    assert_eq!(fan_percentage(800, 1200, 6000), 0.0);
+    // Reversed and zero ranges
+    assert_eq!(fan_percentage(3000, 6000, 1200), 0.0);
+    assert_eq!(fan_percentage(3000, 3000, 3000), 0.0);
+
+    for speed in ADVERSARIAL_U32 {
+        for min in ADVERSARIAL_U32 {
+            for max in ADVERSARIAL_U32 {
+                let percentage = fan_percentage(speed, min, max);
+                assert_in_range(
+                    percentage,
+                    100.0,
+                    &format!("fan_percentage({speed}, {min}, {max})"),
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_gpu_utilization() {
+    assert_eq!(gpu_utilization(50.0, 200.0), 25.0);
+    assert_eq!(gpu_utilization(50.0, 0.0), 0.0);
+
+    for perf_cap in ADVERSARIAL_F64 {
+        for perf_threshold in ADVERSARIAL_F64 {
+            let utilization = gpu_utilization(perf_cap, perf_threshold);
+            assert_in_range(
+                utilization,
+                100.0,
+                &format!("gpu_utilization({perf_cap}, {perf_threshold})"),
+            );
+        }
+    }
+}
+
+#[test]
+fn test_cpu_usage_percent() {
+    let max = std::thread::available_parallelism().map_or(1, |n| n.get()) as f64 * 100.0;
+
+    assert_eq!(cpu_usage_percent(500_000, 1.0), 50.0);
+    assert_eq!(cpu_usage_percent(u64::MAX, 1.0), max);
+    assert_eq!(cpu_usage_percent(1_000_000, 0.0), 0.0);
+
+    for cpu_time_delta in ADVERSARIAL_U64 {
+        for elapsed_secs in ADVERSARIAL_F64 {
+            let usage = cpu_usage_percent(cpu_time_delta, elapsed_secs);
+            assert_in_range(
+                usage,
+                max,
+                &format!("cpu_usage_percent({cpu_time_delta}, {elapsed_secs})"),
+            );
+        }
+    }
+}
+
+#[test]
+fn test_traffic_rates() {
+    let assert_rates_valid = |tracker: &TrafficTracker, context: &str| {
+        for rate in [
+            tracker.download_speed(),
+            tracker.upload_speed(),
+            tracker.packet_receive_rate(),
+            tracker.packet_send_rate(),
+            tracker.receive_error_rate(),
+            tracker.send_error_rate(),
+        ] {
+            assert!(rate.is_finite() && rate >= 0.0, "{context} gave {rate}");
+        }
+    };
+
+    for previous in ADVERSARIAL_U64 {
+        for current in ADVERSARIAL_U64 {
+            let mut tracker =
+                TrafficTracker::new(previous, previous, previous, previous, previous, previous, 0);
+            // Counters that wrapped or were reset go backwards
+            tracker.update(current, current, current, current, current, current, 0);
+            assert_rates_valid(&tracker, &format!("{previous} -> {current}"));
+            // Two updates can share an instant
+            tracker.update(current, current, current, current, current, current, 0);
+            assert_rates_valid(&tracker, &format!("{current} -> {current}"));
+        }
+    }
+
+    let mut tracker = TrafficTracker::new(u64::MAX, u64::MAX, u64::MAX, u64::MAX, 0, 0, 0);
+    tracker.update(10, 10, 10, 10, 0, 0, 0);
+    assert_eq!(tracker.download_speed(), 0.0);
+    assert_eq!(tracker.packet_send_rate(), 0.0);
+}
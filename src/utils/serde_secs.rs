@@ -0,0 +1,19 @@
+//! Serializes a [`Duration`] as a floating-point number of seconds
+//!
+//! For use with `#[serde(with = "crate::utils::serde_secs")]`, so durations read as plain numbers in JSON instead of
+//! serde's default `{ "secs": .., "nanos": .. }` object.
+
+use std::time::Duration;
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+/// Serializes `duration` as seconds.
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// Deserializes a non-negative, finite number of seconds.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs).map_err(D::Error::custom)
+}
@@ -1,6 +1,8 @@
 use objc2::rc::Retained;
 use objc2_foundation::{NSDictionary, NSObject, NSString};
 
+use crate::hardware::iokit::{HandleOwnership, IOServiceHandle};
+
 /// Creates a test dictionary with no entries
 pub fn create_test_dictionary() -> Retained<NSDictionary<NSString, NSObject>> {
     unsafe {
@@ -53,6 +55,16 @@ pub fn create_test_object() -> Retained<NSObject> {
     }
 }
 
+/// Creates a service handle for mocked lookups, which is never released
+pub fn create_test_service() -> IOServiceHandle {
+    create_test_service_with_id(1)
+}
+
+/// Creates a service handle with the raw handle `id` that releases nothing, to tell entries apart in mocks
+pub fn create_test_service_with_id(id: u32) -> IOServiceHandle {
+    IOServiceHandle::with_release(id, HandleOwnership::Borrowed, |_| {})
+}
+
 /// Creates a test string
 pub fn create_test_string(content: &str) -> Retained<NSString> {
     NSString::from_str(content)
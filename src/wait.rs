@@ -0,0 +1,476 @@
+//! # Wait Helpers
+//!
+//! Blocking and async helpers that poll a metric until it satisfies a predicate, e.g. "wait until the CPU is idle" or
+//! "wait until the CPU temperature drops below 70°C".
+//!
+//! A metric is read through a [`Collector`], which is either one of the built-in [`MetricId`] collectors or any
+//! closure returning `Result<f64>`. Built-in collectors create their underlying monitor once and refresh it on every
+//! poll, so waiting doesn't repeatedly spin up heavyweight monitors.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! use darwin_metrics::wait::{wait_for, MetricId, WaitOptions};
+//!
+//! let options = WaitOptions {
+//!     timeout: Duration::from_secs(60),
+//!     settle: Some(Duration::from_secs(5)),
+//!     ..WaitOptions::default()
+//! };
+//!
+//! // Wait until CPU usage stays below 10% for five seconds
+//! match wait_for(MetricId::CpuUsage.collector(), |usage| usage < 0.1, options) {
+//!     Ok(usage) => println!("CPU settled at {:.1}%", usage * 100.0),
+//!     Err(e) => println!("Gave up waiting: {e}"),
+//! }
+//! ```
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+use crate::{
+    error::{Error, Result},
+    hardware::{
+        cpu::{CpuMetrics, CPU},
+        memory::Memory,
+        temperature::Temperature,
+    },
+    utils::bindings::getloadavg,
+};
+
+/// Options controlling how [`wait_for`] polls
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WaitOptions {
+    /// Delay between two polls
+    pub poll_interval: Duration,
+    /// Maximum time to wait before giving up
+    pub timeout: Duration,
+    /// If set, the predicate must hold continuously for this long before the wait succeeds
+    pub settle: Option<Duration>,
+    /// Number of consecutive collector errors tolerated before the wait fails
+    pub max_consecutive_errors: u32,
+}
+
+impl Default for WaitOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_millis(500),
+            timeout: Duration::from_secs(30),
+            settle: None,
+            max_consecutive_errors: 3,
+        }
+    }
+}
+
+/// Why a wait didn't succeed
+#[derive(Debug, Clone, Error)]
+pub enum WaitError {
+    /// The predicate wasn't satisfied before the timeout
+    #[error("Timed out waiting for metric (last value: {last_value:?})")]
+    Timeout {
+        /// The most recent value read, if any read succeeded
+        last_value: Option<f64>,
+    },
+    /// The collector failed more often in a row than allowed by [`WaitOptions::max_consecutive_errors`]
+    #[error("Metric collection failed {consecutive_errors} times in a row: {error}")]
+    Collector {
+        /// The most recent collector error
+        error: Error,
+        /// Number of consecutive failures
+        consecutive_errors: u32,
+    },
+}
+
+impl From<WaitError> for Error {
+    fn from(err: WaitError) -> Self {
+        match err {
+            WaitError::Collector { error, .. } => error,
+            timeout => Error::Io {
+                kind: std::io::ErrorKind::TimedOut,
+                message: timeout.to_string(),
+                code: None,
+            },
+        }
+    }
+}
+
+/// A source of metric values polled by [`wait_for`]
+pub trait Collector {
+    /// Reads the current value of the metric.
+    fn collect(&mut self) -> Result<f64>;
+}
+
+impl<F> Collector for F
+where
+    F: FnMut() -> Result<f64>,
+{
+    fn collect(&mut self) -> Result<f64> {
+        self()
+    }
+}
+
+/// Built-in metrics that can be waited on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MetricId {
+    /// Average CPU usage between 0.0 and 1.0
+    CpuUsage,
+    /// CPU temperature in degrees Celsius
+    CpuTemperature,
+    /// Used memory as a percentage (0-100)
+    MemoryUsage,
+    /// Memory pressure between 0.0 and 1.0
+    MemoryPressure,
+    /// 1 minute load average
+    LoadAverage,
+}
+
+impl MetricId {
+    /// Creates a collector for this metric.
+    pub fn collector(self) -> MetricCollector {
+        MetricCollector { metric: self, cpu: None, memory: None, temperature: None }
+    }
+}
+
+/// Collector for a [`MetricId`], reusing its underlying monitor across polls
+pub struct MetricCollector {
+    metric: MetricId,
+    cpu: Option<CPU>,
+    memory: Option<Memory>,
+    temperature: Option<Temperature>,
+}
+
+impl fmt::Debug for MetricCollector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricCollector").field("metric", &self.metric).finish_non_exhaustive()
+    }
+}
+
+impl Collector for MetricCollector {
+    fn collect(&mut self) -> Result<f64> {
+        match self.metric {
+            MetricId::CpuUsage => {
+                match self.cpu.as_mut() {
+                    Some(cpu) => cpu.update()?,
+                    None => self.cpu = Some(CPU::new()?),
+                }
+                Ok(self.cpu.as_ref().map(|cpu| cpu.get_cpu_usage()).unwrap_or_default())
+            },
+            MetricId::CpuTemperature => {
+                self.temperature.get_or_insert_with(Temperature::new).cpu_temperature()
+            },
+            MetricId::MemoryUsage | MetricId::MemoryPressure => {
+                match self.memory.as_mut() {
+                    Some(memory) => memory.update()?,
+                    None => self.memory = Some(Memory::new()?),
+                }
+                let memory = self.memory.as_ref().expect("memory monitor was just initialized");
+                Ok(match self.metric {
+                    MetricId::MemoryUsage => memory.usage_percentage(),
+                    _ => memory.pressure,
+                })
+            },
+            MetricId::LoadAverage => {
+                let mut loads = [0.0f64; 3];
+                // SAFETY: the buffer has room for the three requested values.
+                if unsafe { getloadavg(loads.as_mut_ptr(), 3) } < 1 {
+                    return Err(Error::system("Failed to get load average"));
+                }
+                Ok(loads[0])
+            },
+        }
+    }
+}
+
+/// Outcome of processing a single sample
+#[derive(Debug)]
+enum Step {
+    Done(f64),
+    Continue,
+    Failed(WaitError),
+}
+
+/// Wait bookkeeping, independent of how time passes so it can be driven by a fake clock in tests
+#[derive(Debug)]
+struct WaitState {
+    started: Instant,
+    satisfied_since: Option<Instant>,
+    consecutive_errors: u32,
+    last_value: Option<f64>,
+}
+
+impl WaitState {
+    fn new(started: Instant) -> Self {
+        Self { started, satisfied_since: None, consecutive_errors: 0, last_value: None }
+    }
+
+    fn step<P>(
+        &mut self,
+        now: Instant,
+        sample: Result<f64>,
+        predicate: &mut P,
+        options: &WaitOptions,
+    ) -> Step
+    where
+        P: FnMut(f64) -> bool,
+    {
+        match sample {
+            Ok(value) => {
+                self.consecutive_errors = 0;
+                self.last_value = Some(value);
+
+                if predicate(value) {
+                    let since = *self.satisfied_since.get_or_insert(now);
+                    let settled = match options.settle {
+                        Some(settle) => now.duration_since(since) >= settle,
+                        None => true,
+                    };
+                    if settled {
+                        return Step::Done(value);
+                    }
+                } else {
+                    // The settle window only counts uninterrupted satisfaction
+                    self.satisfied_since = None;
+                }
+            },
+            Err(error) => {
+                // An unknown value can't prove the predicate held, so it also restarts the settle window
+                self.satisfied_since = None;
+                self.consecutive_errors += 1;
+                if self.consecutive_errors > options.max_consecutive_errors {
+                    return Step::Failed(WaitError::Collector {
+                        error,
+                        consecutive_errors: self.consecutive_errors,
+                    });
+                }
+            },
+        }
+
+        if now.duration_since(self.started) >= options.timeout {
+            return Step::Failed(WaitError::Timeout { last_value: self.last_value });
+        }
+
+        Step::Continue
+    }
+}
+
+fn run_blocking<C, P, N, S>(
+    collector: &mut C,
+    mut predicate: P,
+    options: &WaitOptions,
+    mut now: N,
+    mut sleep: S,
+) -> std::result::Result<f64, WaitError>
+where
+    C: Collector + ?Sized,
+    P: FnMut(f64) -> bool,
+    N: FnMut() -> Instant,
+    S: FnMut(Duration),
+{
+    let mut state = WaitState::new(now());
+
+    loop {
+        let sample = collector.collect();
+        match state.step(now(), sample, &mut predicate, options) {
+            Step::Done(value) => return Ok(value),
+            Step::Failed(err) => return Err(err),
+            Step::Continue => sleep(options.poll_interval),
+        }
+    }
+}
+
+/// Polls a metric until the predicate holds, blocking the current thread.
+///
+/// # Returns
+///
+/// * `Result<f64, WaitError>` - The value that satisfied the predicate (at the end of the settle window, if any)
+///
+/// # Errors
+///
+/// * [`WaitError::Timeout`] if the predicate didn't hold (for the settle duration) before the timeout
+/// * [`WaitError::Collector`] if the collector failed more than `max_consecutive_errors` times in a row
+pub fn wait_for<C, P>(
+    mut collector: C,
+    predicate: P,
+    options: WaitOptions,
+) -> std::result::Result<f64, WaitError>
+where
+    C: Collector,
+    P: FnMut(f64) -> bool,
+{
+    run_blocking(&mut collector, predicate, &options, Instant::now, std::thread::sleep)
+}
+
+/// Polls a metric until the predicate holds, sleeping asynchronously between polls.
+///
+/// The collector itself runs on the calling task, so it should return quickly. Behaves like [`wait_for`] otherwise.
+///
+/// # Errors
+///
+/// Same as [`wait_for`].
+pub async fn wait_for_async<C, P>(
+    mut collector: C,
+    mut predicate: P,
+    options: WaitOptions,
+) -> std::result::Result<f64, WaitError>
+where
+    C: Collector,
+    P: FnMut(f64) -> bool,
+{
+    let mut state = WaitState::new(Instant::now());
+
+    loop {
+        let sample = collector.collect();
+        match state.step(Instant::now(), sample, &mut predicate, &options) {
+            Step::Done(value) => return Ok(value),
+            Step::Failed(err) => return Err(err),
+            Step::Continue => tokio::time::sleep(options.poll_interval).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, collections::VecDeque};
+
+    use super::*;
+
+    /// A collector replaying a fixed script of samples; the last sample repeats forever
+    struct Scripted {
+        samples: VecDeque<Result<f64>>,
+        calls: usize,
+    }
+
+    impl Scripted {
+        fn new(samples: Vec<Result<f64>>) -> Self {
+            Self { samples: samples.into(), calls: 0 }
+        }
+    }
+
+    impl Collector for Scripted {
+        fn collect(&mut self) -> Result<f64> {
+            self.calls += 1;
+            if self.samples.len() > 1 {
+                self.samples.pop_front().unwrap()
+            } else {
+                self.samples.front().cloned().unwrap()
+            }
+        }
+    }
+
+    fn options(settle: Option<u64>) -> WaitOptions {
+        WaitOptions {
+            poll_interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+            settle: settle.map(Duration::from_secs),
+            max_consecutive_errors: 2,
+        }
+    }
+
+    /// Runs a wait against a virtual clock advanced by each sleep
+    fn run(collector: &mut Scripted, options: WaitOptions) -> std::result::Result<f64, WaitError> {
+        let start = Instant::now();
+        let clock = Cell::new(start);
+        run_blocking(
+            collector,
+            |v| v < 50.0,
+            &options,
+            || clock.get(),
+            |d| clock.set(clock.get() + d),
+        )
+    }
+
+    #[test]
+    fn test_immediately_satisfied() {
+        let mut collector = Scripted::new(vec![Ok(10.0)]);
+        assert_eq!(run(&mut collector, options(None)).unwrap(), 10.0);
+        assert_eq!(collector.calls, 1);
+    }
+
+    #[test]
+    fn test_settle_window_resets_on_flapping() {
+        // Satisfied at t=0..1, flaps at t=2, then holds from t=3
+        let mut collector = Scripted::new(vec![
+            Ok(10.0),
+            Ok(20.0),
+            Ok(90.0),
+            Ok(30.0),
+            Ok(31.0),
+            Ok(32.0),
+            Ok(33.0),
+        ]);
+        let value = run(&mut collector, options(Some(3))).unwrap();
+
+        // Satisfaction restarted at t=3, so the settle window ends at t=6
+        assert_eq!(value, 33.0);
+        assert_eq!(collector.calls, 7);
+    }
+
+    #[test]
+    fn test_timeout_reports_last_value() {
+        let mut collector = Scripted::new(vec![Ok(80.0), Ok(75.0)]);
+        match run(&mut collector, options(None)) {
+            Err(WaitError::Timeout { last_value }) => assert_eq!(last_value, Some(75.0)),
+            other => panic!("expected timeout, got {other:?}"),
+        }
+        assert_eq!(collector.calls, 11);
+    }
+
+    #[test]
+    fn test_errors_within_budget_are_tolerated() {
+        let mut collector = Scripted::new(vec![
+            Err(Error::system("transient")),
+            Err(Error::system("transient")),
+            Ok(90.0),
+            Err(Error::system("transient")),
+            Ok(10.0),
+        ]);
+        assert_eq!(run(&mut collector, options(None)).unwrap(), 10.0);
+    }
+
+    #[test]
+    fn test_error_budget_exhausted() {
+        let mut collector = Scripted::new(vec![Ok(90.0), Err(Error::system("sensor gone"))]);
+        match run(&mut collector, options(None)) {
+            Err(WaitError::Collector { error, consecutive_errors }) => {
+                assert_eq!(consecutive_errors, 3);
+                assert!(matches!(error, Error::System(msg) if msg == "sensor gone"));
+            },
+            other => panic!("expected collector error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_wait_for_with_closure() {
+        let mut value = 100.0;
+        let options =
+            WaitOptions { poll_interval: Duration::from_millis(1), ..WaitOptions::default() };
+        let result = wait_for(
+            || {
+                value -= 25.0;
+                Ok(value)
+            },
+            |v| v <= 0.0,
+            options,
+        );
+        assert_eq!(result.unwrap(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_async_timeout() {
+        let options = WaitOptions {
+            poll_interval: Duration::from_millis(1),
+            timeout: Duration::from_millis(20),
+            ..WaitOptions::default()
+        };
+        let result = wait_for_async(|| Ok(1.0), |v| v > 1.0, options).await;
+        assert!(matches!(result, Err(WaitError::Timeout { last_value: Some(_) })));
+
+        let error: Error = result.unwrap_err().into();
+        assert_eq!(error.kind(), crate::error::ErrorKind::Timeout);
+    }
+}
@@ -0,0 +1,81 @@
+//! Looks up services in the IORegistry of the machine through `IOKitImpl`
+//!
+//! ```sh
+//! cargo test --test registry_lookup
+//! ```
+
+#![cfg(target_os = "macos")]
+
+use darwin_metrics::{
+    hardware::iokit::{IOKit, IOKitImpl},
+    Gpu,
+};
+use objc2_foundation::{NSData, NSString};
+
+#[test]
+fn test_platform_expert_model() {
+    let iokit = IOKitImpl::default();
+    let matching = iokit.io_service_matching("IOPlatformExpertDevice");
+    let service =
+        iokit.io_service_get_matching_service(&matching).expect("Every Mac has a platform expert");
+    let properties = iokit
+        .io_registry_entry_create_cf_properties(&service)
+        .expect("Failed to read the platform expert properties");
+
+    // The model is a NUL-terminated string stored as data, e.g. "MacBookPro18,3"
+    let model = properties
+        .valueForKey(&NSString::from_str("model"))
+        .and_then(|value| value.downcast::<NSData>().ok())
+        .map(|data| String::from_utf8_lossy(&data.to_vec()).trim_end_matches('\0').to_string())
+        .expect("The platform expert has no model");
+    assert!(model.contains(','), "Unexpected model identifier: {model}");
+
+    // The lookup by class name finds the same service, and a task has one name per service
+    let by_name = iokit.get_service("IOPlatformExpertDevice").unwrap();
+    assert_eq!(by_name.as_raw(), service.as_raw());
+}
+
+#[test]
+fn test_platform_expert_parent() {
+    let iokit = IOKitImpl::default();
+    let service = iokit.get_service("IOPlatformExpertDevice").unwrap();
+
+    // The platform expert hangs off the root of the service plane
+    let root =
+        iokit.io_registry_entry_get_parent(&service).expect("The platform expert has a parent");
+    assert_eq!(iokit.io_registry_entry_get_parent(&root).map(|parent| parent.as_raw()), None);
+}
+
+#[test]
+fn test_service_plane_root() {
+    let iokit = IOKitImpl::default();
+    let root = iokit.find_service_by_path("IOService:/").expect("The service plane has a root");
+    assert!(!iokit.io_registry_entry_get_name(&root).unwrap().is_empty());
+
+    // The platform expert is a child of the root, and its name is its path
+    let expert = iokit.get_service("IOPlatformExpertDevice").unwrap();
+    let children = iokit.io_registry_iterate_children(&root, "IOService").unwrap();
+    assert!(children.iter().any(|child| child.as_raw() == expert.as_raw()));
+
+    let name = iokit.io_registry_entry_get_name(&expert).unwrap();
+    let by_path = iokit
+        .find_service_by_path(&format!("IOService:/{name}"))
+        .expect("The platform expert can be found by its path");
+    assert_eq!(by_path.as_raw(), expert.as_raw());
+
+    assert!(iokit.find_service_by_path("IOService:/DarwinMetricsNoSuchEntry").is_none());
+}
+
+#[test]
+fn test_gpu_pci_device() {
+    let gpu = Gpu::new().unwrap();
+    match gpu.pci_device() {
+        // The GPUs of Intel Macs are made by Intel, AMD or NVIDIA
+        Some(device) => assert!([0x8086, 0x1002, 0x10de].contains(&device.vendor_id), "{device:?}"),
+        // Apple Silicon GPUs aren't on the PCI bus
+        #[cfg(target_arch = "aarch64")]
+        None => {},
+        #[cfg(not(target_arch = "aarch64"))]
+        None => panic!("The GPU has no PCI device"),
+    }
+}
@@ -0,0 +1,160 @@
+//! Round-trips the public metric types through serde_json, so none of them silently stops being serializable
+//!
+//! ```sh
+//! cargo test --features serde --test serde_roundtrip
+//! ```
+
+#![cfg(feature = "serde")]
+
+use std::time::Duration;
+
+use darwin_metrics::{
+    disk::{Disk, DiskType},
+    hardware::{
+        gpu::GpuMetrics,
+        iokit::{CpuTemperatureSource, FanInfo, GpuStats, ThermalInfo},
+        memory::{Memory, PageStates, PressureLevel, SwapUsage},
+        temperature::Fan,
+    },
+    network::{Interface, InterfaceType, TrafficData},
+    power::{PowerConsumption, PowerState},
+    process::{AppNapState, Process, ProcessIOStats},
+    system::{Architecture, SystemMetrics},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+/// Serializes `value`, deserializes it again and checks that nothing was lost, returning the JSON.
+fn round_trip<T: Serialize + DeserializeOwned>(value: &T) -> Value {
+    let json = serde_json::to_value(value).expect("Failed to serialize");
+    let back: T = serde_json::from_value(json.clone()).expect("Failed to deserialize");
+    assert_eq!(serde_json::to_value(&back).expect("Failed to serialize again"), json);
+    json
+}
+
+#[test]
+fn test_process_types() {
+    let mut process = Process::new(42, "launchd");
+    process.cpu_usage = 12.5;
+    process.uptime = Duration::from_millis(1_500);
+    process.io_stats =
+        ProcessIOStats { read_bytes: 1, write_bytes: 2, read_count: 3, write_count: 4 };
+    process.app_nap_state = Some(AppNapState::Napping);
+
+    let json = round_trip(&process);
+    assert_eq!(json["uptime"], json!(1.5), "Durations are serialized as seconds");
+    assert_eq!(json["app_nap_state"], json!("Napping"));
+    assert!(json.get("pending_future").is_none());
+
+    round_trip(&ProcessIOStats::default());
+    round_trip(&AppNapState::Unknown);
+}
+
+#[test]
+fn test_hardware_types() {
+    let thermal = ThermalInfo {
+        cpu_temp: 45.0,
+        gpu_temp: Some(40.0),
+        heatsink_temp: None,
+        ambient_temp: Some(30.0),
+        battery_temp: None,
+        is_throttling: false,
+        cpu_power: Some(5.5),
+        cpu_temp_source: CpuTemperatureSource::IORegistry,
+        ..Default::default()
+    };
+    assert_eq!(round_trip(&thermal)["cpu_temp_source"], json!("IORegistry"));
+
+    round_trip(&FanInfo {
+        speed_rpm: 2_000,
+        min_speed: 1_200,
+        max_speed: 6_000,
+        percentage: 16.7,
+        ..Default::default()
+    });
+    round_trip(&Fan {
+        name: "Left".to_string(),
+        speed_rpm: 2_000,
+        min_speed: 1_200,
+        max_speed: 6_000,
+        percentage: 16.7,
+        ..Default::default()
+    });
+    round_trip(&GpuStats::default());
+    round_trip(&GpuMetrics::default());
+    round_trip(&PageStates::default());
+    round_trip(&SwapUsage::default());
+    assert_eq!(round_trip(&PressureLevel::Warning), json!("Warning"));
+
+    let memory = Memory::with_values(
+        16_000,
+        8_000,
+        8_000,
+        2_000,
+        0.5,
+        PageStates::default(),
+        SwapUsage::default(),
+    );
+    let json = round_trip(&memory);
+    assert_eq!(json["total"], json!(16_000));
+    assert!(json.get("last_update").is_none(), "Instants aren't serialized");
+    assert!(json.get("iokit").is_none());
+}
+
+#[test]
+fn test_disk_power_and_system_types() {
+    let disk = Disk {
+        device: "/dev/disk3s1".to_string(),
+        mount_point: "/".to_string(),
+        fs_type: "apfs".to_string(),
+        total: 500,
+        available: 200,
+        used: 300,
+        disk_type: DiskType::SSD,
+        name: "Macintosh HD".to_string(),
+        is_boot_volume: true,
+    };
+    assert_eq!(round_trip(&disk)["disk_type"], json!("SSD"));
+
+    assert_eq!(round_trip(&PowerState::Charging), json!("Charging"));
+    let consumption = PowerConsumption {
+        package: 12.5,
+        cores: 8.0,
+        gpu: Some(2.5),
+        dram: None,
+        neural_engine: None,
+        power_state: PowerState::Battery,
+        battery_percentage: Some(80.0),
+        power_impact: None,
+        display_brightness: Some(0.5),
+    };
+    assert_eq!(round_trip(&consumption)["power_state"], json!("Battery"));
+    assert_eq!(round_trip(&Architecture::AppleSilicon), json!("AppleSilicon"));
+    round_trip(&SystemMetrics { architecture: Architecture::Intel });
+}
+
+#[test]
+fn test_network_types() {
+    let interface = Interface::new(
+        "en0".to_string(),
+        InterfaceType::WiFi,
+        0,
+        Some("aa:bb:cc:dd:ee:ff".to_string()),
+        vec!["192.168.1.2".parse().unwrap()],
+        1_000,
+        2_000,
+        10,
+        20,
+        0,
+        0,
+        0,
+    );
+    let json = round_trip(&interface);
+    assert_eq!(json["interface_type"], json!("WiFi"));
+    assert!(json.get("last_update").is_none(), "Instants aren't serialized");
+
+    let traffic = round_trip(&TrafficData::new(1_000, 2_000, 10, 20, 1, 2, 3));
+    assert_eq!(traffic["bytes_received"], json!(1_000));
+    assert!(traffic.get("timestamp").is_none());
+    assert_eq!(round_trip(&InterfaceType::Loopback), json!("Loopback"));
+}
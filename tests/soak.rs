@@ -0,0 +1,187 @@
+//! Soak test of the monitoring loops
+//!
+//! Runs snapshot collection, a process metrics stream, thermal polling and network tracking concurrently, samples the
+//! resident memory and the diagnostics counters of the process while they run, and fails if any of them keeps
+//! growing. Both variants are ignored by default since they take a while and need real hardware:
+//!
+//! ```sh
+//! cargo test --test soak -- --ignored soak_short
+//! DARWIN_METRICS_SOAK_SECS=3600 cargo test --release --test soak -- --ignored --nocapture soak_long
+//! ```
+//!
+//! The long variant is configured through the environment:
+//!
+//! - `DARWIN_METRICS_SOAK_SECS` - duration of the run (default: 600)
+//! - `DARWIN_METRICS_SOAK_SAMPLE_SECS` - interval between diagnostics samples (default: 10)
+//! - `DARWIN_METRICS_SOAK_MAX_SLOPE` - growth per hour allowed for counters and channel depths (default: 1, or 60 for
+//!   the processes in the CPU time history)
+//! - `DARWIN_METRICS_SOAK_MAX_RSS_SLOPE` - growth of the resident memory per hour allowed, in bytes (default: 4 MiB)
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use darwin_metrics::{
+    diagnostics::{sample, LeakThresholds, TrendTracker, CPU_HISTORY_TRACKED, RESIDENT_BYTES},
+    hardware::temperature::Temperature,
+    network::NetworkManager,
+    process::ProcessMetricsStream,
+    snapshot::MetricsSnapshot,
+};
+use futures::StreamExt;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// Name of the series holding the number of queued process metrics
+const CHANNEL_DEPTH: &str = "soak.channel_depth";
+
+/// Capacity of the channel between the process metrics stream and its consumer
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone)]
+struct SoakConfig {
+    duration: Duration,
+    sample_interval: Duration,
+    /// Part of the run before the first sample, so one-off allocations at startup don't count as growth
+    warm_up: Duration,
+    thresholds: LeakThresholds,
+}
+
+impl SoakConfig {
+    fn from_env() -> Self {
+        let mut thresholds = LeakThresholds::default();
+        // The tracked processes follow the processes of the whole machine, which come and go
+        thresholds.per_quantity.insert(CPU_HISTORY_TRACKED.to_string(), 60.0);
+        if let Some(slope) = env_f64("DARWIN_METRICS_SOAK_MAX_SLOPE") {
+            thresholds.default_per_hour = slope;
+        }
+        if let Some(slope) = env_f64("DARWIN_METRICS_SOAK_MAX_RSS_SLOPE") {
+            thresholds.per_quantity.insert(RESIDENT_BYTES.to_string(), slope);
+        }
+
+        let duration =
+            Duration::from_secs_f64(env_f64("DARWIN_METRICS_SOAK_SECS").unwrap_or(600.0));
+        Self {
+            duration,
+            sample_interval: Duration::from_secs_f64(
+                env_f64("DARWIN_METRICS_SOAK_SAMPLE_SECS").unwrap_or(10.0),
+            ),
+            warm_up: duration / 10,
+            thresholds,
+        }
+    }
+}
+
+fn env_f64(name: &str) -> Option<f64> {
+    let value = std::env::var(name).ok()?;
+    Some(value.parse().unwrap_or_else(|_| panic!("{} must be a number, got {:?}", name, value)))
+}
+
+/// Runs `poll` every `interval` on a blocking thread until `stop` is set.
+fn poll_until_stopped(
+    stop: &Arc<AtomicBool>,
+    interval: Duration,
+    mut poll: impl FnMut() + Send + 'static,
+) -> JoinHandle<()> {
+    let stop = stop.clone();
+    tokio::task::spawn_blocking(move || {
+        while !stop.load(Ordering::Relaxed) {
+            poll();
+            std::thread::sleep(interval);
+        }
+    })
+}
+
+async fn soak(config: SoakConfig) {
+    let stop = Arc::new(AtomicBool::new(false));
+
+    // Stands in for a resource monitor: collects everything the crate reports once a second
+    let snapshots = poll_until_stopped(&stop, Duration::from_secs(1), || {
+        let _ = MetricsSnapshot::collect_full();
+    });
+
+    let mut temperature = Temperature::new();
+    let thermal = poll_until_stopped(&stop, Duration::from_millis(500), move || {
+        let _ = temperature.refresh();
+    });
+
+    let mut network = NetworkManager::new().expect("Failed to create the network manager");
+    let network = poll_until_stopped(&stop, Duration::from_millis(500), move || {
+        let _ = network.update();
+    });
+
+    let (sender, mut receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    let producer = tokio::spawn({
+        let sender = sender.clone();
+        async move {
+            let mut stream =
+                ProcessMetricsStream::new(std::process::id(), Duration::from_millis(200));
+            while let Some(metrics) = stream.next().await {
+                if sender.send(metrics).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    let consumer = tokio::spawn(async move {
+        let mut received = 0u64;
+        while let Some(metrics) = receiver.recv().await {
+            metrics.expect("Failed to read the metrics of the test process");
+            received += 1;
+        }
+        received
+    });
+
+    let start = Instant::now();
+    tokio::time::sleep(config.warm_up).await;
+
+    let mut trends = TrendTracker::new();
+    let mut ticker = tokio::time::interval(config.sample_interval);
+    while start.elapsed() < config.duration {
+        ticker.tick().await;
+        let sample = sample();
+        trends.record(&sample);
+        let depth = sender.max_capacity() - sender.capacity();
+        trends.record_value(CHANNEL_DEPTH, sample.taken_at, depth as f64);
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    producer.abort();
+    drop(sender);
+    for poller in [snapshots, thermal, network] {
+        poller.await.expect("A polling loop panicked");
+    }
+    let received = consumer.await.expect("The consumer panicked");
+    assert!(received > 0, "The process metrics stream yielded nothing");
+
+    for (name, slope) in trends.slopes_per_hour() {
+        println!("{:<32} {:>16.1}/h over {} samples", name, slope, trends.sample_count(name));
+    }
+    let leaks = trends.leaks(&config.thresholds);
+    assert!(leaks.is_empty(), "Quantities kept growing: {:#?}", leaks);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "runs for 10 minutes by default"]
+async fn soak_long() {
+    soak(SoakConfig::from_env()).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs real hardware"]
+async fn soak_short() {
+    // Over a few seconds noise outweighs any leak, so only runaway growth fails: more than one resource or 1 MiB of
+    // memory every 4 seconds
+    let mut thresholds = LeakThresholds { default_per_hour: 900.0, ..LeakThresholds::default() };
+    thresholds.per_quantity.insert(RESIDENT_BYTES.to_string(), 900.0 * 1024.0 * 1024.0);
+    soak(SoakConfig {
+        duration: Duration::from_secs(15),
+        sample_interval: Duration::from_secs(1),
+        warm_up: Duration::from_secs(3),
+        thresholds,
+    })
+    .await;
+}